@@ -1,9 +1,15 @@
-use aa_bundler_grpc::{bundler_client::BundlerClient, uo_pool_client::UoPoolClient};
-use aa_bundler_rpc::{DebugApiServer, DebugApiServerImpl, EthApiServer, EthApiServerImpl};
+use aa_bundler_grpc::{
+    auth_token, dump_config, init_tracing, parse_opts, shutdown_tracing, start_metrics_server,
+    wait_for_termination, GrpcClientTlsOpts, MetricsOpts, RuntimeOpts, ShutdownOpts, TelemetryOpts,
+    UoPoolAddress, UoPoolConnector,
+};
+use aa_bundler_primitives::{connect_provider, EthClientRetryOpts, Secret};
+use aa_bundler_rpc::{HealthChecker, JsonRpcServer, JsonRpcServerOpts};
 use anyhow::Result;
-use clap::Parser;
-use jsonrpsee::{core::server::rpc_module::Methods, server::ServerBuilder, tracing::info};
-use std::{collections::HashSet, future::pending};
+use clap::{CommandFactory, Parser};
+use ethers::providers::Middleware;
+use jsonrpsee::tracing::{info, warn};
+use std::{path::PathBuf, sync::Arc};
 
 #[derive(Parser)]
 #[clap(
@@ -17,53 +23,115 @@ pub struct Opt {
     #[clap(long, default_value = "127.0.0.1:3001")]
     pub uopool_grpc_listen_address: String,
 
+    // connect to the uopool over the Unix domain socket instead of TCP
+    #[clap(long)]
+    pub uopool_grpc_ipc_path: Option<PathBuf>,
+
+    // file with the shared secret of the uopool gRPC server (not used over IPC)
+    #[clap(long)]
+    pub uopool_grpc_auth_token_file: Option<PathBuf>,
+
+    // the shared secret given directly, only with AA_BUNDLER_UOPOOL_GRPC_AUTH_TOKEN or the config file
+    #[clap(long, conflicts_with = "uopool_grpc_auth_token_file")]
+    pub uopool_grpc_auth_token: Option<Secret>,
+
     #[clap(long, default_value = "127.0.0.1:3002")]
     pub bundler_grpc_listen_address: String,
 
-    #[clap(long, value_delimiter=',', default_value = "eth", value_parser = ["eth", "debug"])]
+    #[clap(long, value_delimiter=',', default_value = "eth", value_parser = ["eth", "admin"])]
     pub rpc_api: Vec<String>,
-}
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    let opt: Opt = Opt::parse();
+    // enable the debug_bundler namespace (bundler spec tests), the uopool has to be started with it as well
+    #[clap(long)]
+    pub debug_rpc: bool,
 
-    tracing_subscriber::fmt::init();
+    #[clap(flatten)]
+    pub rpc_opts: JsonRpcServerOpts,
 
-    let jsonrpc_server = ServerBuilder::default()
-        .build(&opt.rpc_listen_address)
-        .await?;
+    // execution client rpc endpoints, comma-separated (checked by the health and readiness probes)
+    #[clap(long, value_delimiter = ',', default_value = "http://127.0.0.1:8545")]
+    pub eth_client_address: Vec<String>,
 
-    let mut api = Methods::new();
-    let uopool_grpc_client =
-        UoPoolClient::connect(format!("http://{}", opt.uopool_grpc_listen_address)).await?;
+    #[clap(flatten)]
+    pub eth_client_retry_opts: EthClientRetryOpts,
 
-    let rpc_api: HashSet<String> = HashSet::from_iter(opt.rpc_api.iter().cloned());
+    // TLS of the connections to the uopool and the bundler gRPC servers
+    #[clap(flatten)]
+    pub grpc_client_tls_opts: GrpcClientTlsOpts,
 
-    if rpc_api.contains("eth") {
-        api.merge(
-            EthApiServerImpl {
-                call_gas_limit: 100_000_000,
-                uopool_grpc_client: uopool_grpc_client.clone(),
-            }
-            .into_rpc(),
-        )?;
-    }
+    #[clap(flatten)]
+    pub metrics_opts: MetricsOpts,
 
-    if rpc_api.contains("debug") {
-        let bundler_grpc_client =
-            BundlerClient::connect(format!("http://{}", opt.bundler_grpc_listen_address)).await?;
-        api.merge(
-            DebugApiServerImpl {
-                uopool_grpc_client,
-                bundler_grpc_client,
-            }
-            .into_rpc(),
-        )?;
-    }
+    #[clap(flatten)]
+    pub telemetry_opts: TelemetryOpts,
+
+    #[clap(flatten)]
+    pub shutdown_opts: ShutdownOpts,
+
+    #[clap(flatten)]
+    pub runtime_opts: RuntimeOpts,
+}
+
+fn main() -> Result<()> {
+    let opt: Opt = parse_opts(Opt::command())?;
+    opt.runtime_opts
+        .runtime_builder()
+        .build()?
+        .block_on(run(opt))
+}
 
-    let _jsonrpc_server_handle = jsonrpc_server.start(api.clone())?;
+async fn run(opt: Opt) -> Result<()> {
+    init_tracing(&opt.telemetry_opts, "aa-bundler-rpc")?;
+
+    let grpc_tls_config = opt.grpc_client_tls_opts.tls_config();
+    let uopool_address = match opt.uopool_grpc_ipc_path.clone() {
+        Some(ipc_path) => UoPoolAddress::Ipc(ipc_path),
+        None => UoPoolAddress::Tcp(opt.uopool_grpc_listen_address.clone()),
+    };
+    let uopool_grpc_client = UoPoolConnector::new(uopool_address)
+        .with_tls_config(grpc_tls_config.clone())
+        .with_auth_token(auth_token(
+            opt.uopool_grpc_auth_token.as_ref(),
+            opt.uopool_grpc_auth_token_file.as_deref(),
+        )?)
+        .connect()
+        .await?;
+
+    let mut jsonrpc_server = JsonRpcServer::new(opt.rpc_listen_address.clone(), opt.rpc_opts);
+    jsonrpc_server.set_config(dump_config(Opt::command())?);
+    jsonrpc_server
+        .add_namespaces(
+            &opt.rpc_api,
+            opt.debug_rpc,
+            uopool_grpc_client.clone(),
+            &opt.bundler_grpc_listen_address,
+            grpc_tls_config.as_ref(),
+        )
+        .await?;
+    let eth_provider = Arc::new(
+        connect_provider(
+            &opt.eth_client_address,
+            opt.eth_client_retry_opts.retry_policy(),
+        )
+        .await?,
+    );
+    if let Some(metrics_listen_address) = opt.metrics_opts.metrics_listen_address {
+        let chain_id = eth_provider.get_chainid().await?;
+        start_metrics_server(metrics_listen_address, chain_id.as_u64())?;
+    }
+    jsonrpc_server.set_health_checker(HealthChecker::new(eth_provider, uopool_grpc_client));
+    let jsonrpc_server_handle = jsonrpc_server.start().await?;
     info!("JSON-RPC server listening on {}", opt.rpc_listen_address);
 
-    pending().await
+    wait_for_termination().await;
+    info!("Shutting down the JSON-RPC server");
+    let drain_timeout = opt.shutdown_opts.drain_timeout();
+    if tokio::time::timeout(drain_timeout, jsonrpc_server_handle.stop())
+        .await
+        .is_err()
+    {
+        warn!("JSON-RPC server didn't stop in {drain_timeout:?}, dropping the pending requests");
+    }
+    shutdown_tracing();
+    Ok(())
 }
@@ -0,0 +1,227 @@
+use aa_bundler_grpc::{
+    auth_token, bundler_service_run, init_tracing, parse_opts, shutdown_tracing,
+    start_metrics_server, wait_for_termination, BundlerService, BundlerServiceOpts,
+    GrpcClientTlsOpts, MetricsOpts, RuntimeOpts, ShutdownOpts, ShutdownSignal, Supervisor,
+    TelemetryOpts, UoPoolAddress, UoPoolConnector,
+};
+use aa_bundler_primitives::{
+    connect_provider, parse_checksummed_address, parse_u256, ChainSpec, EthClientRetryOpts,
+    ProviderCapabilities, Secret, Wallet,
+};
+use anyhow::{format_err, Result};
+use clap::{CommandFactory, Parser};
+use ethers::{
+    providers::Middleware,
+    types::{Address, U256},
+};
+use expanded_pathbuf::ExpandedPathBuf;
+use jsonrpsee::tracing::{info, warn};
+use std::{path::PathBuf, sync::Arc};
+use tokio::time::Instant;
+
+#[derive(Parser)]
+#[clap(
+    name = "aa-bundler-service",
+    about = "Bundler (with the signer) for EIP-4337 Account Abstraction, bundling the user operations of a separate uopool"
+)]
+pub struct Opt {
+    #[clap(long, required_unless_present_any = ["mnemonic", "private_key"])]
+    pub mnemonic_file: Option<ExpandedPathBuf>,
+
+    // mnemonic of the signer given directly, only with AA_BUNDLER_MNEMONIC or the config file
+    #[clap(long, conflicts_with_all = ["mnemonic_file", "private_key"])]
+    pub mnemonic: Option<Secret>,
+
+    // hex encoded private key of the signer, only with AA_BUNDLER_PRIVATE_KEY or the config file
+    #[clap(long, conflicts_with = "mnemonic_file")]
+    pub private_key: Option<Secret>,
+
+    /// Entry points to bundle (EIP-55 checksummed if mixed-case), all the entry points of the uopool if none are
+    /// given
+    #[clap(long, value_delimiter=',', value_parser=parse_checksummed_address)]
+    pub entry_points: Vec<Address>,
+
+    /// Address of the uopool gRPC server (host:port)
+    #[clap(long, default_value = "127.0.0.1:3001")]
+    pub uopool_grpc_address: String,
+
+    // connect to the uopool over the Unix domain socket instead of TCP
+    #[clap(long)]
+    pub uopool_grpc_ipc_path: Option<PathBuf>,
+
+    // file with the shared secret of the uopool gRPC server (not used over IPC)
+    #[clap(long)]
+    pub uopool_grpc_auth_token_file: Option<PathBuf>,
+
+    // the shared secret given directly, only with AA_BUNDLER_UOPOOL_GRPC_AUTH_TOKEN or the config file
+    #[clap(long, conflicts_with = "uopool_grpc_auth_token_file")]
+    pub uopool_grpc_auth_token: Option<Secret>,
+
+    #[clap(long, value_parser=parse_u256)]
+    pub max_verification_gas: U256,
+
+    // execution client rpc endpoint (http:// or ws://), the next endpoints (comma-separated) take over when the
+    // preferred one fails or falls behind
+    #[clap(long, value_delimiter = ',', default_value = "http://127.0.0.1:8545")]
+    pub eth_client_address: Vec<String>,
+
+    #[clap(flatten)]
+    pub eth_client_retry_opts: EthClientRetryOpts,
+
+    #[clap(flatten)]
+    pub bundler_opts: BundlerServiceOpts,
+
+    // TLS of the connection to the uopool gRPC server
+    #[clap(flatten)]
+    pub grpc_client_tls_opts: GrpcClientTlsOpts,
+
+    #[clap(flatten)]
+    pub metrics_opts: MetricsOpts,
+
+    #[clap(flatten)]
+    pub telemetry_opts: TelemetryOpts,
+
+    #[clap(flatten)]
+    pub shutdown_opts: ShutdownOpts,
+
+    #[clap(flatten)]
+    pub runtime_opts: RuntimeOpts,
+}
+
+fn main() -> Result<()> {
+    let opt: Opt = parse_opts(Opt::command())?;
+    opt.runtime_opts
+        .runtime_builder()
+        .build()?
+        .block_on(run(opt))
+}
+
+async fn run(opt: Opt) -> Result<()> {
+    init_tracing(&opt.telemetry_opts, "aa-bundler-service")?;
+
+    let eth_provider = Arc::new(
+        connect_provider(
+            &opt.eth_client_address,
+            opt.eth_client_retry_opts.retry_policy(),
+        )
+        .await?,
+    );
+    info!(
+        "Connected to Ethereum execution client at {}: {}",
+        eth_provider.as_ref().as_ref().active_endpoint(),
+        eth_provider.client_version().await?
+    );
+
+    let chain_id = eth_provider.get_chainid().await?;
+    if let Some(metrics_listen_address) = opt.metrics_opts.metrics_listen_address {
+        start_metrics_server(metrics_listen_address, chain_id.as_u64())?;
+    }
+
+    let wallet = if let Some(mnemonic_file) = opt.mnemonic_file.clone() {
+        Wallet::from_file(mnemonic_file, chain_id)
+            .map_err(|error| format_err!("Could not load mnemonic file: {}", error))?
+    } else if let Some(mnemonic) = &opt.mnemonic {
+        // the error could quote the mnemonic
+        Wallet::from_phrase(mnemonic.expose(), chain_id)
+            .map_err(|_| format_err!("Invalid mnemonic"))?
+    } else if let Some(private_key) = &opt.private_key {
+        Wallet::from_private_key(private_key.expose(), chain_id)?
+    } else {
+        return Err(format_err!("No signer is configured"));
+    };
+    info!("{:?}", wallet.signer);
+
+    let uopool_address = match opt.uopool_grpc_ipc_path.clone() {
+        Some(ipc_path) => UoPoolAddress::Ipc(ipc_path),
+        None => UoPoolAddress::Tcp(opt.uopool_grpc_address.clone()),
+    };
+    let mut uopool_grpc_client = UoPoolConnector::new(uopool_address)
+        .with_tls_config(opt.grpc_client_tls_opts.tls_config())
+        .with_auth_token(auth_token(
+            opt.uopool_grpc_auth_token.as_ref(),
+            opt.uopool_grpc_auth_token_file.as_deref(),
+        )?)
+        .connect()
+        .await?;
+    info!("Connected to uopool grpc");
+
+    // the bundler is on the chain of the uopool and bundles only the entry points that the uopool supports
+    let uopool_chain_id: U256 = uopool_grpc_client
+        .get_chain_id(())
+        .await?
+        .into_inner()
+        .chain_id
+        .into();
+    if uopool_chain_id != chain_id {
+        return Err(format_err!(
+            "Uopool is on chain {uopool_chain_id}, the execution client on chain {chain_id}"
+        ));
+    }
+    let supported: Vec<Address> = uopool_grpc_client
+        .get_supported_entry_points(())
+        .await?
+        .into_inner()
+        .eps
+        .into_iter()
+        .map(Into::into)
+        .collect();
+    let entry_points = if opt.entry_points.is_empty() {
+        supported
+    } else {
+        if let Some(entry_point) = opt
+            .entry_points
+            .iter()
+            .find(|entry_point| !supported.contains(entry_point))
+        {
+            return Err(format_err!(
+                "Entry point {entry_point:?} is not supported by the uopool"
+            ));
+        }
+        opt.entry_points.clone()
+    };
+    info!("Bundling the user operations of the entry points {entry_points:?}");
+
+    let (shutdown_sender, shutdown) = ShutdownSignal::new();
+    let supervisor = Supervisor::new(shutdown.clone());
+    let chain_spec = ChainSpec::from_chain_id(chain_id.as_u64())
+        .with_capabilities(&ProviderCapabilities::probe(eth_provider.as_ref()).await);
+    let mut bundler_service = BundlerService::new(
+        wallet,
+        &opt.bundler_opts,
+        uopool_grpc_client,
+        entry_points,
+        chain_spec,
+        (*eth_provider).clone(),
+        opt.max_verification_gas,
+    )?;
+    bundler_service.supervisor = supervisor;
+    bundler_service.start_balance_monitoring()?;
+    bundler_service.start_bundling(opt.bundler_opts.bundle_interval);
+    bundler_service.start_watchdog();
+    let bundler_service = Arc::new(bundler_service);
+    bundler_service_run(
+        bundler_service.clone(),
+        opt.bundler_opts.bundler_grpc_listen_address,
+        opt.bundler_opts.grpc_tls(),
+        shutdown.clone(),
+    )?;
+    info!(
+        "Bundler gRPC server listening on {}",
+        opt.bundler_opts.bundler_grpc_listen_address
+    );
+
+    wait_for_termination().await;
+    info!("Shutting down the bundler");
+    let deadline = Instant::now() + opt.shutdown_opts.drain_timeout();
+    bundler_service.stop_bundling();
+    if opt.bundler_opts.shutdown_final_bundle {
+        match tokio::time::timeout_at(deadline, bundler_service.send_bundles_now()).await {
+            Ok(Ok(tx_hash)) => info!("Sent the final bundle {tx_hash:?}"),
+            Ok(Err(e)) => warn!("Final bundle was not sent: {e:?}"),
+            Err(_) => warn!("Final bundle was not sent within the drain timeout"),
+        }
+    }
+    let _ = shutdown_sender.send(true);
+    shutdown_tracing();
+    Ok(())
+}
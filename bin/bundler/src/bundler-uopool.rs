@@ -1,13 +1,21 @@
-use aa_bundler_grpc::{uopool_service_run, UoPoolServiceOpts};
-use aa_bundler_primitives::{parse_address, parse_u256};
+use aa_bundler_grpc::{
+    auth_token, init_tracing, parse_opts, reparse_opts, resolve_entry_points, shutdown_tracing,
+    start_metrics_server, uopool_service_run, wait_for_termination, ConfigReloader, MetricsOpts,
+    ReloadableOpts, RuntimeOpts, ShutdownOpts, ShutdownSignal, Supervisor, TelemetryOpts,
+    UoPoolAddress, UoPoolConnector, UoPoolServiceOpts,
+};
+use aa_bundler_p2p::P2POpts;
+use aa_bundler_primitives::{
+    connect_provider, parse_checksummed_address, parse_u256, EthClientRetryOpts,
+};
 use anyhow::Result;
-use clap::Parser;
+use clap::{CommandFactory, Parser};
 use ethers::{
-    providers::{Http, Middleware, Provider},
+    providers::Middleware,
     types::{Address, U256},
 };
 use jsonrpsee::tracing::info;
-use std::{future::pending, sync::Arc};
+use std::sync::Arc;
 
 #[derive(Parser)]
 #[clap(
@@ -18,37 +26,112 @@ pub struct Opt {
     #[clap(flatten)]
     pub uopool_opts: UoPoolServiceOpts,
 
-    #[clap(long, value_delimiter=',', value_parser=parse_address)]
+    #[clap(flatten)]
+    pub p2p_opts: P2POpts,
+
+    /// Entry points to support (EIP-55 checksummed if mixed-case), the canonical v0.6/v0.7 entry points deployed on
+    /// the chain if none are given
+    #[clap(long, value_delimiter=',', value_parser=parse_checksummed_address)]
     pub entry_points: Vec<Address>,
 
-    // execution client rpc endpoint
-    #[clap(long, default_value = "127.0.0.1:8545")]
-    pub eth_client_address: String,
+    // execution client rpc endpoint (http:// or ws://, the new blocks are pushed over WebSocket), the
+    // next endpoints (comma-separated) take over when the preferred one fails or falls behind
+    #[clap(long, value_delimiter = ',', default_value = "127.0.0.1:8545")]
+    pub eth_client_address: Vec<String>,
+
+    #[clap(flatten)]
+    pub eth_client_retry_opts: EthClientRetryOpts,
 
     #[clap(long, value_parser=parse_u256)]
     pub max_verification_gas: U256,
+
+    // enable the debug methods used by the debug_bundler namespace (bundler spec tests)
+    #[clap(long)]
+    pub debug_rpc: bool,
+
+    #[clap(flatten)]
+    pub metrics_opts: MetricsOpts,
+
+    #[clap(flatten)]
+    pub telemetry_opts: TelemetryOpts,
+
+    #[clap(flatten)]
+    pub shutdown_opts: ShutdownOpts,
+
+    #[clap(flatten)]
+    pub runtime_opts: RuntimeOpts,
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    let opt: Opt = Opt::parse();
+fn main() -> Result<()> {
+    let opt: Opt = parse_opts(Opt::command())?;
+    opt.runtime_opts
+        .runtime_builder()
+        .build()?
+        .block_on(run(opt))
+}
 
-    tracing_subscriber::fmt::init();
+async fn run(opt: Opt) -> Result<()> {
+    init_tracing(&opt.telemetry_opts, "aa-bundler-uopool")?;
 
-    let eth_provider = Arc::new(Provider::<Http>::try_from(opt.eth_client_address.clone())?);
+    let eth_provider = Arc::new(
+        connect_provider(
+            &opt.eth_client_address,
+            opt.eth_client_retry_opts.retry_policy(),
+        )
+        .await?,
+    );
     info!(
         "Connected to Ethereum execution client at {}: {}",
-        opt.eth_client_address,
+        eth_provider.as_ref().as_ref().active_endpoint(),
         eth_provider.client_version().await?
     );
 
-    uopool_service_run(
-        opt.uopool_opts,
-        opt.entry_points,
+    if let Some(metrics_listen_address) = opt.metrics_opts.metrics_listen_address {
+        let chain_id = eth_provider.get_chainid().await?;
+        start_metrics_server(metrics_listen_address, chain_id.as_u64())?;
+    }
+
+    let entry_points = resolve_entry_points(&eth_provider, opt.entry_points).await?;
+
+    let (shutdown_sender, shutdown) = ShutdownSignal::new();
+    let supervisor = Supervisor::new(shutdown.clone());
+    let uopool_handle = uopool_service_run(
+        opt.uopool_opts.clone(),
+        opt.p2p_opts,
+        entry_points,
         eth_provider,
         opt.max_verification_gas,
+        opt.debug_rpc,
+        shutdown.clone(),
+        supervisor.clone(),
     )
     .await?;
 
-    pending().await
+    // the reloaded options are applied over the gRPC server of the uopool
+    let uopool_address = match opt.uopool_opts.uopool_grpc_ipc_path.clone() {
+        Some(ipc_path) => UoPoolAddress::Ipc(ipc_path),
+        None => UoPoolAddress::Tcp(opt.uopool_opts.uopool_grpc_listen_address.to_string()),
+    };
+    let uopool_grpc_client = UoPoolConnector::new(uopool_address)
+        .with_auth_token(auth_token(
+            opt.uopool_opts.uopool_grpc_auth_token.as_ref(),
+            opt.uopool_opts.uopool_grpc_auth_token_file.as_deref(),
+        )?)
+        .connect()
+        .await?;
+    ConfigReloader::new(
+        || {
+            let opt: Opt = reparse_opts(Opt::command())?;
+            Ok(ReloadableOpts::new(&opt.uopool_opts, None))
+        },
+        uopool_grpc_client,
+    )
+    .reload_on_sighup(&supervisor, shutdown);
+
+    wait_for_termination().await;
+    info!("Shutting down the uopool");
+    let _ = shutdown_sender.send(true);
+    let result = uopool_handle.join(opt.shutdown_opts.drain_timeout()).await;
+    shutdown_tracing();
+    result
 }
@@ -1,29 +1,77 @@
 use aa_bundler_grpc::{
-    bundler_client::BundlerClient, bundler_service_run, uo_pool_client::UoPoolClient,
-    uopool_service_run, BundlerService, BundlerServiceOpts, UoPoolServiceOpts,
+    auth_token, bundler_service_run, dump_config, init_tracing, parse_opts, reparse_opts,
+    resolve_entry_points, shutdown_tracing, start_metrics_server, uopool_service_run,
+    wait_for_termination, BundlerService, BundlerServiceOpts, ConfigReloader, GrpcClientTlsOpts,
+    MetricsOpts, ReloadableOpts, RuntimeOpts, SetAcceptingRequest, ShutdownOpts, ShutdownSignal,
+    Supervisor, TelemetryOpts, UoPoolAddress, UoPoolConnector, UoPoolServiceOpts,
 };
-use aa_bundler_primitives::{parse_address, parse_u256, Wallet};
-use aa_bundler_rpc::{DebugApiServer, DebugApiServerImpl, EthApiServer, EthApiServerImpl};
+use aa_bundler_p2p::P2POpts;
+use aa_bundler_primitives::{
+    connect_provider, parse_checksummed_address, parse_u256, ChainSpec, EthClientRetryOpts,
+    ProviderCapabilities, Secret, Wallet,
+};
+use aa_bundler_rpc::{HealthChecker, JsonRpcServer, JsonRpcServerOpts};
 use anyhow::{format_err, Result};
-use clap::Parser;
+use clap::{builder::ArgPredicate, Args, CommandFactory, Parser, Subcommand};
+use commands::{
+    check_node, doctor, dump, send_test_op, CheckNodeOpt, DoctorOpt, DumpOpt, SendTestOpOpt,
+};
+use dev::{deploy, start_anvil, with_dev_defaults, DEV_MNEMONIC};
 use ethers::{
-    providers::{Http, Middleware, Provider},
+    providers::Middleware,
+    signers::Signer,
     types::{Address, U256},
 };
 use expanded_pathbuf::ExpandedPathBuf;
-use jsonrpsee::{core::server::rpc_module::Methods, server::ServerBuilder, tracing::info};
-use std::{collections::HashSet, future::pending, panic, sync::Arc};
+use jsonrpsee::tracing::{info, warn};
+use std::{future::Future, panic, path::PathBuf, sync::Arc};
+use tokio::time::Instant;
+
+mod commands;
+mod dev;
 
 #[derive(Parser)]
 #[clap(
     name = "aa-bundler",
     about = "Bundler for EIP-4337 Account Abstraction"
 )]
+pub struct Cli {
+    #[clap(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Runs the bundler: the uopool, the bundling and the JSON-RPC server
+    Run(Box<Opt>),
+    /// Prints the mempool and the reputation of a running uopool (with the debug methods enabled) or of its snapshot
+    Dump(DumpOpt),
+    /// Builds, signs and sends a user operation of a test account, for smoke testing
+    SendTestOp(SendTestOpOpt),
+    /// Probes the execution client for the capabilities the bundler depends on
+    CheckNode(CheckNodeOpt),
+    /// Verifies a synthetic user operation with the uopool options (without adding it to the mempool) and reports the
+    /// check that fails
+    Doctor(Box<DoctorOpt>),
+}
+
+#[derive(Args)]
 pub struct Opt {
-    #[clap(long)]
-    pub mnemonic_file: ExpandedPathBuf,
+    // anvil's pre-funded accounts are used in the dev mode if no signer is set
+    #[clap(long, required_unless_present_any = ["dev", "mnemonic", "private_key"])]
+    pub mnemonic_file: Option<ExpandedPathBuf>,
 
-    #[clap(long, value_delimiter=',', value_parser=parse_address)]
+    // mnemonic of the signer given directly, only with AA_BUNDLER_MNEMONIC or the config file
+    #[clap(long, conflicts_with_all = ["mnemonic_file", "private_key"])]
+    pub mnemonic: Option<Secret>,
+
+    // hex encoded private key of the signer, only with AA_BUNDLER_PRIVATE_KEY or the config file
+    #[clap(long, conflicts_with = "mnemonic_file")]
+    pub private_key: Option<Secret>,
+
+    /// Entry points to support (EIP-55 checksummed if mixed-case), the canonical v0.6/v0.7 entry points deployed on
+    /// the chain if none are given
+    #[clap(long, value_delimiter=',', value_parser=parse_checksummed_address)]
     pub entry_points: Vec<Address>,
 
     #[clap(long)]
@@ -32,7 +80,16 @@ pub struct Opt {
     #[clap(flatten)]
     pub uopool_opts: UoPoolServiceOpts,
 
-    #[clap(long, value_parser=parse_u256)]
+    #[clap(flatten)]
+    pub p2p_opts: P2POpts,
+
+    #[clap(
+        long,
+        value_parser=parse_u256,
+        required = false,
+        required_unless_present = "dev",
+        default_value_if("dev", ArgPredicate::IsPresent, "1500000")
+    )]
     pub max_verification_gas: U256,
 
     #[clap(long)]
@@ -41,136 +98,318 @@ pub struct Opt {
     #[clap(long, default_value = "127.0.0.1:3000")]
     pub rpc_listen_address: String,
 
-    #[clap(long, value_delimiter=',', default_value = "eth", value_parser = ["eth", "debug"])]
+    #[clap(long, value_delimiter=',', default_value = "eth", value_parser = ["eth", "admin"])]
     pub rpc_api: Vec<String>,
 
-    // execution client rpc endpoint
-    #[clap(long, default_value = "http://127.0.0.1:8545")]
-    pub eth_client_address: String,
+    // enable the debug_bundler namespace and the uopool debug methods (bundler spec tests)
+    #[clap(long)]
+    pub debug_rpc: bool,
+
+    #[clap(flatten)]
+    pub rpc_opts: JsonRpcServerOpts,
+
+    // execution client rpc endpoint (http:// or ws://, the new blocks are pushed over WebSocket), the
+    // next endpoints (comma-separated) take over when the preferred one fails or falls behind
+    #[clap(long, value_delimiter = ',', default_value = "http://127.0.0.1:8545")]
+    pub eth_client_address: Vec<String>,
+
+    #[clap(flatten)]
+    pub eth_client_retry_opts: EthClientRetryOpts,
 
     #[clap(flatten)]
     pub bundler_opts: BundlerServiceOpts,
+
+    // TLS of the connections to the uopool (with --no-uopool) and the bundler gRPC servers
+    #[clap(flatten)]
+    pub grpc_client_tls_opts: GrpcClientTlsOpts,
+
+    #[clap(flatten)]
+    pub metrics_opts: MetricsOpts,
+
+    #[clap(flatten)]
+    pub telemetry_opts: TelemetryOpts,
+
+    #[clap(flatten)]
+    pub shutdown_opts: ShutdownOpts,
+
+    /// Local development: attaches to the anvil node at the execution client address (or spawns one), places the
+    /// entry point at its canonical address, deploys a sample account factory and funds the bundler's account
+    #[clap(long)]
+    pub dev: bool,
+
+    /// Hardhat artifacts of the account-abstraction contracts deployed in the dev mode
+    #[clap(long)]
+    pub dev_artifacts_dir: Option<PathBuf>,
 }
 
-fn main() -> Result<()> {
-    let opt: Opt = Opt::parse();
+fn command() -> clap::Command {
+    Cli::command().mut_subcommand("run", with_dev_defaults)
+}
+
+/// Options of the `run` subcommand, parsed again on reload
+fn reparse_run_opts() -> Result<Opt> {
+    match reparse_opts::<Cli>(command())?.command {
+        Command::Run(opt) => Ok(*opt),
+        _ => Err(format_err!(
+            "Options of the run subcommand can't be reloaded"
+        )),
+    }
+}
 
-    tracing_subscriber::fmt::init();
+fn block_on<F: Future<Output = Result<()>>>(future: F) -> Result<()> {
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .init();
+    tokio::runtime::Runtime::new()?.block_on(future)
+}
+
+fn main() -> Result<()> {
+    let cli: Cli = parse_opts(command())?;
+    match cli.command {
+        Command::Run(opt) => run(*opt),
+        Command::Dump(opt) => block_on(dump(opt)),
+        Command::SendTestOp(opt) => block_on(send_test_op(opt)),
+        Command::CheckNode(opt) => block_on(check_node(opt)),
+        Command::Doctor(opt) => block_on(doctor(*opt)),
+    }
+}
 
+fn run(opt: Opt) -> Result<()> {
+    let config = dump_config(command())?;
     std::thread::Builder::new()
         .stack_size(128 * 1024 * 1024)
         .spawn(move || {
-            let rt = tokio::runtime::Builder::new_multi_thread()
-                .enable_all()
+            let rt = opt
+                .runtime_opts
+                .runtime_builder()
                 .thread_stack_size(128 * 1024 * 1024)
                 .build()?;
 
             rt.block_on(async move {
+                init_tracing(&opt.telemetry_opts, "aa-bundler")?;
                 info!("Starting AA - Bundler");
 
-                let eth_provider =
-                    Arc::new(Provider::<Http>::try_from(opt.eth_client_address.clone())?);
+                let _anvil = if opt.dev {
+                    start_anvil(&opt.eth_client_address[0]).await?
+                } else {
+                    None
+                };
+
+                let eth_provider = Arc::new(
+                    connect_provider(
+                        &opt.eth_client_address,
+                        opt.eth_client_retry_opts.retry_policy(),
+                    )
+                    .await?,
+                );
                 info!(
                     "Connected to Ethereum execution client at {}: {}",
-                    opt.eth_client_address,
+                    eth_provider.as_ref().as_ref().active_endpoint(),
                     eth_provider.client_version().await?
                 );
 
                 let chain_id = eth_provider.get_chainid().await?;
+                if let Some(metrics_listen_address) = opt.metrics_opts.metrics_listen_address {
+                    start_metrics_server(metrics_listen_address, chain_id.as_u64())?;
+                }
 
-                let wallet = Wallet::from_file(opt.mnemonic_file.clone(), chain_id)
-                    .map_err(|error| format_err!("Could not load mnemonic file: {}", error))?;
+                let wallet = if let Some(mnemonic_file) = opt.mnemonic_file.clone() {
+                    Wallet::from_file(mnemonic_file, chain_id)
+                        .map_err(|error| format_err!("Could not load mnemonic file: {}", error))?
+                } else if let Some(mnemonic) = &opt.mnemonic {
+                    // the error could quote the mnemonic
+                    Wallet::from_phrase(mnemonic.expose(), chain_id)
+                        .map_err(|_| format_err!("Invalid mnemonic"))?
+                } else if let Some(private_key) = &opt.private_key {
+                    Wallet::from_private_key(private_key.expose(), chain_id)?
+                } else {
+                    Wallet::from_phrase(DEV_MNEMONIC, chain_id)?
+                };
                 info!("{:?}", wallet.signer);
 
-                let eth_provider =
-                    Arc::new(Provider::<Http>::try_from(opt.eth_client_address.clone())?);
+                let mut entry_points = opt.entry_points.clone();
+                if opt.dev {
+                    let artifacts_dir = opt.dev_artifacts_dir.clone().unwrap_or_else(|| {
+                        PathBuf::from(env!("CARGO_WORKSPACE_DIR"))
+                            .join("thirdparty/account-abstraction/artifacts")
+                    });
+                    let deployment = deploy(eth_provider.clone(), &wallet, &artifacts_dir).await?;
+                    if !entry_points.contains(&deployment.entry_point) {
+                        entry_points.push(deployment.entry_point);
+                    }
+                    info!(
+                        "Dev mode ready (chain id {chain_id}): entry point {:?}, account factory {:?}, bundler {:?}, JSON-RPC http://{}",
+                        deployment.entry_point,
+                        deployment.account_factory,
+                        wallet.signer.address(),
+                        opt.rpc_listen_address
+                    );
+                }
+                let entry_points = resolve_entry_points(&eth_provider, entry_points).await?;
 
-                if !opt.no_uopool {
+                let (shutdown_sender, shutdown) = ShutdownSignal::new();
+                let supervisor = Supervisor::new(shutdown.clone());
+                let uopool_handle = if !opt.no_uopool {
                     info!("Starting op pool with bundler");
-                    uopool_service_run(
-                        opt.uopool_opts,
-                        opt.entry_points.clone(),
-                        eth_provider,
-                        opt.max_verification_gas,
+                    let mut uopool_opts = opt.uopool_opts.clone();
+                    // the uopool accepts the user operations of the aggregators the bundler can bundle
+                    if uopool_opts.uopool_aggregators.is_empty() {
+                        uopool_opts.uopool_aggregators = opt.bundler_opts.aggregators.clone();
+                    }
+                    Some(
+                        uopool_service_run(
+                            uopool_opts,
+                            opt.p2p_opts.clone(),
+                            entry_points.clone(),
+                            eth_provider.clone(),
+                            opt.max_verification_gas,
+                            opt.debug_rpc,
+                            shutdown.clone(),
+                            supervisor.clone(),
+                        )
+                        .await?,
                     )
-                    .await?;
-                }
+                } else {
+                    None
+                };
 
                 info!("Connecting to uopool grpc");
-                let uopool_grpc_client = UoPoolClient::connect(format!(
-                    "http://{}",
-                    opt.uopool_opts.uopool_grpc_listen_address
-                ))
-                .await?;
+                let grpc_tls_config = opt.grpc_client_tls_opts.tls_config();
+                let uopool_address = match opt.uopool_opts.uopool_grpc_ipc_path.clone() {
+                    Some(ipc_path) => UoPoolAddress::Ipc(ipc_path),
+                    None => {
+                        UoPoolAddress::Tcp(opt.uopool_opts.uopool_grpc_listen_address.to_string())
+                    }
+                };
+                let uopool_grpc_client = UoPoolConnector::new(uopool_address)
+                    .with_tls_config(grpc_tls_config.clone())
+                    .with_auth_token(auth_token(
+                        opt.uopool_opts.uopool_grpc_auth_token.as_ref(),
+                        opt.uopool_opts.uopool_grpc_auth_token_file.as_deref(),
+                        )?,
+                    )
+                    .connect()
+                    .await?;
                 info!("Connected to uopool grpc");
 
-                let bundler_service = BundlerService::new(
+                let chain_spec = ChainSpec::from_chain_id(chain_id.as_u64())
+                    .with_capabilities(&ProviderCapabilities::probe(eth_provider.as_ref()).await);
+                let mut bundler_service = BundlerService::new(
                     wallet,
-                    opt.bundler_opts.beneficiary,
+                    &opt.bundler_opts,
                     uopool_grpc_client.clone(),
-                    opt.entry_points,
-                    chain_id,
-                    opt.eth_client_address.clone(),
-                );
+                    entry_points,
+                    chain_spec,
+                    (*eth_provider).clone(),
+                    opt.max_verification_gas,
+                )?;
+                bundler_service.supervisor = supervisor.clone();
+                let config_reloader = ConfigReloader::new(
+                    || {
+                        let opt = reparse_run_opts()?;
+                        Ok(ReloadableOpts::new(&opt.uopool_opts, Some(&opt.bundler_opts)))
+                    },
+                    uopool_grpc_client.clone(),
+                )
+                .with_bundle_interval(bundler_service.interval.clone());
+                config_reloader
+                    .clone()
+                    .reload_on_sighup(&supervisor, shutdown.clone());
+                bundler_service.start_balance_monitoring()?;
                 info!("Starting bundler manager");
                 bundler_service.start_bundling(opt.bundler_opts.bundle_interval);
+                bundler_service.start_watchdog();
                 info!("Starting bundler rpc server");
+                let bundler_service = Arc::new(bundler_service);
                 bundler_service_run(
-                    bundler_service,
+                    bundler_service.clone(),
                     opt.bundler_opts.bundler_grpc_listen_address,
-                );
+                    opt.bundler_opts.grpc_tls(),
+                    shutdown.clone(),
+                )?;
                 info!(
                     "Starting bundler rpc server at {:}",
                     opt.bundler_opts.bundler_grpc_listen_address
                 );
 
-                if !opt.no_rpc {
+                let drain_timeout = opt.shutdown_opts.drain_timeout();
+                let shutdown_final_bundle = opt.bundler_opts.shutdown_final_bundle;
+                let rpc_server = if !opt.no_rpc {
                     info!("Starting rpc server with bundler");
-                    tokio::spawn({
+                    let uopool_grpc_client = uopool_grpc_client.clone();
+                    let shutdown = shutdown.clone();
+                    Some(tokio::spawn({
                         async move {
-                            let jsonrpc_server = ServerBuilder::default()
-                                .build(&opt.rpc_listen_address)
-                                .await?;
-
-                            let mut api = Methods::new();
-
-                            let rpc_api: HashSet<String> =
-                                HashSet::from_iter(opt.rpc_api.iter().cloned());
-
-                            if rpc_api.contains("eth") {
-                                api.merge(
-                                    EthApiServerImpl {
-                                        call_gas_limit: 100_000_000,
-                                        uopool_grpc_client: uopool_grpc_client.clone(),
-                                    }
-                                    .into_rpc(),
-                                )?;
-                            }
-
-                            if rpc_api.contains("debug") {
-                                let bundler_grpc_client = BundlerClient::connect(format!(
-                                    "http://{}",
-                                    opt.bundler_opts.bundler_grpc_listen_address
-                                ))
+                            let mut jsonrpc_server =
+                                JsonRpcServer::new(opt.rpc_listen_address.clone(), opt.rpc_opts);
+                            jsonrpc_server.set_config_reloader(config_reloader);
+                            jsonrpc_server.set_config(config);
+                            jsonrpc_server
+                                .add_namespaces(
+                                    &opt.rpc_api,
+                                    opt.debug_rpc,
+                                    uopool_grpc_client.clone(),
+                                    &opt.bundler_opts.bundler_grpc_listen_address.to_string(),
+                                    grpc_tls_config.as_ref(),
+                                )
                                 .await?;
-                                api.merge(
-                                    DebugApiServerImpl {
-                                        uopool_grpc_client,
-                                        bundler_grpc_client,
-                                    }
-                                    .into_rpc(),
-                                )?;
-                            }
-
-                            let _jsonrpc_server_handle = jsonrpc_server.start(api.clone())?;
+                            jsonrpc_server.set_health_checker(
+                                HealthChecker::new(eth_provider, uopool_grpc_client)
+                                    .with_supervisor(supervisor),
+                            );
+                            let jsonrpc_server_handle = jsonrpc_server.start().await?;
                             info!("JSON-RPC server listening on {}", opt.rpc_listen_address);
 
-                            pending::<Result<()>>().await
+                            shutdown.wait().await;
+                            jsonrpc_server_handle.stop().await;
+                            info!("JSON-RPC server stopped");
+                            Ok::<(), anyhow::Error>(())
                         }
-                    });
+                    }))
+                } else {
+                    None
+                };
+
+                wait_for_termination().await;
+                info!("Shutting down AA - Bundler");
+                let deadline = Instant::now() + drain_timeout;
+
+                // the uopool of the process rejects the new user operations while the in-flight verifications and
+                // the final bundle complete (a standalone uopool is shared with the other bundlers)
+                if uopool_handle.is_some() {
+                    if let Err(status) = uopool_grpc_client
+                        .clone()
+                        .set_accepting(SetAcceptingRequest { accepting: false })
+                        .await
+                    {
+                        warn!("Failed to stop accepting user operations: {}", status.message());
+                    }
+                }
+                bundler_service.stop_bundling();
+                if shutdown_final_bundle {
+                    match tokio::time::timeout_at(deadline, bundler_service.send_bundles_now()).await
+                    {
+                        Ok(Ok(tx_hash)) => info!("Sent the final bundle {tx_hash:?}"),
+                        Ok(Err(e)) => warn!("Final bundle was not sent: {e:?}"),
+                        Err(_) => warn!("Final bundle was not sent within the drain timeout"),
+                    }
                 }
 
-                pending().await
+                let _ = shutdown_sender.send(true);
+                if let Some(rpc_server) = rpc_server {
+                    match tokio::time::timeout_at(deadline, rpc_server).await {
+                        Ok(Ok(Err(e))) => warn!("JSON-RPC server failed: {e:?}"),
+                        Ok(_) => {}
+                        Err(_) => warn!("JSON-RPC server didn't stop within the drain timeout"),
+                    }
+                }
+                if let Some(uopool_handle) = uopool_handle {
+                    uopool_handle
+                        .join(deadline.saturating_duration_since(Instant::now()))
+                        .await?;
+                }
+                shutdown_tracing();
+                Ok(())
             })
         })?
         .join()
@@ -0,0 +1,512 @@
+use aa_bundler_contracts::{EntryPoint, EntryPointVersion};
+use aa_bundler_grpc::{
+    auth_token, resolve_entry_points, GetAllReputationRequest, GetAllRequest, GrpcClientTlsOpts,
+    MempoolSnapshot, SelfTest, SnapshotUserOperation, UoPoolAddress, UoPoolConnector,
+    UoPoolServiceOpts, UoPoolSnapshot,
+};
+use aa_bundler_primitives::{
+    connect_provider, parse_address, parse_checksummed_address, parse_u256, ChainSpec,
+    EthClientRetryOpts, EthProvider, ProviderCapabilities, Secret, UserOperationBuilder,
+    UserOperationGasEstimation, Wallet, DUMMY_SIGNATURE,
+};
+use anyhow::{format_err, Result};
+use clap::Parser;
+use ethers::{
+    abi::{encode, Token},
+    core::rand::thread_rng,
+    prelude::SignerMiddleware,
+    providers::{Http, Middleware, Provider},
+    signers::{LocalWallet, Signer},
+    types::{Address, Bytes, H256, U256},
+    utils::id,
+};
+use expanded_pathbuf::ExpandedPathBuf;
+use jsonrpsee::tracing::{info, warn};
+use std::{path::PathBuf, sync::Arc, time::Duration};
+
+/// Interval of the polls of the receipt of the test user operation
+const RECEIPT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// Call gas limit of the synthetic user operation of the self-test (the account calls its owner with no value)
+const SELF_TEST_CALL_GAS_LIMIT: u64 = 100_000;
+
+#[derive(Parser)]
+pub struct DumpOpt {
+    /// Snapshot of the uopool (its `--uopool-snapshot-path`) that is printed instead of the mempool of the running
+    /// node
+    #[clap(long)]
+    pub snapshot_path: Option<PathBuf>,
+
+    #[clap(long, default_value = "127.0.0.1:3001")]
+    pub uopool_grpc_listen_address: String,
+
+    // connect to the uopool over the Unix domain socket instead of TCP
+    #[clap(long)]
+    pub uopool_grpc_ipc_path: Option<PathBuf>,
+
+    // file with the shared secret of the uopool gRPC server (not used over IPC)
+    #[clap(long)]
+    pub uopool_grpc_auth_token_file: Option<PathBuf>,
+
+    // the shared secret given directly, only with AA_BUNDLER_UOPOOL_GRPC_AUTH_TOKEN or the config file
+    #[clap(long, conflicts_with = "uopool_grpc_auth_token_file")]
+    pub uopool_grpc_auth_token: Option<Secret>,
+
+    #[clap(flatten)]
+    pub grpc_client_tls_opts: GrpcClientTlsOpts,
+}
+
+#[derive(Parser)]
+pub struct SendTestOpOpt {
+    /// Mnemonic of the owner of the test account, which also pays the deposit
+    #[clap(long)]
+    pub mnemonic_file: ExpandedPathBuf,
+
+    #[clap(long, value_parser=parse_checksummed_address)]
+    pub entry_point: Address,
+
+    /// Account factory with `createAccount(address owner, uint256 salt)` (e.g. `SimpleAccountFactory`)
+    #[clap(long, value_parser=parse_address)]
+    pub factory: Address,
+
+    #[clap(long, value_parser=parse_u256, default_value = "0")]
+    pub salt: U256,
+
+    /// The entry point deposit of the test account is topped up to this amount (in wei) before the user operation
+    /// is sent
+    #[clap(long, value_parser=parse_u256)]
+    pub deposit: Option<U256>,
+
+    #[clap(long, default_value = "http://127.0.0.1:3000")]
+    pub rpc_address: String,
+
+    // execution client rpc endpoints, comma-separated
+    #[clap(long, value_delimiter = ',', default_value = "http://127.0.0.1:8545")]
+    pub eth_client_address: Vec<String>,
+
+    #[clap(flatten)]
+    pub eth_client_retry_opts: EthClientRetryOpts,
+
+    /// Seconds to wait for the user operation to be included (0 doesn't wait)
+    #[clap(long, default_value = "60")]
+    pub receipt_timeout: u64,
+}
+
+#[derive(Parser)]
+pub struct CheckNodeOpt {
+    // execution client rpc endpoints, comma-separated
+    #[clap(long, value_delimiter = ',', default_value = "http://127.0.0.1:8545")]
+    pub eth_client_address: Vec<String>,
+
+    #[clap(flatten)]
+    pub eth_client_retry_opts: EthClientRetryOpts,
+
+    /// Entry points that are checked to be deployed, the canonical v0.6/v0.7 entry points deployed on the chain if
+    /// none are given
+    #[clap(long, value_delimiter=',', value_parser=parse_checksummed_address)]
+    pub entry_points: Vec<Address>,
+}
+
+#[derive(Parser)]
+pub struct DoctorOpt {
+    /// Account factory with `createAccount(address owner, uint256 salt)` (e.g. `SimpleAccountFactory`) of the
+    /// synthetic account, whose owner is a new random key
+    #[clap(long, value_parser=parse_address)]
+    pub factory: Address,
+
+    /// Entry points that are self-tested, the canonical v0.6/v0.7 entry points deployed on the chain if none are
+    /// given
+    #[clap(long, value_delimiter=',', value_parser=parse_checksummed_address)]
+    pub entry_points: Vec<Address>,
+
+    #[clap(flatten)]
+    pub uopool_opts: UoPoolServiceOpts,
+
+    #[clap(long, value_parser=parse_u256)]
+    pub max_verification_gas: U256,
+
+    // execution client rpc endpoints, comma-separated
+    #[clap(long, value_delimiter = ',', default_value = "http://127.0.0.1:8545")]
+    pub eth_client_address: Vec<String>,
+
+    #[clap(flatten)]
+    pub eth_client_retry_opts: EthClientRetryOpts,
+}
+
+/// Prints the mempools and the reputation (as JSON) of the running uopool or of its snapshot
+pub async fn dump(opt: DumpOpt) -> Result<()> {
+    let snapshot = match opt.snapshot_path {
+        Some(snapshot_path) => UoPoolSnapshot::load(&snapshot_path)?
+            .ok_or_else(|| format_err!("No uopool snapshot at {}", snapshot_path.display()))?,
+        None => {
+            let uopool_address = match opt.uopool_grpc_ipc_path {
+                Some(ipc_path) => UoPoolAddress::Ipc(ipc_path),
+                None => UoPoolAddress::Tcp(opt.uopool_grpc_listen_address),
+            };
+            let mut uopool_grpc_client = UoPoolConnector::new(uopool_address)
+                .with_tls_config(opt.grpc_client_tls_opts.tls_config())
+                .with_auth_token(auth_token(
+                    opt.uopool_grpc_auth_token.as_ref(),
+                    opt.uopool_grpc_auth_token_file.as_deref(),
+                )?)
+                .with_connect_attempts(1)
+                .connect()
+                .await?;
+
+            let chain_id = uopool_grpc_client
+                .get_chain_id(())
+                .await?
+                .into_inner()
+                .chain_id;
+            let entry_points = uopool_grpc_client
+                .get_supported_entry_points(())
+                .await?
+                .into_inner()
+                .eps;
+            let mut snapshot = UoPoolSnapshot {
+                chain_id: chain_id.into(),
+                mempools: vec![],
+            };
+            // the user operations and the reputation are served by the debug methods of the uopool
+            for entry_point in entry_points {
+                let user_operations = uopool_grpc_client
+                    .get_all(GetAllRequest {
+                        ep: Some(entry_point.clone()),
+                        ..Default::default()
+                    })
+                    .await?
+                    .into_inner()
+                    .uos;
+                let reputation = uopool_grpc_client
+                    .get_all_reputation(GetAllReputationRequest {
+                        ep: Some(entry_point.clone()),
+                        ..Default::default()
+                    })
+                    .await?
+                    .into_inner()
+                    .res;
+                snapshot.mempools.push(MempoolSnapshot {
+                    entry_point: entry_point.into(),
+                    user_operations: user_operations
+                        .into_iter()
+                        .map(|user_operation| SnapshotUserOperation {
+                            user_operation: user_operation.into(),
+                            authorization: None,
+                            private: false,
+                        })
+                        .collect(),
+                    reputation: reputation.into_iter().map(Into::into).collect(),
+                });
+            }
+            snapshot
+        }
+    };
+
+    println!("{}", serde_json::to_string_pretty(&snapshot)?);
+    Ok(())
+}
+
+/// Call data of `createAccount(address owner, uint256 salt)` of the account factory
+fn create_account_call(owner: Address, salt: U256) -> Vec<u8> {
+    [
+        &id("createAccount(address,uint256)")[..],
+        &encode(&[Token::Address(owner), Token::Uint(salt)]),
+    ]
+    .concat()
+}
+
+/// Call data of `execute(address dest, uint256 value, bytes func)` of the account
+fn execute_call(dest: Address) -> Vec<u8> {
+    [
+        &id("execute(address,uint256,bytes)")[..],
+        &encode(&[
+            Token::Address(dest),
+            Token::Uint(U256::zero()),
+            Token::Bytes(vec![]),
+        ]),
+    ]
+    .concat()
+}
+
+/// Max fee and max priority fee per gas of the test user operations, the gas price on the chains without EIP-1559
+async fn fees(eth_provider: &EthProvider) -> Result<(U256, U256)> {
+    match eth_provider.estimate_eip1559_fees(None).await {
+        Ok(fees) => Ok(fees),
+        Err(_) => {
+            let gas_price = eth_provider.get_gas_price().await?;
+            Ok((gas_price, gas_price))
+        }
+    }
+}
+
+/// Builds a user operation of the test account that calls its owner with no value, signs it and sends it to the
+/// bundler, for smoke testing of the whole path to the inclusion
+pub async fn send_test_op(opt: SendTestOpOpt) -> Result<()> {
+    let eth_provider = Arc::new(
+        connect_provider(
+            &opt.eth_client_address,
+            opt.eth_client_retry_opts.retry_policy(),
+        )
+        .await?,
+    );
+    let chain_id = eth_provider.get_chainid().await?;
+    let wallet = Wallet::from_file(opt.mnemonic_file.clone(), chain_id)
+        .map_err(|error| format_err!("Could not load mnemonic file: {}", error))?;
+    let owner = wallet.signer.address();
+    let entry_point = EntryPoint::<EthProvider>::new(eth_provider.clone(), opt.entry_point);
+
+    let init_code: Bytes = [
+        opt.factory.as_bytes(),
+        &create_account_call(owner, opt.salt),
+    ]
+    .concat()
+    .into();
+    let sender = entry_point
+        .get_sender_address(init_code.clone())
+        .await
+        .map_err(|error| format_err!("Getting the address of the test account failed: {error}"))?
+        .sender;
+    let deployed = !eth_provider.get_code(sender, None).await?.is_empty();
+    info!("Test account {sender:?} of the owner {owner:?} (deployed: {deployed})");
+
+    if let Some(deposit) = opt.deposit {
+        let balance = entry_point
+            .balance_of(&sender)
+            .await
+            .map_err(|error| format_err!("{error}"))?;
+        if balance < deposit {
+            let client = Arc::new(SignerMiddleware::new(
+                (*eth_provider).clone(),
+                wallet.signer.clone(),
+            ));
+            let receipt = EntryPoint::<SignerMiddleware<EthProvider, LocalWallet>>::new(
+                client,
+                opt.entry_point,
+            )
+            .deposit_to(&sender, deposit - balance)
+            .await
+            .map_err(|error| format_err!("{error}"))?;
+            info!(
+                "Deposited {} wei for the test account in transaction {:?}",
+                deposit - balance,
+                receipt.transaction_hash
+            );
+        }
+    }
+
+    let (max_fee_per_gas, max_priority_fee_per_gas) = fees(&eth_provider).await?;
+    let nonce = entry_point
+        .get_nonce(&sender, U256::zero())
+        .await
+        .map_err(|error| format_err!("{error}"))?;
+    let mut builder = UserOperationBuilder::new(sender, nonce)
+        .call_data(execute_call(owner).into())
+        .fees(max_fee_per_gas, max_priority_fee_per_gas);
+    if !deployed {
+        builder = builder.init_code(init_code);
+    }
+    // the estimation is simulated with a well-formed signature (of the user operation without the gas limits)
+    let mut user_operation = builder
+        .build_signed(&wallet.signer, &opt.entry_point, &chain_id)
+        .await?;
+
+    let rpc = Provider::<Http>::try_from(opt.rpc_address.as_str())?;
+    let estimation: UserOperationGasEstimation = rpc
+        .request(
+            "eth_estimateUserOperationGas",
+            (&user_operation, opt.entry_point),
+        )
+        .await?;
+    user_operation.call_gas_limit = estimation.call_gas_limit;
+    user_operation.verification_gas_limit = estimation.verification_gas_limit;
+    user_operation.pre_verification_gas = estimation.pre_verification_gas;
+    user_operation.signature = user_operation
+        .sign(&wallet.signer, &opt.entry_point, &chain_id)
+        .await?;
+
+    let user_operation_hash: H256 = rpc
+        .request("eth_sendUserOperation", (&user_operation, opt.entry_point))
+        .await?;
+    info!("Sent the test user operation {user_operation_hash:?}");
+
+    if opt.receipt_timeout == 0 {
+        return Ok(());
+    }
+    let receipt = tokio::time::timeout(Duration::from_secs(opt.receipt_timeout), async {
+        loop {
+            let receipt: Option<serde_json::Value> = rpc
+                .request("eth_getUserOperationReceipt", [user_operation_hash])
+                .await?;
+            if let Some(receipt) = receipt {
+                return Ok::<_, anyhow::Error>(receipt);
+            }
+            tokio::time::sleep(RECEIPT_POLL_INTERVAL).await;
+        }
+    })
+    .await
+    .map_err(|_| {
+        format_err!(
+            "Test user operation {user_operation_hash:?} was not included in {}s",
+            opt.receipt_timeout
+        )
+    })??;
+    if receipt["success"] != serde_json::Value::Bool(true) {
+        return Err(format_err!(
+            "Test user operation {user_operation_hash:?} was included, but failed: {receipt}"
+        ));
+    }
+    info!(
+        "Test user operation {user_operation_hash:?} was included in transaction {}",
+        receipt["receipt"]["transactionHash"]
+    );
+    Ok(())
+}
+
+/// Logs the execution client, the chain, the head and the capabilities the bundler depends on
+async fn probe_node(eth_provider: &EthProvider) -> Result<()> {
+    info!(
+        "Execution client at {}: {}",
+        eth_provider.as_ref().active_endpoint(),
+        eth_provider.client_version().await?
+    );
+
+    let chain_id = eth_provider.get_chainid().await?;
+    info!(
+        "Chain id {chain_id} ({} chain profile)",
+        ChainSpec::from_chain_id(chain_id.as_u64()).name
+    );
+    let block_number = eth_provider.get_block_number().await?;
+    info!("Latest block {block_number}");
+
+    let capabilities = ProviderCapabilities::probe(eth_provider).await;
+    info!(
+        "debug_traceCall with JS tracers (validation rules): {}",
+        capabilities.js_tracer
+    );
+    info!(
+        "eth_sendRawTransactionConditional: {}",
+        capabilities.conditional_transactions
+    );
+    info!(
+        "eth_feeHistory (EIP-1559 fees): {}",
+        capabilities.fee_history
+    );
+    if !capabilities.js_tracer {
+        warn!("The validation rules (opcodes, storage access, code hashes) can't be checked on this node");
+    }
+    Ok(())
+}
+
+/// Probes the execution client: the chain, the head, the capabilities the bundler depends on and the entry points
+pub async fn check_node(opt: CheckNodeOpt) -> Result<()> {
+    let eth_provider = Arc::new(
+        connect_provider(
+            &opt.eth_client_address,
+            opt.eth_client_retry_opts.retry_policy(),
+        )
+        .await?,
+    );
+    probe_node(&eth_provider).await?;
+
+    let mut unsupported = vec![];
+    for entry_point in resolve_entry_points(&eth_provider, opt.entry_points).await? {
+        if eth_provider.get_code(entry_point, None).await?.is_empty() {
+            warn!("No entry point deployed at {entry_point:?}");
+            unsupported.push(entry_point);
+            continue;
+        }
+        match EntryPointVersion::detect(eth_provider.as_ref(), entry_point).await {
+            Ok(version) => info!("Entry point {entry_point:?} is {version}"),
+            Err(e) => {
+                warn!("Failed to detect the version of the entry point {entry_point:?}: {e:?}");
+                unsupported.push(entry_point);
+            }
+        }
+    }
+    if !unsupported.is_empty() {
+        return Err(format_err!(
+            "Entry points {unsupported:?} are not deployed or not supported"
+        ));
+    }
+    Ok(())
+}
+
+/// Runs a synthetic user operation (of a new counterfactual account) through the sanity checks and the simulation of
+/// the entry points, without adding it to the mempool, and reports the check that it fails
+pub async fn doctor(opt: DoctorOpt) -> Result<()> {
+    let eth_provider = Arc::new(
+        connect_provider(
+            &opt.eth_client_address,
+            opt.eth_client_retry_opts.retry_policy(),
+        )
+        .await?,
+    );
+    probe_node(&eth_provider).await?;
+    let chain_id = eth_provider.get_chainid().await?;
+    let (max_fee_per_gas, max_priority_fee_per_gas) = fees(&eth_provider).await?;
+
+    let mut failed = vec![];
+    for entry_point in resolve_entry_points(&eth_provider, opt.entry_points).await? {
+        if let Err(e) = EntryPointVersion::detect(eth_provider.as_ref(), entry_point).await {
+            warn!("Entry point {entry_point:?} is not supported: {e:?}");
+            failed.push(entry_point);
+            continue;
+        }
+        let self_test = SelfTest::new(
+            &opt.uopool_opts,
+            eth_provider.clone(),
+            entry_point,
+            opt.max_verification_gas,
+        )
+        .await?;
+        if !self_test.trace_validation() {
+            warn!("Validation rules are not checked for the entry point {entry_point:?}");
+        }
+
+        let owner = LocalWallet::new(&mut thread_rng());
+        let init_code: Bytes = [
+            opt.factory.as_bytes(),
+            &create_account_call(owner.address(), U256::zero()),
+        ]
+        .concat()
+        .into();
+        let sender = match EntryPoint::<EthProvider>::new(eth_provider.clone(), entry_point)
+            .get_sender_address(init_code.clone())
+            .await
+        {
+            Ok(result) => result.sender,
+            Err(e) => {
+                warn!(
+                    "Factory {:?} doesn't create accounts with the entry point {entry_point:?}: {e}",
+                    opt.factory
+                );
+                failed.push(entry_point);
+                continue;
+            }
+        };
+        let mut user_operation = UserOperationBuilder::new(sender, U256::zero())
+            .init_code(init_code)
+            .call_data(execute_call(owner.address()).into())
+            .call_gas_limit(SELF_TEST_CALL_GAS_LIMIT.into())
+            .verification_gas_limit(self_test.max_verification_gas())
+            .fees(max_fee_per_gas, max_priority_fee_per_gas)
+            // placeholder of the signature's length for the pre-verification gas
+            .signature(DUMMY_SIGNATURE.to_vec().into())
+            .build();
+        user_operation.pre_verification_gas = self_test.pre_verification_gas(&user_operation);
+        user_operation.signature = user_operation.sign(&owner, &entry_point, &chain_id).await?;
+
+        match self_test.run(&user_operation).await {
+            Ok(()) => info!("Synthetic user operation passed the verification of the entry point {entry_point:?}"),
+            Err(failure) => {
+                warn!("Synthetic user operation of the entry point {entry_point:?}: {failure}");
+                failed.push(entry_point);
+            }
+        }
+    }
+    if !failed.is_empty() {
+        return Err(format_err!(
+            "Self-test failed for the entry points {failed:?}"
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,134 @@
+use aa_bundler_contracts::ENTRY_POINT_V06_ADDRESS;
+use aa_bundler_primitives::{EthProvider, Wallet};
+use anyhow::{format_err, Result};
+use clap::{builder::ArgPredicate, Command};
+use ethers::{
+    abi::{Abi, Tokenize},
+    contract::ContractFactory,
+    prelude::SignerMiddleware,
+    providers::{Http, Middleware, Provider},
+    signers::Signer,
+    types::{Address, Bytes},
+    utils::{parse_ether, Anvil, AnvilInstance},
+};
+use jsonrpsee::tracing::info;
+use std::{fs, path::Path, sync::Arc};
+
+/// Mnemonic of the pre-funded anvil accounts, used as the bundler's wallet if no mnemonic file is given
+pub const DEV_MNEMONIC: &str = "test test test test test test test test test test test junk";
+
+/// Balance (in ETH) the bundler's account is funded with
+const DEV_BUNDLER_BALANCE: u64 = 1000;
+
+/// Contracts deployed for the local development
+pub struct DevDeployment {
+    pub entry_point: Address,
+    pub account_factory: Address,
+}
+
+/// The bundler's account is funded in the dev mode, so the minimum balance isn't required
+pub fn with_dev_defaults(command: Command) -> Command {
+    command.mut_arg("min_balance", |arg| {
+        arg.required(false)
+            .required_unless_present("dev")
+            .default_value_if("dev", ArgPredicate::IsPresent, "0")
+    })
+}
+
+/// Attaches to the anvil node at the address or spawns one on its port (it's stopped when the instance is dropped)
+pub async fn start_anvil(eth_client_address: &str) -> Result<Option<AnvilInstance>> {
+    let provider = Provider::<Http>::try_from(eth_client_address)?;
+    match provider.client_version().await {
+        Ok(client_version) if client_version.starts_with("anvil") => {
+            info!("Attached to {client_version} at {eth_client_address}");
+            Ok(None)
+        }
+        Ok(client_version) => Err(format_err!(
+            "Dev mode requires anvil, {client_version} is running at {eth_client_address}"
+        )),
+        Err(_) => {
+            let port = provider
+                .url()
+                .port_or_known_default()
+                .ok_or_else(|| format_err!("No port in {eth_client_address}"))?;
+            let anvil = tokio::task::spawn_blocking(move || {
+                Anvil::new().port(port).mnemonic(DEV_MNEMONIC).spawn()
+            })
+            .await
+            .map_err(|_| format_err!("Could not start anvil, is it installed?"))?;
+            info!("Started anvil at {}", anvil.endpoint());
+            Ok(Some(anvil))
+        }
+    }
+}
+
+/// Funds the bundler's account, places the entry point at its canonical address and deploys the sample account
+/// factory (`SimpleAccountFactory`) from the artifacts of the account-abstraction contracts
+pub async fn deploy(
+    eth_provider: Arc<EthProvider>,
+    wallet: &Wallet,
+    artifacts_dir: &Path,
+) -> Result<DevDeployment> {
+    eth_provider
+        .request::<_, ()>(
+            "anvil_setBalance",
+            (wallet.signer.address(), parse_ether(DEV_BUNDLER_BALANCE)?),
+        )
+        .await?;
+    let client = Arc::new(SignerMiddleware::new(
+        (*eth_provider).clone(),
+        wallet.signer.clone(),
+    ));
+
+    let entry_point: Address = ENTRY_POINT_V06_ADDRESS.parse()?;
+    if eth_provider.get_code(entry_point, None).await?.is_empty() {
+        let deployed = deploy_artifact(
+            client.clone(),
+            &artifacts_dir.join("contracts/core/EntryPoint.sol/EntryPoint.json"),
+            (),
+        )
+        .await?;
+        // the code refers to the sender creator deployed by the constructor, so it can be moved as is
+        let code = eth_provider.get_code(deployed, None).await?;
+        eth_provider
+            .request::<_, ()>("anvil_setCode", (entry_point, code))
+            .await?;
+    }
+
+    let account_factory = deploy_artifact(
+        client,
+        &artifacts_dir.join("contracts/samples/SimpleAccountFactory.sol/SimpleAccountFactory.json"),
+        entry_point,
+    )
+    .await?;
+
+    Ok(DevDeployment {
+        entry_point,
+        account_factory,
+    })
+}
+
+/// Deploys the contract of the Hardhat artifact
+async fn deploy_artifact<M: Middleware + 'static, T: Tokenize>(
+    client: Arc<M>,
+    path: &Path,
+    constructor_args: T,
+) -> Result<Address> {
+    let artifact: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(path).map_err(|error| {
+            format_err!(
+                "Could not read {} (run make setup-thirdparty): {error}",
+                path.display()
+            )
+        })?)?;
+    let abi: Abi = serde_json::from_value(artifact["abi"].clone())?;
+    let bytecode: Bytes = serde_json::from_value(artifact["bytecode"].clone())?;
+
+    let (_, receipt) = ContractFactory::new(abi, bytecode, client)
+        .deploy(constructor_args)?
+        .send_with_receipt()
+        .await?;
+    receipt
+        .contract_address
+        .ok_or_else(|| format_err!("{} was not deployed", path.display()))
+}
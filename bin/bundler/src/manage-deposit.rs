@@ -0,0 +1,143 @@
+use aa_bundler_contracts::EntryPoint;
+use aa_bundler_primitives::{
+    connect_provider, parse_address, parse_u256, EthClientRetryOpts, EthProvider, Wallet,
+};
+use anyhow::{format_err, Result};
+use clap::{Parser, Subcommand};
+use ethers::{
+    prelude::SignerMiddleware,
+    providers::Middleware,
+    signers::{LocalWallet, Signer},
+    types::{Address, TransactionReceipt, U256},
+};
+use expanded_pathbuf::ExpandedPathBuf;
+use jsonrpsee::tracing::info;
+use std::sync::Arc;
+
+#[derive(Parser)]
+#[clap(
+    name = "aa-bundler-manage-deposit",
+    about = "Management of the bundler's deposit and stake in the EIP-4337 Account Abstraction entry point"
+)]
+pub struct Opt {
+    #[clap(long)]
+    pub mnemonic_file: ExpandedPathBuf,
+
+    #[clap(long, value_parser=parse_address)]
+    pub entry_point: Address,
+
+    // execution client rpc endpoints, comma-separated
+    #[clap(long, value_delimiter = ',', default_value = "http://127.0.0.1:8545")]
+    pub eth_client_address: Vec<String>,
+
+    #[clap(flatten)]
+    pub eth_client_retry_opts: EthClientRetryOpts,
+
+    #[clap(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Prints the deposit and the stake of the bundler's account
+    Info,
+    /// Adds the amount (in wei) to the deposit of the bundler's account
+    Deposit {
+        #[clap(value_parser=parse_u256)]
+        amount: U256,
+    },
+    /// Withdraws the amount (in wei) from the deposit to the address (the bundler's account by default)
+    Withdraw {
+        #[clap(value_parser=parse_u256)]
+        amount: U256,
+
+        #[clap(long, value_parser=parse_address)]
+        to: Option<Address>,
+    },
+    /// Adds the amount (in wei) to the stake, locked for at least the unstake delay (in seconds)
+    AddStake {
+        #[clap(value_parser=parse_u256)]
+        amount: U256,
+
+        #[clap(long)]
+        unstake_delay: u32,
+    },
+    /// Unlocks the stake, it can be withdrawn once the unstake delay passes
+    UnlockStake,
+    /// Withdraws the unlocked stake to the address (the bundler's account by default)
+    WithdrawStake {
+        #[clap(long, value_parser=parse_address)]
+        to: Option<Address>,
+    },
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let opt: Opt = Opt::parse();
+
+    tracing_subscriber::fmt::init();
+
+    let eth_provider = connect_provider(
+        &opt.eth_client_address,
+        opt.eth_client_retry_opts.retry_policy(),
+    )
+    .await?;
+    let chain_id = eth_provider.get_chainid().await?;
+    let wallet = Wallet::from_file(opt.mnemonic_file.clone(), chain_id)
+        .map_err(|error| format_err!("Could not load mnemonic file: {}", error))?;
+    let account = wallet.signer.address();
+    let client = Arc::new(SignerMiddleware::new(eth_provider, wallet.signer));
+    let entry_point =
+        EntryPoint::<SignerMiddleware<EthProvider, LocalWallet>>::new(client, opt.entry_point);
+
+    let receipt = match opt.command {
+        Command::Info => {
+            let deposit_info = entry_point
+                .get_deposit_info(&account)
+                .await
+                .map_err(|error| format_err!("{error}"))?;
+            info!(
+                "Account {account:?}: deposit {} wei, stake {} wei (staked: {}, unstake delay {}s, withdraw time {})",
+                deposit_info.deposit,
+                deposit_info.stake,
+                deposit_info.staked,
+                deposit_info.unstake_delay_sec,
+                deposit_info.withdraw_time
+            );
+            return Ok(());
+        }
+        Command::Deposit { amount } => entry_point.deposit_to(&account, amount).await,
+        Command::Withdraw { amount, to } => {
+            entry_point
+                .withdraw_to(&to.unwrap_or(account), amount)
+                .await
+        }
+        Command::AddStake {
+            amount,
+            unstake_delay,
+        } => entry_point.add_stake(unstake_delay, amount).await,
+        Command::UnlockStake => entry_point.unlock_stake().await,
+        Command::WithdrawStake { to } => {
+            entry_point
+                .withdraw_stake(&to.unwrap_or(account))
+                .await
+        }
+    }
+    .map_err(|error| format_err!("{error}"))?;
+
+    log_receipt(&receipt)
+}
+
+fn log_receipt(receipt: &TransactionReceipt) -> Result<()> {
+    if receipt.status != Some(1.into()) {
+        return Err(format_err!(
+            "Transaction {:?} reverted",
+            receipt.transaction_hash
+        ));
+    }
+    info!(
+        "Transaction {:?} included in block {:?}",
+        receipt.transaction_hash, receipt.block_number
+    );
+    Ok(())
+}
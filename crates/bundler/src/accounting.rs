@@ -0,0 +1,339 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::PathBuf,
+};
+
+use aa_bundler_contracts::{EntryPointAPIEvents, UserOperationEventFilter};
+use ethers::{
+    contract::EthLogDecode,
+    types::{Address, TransactionReceipt, I256, U256},
+};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::persist::FileWriter;
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// Gas spent and fees earned by the bundler
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountingEntry {
+    pub bundles: u64,
+    pub user_operations: u64,
+    pub gas_used: U256,
+    /// Cost of the bundle transactions
+    pub cost: U256,
+    /// Fees paid by the user operations to the beneficiary
+    pub revenue: U256,
+    /// Bundles included but reverted
+    #[serde(default)]
+    pub reverted: u64,
+    /// Bundles whose cancellation (zero-value transfer to the bundler itself) was included instead
+    #[serde(default)]
+    pub cancelled: u64,
+    /// Gas limit of the bundles, to compare with the gas used
+    #[serde(default)]
+    pub gas_estimated: U256,
+    /// Blocks between the submission and the inclusion of the bundles (divided by `bundles` for the average latency)
+    #[serde(default)]
+    pub inclusion_blocks: u64,
+}
+
+impl AccountingEntry {
+    pub fn profit(&self) -> I256 {
+        I256::from_raw(self.revenue) - I256::from_raw(self.cost)
+    }
+
+    fn add(&mut self, other: &AccountingEntry) {
+        self.bundles += other.bundles;
+        self.user_operations += other.user_operations;
+        self.gas_used = self.gas_used.saturating_add(other.gas_used);
+        self.cost = self.cost.saturating_add(other.cost);
+        self.revenue = self.revenue.saturating_add(other.revenue);
+        self.reverted += other.reverted;
+        self.cancelled += other.cancelled;
+        self.gas_estimated = self.gas_estimated.saturating_add(other.gas_estimated);
+        self.inclusion_blocks += other.inclusion_blocks;
+    }
+}
+
+/// Profit and cost of the included bundles, aggregated per day (days since the Unix epoch) and per entity
+/// (paymaster, or sender for user operations without paymaster). If a path is set, the accounting is written to
+/// the file on every change (atomically, on a background thread) and loaded from it on startup.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Accounting {
+    pub total: AccountingEntry,
+    pub days: BTreeMap<u64, AccountingEntry>,
+    pub entities: HashMap<Address, AccountingEntry>,
+    #[serde(skip)]
+    writer: Option<FileWriter>,
+}
+
+impl Accounting {
+    pub fn new(path: Option<PathBuf>) -> anyhow::Result<Self> {
+        let mut accounting: Accounting = match path {
+            Some(ref path) if path.exists() => serde_json::from_slice(&fs::read(path)?)?,
+            _ => Accounting::default(),
+        };
+        accounting.writer = path.map(|path| FileWriter::new(path, "accounting"));
+        Ok(accounting)
+    }
+
+    /// Records the included bundle transaction, the revenue is taken from `UserOperationEvent`s of the entry point
+    /// and the cost is split between entities by the gas used by their user operations. The gas limit of the bundle
+    /// and the block at which it was sent are only accounted for the bundle (not for the entities).
+    /// Reverted bundle transactions and cancellations (transfers of the bundler to itself) are recorded with their
+    /// cost and zero revenue.
+    pub fn record(
+        &mut self,
+        entry_point: &Address,
+        tx_receipt: &TransactionReceipt,
+        gas_estimated: U256,
+        sent_at_block: u64,
+        timestamp: u64,
+    ) -> AccountingEntry {
+        let reverted = tx_receipt.status != Some(1.into());
+        let cancelled = tx_receipt.to == Some(tx_receipt.from);
+        let events: Vec<UserOperationEventFilter> = tx_receipt
+            .logs
+            .iter()
+            .filter(|log| !reverted && !cancelled && log.address == *entry_point)
+            .filter_map(|log| EntryPointAPIEvents::decode_log(&log.clone().into()).ok())
+            .filter_map(|event| match event {
+                EntryPointAPIEvents::UserOperationEventFilter(event) => Some(event),
+                _ => None,
+            })
+            .collect();
+
+        let gas_used = tx_receipt.gas_used.unwrap_or_default();
+        let cost = gas_used.saturating_mul(tx_receipt.effective_gas_price.unwrap_or_default());
+        let user_operations_gas_used = events.iter().fold(U256::zero(), |gas, event| {
+            gas.saturating_add(event.actual_gas_used)
+        });
+
+        let mut bundle = AccountingEntry {
+            bundles: 1,
+            ..Default::default()
+        };
+        for event in events.iter() {
+            let entity = if event.paymaster.is_zero() {
+                event.sender
+            } else {
+                event.paymaster
+            };
+            let entry = AccountingEntry {
+                bundles: 0,
+                user_operations: 1,
+                gas_used: event.actual_gas_used,
+                cost: if user_operations_gas_used.is_zero() {
+                    U256::zero()
+                } else {
+                    cost.saturating_mul(event.actual_gas_used) / user_operations_gas_used
+                },
+                revenue: event.actual_gas_cost,
+                ..Default::default()
+            };
+            self.entities.entry(entity).or_default().add(&entry);
+            bundle.user_operations += 1;
+            bundle.revenue = bundle.revenue.saturating_add(event.actual_gas_cost);
+        }
+        bundle.gas_used = gas_used;
+        bundle.cost = cost;
+        bundle.reverted = u64::from(reverted && !cancelled);
+        bundle.cancelled = u64::from(cancelled);
+        bundle.gas_estimated = gas_estimated;
+        bundle.inclusion_blocks = tx_receipt
+            .block_number
+            .map(|block_number| block_number.as_u64().saturating_sub(sent_at_block))
+            .unwrap_or_default();
+
+        self.total.add(&bundle);
+        self.days
+            .entry(timestamp / SECONDS_PER_DAY)
+            .or_default()
+            .add(&bundle);
+        self.persist();
+
+        bundle
+    }
+
+    fn persist(&self) {
+        if let Some(ref writer) = self.writer {
+            match serde_json::to_vec(self) {
+                Ok(data) => writer.write(data),
+                Err(e) => warn!("Failed to serialize accounting: {e:?}"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::{
+        abi::{encode, Token},
+        contract::EthEvent,
+        types::{Log, H256},
+    };
+
+    fn user_operation_event(
+        entry_point: Address,
+        sender: Address,
+        paymaster: Address,
+        actual_gas_cost: u64,
+        actual_gas_used: u64,
+    ) -> Log {
+        Log {
+            address: entry_point,
+            topics: vec![
+                UserOperationEventFilter::signature(),
+                H256::random(),
+                sender.into(),
+                paymaster.into(),
+            ],
+            data: encode(&[
+                Token::Uint(U256::zero()),
+                Token::Bool(true),
+                Token::Uint(actual_gas_cost.into()),
+                Token::Uint(actual_gas_used.into()),
+            ])
+            .into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn accounting_record() {
+        let entry_point = Address::random();
+        let sender = Address::random();
+        let paymaster = Address::random();
+        let tx_receipt = TransactionReceipt {
+            logs: vec![
+                user_operation_event(entry_point, sender, Address::zero(), 3000, 100),
+                user_operation_event(entry_point, Address::random(), paymaster, 9000, 300),
+                user_operation_event(Address::random(), sender, Address::zero(), 1000, 100),
+            ],
+            gas_used: Some(500.into()),
+            effective_gas_price: Some(4.into()),
+            status: Some(1.into()),
+            block_number: Some(12.into()),
+            ..Default::default()
+        };
+
+        let mut accounting = Accounting::new(None).unwrap();
+        let entry = accounting.record(
+            &entry_point,
+            &tx_receipt,
+            600.into(),
+            10,
+            SECONDS_PER_DAY * 2 + 1,
+        );
+        assert_eq!(entry.user_operations, 2);
+        assert_eq!(entry.revenue, U256::from(12000));
+        assert_eq!(entry.cost, U256::from(2000));
+        assert_eq!(entry.profit(), I256::from(10000));
+        assert_eq!(entry.reverted, 0);
+        assert_eq!(entry.inclusion_blocks, 2);
+
+        assert_eq!(accounting.entities[&sender].cost, U256::from(500));
+        assert_eq!(accounting.entities[&paymaster].cost, U256::from(1500));
+        assert_eq!(accounting.entities[&paymaster].revenue, U256::from(9000));
+
+        accounting.record(
+            &entry_point,
+            &tx_receipt,
+            600.into(),
+            11,
+            SECONDS_PER_DAY * 2 + 2,
+        );
+        assert_eq!(accounting.days[&2].bundles, 2);
+        assert_eq!(accounting.days[&2].gas_estimated, U256::from(1200));
+        assert_eq!(accounting.days[&2].inclusion_blocks, 3);
+        assert_eq!(accounting.total.revenue, U256::from(24000));
+    }
+
+    #[test]
+    fn reverted_and_cancelled_bundles() {
+        let entry_point = Address::random();
+        let sender = Address::random();
+        let reverted_receipt = TransactionReceipt {
+            to: Some(entry_point),
+            logs: vec![user_operation_event(
+                entry_point,
+                sender,
+                Address::zero(),
+                3000,
+                100,
+            )],
+            gas_used: Some(500.into()),
+            effective_gas_price: Some(4.into()),
+            status: Some(0.into()),
+            block_number: Some(12.into()),
+            ..Default::default()
+        };
+        let bundler = Address::random();
+        let cancellation_receipt = TransactionReceipt {
+            from: bundler,
+            to: Some(bundler),
+            gas_used: Some(21000.into()),
+            effective_gas_price: Some(10.into()),
+            status: Some(1.into()),
+            block_number: Some(13.into()),
+            ..Default::default()
+        };
+
+        let mut accounting = Accounting::new(None).unwrap();
+        let entry = accounting.record(&entry_point, &reverted_receipt, 600.into(), 10, 0);
+        assert_eq!(entry.reverted, 1);
+        assert_eq!(entry.user_operations, 0);
+        assert_eq!(entry.revenue, U256::zero());
+        assert_eq!(entry.profit(), I256::from(-2000));
+
+        let entry = accounting.record(&entry_point, &cancellation_receipt, 600.into(), 10, 0);
+        assert_eq!((entry.reverted, entry.cancelled), (0, 1));
+        assert_eq!(entry.cost, U256::from(210000));
+
+        assert_eq!(accounting.total.bundles, 2);
+        assert_eq!(accounting.total.revenue, U256::zero());
+        assert_eq!(accounting.total.cost, U256::from(212000));
+        assert!(accounting.entities.is_empty());
+    }
+
+    #[test]
+    fn reload() {
+        let dir = std::env::temp_dir().join(format!("bundler-accounting-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("accounting.json");
+        let entry_point = Address::random();
+        let tx_receipt = TransactionReceipt {
+            logs: vec![user_operation_event(
+                entry_point,
+                Address::random(),
+                Address::zero(),
+                3000,
+                100,
+            )],
+            gas_used: Some(500.into()),
+            effective_gas_price: Some(4.into()),
+            status: Some(1.into()),
+            ..Default::default()
+        };
+
+        let mut accounting = Accounting::new(Some(path.clone())).unwrap();
+        accounting.record(&entry_point, &tx_receipt, 600.into(), 10, 0);
+        accounting.record(&entry_point, &tx_receipt, 600.into(), 10, SECONDS_PER_DAY);
+        // the pending write is completed when the accounting is dropped
+        drop(accounting);
+
+        let accounting = Accounting::new(Some(path.clone())).unwrap();
+        assert_eq!(accounting.total.bundles, 2);
+        assert_eq!(accounting.total.revenue, U256::from(6000));
+        assert_eq!(accounting.days.len(), 2);
+        assert!(!path.with_extension("tmp").exists());
+        drop(accounting);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,320 @@
+use aa_bundler_primitives::{Authorization, AuthorizationList, UserOperation};
+use ethers::types::{spoof, Address, U256};
+
+/// Fixed gas overhead of the `handleOps` transaction (intrinsic gas and entry point bookkeeping)
+pub const BUNDLE_GAS_OVERHEAD: u64 = 50000;
+
+/// Size of the `handleOps` calldata without user operations (selector, beneficiary, array offset and length)
+pub const BUNDLE_CALLDATA_OVERHEAD: usize = 4 + 32 * 3;
+
+/// Maximum gas the user operation can use in the bundle (verification gas is used up to 3 times with paymaster)
+pub fn user_operation_gas(user_operation: &UserOperation) -> U256 {
+    let mul = if user_operation.paymaster_and_data.is_empty() {
+        1
+    } else {
+        3
+    };
+    user_operation
+        .call_gas_limit
+        .saturating_add(
+            user_operation
+                .verification_gas_limit
+                .saturating_mul(mul.into()),
+        )
+        .saturating_add(user_operation.pre_verification_gas)
+}
+
+/// User operations that are validated by the same signature aggregator
+/// (zero address is used for user operations without aggregator)
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct UserOperationsPerAggregator {
+    pub aggregator: Address,
+    pub user_operations: Vec<UserOperation>,
+}
+
+/// Bundle of user operations, grouped by signature aggregators in the same order as they are passed to the entry point
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Bundle {
+    pub user_operations_per_aggregator: Vec<UserOperationsPerAggregator>,
+    /// EIP-7702 authorizations of the senders, the bundle is sent as a set code transaction if there are any
+    pub authorizations: AuthorizationList,
+}
+
+impl Bundle {
+    pub fn new(
+        user_operations: Vec<UserOperation>,
+        aggregated: Vec<UserOperationsPerAggregator>,
+    ) -> Self {
+        let mut user_operations_per_aggregator = vec![];
+        if !user_operations.is_empty() {
+            user_operations_per_aggregator.push(UserOperationsPerAggregator {
+                aggregator: Address::zero(),
+                user_operations,
+            });
+        }
+        user_operations_per_aggregator.extend(
+            aggregated
+                .into_iter()
+                .filter(|uos| !uos.user_operations.is_empty()),
+        );
+        Self {
+            user_operations_per_aggregator,
+            authorizations: AuthorizationList::default(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.user_operations_per_aggregator
+            .iter()
+            .map(|uos| uos.user_operations.len())
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Worst-case gas used by the bundle (including the fixed `handleOps` overhead)
+    pub fn gas_limit(&self) -> U256 {
+        self.user_operations_per_aggregator
+            .iter()
+            .flat_map(|uos| uos.user_operations.iter())
+            .fold(U256::from(BUNDLE_GAS_OVERHEAD), |gas, uo| {
+                gas.saturating_add(user_operation_gas(uo))
+            })
+    }
+
+    /// Keeps only user operations (in order) that fit into the gas budget and maximum number of user operations
+    pub fn limit(&mut self, max_bundle_gas: U256, max_ops: Option<usize>) {
+        let mut gas = U256::from(BUNDLE_GAS_OVERHEAD);
+        let mut ops = 0;
+        for uos in self.user_operations_per_aggregator.iter_mut() {
+            uos.user_operations.retain(|uo| {
+                if max_ops.map(|max_ops| ops >= max_ops).unwrap_or(false) {
+                    return false;
+                }
+                let new_gas = gas.saturating_add(user_operation_gas(uo));
+                if new_gas > max_bundle_gas {
+                    return false;
+                }
+                gas = new_gas;
+                ops += 1;
+                true
+            });
+        }
+        self.user_operations_per_aggregator
+            .retain(|uos| !uos.user_operations.is_empty());
+    }
+
+    /// Keeps only user operations (in order) whose ABI-encoded size fits into the maximum calldata size
+    pub fn limit_calldata_size(&mut self, max_calldata_size: usize) {
+        let mut size = BUNDLE_CALLDATA_OVERHEAD;
+        for uos in self.user_operations_per_aggregator.iter_mut() {
+            uos.user_operations.retain(|uo| {
+                let new_size = size + uo.pack().len();
+                if new_size > max_calldata_size {
+                    return false;
+                }
+                size = new_size;
+                true
+            });
+        }
+        self.user_operations_per_aggregator
+            .retain(|uos| !uos.user_operations.is_empty());
+    }
+
+    /// Adds the EIP-7702 authorization of the sender, returns false if it conflicts with another authorization
+    /// of the sender in the bundle or the sender is the bundler itself (authorization nonce would not match)
+    pub fn add_authorization(
+        &mut self,
+        sender: Address,
+        authorization: Authorization,
+        bundler: &Address,
+    ) -> bool {
+        sender != *bundler && self.authorizations.add(sender, authorization)
+    }
+
+    /// Authorizations of the senders whose user operations are in the bundle
+    pub fn authorization_list(&self) -> Vec<Authorization> {
+        self.authorizations.for_senders(
+            self.user_operations_per_aggregator
+                .iter()
+                .flat_map(|uos| uos.user_operations.iter().map(|uo| &uo.sender)),
+        )
+    }
+
+    /// State override with the EIP-7702 delegations of the senders in the bundle, which are applied only by the bundle
+    /// transaction itself (`None` if there are no authorizations)
+    pub fn state_override(&self) -> Option<spoof::State> {
+        let mut state_override = spoof::State::default();
+        let mut delegated = false;
+        for uos in self.user_operations_per_aggregator.iter() {
+            for uo in uos.user_operations.iter() {
+                if let Some(authorization) = self.authorizations.get(&uo.sender) {
+                    authorization.apply(uo.sender, &mut state_override);
+                    delegated = true;
+                }
+            }
+        }
+        delegated.then_some(state_override)
+    }
+
+    /// Removes all user operations of the sender
+    pub fn remove_sender(&mut self, sender: &Address) -> Vec<UserOperation> {
+        let mut removed = vec![];
+        for uos in self.user_operations_per_aggregator.iter_mut() {
+            uos.user_operations.retain(|uo| {
+                if uo.sender == *sender {
+                    removed.push(uo.clone());
+                    return false;
+                }
+                true
+            });
+        }
+        self.user_operations_per_aggregator
+            .retain(|uos| !uos.user_operations.is_empty());
+        removed
+    }
+
+    /// Whether the bundle has to be sent with `handleAggregatedOps`
+    pub fn is_aggregated(&self) -> bool {
+        self.user_operations_per_aggregator
+            .iter()
+            .any(|uos| !uos.aggregator.is_zero())
+    }
+
+    /// All user operations in the bundle, in the order used by the entry point (e.g. for `FailedOp` index)
+    pub fn user_operations(&self) -> Vec<UserOperation> {
+        self.user_operations_per_aggregator
+            .iter()
+            .flat_map(|uos| uos.user_operations.iter().cloned())
+            .collect()
+    }
+
+    /// Removes the user operation at the given (entry point) index
+    pub fn remove(&mut self, mut index: usize) -> Option<UserOperation> {
+        for (i, uos) in self.user_operations_per_aggregator.iter_mut().enumerate() {
+            if index < uos.user_operations.len() {
+                let user_operation = uos.user_operations.remove(index);
+                if uos.user_operations.is_empty() {
+                    self.user_operations_per_aggregator.remove(i);
+                }
+                return Some(user_operation);
+            }
+            index -= uos.user_operations.len();
+        }
+        None
+    }
+}
+
+impl From<Vec<UserOperation>> for Bundle {
+    fn from(user_operations: Vec<UserOperation>) -> Self {
+        Self::new(user_operations, vec![])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bundle_remove() {
+        let user_operations: Vec<UserOperation> = (0..4).map(|_| UserOperation::random()).collect();
+        let aggregator = Address::random();
+        let mut bundle = Bundle::new(
+            user_operations[0..1].to_vec(),
+            vec![UserOperationsPerAggregator {
+                aggregator,
+                user_operations: user_operations[1..].to_vec(),
+            }],
+        );
+
+        assert_eq!(bundle.len(), 4);
+        assert!(bundle.is_aggregated());
+        assert_eq!(bundle.user_operations(), user_operations);
+
+        assert_eq!(bundle.remove(2), Some(user_operations[2].clone()));
+        assert_eq!(bundle.remove(0), Some(user_operations[0].clone()));
+        assert_eq!(bundle.user_operations_per_aggregator.len(), 1);
+        assert_eq!(bundle.remove(2), None);
+        assert_eq!(
+            bundle.user_operations(),
+            vec![user_operations[1].clone(), user_operations[3].clone()]
+        );
+    }
+
+    #[test]
+    fn bundle_limit() {
+        let user_operations: Vec<UserOperation> = (0..4).map(|_| UserOperation::random()).collect();
+        let user_operation_gas = user_operation_gas(&user_operations[0]);
+
+        let mut bundle = Bundle::from(user_operations.clone());
+        assert_eq!(
+            bundle.gas_limit(),
+            user_operation_gas * 4 + BUNDLE_GAS_OVERHEAD
+        );
+        bundle.limit(user_operation_gas * 3 + BUNDLE_GAS_OVERHEAD, None);
+        assert_eq!(bundle.user_operations(), user_operations[0..3].to_vec());
+
+        let mut bundle = Bundle::from(user_operations.clone());
+        bundle.limit(U256::MAX, Some(2));
+        assert_eq!(bundle.user_operations(), user_operations[0..2].to_vec());
+
+        let mut bundle = Bundle::from(user_operations.clone());
+        bundle.limit(U256::from(BUNDLE_GAS_OVERHEAD), None);
+        assert!(bundle.is_empty());
+        assert!(bundle.user_operations_per_aggregator.is_empty());
+
+        let mut bundle = Bundle::from(user_operations.clone());
+        bundle.limit_calldata_size(BUNDLE_CALLDATA_OVERHEAD + user_operations[0].pack().len());
+        assert_eq!(bundle.user_operations(), user_operations[0..1].to_vec());
+    }
+
+    #[test]
+    fn bundle_authorizations() {
+        let user_operations: Vec<UserOperation> = (0..3).map(|_| UserOperation::random()).collect();
+        let authorization = |address: Address| Authorization {
+            chain_id: U256::zero(),
+            address,
+            nonce: U256::zero(),
+            y_parity: Default::default(),
+            r: U256::one(),
+            s: U256::one(),
+        };
+        let bundler = Address::random();
+        let delegate = Address::random();
+
+        let mut bundle = Bundle::from(user_operations.clone());
+        assert!(bundle.add_authorization(
+            user_operations[0].sender,
+            authorization(delegate),
+            &bundler
+        ));
+        assert!(bundle.add_authorization(
+            user_operations[1].sender,
+            authorization(delegate),
+            &bundler
+        ));
+        assert!(!bundle.add_authorization(
+            user_operations[1].sender,
+            authorization(Address::random()),
+            &bundler
+        ));
+        assert!(!bundle.add_authorization(bundler, authorization(delegate), &bundler));
+        assert_eq!(bundle.authorization_list().len(), 2);
+
+        assert_eq!(
+            bundle.remove_sender(&user_operations[1].sender),
+            vec![user_operations[1].clone()]
+        );
+        assert_eq!(bundle.authorization_list(), vec![authorization(delegate)]);
+
+        let state_override = serde_json::to_value(bundle.state_override().unwrap()).unwrap();
+        assert_eq!(state_override.as_object().unwrap().len(), 1);
+        assert_eq!(
+            state_override[format!("{:?}", user_operations[0].sender)]["code"],
+            serde_json::to_value(authorization(delegate).delegation_code()).unwrap()
+        );
+        assert!(Bundle::from(user_operations).state_override().is_none());
+    }
+}
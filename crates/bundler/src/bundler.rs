@@ -1,14 +1,183 @@
-use std::{sync::Arc, time::Duration};
+use std::{fmt, sync::Arc, time::Duration};
 
-use aa_bundler_contracts::EntryPointAPI;
-use aa_bundler_primitives::{UserOperation, Wallet};
+use aa_bundler_contracts::{
+    AggregatorAPI, EntryPoint, EntryPointAPI, EntryPointErr, UserOpsPerAggregator,
+};
+use aa_bundler_primitives::{Authorization, BundleAttempt, ChainSpec, EthProvider, Wallet};
 use ethers::{
     prelude::SignerMiddleware,
-    providers::{Http, Middleware, Provider},
-    signers::Signer,
-    types::{transaction::eip2718::TypedTransaction, Address, H256, U256},
+    providers::Middleware,
+    signers::{LocalWallet, Signer},
+    types::{
+        transaction::eip2718::TypedTransaction, Address, BlockNumber, Bytes, TransactionReceipt,
+        H256, I256, U256, U64,
+    },
+    utils::{hex, keccak256, to_checksum},
+};
+use reqwest::header::CONTENT_TYPE;
+use serde_json::json;
+use tokio::sync::Mutex;
+use tracing::{info, trace, warn};
+
+use crate::{
+    bundle::{user_operation_gas, Bundle},
+    eip7702,
+    nonce_manager::{NonceManager, PendingAction},
 };
-use tracing::{info, trace};
+
+const RECEIPT_POLL_INTERVAL: Duration = Duration::from_millis(75);
+/// Number of past blocks that are searched for user operations included by other bundlers
+const INCLUSION_SCAN_DEPTH: u64 = 1000;
+/// Blocks after the bundle was built in which its conditional transaction can be included
+const CONDITIONAL_BLOCK_RANGE: u64 = 10;
+/// Header with the searcher's signature of the `eth_sendBundle` request, required by the Flashbots relay
+const FLASHBOTS_SIGNATURE_HEADER: &str = "X-Flashbots-Signature";
+
+/// Bundle transaction that was included without the user operations: it reverted on chain, or its cancellation was
+/// included instead
+#[derive(Debug)]
+pub struct UnsuccessfulBundle {
+    pub tx_receipt: TransactionReceipt,
+    /// Block at which the first transaction of the bundle was sent
+    pub sent_at_block: U64,
+}
+
+impl fmt::Display for UnsuccessfulBundle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.tx_receipt.to == Some(self.tx_receipt.from) {
+            write!(
+                f,
+                "Bundle transaction was cancelled, cancellation {:?} was included",
+                self.tx_receipt.transaction_hash
+            )
+        } else {
+            write!(
+                f,
+                "Bundle transaction {:?} reverted on chain",
+                self.tx_receipt.transaction_hash
+            )
+        }
+    }
+}
+
+impl std::error::Error for UnsuccessfulBundle {}
+
+/// How the bundle transactions are submitted
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum Submission {
+    /// Regular transaction sent through the execution client (public mempool)
+    #[default]
+    Mempool,
+    /// Signed transaction sent to the block builder (or relay) with `eth_sendBundle`, targeting the next block
+    Builder(BuilderEndpoint),
+    /// Signed transaction sent with `eth_sendRawTransactionConditional`, which is dropped instead of being included
+    /// too late (a stale bundle would revert)
+    Conditional,
+}
+
+/// Block builder (or relay) that receives the bundles. The requests are signed with the searcher key, which identifies
+/// the bundler to the relay (`X-Flashbots-Signature` header), independently of the bundler's signer.
+#[derive(Clone, Debug)]
+pub struct BuilderEndpoint {
+    pub url: String,
+    pub searcher: LocalWallet,
+    client: reqwest::Client,
+}
+
+impl BuilderEndpoint {
+    pub fn new(url: String, searcher: LocalWallet) -> Self {
+        Self {
+            url,
+            searcher,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// Value of the `X-Flashbots-Signature` header of the request body: `<address>:<signature>`, where the signature
+    /// is the EIP-191 signature of the hex encoded `keccak256(body)`
+    pub async fn signature(&self, body: &[u8]) -> anyhow::Result<String> {
+        let signature = self
+            .searcher
+            .sign_message(format!("0x{}", hex::encode(keccak256(body))))
+            .await?;
+        Ok(format!(
+            "{}:0x{signature}",
+            to_checksum(&self.searcher.address(), None)
+        ))
+    }
+
+    /// Sends the signed JSON-RPC request to the builder and returns its result
+    async fn request(
+        &self,
+        method: &str,
+        params: serde_json::Value,
+    ) -> anyhow::Result<serde_json::Value> {
+        let body = serde_json::to_vec(&json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": method,
+            "params": params,
+        }))?;
+        let mut res: serde_json::Value = self
+            .client
+            .post(&self.url)
+            .header(CONTENT_TYPE, "application/json")
+            .header(FLASHBOTS_SIGNATURE_HEADER, self.signature(&body).await?)
+            .body(body)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        if let Some(error) = res.get("error") {
+            return Err(anyhow::anyhow!("{error}"));
+        }
+        Ok(res["result"].take())
+    }
+}
+
+impl PartialEq for BuilderEndpoint {
+    fn eq(&self, other: &Self) -> bool {
+        self.url == other.url && self.searcher.address() == other.searcher.address()
+    }
+}
+
+impl Eq for BuilderEndpoint {}
+
+/// Estimated economics of the bundle transaction
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BundleEstimate {
+    pub gas: U256,
+    /// Fees paid by the user operations to the beneficiary
+    pub revenue: U256,
+    /// Cost of the bundle transaction
+    pub cost: U256,
+}
+
+impl BundleEstimate {
+    pub fn profit(&self) -> I256 {
+        I256::from_raw(self.revenue) - I256::from_raw(self.cost)
+    }
+}
+
+/// Estimates max fee per gas and max priority fee per gas (both are the gas price on chains with legacy gas pricing
+/// or if the fee history isn't available)
+pub async fn estimate_fees<M: Middleware>(
+    provider: &M,
+    chain_spec: &ChainSpec,
+) -> anyhow::Result<(U256, U256)> {
+    if chain_spec.legacy_gas || !chain_spec.fee_history {
+        let gas_price = provider
+            .get_gas_price()
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to get gas price: {e:?}"))?;
+        return Ok((gas_price, gas_price));
+    }
+    provider
+        .estimate_eip1559_fees(None)
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to estimate fees: {e:?}"))
+}
 
 #[derive(Clone)]
 pub struct Bundler {
@@ -16,7 +185,10 @@ pub struct Bundler {
     pub beneficiary: Address,
     pub entry_point: Address,
     pub chain_id: U256,
-    pub eth_client_address: String,
+    pub chain_spec: ChainSpec,
+    pub eth_provider: EthProvider,
+    pub nonce_manager: Arc<Mutex<NonceManager>>,
+    pub submission: Submission,
 }
 
 impl Bundler {
@@ -24,53 +196,581 @@ impl Bundler {
         wallet: Wallet,
         beneficiary: Address,
         entry_point: Address,
-        chain_id: U256,
-        eth_client_address: String,
+        chain_spec: ChainSpec,
+        eth_provider: EthProvider,
+        nonce_manager: Arc<Mutex<NonceManager>>,
+        submission: Submission,
     ) -> Self {
         Self {
             wallet,
             beneficiary,
             entry_point,
-            chain_id,
-            eth_client_address,
+            chain_id: chain_spec.chain_id.into(),
+            chain_spec,
+            eth_provider,
+            nonce_manager,
+            submission,
+        }
+    }
+
+    /// Signs the (filled) transaction, as a set code (EIP-7702) transaction if the authorization list is not empty
+    async fn sign_transaction(
+        signer: &LocalWallet,
+        tx: &TypedTransaction,
+        authorization_list: &[Authorization],
+    ) -> anyhow::Result<Bytes> {
+        if authorization_list.is_empty() {
+            let signature = signer.sign_transaction(tx).await?;
+            return Ok(tx.rlp_signed(&signature));
+        }
+
+        let tx = match tx {
+            TypedTransaction::Eip1559(tx) => tx,
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "EIP-7702 authorizations require EIP-1559 bundle transaction"
+                ))
+            }
+        };
+        let signature = signer.sign_hash(eip7702::sighash(tx, authorization_list)?)?;
+        eip7702::rlp_signed(tx, authorization_list, &signature)
+    }
+
+    /// Submits the (filled) transaction with the configured submission backend and returns its hash
+    async fn submit(
+        &self,
+        client: &SignerMiddleware<EthProvider, LocalWallet>,
+        tx: TypedTransaction,
+        authorization_list: &[Authorization],
+        block_number: U64,
+    ) -> anyhow::Result<H256> {
+        match self.submission {
+            Submission::Mempool if authorization_list.is_empty() => {
+                Ok(client.send_transaction(tx, None).await?.tx_hash())
+            }
+            Submission::Mempool => {
+                let raw_tx =
+                    Self::sign_transaction(client.signer(), &tx, authorization_list).await?;
+                Ok(client.send_raw_transaction(raw_tx).await?.tx_hash())
+            }
+            Submission::Builder(ref builder) => {
+                let raw_tx =
+                    Self::sign_transaction(client.signer(), &tx, authorization_list).await?;
+                let tx_hash = H256::from(keccak256(&raw_tx));
+
+                let res = builder
+                    .request(
+                        "eth_sendBundle",
+                        json!([{
+                            "txs": [raw_tx],
+                            "blockNumber": block_number + 1,
+                        }]),
+                    )
+                    .await
+                    .map_err(|e| {
+                        anyhow::anyhow!("Failed to submit bundle to builder {}: {e:?}", builder.url)
+                    })?;
+                trace!("Bundle transaction {tx_hash:?} submitted to builder: {res:?}");
+
+                Ok(tx_hash)
+            }
+            Submission::Conditional => {
+                let raw_tx =
+                    Self::sign_transaction(client.signer(), &tx, authorization_list).await?;
+                let tx_hash: H256 = client
+                    .provider()
+                    .request(
+                        "eth_sendRawTransactionConditional",
+                        (
+                            raw_tx,
+                            json!({ "blockNumberMax": block_number + CONDITIONAL_BLOCK_RANGE }),
+                        ),
+                    )
+                    .await
+                    .map_err(|e| {
+                        anyhow::anyhow!("Failed to submit conditional transaction: {e:?}")
+                    })?;
+                Ok(tx_hash)
+            }
+        }
+    }
+
+    /// Calls the aggregators to aggregate signatures of their user operations
+    async fn user_ops_per_aggregator(
+        &self,
+        client: Arc<SignerMiddleware<EthProvider, LocalWallet>>,
+        bundle: &Bundle,
+    ) -> anyhow::Result<Vec<UserOpsPerAggregator>> {
+        let mut user_ops_per_aggregator = vec![];
+        for uos in bundle.user_operations_per_aggregator.iter() {
+            let signature = if uos.aggregator.is_zero() {
+                Default::default()
+            } else {
+                AggregatorAPI::new(uos.aggregator, client.clone())
+                    .aggregate_signatures(
+                        uos.user_operations
+                            .iter()
+                            .cloned()
+                            .map(Into::into)
+                            .collect(),
+                    )
+                    .call()
+                    .await
+                    .map_err(|e| {
+                        anyhow::anyhow!(
+                            "Failed to aggregate signatures with aggregator {:?}: {e:?}",
+                            uos.aggregator
+                        )
+                    })?
+            };
+            user_ops_per_aggregator.push(UserOpsPerAggregator {
+                user_ops: uos
+                    .user_operations
+                    .iter()
+                    .cloned()
+                    .map(Into::into)
+                    .collect(),
+                aggregator: uos.aggregator,
+                signature,
+            });
+        }
+        Ok(user_ops_per_aggregator)
+    }
+
+    /// Simulates the bundle with `handleOps` (or `handleAggregatedOps`) call, returning index and reason of the
+    /// user operation that caused `FailedOp` revert (if any)
+    pub async fn simulate_bundle(
+        &self,
+        bundle: &Bundle,
+    ) -> anyhow::Result<Option<(usize, String)>> {
+        let provider = self.eth_provider.clone();
+        let client = Arc::new(SignerMiddleware::new(provider, self.wallet.signer.clone()));
+        let entry_point = EntryPoint::new(client.clone(), self.entry_point);
+
+        // the senders that are delegated by the bundle transaction itself have no code yet
+        let state_override = bundle.state_override();
+        let res = if bundle.is_aggregated() {
+            let user_ops_per_aggregator = self.user_ops_per_aggregator(client, bundle).await?;
+            entry_point
+                .handle_aggregated_ops(
+                    user_ops_per_aggregator,
+                    self.beneficiary,
+                    state_override.as_ref(),
+                )
+                .await
+        } else {
+            entry_point
+                .handle_ops(
+                    bundle.user_operations(),
+                    self.beneficiary,
+                    state_override.as_ref(),
+                )
+                .await
+        };
+
+        match res {
+            Ok(_) => Ok(None),
+            Err(EntryPointErr::FailedOp(failed_op)) => {
+                trace!("Bundle simulation failed with: {failed_op:?}");
+                Ok(Some((failed_op.op_index.as_usize(), failed_op.reason)))
+            }
+            Err(e) => Err(anyhow::anyhow!("Bundle simulation failed: {e:?}")),
         }
     }
 
-    pub async fn send_next_bundle(&self, bundle: &Vec<UserOperation>) -> anyhow::Result<H256> {
+    /// Indexes of the user operations in the bundle that were already included on chain (e.g. by another
+    /// bundler that picked them up from the shared mempool)
+    pub async fn included_user_operations(&self, bundle: &Bundle) -> anyhow::Result<Vec<usize>> {
+        let provider = Arc::new(self.eth_provider.clone());
+        let user_operation_hashes: Vec<H256> = bundle
+            .user_operations()
+            .iter()
+            .map(|uo| uo.hash(&self.entry_point, &self.chain_id).into())
+            .collect();
+        if user_operation_hashes.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let block_number = provider.get_block_number().await?;
+        let events = EntryPointAPI::new(self.entry_point, provider)
+            .user_operation_event_filter()
+            .topic1(user_operation_hashes.clone())
+            .from_block(block_number.saturating_sub(INCLUSION_SCAN_DEPTH.into()))
+            .query()
+            .await?;
+
+        Ok(user_operation_hashes
+            .iter()
+            .enumerate()
+            .filter(|(_, hash)| {
+                events
+                    .iter()
+                    .any(|event| H256::from(event.user_op_hash) == **hash)
+            })
+            .map(|(index, _)| index)
+            .collect())
+    }
+
+    /// Builds the `handleOps` (or `handleAggregatedOps`) transaction for the bundle
+    async fn bundle_transaction(
+        &self,
+        client: Arc<SignerMiddleware<EthProvider, LocalWallet>>,
+        bundle: &Bundle,
+    ) -> anyhow::Result<TypedTransaction> {
+        let entry_point = EntryPointAPI::new(self.entry_point, client.clone());
+        let call = if bundle.is_aggregated() {
+            let user_ops_per_aggregator = self.user_ops_per_aggregator(client, bundle).await?;
+            entry_point.handle_aggregated_ops(user_ops_per_aggregator, self.beneficiary)
+        } else {
+            entry_point.handle_ops(
+                bundle
+                    .user_operations()
+                    .into_iter()
+                    .map(Into::into)
+                    .collect(),
+                self.beneficiary,
+            )
+        };
+        Ok(if self.chain_spec.legacy_gas {
+            call.legacy().tx
+        } else {
+            call.tx
+        })
+    }
+
+    /// Estimates gas, fees paid by the user operations (to the beneficiary) and the transaction cost of the bundle,
+    /// without sending it
+    pub async fn estimate_bundle(&self, bundle: &Bundle) -> anyhow::Result<BundleEstimate> {
+        let provider = self.eth_provider.clone();
+        let client = Arc::new(SignerMiddleware::new(
+            provider.clone(),
+            self.wallet.signer.clone(),
+        ));
+        let tx = self.bundle_transaction(client.clone(), bundle).await?;
+
+        let gas = client.estimate_gas(&tx, None).await?;
+        let base_fee = provider
+            .get_block(BlockNumber::Latest)
+            .await?
+            .and_then(|block| block.base_fee_per_gas)
+            .unwrap_or_default();
+        let (max_fee_per_gas, max_priority_fee_per_gas) =
+            estimate_fees(&provider, &self.chain_spec).await?;
+
+        // user operations pay for the gas they actually use, which is approximated by scaling their gas limits
+        let gas_limit = bundle.gas_limit();
+        let revenue = bundle
+            .user_operations()
+            .iter()
+            .fold(U256::zero(), |revenue, uo| {
+                let gas_price = uo
+                    .max_fee_per_gas
+                    .min(base_fee.saturating_add(uo.max_priority_fee_per_gas));
+                revenue.saturating_add(
+                    user_operation_gas(uo)
+                        .saturating_mul(gas_price)
+                        .saturating_mul(gas)
+                        / gas_limit,
+                )
+            });
+        let cost = gas
+            .saturating_mul(max_fee_per_gas.min(base_fee.saturating_add(max_priority_fee_per_gas)));
+
+        Ok(BundleEstimate { gas, revenue, cost })
+    }
+
+    /// Sends the bundle and waits until it's included, `on_attempt` is called for each transaction sent for the bundle
+    /// (original, replacements and cancellation)
+    pub async fn send_next_bundle<F: Fn(BundleAttempt) + Sync>(
+        &self,
+        bundle: &Bundle,
+        on_attempt: F,
+    ) -> anyhow::Result<H256> {
         info!(
             "Creating the next bundle, got {} user operations",
             bundle.len()
         );
-        let provider = Provider::<Http>::try_from(self.eth_client_address.clone())?;
+        let provider = self.eth_provider.clone();
         let client = Arc::new(SignerMiddleware::new(
             provider.clone(),
             self.wallet.signer.clone(),
         ));
-        let entry_point = EntryPointAPI::new(self.entry_point, client.clone());
-        let nonce = client
-            .clone()
-            .get_transaction_count(self.wallet.signer.address(), None)
-            .await?;
-        let mut tx: TypedTransaction = entry_point
-            .handle_ops(
-                bundle.clone().into_iter().map(Into::into).collect(),
-                self.beneficiary,
-            )
-            .tx
-            .clone();
-        tx.set_nonce(nonce).set_chain_id(self.chain_id.as_u64());
+        let mut tx = self.bundle_transaction(client.clone(), bundle).await?;
+        let authorization_list = bundle.authorization_list();
+        if !authorization_list.is_empty() {
+            // gas can't be estimated without the authorizations applied, since the senders might not be delegated yet
+            tx.set_gas(bundle.gas_limit().saturating_add(
+                U256::from(eip7702::PER_AUTHORIZATION_GAS) * authorization_list.len(),
+            ));
+        }
 
-        trace!("Prepare the transaction {tx:?} send to execution client!");
-        let tx = client
-            .send_transaction(tx, None)
-            .await?
-            .interval(Duration::from_millis(75));
-        let tx_hash = tx.tx_hash();
-        trace!("Send bundle with transaction: {tx:?}");
+        let nonce = {
+            let mut nonce_manager = self.nonce_manager.lock().await;
+            let nonce = nonce_manager.next_nonce(&provider).await?;
+            tx.set_nonce(nonce).set_chain_id(self.chain_id.as_u64());
+            client.fill_transaction(&mut tx, None).await?;
+
+            trace!("Prepare the transaction {tx:?} send to execution client!");
+            let block_number = client.get_block_number().await?;
+            let tx_hash = self
+                .submit(&client, tx.clone(), &authorization_list, block_number)
+                .await?;
+            trace!("Send bundle with transaction: {tx_hash:?}");
+
+            on_attempt(nonce_manager.track(nonce, tx, tx_hash, block_number));
+            nonce
+        };
 
-        let tx_receipt = tx.await?;
-        trace!("Bundle transaction receipt: {tx_receipt:?}");
+        self.wait_for_inclusion(&client, nonce, bundle, &authorization_list, &on_attempt)
+            .await
+    }
+
+    /// Waits until one of the transactions with the given nonce is included,
+    /// replacing (or cancelling) the transaction if it gets stuck
+    async fn wait_for_inclusion<F: Fn(BundleAttempt) + Sync>(
+        &self,
+        client: &SignerMiddleware<EthProvider, LocalWallet>,
+        nonce: U256,
+        bundle: &Bundle,
+        authorization_list: &[Authorization],
+        on_attempt: &F,
+    ) -> anyhow::Result<H256> {
+        let mut last_block = client.get_block_number().await?;
+        loop {
+            tokio::time::sleep(RECEIPT_POLL_INTERVAL).await;
+
+            let pending = match self.nonce_manager.lock().await.get(&nonce) {
+                Some(pending) => pending.clone(),
+                None => {
+                    return Err(anyhow::anyhow!(
+                        "Bundle transaction with nonce {nonce:?} is not tracked anymore"
+                    ))
+                }
+            };
+            // cancellations are plain transactions to the bundler itself, without the authorizations
+            let authorization_list = if pending.cancel_tx_hash.is_some() {
+                &[][..]
+            } else {
+                authorization_list
+            };
+
+            for tx_hash in pending.tx_hashes().iter() {
+                if let Some(tx_receipt) = client.get_transaction_receipt(*tx_hash).await? {
+                    trace!("Bundle transaction receipt: {tx_receipt:?}");
+                    self.nonce_manager.lock().await.remove(&nonce);
+                    if pending.cancel_tx_hash == Some(*tx_hash)
+                        || tx_receipt.status == Some(0.into())
+                    {
+                        return Err(UnsuccessfulBundle {
+                            tx_receipt,
+                            sent_at_block: pending.attempts[0].block_number,
+                        }
+                        .into());
+                    }
+                    return Ok(*tx_hash);
+                }
+            }
+
+            let block_number = client.get_block_number().await?;
+            if block_number == last_block {
+                continue;
+            }
+            last_block = block_number;
+
+            if client
+                .get_transaction_count(self.wallet.signer.address(), None)
+                .await?
+                > nonce
+            {
+                // one of the transactions was included, receipt is picked up in the next iteration
+                continue;
+            }
+
+            let (max_fee_per_gas, max_priority_fee_per_gas) =
+                estimate_fees(client, &self.chain_spec).await?;
+            let action = self.nonce_manager.lock().await.action(
+                &nonce,
+                block_number,
+                max_fee_per_gas,
+                max_priority_fee_per_gas,
+            );
+            // replacement would revert on chain if some of the user operations were included by another bundler
+            let frontrun = matches!(action, PendingAction::Replace(_))
+                && !self.included_user_operations(bundle).await?.is_empty();
+
+            let mut nonce_manager = self.nonce_manager.lock().await;
+            let (tx, authorization_list) = match action {
+                PendingAction::Wait => {
+                    // bundles sent to the builder are valid only for the targeted block
+                    if let Submission::Builder(_) = self.submission {
+                        if let Err(e) = self
+                            .submit(client, pending.tx.clone(), authorization_list, block_number)
+                            .await
+                        {
+                            warn!("Failed to resubmit bundle transaction to builder: {e:?}");
+                        }
+                    }
+                    continue;
+                }
+                PendingAction::Replace(_) if frontrun => {
+                    match nonce_manager.cancellation(
+                        &nonce,
+                        max_fee_per_gas,
+                        max_priority_fee_per_gas,
+                    ) {
+                        Some(tx) => {
+                            warn!(
+                                "User operations of bundle transaction {:?} were included by another bundler, cancelling it",
+                                pending.tx_hash()
+                            );
+                            (tx, &[][..])
+                        }
+                        None => continue,
+                    }
+                }
+                PendingAction::Replace(tx) => {
+                    warn!(
+                        "Bundle transaction {:?} is stuck, replacing it with higher fees",
+                        pending.tx_hash()
+                    );
+                    (tx, authorization_list)
+                }
+                PendingAction::Cancel(tx) => {
+                    warn!(
+                        "Bundle transaction {:?} is stuck after {} fee bumps, cancelling it",
+                        pending.tx_hash(),
+                        pending.fee_bumps
+                    );
+                    (tx, &[][..])
+                }
+            };
 
-        Ok(tx_hash)
+            match self
+                .submit(client, tx.clone(), authorization_list, block_number)
+                .await
+            {
+                Ok(tx_hash) => {
+                    if let Some(attempt) = nonce_manager.replace(nonce, tx, tx_hash, block_number) {
+                        on_attempt(attempt);
+                    }
+                }
+                Err(e) => warn!("Failed to replace bundle transaction: {e:?}"),
+            }
+        }
+    }
+
+    /// Waits until the included bundle transaction reaches the given number of confirmations
+    pub async fn wait_for_confirmations(
+        &self,
+        tx_hash: H256,
+        confirmations: u64,
+    ) -> anyhow::Result<TransactionReceipt> {
+        let provider = self.eth_provider.clone();
+        loop {
+            let tx_receipt = provider
+                .get_transaction_receipt(tx_hash)
+                .await?
+                .ok_or_else(|| {
+                    anyhow::anyhow!("Bundle transaction {tx_hash:?} is not included anymore")
+                })?;
+            let included_at = tx_receipt.block_number.ok_or_else(|| {
+                anyhow::anyhow!("Bundle transaction {tx_hash:?} receipt without block number")
+            })?;
+            let block_number = provider.get_block_number().await?;
+
+            if block_number.saturating_sub(included_at).as_u64() + 1 >= confirmations {
+                trace!("Bundle transaction {tx_hash:?} reached {confirmations} confirmations");
+                return Ok(tx_receipt);
+            }
+
+            tokio::time::sleep(self.chain_spec.inclusion_latency).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::{
+        types::{Eip1559TransactionRequest, Signature},
+        utils::rlp,
+    };
+
+    use super::*;
+
+    #[test]
+    fn unsuccessful_bundle() {
+        let bundler = Address::random();
+        let error: anyhow::Error = UnsuccessfulBundle {
+            tx_receipt: TransactionReceipt {
+                from: bundler,
+                to: Some(bundler),
+                ..Default::default()
+            },
+            sent_at_block: U64::from(10),
+        }
+        .into();
+        assert!(error
+            .to_string()
+            .starts_with("Bundle transaction was cancelled"));
+
+        // the receipt is passed through to the accounting
+        let unsuccessful = error.downcast_ref::<UnsuccessfulBundle>().unwrap();
+        assert_eq!(unsuccessful.sent_at_block, U64::from(10));
+    }
+
+    #[tokio::test]
+    async fn builder_cancellation() {
+        let signer = "ac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80"
+            .parse::<LocalWallet>()
+            .unwrap()
+            .with_chain_id(1337_u64);
+        let mut nonce_manager = NonceManager::new(signer.address(), 3, vec![10], 2, None);
+        nonce_manager.track(
+            U256::from(1),
+            Eip1559TransactionRequest::new()
+                .to(Address::random())
+                .nonce(1)
+                .gas(500_000)
+                .max_fee_per_gas(100)
+                .max_priority_fee_per_gas(10)
+                .chain_id(1337)
+                .into(),
+            H256::random(),
+            U64::from(10),
+        );
+        let cancellation = nonce_manager
+            .cancellation(&U256::from(1), 50.into(), 5.into())
+            .unwrap();
+
+        // the transactions submitted to the builder are signed as they are, without being filled
+        let raw_tx = Bundler::sign_transaction(&signer, &cancellation, &[])
+            .await
+            .unwrap();
+        let (tx, signature) = TypedTransaction::decode_signed(&rlp::Rlp::new(&raw_tx)).unwrap();
+        assert_eq!(tx.gas(), Some(&U256::from(21_000)));
+        assert_eq!(tx.nonce(), Some(&U256::from(1)));
+        assert_eq!(tx.to_addr(), Some(&signer.address()));
+        assert_eq!(signature.recover(tx.sighash()).unwrap(), signer.address());
+    }
+
+    #[tokio::test]
+    async fn flashbots_signature() {
+        let searcher: LocalWallet =
+            "ac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80"
+                .parse()
+                .unwrap();
+        let builder = BuilderEndpoint::new("https://relay.flashbots.net".to_string(), searcher);
+        let body = br#"{"jsonrpc":"2.0","id":1,"method":"eth_sendBundle","params":[]}"#;
+
+        let header = builder.signature(body).await.unwrap();
+        let (address, signature) = header.split_once(':').unwrap();
+        assert_eq!(address, "0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266");
+        let signature: Signature = signature.parse().unwrap();
+        assert_eq!(
+            signature
+                .recover(format!("0x{}", hex::encode(keccak256(body))))
+                .unwrap(),
+            builder.searcher.address()
+        );
     }
 }
@@ -0,0 +1,109 @@
+use aa_bundler_primitives::Authorization;
+use ethers::{
+    types::{transaction::eip1559::Eip1559TransactionRequest, Bytes, Signature, H256},
+    utils::{keccak256, rlp::RlpStream},
+};
+
+/// Type of the transaction that carries the authorization list (EIP-7702)
+pub const SET_CODE_TX_TYPE: u8 = 0x04;
+
+/// Gas charged for each authorization in the authorization list (`PER_EMPTY_ACCOUNT_COST`)
+pub const PER_AUTHORIZATION_GAS: u64 = 25000;
+
+fn rlp_append_fields(
+    stream: &mut RlpStream,
+    tx: &Eip1559TransactionRequest,
+    authorization_list: &[Authorization],
+) -> anyhow::Result<()> {
+    let to = tx
+        .to
+        .as_ref()
+        .and_then(|to| to.as_address())
+        .ok_or_else(|| anyhow::anyhow!("Set code transaction requires the recipient address"))?;
+    stream.append(&tx.chain_id.unwrap_or_default());
+    stream.append(&tx.nonce.unwrap_or_default());
+    stream.append(&tx.max_priority_fee_per_gas.unwrap_or_default());
+    stream.append(&tx.max_fee_per_gas.unwrap_or_default());
+    stream.append(&tx.gas.unwrap_or_default());
+    stream.append(to);
+    stream.append(&tx.value.unwrap_or_default());
+    stream.append(&tx.data.clone().unwrap_or_default().to_vec());
+    stream.append(&tx.access_list);
+    stream.begin_list(authorization_list.len());
+    for authorization in authorization_list {
+        authorization.rlp_append(stream);
+    }
+    Ok(())
+}
+
+/// Hash of the set code transaction that is signed by the bundler
+pub fn sighash(
+    tx: &Eip1559TransactionRequest,
+    authorization_list: &[Authorization],
+) -> anyhow::Result<H256> {
+    let mut stream = RlpStream::new_list(10);
+    rlp_append_fields(&mut stream, tx, authorization_list)?;
+    let mut encoded = vec![SET_CODE_TX_TYPE];
+    encoded.extend_from_slice(&stream.out());
+    Ok(keccak256(encoded).into())
+}
+
+/// Signed set code transaction (`0x04 || rlp([..., authorization_list, y_parity, r, s])`)
+pub fn rlp_signed(
+    tx: &Eip1559TransactionRequest,
+    authorization_list: &[Authorization],
+    signature: &Signature,
+) -> anyhow::Result<Bytes> {
+    let mut stream = RlpStream::new_list(13);
+    rlp_append_fields(&mut stream, tx, authorization_list)?;
+    stream.append(&signature.v.saturating_sub(27));
+    stream.append(&signature.r);
+    stream.append(&signature.s);
+    let mut encoded = vec![SET_CODE_TX_TYPE];
+    encoded.extend_from_slice(&stream.out());
+    Ok(encoded.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::{
+        signers::{LocalWallet, Signer},
+        types::{Address, RecoveryMessage, U256, U64},
+        utils::rlp::Rlp,
+    };
+
+    #[test]
+    fn set_code_transaction() {
+        let wallet = LocalWallet::new(&mut ethers::core::rand::thread_rng());
+        let tx = Eip1559TransactionRequest::new()
+            .to(Address::random())
+            .chain_id(1)
+            .nonce(3)
+            .gas(100000)
+            .max_fee_per_gas(10)
+            .max_priority_fee_per_gas(1)
+            .data(vec![1, 2, 3]);
+        let authorization_list = vec![Authorization {
+            chain_id: U256::zero(),
+            address: Address::random(),
+            nonce: U256::zero(),
+            y_parity: U64::one(),
+            r: U256::one(),
+            s: U256::one(),
+        }];
+
+        let hash = sighash(&tx, &authorization_list).unwrap();
+        let signature = wallet.sign_hash(hash).unwrap();
+        let raw_tx = rlp_signed(&tx, &authorization_list, &signature).unwrap();
+
+        assert_eq!(raw_tx[0], SET_CODE_TX_TYPE);
+        let rlp = Rlp::new(&raw_tx[1..]);
+        assert_eq!(rlp.item_count().unwrap(), 13);
+        assert_eq!(rlp.at(9).unwrap().item_count().unwrap(), 1);
+        assert_eq!(
+            signature.recover(RecoveryMessage::Hash(hash)).unwrap(),
+            wallet.address()
+        );
+    }
+}
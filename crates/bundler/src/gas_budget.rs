@@ -0,0 +1,57 @@
+use ethers::types::{U256, U64};
+
+/// Gas budget per block shared by all bundlers (entry points) that use the same EOA,
+/// so that bundles sent in the same block don't exceed the global limit
+#[derive(Debug)]
+pub struct BlockGasBudget {
+    pub max_gas: U256,
+    block_number: U64,
+    used_gas: U256,
+}
+
+impl BlockGasBudget {
+    pub fn new(max_gas: U256) -> Self {
+        Self {
+            max_gas,
+            block_number: U64::zero(),
+            used_gas: U256::zero(),
+        }
+    }
+
+    fn refresh(&mut self, block_number: U64) {
+        if block_number > self.block_number {
+            self.block_number = block_number;
+            self.used_gas = U256::zero();
+        }
+    }
+
+    /// Gas that is still available in the given block
+    pub fn remaining(&mut self, block_number: U64) -> U256 {
+        self.refresh(block_number);
+        self.max_gas.saturating_sub(self.used_gas)
+    }
+
+    /// Reserves gas for the bundle in the given block, returns false if there is not enough gas left
+    pub fn reserve(&mut self, block_number: U64, gas: U256) -> bool {
+        if self.remaining(block_number) < gas {
+            return false;
+        }
+        self.used_gas = self.used_gas.saturating_add(gas);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn block_gas_budget() {
+        let mut budget = BlockGasBudget::new(U256::from(100));
+        assert!(budget.reserve(U64::from(1), U256::from(60)));
+        assert_eq!(budget.remaining(U64::from(1)), U256::from(40));
+        assert!(!budget.reserve(U64::from(1), U256::from(50)));
+        assert_eq!(budget.remaining(U64::from(2)), U256::from(100));
+        assert!(budget.reserve(U64::from(2), U256::from(50)));
+    }
+}
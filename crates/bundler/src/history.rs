@@ -0,0 +1,187 @@
+use std::{collections::VecDeque, fs, path::PathBuf};
+
+use aa_bundler_primitives::{
+    BundleAttempt, BundleRecord, BundleStatus, DroppedUserOperation, UserOperationHash,
+};
+use ethers::types::Address;
+use tracing::warn;
+
+use crate::persist::FileWriter;
+
+/// History of assembled bundles (oldest records are removed first). If a path is set,
+/// the history is written to the file on every change and loaded from it on startup.
+#[derive(Debug)]
+pub struct BundleHistory {
+    records: VecDeque<BundleRecord>,
+    next_id: u64,
+    max_size: usize,
+    writer: Option<FileWriter>,
+}
+
+impl BundleHistory {
+    pub fn new(max_size: usize, path: Option<PathBuf>) -> anyhow::Result<Self> {
+        let records: VecDeque<BundleRecord> = match path {
+            Some(ref path) if path.exists() => serde_json::from_slice(&fs::read(path)?)?,
+            _ => VecDeque::new(),
+        };
+        let next_id = records
+            .back()
+            .map(|record| record.id + 1)
+            .unwrap_or_default();
+        Ok(Self {
+            records,
+            next_id,
+            max_size,
+            writer: path.map(|path| FileWriter::new(path, "bundle history")),
+        })
+    }
+
+    /// Records a newly assembled bundle and returns its id
+    pub fn add(
+        &mut self,
+        entry_point: Address,
+        user_operations: Vec<UserOperationHash>,
+        created_at: u64,
+    ) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.records.push_back(BundleRecord {
+            id,
+            entry_point,
+            user_operations,
+            dropped_user_operations: vec![],
+            transaction_hash: None,
+            gas_used: None,
+            status: BundleStatus::Pending,
+            revert_reason: None,
+            attempts: vec![],
+            created_at,
+        });
+        while self.records.len() > self.max_size {
+            self.records.pop_front();
+        }
+        self.persist();
+        id
+    }
+
+    /// Records that the user operation was removed from the bundle
+    pub fn drop_user_operation(
+        &mut self,
+        id: u64,
+        user_operation_hash: UserOperationHash,
+        reason: String,
+    ) {
+        self.update(id, |record| {
+            record
+                .user_operations
+                .retain(|hash| *hash != user_operation_hash);
+            record.dropped_user_operations.push(DroppedUserOperation {
+                user_operation_hash,
+                reason,
+            });
+        });
+    }
+
+    /// Records the transaction sent for the bundle (original, replacement or cancellation)
+    pub fn add_attempt(&mut self, id: u64, attempt: BundleAttempt) {
+        self.update(id, |record| record.attempts.push(attempt));
+    }
+
+    pub fn update<F: FnOnce(&mut BundleRecord)>(&mut self, id: u64, f: F) {
+        if let Some(record) = self.records.iter_mut().find(|record| record.id == id) {
+            f(record);
+            self.persist();
+        }
+    }
+
+    pub fn get(&self, id: u64) -> Option<BundleRecord> {
+        self.records.iter().find(|record| record.id == id).cloned()
+    }
+
+    /// All bundle records of the entry point, the newest first
+    pub fn get_all(&self, entry_point: &Address) -> Vec<BundleRecord> {
+        self.records
+            .iter()
+            .rev()
+            .filter(|record| record.entry_point == *entry_point)
+            .cloned()
+            .collect()
+    }
+
+    /// Pending bundles assembled in the time range (Unix timestamps, inclusive), the oldest first
+    pub fn get_pending(&self, from: u64, to: u64) -> Vec<BundleRecord> {
+        self.records
+            .iter()
+            .filter(|record| {
+                record.status == BundleStatus::Pending && (from..=to).contains(&record.created_at)
+            })
+            .cloned()
+            .collect()
+    }
+
+    fn persist(&self) {
+        if let Some(ref writer) = self.writer {
+            match serde_json::to_vec(&self.records) {
+                Ok(data) => writer.write(data),
+                Err(e) => warn!("Failed to serialize bundle history: {e:?}"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::types::{H256, U256, U64};
+
+    #[test]
+    fn pending_bundles() {
+        let entry_point = Address::random();
+        let mut history = BundleHistory::new(10, None).unwrap();
+        let old = history.add(entry_point, vec![H256::random().into()], 100);
+        let included = history.add(entry_point, vec![H256::random().into()], 200);
+        let pending = history.add(entry_point, vec![H256::random().into()], 300);
+        history.update(included, |record| record.status = BundleStatus::Included);
+
+        let ids = |records: Vec<BundleRecord>| -> Vec<u64> {
+            records.into_iter().map(|record| record.id).collect()
+        };
+        assert_eq!(ids(history.get_pending(0, 300)), vec![old, pending]);
+        assert_eq!(ids(history.get_pending(150, 250)), Vec::<u64>::new());
+        assert_eq!(ids(history.get_pending(150, 300)), vec![pending]);
+
+        let attempt = BundleAttempt {
+            tx_hash: H256::random(),
+            max_fee_per_gas: U256::from(100),
+            max_priority_fee_per_gas: U256::from(10),
+            block_number: U64::from(10),
+        };
+        history.add_attempt(pending, attempt.clone());
+        assert_eq!(history.get(pending).unwrap().attempts, vec![attempt]);
+    }
+
+    #[test]
+    fn reload() {
+        let dir = std::env::temp_dir().join(format!("bundle-history-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("history.json");
+        let entry_point = Address::random();
+
+        let mut history = BundleHistory::new(2, Some(path.clone())).unwrap();
+        history.add(entry_point, vec![H256::random().into()], 100);
+        let dropped = history.add(entry_point, vec![H256::random().into()], 200);
+        let included = history.add(entry_point, vec![H256::random().into()], 300);
+        history.update(included, |record| record.status = BundleStatus::Included);
+        history.drop_user_operation(dropped, H256::random().into(), "AA23 reverted".to_string());
+        let records = history.get_all(&entry_point);
+        // the pending write is completed when the history is dropped
+        drop(history);
+
+        let mut history = BundleHistory::new(2, Some(path.clone())).unwrap();
+        assert_eq!(history.get_all(&entry_point), records);
+        assert_eq!(history.add(entry_point, vec![], 400), included + 1);
+        assert!(!path.with_extension("tmp").exists());
+        drop(history);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,5 +1,22 @@
 #![allow(dead_code)]
 
+mod accounting;
+mod bundle;
 mod bundler;
+mod eip7702;
+mod gas_budget;
+mod history;
+mod nonce_manager;
+mod persist;
 
-pub use bundler::Bundler;
+pub use accounting::{Accounting, AccountingEntry};
+pub use bundle::{
+    user_operation_gas, Bundle, UserOperationsPerAggregator, BUNDLE_CALLDATA_OVERHEAD,
+    BUNDLE_GAS_OVERHEAD,
+};
+pub use bundler::{
+    estimate_fees, BuilderEndpoint, BundleEstimate, Bundler, Submission, UnsuccessfulBundle,
+};
+pub use gas_budget::BlockGasBudget;
+pub use history::BundleHistory;
+pub use nonce_manager::{NonceManager, PendingAction, PendingTransaction, MIN_FEE_BUMP_PERCENTAGE};
@@ -0,0 +1,526 @@
+use std::collections::BTreeMap;
+
+use aa_bundler_primitives::BundleAttempt;
+use ethers::{
+    providers::Middleware,
+    types::{
+        transaction::eip2718::TypedTransaction, Address, Eip1559TransactionRequest, NameOrAddress,
+        TransactionRequest, H256, U256, U64,
+    },
+};
+use tracing::{info, trace, warn};
+
+/// Minimum fee bump accepted by the execution clients for replacement transactions
+pub const MIN_FEE_BUMP_PERCENTAGE: u64 = 10;
+/// Gas of the cancellation (zero-value transfer to self), set up front since the transactions signed for the builder
+/// (or the conditional submission) are not filled
+const CANCELLATION_GAS: u64 = 21_000;
+
+/// Bundle transaction that was sent by the bundler, but not yet included on chain
+#[derive(Clone, Debug)]
+pub struct PendingTransaction {
+    pub nonce: U256,
+    pub tx: TypedTransaction,
+    /// All transactions sent with this nonce (original and replacements)
+    pub attempts: Vec<BundleAttempt>,
+    /// Block number at which the latest transaction with this nonce was sent
+    pub sent_at_block: U64,
+    pub fee_bumps: u64,
+    /// Hash of the cancellation transaction (zero-value transfer to self), if one was sent
+    pub cancel_tx_hash: Option<H256>,
+}
+
+impl PendingTransaction {
+    pub fn tx_hash(&self) -> H256 {
+        self.attempts
+            .last()
+            .expect("Pending transaction must have at least one attempt")
+            .tx_hash
+    }
+
+    pub fn tx_hashes(&self) -> Vec<H256> {
+        self.attempts
+            .iter()
+            .map(|attempt| attempt.tx_hash)
+            .collect()
+    }
+}
+
+/// Next step that should be taken for a pending bundle transaction
+#[derive(Clone, Debug, PartialEq)]
+pub enum PendingAction {
+    Wait,
+    Replace(TypedTransaction),
+    Cancel(TypedTransaction),
+}
+
+/// Manages nonces of the bundler EOA and keeps track of pending bundle transactions,
+/// so that stuck transactions can be replaced (or cancelled) instead of blocking all subsequent bundles
+#[derive(Debug)]
+pub struct NonceManager {
+    pub address: Address,
+    /// Number of blocks after which the pending transaction is considered stuck
+    pub stuck_blocks: u64,
+    /// Percentages by which the fees are increased for each subsequent replacement
+    /// (the last one is used for all further replacements)
+    pub fee_bump_schedule: Vec<u64>,
+    /// Maximum number of fee bumps, after that the transaction is cancelled
+    pub max_fee_bumps: u64,
+    /// Max fee per gas that is never exceeded by replacements (cancellations aren't capped, so that the nonce
+    /// isn't blocked forever)
+    pub max_fee_per_gas_cap: Option<U256>,
+    next_nonce: Option<U256>,
+    pending: BTreeMap<U256, PendingTransaction>,
+}
+
+impl NonceManager {
+    pub fn new(
+        address: Address,
+        stuck_blocks: u64,
+        fee_bump_schedule: Vec<u64>,
+        max_fee_bumps: u64,
+        max_fee_per_gas_cap: Option<U256>,
+    ) -> Self {
+        Self {
+            address,
+            stuck_blocks,
+            fee_bump_schedule,
+            max_fee_bumps,
+            max_fee_per_gas_cap,
+            next_nonce: None,
+            pending: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the nonce for the next bundle transaction, taking into account transactions that are still pending.
+    /// The nonce is used up only when the transaction sent with it is tracked, so a failed submission doesn't leave a
+    /// gap.
+    pub async fn next_nonce<M: Middleware + 'static>(
+        &mut self,
+        provider: &M,
+    ) -> anyhow::Result<U256> {
+        let on_chain_nonce = provider
+            .get_transaction_count(self.address, None)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to get transaction count: {e:?}"))?;
+        self.prune(on_chain_nonce);
+
+        Ok(match self.next_nonce {
+            Some(nonce) if nonce > on_chain_nonce => nonce,
+            _ => on_chain_nonce,
+        })
+    }
+
+    /// Starts tracking the bundle transaction that was sent with the given nonce, returns its attempt
+    pub fn track(
+        &mut self,
+        nonce: U256,
+        tx: TypedTransaction,
+        tx_hash: H256,
+        block_number: U64,
+    ) -> BundleAttempt {
+        trace!("Tracking bundle transaction {tx_hash:?} with nonce {nonce:?}");
+        if self
+            .next_nonce
+            .map_or(true, |next_nonce| next_nonce <= nonce)
+        {
+            self.next_nonce = Some(nonce + 1);
+        }
+        let attempt = Self::attempt(&tx, tx_hash, block_number);
+        self.pending.insert(
+            nonce,
+            PendingTransaction {
+                nonce,
+                tx,
+                attempts: vec![attempt.clone()],
+                sent_at_block: block_number,
+                fee_bumps: 0,
+                cancel_tx_hash: None,
+            },
+        );
+        attempt
+    }
+
+    /// Records that the pending transaction with the given nonce was replaced, returns the attempt of the
+    /// replacement (`None` if the nonce isn't tracked)
+    pub fn replace(
+        &mut self,
+        nonce: U256,
+        tx: TypedTransaction,
+        tx_hash: H256,
+        block_number: U64,
+    ) -> Option<BundleAttempt> {
+        let pending = self.pending.get_mut(&nonce)?;
+        info!(
+            "Replacing bundle transaction {:?} with {tx_hash:?} (nonce {nonce:?})",
+            pending.tx_hash()
+        );
+        if tx.to() == Some(&NameOrAddress::Address(self.address)) {
+            pending.cancel_tx_hash = Some(tx_hash);
+        }
+        let attempt = Self::attempt(&tx, tx_hash, block_number);
+        pending.attempts.push(attempt.clone());
+        pending.tx = tx;
+        pending.sent_at_block = block_number;
+        pending.fee_bumps += 1;
+        Some(attempt)
+    }
+
+    fn attempt(tx: &TypedTransaction, tx_hash: H256, block_number: U64) -> BundleAttempt {
+        let (max_fee_per_gas, max_priority_fee_per_gas) = Self::fees(tx);
+        BundleAttempt {
+            tx_hash,
+            max_fee_per_gas,
+            max_priority_fee_per_gas,
+            block_number,
+        }
+    }
+
+    pub fn get(&self, nonce: &U256) -> Option<&PendingTransaction> {
+        self.pending.get(nonce)
+    }
+
+    pub fn get_all(&self) -> Vec<PendingTransaction> {
+        self.pending.values().cloned().collect()
+    }
+
+    /// Stops tracking the transaction with the given nonce (included or dropped)
+    pub fn remove(&mut self, nonce: &U256) -> Option<PendingTransaction> {
+        self.pending.remove(nonce)
+    }
+
+    /// Removes all pending transactions with nonce lower than the on-chain nonce
+    pub fn prune(&mut self, on_chain_nonce: U256) {
+        self.pending = self.pending.split_off(&on_chain_nonce);
+    }
+
+    /// Decides what to do with the pending transaction, based on the current block and gas market
+    pub fn action(
+        &self,
+        nonce: &U256,
+        block_number: U64,
+        max_fee_per_gas: U256,
+        max_priority_fee_per_gas: U256,
+    ) -> PendingAction {
+        let pending = match self.pending.get(nonce) {
+            Some(pending) => pending,
+            None => return PendingAction::Wait,
+        };
+
+        if block_number < pending.sent_at_block + self.stuck_blocks {
+            return PendingAction::Wait;
+        }
+
+        if pending.fee_bumps >= self.max_fee_bumps {
+            return self
+                .cancellation(nonce, max_fee_per_gas, max_priority_fee_per_gas)
+                .map(PendingAction::Cancel)
+                .unwrap_or(PendingAction::Wait);
+        }
+
+        // replacement capped below the minimum fee bump would be rejected by the execution clients
+        match self.bump(
+            pending.tx.clone(),
+            pending.fee_bumps,
+            Self::fees(&pending.tx),
+            (max_fee_per_gas, max_priority_fee_per_gas),
+            self.max_fee_per_gas_cap,
+        ) {
+            Some(tx) => PendingAction::Replace(tx),
+            None => self
+                .cancellation(nonce, max_fee_per_gas, max_priority_fee_per_gas)
+                .map(PendingAction::Cancel)
+                .unwrap_or(PendingAction::Wait),
+        }
+    }
+
+    /// Builds the cancellation (zero-value transfer to self) of the pending transaction with the given nonce,
+    /// returns `None` if there is no such transaction
+    pub fn cancellation(
+        &self,
+        nonce: &U256,
+        max_fee_per_gas: U256,
+        max_priority_fee_per_gas: U256,
+    ) -> Option<TypedTransaction> {
+        let pending = self.pending.get(nonce)?;
+
+        // same transaction type as the pending one (legacy on the chains without EIP-1559)
+        let mut tx: TypedTransaction = match pending.tx {
+            TypedTransaction::Legacy(_) => TransactionRequest::new()
+                .from(self.address)
+                .to(self.address)
+                .value(U256::zero())
+                .gas(CANCELLATION_GAS)
+                .nonce(*nonce)
+                .into(),
+            _ => Eip1559TransactionRequest::new()
+                .from(self.address)
+                .to(self.address)
+                .value(U256::zero())
+                .gas(CANCELLATION_GAS)
+                .nonce(*nonce)
+                .into(),
+        };
+        if let Some(chain_id) = pending.tx.chain_id() {
+            tx.set_chain_id(chain_id);
+        }
+        self.bump(
+            tx,
+            pending.fee_bumps,
+            Self::fees(&pending.tx),
+            (max_fee_per_gas, max_priority_fee_per_gas),
+            None,
+        )
+    }
+
+    fn fees(tx: &TypedTransaction) -> (U256, U256) {
+        match tx {
+            TypedTransaction::Eip1559(tx) => (
+                tx.max_fee_per_gas.unwrap_or_default(),
+                tx.max_priority_fee_per_gas.unwrap_or_default(),
+            ),
+            _ => {
+                let gas_price = tx.gas_price().unwrap_or_default();
+                (gas_price, gas_price)
+            }
+        }
+    }
+
+    /// Increases the fees (max fee and max priority fee) of the pending transaction by the percentage from the bump
+    /// schedule, but never below the current gas market (max fee and max priority fee). Returns `None` if the capped
+    /// fees are below the minimum fee bump accepted for replacement transactions.
+    fn bump(
+        &self,
+        mut tx: TypedTransaction,
+        fee_bumps: u64,
+        (tx_max_fee, tx_max_priority_fee): (U256, U256),
+        (max_fee_per_gas, max_priority_fee_per_gas): (U256, U256),
+        max_fee_per_gas_cap: Option<U256>,
+    ) -> Option<TypedTransaction> {
+        let percentage = self
+            .fee_bump_schedule
+            .get(fee_bumps as usize)
+            .or_else(|| self.fee_bump_schedule.last())
+            .copied()
+            .unwrap_or(MIN_FEE_BUMP_PERCENTAGE);
+        let bump = |fee: U256| fee * (100 + percentage) / 100 + 1;
+        let min_bump = |fee: U256| fee * (100 + MIN_FEE_BUMP_PERCENTAGE) / 100;
+        let mut max_fee = bump(tx_max_fee).max(max_fee_per_gas);
+        if let Some(cap) = max_fee_per_gas_cap {
+            max_fee = max_fee.min(cap);
+        }
+        let max_priority_fee = bump(tx_max_priority_fee)
+            .max(max_priority_fee_per_gas)
+            .min(max_fee);
+        if max_fee <= tx_max_fee
+            || max_fee < min_bump(tx_max_fee)
+            || max_priority_fee < min_bump(tx_max_priority_fee)
+        {
+            match max_fee_per_gas_cap {
+                Some(cap) => warn!(
+                    "Bundle transaction fees can't be bumped enough below the max fee per gas cap {cap:?}"
+                ),
+                None => warn!(
+                    "Bundle transaction fees (max fee {tx_max_fee:?}, max priority fee {tx_max_priority_fee:?}) can't be bumped by the minimum of {MIN_FEE_BUMP_PERCENTAGE}%"
+                ),
+            }
+            return None;
+        }
+
+        match tx {
+            TypedTransaction::Eip1559(ref mut inner) => {
+                inner.max_fee_per_gas = Some(max_fee);
+                inner.max_priority_fee_per_gas = Some(max_priority_fee);
+            }
+            _ => {
+                tx.set_gas_price(max_fee);
+            }
+        }
+        Some(tx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::providers::Provider;
+
+    use super::*;
+
+    fn pending_tx(max_fee: u64, max_priority_fee: u64) -> TypedTransaction {
+        Eip1559TransactionRequest::new()
+            .nonce(1)
+            .max_fee_per_gas(max_fee)
+            .max_priority_fee_per_gas(max_priority_fee)
+            .chain_id(1337)
+            .into()
+    }
+
+    #[tokio::test]
+    async fn failed_submission_does_not_advance_nonce() {
+        let (provider, mock) = Provider::mocked();
+        let mut nonce_manager = NonceManager::new(Address::random(), 3, vec![10], 2, None);
+
+        mock.push(U256::from(5)).unwrap();
+        assert_eq!(
+            nonce_manager.next_nonce(&provider).await.unwrap(),
+            U256::from(5)
+        );
+
+        // the submission failed, so the transaction wasn't tracked
+        mock.push(U256::from(5)).unwrap();
+        assert_eq!(
+            nonce_manager.next_nonce(&provider).await.unwrap(),
+            U256::from(5)
+        );
+
+        nonce_manager.track(
+            U256::from(5),
+            pending_tx(100, 10),
+            H256::random(),
+            U64::from(10),
+        );
+        mock.push(U256::from(5)).unwrap();
+        assert_eq!(
+            nonce_manager.next_nonce(&provider).await.unwrap(),
+            U256::from(6)
+        );
+    }
+
+    #[test]
+    fn stuck_transaction_replacement() {
+        let mut nonce_manager = NonceManager::new(Address::random(), 3, vec![10], 2, None);
+        nonce_manager.track(
+            U256::from(1),
+            pending_tx(100, 10),
+            H256::random(),
+            U64::from(10),
+        );
+
+        assert_eq!(
+            nonce_manager.action(&U256::from(1), U64::from(12), 50.into(), 5.into()),
+            PendingAction::Wait
+        );
+
+        let tx = match nonce_manager.action(&U256::from(1), U64::from(13), 50.into(), 5.into()) {
+            PendingAction::Replace(tx) => tx,
+            action => panic!("Expected replacement, got {action:?}"),
+        };
+        assert_eq!(NonceManager::fees(&tx), (U256::from(111), U256::from(12)));
+
+        let tx = match nonce_manager.action(&U256::from(1), U64::from(13), 200.into(), 20.into()) {
+            PendingAction::Replace(tx) => tx,
+            action => panic!("Expected replacement, got {action:?}"),
+        };
+        assert_eq!(NonceManager::fees(&tx), (U256::from(200), U256::from(20)));
+
+        nonce_manager.replace(U256::from(1), tx.clone(), H256::random(), U64::from(13));
+        nonce_manager.replace(U256::from(1), tx, H256::random(), U64::from(16));
+        assert_eq!(nonce_manager.get(&U256::from(1)).unwrap().attempts.len(), 3);
+
+        match nonce_manager.action(&U256::from(1), U64::from(19), 50.into(), 5.into()) {
+            PendingAction::Cancel(tx) => {
+                assert_eq!(tx.nonce(), Some(&U256::from(1)));
+                assert_eq!(tx.value(), Some(&U256::zero()));
+                let tx_hash = H256::random();
+                nonce_manager.replace(U256::from(1), tx, tx_hash, U64::from(19));
+                assert_eq!(
+                    nonce_manager.get(&U256::from(1)).unwrap().cancel_tx_hash,
+                    Some(tx_hash)
+                );
+            }
+            action => panic!("Expected cancellation, got {action:?}"),
+        }
+
+        nonce_manager.prune(U256::from(2));
+        assert!(nonce_manager.get_all().is_empty());
+    }
+
+    #[test]
+    fn fee_bump_schedule() {
+        let mut nonce_manager =
+            NonceManager::new(Address::random(), 1, vec![10, 50], 5, Some(U256::from(200)));
+        nonce_manager.track(
+            U256::from(1),
+            pending_tx(100, 10),
+            H256::random(),
+            U64::from(10),
+        );
+
+        let tx = match nonce_manager.action(&U256::from(1), U64::from(11), 0.into(), 0.into()) {
+            PendingAction::Replace(tx) => tx,
+            action => panic!("Expected replacement, got {action:?}"),
+        };
+        assert_eq!(NonceManager::fees(&tx), (U256::from(111), U256::from(12)));
+        nonce_manager.replace(U256::from(1), tx, H256::random(), U64::from(11));
+
+        let tx = match nonce_manager.action(&U256::from(1), U64::from(12), 0.into(), 0.into()) {
+            PendingAction::Replace(tx) => tx,
+            action => panic!("Expected replacement, got {action:?}"),
+        };
+        assert_eq!(NonceManager::fees(&tx), (U256::from(167), U256::from(19)));
+        nonce_manager.replace(U256::from(1), tx, H256::random(), U64::from(12));
+
+        let tx = match nonce_manager.action(&U256::from(1), U64::from(13), 0.into(), 0.into()) {
+            PendingAction::Replace(tx) => tx,
+            action => panic!("Expected replacement, got {action:?}"),
+        };
+        assert_eq!(NonceManager::fees(&tx), (U256::from(200), U256::from(29)));
+        nonce_manager.replace(U256::from(1), tx, H256::random(), U64::from(13));
+
+        // the fees can't be bumped above the cap anymore, the cancellation isn't capped
+        match nonce_manager.action(&U256::from(1), U64::from(14), 0.into(), 0.into()) {
+            PendingAction::Cancel(tx) => {
+                assert_eq!(NonceManager::fees(&tx), (U256::from(301), U256::from(44)))
+            }
+            action => panic!("Expected cancellation, got {action:?}"),
+        }
+        let attempts = &nonce_manager.get(&U256::from(1)).unwrap().attempts;
+        assert_eq!(attempts.len(), 4);
+        assert_eq!(attempts[3].max_fee_per_gas, U256::from(200));
+    }
+
+    #[test]
+    fn capped_bump_below_minimum() {
+        let mut nonce_manager =
+            NonceManager::new(Address::random(), 1, vec![20], 5, Some(U256::from(105)));
+        nonce_manager.track(
+            U256::from(1),
+            pending_tx(100, 10),
+            H256::random(),
+            U64::from(10),
+        );
+
+        // replacement with the max fee capped at 105 would be rejected (less than 10% above 100)
+        match nonce_manager.action(&U256::from(1), U64::from(11), 0.into(), 0.into()) {
+            PendingAction::Cancel(tx) => {
+                assert_eq!(tx.value(), Some(&U256::zero()));
+                assert_eq!(NonceManager::fees(&tx), (U256::from(121), U256::from(13)));
+            }
+            action => panic!("Expected cancellation, got {action:?}"),
+        }
+    }
+
+    #[test]
+    fn legacy_transaction_cancellation() {
+        let mut nonce_manager = NonceManager::new(Address::random(), 1, vec![10], 0, None);
+        nonce_manager.track(
+            U256::from(1),
+            TransactionRequest::new()
+                .nonce(1)
+                .gas_price(100)
+                .chain_id(56)
+                .into(),
+            H256::random(),
+            U64::from(10),
+        );
+
+        match nonce_manager.action(&U256::from(1), U64::from(11), 50.into(), 50.into()) {
+            PendingAction::Cancel(tx) => {
+                assert!(matches!(tx, TypedTransaction::Legacy(_)));
+                assert_eq!(tx.gas_price(), Some(U256::from(111)));
+                assert_eq!(tx.chain_id(), Some(U64::from(56)));
+            }
+            action => panic!("Expected cancellation, got {action:?}"),
+        }
+    }
+}
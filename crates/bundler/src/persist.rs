@@ -0,0 +1,62 @@
+use std::{
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::mpsc,
+    thread,
+};
+
+use tracing::warn;
+
+/// Replaces the file with the data, so that the file is never left half written
+fn write_atomically(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    let tmp_path = path.with_extension("tmp");
+    let mut file = File::create(&tmp_path)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, path)
+}
+
+/// Writes the data (the bundle history or the accounting) to the file on a background thread, so that the runtime
+/// isn't blocked by the file system. If the writes fall behind, only the latest data is written.
+#[derive(Debug)]
+pub(crate) struct FileWriter {
+    sender: Option<mpsc::Sender<Vec<u8>>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl FileWriter {
+    pub(crate) fn new(path: PathBuf, name: &'static str) -> Self {
+        let (sender, receiver) = mpsc::channel::<Vec<u8>>();
+        let thread = thread::spawn(move || {
+            while let Ok(mut data) = receiver.recv() {
+                while let Ok(newer) = receiver.try_recv() {
+                    data = newer;
+                }
+                if let Err(e) = write_atomically(&path, &data) {
+                    warn!("Failed to persist {name} to {path:?}: {e:?}");
+                }
+            }
+        });
+        Self {
+            sender: Some(sender),
+            thread: Some(thread),
+        }
+    }
+
+    pub(crate) fn write(&self, data: Vec<u8>) {
+        if let Some(ref sender) = self.sender {
+            let _ = sender.send(data);
+        }
+    }
+}
+
+impl Drop for FileWriter {
+    /// Waits until the last data is written
+    fn drop(&mut self) {
+        self.sender.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
@@ -2,28 +2,45 @@ use std::fmt::Display;
 use std::sync::Arc;
 
 use super::gen::entry_point_api::{
-    EntryPointAPIErrors, FailedOp, SenderAddressResult, UserOperation, ValidationResult,
-    ValidationResultWithAggregation,
+    EntryPointAPIErrors, ExecutionResult, FailedOp, SenderAddressResult, UserOperation,
+    UserOpsPerAggregator, ValidationResult, ValidationResultWithAggregation,
 };
 use super::gen::stake_manager_api::DepositInfo;
 use super::gen::{EntryPointAPI, EntryPointAPIEvents, StakeManagerAPI};
+use super::multicall::aggregate;
 use super::tracer::JS_TRACER;
 use ethers::abi::AbiDecode;
-use ethers::prelude::{ContractError, Event};
-use ethers::providers::{Middleware, ProviderError};
+use ethers::prelude::{ContractCall, ContractError, Event};
+use ethers::providers::{call_raw::RawCall, Middleware, ProviderError};
 use ethers::types::{
-    Address, Bytes, GethDebugTracerType, GethDebugTracingCallOptions, GethDebugTracingOptions,
-    GethTrace, TransactionRequest, U256,
+    spoof, transaction::eip2718::TypedTransaction, Address, BlockNumber, Bytes,
+    GethDebugTracerType, GethDebugTracingCallOptions, GethDebugTracingOptions, GethTrace,
+    TransactionReceipt, TransactionRequest, U256,
 };
 use ethers_providers::{JsonRpcError, MiddlewareError};
 use thiserror::Error;
 use tracing::trace;
 
+/// Precision (in gas) of the binary search for the call gas limit
+const CALL_GAS_SEARCH_TOLERANCE: u64 = 1000;
+
+/// Whether the `eth_call` error is the revert or the out of gas of the call, the other errors (e.g. the rate limit of
+/// the node) say nothing about the gas
+fn is_revert_or_out_of_gas(err: &JsonRpcError) -> bool {
+    let message = err.message.to_lowercase();
+    err.as_revert_data().is_some()
+        || message.contains("revert")
+        || message.contains("out of gas")
+        || message.contains("gas required exceeds")
+}
+
 pub struct EntryPoint<M: Middleware> {
     provider: Arc<M>,
     address: Address,
     entry_point_api: EntryPointAPI<M>,
     stake_manager_api: StakeManagerAPI<M>,
+    /// Block on which the validation is simulated and the gas is estimated
+    simulation_block: BlockNumber,
 }
 
 impl<M: Middleware + 'static> EntryPoint<M> {
@@ -35,9 +52,17 @@ impl<M: Middleware + 'static> EntryPoint<M> {
             address,
             entry_point_api,
             stake_manager_api,
+            simulation_block: BlockNumber::Latest,
         }
     }
 
+    /// Simulates on the block (e.g. the pending block, on the chains where its state differs from the latest block
+    /// right after the transactions the user operations depend on)
+    pub fn with_simulation_block(mut self, simulation_block: BlockNumber) -> Self {
+        self.simulation_block = simulation_block;
+        self
+    }
+
     pub fn entry_point_api(&self) -> &EntryPointAPI<M> {
         &self.entry_point_api
     }
@@ -77,14 +102,64 @@ impl<M: Middleware + 'static> EntryPoint<M> {
         }
     }
 
+    /// Error of the `eth_call` with a state override set as if it was made through the contract call, so that the
+    /// revert data is decoded the same way
+    fn state_override_call_error(e: ProviderError) -> ContractError<M> {
+        let revert_data = match &e {
+            ProviderError::JsonRpcClientError(err) => {
+                err.as_error_response().and_then(|err| err.as_revert_data())
+            }
+            _ => None,
+        };
+        match revert_data {
+            Some(data) => ContractError::Revert(data),
+            None => ContractError::ProviderError { e },
+        }
+    }
+
+    /// `eth_call` on the simulation block, with the state override set if given (e.g. code of a counterfactual account or
+    /// balance of a paymaster deposit)
+    async fn call(
+        &self,
+        tx: &TypedTransaction,
+        state_override: Option<&spoof::State>,
+    ) -> Result<Bytes, EntryPointErr> {
+        match state_override {
+            Some(state_override) => Ok(self
+                .provider
+                .provider()
+                .call_raw(tx)
+                .block(self.simulation_block.into())
+                .state(state_override)
+                .await?),
+            None => self
+                .provider
+                .call(tx, Some(self.simulation_block.into()))
+                .await
+                .map_err(EntryPointErr::from_middleware_err::<M>),
+        }
+    }
+
     pub async fn simulate_validation<U: Into<UserOperation>>(
         &self,
         user_operation: U,
+        state_override: Option<&spoof::State>,
     ) -> Result<SimulateValidationResult, EntryPointErr> {
-        let request_result = self
+        let call = self
             .entry_point_api
-            .simulate_validation(user_operation.into())
-            .await;
+            .simulate_validation(user_operation.into());
+        let request_result = match state_override {
+            Some(state_override) => self
+                .provider
+                .provider()
+                .call_raw(&call.tx)
+                .block(self.simulation_block.into())
+                .state(state_override)
+                .await
+                .map(|_| ())
+                .map_err(Self::state_override_call_error),
+            None => call.block(self.simulation_block).call().await,
+        };
         match request_result {
             Ok(_) => Err(EntryPointErr::UnknownErr(
                 "Simulate validation should expect revert".to_string(),
@@ -107,51 +182,93 @@ impl<M: Middleware + 'static> EntryPoint<M> {
     pub async fn simulate_validation_trace<U: Into<UserOperation>>(
         &self,
         user_operation: U,
+        state_override: Option<&spoof::State>,
     ) -> Result<GethTrace, EntryPointErr> {
         let call = self
             .entry_point_api
             .simulate_validation(user_operation.into());
-        let request_result = self
-            .provider
-            .debug_trace_call(
-                call.tx,
-                None,
-                GethDebugTracingCallOptions {
-                    tracing_options: GethDebugTracingOptions {
-                        disable_storage: None,
-                        disable_stack: None,
-                        enable_memory: None,
-                        enable_return_data: None,
-                        tracer: Some(GethDebugTracerType::JsTracer(JS_TRACER.to_string())),
-                        tracer_config: None,
-                        timeout: None,
-                    },
-                },
-            )
-            .await
-            .map_err(|e| EntryPointErr::from_middleware_err::<M>(e))?;
+        let options = GethDebugTracingCallOptions {
+            tracing_options: GethDebugTracingOptions {
+                disable_storage: None,
+                disable_stack: None,
+                enable_memory: None,
+                enable_return_data: None,
+                tracer: Some(GethDebugTracerType::JsTracer(JS_TRACER.to_string())),
+                tracer_config: None,
+                timeout: None,
+            },
+        };
+
+        let request_result = match state_override {
+            Some(state_override) => {
+                // the state overrides are passed along the tracing options of `debug_traceCall`
+                let mut options = serde_json::to_value(&options).map_err(|e| {
+                    EntryPointErr::UnknownErr(format!("Serializing tracing options error: {e:?}"))
+                })?;
+                options["stateOverrides"] = serde_json::to_value(state_override).map_err(|e| {
+                    EntryPointErr::UnknownErr(format!("Serializing state override error: {e:?}"))
+                })?;
+                self.provider
+                    .provider()
+                    .request("debug_traceCall", (call.tx, self.simulation_block, options))
+                    .await?
+            }
+            None => self
+                .provider
+                .debug_trace_call(call.tx, Some(self.simulation_block.into()), options)
+                .await
+                .map_err(|e| EntryPointErr::from_middleware_err::<M>(e))?,
+        };
         Ok(request_result)
     }
 
+    /// `eth_call` of the bundle transaction, with the state override set if given (e.g. the EIP-7702 delegations of
+    /// the senders that are applied only by the bundle transaction itself)
+    async fn call_bundle(
+        &self,
+        call: ContractCall<M, ()>,
+        state_override: Option<&spoof::State>,
+    ) -> Result<(), ContractError<M>> {
+        match state_override {
+            Some(state_override) => {
+                let mut tx = call.tx;
+                if tx.from().is_none() {
+                    if let Some(sender) = self.provider.default_sender() {
+                        tx.set_from(sender);
+                    }
+                }
+                self.provider
+                    .provider()
+                    .call_raw(&tx)
+                    .state(state_override)
+                    .await
+                    .map(|_| ())
+                    .map_err(Self::state_override_call_error)
+            }
+            None => call.call().await,
+        }
+    }
+
     pub async fn handle_ops<U: Into<UserOperation>>(
         &self,
         ops: Vec<U>,
         beneficiary: Address,
+        state_override: Option<&spoof::State>,
     ) -> Result<(), EntryPointErr> {
-        self.entry_point_api
-            .handle_ops(ops.into_iter().map(|u| u.into()).collect(), beneficiary)
-            .call()
-            .await
-            .or_else(|e| {
-                Self::deserialize_error_msg(e).and_then(|op| match op {
-                    EntryPointAPIErrors::FailedOp(failed_op) => {
-                        Err(EntryPointErr::FailedOp(failed_op))
-                    }
-                    _ => Err(EntryPointErr::UnknownErr(format!(
-                        "Handle ops with invalid error: {op:?}"
-                    ))),
-                })
+        self.call_bundle(
+            self.entry_point_api
+                .handle_ops(ops.into_iter().map(|u| u.into()).collect(), beneficiary),
+            state_override,
+        )
+        .await
+        .or_else(|e| {
+            Self::deserialize_error_msg(e).and_then(|op| match op {
+                EntryPointAPIErrors::FailedOp(failed_op) => Err(EntryPointErr::FailedOp(failed_op)),
+                _ => Err(EntryPointErr::UnknownErr(format!(
+                    "Handle ops with invalid error: {op:?}"
+                ))),
             })
+        })
     }
 
     pub async fn get_deposit_info(&self, address: &Address) -> Result<DepositInfo, EntryPointErr> {
@@ -169,6 +286,131 @@ impl<M: Middleware + 'static> EntryPoint<M> {
         }
     }
 
+    pub async fn balance_of(&self, address: &Address) -> Result<U256, EntryPointErr> {
+        self.stake_manager_api
+            .balance_of(*address)
+            .call()
+            .await
+            .map_err(Self::transaction_error)
+    }
+
+    /// Adds the value to the deposit of the account (which pays for its user operations), the transaction is sent
+    /// by the signer of the middleware
+    pub async fn deposit_to(
+        &self,
+        address: &Address,
+        value: U256,
+    ) -> Result<TransactionReceipt, EntryPointErr> {
+        self.send_transaction(self.stake_manager_api.deposit_to(*address).value(value))
+            .await
+    }
+
+    /// Withdraws the amount from the deposit of the signer
+    pub async fn withdraw_to(
+        &self,
+        withdraw_address: &Address,
+        amount: U256,
+    ) -> Result<TransactionReceipt, EntryPointErr> {
+        self.send_transaction(
+            self.stake_manager_api
+                .withdraw_to(*withdraw_address, amount),
+        )
+        .await
+    }
+
+    /// Adds the value to the stake of the signer and sets its unstake delay (it can't be lowered)
+    pub async fn add_stake(
+        &self,
+        unstake_delay_sec: u32,
+        value: U256,
+    ) -> Result<TransactionReceipt, EntryPointErr> {
+        self.send_transaction(
+            self.stake_manager_api
+                .add_stake(unstake_delay_sec)
+                .value(value),
+        )
+        .await
+    }
+
+    /// Unlocks the stake of the signer, it can be withdrawn after the unstake delay
+    pub async fn unlock_stake(&self) -> Result<TransactionReceipt, EntryPointErr> {
+        self.send_transaction(self.stake_manager_api.unlock_stake())
+            .await
+    }
+
+    /// Withdraws the unlocked stake of the signer
+    pub async fn withdraw_stake(
+        &self,
+        withdraw_address: &Address,
+    ) -> Result<TransactionReceipt, EntryPointErr> {
+        self.send_transaction(self.stake_manager_api.withdraw_stake(*withdraw_address))
+            .await
+    }
+
+    async fn send_transaction(
+        &self,
+        call: ContractCall<M, ()>,
+    ) -> Result<TransactionReceipt, EntryPointErr> {
+        call.send()
+            .await
+            .map_err(Self::transaction_error)?
+            .await?
+            .ok_or_else(|| {
+                EntryPointErr::UnknownErr("Transaction was dropped from the mempool".to_string())
+            })
+    }
+
+    /// Error of the contract call, with the revert reason if it reverted
+    fn transaction_error(e: ContractError<M>) -> EntryPointErr {
+        match Self::deserialize_error_msg(e) {
+            Ok(error) => EntryPointErr::UnknownErr(format!("Entry point reverted: {error:?}")),
+            Err(error) => error,
+        }
+    }
+
+    pub async fn get_nonce(&self, address: &Address, key: U256) -> Result<U256, EntryPointErr> {
+        let result = self.entry_point_api.get_nonce(*address, key).call().await;
+
+        match result {
+            Ok(nonce) => Ok(nonce),
+            _ => Err(EntryPointErr::UnknownErr(
+                "Error calling get nonce".to_string(),
+            )),
+        }
+    }
+
+    /// Deposit infos of the accounts, read in a single multicall
+    pub async fn get_deposit_infos(
+        &self,
+        addresses: &[Address],
+    ) -> Result<Vec<DepositInfo>, EntryPointErr> {
+        aggregate(
+            self.provider.clone(),
+            addresses
+                .iter()
+                .map(|address| self.stake_manager_api.get_deposit_info(*address))
+                .collect(),
+        )
+        .await
+        .map_err(Self::transaction_error)
+    }
+
+    /// Nonces of the (sender, key) pairs, read in a single multicall
+    pub async fn get_nonces(
+        &self,
+        senders: &[(Address, U256)],
+    ) -> Result<Vec<U256>, EntryPointErr> {
+        aggregate(
+            self.provider.clone(),
+            senders
+                .iter()
+                .map(|(address, key)| self.entry_point_api.get_nonce(*address, *key))
+                .collect(),
+        )
+        .await
+        .map_err(Self::transaction_error)
+    }
+
     pub async fn get_sender_address(
         &self,
         initcode: Bytes,
@@ -210,7 +452,7 @@ impl<M: Middleware + 'static> EntryPoint<M> {
                         .to(user_operation.sender)
                         .data(user_operation.call_data.clone())
                         .into(),
-                    None,
+                    Some(self.simulation_block.into()),
                 )
                 .await;
             trace!("Estimate call gas on {user_operation:?} returned {result:?}");
@@ -221,12 +463,144 @@ impl<M: Middleware + 'static> EntryPoint<M> {
         }
     }
 
-    pub async fn handle_aggregated_ops<U: Into<UserOperation>>(
+    /// `simulateHandleOp` of the user operation with the gas of the `eth_call` limited, `None` if the simulation ran out
+    /// of gas. The execution of the user operation itself is skipped (no call gas), it's made by the target call (the
+    /// call from the entry point to the sender after the validation, so that the init code of a counterfactual sender is
+    /// applied) if `execute` is set.
+    async fn simulate_execution(
+        &self,
+        user_operation: &UserOperation,
+        execute: bool,
+        gas: U256,
+        state_override: Option<&spoof::State>,
+    ) -> Result<Option<ExecutionResult>, EntryPointErr> {
+        // the call data is the target call data either way, so that the calldata gas of both simulations is the same
+        let target = if execute {
+            user_operation.sender
+        } else {
+            Address::zero()
+        };
+        let mut tx = self
+            .entry_point_api
+            .simulate_handle_op(
+                UserOperation {
+                    call_gas_limit: U256::zero(),
+                    ..user_operation.clone()
+                },
+                target,
+                user_operation.call_data.clone(),
+            )
+            .tx;
+        tx.set_gas(gas);
+
+        match self.call(&tx, state_override).await {
+            Ok(_) => Err(EntryPointErr::UnknownErr(
+                "Simulate handle op should expect revert".to_string(),
+            )),
+            Err(EntryPointErr::JsonRpcError(err)) if is_revert_or_out_of_gas(&err) => {
+                match err.as_revert_data().map(EntryPointAPIErrors::decode) {
+                    Some(Ok(EntryPointAPIErrors::ExecutionResult(result))) => Ok(Some(result)),
+                    Some(Ok(EntryPointAPIErrors::FailedOp(failed_op))) => {
+                        Err(EntryPointErr::FailedOp(failed_op))
+                    }
+                    _ => Ok(None),
+                }
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Binary searches the lowest gas of the `simulateHandleOp` with which the simulation completes (and the execution
+    /// succeeds if `execute` is set)
+    async fn search_simulation_gas(
+        &self,
+        user_operation: &UserOperation,
+        execute: bool,
+        max_gas: U256,
+        state_override: Option<&spoof::State>,
+    ) -> Result<U256, EntryPointErr> {
+        let mut low = U256::zero();
+        let mut high = max_gas;
+        while high - low > U256::from(CALL_GAS_SEARCH_TOLERANCE) {
+            let mid = (low + high) / 2;
+            let succeeds = match self
+                .simulate_execution(user_operation, execute, mid, state_override)
+                .await
+            {
+                Ok(Some(result)) => !execute || result.target_success,
+                // the validation ran out of gas (it passed with the maximum gas)
+                Ok(None) | Err(EntryPointErr::FailedOp(_)) => false,
+                Err(e) => return Err(e),
+            };
+            if succeeds {
+                high = mid;
+            } else {
+                low = mid;
+            }
+        }
+        Ok(high)
+    }
+
+    /// Binary searches the lowest gas with which the execution of the user operation succeeds, as the difference of
+    /// the gas of the `simulateHandleOp` with and without the execution (plus the tolerance of the searches)
+    pub async fn search_call_gas<U: Into<UserOperation>>(
         &self,
-        _ops_per_aggregator: Vec<U>,
-        _beneficiary: Address,
+        user_operation: U,
+        max_gas: U256,
+        state_override: Option<&spoof::State>,
+    ) -> Result<U256, EntryPointErr> {
+        let user_operation = user_operation.into();
+        if user_operation.call_data.is_empty() {
+            return Ok(U256::zero());
+        }
+
+        // execution that fails with the maximum gas fails regardless of the gas, the revert is returned to the user
+        match self
+            .simulate_execution(&user_operation, true, max_gas, state_override)
+            .await?
+        {
+            Some(result) if result.target_success => {}
+            Some(result) => return Err(EntryPointErr::ExecutionReverted(result.target_result)),
+            None => {
+                return Err(EntryPointErr::UnknownErr(format!(
+                    "Simulate handle op ran out of gas with {max_gas:?}"
+                )))
+            }
+        }
+
+        let validation_gas = self
+            .search_simulation_gas(&user_operation, false, max_gas, state_override)
+            .await?;
+        let total_gas = self
+            .search_simulation_gas(&user_operation, true, max_gas, state_override)
+            .await?;
+        let call_gas =
+            total_gas.saturating_sub(validation_gas) + U256::from(CALL_GAS_SEARCH_TOLERANCE);
+        trace!("Call gas search on {user_operation:?} returned {call_gas:?}");
+
+        Ok(call_gas)
+    }
+
+    pub async fn handle_aggregated_ops(
+        &self,
+        ops_per_aggregator: Vec<UserOpsPerAggregator>,
+        beneficiary: Address,
+        state_override: Option<&spoof::State>,
     ) -> Result<(), EntryPointErr> {
-        todo!()
+        self.call_bundle(
+            self.entry_point_api
+                .handle_aggregated_ops(ops_per_aggregator, beneficiary),
+            state_override,
+        )
+        .await
+        .or_else(|e| {
+            Self::deserialize_error_msg(e).and_then(|op| match op {
+                EntryPointAPIErrors::FailedOp(failed_op) => Err(EntryPointErr::FailedOp(failed_op)),
+                _ => Err(EntryPointErr::UnknownErr(format!(
+                    "Handle aggregated ops with invalid error: {op:?}"
+                ))),
+            })
+        })
     }
 }
 
@@ -234,6 +608,8 @@ impl<M: Middleware + 'static> EntryPoint<M> {
 pub enum EntryPointErr {
     FailedOp(FailedOp),
     JsonRpcError(JsonRpcError),
+    /// Revert data of the execution of the user operation
+    ExecutionReverted(Bytes),
     NetworkErr(String),
     DecodeErr(String),
     UnknownErr(String), // describe impossible error. We should fix the codes here(or contract codes) if this occurs.
@@ -267,7 +643,7 @@ impl EntryPointErr {
         }
     }
 
-    fn from_middleware_err<M: Middleware>(value: M::Error) -> Self {
+    pub(crate) fn from_middleware_err<M: Middleware>(value: M::Error) -> Self {
         if let Some(json_err) = value.as_error_response() {
             return EntryPointErr::JsonRpcError(json_err.clone());
         }
@@ -289,7 +665,8 @@ pub enum SimulateValidationResult {
 #[cfg(test)]
 mod tests {
     use ethers::{
-        providers::{Http, Middleware, Provider},
+        abi::AbiEncode,
+        providers::{Http, Middleware, MockResponse, Provider},
         types::{Address, Bytes, GethTrace, U256},
     };
 
@@ -298,6 +675,88 @@ mod tests {
     use super::*;
     use std::{str::FromStr, sync::Arc};
 
+    fn json_rpc_error(code: i64, message: &str, data: Option<serde_json::Value>) -> JsonRpcError {
+        JsonRpcError {
+            code,
+            message: message.to_string(),
+            data,
+        }
+    }
+
+    #[test]
+    fn revert_or_out_of_gas_errors() {
+        assert!(is_revert_or_out_of_gas(&json_rpc_error(
+            3,
+            "execution reverted",
+            Some(serde_json::json!("0x12345678"))
+        )));
+        assert!(is_revert_or_out_of_gas(&json_rpc_error(
+            -32000,
+            "out of gas",
+            None
+        )));
+        assert!(is_revert_or_out_of_gas(&json_rpc_error(
+            -32000,
+            "gas required exceeds allowance (21000)",
+            None
+        )));
+        assert!(!is_revert_or_out_of_gas(&json_rpc_error(
+            -32005,
+            "limit exceeded",
+            None
+        )));
+        assert!(!is_revert_or_out_of_gas(&json_rpc_error(
+            -32603,
+            "internal error",
+            None
+        )));
+    }
+
+    #[tokio::test]
+    async fn search_call_gas_errors() {
+        let (provider, mock) = Provider::mocked();
+        let entry_point = EntryPoint::new(Arc::new(provider), Address::repeat_byte(1));
+        let user_operation = super::UserOperation {
+            call_data: Bytes::from(vec![1]),
+            ..Default::default()
+        };
+        let max_gas = U256::from(30_000_000);
+
+        // the errors of the node are returned rather than taken as the lack of gas
+        mock.push_response(MockResponse::Error(json_rpc_error(
+            -32005,
+            "limit exceeded",
+            None,
+        )));
+        assert!(matches!(
+            entry_point
+                .search_call_gas(user_operation.clone(), max_gas, None)
+                .await,
+            Err(EntryPointErr::JsonRpcError(err)) if err.code == -32005
+        ));
+
+        // the execution reverts with the maximum gas
+        let execution_result = EntryPointAPIErrors::ExecutionResult(ExecutionResult {
+            pre_op_gas: U256::from(50_000),
+            paid: U256::zero(),
+            valid_after: 0,
+            valid_until: 0,
+            target_success: false,
+            target_result: Bytes::from(vec![0xde, 0xad]),
+        });
+        mock.push_response(MockResponse::Error(json_rpc_error(
+            3,
+            "execution reverted",
+            Some(serde_json::json!(Bytes::from(execution_result.encode()))),
+        )));
+        assert!(matches!(
+            entry_point
+                .search_call_gas(user_operation, max_gas, None)
+                .await,
+            Err(EntryPointErr::ExecutionReverted(data)) if data == Bytes::from(vec![0xde, 0xad])
+        ));
+    }
+
     #[tokio::test]
     #[ignore]
     async fn simulate_validation() {
@@ -326,7 +785,7 @@ mod tests {
         };
 
         let simulate_validation = entry_point
-            .simulate_validation(user_operation.clone())
+            .simulate_validation(user_operation.clone(), None)
             .await
             .unwrap();
 
@@ -336,7 +795,7 @@ mod tests {
         ));
 
         let simulate_validation_trace = entry_point
-            .simulate_validation_trace(user_operation)
+            .simulate_validation_trace(user_operation, None)
             .await
             .unwrap();
 
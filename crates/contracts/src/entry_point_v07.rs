@@ -0,0 +1,154 @@
+use std::sync::Arc;
+
+use aa_bundler_primitives::PackedUserOperation as UserOperationV07;
+
+use super::entry_point::EntryPointErr;
+use super::gen::entry_point_api::FailedOp;
+use super::gen::entry_point_v07api::{DepositInfo, EntryPointV07APIErrors, PackedUserOperation};
+use super::gen::{EntryPointV07API, EntryPointV07APIEvents};
+use ethers::abi::AbiDecode;
+use ethers::prelude::{ContractError, Event};
+use ethers::providers::Middleware;
+use ethers::types::{Address, H256, U256};
+
+impl From<UserOperationV07> for PackedUserOperation {
+    fn from(user_operation: UserOperationV07) -> Self {
+        Self {
+            sender: user_operation.sender,
+            nonce: user_operation.nonce,
+            init_code: user_operation.init_code,
+            call_data: user_operation.call_data,
+            account_gas_limits: user_operation.account_gas_limits.0,
+            pre_verification_gas: user_operation.pre_verification_gas,
+            gas_fees: user_operation.gas_fees.0,
+            paymaster_and_data: user_operation.paymaster_and_data,
+            signature: user_operation.signature,
+        }
+    }
+}
+
+impl From<PackedUserOperation> for UserOperationV07 {
+    fn from(user_operation: PackedUserOperation) -> Self {
+        Self {
+            sender: user_operation.sender,
+            nonce: user_operation.nonce,
+            init_code: user_operation.init_code,
+            call_data: user_operation.call_data,
+            account_gas_limits: user_operation.account_gas_limits.into(),
+            pre_verification_gas: user_operation.pre_verification_gas,
+            gas_fees: user_operation.gas_fees.into(),
+            paymaster_and_data: user_operation.paymaster_and_data,
+            signature: user_operation.signature,
+        }
+    }
+}
+
+/// Entry point v0.7: the user operations are packed (`PackedUserOperation`) and the validation is simulated off
+/// chain (there are no simulation functions in the contract)
+pub struct EntryPointV07<M: Middleware> {
+    provider: Arc<M>,
+    address: Address,
+    entry_point_api: EntryPointV07API<M>,
+}
+
+impl<M: Middleware + 'static> EntryPointV07<M> {
+    pub fn new(provider: Arc<M>, address: Address) -> Self {
+        let entry_point_api = EntryPointV07API::new(address, provider.clone());
+        Self {
+            provider,
+            address,
+            entry_point_api,
+        }
+    }
+
+    pub fn entry_point_api(&self) -> &EntryPointV07API<M> {
+        &self.entry_point_api
+    }
+
+    pub fn events(&self) -> Event<Arc<M>, M, EntryPointV07APIEvents> {
+        self.entry_point_api.events()
+    }
+
+    pub fn provider(&self) -> Arc<M> {
+        self.provider.clone()
+    }
+
+    pub fn address(&self) -> Address {
+        self.address
+    }
+
+    fn deserialize_error_msg(err_msg: ContractError<M>) -> EntryPointErr {
+        match err_msg {
+            ContractError::Revert(data) => match EntryPointV07APIErrors::decode(&data) {
+                Ok(EntryPointV07APIErrors::FailedOp(failed_op)) => {
+                    EntryPointErr::FailedOp(FailedOp {
+                        op_index: failed_op.op_index,
+                        reason: failed_op.reason,
+                    })
+                }
+                Ok(EntryPointV07APIErrors::FailedOpWithRevert(failed_op)) => {
+                    EntryPointErr::FailedOp(FailedOp {
+                        op_index: failed_op.op_index,
+                        reason: format!("{} {}", failed_op.reason, failed_op.inner),
+                    })
+                }
+                Ok(error) => EntryPointErr::UnknownErr(format!("Entry point error: {error:?}")),
+                Err(e) => EntryPointErr::DecodeErr(format!(
+                    "{e:?} data field could not be deserialize to EntryPointV07APIErrors",
+                )),
+            },
+            ContractError::MiddlewareError { e } => EntryPointErr::from_middleware_err::<M>(e),
+            ContractError::ProviderError { e } => e.into(),
+            _ => EntryPointErr::UnknownErr(format!("Unkown error: {err_msg:?}")),
+        }
+    }
+
+    pub async fn handle_ops(
+        &self,
+        ops: Vec<PackedUserOperation>,
+        beneficiary: Address,
+    ) -> Result<(), EntryPointErr> {
+        self.entry_point_api
+            .handle_ops(ops, beneficiary)
+            .call()
+            .await
+            .map_err(Self::deserialize_error_msg)
+    }
+
+    pub async fn get_user_op_hash(
+        &self,
+        user_operation: PackedUserOperation,
+    ) -> Result<H256, EntryPointErr> {
+        self.entry_point_api
+            .get_user_op_hash(user_operation)
+            .call()
+            .await
+            .map(H256::from)
+            .map_err(Self::deserialize_error_msg)
+    }
+
+    pub async fn get_deposit_info(&self, address: &Address) -> Result<DepositInfo, EntryPointErr> {
+        self.entry_point_api
+            .get_deposit_info(*address)
+            .call()
+            .await
+            .map(
+                |(deposit, staked, stake, unstake_delay_sec, withdraw_time)| DepositInfo {
+                    deposit,
+                    staked,
+                    stake,
+                    unstake_delay_sec,
+                    withdraw_time,
+                },
+            )
+            .map_err(Self::deserialize_error_msg)
+    }
+
+    pub async fn get_nonce(&self, address: &Address, key: U256) -> Result<U256, EntryPointErr> {
+        self.entry_point_api
+            .get_nonce(*address, key)
+            .call()
+            .await
+            .map_err(Self::deserialize_error_msg)
+    }
+}
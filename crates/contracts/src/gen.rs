@@ -11,6 +11,49 @@ abigen!(
     "$OUT_DIR/IStakeManager.sol/IStakeManager.json"
 );
 abigen!(PaymasterAPI, "$OUT_DIR/IPaymaster.sol/IPaymaster.json");
+abigen!(AggregatorAPI, "$OUT_DIR/IAggregator.sol/IAggregator.json");
+// the contracts of the v0.7 entry point are not compiled (the submodule is the v0.6 release), so its interface is
+// declared in the human-readable form
+abigen!(
+    EntryPointV07API,
+    r#"[
+        struct PackedUserOperation { address sender; uint256 nonce; bytes initCode; bytes callData; bytes32 accountGasLimits; uint256 preVerificationGas; bytes32 gasFees; bytes paymasterAndData; bytes signature; }
+        struct UserOpsPerAggregator { PackedUserOperation[] userOps; address aggregator; bytes signature; }
+        struct DepositInfo { uint256 deposit; bool staked; uint112 stake; uint32 unstakeDelaySec; uint48 withdrawTime; }
+        function handleOps(PackedUserOperation[] ops, address beneficiary)
+        function handleAggregatedOps(UserOpsPerAggregator[] opsPerAggregator, address beneficiary)
+        function getUserOpHash(PackedUserOperation userOp) view returns (bytes32)
+        function getNonce(address sender, uint192 key) view returns (uint256 nonce)
+        function getSenderAddress(bytes initCode)
+        function getDepositInfo(address account) view returns (DepositInfo info)
+        function balanceOf(address account) view returns (uint256)
+        function depositTo(address account) payable
+        function addStake(uint32 unstakeDelaySec) payable
+        function unlockStake()
+        function withdrawStake(address withdrawAddress)
+        function withdrawTo(address withdrawAddress, uint256 withdrawAmount)
+        function delegateAndRevert(address target, bytes data)
+        function supportsInterface(bytes4 interfaceId) view returns (bool)
+        event UserOperationEvent(bytes32 indexed userOpHash, address indexed sender, address indexed paymaster, uint256 nonce, bool success, uint256 actualGasCost, uint256 actualGasUsed)
+        event AccountDeployed(bytes32 indexed userOpHash, address indexed sender, address factory, address paymaster)
+        event UserOperationRevertReason(bytes32 indexed userOpHash, address indexed sender, uint256 nonce, bytes revertReason)
+        event PostOpRevertReason(bytes32 indexed userOpHash, address indexed sender, uint256 nonce, bytes revertReason)
+        event UserOperationPrefundTooLow(bytes32 indexed userOpHash, address indexed sender, uint256 nonce)
+        event BeforeExecution()
+        event SignatureAggregatorChanged(address indexed aggregator)
+        event Deposited(address indexed account, uint256 totalDeposit)
+        event Withdrawn(address indexed account, address withdrawAddress, uint256 amount)
+        event StakeLocked(address indexed account, uint256 totalStaked, uint256 unstakeDelaySec)
+        event StakeUnlocked(address indexed account, uint256 withdrawTime)
+        event StakeWithdrawn(address indexed account, address withdrawAddress, uint256 amount)
+        error FailedOp(uint256 opIndex, string reason)
+        error FailedOpWithRevert(uint256 opIndex, string reason, bytes inner)
+        error PostOpReverted(bytes returnData)
+        error SignatureValidationFailed(address aggregator)
+        error SenderAddressResult(address sender)
+        error DelegateAndRevert(bool success, bytes ret)
+    ]"#
+);
 
 lazy_static! {
     pub static ref CONTRACTS_FUNCTIONS: HashMap<Selector, String> = {
@@ -29,6 +72,12 @@ lazy_static! {
         map.insert(entry_point_api::UnlockStakeCall::selector(), entry_point_api::UnlockStakeCall::function_name().to_string());
         map.insert(entry_point_api::WithdrawStakeCall::selector(), entry_point_api::WithdrawStakeCall::function_name().to_string());
         map.insert(entry_point_api::WithdrawToCall::selector(), entry_point_api::WithdrawToCall::function_name().to_string());
+        // entry point v0.7 (the other functions have the same selectors as in v0.6)
+        map.insert(entry_point_v07api::HandleOpsCall::selector(), entry_point_v07api::HandleOpsCall::function_name().to_string());
+        map.insert(entry_point_v07api::HandleAggregatedOpsCall::selector(), entry_point_v07api::HandleAggregatedOpsCall::function_name().to_string());
+        map.insert(entry_point_v07api::GetUserOpHashCall::selector(), entry_point_v07api::GetUserOpHashCall::function_name().to_string());
+        map.insert(entry_point_v07api::DelegateAndRevertCall::selector(), entry_point_v07api::DelegateAndRevertCall::function_name().to_string());
+        map.insert(entry_point_v07api::SupportsInterfaceCall::selector(), entry_point_v07api::SupportsInterfaceCall::function_name().to_string());
         // paymaster
         map.insert(paymaster_api::PostOpCall::selector(), paymaster_api::PostOpCall::function_name().to_string());
         map.insert(paymaster_api::ValidatePaymasterUserOpCall::selector(), paymaster_api::ValidatePaymasterUserOpCall::function_name().to_string());
@@ -41,7 +90,6 @@ lazy_static! {
 //     AggregatedAccount,
 //     "$OUT_DIR/IAggregatedAccount.sol/IAggregatedAccount.json"
 // );
-// abigen!(Aggregator, "$OUT_DIR/IAggregator.sol/IAggregator.json");
 // abigen!(
 //     Create2Deployer,
 //     "$OUT_DIR/ICreate2Deployer.sol/ICreate2Deployer.json"
@@ -1,14 +1,24 @@
 #![allow(dead_code)]
 
 mod entry_point;
+mod entry_point_v07;
 mod gen;
+mod multicall;
 mod tracer;
 mod utils;
+mod version;
 
 pub use entry_point::{EntryPoint, EntryPointErr, SimulateValidationResult};
+pub use entry_point_v07::EntryPointV07;
 pub use gen::{
-    EntryPointAPI, EntryPointAPIEvents, UserOperationEventFilter, ValidatePaymasterUserOpReturn,
-    CONTRACTS_FUNCTIONS,
+    entry_point_api::{UserOperationEventFilter, UserOpsPerAggregator},
+    entry_point_v07api, AggregatorAPI, EntryPointAPI, EntryPointAPIEvents, EntryPointV07API,
+    EntryPointV07APIEvents, ValidatePaymasterUserOpReturn, CONTRACTS_FUNCTIONS,
 };
+pub use multicall::{aggregate, get_code_hashes};
 pub use tracer::{Call, CallEntry, JsTracerFrame, JS_TRACER};
 pub use utils::parse_from_input_data;
+pub use version::{
+    deployed_canonical_entry_points, EntryPointVersion, ENTRY_POINT_V06_ADDRESS,
+    ENTRY_POINT_V07_ADDRESS,
+};
@@ -0,0 +1,130 @@
+use std::sync::Arc;
+
+use ethers::{
+    abi::{encode, Token, Tokenizable},
+    contract::{ContractCall, ContractError, Multicall, MULTICALL_ADDRESS},
+    providers::Middleware,
+    types::{Address, TransactionRequest, H256},
+    utils::keccak256,
+};
+use tracing::trace;
+
+/// Init code that returns the `EXTCODEHASH` of every ABI encoded address appended to it (no contract is deployed, it's
+/// only run by `eth_call`), since Multicall3 has no getter of the code hash. The code hashes are preceded by a zero
+/// word, the returned code can't start with the 0xEF byte (EIP-3541).
+///
+/// ```text
+/// PUSH2 0x2b CODESIZE SUB DUP1 PUSH2 0x2b PUSH1 0x20 CODECOPY     // addresses copied to the memory after the zero word
+/// PUSH1 0x20 ADD PUSH1 0x20                                       // end of the addresses, offset of the address
+/// loop: JUMPDEST DUP2 DUP2 LT ISZERO PUSH2 end JUMPI
+///       DUP1 MLOAD EXTCODEHASH DUP2 MSTORE                        // address replaced with its code hash
+///       PUSH1 0x20 ADD PUSH2 loop JUMP
+/// end:  JUMPDEST POP PUSH1 0 RETURN
+/// ```
+const CODE_HASHES_INIT_CODE: [u8; 43] = [
+    0x61, 0x00, 0x2b, 0x38, 0x03, 0x80, 0x61, 0x00, 0x2b, 0x60, 0x20, 0x39, 0x60, 0x20, 0x01, 0x60,
+    0x20, 0x5b, 0x81, 0x81, 0x10, 0x15, 0x61, 0x00, 0x26, 0x57, 0x80, 0x51, 0x3f, 0x81, 0x52, 0x60,
+    0x20, 0x01, 0x61, 0x00, 0x11, 0x56, 0x5b, 0x50, 0x60, 0x00, 0xf3,
+];
+/// Returned code is limited to 24576 bytes (EIP-170)
+const MAX_CODE_HASHES: usize = 24576 / 32 - 1;
+
+/// Results of the contract calls, batched into a single `eth_call` through Multicall3 (deployed at the same address
+/// on most chains). The calls are made one by one if the batch fails (e.g. Multicall3 isn't deployed on a devnet).
+pub async fn aggregate<M, D>(
+    provider: Arc<M>,
+    calls: Vec<ContractCall<M, D>>,
+) -> Result<Vec<D>, ContractError<M>>
+where
+    M: Middleware + 'static,
+    D: Tokenizable + Send + Sync,
+{
+    if calls.len() > 1 {
+        let mut multicall = Multicall::new(provider, Some(MULTICALL_ADDRESS))
+            .await
+            .expect("multicall address is set");
+        for call in calls.iter() {
+            multicall.add_call(call.clone(), false);
+        }
+        match multicall.call_array().await {
+            Ok(results) => return Ok(results),
+            Err(e) => trace!(
+                "Multicall of {} calls failed, calling one by one: {e:?}",
+                calls.len()
+            ),
+        }
+    }
+
+    let mut results = Vec::with_capacity(calls.len());
+    for call in calls {
+        results.push(call.call().await?);
+    }
+    Ok(results)
+}
+
+/// Code hashes of the accounts (the hash of the empty code for the accounts without code), read in a single
+/// `eth_call` if possible
+pub async fn get_code_hashes<M: Middleware>(
+    provider: &M,
+    addresses: &[Address],
+) -> Result<Vec<H256>, M::Error> {
+    let empty_code_hash = H256::from(keccak256([]));
+
+    if !addresses.is_empty() && addresses.len() <= MAX_CODE_HASHES {
+        let data = [
+            CODE_HASHES_INIT_CODE.as_slice(),
+            &encode(
+                &addresses
+                    .iter()
+                    .map(|address| Token::Address(*address))
+                    .collect::<Vec<_>>(),
+            ),
+        ]
+        .concat();
+        match provider
+            .call(&TransactionRequest::new().data(data).into(), None)
+            .await
+        {
+            Ok(result) if result.len() == 32 * (addresses.len() + 1) => {
+                // the accounts that don't exist have zero code hash
+                return Ok(result[32..]
+                    .chunks(32)
+                    .map(H256::from_slice)
+                    .map(|hash| {
+                        if hash.is_zero() {
+                            empty_code_hash
+                        } else {
+                            hash
+                        }
+                    })
+                    .collect());
+            }
+            Ok(result) => trace!("Unexpected code hashes {result:?}, getting the code one by one"),
+            Err(e) => trace!("Failed to get the code hashes, getting the code one by one: {e:?}"),
+        }
+    }
+
+    let mut code_hashes = Vec::with_capacity(addresses.len());
+    for address in addresses {
+        code_hashes.push(keccak256(provider.get_code(*address, None).await?).into());
+    }
+    Ok(code_hashes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn code_hashes_init_code() {
+        const JUMPDEST: u8 = 0x5b;
+        // code size pushed by PUSH2 (the addresses are appended after it)
+        assert_eq!(CODE_HASHES_INIT_CODE[1..3], [0x00, 43]);
+        assert_eq!(CODE_HASHES_INIT_CODE[7..9], [0x00, 43]);
+        // loop and end jump destinations
+        assert_eq!(CODE_HASHES_INIT_CODE[0x11], JUMPDEST);
+        assert_eq!(CODE_HASHES_INIT_CODE[0x26], JUMPDEST);
+        assert_eq!(CODE_HASHES_INIT_CODE[23..25], [0x00, 0x26]);
+        assert_eq!(CODE_HASHES_INIT_CODE[35..37], [0x00, 0x11]);
+    }
+}
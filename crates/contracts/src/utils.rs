@@ -1,7 +1,10 @@
 use aa_bundler_primitives::UserOperation;
 use ethers::{abi::AbiDecode, types::Bytes};
 
-use crate::gen::entry_point_api::{self, EntryPointAPICalls};
+use crate::gen::{
+    aggregator_api,
+    entry_point_api::{self, EntryPointAPICalls},
+};
 
 impl From<UserOperation> for entry_point_api::UserOperation {
     fn from(user_operation: UserOperation) -> Self {
@@ -39,6 +42,24 @@ impl From<entry_point_api::UserOperation> for UserOperation {
     }
 }
 
+impl From<UserOperation> for aggregator_api::UserOperation {
+    fn from(user_operation: UserOperation) -> Self {
+        Self {
+            sender: user_operation.sender,
+            nonce: user_operation.nonce,
+            init_code: user_operation.init_code,
+            call_data: user_operation.call_data,
+            call_gas_limit: user_operation.call_gas_limit,
+            verification_gas_limit: user_operation.verification_gas_limit,
+            pre_verification_gas: user_operation.pre_verification_gas,
+            max_fee_per_gas: user_operation.max_fee_per_gas,
+            max_priority_fee_per_gas: user_operation.max_priority_fee_per_gas,
+            paymaster_and_data: user_operation.paymaster_and_data,
+            signature: user_operation.signature,
+        }
+    }
+}
+
 pub fn parse_from_input_data(data: Bytes) -> Option<Vec<UserOperation>> {
     EntryPointAPICalls::decode(data)
         .ok()
@@ -46,6 +67,12 @@ pub fn parse_from_input_data(data: Bytes) -> Option<Vec<UserOperation>> {
             EntryPointAPICalls::HandleOps(ops) => {
                 Some(ops.ops.into_iter().map(|op| op.into()).collect())
             }
+            EntryPointAPICalls::HandleAggregatedOps(ops) => Some(
+                ops.ops_per_aggregator
+                    .into_iter()
+                    .flat_map(|ops| ops.user_ops.into_iter().map(|op| op.into()))
+                    .collect(),
+            ),
             _ => None,
         })
 }
@@ -0,0 +1,175 @@
+use std::fmt::Display;
+
+use ethers::{
+    contract::EthCall,
+    providers::Middleware,
+    types::{Address, Selector},
+};
+
+use super::entry_point::EntryPointErr;
+use super::gen::{entry_point_api, entry_point_v07api};
+
+/// Canonical deployment of the v0.6 entry point
+pub const ENTRY_POINT_V06_ADDRESS: &str = "0x5FF137D4b0FDCD49DcA30c7CF57E578a026d2789";
+/// Canonical deployment of the v0.7 entry point
+pub const ENTRY_POINT_V07_ADDRESS: &str = "0x0000000071727De22E5E9d8BAf0edAc6f37da032";
+
+/// Opcode that pushes the 4-byte selector in the function dispatcher of the contract
+const PUSH4: u8 = 0x63;
+
+/// Version of the entry point contract, the versions differ in the user operation layout
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum EntryPointVersion {
+    V06,
+    V07,
+}
+
+impl EntryPointVersion {
+    /// Address of the canonical deployment of the version
+    pub fn canonical_address(&self) -> Address {
+        match self {
+            Self::V06 => ENTRY_POINT_V06_ADDRESS,
+            Self::V07 => ENTRY_POINT_V07_ADDRESS,
+        }
+        .parse()
+        .expect("canonical entry point address is valid")
+    }
+
+    /// Version of the canonical deployment at the address
+    pub fn from_address(address: &Address) -> Option<Self> {
+        [
+            (ENTRY_POINT_V06_ADDRESS, Self::V06),
+            (ENTRY_POINT_V07_ADDRESS, Self::V07),
+        ]
+        .into_iter()
+        .find(|(canonical, _)| canonical.parse::<Address>().ok().as_ref() == Some(address))
+        .map(|(_, version)| version)
+    }
+
+    /// Version of the deployed code, by the `handleOps` selector in the function dispatcher
+    pub fn from_code(code: &[u8]) -> Option<Self> {
+        [
+            (entry_point_api::HandleOpsCall::selector(), Self::V06),
+            (entry_point_v07api::HandleOpsCall::selector(), Self::V07),
+        ]
+        .into_iter()
+        .find(|(selector, _)| dispatches(code, selector))
+        .map(|(_, version)| version)
+    }
+
+    /// Detects the version of the entry point at the address, the code is fetched only for the non-canonical
+    /// deployments (e.g. on the local devnets)
+    pub async fn detect<M: Middleware>(
+        provider: &M,
+        address: Address,
+    ) -> Result<Self, EntryPointErr> {
+        if let Some(version) = Self::from_address(&address) {
+            return Ok(version);
+        }
+        let code = provider
+            .get_code(address, None)
+            .await
+            .map_err(EntryPointErr::from_middleware_err::<M>)?;
+        if code.is_empty() {
+            return Err(EntryPointErr::UnknownErr(format!(
+                "No entry point deployed at {address:?}"
+            )));
+        }
+        Self::from_code(&code).ok_or_else(|| {
+            EntryPointErr::UnknownErr(format!(
+                "Contract at {address:?} is not a supported entry point (v0.6 or v0.7)"
+            ))
+        })
+    }
+}
+
+/// Canonical entry points (v0.6 and v0.7) that are deployed on the chain of the provider, the defaults when no entry
+/// points are configured
+pub async fn deployed_canonical_entry_points<M: Middleware>(
+    provider: &M,
+) -> Result<Vec<Address>, EntryPointErr> {
+    let mut entry_points = vec![];
+    for version in [EntryPointVersion::V06, EntryPointVersion::V07] {
+        let address = version.canonical_address();
+        let code = provider
+            .get_code(address, None)
+            .await
+            .map_err(EntryPointErr::from_middleware_err::<M>)?;
+        if !code.is_empty() {
+            entry_points.push(address);
+        }
+    }
+    Ok(entry_points)
+}
+
+impl Display for EntryPointVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::V06 => write!(f, "v0.6"),
+            Self::V07 => write!(f, "v0.7"),
+        }
+    }
+}
+
+fn dispatches(code: &[u8], selector: &Selector) -> bool {
+    code.windows(5)
+        .any(|window| window[0] == PUSH4 && window[1..] == selector[..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn handle_ops_selectors() {
+        assert_eq!(
+            entry_point_api::HandleOpsCall::selector(),
+            [0x1f, 0xad, 0x94, 0x8c]
+        );
+        assert_eq!(
+            entry_point_v07api::HandleOpsCall::selector(),
+            [0x76, 0x5e, 0x82, 0x7f]
+        );
+    }
+
+    #[test]
+    fn version_detection() {
+        assert_eq!(
+            EntryPointVersion::from_address(&ENTRY_POINT_V06_ADDRESS.parse().unwrap()),
+            Some(EntryPointVersion::V06)
+        );
+        assert_eq!(
+            EntryPointVersion::from_address(&ENTRY_POINT_V07_ADDRESS.parse().unwrap()),
+            Some(EntryPointVersion::V07)
+        );
+        assert_eq!(EntryPointVersion::from_address(&Address::random()), None);
+        for version in [EntryPointVersion::V06, EntryPointVersion::V07] {
+            assert_eq!(
+                EntryPointVersion::from_address(&version.canonical_address()),
+                Some(version)
+            );
+        }
+
+        // DUP1 PUSH4 <selector> EQ PUSH2 <jump destination> JUMPI
+        let dispatcher = |selector: [u8; 4]| {
+            [
+                &[0x80, PUSH4][..],
+                &selector,
+                &[0x14, 0x61, 0x01, 0x00, 0x57],
+            ]
+            .concat()
+        };
+        assert_eq!(
+            EntryPointVersion::from_code(&dispatcher([0x76, 0x5e, 0x82, 0x7f])),
+            Some(EntryPointVersion::V07)
+        );
+        assert_eq!(
+            EntryPointVersion::from_code(&dispatcher([0x1f, 0xad, 0x94, 0x8c])),
+            Some(EntryPointVersion::V06)
+        );
+        assert_eq!(
+            EntryPointVersion::from_code(&dispatcher([0x12, 0x34, 0x56, 0x78])),
+            None
+        );
+    }
+}
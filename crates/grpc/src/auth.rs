@@ -0,0 +1,170 @@
+use std::{path::Path, sync::Arc};
+
+use aa_bundler_primitives::Secret;
+use tonic::{
+    metadata::{Ascii, MetadataValue},
+    service::{interceptor::InterceptedService, Interceptor},
+    transport::Channel,
+    Request, Status,
+};
+
+use crate::{telemetry::inject_trace_context, uo_pool_client::UoPoolClient};
+
+const AUTHORIZATION_METADATA: &str = "authorization";
+const BEARER_PREFIX: &str = "Bearer ";
+
+/// Client of the uopool gRPC server that sends the shared secret (if set) with each request
+pub type UoPoolGrpcClient = UoPoolClient<InterceptedService<Channel, AuthInterceptor>>;
+
+pub fn uopool_client(channel: Channel, auth_token: Option<Arc<str>>) -> UoPoolGrpcClient {
+    UoPoolClient::with_interceptor(channel, AuthInterceptor::new(auth_token))
+}
+
+/// Reads the shared secret of the gRPC server from the file (surrounding whitespace is ignored)
+pub fn read_auth_token(path: &Path) -> anyhow::Result<Arc<str>> {
+    let token = std::fs::read_to_string(path)?;
+    let token = token.trim();
+    if token.is_empty() {
+        return Err(anyhow::anyhow!(
+            "Auth token file {} is empty",
+            path.display()
+        ));
+    }
+    check_auth_token(token)?;
+    Ok(token.into())
+}
+
+/// Shared secret of the gRPC server, given directly (e.g. with `AA_BUNDLER_UOPOOL_GRPC_AUTH_TOKEN`, so that it isn't
+/// in the process arguments) or in the file
+pub fn auth_token(token: Option<&Secret>, path: Option<&Path>) -> anyhow::Result<Option<Arc<str>>> {
+    match (token, path) {
+        (Some(token), _) => {
+            let token = token.expose().trim();
+            if token.is_empty() {
+                return Err(anyhow::anyhow!("Auth token is empty"));
+            }
+            check_auth_token(token)?;
+            Ok(Some(token.into()))
+        }
+        (None, Some(path)) => read_auth_token(path).map(Some),
+        (None, None) => Ok(None),
+    }
+}
+
+// the token is sent in the metadata, which only allows visible ASCII characters
+fn check_auth_token(token: &str) -> anyhow::Result<()> {
+    MetadataValue::<Ascii>::try_from(format!("{BEARER_PREFIX}{token}"))
+        .map_err(|_| anyhow::anyhow!("Auth token must consist of visible ASCII characters"))?;
+    Ok(())
+}
+
+/// Client interceptor that adds the `authorization: Bearer <token>` metadata and the trace context to the requests
+#[derive(Clone, Debug, Default)]
+pub struct AuthInterceptor {
+    authorization: Option<MetadataValue<Ascii>>,
+}
+
+impl AuthInterceptor {
+    pub fn new(token: Option<Arc<str>>) -> Self {
+        Self {
+            authorization: token
+                .and_then(|token| format!("{BEARER_PREFIX}{token}").try_into().ok()),
+        }
+    }
+}
+
+impl Interceptor for AuthInterceptor {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        if let Some(authorization) = &self.authorization {
+            request
+                .metadata_mut()
+                .insert(AUTHORIZATION_METADATA, authorization.clone());
+        }
+        inject_trace_context(request.metadata_mut());
+        Ok(request)
+    }
+}
+
+/// Compares the tokens in constant time, so that the secret can't be guessed from the response times
+fn tokens_match(expected: &[u8], token: &[u8]) -> bool {
+    expected.len() == token.len()
+        && expected
+            .iter()
+            .zip(token.iter())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// Server interceptor that rejects the requests without the shared secret (all requests are accepted if not set)
+#[derive(Clone, Debug, Default)]
+pub struct AuthValidator {
+    token: Option<Arc<str>>,
+}
+
+impl AuthValidator {
+    pub fn new(token: Option<Arc<str>>) -> Self {
+        Self { token }
+    }
+}
+
+impl Interceptor for AuthValidator {
+    fn call(&mut self, request: Request<()>) -> Result<Request<()>, Status> {
+        let expected = match &self.token {
+            Some(token) => token,
+            None => return Ok(request),
+        };
+
+        let token = request
+            .metadata()
+            .get(AUTHORIZATION_METADATA)
+            .and_then(|authorization| authorization.to_str().ok())
+            .and_then(|authorization| authorization.strip_prefix(BEARER_PREFIX));
+        match token {
+            Some(token) if tokens_match(expected.as_bytes(), token.as_bytes()) => Ok(request),
+            Some(_) => Err(Status::unauthenticated("invalid auth token")),
+            None => Err(Status::unauthenticated("missing auth token")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn auth_token() {
+        let token: Arc<str> = "secret".into();
+        let mut validator = AuthValidator::new(Some(token.clone()));
+
+        let request = AuthInterceptor::new(Some(token))
+            .call(Request::new(()))
+            .unwrap();
+        assert!(validator.call(request).is_ok());
+
+        let request = AuthInterceptor::new(Some("other".into()))
+            .call(Request::new(()))
+            .unwrap();
+        assert_eq!(
+            validator.call(request).unwrap_err().code(),
+            tonic::Code::Unauthenticated
+        );
+
+        let request = AuthInterceptor::default().call(Request::new(())).unwrap();
+        assert_eq!(
+            validator.call(request).unwrap_err().code(),
+            tonic::Code::Unauthenticated
+        );
+
+        // disabled authentication
+        assert!(AuthValidator::default().call(Request::new(())).is_ok());
+
+        let token: Secret = " secret\n".parse().unwrap();
+        assert_eq!(
+            auth_token(Some(&token), None).unwrap().as_deref(),
+            Some("secret")
+        );
+        assert!(auth_token(Some(&"".parse().unwrap()), None).is_err());
+        assert!(auth_token(Some(&"new\nline".parse().unwrap()), None).is_err());
+        assert_eq!(auth_token(None, None).unwrap(), None);
+    }
+}
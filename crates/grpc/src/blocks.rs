@@ -0,0 +1,57 @@
+use std::{sync::Arc, time::Duration};
+
+use aa_bundler_primitives::EthProvider;
+use ethers::{providers::Middleware, types::U64};
+use tokio::sync::mpsc;
+use tokio_stream::StreamExt;
+use tracing::warn;
+
+/// Numbers of the new blocks: pushed by the execution client (`newHeads` subscription) over WebSocket, polled at the
+/// interval over HTTP. The task stops once the receiver is dropped.
+pub fn watch_new_blocks(
+    eth_provider: Arc<EthProvider>,
+    poll_interval: Duration,
+) -> mpsc::Receiver<U64> {
+    let (sender, receiver) = mpsc::channel(1);
+    tokio::spawn(async move {
+        if eth_provider.as_ref().as_ref().is_pubsub() {
+            loop {
+                match eth_provider.subscribe_blocks().await {
+                    Ok(mut blocks) => {
+                        while let Some(block) = blocks.next().await {
+                            if let Some(block_number) = block.number {
+                                if sender.send(block_number).await.is_err() {
+                                    return;
+                                }
+                            }
+                        }
+                        warn!("Subscription to the new blocks ended, resubscribing");
+                    }
+                    Err(e) => warn!("Failed to subscribe to the new blocks: {e:?}"),
+                }
+                tokio::time::sleep(poll_interval).await;
+            }
+        }
+
+        let mut interval = tokio::time::interval(poll_interval);
+        let mut last_block = None;
+        loop {
+            interval.tick().await;
+            let block_number = match eth_provider.get_block_number().await {
+                Ok(block_number) => block_number,
+                Err(e) => {
+                    warn!("Failed to get the latest block number: {e:?}");
+                    continue;
+                }
+            };
+            if last_block == Some(block_number) {
+                continue;
+            }
+            last_block = Some(block_number);
+            if sender.send(block_number).await.is_err() {
+                return;
+            }
+        }
+    });
+    receiver
+}
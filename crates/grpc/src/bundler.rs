@@ -1,42 +1,211 @@
-use std::{net::SocketAddr, sync::Arc, time::Duration};
+use std::{
+    collections::{HashMap, HashSet},
+    net::SocketAddr,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
-use aa_bundler_bundler::Bundler as BundlerCore;
-use aa_bundler_primitives::{parse_address, parse_u256, UserOperation, Wallet};
+use aa_bundler_bundler::{
+    estimate_fees, Accounting, AccountingEntry, BlockGasBudget, BuilderEndpoint, Bundle,
+    BundleHistory, Bundler as BundlerCore, NonceManager, Submission, UnsuccessfulBundle,
+    UserOperationsPerAggregator, MIN_FEE_BUMP_PERCENTAGE,
+};
+use aa_bundler_primitives::{
+    parse_address, parse_u256, BundleStatus, ChainSpec, EthProvider, Secret, UserOperation,
+    UserOperationHash, Wallet,
+};
 use async_trait::async_trait;
 use clap::Parser;
-use ethers::types::{Address, H256, U256};
+use ethers::{
+    providers::Middleware,
+    signers::{LocalWallet, Signer},
+    types::{Address, TransactionReceipt, H256, I256, U256},
+    utils::format_ether,
+};
 use parking_lot::Mutex;
-use tonic::Response;
-use tracing::{error, info, warn};
+use tokio_stream::{Stream, StreamExt};
+use tonic::{Response, Streaming};
+use tracing::{debug, error, field, info, instrument, warn, Span};
 
-use crate::proto::uopool::{GetSortedRequest, HandlePastEventRequest};
+use crate::blocks::watch_new_blocks;
+use crate::proto::uopool::{
+    GetSortedRequest, HandleBundleTransactionRequest, HandleFailedOpRequest,
+    HandlePastEventRequest, MempoolEvent, MempoolEventKind, SubscribeEventsRequest,
+};
+use crate::telemetry::TraceContext;
 use crate::{GetChainIdResponse, GetSupportedEntryPointsResponse};
+use crate::{ShutdownSignal, Supervisor};
 
 use crate::proto::bundler::*;
-use crate::uo_pool_client::UoPoolClient;
+use crate::TlsConfig;
+use crate::UoPoolGrpcClient;
+
+fn parse_fee_bump(s: &str) -> Result<u64, String> {
+    match s.parse::<u64>() {
+        Ok(percentage) if percentage >= MIN_FEE_BUMP_PERCENTAGE => Ok(percentage),
+        _ => Err(format!(
+            "{s} is not a fee bump percentage of at least {MIN_FEE_BUMP_PERCENTAGE}"
+        )),
+    }
+}
 
 #[derive(Debug, Parser, PartialEq)]
 pub struct BundlerServiceOpts {
+    /// Address that receives the bundle fees (defaults to the bundler signer address)
     #[clap(long, value_parser=parse_address)]
-    pub beneficiary: Address,
+    pub beneficiary: Option<Address>,
 
     #[clap(long, default_value = "1", value_parser=parse_u256)]
     pub gas_factor: U256,
 
+    /// Balance of the bundler signer (in wei) below which a warning is emitted
     #[clap(long, value_parser=parse_u256)]
     pub min_balance: U256,
 
     #[clap(long, default_value = "127.0.0.1:3002")]
     pub bundler_grpc_listen_address: SocketAddr,
 
+    /// PEM encoded certificate chain of the bundler gRPC server, which then accepts only TLS connections (requires
+    /// the key)
+    #[clap(long, requires = "bundler_grpc_tls_key")]
+    pub bundler_grpc_tls_cert: Option<PathBuf>,
+
+    /// PEM encoded private key of the bundler gRPC server certificate
+    #[clap(long, requires = "bundler_grpc_tls_cert")]
+    pub bundler_grpc_tls_key: Option<PathBuf>,
+
+    /// PEM encoded CA certificate of the clients of the bundler gRPC server, which then have to authenticate with a
+    /// certificate signed by it (mutual TLS)
+    #[clap(long, requires = "bundler_grpc_tls_cert")]
+    pub bundler_grpc_tls_client_ca: Option<PathBuf>,
+
+    /// Interval in seconds between the bundles, after an empty bundle the next one is sent as soon as a user operation
+    /// arrives
     #[clap(long, default_value = "10")]
     pub bundle_interval: u64,
+
+    /// Number of blocks after which a pending bundle transaction is considered stuck
+    #[clap(long, default_value = "5")]
+    pub stuck_transaction_blocks: u64,
+
+    /// Seconds after which the user operations of a bundle that is still being sent (or not yet included) are
+    /// reported as stuck (0 disables the watchdog)
+    #[clap(long, default_value = "300")]
+    pub stuck_user_operation_timeout: u64,
+
+    /// Percentages by which the fees of a stuck bundle transaction are bumped on each replacement (at least 10, the
+    /// execution clients reject smaller bumps)
+    #[clap(long, value_delimiter = ',', default_value = "10", value_parser = parse_fee_bump)]
+    pub fee_bump_schedule: Vec<u64>,
+
+    /// Max fee per gas (in wei) that is never exceeded when replacing a stuck bundle transaction
+    #[clap(long, value_parser=parse_u256)]
+    pub max_fee_per_gas_cap: Option<U256>,
+
+    /// Maximum number of fee bumps, after which the stuck bundle transaction is cancelled
+    #[clap(long, default_value = "3")]
+    pub max_fee_bumps: u64,
+
+    /// Maximum number of times the bundle is rebuilt after a user operation fails with FailedOp
+    #[clap(long, default_value = "3")]
+    pub max_bundle_retries: u64,
+
+    /// Signature aggregators whose user operations are allowed to be bundled (with handleAggregatedOps)
+    #[clap(long, value_delimiter=',', value_parser=parse_address)]
+    pub aggregators: Vec<Address>,
+
+    /// Number of confirmations after which the bundle transaction is considered final
+    #[clap(long, default_value = "1")]
+    pub confirmations: u64,
+
+    /// Maximum gas of the bundle (defaults to the max verification gas)
+    #[clap(long, value_parser=parse_u256)]
+    pub max_bundle_gas: Option<U256>,
+
+    /// Maximum number of user operations in the bundle
+    #[clap(long)]
+    pub max_ops_per_bundle: Option<usize>,
+
+    /// Maximum gas of all bundles (from all entry points) sent in the same block
+    #[clap(long, value_parser=parse_u256)]
+    pub max_block_gas: Option<U256>,
+
+    /// Number of assembled bundles that are kept in the bundle history
+    #[clap(long, default_value = "1000")]
+    pub bundle_history_size: usize,
+
+    /// File in which the bundle history is persisted (kept only in memory if not set)
+    #[clap(long)]
+    pub bundle_history_path: Option<PathBuf>,
+
+    /// File in which the profit and cost accounting is persisted (kept only in memory if not set)
+    #[clap(long)]
+    pub accounting_path: Option<PathBuf>,
+
+    /// Block builder (or relay) endpoint to which the bundles are submitted with `eth_sendBundle`
+    /// instead of the public mempool
+    #[clap(long)]
+    pub builder_url: Option<String>,
+
+    /// Submit the bundles to the private relay of the chain (ignored if the builder URL is set)
+    #[clap(long)]
+    pub private_relay: bool,
+
+    // hex encoded private key that signs the requests to the builder (X-Flashbots-Signature), only with
+    // AA_BUNDLER_BUILDER_SIGNING_KEY or the config file; a new key is generated on every start if not set
+    #[clap(long)]
+    pub builder_signing_key: Option<Secret>,
+
+    /// Assemble and simulate bundles, but never send them (only log what would be sent)
+    #[clap(long)]
+    pub dry_run: bool,
+
+    /// Send a final bundle of the user operations left in the mempool on shutdown (within the drain timeout)
+    #[clap(long)]
+    pub shutdown_final_bundle: bool,
+}
+
+const BALANCE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// Interval of the checks of the stuck user operations
+const WATCHDOG_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Settings shared by the bundling processes of all entry points
+#[derive(Clone, Debug)]
+pub struct BundlingConfig {
+    pub aggregators: Vec<Address>,
+    pub max_bundle_retries: u64,
+    pub confirmations: u64,
+    pub max_bundle_gas: U256,
+    pub max_ops_per_bundle: Option<usize>,
+    pub max_calldata_size: usize,
+    pub dry_run: bool,
+    pub stuck_user_operation_timeout: u64,
 }
 
 pub struct BundlerService {
     pub bundlers: Vec<BundlerCore>,
     pub running: Arc<Mutex<bool>>,
-    pub uopool_grpc_client: UoPoolClient<tonic::transport::Channel>,
+    /// Interval in seconds between the bundles, it can be changed while bundling (e.g. on config reload)
+    pub interval: Arc<Mutex<u64>>,
+    /// Bundling is paused when the signer balance can't cover the worst-case bundle
+    pub paused: Arc<Mutex<bool>>,
+    /// Bundling is paused by the operator (admin methods), the uopool keeps accepting user operations
+    pub operator_paused: Arc<Mutex<bool>>,
+    pub uopool_grpc_client: UoPoolGrpcClient,
+    pub config: BundlingConfig,
+    /// Gas budget per block, shared by bundlers of all entry points
+    pub block_gas_budget: Arc<Mutex<BlockGasBudget>>,
+    /// History of all assembled bundles (for auditing)
+    pub history: Arc<Mutex<BundleHistory>>,
+    /// Profit and cost of the included bundles
+    pub accounting: Arc<Mutex<Accounting>>,
+    pub chain_spec: ChainSpec,
+    pub signer: Address,
+    pub eth_provider: EthProvider,
+    pub min_balance: U256,
+    /// Restarts the bundling and balance monitoring tasks if they fail
+    pub supervisor: Supervisor,
 }
 
 fn is_running(running: Arc<Mutex<bool>>) -> bool {
@@ -44,15 +213,194 @@ fn is_running(running: Arc<Mutex<bool>>) -> bool {
     *r
 }
 
+fn is_paused(paused: Arc<Mutex<bool>>) -> bool {
+    let p = paused.lock();
+    *p
+}
+
+/// Stream of the events of the mempool of the entry point, the bundling falls back to the interval without it
+async fn subscribe_events(
+    uopool_grpc_client: &UoPoolGrpcClient,
+    entry_point: &Address,
+) -> Option<Streaming<MempoolEvent>> {
+    let request = tonic::Request::new(SubscribeEventsRequest {
+        eps: vec![(*entry_point).into()],
+    });
+    match uopool_grpc_client.clone().subscribe_events(request).await {
+        Ok(response) => Some(response.into_inner()),
+        Err(status) => {
+            warn!(
+                "Subscribing to the mempool events of the entry point {entry_point:?} failed: {}",
+                status.message()
+            );
+            None
+        }
+    }
+}
+
+/// Waits for the next new user operation of the stream. The stream is dropped once it's closed and this never
+/// resolves without it, so that the bundling falls back to the interval.
+async fn next_user_operation<S>(events: &mut Option<S>)
+where
+    S: Stream<Item = Result<MempoolEvent, tonic::Status>> + Unpin,
+{
+    if let Some(stream) = events.as_mut() {
+        while let Some(Ok(event)) = stream.next().await {
+            if event.kind == MempoolEventKind::NewUserOperation as i32 {
+                return;
+            }
+        }
+        warn!("Mempool events stream closed, bundling on the interval until it's resubscribed");
+        *events = None;
+    }
+    std::future::pending().await
+}
+
+/// Time since the Unix epoch in seconds
+fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+async fn watch_stuck_user_operations(
+    history: Arc<Mutex<BundleHistory>>,
+    entry_points: Vec<Address>,
+    timeout: u64,
+    started_at: u64,
+) -> anyhow::Result<()> {
+    let mut flagged: HashSet<u64> = HashSet::new();
+    let mut interval = tokio::time::interval(WATCHDOG_INTERVAL);
+    loop {
+        interval.tick().await;
+        let now = unix_timestamp();
+        let stuck = history
+            .lock()
+            .get_pending(started_at, now.saturating_sub(timeout));
+
+        // (bundling, submitted) user operations per entry point
+        let mut counts: HashMap<Address, (usize, usize)> = entry_points
+            .iter()
+            .map(|entry_point| (*entry_point, (0, 0)))
+            .collect();
+        for record in stuck.iter() {
+            let count = counts.entry(record.entry_point).or_default();
+            let state = match record.transaction_hash {
+                Some(_) => {
+                    count.1 += record.user_operations.len();
+                    "submitted"
+                }
+                None => {
+                    count.0 += record.user_operations.len();
+                    "bundling"
+                }
+            };
+            if flagged.insert(record.id) {
+                warn!(
+                    bundle_id = record.id,
+                    tx_hash = ?record.transaction_hash,
+                    "User operations {:?} stuck in the {state} state for {}s",
+                    record.user_operations,
+                    now.saturating_sub(record.created_at)
+                );
+            }
+        }
+        flagged.retain(|id| stuck.iter().any(|record| record.id == *id));
+
+        for (entry_point, (bundling, submitted)) in counts {
+            let entry_point = format!("{entry_point:?}");
+            metrics::gauge!("bundler_stuck_user_operations", bundling as f64, "entry_point" => entry_point.clone(), "state" => "bundling");
+            metrics::gauge!("bundler_stuck_user_operations", submitted as f64, "entry_point" => entry_point, "state" => "submitted");
+        }
+    }
+}
+
+/// Value in wei (possibly negative) as ether
+fn wei_to_ether(value: I256) -> f64 {
+    let ether = format_ether(value.unsigned_abs())
+        .parse::<f64>()
+        .unwrap_or_default();
+    if value.is_negative() {
+        -ether
+    } else {
+        ether
+    }
+}
+
+/// Records the outcome of the bundle included by the entry point (the metrics are labeled with the `entry_point`):
+/// - `bundler_bundles_included`, `bundler_bundles_reverted`, `bundler_bundles_cancelled`: number of included,
+///   reverted and cancelled bundles
+/// - `bundler_gas_used`, `bundler_gas_estimated`: gas used by the bundles and their gas limits
+/// - `bundler_bundle_user_operations`: number of user operations per bundle
+/// - `bundler_bundle_inclusion_blocks`: blocks between the submission and the inclusion of the bundle
+/// - `bundler_bundle_profit`: realized profit (or loss) of the bundle in ETH
+fn record_bundle_outcome(entry_point: &Address, entry: &AccountingEntry) {
+    let entry_point = format!("{entry_point:?}");
+    metrics::counter!("bundler_bundles_included", entry.bundles - entry.reverted - entry.cancelled, "entry_point" => entry_point.clone());
+    metrics::counter!("bundler_bundles_reverted", entry.reverted, "entry_point" => entry_point.clone());
+    metrics::counter!("bundler_bundles_cancelled", entry.cancelled, "entry_point" => entry_point.clone());
+    metrics::counter!("bundler_gas_used", entry.gas_used.low_u64(), "entry_point" => entry_point.clone());
+    metrics::counter!("bundler_gas_estimated", entry.gas_estimated.low_u64(), "entry_point" => entry_point.clone());
+    metrics::histogram!("bundler_bundle_user_operations", entry.user_operations as f64, "entry_point" => entry_point.clone());
+    metrics::histogram!("bundler_bundle_inclusion_blocks", entry.inclusion_blocks as f64, "entry_point" => entry_point.clone());
+    metrics::histogram!("bundler_bundle_profit", wei_to_ether(entry.profit()), "entry_point" => entry_point);
+}
+
 impl BundlerService {
     pub fn new(
         wallet: Wallet,
-        beneficiary: Address,
-        uopool_grpc_client: UoPoolClient<tonic::transport::Channel>,
+        opts: &BundlerServiceOpts,
+        uopool_grpc_client: UoPoolGrpcClient,
         entry_points: Vec<Address>,
-        chain_id: U256,
-        eth_client_address: String,
-    ) -> Self {
+        chain_spec: ChainSpec,
+        eth_provider: EthProvider,
+        max_verification_gas: U256,
+    ) -> anyhow::Result<Self> {
+        // all bundlers share the same EOA, so they have to share the nonce manager as well
+        let nonce_manager = Arc::new(tokio::sync::Mutex::new(NonceManager::new(
+            wallet.signer.address(),
+            opts.stuck_transaction_blocks,
+            opts.fee_bump_schedule.clone(),
+            opts.max_fee_bumps,
+            opts.max_fee_per_gas_cap,
+        )));
+        let beneficiary = opts.beneficiary.unwrap_or_else(|| wallet.signer.address());
+        info!("Bundle fees are sent to the beneficiary: {beneficiary:?}");
+        info!(
+            "Using the {} chain profile: {chain_spec:?}",
+            chain_spec.name
+        );
+        if !chain_spec.legacy_gas && !chain_spec.fee_history {
+            warn!("Execution client doesn't serve eth_feeHistory, the bundle fees are set to the gas price");
+        }
+        let submission = match (opts.builder_url.as_ref(), opts.private_relay) {
+            (Some(builder_url), _) => {
+                info!("Bundles are submitted to the block builder: {builder_url}");
+                Submission::Builder(BuilderEndpoint::new(
+                    builder_url.clone(),
+                    opts.builder_searcher()?,
+                ))
+            }
+            (None, true) => {
+                let private_relay = chain_spec.private_relay.ok_or_else(|| {
+                    anyhow::anyhow!("Chain {} has no private relay", chain_spec.name)
+                })?;
+                info!("Bundles are submitted to the private relay: {private_relay}");
+                Submission::Builder(BuilderEndpoint::new(
+                    private_relay.to_string(),
+                    opts.builder_searcher()?,
+                ))
+            }
+            (None, false) if chain_spec.conditional_transactions => {
+                info!("Bundles are submitted as conditional transactions");
+                Submission::Conditional
+            }
+            (None, false) => {
+                info!("Bundles are submitted to the mempool");
+                Submission::Mempool
+            }
+        };
         let bundlers: Vec<BundlerCore> = entry_points
             .iter()
             .map(|entry_point| {
@@ -60,60 +408,584 @@ impl BundlerService {
                     wallet.clone(),
                     beneficiary,
                     *entry_point,
-                    chain_id,
-                    eth_client_address.clone(),
+                    chain_spec.clone(),
+                    eth_provider.clone(),
+                    nonce_manager.clone(),
+                    submission.clone(),
                 )
             })
             .collect();
+        let history =
+            BundleHistory::new(opts.bundle_history_size, opts.bundle_history_path.clone())?;
+        let accounting = Accounting::new(opts.accounting_path.clone())?;
 
-        Self {
+        Ok(Self {
             bundlers,
             running: Arc::new(Mutex::new(false)),
+            interval: Arc::new(Mutex::new(opts.bundle_interval)),
+            paused: Arc::new(Mutex::new(false)),
+            operator_paused: Arc::new(Mutex::new(false)),
             uopool_grpc_client,
+            config: BundlingConfig {
+                aggregators: opts.aggregators.clone(),
+                max_bundle_retries: opts.max_bundle_retries,
+                confirmations: opts.confirmations,
+                max_bundle_gas: opts.max_bundle_gas.unwrap_or(max_verification_gas),
+                max_ops_per_bundle: opts.max_ops_per_bundle,
+                max_calldata_size: chain_spec.max_calldata_size,
+                dry_run: opts.dry_run,
+                stuck_user_operation_timeout: opts.stuck_user_operation_timeout,
+            },
+            block_gas_budget: Arc::new(Mutex::new(BlockGasBudget::new(
+                opts.max_block_gas.unwrap_or(U256::MAX),
+            ))),
+            history: Arc::new(Mutex::new(history)),
+            accounting: Arc::new(Mutex::new(accounting)),
+            chain_spec,
+            signer: wallet.signer.address(),
+            eth_provider,
+            min_balance: opts.min_balance,
+            supervisor: Supervisor::default(),
+        })
+    }
+
+    /// Checks the signer balance on each new block and pauses bundling (the uopool keeps accepting user operations)
+    /// when the balance can't cover the worst-case bundle
+    pub fn start_balance_monitoring(&self) -> anyhow::Result<()> {
+        let provider = self.eth_provider.clone();
+        let signer = self.signer;
+        let min_balance = self.min_balance;
+        let max_bundle_gas = self.config.max_bundle_gas;
+        let paused = self.paused.clone();
+        let chain_spec = self.chain_spec.clone();
+
+        self.supervisor.spawn("bundler_balance", move || {
+            let provider = provider.clone();
+            let paused = paused.clone();
+            let chain_spec = chain_spec.clone();
+            let mut blocks = watch_new_blocks(Arc::new(provider.clone()), BALANCE_POLL_INTERVAL);
+            async move {
+                while blocks.recv().await.is_some() {
+                    let (balance, max_fee_per_gas) = match tokio::try_join!(
+                        async { Ok(provider.get_balance(signer, None).await?) },
+                        estimate_fees(&provider, &chain_spec)
+                    ) {
+                        Ok((balance, (max_fee_per_gas, _))) => (balance, max_fee_per_gas),
+                        Err(e) => {
+                            warn!("Failed to check the bundler balance: {e:?}");
+                            continue;
+                        }
+                    };
+
+                    metrics::gauge!(
+                        "bundler_balance",
+                        format_ether(balance).parse::<f64>().unwrap_or_default()
+                    );
+
+                    if balance < min_balance {
+                        warn!(
+                            "Bundler balance {} ETH is below the minimum balance {} ETH",
+                            format_ether(balance),
+                            format_ether(min_balance)
+                        );
+                    }
+
+                    let worst_case_cost = max_bundle_gas.saturating_mul(max_fee_per_gas);
+                    let mut p = paused.lock();
+                    if balance < worst_case_cost {
+                        if !*p {
+                            error!(
+                                "Bundler balance {} ETH can't cover the worst-case bundle cost {} ETH, pausing bundling",
+                                format_ether(balance),
+                                format_ether(worst_case_cost)
+                            );
+                        }
+                        *p = true;
+                    } else {
+                        if *p {
+                            info!(
+                                "Bundler balance {} ETH is sufficient again, resuming bundling",
+                                format_ether(balance)
+                            );
+                        }
+                        *p = false;
+                    }
+                }
+                Err(anyhow::anyhow!("Watching of the new blocks stopped"))
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Reports the user operations of the bundles (assembled since the start) that are stuck in the bundling (the
+    /// bundle transaction wasn't sent yet) or the submitted state (the transaction isn't included yet) for longer
+    /// than the timeout:
+    /// - `bundler_stuck_user_operations` (`entry_point`, `state`): number of the stuck user operations
+    pub fn start_watchdog(&self) {
+        let timeout = self.config.stuck_user_operation_timeout;
+        if timeout == 0 {
+            return;
         }
+        let history = self.history.clone();
+        let entry_points: Vec<Address> = self
+            .bundlers
+            .iter()
+            .map(|bundler| bundler.entry_point)
+            .collect();
+        let started_at = unix_timestamp();
+
+        self.supervisor.spawn("bundler_watchdog", move || {
+            watch_stuck_user_operations(history.clone(), entry_points.clone(), timeout, started_at)
+        });
     }
 
+    pub fn is_paused(&self) -> bool {
+        is_paused(self.paused.clone())
+    }
+
+    #[instrument(skip_all)]
     async fn create_bundle(
-        uopool_grpc_client: &UoPoolClient<tonic::transport::Channel>,
+        uopool_grpc_client: &UoPoolGrpcClient,
         entry_point: &Address,
-    ) -> anyhow::Result<Vec<UserOperation>> {
+        bundler_address: &Address,
+        config: &BundlingConfig,
+    ) -> anyhow::Result<Bundle> {
+        // the uopool stops at the budget, the bundle is still limited below since its gas is computed differently
         let request = tonic::Request::new(GetSortedRequest {
             entry_point: Some((*entry_point).into()),
+            max_gas: Some(config.max_bundle_gas.into()),
+            max_user_operations: config.max_ops_per_bundle.unwrap_or_default() as u64,
         });
         let response = uopool_grpc_client
             .clone()
             .get_sorted_user_operations(request)
-            .await?;
+            .await?
+            .into_inner();
         let user_operations: Vec<UserOperation> = response
-            .into_inner()
             .user_operations
             .into_iter()
             .map(|u| u.into())
             .collect();
-        Ok(user_operations)
+
+        let mut aggregated = vec![];
+        for uos in response.user_operations_per_aggregator.into_iter() {
+            let aggregator: Address = uos
+                .aggregator
+                .ok_or_else(|| anyhow::anyhow!("Aggregator is missing"))?
+                .into();
+            if !config.aggregators.contains(&aggregator) {
+                warn!(
+                    "Skipping user operations with aggregator {aggregator:?} that is not allowed"
+                );
+                continue;
+            }
+            aggregated.push(UserOperationsPerAggregator {
+                aggregator,
+                user_operations: uos.user_operations.into_iter().map(|u| u.into()).collect(),
+            });
+        }
+
+        let mut bundle = Bundle::new(user_operations, aggregated);
+        for sender_authorization in response.authorizations.into_iter() {
+            let (Some(sender), Some(authorization)) = (
+                sender_authorization.sender,
+                sender_authorization.authorization,
+            ) else {
+                continue;
+            };
+            let sender: Address = sender.into();
+            if !bundle.add_authorization(sender, authorization.into(), bundler_address) {
+                warn!(
+                    "Skipping user operations of sender {sender:?} with conflicting authorization"
+                );
+                bundle.remove_sender(&sender);
+            }
+        }
+        bundle.limit(config.max_bundle_gas, config.max_ops_per_bundle);
+        bundle.limit_calldata_size(config.max_calldata_size);
+        Ok(bundle)
+    }
+
+    /// Limits the bundle to the gas that is still available in the current block (other entry points
+    /// could already send bundles in the same block) and reserves the gas for the bundle
+    async fn reserve_block_gas(
+        bundler: &BundlerCore,
+        block_gas_budget: &Arc<Mutex<BlockGasBudget>>,
+        config: &BundlingConfig,
+        bundle: &mut Bundle,
+    ) -> anyhow::Result<()> {
+        let block_number = bundler.eth_provider.get_block_number().await?;
+
+        let mut block_gas_budget = block_gas_budget.lock();
+        let remaining = block_gas_budget.remaining(block_number);
+        bundle.limit(
+            remaining.min(config.max_bundle_gas),
+            config.max_ops_per_bundle,
+        );
+        if !bundle.is_empty() {
+            block_gas_budget.reserve(block_number, bundle.gas_limit());
+        }
+
+        Ok(())
+    }
+
+    /// Removes the user operation that failed with FailedOp from the bundle, unless the retry budget is exhausted
+    fn remove_failed_op(
+        bundle: &mut Bundle,
+        (index, reason): &(usize, String),
+        retries: u64,
+        max_bundle_retries: u64,
+    ) -> anyhow::Result<UserOperation> {
+        if retries >= max_bundle_retries {
+            return Err(anyhow::anyhow!(
+                "Bundle failed with FailedOp at index {index}: {reason}"
+            ));
+        }
+        bundle.remove(*index).ok_or_else(|| {
+            anyhow::anyhow!("Bundle failed with FailedOp at index {index}: {reason}")
+        })
+    }
+
+    /// Sends the bundle, removing user operations that fail with FailedOp (either in
+    /// the simulation or on chain) and retrying until the retry budget is exhausted.
+    /// Returns the transaction hash and the gas limit of the sent bundle, or `None` if no user operation was left
+    /// to send.
+    #[instrument(skip_all, fields(bundle_id = bundle_id))]
+    async fn send_bundle(
+        bundler: &BundlerCore,
+        uopool_grpc_client: &UoPoolGrpcClient,
+        history: &Arc<Mutex<BundleHistory>>,
+        accounting: &Arc<Mutex<Accounting>>,
+        bundle_id: u64,
+        mut bundle: Bundle,
+        max_bundle_retries: u64,
+    ) -> anyhow::Result<Option<(H256, U256)>> {
+        let included = bundler.included_user_operations(&bundle).await?;
+        for index in included.iter().rev() {
+            if let Some(user_operation) = bundle.remove(*index) {
+                let user_operation_hash =
+                    user_operation.hash(&bundler.entry_point, &bundler.chain_id);
+                info!("Removing user operation {user_operation_hash:?} from bundle, already included by another bundler");
+                history.lock().drop_user_operation(
+                    bundle_id,
+                    user_operation_hash,
+                    "Already included by another bundler".to_string(),
+                );
+            }
+        }
+        if !included.is_empty() && bundle.is_empty() {
+            info!("All user operations in the bundle were already included by another bundler");
+            return Ok(None);
+        }
+
+        let mut retries = 0;
+        loop {
+            let failed_op = match bundler.simulate_bundle(&bundle).await? {
+                Some(failed_op) => failed_op,
+                None => match bundler
+                    .send_next_bundle(&bundle, |attempt| {
+                        history.lock().add_attempt(bundle_id, attempt)
+                    })
+                    .await
+                {
+                    Ok(tx_hash) => return Ok(Some((tx_hash, bundle.gas_limit()))),
+                    Err(e) => {
+                        // the reverted transaction (or the cancellation) was paid for as well
+                        if let Some(unsuccessful) = e.downcast_ref::<UnsuccessfulBundle>() {
+                            Self::account_bundle(
+                                bundler,
+                                accounting,
+                                &unsuccessful.tx_receipt,
+                                bundle.gas_limit(),
+                                unsuccessful.sent_at_block.as_u64(),
+                                unix_timestamp(),
+                            );
+                        }
+                        match bundler.simulate_bundle(&bundle).await {
+                            Ok(Some(failed_op)) => {
+                                warn!("Bundle failed on chain: {e:?}");
+                                failed_op
+                            }
+                            _ => return Err(e),
+                        }
+                    }
+                },
+            };
+
+            let user_operation =
+                Self::remove_failed_op(&mut bundle, &failed_op, retries, max_bundle_retries)?;
+            let (_, reason) = failed_op;
+            retries += 1;
+
+            warn!("Removing user operation {user_operation:?} from bundle, failed with: {reason}");
+            history.lock().drop_user_operation(
+                bundle_id,
+                user_operation.hash(&bundler.entry_point, &bundler.chain_id),
+                reason.clone(),
+            );
+            let request = tonic::Request::new(HandleFailedOpRequest {
+                uo: Some(user_operation.into()),
+                ep: Some(bundler.entry_point.into()),
+                reason,
+            });
+            if let Err(e) = uopool_grpc_client.clone().handle_failed_op(request).await {
+                warn!("Failed to handle failed user operation: {e:?}");
+            }
+
+            // `handleOps` with no user operations would be sent (and pay for the gas) just fine
+            if bundle.is_empty() {
+                info!("All user operations were removed from the bundle, not sending it");
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Simulates the bundle (removing user operations that fail with FailedOp) and logs what would be sent
+    async fn dry_run_bundle(bundler: &BundlerCore, mut bundle: Bundle) -> anyhow::Result<()> {
+        while let Some((index, reason)) = bundler.simulate_bundle(&bundle).await? {
+            match bundle.remove(index) {
+                Some(user_operation) => info!(
+                    "Dry run: user operation {:?} would be removed from bundle, failed with: {reason}",
+                    user_operation.hash(&bundler.entry_point, &bundler.chain_id)
+                ),
+                None => {
+                    return Err(anyhow::anyhow!(
+                        "Bundle failed with FailedOp at index {index}: {reason}"
+                    ))
+                }
+            }
+        }
+
+        if bundle.is_empty() {
+            info!(
+                "Dry run: no bundle would be sent for entry point {:?}",
+                bundler.entry_point
+            );
+            return Ok(());
+        }
+
+        let estimate = bundler.estimate_bundle(&bundle).await?;
+        info!(
+            "Dry run: would send bundle with {} user operations to entry point {:?}, gas: {}, revenue: {} ETH, cost: {} ETH, profit: {} wei",
+            bundle.len(),
+            bundler.entry_point,
+            estimate.gas,
+            format_ether(estimate.revenue),
+            format_ether(estimate.cost),
+            estimate.profit()
+        );
+
+        Ok(())
+    }
+
+    /// Waits for the bundle transaction to be confirmed, then lets the uopool handle its events
+    /// (removal of included user operations and reputation updates)
+    #[instrument(skip_all, fields(tx_hash = ?tx_hash))]
+    async fn track_bundle(
+        bundler: &BundlerCore,
+        uopool_grpc_client: &UoPoolGrpcClient,
+        tx_hash: H256,
+        confirmations: u64,
+    ) -> anyhow::Result<TransactionReceipt> {
+        let tx_receipt = bundler
+            .wait_for_confirmations(tx_hash, confirmations)
+            .await?;
+
+        let request = tonic::Request::new(HandleBundleTransactionRequest {
+            ep: Some(bundler.entry_point.into()),
+            tx_hash: Some(tx_hash.into()),
+        });
+        uopool_grpc_client
+            .clone()
+            .handle_bundle_transaction(request)
+            .await?;
+
+        Ok(tx_receipt)
+    }
+
+    /// Creates, sends and tracks the next bundle for the entry point. Empty bundles are not sent if `skip_empty` is set.
+    #[instrument(skip_all, fields(entry_point = ?bundler.entry_point, bundle_id = field::Empty))]
+    async fn bundle(
+        bundler: &BundlerCore,
+        uopool_grpc_client: &UoPoolGrpcClient,
+        block_gas_budget: &Arc<Mutex<BlockGasBudget>>,
+        history: &Arc<Mutex<BundleHistory>>,
+        accounting: &Arc<Mutex<Accounting>>,
+        config: &BundlingConfig,
+        skip_empty: bool,
+    ) -> anyhow::Result<Option<H256>> {
+        let mut bundle = Self::create_bundle(
+            uopool_grpc_client,
+            &bundler.entry_point,
+            &bundler.wallet.signer.address(),
+            config,
+        )
+        .await?;
+        Self::reserve_block_gas(bundler, block_gas_budget, config, &mut bundle).await?;
+        if skip_empty && bundle.is_empty() {
+            return Ok(None);
+        }
+        if config.dry_run {
+            Self::dry_run_bundle(bundler, bundle).await?;
+            return Ok(None);
+        }
+
+        let created_at = unix_timestamp();
+        let user_operation_hashes: Vec<UserOperationHash> = bundle
+            .user_operations()
+            .iter()
+            .map(|uo| uo.hash(&bundler.entry_point, &bundler.chain_id))
+            .collect();
+        let bundle_id = history.lock().add(
+            bundler.entry_point,
+            user_operation_hashes.clone(),
+            created_at,
+        );
+        Span::current().record("bundle_id", bundle_id);
+        for user_operation_hash in &user_operation_hashes {
+            debug!(?user_operation_hash, "User operation bundled");
+        }
+        let fail = |e: anyhow::Error| {
+            history.lock().update(bundle_id, |record| {
+                record.status = BundleStatus::Failed;
+                record.revert_reason = Some(format!("{e:?}"));
+            });
+            e
+        };
+
+        let sent_at_block = bundler.eth_provider.get_block_number().await?.as_u64();
+        let Some((tx_hash, gas_estimated)) = Self::send_bundle(
+            bundler,
+            uopool_grpc_client,
+            history,
+            accounting,
+            bundle_id,
+            bundle,
+            config.max_bundle_retries,
+        )
+        .await
+        .map_err(fail)?
+        else {
+            history.lock().update(bundle_id, |record| {
+                record.status = BundleStatus::Failed;
+                record.revert_reason =
+                    Some("All user operations were removed from the bundle".to_string());
+            });
+            return Ok(None);
+        };
+        metrics::counter!("bundler_bundles_sent", 1, "entry_point" => format!("{:?}", bundler.entry_point));
+        history.lock().update(bundle_id, |record| {
+            record.transaction_hash = Some(tx_hash);
+        });
+
+        let tx_receipt =
+            Self::track_bundle(bundler, uopool_grpc_client, tx_hash, config.confirmations)
+                .await
+                .map_err(fail)?;
+        history.lock().update(bundle_id, |record| {
+            record.status = BundleStatus::Included;
+            record.gas_used = tx_receipt.gas_used;
+        });
+        for user_operation_hash in &user_operation_hashes {
+            debug!(?user_operation_hash, ?tx_hash, "User operation included");
+        }
+
+        let entry = Self::account_bundle(
+            bundler,
+            accounting,
+            &tx_receipt,
+            gas_estimated,
+            sent_at_block,
+            created_at,
+        );
+        info!(
+            "Bundle {tx_hash:?} included with {} user operations, revenue: {} ETH, cost: {} ETH",
+            entry.user_operations,
+            format_ether(entry.revenue),
+            format_ether(entry.cost)
+        );
+
+        Ok(Some(tx_hash))
+    }
+
+    /// Records the included bundle transaction (reverted or not) or its cancellation in the accounting and the
+    /// metrics
+    fn account_bundle(
+        bundler: &BundlerCore,
+        accounting: &Arc<Mutex<Accounting>>,
+        tx_receipt: &TransactionReceipt,
+        gas_estimated: U256,
+        sent_at_block: u64,
+        timestamp: u64,
+    ) -> AccountingEntry {
+        let (entry, total) = {
+            let mut accounting = accounting.lock();
+            let entry = accounting.record(
+                &bundler.entry_point,
+                tx_receipt,
+                gas_estimated,
+                sent_at_block,
+                timestamp,
+            );
+            (entry, accounting.total)
+        };
+        record_bundle_outcome(&bundler.entry_point, &entry);
+        metrics::gauge!(
+            "bundler_revenue_total",
+            format_ether(total.revenue)
+                .parse::<f64>()
+                .unwrap_or_default()
+        );
+        metrics::gauge!(
+            "bundler_cost_total",
+            format_ether(total.cost).parse::<f64>().unwrap_or_default()
+        );
+        metrics::gauge!("bundler_profit_total", wei_to_ether(total.profit()));
+
+        entry
+    }
+
+    pub fn get_bundles(&self, entry_point: &Address) -> Vec<aa_bundler_primitives::BundleRecord> {
+        self.history.lock().get_all(entry_point)
     }
 
     pub async fn send_bundles_now(&self) -> anyhow::Result<H256> {
+        if self.is_paused() {
+            return Err(anyhow::anyhow!(
+                "Bundling is paused, because the bundler balance is too low"
+            ));
+        }
+
         info!("Sending bundles now");
         let mut tx_hashes: Vec<H256> = vec![];
         for bundler in self.bundlers.iter() {
             info!("Sending bundle for entry point: {:?}", bundler.entry_point);
 
-            let bundle =
-                Self::create_bundle(&self.uopool_grpc_client, &bundler.entry_point).await?;
-            let tx_hash = bundler.send_next_bundle(&bundle).await?;
+            let tx_hash = Self::bundle(
+                bundler,
+                &self.uopool_grpc_client,
+                &self.block_gas_budget,
+                &self.history,
+                &self.accounting,
+                &self.config,
+                false,
+            )
+            .await?;
 
             Self::handle_past_events(&self.uopool_grpc_client, &bundler.entry_point).await?;
 
-            tx_hashes.push(tx_hash)
+            if let Some(tx_hash) = tx_hash {
+                tx_hashes.push(tx_hash)
+            }
         }
 
         // FIXME: Because currently the bundler support multiple bundler and
         // we don't have a way to know which bundler is the one that is
-        Ok(tx_hashes
+        tx_hashes
             .into_iter()
             .next()
-            .expect("Must have at least one tx hash"))
+            .ok_or_else(|| anyhow::anyhow!("No bundle was sent"))
     }
 
     pub fn stop_bundling(&self) {
@@ -127,7 +999,7 @@ impl BundlerService {
     }
 
     async fn handle_past_events(
-        uopool_grpc_client: &UoPoolClient<tonic::transport::Channel>,
+        uopool_grpc_client: &UoPoolGrpcClient,
         entry_point: &Address,
     ) -> anyhow::Result<()> {
         info!("Send handlePastEvents request");
@@ -148,7 +1020,11 @@ impl BundlerService {
         Ok(())
     }
 
+    /// Starts bundling every `interval` seconds, or only changes the interval if the bundling is already running.
+    /// After an empty bundle, the next one is sent as soon as a user operation arrives in the mempool of the entry
+    /// point (the interval then restarts).
     pub fn start_bundling(&self, interval: u64) {
+        *self.interval.lock() = interval;
         if !self.is_running() {
             for bundler in self.bundlers.iter() {
                 info!(
@@ -157,35 +1033,102 @@ impl BundlerService {
                 );
                 let bundler_own = bundler.clone();
                 let running_lock = self.running.clone();
+                let interval_lock = self.interval.clone();
+                let paused_lock = self.paused.clone();
+                let operator_paused_lock = self.operator_paused.clone();
                 let uopool_grpc_client = self.uopool_grpc_client.clone();
-                tokio::spawn(async move {
-                    let mut interval = tokio::time::interval(Duration::from_secs(interval));
-                    loop {
-                        if !is_running(running_lock.clone()) {
-                            break;
-                        }
-                        interval.tick().await;
+                let block_gas_budget = self.block_gas_budget.clone();
+                let history = self.history.clone();
+                let accounting = self.accounting.clone();
+                let config = self.config.clone();
+                let name = format!("bundler_{:?}", bundler.entry_point);
+                self.supervisor.spawn(&name, move || {
+                    let bundler_own = bundler_own.clone();
+                    let running_lock = running_lock.clone();
+                    let interval_lock = interval_lock.clone();
+                    let paused_lock = paused_lock.clone();
+                    let operator_paused_lock = operator_paused_lock.clone();
+                    let uopool_grpc_client = uopool_grpc_client.clone();
+                    let block_gas_budget = block_gas_budget.clone();
+                    let history = history.clone();
+                    let accounting = accounting.clone();
+                    let config = config.clone();
+                    async move {
+                        let mut period = *interval_lock.lock();
+                        let mut interval = tokio::time::interval(Duration::from_secs(period));
+                        let mut events = None;
+                        // the last bundle was empty
+                        let mut idle = false;
+                        loop {
+                            if !is_running(running_lock.clone()) {
+                                break;
+                            }
+                            if events.is_none() {
+                                events =
+                                    subscribe_events(&uopool_grpc_client, &bundler_own.entry_point)
+                                        .await;
+                            }
+                            tokio::select! {
+                                _ = interval.tick() => {}
+                                _ = next_user_operation(&mut events) => {
+                                    // the user operations that arrive in between the bundles wait for the interval
+                                    if !idle {
+                                        continue;
+                                    }
+                                    interval.reset();
+                                }
+                            }
+
+                            let current_period = *interval_lock.lock();
+                            if current_period != period {
+                                info!("Bundle interval changed to {current_period}s");
+                                period = current_period;
+                                interval = tokio::time::interval_at(
+                                    tokio::time::Instant::now() + Duration::from_secs(period),
+                                    Duration::from_secs(period),
+                                );
+                            }
+
+                            if is_paused(operator_paused_lock.clone()) {
+                                debug!(
+                                    "Skipping bundle for entry point {:?}, bundling is paused by the operator",
+                                    bundler_own.entry_point
+                                );
+                                continue;
+                            }
+
+                            if is_paused(paused_lock.clone()) {
+                                warn!(
+                                    "Skipping bundle for entry point {:?}, bundling is paused because of low balance",
+                                    bundler_own.entry_point
+                                );
+                                continue;
+                            }
 
-                        match Self::create_bundle(&uopool_grpc_client, &bundler_own.entry_point)
+                            match Self::bundle(
+                                &bundler_own,
+                                &uopool_grpc_client,
+                                &block_gas_budget,
+                                &history,
+                                &accounting,
+                                &config,
+                                true,
+                            )
                             .await
-                        {
-                            Ok(bundle) => {
-                                if let Err(e) = bundler_own.send_next_bundle(&bundle).await {
-                                    error!("Error while sending bundle: {e:?}");
-                                }
-                                if let Err(e) = Self::handle_past_events(
-                                    &uopool_grpc_client,
-                                    &bundler_own.entry_point,
-                                )
-                                .await
-                                {
-                                    error!("Error while handling past events: {e:?}");
-                                }
+                            {
+                                Ok(tx_hash) => idle = tx_hash.is_none(),
+                                Err(e) => error!("Error while bundling: {e:?}"),
                             }
-                            Err(e) => {
-                                error!("Error while creating bundle: {e:?}");
+                            if let Err(e) = Self::handle_past_events(
+                                &uopool_grpc_client,
+                                &bundler_own.entry_point,
+                            )
+                            .await
+                            {
+                                error!("Error while handling past events: {e:?}");
                             }
                         }
+                        Ok(())
                     }
                 });
             }
@@ -232,6 +1175,19 @@ impl bundler_server::Bundler for BundlerService {
         }
     }
 
+    async fn set_bundling_paused(
+        &self,
+        request: tonic::Request<SetBundlingPausedRequest>,
+    ) -> Result<Response<()>, tonic::Status> {
+        let paused = request.into_inner().paused;
+        info!(
+            "{} bundling by the operator",
+            if paused { "Pausing" } else { "Resuming" }
+        );
+        *self.operator_paused.lock() = paused;
+        Ok(Response::new(()))
+    }
+
     async fn send_bundle_now(
         &self,
         _request: tonic::Request<()>,
@@ -244,14 +1200,107 @@ impl bundler_server::Bundler for BundlerService {
             result: Some(res.into()),
         }))
     }
+
+    async fn get_accounting_report(
+        &self,
+        _request: tonic::Request<()>,
+    ) -> Result<Response<GetAccountingReportResponse>, tonic::Status> {
+        let accounting = self.accounting.lock();
+        Ok(Response::new(GetAccountingReportResponse {
+            total: Some(accounting.total.into()),
+            days: accounting
+                .days
+                .iter()
+                .map(|(day, entry)| DailyAccounting {
+                    day: *day,
+                    entry: Some((*entry).into()),
+                })
+                .collect(),
+            entities: accounting
+                .entities
+                .iter()
+                .map(|(entity, entry)| EntityAccounting {
+                    entity: Some((*entity).into()),
+                    entry: Some((*entry).into()),
+                })
+                .collect(),
+        }))
+    }
+
+    async fn get_bundles(
+        &self,
+        request: tonic::Request<GetBundlesRequest>,
+    ) -> Result<Response<GetBundlesResponse>, tonic::Status> {
+        let req = request.into_inner();
+        let entry_point: Address = req
+            .ep
+            .ok_or_else(|| tonic::Status::invalid_argument("Entry point is missing"))?
+            .into();
+        Ok(Response::new(GetBundlesResponse {
+            bundles: self
+                .get_bundles(&entry_point)
+                .into_iter()
+                .map(|record| record.into())
+                .collect(),
+        }))
+    }
+}
+
+impl BundlerServiceOpts {
+    /// TLS config of the bundler gRPC server, if configured
+    pub fn grpc_tls(&self) -> Option<TlsConfig> {
+        Some(
+            TlsConfig::from_paths(
+                self.bundler_grpc_tls_cert.clone(),
+                self.bundler_grpc_tls_key.clone(),
+            )?
+            .with_client_ca(self.bundler_grpc_tls_client_ca.clone()),
+        )
+    }
+
+    /// Searcher key that signs the requests to the block builder (or relay)
+    pub fn builder_searcher(&self) -> anyhow::Result<LocalWallet> {
+        match self.builder_signing_key {
+            Some(ref key) => key
+                .expose()
+                .trim()
+                .trim_start_matches("0x")
+                .parse()
+                .map_err(|_| anyhow::anyhow!("Invalid builder signing key")),
+            None => {
+                let searcher = LocalWallet::new(&mut ethers::core::rand::thread_rng());
+                info!(
+                    "Signing the builder requests with the generated key of {:?}",
+                    searcher.address()
+                );
+                Ok(searcher)
+            }
+        }
+    }
 }
 
-pub fn bundler_service_run(bundler_service: BundlerService, listen_address: SocketAddr) {
+/// Serves the bundler gRPC server until the shutdown signal, the service is shared with the caller (e.g. to send the
+/// final bundle on shutdown)
+pub fn bundler_service_run(
+    bundler_service: Arc<BundlerService>,
+    listen_address: SocketAddr,
+    grpc_tls: Option<TlsConfig>,
+    shutdown: ShutdownSignal,
+) -> anyhow::Result<()> {
+    let mut builder = tonic::transport::Server::builder();
+    if let Some(tls_config) = grpc_tls {
+        builder = builder.tls_config(tls_config.server_tls_config()?)?;
+    }
+
     tokio::spawn(async move {
-        let mut builder = tonic::transport::Server::builder();
-        let svc = bundler_server::BundlerServer::new(bundler_service);
-        builder.add_service(svc).serve(listen_address).await
+        let svc = TraceContext::new(bundler_server::BundlerServer::from_arc(bundler_service));
+        builder
+            .add_service(svc)
+            .serve_with_shutdown(listen_address, shutdown.wait())
+            .await
     });
+
+    Ok(())
 }
 
 #[cfg(test)]
@@ -276,20 +1325,118 @@ mod tests {
             "127.0.0.1:3002",
             "--bundle-interval",
             "10",
+            "--stuck-transaction-blocks",
+            "3",
+            "--fee-bump-schedule",
+            "10,20",
         ];
         assert_eq!(
             BundlerServiceOpts {
-                beneficiary: Address::from_str("0x690B9A9E9aa1C9dB991C7721a92d351Db4FaC990")
-                    .unwrap(),
+                beneficiary: Some(
+                    Address::from_str("0x690B9A9E9aa1C9dB991C7721a92d351Db4FaC990").unwrap()
+                ),
                 gas_factor: U256::from(600),
                 min_balance: U256::from(1),
                 bundler_grpc_listen_address: SocketAddr::new(
                     IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
                     3002
                 ),
+                bundler_grpc_tls_listen_address: None,
+                bundler_grpc_tls_cert: None,
+                bundler_grpc_tls_key: None,
+                bundler_grpc_tls_client_ca: None,
                 bundle_interval: 10,
+                stuck_transaction_blocks: 3,
+                stuck_user_operation_timeout: 300,
+                fee_bump_schedule: vec![10, 20],
+                max_fee_per_gas_cap: None,
+                max_fee_bumps: 3,
+                max_bundle_retries: 3,
+                aggregators: vec![],
+                confirmations: 1,
+                max_bundle_gas: None,
+                max_ops_per_bundle: None,
+                max_block_gas: None,
+                bundle_history_size: 1000,
+                bundle_history_path: None,
+                builder_url: None,
+                accounting_path: None,
+                private_relay: false,
+                builder_signing_key: None,
+                dry_run: false,
+                shutdown_final_bundle: false,
             },
             BundlerServiceOpts::try_parse_from(args).unwrap()
         );
+
+        let args = vec!["bundleropts", "--min-balance", "1"];
+        assert_eq!(
+            BundlerServiceOpts::try_parse_from(args)
+                .unwrap()
+                .beneficiary,
+            None
+        );
+
+        // replacements bumped by less than 10% would be rejected
+        let args = vec!["bundleropts", "--fee-bump-schedule", "10,5"];
+        assert!(BundlerServiceOpts::try_parse_from(args).is_err());
+    }
+
+    #[test]
+    fn remove_failed_op() {
+        let user_operations = vec![UserOperation::random(), UserOperation::random()];
+        let mut bundle = Bundle::new(user_operations.clone(), vec![]);
+        let failed_op = |index: usize| (index, "AA23 reverted".to_string());
+
+        assert_eq!(
+            BundlerService::remove_failed_op(&mut bundle, &failed_op(1), 0, 3).unwrap(),
+            user_operations[1]
+        );
+        assert!(BundlerService::remove_failed_op(&mut bundle, &failed_op(1), 1, 3).is_err());
+        assert!(!bundle.is_empty());
+
+        // the last user operation is removed, the empty bundle must not be sent
+        assert_eq!(
+            BundlerService::remove_failed_op(&mut bundle, &failed_op(0), 1, 3).unwrap(),
+            user_operations[0]
+        );
+        assert!(bundle.is_empty());
+
+        let mut bundle = Bundle::new(user_operations, vec![]);
+        assert!(BundlerService::remove_failed_op(&mut bundle, &failed_op(0), 3, 3).is_err());
+        assert_eq!(bundle.len(), 2);
+    }
+
+    #[test]
+    fn wei_to_ether_signed() {
+        let wei = I256::from_dec_str("1500000000000000000").unwrap();
+        assert_eq!(wei_to_ether(wei), 1.5);
+        assert_eq!(wei_to_ether(-wei), -1.5);
+    }
+
+    #[tokio::test]
+    async fn wake_on_new_user_operation() {
+        let event = |kind: MempoolEventKind| {
+            Ok::<_, tonic::Status>(MempoolEvent {
+                kind: kind as i32,
+                ..Default::default()
+            })
+        };
+        let mut events = Some(tokio_stream::iter(vec![
+            event(MempoolEventKind::UserOperationIncluded),
+            event(MempoolEventKind::NewUserOperation),
+        ]));
+        tokio::time::timeout(Duration::from_secs(1), next_user_operation(&mut events))
+            .await
+            .unwrap();
+        assert!(events.is_some());
+
+        // the closed stream is dropped and the bundling waits for the interval
+        assert!(
+            tokio::time::timeout(Duration::from_millis(100), next_user_operation(&mut events))
+                .await
+                .is_err()
+        );
+        assert!(events.is_none());
     }
 }
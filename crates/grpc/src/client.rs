@@ -0,0 +1,124 @@
+use std::{path::PathBuf, sync::Arc, time::Duration};
+
+use tokio::net::UnixStream;
+use tonic::transport::{Channel, Endpoint, Uri};
+use tower::service_fn;
+use tracing::warn;
+
+use crate::{
+    tls::{connect_endpoint, grpc_endpoint},
+    uopool_client, ClientTlsConfig, UoPoolGrpcClient,
+};
+
+/// Timeout of the requests to the uopool (the sorted user operations are simulated, so it has to be generous)
+const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+/// Number of attempts to connect before giving up, the delay between the attempts doubles up to the maximum
+const DEFAULT_CONNECT_ATTEMPTS: u32 = 10;
+const INITIAL_CONNECT_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_CONNECT_BACKOFF: Duration = Duration::from_secs(8);
+
+/// Address of the uopool gRPC server
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum UoPoolAddress {
+    /// `host:port` of the TCP listener
+    Tcp(String),
+    /// Unix domain socket (IPC)
+    Ipc(PathBuf),
+}
+
+/// Establishes the connections of the uopool gRPC clients (of the JSON-RPC server and the bundler). The connection
+/// is retried with exponential backoff until the uopool is up, afterwards the channel reconnects by itself when the
+/// connection is lost.
+#[derive(Clone, Debug)]
+pub struct UoPoolConnector {
+    address: UoPoolAddress,
+    tls_config: Option<ClientTlsConfig>,
+    auth_token: Option<Arc<str>>,
+    request_timeout: Duration,
+    connect_attempts: u32,
+}
+
+impl UoPoolConnector {
+    pub fn new(address: UoPoolAddress) -> Self {
+        Self {
+            address,
+            tls_config: None,
+            auth_token: None,
+            request_timeout: DEFAULT_REQUEST_TIMEOUT,
+            connect_attempts: DEFAULT_CONNECT_ATTEMPTS,
+        }
+    }
+
+    /// TLS of the TCP connection (not used over IPC)
+    pub fn with_tls_config(mut self, tls_config: Option<ClientTlsConfig>) -> Self {
+        self.tls_config = tls_config;
+        self
+    }
+
+    /// Shared secret of the uopool gRPC server (not used over IPC)
+    pub fn with_auth_token(mut self, auth_token: Option<Arc<str>>) -> Self {
+        self.auth_token = auth_token;
+        self
+    }
+
+    pub fn with_request_timeout(mut self, request_timeout: Duration) -> Self {
+        self.request_timeout = request_timeout;
+        self
+    }
+
+    pub fn with_connect_attempts(mut self, connect_attempts: u32) -> Self {
+        self.connect_attempts = connect_attempts.max(1);
+        self
+    }
+
+    async fn channel(&self) -> anyhow::Result<Channel> {
+        match &self.address {
+            UoPoolAddress::Tcp(address) => {
+                let endpoint = grpc_endpoint(address)?.timeout(self.request_timeout);
+                connect_endpoint(endpoint, address, self.tls_config.as_ref()).await
+            }
+            UoPoolAddress::Ipc(ipc_path) => {
+                let ipc_path = ipc_path.clone();
+                // the connector ignores the URI, but the endpoint requires a valid one
+                Ok(Endpoint::try_from("http://[::]:50051")?
+                    .timeout(self.request_timeout)
+                    .connect_with_connector(service_fn(move |_: Uri| {
+                        UnixStream::connect(ipc_path.clone())
+                    }))
+                    .await?)
+            }
+        }
+    }
+
+    pub async fn connect(&self) -> anyhow::Result<UoPoolGrpcClient> {
+        let mut backoff = INITIAL_CONNECT_BACKOFF;
+        let mut attempt = 1;
+        let channel = loop {
+            match self.channel().await {
+                Ok(channel) => break channel,
+                Err(err) if attempt < self.connect_attempts => {
+                    warn!(
+                        "Connecting to the uopool at {:?} failed (attempt {attempt}), retrying in {backoff:?}: {err:?}",
+                        self.address
+                    );
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_CONNECT_BACKOFF);
+                    attempt += 1;
+                }
+                Err(err) => {
+                    return Err(err.context(format!(
+                        "Connecting to the uopool at {:?} failed after {attempt} attempts",
+                        self.address
+                    )))
+                }
+            }
+        };
+
+        let auth_token = match self.address {
+            UoPoolAddress::Tcp(_) => self.auth_token.clone(),
+            // the socket is protected by its file permissions
+            UoPoolAddress::Ipc(_) => None,
+        };
+        Ok(uopool_client(channel, auth_token))
+    }
+}
@@ -0,0 +1,579 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    ffi::OsString,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use aa_bundler_primitives::REDACTED;
+use anyhow::{format_err, Result};
+use clap::{
+    builder::Resettable, parser::ValueSource, value_parser, Arg, ArgAction, ArgMatches, Command,
+    FromArgMatches, Id,
+};
+use serde_json::{Map, Value};
+
+/// Prefix of the environment variables that set the options, e.g. `AA_BUNDLER_MIN_STAKE` sets `--min-stake`
+pub const ENV_PREFIX: &str = "AA_BUNDLER_";
+
+/// Options with the secrets (the signer's key, the auth tokens), which are only taken from the environment variables
+/// or the config file so that they never appear in the process arguments, and which are redacted in the config dump
+pub const SECRET_OPTIONS: &[&str] = &[
+    "mnemonic",
+    "private-key",
+    "builder-signing-key",
+    "uopool-grpc-auth-token",
+    "rpc-api-keys",
+];
+
+/// Table of the config file with the chain profiles (e.g. `[chains.mainnet]`), selected with `--chain-profile`
+const CHAIN_PROFILES: &str = "chains";
+/// Table of the config file with the options of the entry points (e.g. `[entry-point-overrides."0x5FF1..."]`), which
+/// are passed as `--entry-point-override`
+const ENTRY_POINT_OVERRIDES: &str = "entry-point-overrides";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Toml,
+    Yaml,
+}
+
+impl ConfigFormat {
+    fn from_path(path: &Path) -> Result<Self> {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("toml") => Ok(Self::Toml),
+            Some("yaml") | Some("yml") => Ok(Self::Yaml),
+            _ => Err(format_err!(
+                "Config file {} must have the .toml, .yaml or .yml extension",
+                path.display()
+            )),
+        }
+    }
+}
+
+/// Options set in the config file, by the long name of the option (`min-stake` or `min_stake`). The nested tables
+/// are sections (e.g. `[uopool]`, `[rpc]`) that only group the options, except the chain profiles which override the
+/// other options when selected.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Config {
+    values: BTreeMap<String, Vec<String>>,
+}
+
+fn scalar(key: &str, value: &Value) -> Result<String> {
+    match value {
+        Value::String(value) => Ok(value.clone()),
+        Value::Number(value) => Ok(value.to_string()),
+        Value::Bool(value) => Ok(value.to_string()),
+        _ => Err(format_err!(
+            "Option {key} must be a string, a number, a boolean or a list of them"
+        )),
+    }
+}
+
+impl Config {
+    pub fn parse(
+        contents: &str,
+        format: ConfigFormat,
+        chain_profile: Option<&str>,
+    ) -> Result<Self> {
+        let root: Value = match format {
+            ConfigFormat::Toml => toml::from_str(contents)?,
+            ConfigFormat::Yaml => serde_yaml::from_str(contents)?,
+        };
+        let mut root = match root {
+            Value::Object(root) => root,
+            Value::Null => Map::new(),
+            _ => return Err(format_err!("Config file must be a table of options")),
+        };
+        let profiles = root.remove(CHAIN_PROFILES);
+        let entry_point_overrides = root
+            .remove(ENTRY_POINT_OVERRIDES)
+            .or_else(|| root.remove(&ENTRY_POINT_OVERRIDES.replace('-', "_")));
+
+        let mut config = Self::default();
+        config.extend(&root, false)?;
+        if let Some(entry_point_overrides) = entry_point_overrides {
+            config.extend_entry_point_overrides(&entry_point_overrides)?;
+        }
+        if let Some(chain_profile) = chain_profile {
+            let profile = profiles
+                .as_ref()
+                .and_then(|profiles| profiles.get(chain_profile))
+                .and_then(Value::as_object)
+                .ok_or_else(|| {
+                    format_err!("Chain profile {chain_profile} is not in the config file")
+                })?;
+            config.extend(profile, true)?;
+        }
+        Ok(config)
+    }
+
+    pub fn load(path: &Path, chain_profile: Option<&str>) -> Result<Self> {
+        let contents = fs::read_to_string(path).map_err(|error| {
+            format_err!("Could not read config file {}: {error}", path.display())
+        })?;
+        Self::parse(&contents, ConfigFormat::from_path(path)?, chain_profile)
+            .map_err(|error| format_err!("Invalid config file {}: {error}", path.display()))
+    }
+
+    fn extend(&mut self, table: &Map<String, Value>, overriding: bool) -> Result<()> {
+        for (key, value) in table {
+            let values = match value {
+                Value::Object(section) => {
+                    self.extend(section, overriding)?;
+                    continue;
+                }
+                Value::Array(values) => values
+                    .iter()
+                    .map(|value| scalar(key, value))
+                    .collect::<Result<Vec<_>>>()?,
+                value => vec![scalar(key, value)?],
+            };
+            if self.values.insert(key.replace('_', "-"), values).is_some() && !overriding {
+                return Err(format_err!("Option {key} is set more than once"));
+            }
+        }
+        Ok(())
+    }
+
+    fn extend_entry_point_overrides(&mut self, entry_point_overrides: &Value) -> Result<()> {
+        let entry_point_overrides = entry_point_overrides.as_object().ok_or_else(|| {
+            format_err!("{ENTRY_POINT_OVERRIDES} must be a table of the entry points")
+        })?;
+        let mut values = vec![];
+        for (entry_point, options) in entry_point_overrides {
+            let options = options
+                .as_object()
+                .ok_or_else(|| {
+                    format_err!("Options of the entry point {entry_point} must be a table")
+                })?
+                .iter()
+                .map(|(name, value)| Ok(format!("{name}={}", scalar(name, value)?)))
+                .collect::<Result<Vec<_>>>()?;
+            values.push(format!("{entry_point}:{}", options.join(",")));
+        }
+        self.values
+            .insert("entry-point-override".to_string(), values);
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&[String]> {
+        self.values.get(name).map(Vec::as_slice)
+    }
+
+    /// Sets the options as the defaults of the arguments (of the command and its subcommands), so that the
+    /// environment variables and the command line arguments take precedence over the config file
+    pub fn apply(&self, command: Command) -> Result<Command> {
+        let names = long_names(&command);
+        let unknown: Vec<&str> = self
+            .values
+            .keys()
+            .filter(|name| !names.contains(*name))
+            .map(String::as_str)
+            .collect();
+        if !unknown.is_empty() {
+            return Err(format_err!(
+                "Unknown options in the config file: {}",
+                unknown.join(", ")
+            ));
+        }
+
+        Ok(map_commands(command, &|command| {
+            self.apply_defaults(command)
+        }))
+    }
+
+    fn apply_defaults(&self, mut command: Command) -> Command {
+        let ids: BTreeMap<String, Id> = command
+            .get_arguments()
+            .filter_map(|arg| Some((arg.get_long()?.to_string(), arg.get_id().clone())))
+            .collect();
+        for (name, values) in self.values.iter() {
+            if let Some(id) = ids.get(name) {
+                command = command.mut_arg(id.clone(), |arg| {
+                    // the value of the config file satisfies the requirement of the argument
+                    arg.required(false)
+                        .required_unless_present(Resettable::<Id>::Reset)
+                        .default_values(values.clone())
+                });
+            }
+        }
+        command
+    }
+}
+
+/// Long names of the arguments of the command and of its subcommands
+fn long_names(command: &Command) -> BTreeSet<String> {
+    command
+        .get_arguments()
+        .filter_map(|arg| Some(arg.get_long()?.to_string()))
+        .chain(command.get_subcommands().flat_map(long_names))
+        .collect()
+}
+
+/// Modifies the command and its subcommands (e.g. the options of the `run` subcommand)
+fn map_commands<F: Fn(Command) -> Command>(mut command: Command, f: &F) -> Command {
+    for subcommand in command.get_subcommands_mut() {
+        *subcommand = map_commands(std::mem::take(subcommand), f);
+    }
+    f(command)
+}
+
+fn env_name(long: &str) -> String {
+    format!("{ENV_PREFIX}{}", long.replace('-', "_").to_uppercase())
+}
+
+fn is_secret(arg: &Arg) -> bool {
+    arg.get_long()
+        .map_or(false, |long| SECRET_OPTIONS.contains(&long))
+}
+
+/// Lets the environment variables (`AA_BUNDLER_*`) set the options that aren't given on the command line (the values
+/// of the secrets aren't shown in the usage)
+pub fn with_env(command: Command) -> Command {
+    map_commands(command, &|mut command: Command| {
+        let envs: Vec<(Id, String, bool)> = command
+            .get_arguments()
+            .filter(|arg| arg.get_env().is_none())
+            .filter_map(|arg| {
+                Some((
+                    arg.get_id().clone(),
+                    env_name(arg.get_long()?),
+                    is_secret(arg),
+                ))
+            })
+            .collect();
+        for (id, env, secret) in envs {
+            command = command.mut_arg(id, |arg| arg.env(env).hide_env_values(secret));
+        }
+        command
+    })
+}
+
+/// Fails if a secret is given on the command line, where it's visible to the other users (e.g. in `ps`)
+fn check_secrets(command: &Command, matches: &ArgMatches) -> Result<()> {
+    for arg in command.get_arguments().filter(|arg| is_secret(arg)) {
+        if matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine) {
+            let long = arg.get_long().unwrap_or_default();
+            return Err(format_err!(
+                "Option --{long} is a secret, set it with the {} environment variable or in the config file",
+                env_name(long)
+            ));
+        }
+    }
+    match matches.subcommand() {
+        Some((name, matches)) => match command.find_subcommand(name) {
+            Some(subcommand) => check_secrets(subcommand, matches),
+            None => Ok(()),
+        },
+        None => Ok(()),
+    }
+}
+
+/// Effective options (by the long name) of the command and of the selected subcommand, the secrets redacted
+fn dump(command: &Command, matches: &ArgMatches, options: &mut BTreeMap<String, Value>) {
+    for arg in command.get_arguments() {
+        let long = match arg.get_long() {
+            Some(long) => long,
+            None => continue,
+        };
+        let values: Vec<Value> = match matches.try_get_raw(arg.get_id().as_str()) {
+            Ok(Some(values)) => values
+                .map(|value| Value::String(value.to_string_lossy().into_owned()))
+                .collect(),
+            _ => continue,
+        };
+        let value = if is_secret(arg) {
+            Value::String(REDACTED.to_string())
+        } else if matches!(arg.get_action(), ArgAction::Append)
+            || arg.get_value_delimiter().is_some()
+        {
+            Value::Array(values)
+        } else {
+            values.into_iter().next().unwrap_or(Value::Null)
+        };
+        options.insert(long.to_string(), value);
+    }
+    if let Some((name, matches)) = matches.subcommand() {
+        if let Some(subcommand) = command.find_subcommand(name) {
+            dump(subcommand, matches, options);
+        }
+    }
+}
+
+fn with_config_args(command: Command) -> Command {
+    command
+        .arg(
+            Arg::new("config")
+                .long("config")
+                .value_name("FILE")
+                .value_parser(value_parser!(PathBuf))
+                .action(ArgAction::Set)
+                .global(true)
+                .help("Config file (TOML or YAML), the environment variables and the command line arguments override its options"),
+        )
+        .arg(
+            Arg::new("chain_profile")
+                .long("chain-profile")
+                .value_name("NAME")
+                .requires("config")
+                .action(ArgAction::Set)
+                .global(true)
+                .help("Chain profile of the config file (e.g. `mainnet` for `[chains.mainnet]`) that overrides its other options"),
+        )
+}
+
+/// Parses the options, each taken from the command line arguments, the environment variables (`AA_BUNDLER_*`) or the
+/// config file given with `--config`, in this order of precedence. Exits with the usage on invalid arguments.
+pub fn parse_opts<T: FromArgMatches>(command: Command) -> Result<T> {
+    match parse_opts_from(command, std::env::args_os()) {
+        Err(err) => match err.downcast::<clap::Error>() {
+            Ok(err) => err.exit(),
+            Err(err) => Err(err),
+        },
+        opts => opts,
+    }
+}
+
+/// Parses the options again (e.g. after the config file was changed), the invalid arguments are returned as errors
+pub fn reparse_opts<T: FromArgMatches>(command: Command) -> Result<T> {
+    parse_opts_from(command, std::env::args_os())
+}
+
+/// Options the process was started with (from all the sources), the secrets redacted, for the `admin_getConfig`
+/// method
+pub fn dump_config(command: Command) -> Result<BTreeMap<String, Value>> {
+    dump_config_from(command, std::env::args_os())
+}
+
+pub fn dump_config_from<I>(command: Command, args: I) -> Result<BTreeMap<String, Value>>
+where
+    I: IntoIterator,
+    I::Item: Into<OsString> + Clone,
+{
+    let (command, matches) = matches_from(command, args)?;
+    let mut options = BTreeMap::new();
+    dump(&command, &matches, &mut options);
+    Ok(options)
+}
+
+pub fn parse_opts_from<T, I>(command: Command, args: I) -> Result<T>
+where
+    T: FromArgMatches,
+    I: IntoIterator,
+    I::Item: Into<OsString> + Clone,
+{
+    let (_, matches) = matches_from(command, args)?;
+    Ok(T::from_arg_matches(&matches)?)
+}
+
+fn matches_from<I>(command: Command, args: I) -> Result<(Command, ArgMatches)>
+where
+    I: IntoIterator,
+    I::Item: Into<OsString> + Clone,
+{
+    let args: Vec<OsString> = args.into_iter().map(Into::into).collect();
+    let command = with_env(with_config_args(command));
+
+    // the config file has to be known before the other arguments are validated (it may set the required ones)
+    let (config_path, chain_profile) = match command
+        .clone()
+        .ignore_errors(true)
+        .try_get_matches_from(&args)
+    {
+        Ok(matches) => (
+            matches.get_one::<PathBuf>("config").cloned(),
+            matches.get_one::<String>("chain_profile").cloned(),
+        ),
+        Err(_) => (None, None),
+    };
+    let command = match config_path {
+        Some(config_path) => {
+            Config::load(&config_path, chain_profile.as_deref())?.apply(command)?
+        }
+        None => command,
+    };
+
+    let matches = command.clone().try_get_matches_from(args)?;
+    check_secrets(&command, &matches)?;
+    Ok((command, matches))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::ArgMatches;
+
+    const CONFIG: &str = r#"
+entry-points = ["0x5FF137D4b0FDCD49DcA30c7CF57E578a026d2789"]
+max_verification_gas = 1500000
+
+[uopool]
+min-stake = "1000000000000000000"
+
+[chains.polygon]
+min-stake = "10000000000000000000"
+
+[entry-point-overrides."0x5FF137D4b0FDCD49DcA30c7CF57E578a026d2789"]
+max-verification-gas = 3000000
+simulation-block = "pending"
+"#;
+
+    fn command() -> Command {
+        Command::new("test")
+            .arg(
+                Arg::new("entry_points")
+                    .long("entry-points")
+                    .value_delimiter(',')
+                    .action(ArgAction::Append),
+            )
+            .arg(
+                Arg::new("max_verification_gas")
+                    .long("max-verification-gas")
+                    .required(true),
+            )
+            .arg(Arg::new("min_stake").long("min-stake"))
+            .arg(
+                Arg::new("entry_point_overrides")
+                    .long("entry-point-override")
+                    .action(ArgAction::Append),
+            )
+    }
+
+    fn value(matches: &ArgMatches, id: &str) -> String {
+        matches.get_one::<String>(id).cloned().unwrap()
+    }
+
+    #[test]
+    fn parse_config_sections_and_profiles() {
+        let config = Config::parse(CONFIG, ConfigFormat::Toml, None).unwrap();
+        assert_eq!(
+            config.get("entry-points"),
+            Some(&["0x5FF137D4b0FDCD49DcA30c7CF57E578a026d2789".to_string()][..])
+        );
+        assert_eq!(
+            config.get("max-verification-gas"),
+            Some(&["1500000".to_string()][..])
+        );
+        assert_eq!(
+            config.get("min-stake"),
+            Some(&["1000000000000000000".to_string()][..])
+        );
+        assert_eq!(
+            config.get("entry-point-override"),
+            Some(&["0x5FF137D4b0FDCD49DcA30c7CF57E578a026d2789:max-verification-gas=3000000,simulation-block=pending".to_string()][..])
+        );
+
+        let config = Config::parse(CONFIG, ConfigFormat::Toml, Some("polygon")).unwrap();
+        assert_eq!(
+            config.get("min-stake"),
+            Some(&["10000000000000000000".to_string()][..])
+        );
+        assert!(Config::parse(CONFIG, ConfigFormat::Toml, Some("mainnet")).is_err());
+
+        let yaml = "max-verification-gas: 1500000\nrpc:\n  min-stake: '1'\n";
+        let config = Config::parse(yaml, ConfigFormat::Yaml, None).unwrap();
+        assert_eq!(config.get("min-stake"), Some(&["1".to_string()][..]));
+
+        assert!(Config::parse(
+            "min-stake = 1\n[uopool]\nmin_stake = 2",
+            ConfigFormat::Toml,
+            None
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn command_line_overrides_config() {
+        let config = Config::parse(CONFIG, ConfigFormat::Toml, None).unwrap();
+        let matches = config
+            .apply(command())
+            .unwrap()
+            .try_get_matches_from(["test", "--min-stake", "5"])
+            .unwrap();
+        assert_eq!(value(&matches, "max_verification_gas"), "1500000");
+        assert_eq!(value(&matches, "min_stake"), "5");
+        assert_eq!(
+            value(&matches, "entry_points"),
+            "0x5FF137D4b0FDCD49DcA30c7CF57E578a026d2789"
+        );
+
+        let config = Config::parse("unknown-option = 1", ConfigFormat::Toml, None).unwrap();
+        assert!(config.apply(command()).is_err());
+    }
+
+    #[test]
+    fn config_applies_to_subcommands() {
+        let config = Config::parse(CONFIG, ConfigFormat::Toml, None).unwrap();
+        let matches = config
+            .apply(Command::new("test").subcommand(command().name("run")))
+            .unwrap()
+            .try_get_matches_from(["test", "run"])
+            .unwrap();
+        let (_, matches) = matches.subcommand().unwrap();
+        assert_eq!(value(matches, "max_verification_gas"), "1500000");
+        assert_eq!(value(matches, "min_stake"), "1000000000000000000");
+    }
+
+    #[test]
+    fn secrets_are_not_taken_from_command_line() {
+        let secret_command = || {
+            command().arg(
+                Arg::new("uopool_grpc_auth_token")
+                    .long("uopool-grpc-auth-token")
+                    .action(ArgAction::Set),
+            )
+        };
+        assert!(matches_from(
+            secret_command(),
+            [
+                "test",
+                "--max-verification-gas",
+                "1",
+                "--uopool-grpc-auth-token",
+                "secret"
+            ]
+        )
+        .is_err());
+
+        std::env::set_var("AA_BUNDLER_UOPOOL_GRPC_AUTH_TOKEN", "secret");
+        let (_, matches) = matches_from(
+            secret_command(),
+            [
+                "test",
+                "--max-verification-gas",
+                "1",
+                "--entry-points",
+                "0x1,0x2",
+            ],
+        )
+        .unwrap();
+        assert_eq!(value(&matches, "uopool_grpc_auth_token"), "secret");
+
+        let options = dump_config_from(
+            secret_command(),
+            [
+                "test",
+                "--max-verification-gas",
+                "1",
+                "--entry-points",
+                "0x1,0x2",
+            ],
+        )
+        .unwrap();
+        std::env::remove_var("AA_BUNDLER_UOPOOL_GRPC_AUTH_TOKEN");
+        assert_eq!(options["uopool-grpc-auth-token"], REDACTED);
+        assert_eq!(options["max-verification-gas"], "1");
+        assert_eq!(options["entry-points"], serde_json::json!(["0x1", "0x2"]));
+        assert!(!options.contains_key("min-stake"));
+    }
+
+    #[test]
+    fn env_names() {
+        assert_eq!(env_name("min-stake"), "AA_BUNDLER_MIN_STAKE");
+        assert_eq!(
+            env_name("uopool-grpc-listen-address"),
+            "AA_BUNDLER_UOPOOL_GRPC_LISTEN_ADDRESS"
+        );
+    }
+}
@@ -0,0 +1,127 @@
+use std::{fmt, sync::Arc};
+
+use aa_bundler_primitives::{
+    EthProvider, ProviderCapabilities, SanityCheckError, SimulationError, UserOperation,
+};
+use aa_bundler_uopool::{required_prefund, Overhead, UoPool as UserOperationPool};
+use anyhow::{format_err, Result};
+use ethers::{
+    providers::Middleware,
+    types::{spoof, Address, BlockNumber, U256},
+};
+
+use crate::uopool::{new_user_operation_pool, UoPoolServiceOpts};
+
+/// Rule that the synthetic user operation of the self-test didn't pass
+#[derive(Debug)]
+pub enum SelfTestFailure {
+    /// Sanity check (e.g. `max_fee_per_gas`), before the simulation
+    SanityCheck {
+        rule: &'static str,
+        error: SanityCheckError,
+    },
+    /// Check of the simulation of the validation (e.g. `opcode` of the factory)
+    Simulation {
+        kind: &'static str,
+        entity: Option<String>,
+        error: SimulationError,
+    },
+}
+
+impl fmt::Display for SelfTestFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SelfTestFailure::SanityCheck { rule, error } => {
+                write!(f, "sanity check `{rule}` failed: {}", error.message())
+            }
+            SelfTestFailure::Simulation {
+                kind,
+                entity: Some(entity),
+                error,
+            } => write!(
+                f,
+                "simulation check `{kind}` of the {entity} failed: {}",
+                error.message()
+            ),
+            SelfTestFailure::Simulation {
+                kind,
+                entity: None,
+                error,
+            } => write!(f, "simulation check `{kind}` failed: {}", error.message()),
+        }
+    }
+}
+
+/// Verification of a synthetic user operation by a mempool that is configured like the uopool's, without adding the
+/// user operation to the mempool (for diagnosing the setups that reject every user operation)
+pub struct SelfTest {
+    uopool: UserOperationPool<EthProvider>,
+}
+
+impl SelfTest {
+    pub async fn new(
+        opts: &UoPoolServiceOpts,
+        eth_provider: Arc<EthProvider>,
+        entry_point: Address,
+        max_verification_gas: U256,
+    ) -> Result<Self> {
+        let chain_id = eth_provider.get_chainid().await?;
+        let simulation_block = opts
+            .simulation_block
+            .parse::<BlockNumber>()
+            .map_err(|e| format_err!("Invalid simulation block: {e}"))?;
+        let capabilities = ProviderCapabilities::probe(eth_provider.as_ref()).await;
+        Ok(Self {
+            uopool: new_user_operation_pool(
+                opts,
+                eth_provider,
+                entry_point,
+                chain_id,
+                &capabilities,
+                max_verification_gas,
+                simulation_block,
+            ),
+        })
+    }
+
+    /// Maximum verification gas limit of the user operations (with the overrides of the entry point)
+    pub fn max_verification_gas(&self) -> U256 {
+        self.uopool.max_verification_gas
+    }
+
+    /// Whether the validation rules are checked on the trace of the simulation
+    pub fn trace_validation(&self) -> bool {
+        self.uopool.trace_validation
+    }
+
+    /// Pre-verification gas that the sanity checks require of the user operation
+    pub fn pre_verification_gas(&self, user_operation: &UserOperation) -> U256 {
+        Overhead::default().calculate_pre_verification_gas(user_operation)
+    }
+
+    /// Runs the sanity checks and the simulation of the user operation. The sender's balance is set to the prefund of
+    /// the user operation for the simulation, so that the synthetic account doesn't need a deposit.
+    pub async fn run(&self, user_operation: &UserOperation) -> Result<(), SelfTestFailure> {
+        self.uopool
+            .validate_user_operation(user_operation)
+            .await
+            .map_err(|error| SelfTestFailure::SanityCheck {
+                rule: error.rule(),
+                error: error.into(),
+            })?;
+
+        let mut state_override = spoof::State::default();
+        state_override
+            .account(user_operation.sender)
+            .balance(required_prefund(user_operation));
+        self.uopool
+            .simulate_user_operation(user_operation, Some(&state_override))
+            .await
+            .map_err(|error| SelfTestFailure::Simulation {
+                kind: error.kind(),
+                entity: error.entity().map(str::to_string),
+                error: error.into(),
+            })?;
+        Ok(())
+    }
+}
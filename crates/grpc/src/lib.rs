@@ -1,12 +1,51 @@
 #![allow(dead_code)]
 
+mod auth;
+mod blocks;
 mod bundler;
+mod client;
+mod config;
+mod doctor;
+mod metrics;
+mod p2p;
 mod proto;
+mod reload;
+mod runtime;
+mod shutdown;
+mod snapshot;
+mod status;
+mod supervisor;
+mod telemetry;
+mod tls;
 mod uopool;
 
 pub use proto::bundler::*;
 pub use proto::types::*;
 pub use proto::uopool::*;
 
+pub use auth::{
+    auth_token, read_auth_token, uopool_client, AuthInterceptor, AuthValidator, UoPoolGrpcClient,
+};
 pub use bundler::{bundler_service_run, BundlerService, BundlerServiceOpts};
-pub use uopool::{uopool_service_run, UoPoolServiceOpts};
+pub use client::{UoPoolAddress, UoPoolConnector};
+pub use config::{
+    dump_config, dump_config_from, parse_opts, parse_opts_from, reparse_opts, with_env, Config,
+    ConfigFormat, ENV_PREFIX, SECRET_OPTIONS,
+};
+pub use doctor::{SelfTest, SelfTestFailure};
+pub use metrics::{start_metrics_server, MetricsOpts};
+pub use reload::{ConfigReloader, ReloadableOpts};
+pub use runtime::RuntimeOpts;
+pub use shutdown::{wait_for_termination, ShutdownOpts, ShutdownSignal};
+pub use snapshot::{MempoolSnapshot, SnapshotUserOperation, UoPoolSnapshot};
+pub use status::{error_status, status_code, user_operation_error, user_operation_status};
+pub use supervisor::{Supervisor, TaskHealth};
+pub use telemetry::{
+    init_tracing, new_submission_id, set_submission_id, shutdown_tracing, submission_id, LogFormat,
+    TelemetryOpts,
+};
+pub use tls::{grpc_channel, ClientTlsConfig, GrpcClientTlsOpts, TlsConfig, ALPN_H2, ALPN_HTTP1};
+pub use uopool::{
+    parse_entry_point_overrides, resolve_entry_points, uopool_service_run, EntryPointOverrides,
+    UoPoolServiceHandle, UoPoolServiceOpts,
+};
@@ -0,0 +1,156 @@
+use std::{convert::Infallible, net::SocketAddr, sync::Arc, time::Duration};
+
+use aa_bundler_primitives::ReputationStatus;
+use aa_bundler_uopool::{MempoolId, UoPool as UserOperationPool};
+use clap::Parser;
+use dashmap::DashMap;
+use ethers::{providers::Middleware, types::Address};
+use hyper::{
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+use tracing::{error, info};
+
+use crate::user_operation_error;
+
+/// Buckets of the latency histograms (the metrics with the `_seconds` suffix)
+const LATENCY_BUCKETS: [f64; 12] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0,
+];
+const METRICS_PATH: &str = "/metrics";
+/// Interval of the updates of the mempool and reputation gauges
+const UOPOOL_METRICS_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Prometheus metrics options
+#[derive(Clone, Debug, Parser, PartialEq, Eq)]
+pub struct MetricsOpts {
+    /// Address of the HTTP listener that serves the metrics in the Prometheus format at `/metrics`, the metrics are
+    /// not exported if not set
+    #[clap(long)]
+    pub metrics_listen_address: Option<SocketAddr>,
+}
+
+/// Installs the Prometheus recorder (for the metrics of the whole process) and serves the metrics at `/metrics`. All
+/// the metrics are labeled with the chain id, the metrics specific to an entry point with the `entry_point` as well.
+pub fn start_metrics_server(listen_address: SocketAddr, chain_id: u64) -> anyhow::Result<()> {
+    let handle = PrometheusBuilder::new()
+        .add_global_label("chain_id", chain_id.to_string())
+        .set_buckets_for_metric(Matcher::Suffix("_seconds".to_string()), &LATENCY_BUCKETS)?
+        .install_recorder()?;
+
+    let make_service = make_service_fn(move |_| {
+        let handle = handle.clone();
+        async move { Ok::<_, Infallible>(service_fn(move |req| serve(handle.clone(), req))) }
+    });
+    let server = Server::try_bind(&listen_address)?.serve(make_service);
+    info!("Metrics server listening on http://{listen_address}{METRICS_PATH}");
+    tokio::spawn(async move {
+        if let Err(err) = server.await {
+            error!("Metrics server failed: {err:?}");
+        }
+    });
+    Ok(())
+}
+
+async fn serve(handle: PrometheusHandle, req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    Ok(match (req.method(), req.uri().path()) {
+        (&Method::GET, METRICS_PATH) => Response::new(Body::from(handle.render())),
+        _ => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty())
+            .unwrap_or_default(),
+    })
+}
+
+/// Updates the gauges of the mempools periodically:
+/// - `uopool_mempool_size` (`entry_point`): number of user operations in the mempool
+/// - `uopool_reputation_entities` (`entry_point`, `status`): number of entities by the reputation status
+pub fn start_uopool_metrics<M: Middleware + 'static>(
+    mempools: Arc<DashMap<MempoolId, UserOperationPool<M>>>,
+) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(UOPOOL_METRICS_INTERVAL);
+        loop {
+            interval.tick().await;
+            for uopool in mempools.iter() {
+                let entry_point = format!("{:?}", uopool.entry_point.address());
+                metrics::gauge!("uopool_mempool_size", uopool.mempool.get_all().len() as f64, "entry_point" => entry_point.clone());
+
+                let mut statuses = [0; 3];
+                for entry in uopool.reputation.get_all() {
+                    statuses[uopool.reputation.get_status(&entry.address) as usize] += 1;
+                }
+                for (status, count) in [
+                    ReputationStatus::OK,
+                    ReputationStatus::THROTTLED,
+                    ReputationStatus::BANNED,
+                ]
+                .iter()
+                .zip(statuses)
+                {
+                    metrics::gauge!("uopool_reputation_entities", count as f64, "entry_point" => entry_point.clone(), "status" => reputation_status_label(status));
+                }
+            }
+        }
+    });
+}
+
+fn reputation_status_label(status: &ReputationStatus) -> &'static str {
+    match status {
+        ReputationStatus::OK => "ok",
+        ReputationStatus::THROTTLED => "throttled",
+        ReputationStatus::BANNED => "banned",
+    }
+}
+
+/// Records the result of adding the user operation to the mempool of the entry point:
+/// - `uopool_user_operations_accepted` (`entry_point`): number of accepted user operations
+/// - `uopool_user_operations_rejected` (`entry_point`, `reason`): number of rejected user operations by the JSON-RPC
+///   error code (or the gRPC code of the other errors)
+pub fn record_user_operation_result<T>(entry_point: &Address, result: &Result<T, tonic::Status>) {
+    let entry_point = format!("{entry_point:?}");
+    match result {
+        Ok(_) => {
+            metrics::counter!("uopool_user_operations_accepted", 1, "entry_point" => entry_point)
+        }
+        Err(status) => {
+            metrics::counter!("uopool_user_operations_rejected", 1, "entry_point" => entry_point, "reason" => rejection_code(status));
+        }
+    }
+}
+
+/// JSON-RPC error code of the rejected user operation (or the gRPC code of the other errors)
+pub fn rejection_code(status: &tonic::Status) -> String {
+    user_operation_error(status).map_or_else(
+        || format!("{:?}", status.code()),
+        |error| error.code().to_string(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn metrics_path() {
+        let handle = PrometheusBuilder::new().build_recorder().handle();
+        let request = |method: Method, path: &str| {
+            Request::builder()
+                .method(method)
+                .uri(path)
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let response = serve(handle.clone(), request(Method::GET, METRICS_PATH))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        for (method, path) in [(Method::GET, "/"), (Method::POST, METRICS_PATH)] {
+            let response = serve(handle.clone(), request(method, path)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        }
+    }
+}
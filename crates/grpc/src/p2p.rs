@@ -0,0 +1,409 @@
+use std::sync::Arc;
+
+use aa_bundler_contracts::EntryPointErr;
+use aa_bundler_p2p::{
+    MessageAcceptance, Network, NetworkEvent, NetworkHandle, P2POpts, PeerAction, PeerId,
+    PooledUserOpHashes, PooledUserOpHashesRequest, PooledUserOpsByHash, PooledUserOpsByHashRequest,
+    UserOperationsWithEntryPoint, MAX_OPS_PER_REQUEST,
+};
+use aa_bundler_primitives::{get_addr, ReputationStatus, UserOperation, UserOperationHash};
+use aa_bundler_uopool::mempool_id;
+use ethers::{
+    providers::Middleware,
+    types::{Address, BlockNumber, H256},
+};
+use tokio::{
+    sync::{mpsc, Semaphore},
+    task::JoinHandle,
+};
+use tracing::{debug, info, trace};
+
+use crate::{uopool::UoPoolService, ShutdownSignal, Supervisor};
+
+/// Maximum number of the gossiped messages (or the user operations received from the peers) that are verified
+/// concurrently
+const MAX_CONCURRENT_MESSAGE_VALIDATIONS: usize = 8;
+
+/// Joins the shared mempool (if the P2P listener is configured): the user operations gossiped by the peers are
+/// verified and added to the mempools, and the messages are forwarded to the other peers only if they are valid. The
+/// mempools are synced with the newly connected peers (the missing user operations are requested from them). The
+/// network tasks can't be restarted, their failure is reported by the supervisor.
+pub fn start_p2p<M>(
+    opts: &P2POpts,
+    uopool_service: UoPoolService<M>,
+    shutdown: ShutdownSignal,
+    supervisor: &Supervisor,
+) -> anyhow::Result<Option<(NetworkHandle, JoinHandle<()>)>>
+where
+    M: Middleware + 'static,
+    EntryPointErr: From<<M as Middleware>::Error>,
+{
+    if !opts.enabled() {
+        return Ok(None);
+    }
+
+    let mempools = uopool_service
+        .entry_points
+        .iter()
+        .map(|entry_point| {
+            (
+                mempool_id(entry_point, &uopool_service.chain_id),
+                *entry_point,
+            )
+        })
+        .collect();
+    let (network, handle, events) = Network::new(opts, uopool_service.chain_id, mempools)?;
+    info!("Joining the shared mempool as {}", network.local_peer_id());
+
+    supervisor.watch(
+        "p2p_events",
+        handle_network_events(uopool_service, handle.clone(), events),
+    );
+    Ok(Some((
+        handle,
+        supervisor.watch("p2p_network", network.run(shutdown.wait())),
+    )))
+}
+
+async fn handle_network_events<M>(
+    uopool_service: UoPoolService<M>,
+    handle: NetworkHandle,
+    mut events: mpsc::Receiver<NetworkEvent>,
+) where
+    M: Middleware + 'static,
+    EntryPointErr: From<<M as Middleware>::Error>,
+{
+    let semaphore = Arc::new(Semaphore::new(MAX_CONCURRENT_MESSAGE_VALIDATIONS));
+    while let Some(event) = events.recv().await {
+        match event {
+            NetworkEvent::UserOperations {
+                message_id,
+                peer,
+                message,
+                ..
+            } => {
+                let permit = match semaphore.clone().acquire_owned().await {
+                    Ok(permit) => permit,
+                    Err(_) => break,
+                };
+                let uopool_service = uopool_service.clone();
+                let handle = handle.clone();
+                tokio::spawn(async move {
+                    let acceptance =
+                        validate_message(&uopool_service, &handle, peer, message).await;
+                    handle.report_validation(message_id, peer, acceptance);
+                    drop(permit);
+                });
+            }
+            NetworkEvent::PooledUserOpHashesRequest {
+                request_id,
+                request,
+                ..
+            } => handle.respond_pooled_user_op_hashes(
+                request_id,
+                pooled_user_op_hashes(&uopool_service, request),
+            ),
+            NetworkEvent::PooledUserOpsByHashRequest {
+                request_id,
+                request,
+                ..
+            } => handle.respond_pooled_user_ops_by_hash(
+                request_id,
+                pooled_user_ops_by_hash(&uopool_service, request),
+            ),
+            NetworkEvent::PooledUserOpHashes {
+                peer,
+                mempool_id,
+                hashes,
+            } => {
+                let missing = missing_user_operations(&uopool_service, mempool_id, hashes);
+                if !missing.is_empty() {
+                    debug!(
+                        "Requesting {} missing user operations from the peer {peer}",
+                        missing.len()
+                    );
+                    handle.request_pooled_user_ops(peer, mempool_id, missing);
+                }
+            }
+            NetworkEvent::PooledUserOps {
+                peer,
+                entry_point,
+                user_operations,
+                ..
+            } => {
+                let permit = match semaphore.clone().acquire_owned().await {
+                    Ok(permit) => permit,
+                    Err(_) => break,
+                };
+                let uopool_service = uopool_service.clone();
+                let handle = handle.clone();
+                tokio::spawn(async move {
+                    for action in
+                        add_pooled_user_operations(&uopool_service, entry_point, user_operations)
+                            .await
+                    {
+                        handle.report_peer(peer, action);
+                    }
+                    drop(permit);
+                });
+            }
+        }
+    }
+}
+
+/// Gossips the user operation (verified and added to the mempool) to the topic of its mempool. The user operations
+/// that are only queued (nonce gap), private or whose entities are throttled (or banned) are not gossiped.
+pub async fn gossip_user_operation<M>(
+    uopool_service: &UoPoolService<M>,
+    network: &NetworkHandle,
+    entry_point: Address,
+    user_operation_hash: &UserOperationHash,
+) where
+    M: Middleware + 'static,
+{
+    let mempool_id = mempool_id(&entry_point, &uopool_service.chain_id);
+    let user_operation = {
+        let uopool = match uopool_service.mempools.get(&mempool_id) {
+            Some(uopool) => uopool,
+            None => return,
+        };
+        let user_operation = match uopool.mempool.get(user_operation_hash) {
+            Ok(Some(user_operation)) if !uopool.is_private(user_operation_hash) => user_operation,
+            _ => return,
+        };
+        let throttled = [
+            Some(user_operation.sender),
+            get_addr(&user_operation.init_code),
+            get_addr(&user_operation.paymaster_and_data),
+        ]
+        .iter()
+        .flatten()
+        .any(|entity| uopool.reputation.get_status(entity) != ReputationStatus::OK);
+        if throttled {
+            debug!("User operation {user_operation_hash:?} of a throttled entity is not gossiped");
+            return;
+        }
+        user_operation
+    };
+
+    let verified_at_block_hash = match uopool_service
+        .eth_provider
+        .get_block(BlockNumber::Latest)
+        .await
+    {
+        Ok(Some(block)) => block.hash.unwrap_or_default(),
+        Ok(None) => return,
+        Err(err) => {
+            debug!("Getting the latest block failed, the user operation is not gossiped: {err:?}");
+            return;
+        }
+    };
+    network.publish_user_operations(
+        mempool_id,
+        UserOperationsWithEntryPoint {
+            entry_point,
+            verified_at_block_hash,
+            chain_id: uopool_service.chain_id,
+            user_operations: vec![user_operation],
+        },
+    );
+}
+
+/// Page of the hashes of the user operations in the mempool (sorted, so that the pages are stable), without the
+/// private ones
+fn pooled_user_op_hashes<M>(
+    uopool_service: &UoPoolService<M>,
+    request: PooledUserOpHashesRequest,
+) -> PooledUserOpHashes
+where
+    M: Middleware + 'static,
+{
+    let mut hashes: Vec<H256> = match uopool_service.mempools.get(&request.mempool) {
+        Some(uopool) => {
+            let entry_point = uopool.entry_point.address();
+            uopool
+                .mempool
+                .get_all()
+                .iter()
+                .map(|uo| uo.hash(&entry_point, &uopool_service.chain_id))
+                .filter(|hash| !uopool.is_private(hash))
+                .map(Into::into)
+                .collect()
+        }
+        None => vec![],
+    };
+    hashes.sort();
+
+    let offset = usize::try_from(request.offset).unwrap_or(usize::MAX);
+    let page: Vec<H256> = hashes
+        .iter()
+        .skip(offset)
+        .take(MAX_OPS_PER_REQUEST)
+        .copied()
+        .collect();
+    PooledUserOpHashes {
+        more_flag: (offset.saturating_add(page.len()) < hashes.len()) as u64,
+        hashes: page,
+    }
+}
+
+/// User operations (of any of the mempools) with the requested hashes, the private ones are not shared
+fn pooled_user_ops_by_hash<M>(
+    uopool_service: &UoPoolService<M>,
+    request: PooledUserOpsByHashRequest,
+) -> PooledUserOpsByHash
+where
+    M: Middleware + 'static,
+{
+    PooledUserOpsByHash {
+        user_operations: request
+            .hashes
+            .into_iter()
+            .take(MAX_OPS_PER_REQUEST)
+            .filter_map(|hash| {
+                uopool_service
+                    .mempools
+                    .iter()
+                    .filter(|uopool| !uopool.is_private(&hash.into()))
+                    .find_map(|uopool| uopool.mempool.get(&hash.into()).ok().flatten())
+            })
+            .collect(),
+    }
+}
+
+/// Hashes of the user operations of the peer's mempool that are not in the local one
+fn missing_user_operations<M>(
+    uopool_service: &UoPoolService<M>,
+    mempool_id: H256,
+    hashes: Vec<H256>,
+) -> Vec<H256>
+where
+    M: Middleware + 'static,
+{
+    match uopool_service.mempools.get(&mempool_id) {
+        Some(uopool) => hashes
+            .into_iter()
+            .filter(|hash| uopool.get_pending_user_operation(&(*hash).into()).is_none())
+            .collect(),
+        None => vec![],
+    }
+}
+
+/// Verifies the user operations received from the peer and adds the valid ones to the mempool, returns the
+/// misbehavior of the peer (the invalid user operations)
+async fn add_pooled_user_operations<M>(
+    uopool_service: &UoPoolService<M>,
+    entry_point: Address,
+    user_operations: Vec<UserOperation>,
+) -> Vec<PeerAction>
+where
+    M: Middleware + 'static,
+    EntryPointErr: From<<M as Middleware>::Error>,
+{
+    let mut actions = vec![];
+    for user_operation in user_operations {
+        match uopool_service
+            .insert_user_operation(user_operation, entry_point, None, false)
+            .await
+        {
+            Ok(user_operation_hash) => {
+                debug!("Pooled user operation {user_operation_hash:?} of the peer added to the mempool")
+            }
+            Err(status) => {
+                debug!(
+                    "Pooled user operation of the peer is not added: {}",
+                    status.message()
+                );
+                if let MessageAcceptance::Reject = message_acceptance(&status) {
+                    actions.push(PeerAction::InvalidUserOperation);
+                }
+            }
+        }
+    }
+    actions
+}
+
+/// Acceptance of the gossiped message with the user operation that was not added to the mempool: the message is
+/// ignored if the user operation is already known or if it is not added for a local reason (the uopool is not
+/// accepting or is full, the entities are throttled or banned by the local reputation, the execution client failed),
+/// otherwise the user operation is invalid and the message is rejected
+fn message_acceptance(status: &tonic::Status) -> MessageAcceptance {
+    match status.code() {
+        tonic::Code::AlreadyExists
+        | tonic::Code::Unavailable
+        | tonic::Code::ResourceExhausted
+        | tonic::Code::PermissionDenied
+        | tonic::Code::Internal => MessageAcceptance::Ignore,
+        _ => MessageAcceptance::Reject,
+    }
+}
+
+/// Verifies (sanity check and simulation) the user operations of the message and adds them to the mempool of the
+/// topic's entry point, the message is rejected if any of them is invalid. The peer is penalized for the user
+/// operations that are already in the mempool.
+async fn validate_message<M>(
+    uopool_service: &UoPoolService<M>,
+    handle: &NetworkHandle,
+    peer: PeerId,
+    message: UserOperationsWithEntryPoint,
+) -> MessageAcceptance
+where
+    M: Middleware + 'static,
+    EntryPointErr: From<<M as Middleware>::Error>,
+{
+    let mut acceptance = MessageAcceptance::Accept;
+    for user_operation in message.user_operations {
+        match uopool_service
+            .insert_user_operation(user_operation, message.entry_point, None, false)
+            .await
+        {
+            Ok(user_operation_hash) => {
+                debug!("Gossiped user operation {user_operation_hash:?} added to the mempool")
+            }
+            Err(status) => match message_acceptance(&status) {
+                MessageAcceptance::Reject => {
+                    debug!("Gossiped user operation is not valid: {}", status.message());
+                    return MessageAcceptance::Reject;
+                }
+                ignore => {
+                    trace!("Gossiped user operation is not added: {}", status.message());
+                    if status.code() == tonic::Code::AlreadyExists {
+                        handle.report_peer(peer, PeerAction::DuplicateUserOperation);
+                    }
+                    acceptance = ignore;
+                }
+            },
+        }
+    }
+    acceptance
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gossiped_user_operation_acceptance() {
+        for code in [
+            tonic::Code::AlreadyExists,
+            tonic::Code::Unavailable,
+            tonic::Code::ResourceExhausted,
+            tonic::Code::PermissionDenied,
+            tonic::Code::Internal,
+        ] {
+            assert!(matches!(
+                message_acceptance(&tonic::Status::new(code, "")),
+                MessageAcceptance::Ignore
+            ));
+        }
+        for code in [
+            tonic::Code::InvalidArgument,
+            tonic::Code::FailedPrecondition,
+        ] {
+            assert!(matches!(
+                message_acceptance(&tonic::Status::new(code, "")),
+                MessageAcceptance::Reject
+            ));
+        }
+    }
+}
@@ -146,6 +146,32 @@ pub mod types {
         }
     }
 
+    impl From<aa_bundler_primitives::Authorization> for Authorization {
+        fn from(authorization: aa_bundler_primitives::Authorization) -> Self {
+            Self {
+                chain_id: Some(authorization.chain_id.into()),
+                address: Some(authorization.address.into()),
+                nonce: Some(authorization.nonce.into()),
+                y_parity: authorization.y_parity.as_u64(),
+                r: Some(authorization.r.into()),
+                s: Some(authorization.s.into()),
+            }
+        }
+    }
+
+    impl From<Authorization> for aa_bundler_primitives::Authorization {
+        fn from(authorization: Authorization) -> Self {
+            Self {
+                chain_id: authorization.chain_id.map(Into::into).unwrap_or_default(),
+                address: authorization.address.map(Into::into).unwrap_or_default(),
+                nonce: authorization.nonce.map(Into::into).unwrap_or_default(),
+                y_parity: authorization.y_parity.into(),
+                r: authorization.r.map(Into::into).unwrap_or_default(),
+                s: authorization.s.map(Into::into).unwrap_or_default(),
+            }
+        }
+    }
+
     impl From<aa_bundler_primitives::ReputationEntry> for ReputationEntry {
         fn from(reputation_entry: aa_bundler_primitives::ReputationEntry) -> Self {
             Self {
@@ -291,13 +317,246 @@ pub mod types {
 
 pub mod uopool {
     tonic::include_proto!("uopool");
+
+    impl From<aa_bundler_primitives::UserOperationGasEstimation> for UserOperationGasEstimation {
+        fn from(estimation: aa_bundler_primitives::UserOperationGasEstimation) -> Self {
+            Self {
+                pre_verification_gas: Some(estimation.pre_verification_gas.into()),
+                verification_gas_limit: Some(estimation.verification_gas_limit.into()),
+                call_gas_limit: Some(estimation.call_gas_limit.into()),
+            }
+        }
+    }
+
+    impl From<UserOperationGasEstimation> for aa_bundler_primitives::UserOperationGasEstimation {
+        fn from(estimation: UserOperationGasEstimation) -> Self {
+            Self {
+                pre_verification_gas: estimation.pre_verification_gas.unwrap_or_default().into(),
+                verification_gas_limit: estimation
+                    .verification_gas_limit
+                    .unwrap_or_default()
+                    .into(),
+                call_gas_limit: estimation.call_gas_limit.unwrap_or_default().into(),
+            }
+        }
+    }
+
+    impl From<aa_bundler_primitives::PeerInfo> for PeerInfo {
+        fn from(peer: aa_bundler_primitives::PeerInfo) -> Self {
+            Self {
+                peer_id: peer.peer_id,
+                score: peer.score,
+                throttled: peer.throttled,
+                banned: peer.banned,
+                connected: peer.connected,
+                enr: peer.enr.unwrap_or_default(),
+                mempools: peer.mempools.into_iter().map(Into::into).collect(),
+            }
+        }
+    }
+
+    impl From<aa_bundler_primitives::RejectedUserOperation> for RejectedUserOperation {
+        fn from(rejected: aa_bundler_primitives::RejectedUserOperation) -> Self {
+            Self {
+                uo_hash: Some(rejected.user_operation_hash.into()),
+                ep: Some(rejected.entry_point.into()),
+                sender: Some(rejected.sender.into()),
+                factory: rejected.factory.map(Into::into),
+                paymaster: rejected.paymaster.map(Into::into),
+                reason: rejected.reason,
+                code: rejected.code,
+                message: rejected.message,
+                rejected_at: rejected.rejected_at,
+            }
+        }
+    }
+
+    impl From<RejectedUserOperation> for aa_bundler_primitives::RejectedUserOperation {
+        fn from(rejected: RejectedUserOperation) -> Self {
+            Self {
+                user_operation_hash: rejected.uo_hash.unwrap_or_default().into(),
+                entry_point: rejected.ep.unwrap_or_default().into(),
+                sender: rejected.sender.unwrap_or_default().into(),
+                factory: rejected.factory.map(Into::into),
+                paymaster: rejected.paymaster.map(Into::into),
+                reason: rejected.reason,
+                code: rejected.code,
+                message: rejected.message,
+                rejected_at: rejected.rejected_at,
+            }
+        }
+    }
+
+    impl From<aa_bundler_primitives::EntryPointHealth> for EntryPointHealth {
+        fn from(health: aa_bundler_primitives::EntryPointHealth) -> Self {
+            Self {
+                ep: Some(health.entry_point.into()),
+                deployed: health.deployed,
+                mempool_size: health.mempool_size,
+                throttled_entities: health.throttled_entities,
+                banned_entities: health.banned_entities,
+                rejected_user_operations: health.rejected_user_operations,
+            }
+        }
+    }
+
+    /// The last bundle isn't known to the uopool, it's filled in from the bundler
+    impl From<EntryPointHealth> for aa_bundler_primitives::EntryPointHealth {
+        fn from(health: EntryPointHealth) -> Self {
+            Self {
+                entry_point: health.ep.unwrap_or_default().into(),
+                deployed: health.deployed,
+                mempool_size: health.mempool_size,
+                throttled_entities: health.throttled_entities,
+                banned_entities: health.banned_entities,
+                rejected_user_operations: health.rejected_user_operations,
+                last_bundle_status: None,
+                last_bundle_at: None,
+            }
+        }
+    }
+
+    impl From<PeerInfo> for aa_bundler_primitives::PeerInfo {
+        fn from(peer: PeerInfo) -> Self {
+            Self {
+                peer_id: peer.peer_id,
+                connected: peer.connected,
+                enr: Some(peer.enr).filter(|enr| !enr.is_empty()),
+                mempools: peer.mempools.into_iter().map(Into::into).collect(),
+                score: peer.score,
+                throttled: peer.throttled,
+                banned: peer.banned,
+            }
+        }
+    }
 }
 
+/// Descriptors of all the compiled proto files (and their imports), served by the reflection service
+pub const FILE_DESCRIPTOR_SET: &[u8] = tonic::include_file_descriptor_set!("descriptor");
+
 pub mod bundler {
     use aa_bundler_primitives::Mode as GrpcMode;
 
     tonic::include_proto!("bundler");
 
+    impl From<aa_bundler_bundler::AccountingEntry> for AccountingEntry {
+        fn from(value: aa_bundler_bundler::AccountingEntry) -> Self {
+            Self {
+                bundles: value.bundles,
+                user_operations: value.user_operations,
+                gas_used: Some(value.gas_used.into()),
+                cost: Some(value.cost.into()),
+                revenue: Some(value.revenue.into()),
+                reverted: value.reverted,
+                gas_estimated: Some(value.gas_estimated.into()),
+                inclusion_blocks: value.inclusion_blocks,
+                cancelled: value.cancelled,
+            }
+        }
+    }
+
+    impl From<aa_bundler_primitives::BundleStatus> for BundleStatus {
+        fn from(value: aa_bundler_primitives::BundleStatus) -> Self {
+            match value {
+                aa_bundler_primitives::BundleStatus::Pending => Self::Pending,
+                aa_bundler_primitives::BundleStatus::Included => Self::Included,
+                aa_bundler_primitives::BundleStatus::Failed => Self::Failed,
+            }
+        }
+    }
+
+    impl From<BundleStatus> for aa_bundler_primitives::BundleStatus {
+        fn from(value: BundleStatus) -> Self {
+            match value {
+                BundleStatus::Pending => Self::Pending,
+                BundleStatus::Included => Self::Included,
+                BundleStatus::Failed => Self::Failed,
+            }
+        }
+    }
+
+    impl From<aa_bundler_primitives::BundleRecord> for BundleRecord {
+        fn from(value: aa_bundler_primitives::BundleRecord) -> Self {
+            Self {
+                id: value.id,
+                ep: Some(value.entry_point.into()),
+                uo_hashes: value
+                    .user_operations
+                    .into_iter()
+                    .map(|hash| hash.into())
+                    .collect(),
+                dropped: value
+                    .dropped_user_operations
+                    .into_iter()
+                    .map(|dropped| DroppedUserOperation {
+                        uo_hash: Some(dropped.user_operation_hash.into()),
+                        reason: dropped.reason,
+                    })
+                    .collect(),
+                tx_hash: value.transaction_hash.map(|hash| hash.into()),
+                gas_used: value.gas_used.map(|gas| gas.into()),
+                status: BundleStatus::from(value.status).into(),
+                revert_reason: value.revert_reason.unwrap_or_default(),
+                created_at: value.created_at,
+                attempts: value
+                    .attempts
+                    .into_iter()
+                    .map(|attempt| BundleAttempt {
+                        tx_hash: Some(attempt.tx_hash.into()),
+                        max_fee_per_gas: Some(attempt.max_fee_per_gas.into()),
+                        max_priority_fee_per_gas: Some(attempt.max_priority_fee_per_gas.into()),
+                        block_number: attempt.block_number.as_u64(),
+                    })
+                    .collect(),
+            }
+        }
+    }
+
+    impl From<BundleRecord> for aa_bundler_primitives::BundleRecord {
+        fn from(value: BundleRecord) -> Self {
+            let status = value.status().into();
+            Self {
+                id: value.id,
+                entry_point: value.ep.unwrap_or_default().into(),
+                user_operations: value
+                    .uo_hashes
+                    .into_iter()
+                    .map(|hash| hash.into())
+                    .collect(),
+                dropped_user_operations: value
+                    .dropped
+                    .into_iter()
+                    .map(|dropped| aa_bundler_primitives::DroppedUserOperation {
+                        user_operation_hash: dropped.uo_hash.unwrap_or_default().into(),
+                        reason: dropped.reason,
+                    })
+                    .collect(),
+                transaction_hash: value.tx_hash.map(|hash| hash.into()),
+                gas_used: value.gas_used.map(|gas| gas.into()),
+                status,
+                revert_reason: if value.revert_reason.is_empty() {
+                    None
+                } else {
+                    Some(value.revert_reason)
+                },
+                attempts: value
+                    .attempts
+                    .into_iter()
+                    .map(|attempt| aa_bundler_primitives::BundleAttempt {
+                        tx_hash: attempt.tx_hash.unwrap_or_default().into(),
+                        max_fee_per_gas: attempt.max_fee_per_gas.unwrap_or_default().into(),
+                        max_priority_fee_per_gas: attempt
+                            .max_priority_fee_per_gas
+                            .unwrap_or_default()
+                            .into(),
+                        block_number: attempt.block_number.into(),
+                    })
+                    .collect(),
+                created_at: value.created_at,
+            }
+        }
+    }
+
     impl From<Mode> for GrpcMode {
         fn from(value: Mode) -> Self {
             match value {
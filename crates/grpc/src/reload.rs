@@ -0,0 +1,139 @@
+use std::sync::Arc;
+
+use aa_bundler_primitives::ThrottlingParams;
+use anyhow::Result;
+use ethers::types::{Address, U256};
+use parking_lot::Mutex;
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    task::JoinHandle,
+};
+use tracing::{error, info};
+
+use crate::{
+    BundlerServiceOpts, SetMinPriorityFeeRequest, SetReputationListsRequest, SetThrottlingRequest,
+    ShutdownSignal, Supervisor, UoPoolGrpcClient, UoPoolServiceOpts,
+};
+
+/// Options that are reloaded without restarting (and losing the mempool)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReloadableOpts {
+    pub min_priority_fee_per_gas: U256,
+    pub whitelist: Vec<Address>,
+    pub blacklist: Vec<Address>,
+    pub throttling: ThrottlingParams,
+    /// Interval in seconds between the bundles, if the bundler runs in the process
+    pub bundle_interval: Option<u64>,
+}
+
+impl ReloadableOpts {
+    pub fn new(uopool_opts: &UoPoolServiceOpts, bundler_opts: Option<&BundlerServiceOpts>) -> Self {
+        Self {
+            min_priority_fee_per_gas: uopool_opts.min_priority_fee_per_gas,
+            whitelist: uopool_opts.whitelist.clone(),
+            blacklist: uopool_opts.blacklist.clone(),
+            throttling: ThrottlingParams {
+                min_inclusion_denominator: uopool_opts.min_inclusion_denominator,
+                throttling_slack: uopool_opts.throttling_slack,
+                ban_slack: uopool_opts.ban_slack,
+            },
+            bundle_interval: bundler_opts.map(|bundler_opts| bundler_opts.bundle_interval),
+        }
+    }
+}
+
+type LoadOpts = dyn Fn() -> Result<ReloadableOpts> + Send + Sync;
+
+/// Reloads the options (e.g. from the config file and the environment variables) on SIGHUP or with the
+/// `admin_reloadConfig` method, and applies them to the uopool (over gRPC) and to the bundler
+#[derive(Clone)]
+pub struct ConfigReloader {
+    load: Arc<LoadOpts>,
+    uopool_grpc_client: UoPoolGrpcClient,
+    bundle_interval: Option<Arc<Mutex<u64>>>,
+}
+
+impl ConfigReloader {
+    pub fn new<F>(load: F, uopool_grpc_client: UoPoolGrpcClient) -> Self
+    where
+        F: Fn() -> Result<ReloadableOpts> + Send + Sync + 'static,
+    {
+        Self {
+            load: Arc::new(load),
+            uopool_grpc_client,
+            bundle_interval: None,
+        }
+    }
+
+    /// Applies the reloaded bundle interval to the bundler (see `BundlerService::interval`)
+    pub fn with_bundle_interval(mut self, bundle_interval: Arc<Mutex<u64>>) -> Self {
+        self.bundle_interval = Some(bundle_interval);
+        self
+    }
+
+    pub async fn reload(&self) -> Result<ReloadableOpts> {
+        let opts = (self.load)()?;
+        info!("Reloading the configuration: {opts:?}");
+
+        let mut uopool_grpc_client = self.uopool_grpc_client.clone();
+        uopool_grpc_client
+            .set_min_priority_fee(tonic::Request::new(SetMinPriorityFeeRequest {
+                min_priority_fee_per_gas: Some(opts.min_priority_fee_per_gas.into()),
+            }))
+            .await?;
+        uopool_grpc_client
+            .set_throttling(tonic::Request::new(SetThrottlingRequest {
+                min_inclusion_denominator: opts.throttling.min_inclusion_denominator,
+                throttling_slack: opts.throttling.throttling_slack,
+                ban_slack: opts.throttling.ban_slack,
+            }))
+            .await?;
+        uopool_grpc_client
+            .set_reputation_lists(tonic::Request::new(SetReputationListsRequest {
+                whitelist: opts
+                    .whitelist
+                    .iter()
+                    .map(|address| (*address).into())
+                    .collect(),
+                blacklist: opts
+                    .blacklist
+                    .iter()
+                    .map(|address| (*address).into())
+                    .collect(),
+            }))
+            .await?;
+
+        if let (Some(bundle_interval), Some(interval)) =
+            (&self.bundle_interval, opts.bundle_interval)
+        {
+            *bundle_interval.lock() = interval;
+        }
+
+        Ok(opts)
+    }
+
+    /// Reloads the configuration whenever the process receives SIGHUP, until the shutdown
+    pub fn reload_on_sighup(
+        self,
+        supervisor: &Supervisor,
+        shutdown: ShutdownSignal,
+    ) -> JoinHandle<()> {
+        supervisor.spawn("config_reload", move || {
+            let reloader = self.clone();
+            let shutdown = shutdown.clone();
+            async move {
+                let mut hangup = signal(SignalKind::hangup())?;
+                loop {
+                    tokio::select! {
+                        _ = hangup.recv() => {},
+                        _ = shutdown.clone().wait() => return Ok(()),
+                    }
+                    info!("Received SIGHUP");
+                    if let Err(err) = reloader.reload().await {
+                        error!("Reloading the configuration failed: {err:?}");
+                    }
+                }
+            }
+        })
+    }
+}
@@ -0,0 +1,61 @@
+use clap::Parser;
+use tokio::runtime::Builder;
+
+/// Sizing of the async runtime, e.g. a few threads on a small VPS or a thread per core on a dedicated host. The memory
+/// isn't capped here, it's bounded by the JSON-RPC size limits and the concurrent simulations.
+#[derive(Clone, Debug, Parser, PartialEq, Eq)]
+pub struct RuntimeOpts {
+    /// Number of the worker threads of the async runtime (the number of CPU cores if not set)
+    #[clap(long, value_parser = parse_thread_count)]
+    pub worker_threads: Option<usize>,
+
+    /// Maximum number of the threads of the blocking pool (file I/O, the blocking tasks)
+    #[clap(long, default_value = "512", value_parser = parse_thread_count)]
+    pub max_blocking_threads: usize,
+}
+
+impl Default for RuntimeOpts {
+    fn default() -> Self {
+        Self {
+            worker_threads: None,
+            max_blocking_threads: 512,
+        }
+    }
+}
+
+fn parse_thread_count(s: &str) -> Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(count) if count > 0 => Ok(count),
+        _ => Err(format!("{s} is not a positive number of threads")),
+    }
+}
+
+impl RuntimeOpts {
+    /// Builder of the multi-threaded runtime with the I/O and the time drivers enabled
+    pub fn runtime_builder(&self) -> Builder {
+        let mut builder = Builder::new_multi_thread();
+        builder
+            .enable_all()
+            .max_blocking_threads(self.max_blocking_threads);
+        if let Some(worker_threads) = self.worker_threads {
+            builder.worker_threads(worker_threads);
+        }
+        builder
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runtime_opts() {
+        let opts = RuntimeOpts::try_parse_from(["test", "--worker-threads", "2"]).unwrap();
+        assert_eq!(opts.worker_threads, Some(2));
+        assert_eq!(opts.max_blocking_threads, 512);
+        assert!(RuntimeOpts::try_parse_from(["test", "--max-blocking-threads", "0"]).is_err());
+
+        let runtime = opts.runtime_builder().build().unwrap();
+        assert_eq!(runtime.block_on(async { 1 + 1 }), 2);
+    }
+}
@@ -0,0 +1,88 @@
+use std::time::Duration;
+
+use clap::Parser;
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    sync::watch,
+};
+use tracing::error;
+
+/// Options of the graceful shutdown (on ctrl-c or SIGTERM)
+#[derive(Clone, Debug, Parser, PartialEq, Eq)]
+pub struct ShutdownOpts {
+    /// Time in seconds given on shutdown to the in-flight requests (e.g. the verifications of the user operations)
+    /// and to the final bundle, the pending requests are dropped after it
+    #[clap(long, default_value = "10")]
+    pub shutdown_drain_timeout: u64,
+}
+
+impl ShutdownOpts {
+    pub fn drain_timeout(&self) -> Duration {
+        Duration::from_secs(self.shutdown_drain_timeout)
+    }
+}
+
+/// Signal to stop the servers and the background tasks, a clone is passed to each of them
+#[derive(Clone, Debug)]
+pub struct ShutdownSignal {
+    receiver: watch::Receiver<bool>,
+}
+
+impl ShutdownSignal {
+    /// The signal is triggered by sending `true` (or by dropping the sender)
+    pub fn new() -> (watch::Sender<bool>, Self) {
+        let (sender, receiver) = watch::channel(false);
+        (sender, Self { receiver })
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        *self.receiver.borrow()
+    }
+
+    /// Resolves once the signal is triggered
+    pub async fn wait(mut self) {
+        while !*self.receiver.borrow_and_update() {
+            if self.receiver.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// Resolves when the process receives ctrl-c (SIGINT) or SIGTERM
+pub async fn wait_for_termination() {
+    let mut terminate = match signal(SignalKind::terminate()) {
+        Ok(terminate) => terminate,
+        Err(err) => {
+            error!("Listening for SIGTERM failed: {err:?}");
+            let _ = tokio::signal::ctrl_c().await;
+            return;
+        }
+    };
+
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {},
+        _ = terminate.recv() => {},
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn shutdown_signal() {
+        let (sender, shutdown) = ShutdownSignal::new();
+        assert!(!shutdown.is_triggered());
+
+        let waiting = tokio::spawn(shutdown.clone().wait());
+        sender.send(true).unwrap();
+        waiting.await.unwrap();
+        assert!(shutdown.is_triggered());
+
+        // also triggered once the sender is dropped
+        let (sender, shutdown) = ShutdownSignal::new();
+        drop(sender);
+        shutdown.wait().await;
+    }
+}
@@ -0,0 +1,175 @@
+use std::{fs, path::Path};
+
+use aa_bundler_primitives::{Authorization, EthProvider, ReputationEntry, UserOperation};
+use aa_bundler_uopool::{mempool_id, MempoolId, UoPool as UserOperationPool};
+use anyhow::Result;
+use dashmap::DashMap;
+use ethers::{
+    providers::Middleware,
+    types::{Address, U256},
+};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::uopool::UoPoolService;
+
+/// User operation of the snapshot, with what's needed to insert it into the mempool again
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SnapshotUserOperation {
+    pub user_operation: UserOperation,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub authorization: Option<Authorization>,
+    #[serde(default)]
+    pub private: bool,
+}
+
+/// User operations (also the queued ones) and reputation of the mempool of an entry point
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MempoolSnapshot {
+    pub entry_point: Address,
+    pub user_operations: Vec<SnapshotUserOperation>,
+    pub reputation: Vec<ReputationEntry>,
+}
+
+/// State of the uopool that is saved on shutdown and restored at the next start. The restored user operations are
+/// verified again, since the chain moved on meanwhile.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UoPoolSnapshot {
+    pub chain_id: U256,
+    pub mempools: Vec<MempoolSnapshot>,
+}
+
+impl UoPoolSnapshot {
+    pub fn take<M: Middleware>(mempools: &DashMap<MempoolId, UserOperationPool<M>>) -> Self {
+        let mut snapshot = Self::default();
+        for uopool in mempools.iter() {
+            let entry_point = uopool.entry_point.address();
+            snapshot.chain_id = uopool.chain_id;
+            let mut user_operations = uopool.mempool.get_all();
+            user_operations.extend(uopool.get_queued_user_operations());
+            user_operations
+                .sort_by_key(|user_operation| (user_operation.sender, user_operation.nonce));
+            snapshot.mempools.push(MempoolSnapshot {
+                entry_point,
+                user_operations: user_operations
+                    .into_iter()
+                    .map(|user_operation| SnapshotUserOperation {
+                        authorization: uopool.get_authorization(&user_operation.sender),
+                        private: uopool
+                            .is_private(&user_operation.hash(&entry_point, &uopool.chain_id)),
+                        user_operation,
+                    })
+                    .collect(),
+                reputation: uopool.reputation.get_all(),
+            });
+        }
+        snapshot.mempools.sort_by_key(|mempool| mempool.entry_point);
+        snapshot
+    }
+
+    /// Writes the snapshot to a temporary file first, so that a crash while saving doesn't leave a truncated one
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let temp_path = path.with_extension("tmp");
+        fs::write(&temp_path, serde_json::to_vec(self)?)?;
+        fs::rename(&temp_path, path)?;
+        Ok(())
+    }
+
+    /// Reads the snapshot, none if there's no snapshot yet
+    pub fn load(path: &Path) -> Result<Option<Self>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_slice(&fs::read(path)?)?))
+    }
+
+    /// Restores the reputation right away and re-inserts the user operations (in the background, as they're verified
+    /// first). The mempools of the entry points that are no longer supported are dropped.
+    pub fn restore(self, uopool_service: &UoPoolService<EthProvider>) {
+        if self.chain_id != uopool_service.chain_id {
+            warn!(
+                "UoPool snapshot is of chain {}, not restoring it on chain {}",
+                self.chain_id, uopool_service.chain_id
+            );
+            return;
+        }
+
+        let mut user_operations = vec![];
+        for mempool in self.mempools {
+            if !uopool_service.entry_points.contains(&mempool.entry_point) {
+                warn!(
+                    "Dropping the snapshot of the entry point {:?}, which is not supported",
+                    mempool.entry_point
+                );
+                continue;
+            }
+            let id = mempool_id(&mempool.entry_point, &self.chain_id);
+            if let Some(mut uopool) = uopool_service.mempools.get_mut(&id) {
+                uopool.reputation.set(mempool.reputation);
+            }
+            user_operations.extend(
+                mempool
+                    .user_operations
+                    .into_iter()
+                    .map(|user_operation| (mempool.entry_point, user_operation)),
+            );
+        }
+
+        let uopool_service = uopool_service.clone();
+        tokio::spawn(async move {
+            let total = user_operations.len();
+            let mut restored = 0;
+            for (entry_point, snapshot) in user_operations {
+                match uopool_service
+                    .insert_user_operation(
+                        snapshot.user_operation,
+                        entry_point,
+                        snapshot.authorization,
+                        snapshot.private,
+                    )
+                    .await
+                {
+                    Ok(_) => restored += 1,
+                    Err(status) => warn!(
+                        "Dropping user operation of the snapshot: {}",
+                        status.message()
+                    ),
+                }
+            }
+            info!("Restored {restored} of {total} user operations from the uopool snapshot");
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshot_roundtrip() {
+        let snapshot = UoPoolSnapshot {
+            chain_id: U256::from(1337),
+            mempools: vec![MempoolSnapshot {
+                entry_point: Address::random(),
+                user_operations: vec![SnapshotUserOperation {
+                    user_operation: UserOperation::random(),
+                    authorization: None,
+                    private: true,
+                }],
+                reputation: vec![],
+            }],
+        };
+
+        let dir = std::env::temp_dir().join(format!("uopool-snapshot-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("snapshot.json");
+        assert_eq!(UoPoolSnapshot::load(&path).unwrap(), None);
+
+        snapshot.save(&path).unwrap();
+        assert_eq!(UoPoolSnapshot::load(&path).unwrap(), Some(snapshot));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,71 @@
+use aa_bundler_primitives::{
+    SanityCheckError, ENTITY_BANNED_ERROR_CODE, EXECUTION_ERROR_CODE, EXPIRATION_ERROR_CODE,
+    LIMIT_EXCEEDED_ERROR_CODE, OPCODE_VALIDATION_ERROR_CODE, PAYMASTER_VALIDATION_ERROR_CODE,
+    RESOURCE_UNAVAILABLE_ERROR_CODE, SANITY_CHECK_ERROR_CODE, SIGNATURE_FAILED_ERROR_CODE,
+    SIMULATE_VALIDATION_ERROR_CODE, STAKE_TOO_LOW_ERROR_CODE, UNSUPPORTED_AGGREGATOR_ERROR_CODE,
+    USER_OPERATION_HASH_ERROR_CODE,
+};
+use tonic::{Code, Status};
+
+/// gRPC code of the JSON-RPC error of the user operation, so that the clients can branch on the kind of the error
+pub fn status_code(error: &SanityCheckError) -> Code {
+    match error.code() {
+        SANITY_CHECK_ERROR_CODE
+        | UNSUPPORTED_AGGREGATOR_ERROR_CODE
+        | SIGNATURE_FAILED_ERROR_CODE => Code::InvalidArgument,
+        SIMULATE_VALIDATION_ERROR_CODE
+        | PAYMASTER_VALIDATION_ERROR_CODE
+        | OPCODE_VALIDATION_ERROR_CODE
+        | EXPIRATION_ERROR_CODE
+        | STAKE_TOO_LOW_ERROR_CODE
+        | EXECUTION_ERROR_CODE => Code::FailedPrecondition,
+        ENTITY_BANNED_ERROR_CODE => Code::PermissionDenied,
+        LIMIT_EXCEEDED_ERROR_CODE => Code::ResourceExhausted,
+        RESOURCE_UNAVAILABLE_ERROR_CODE => Code::Unavailable,
+        USER_OPERATION_HASH_ERROR_CODE => Code::NotFound,
+        _ => Code::Internal,
+    }
+}
+
+/// Status with the code and the JSON serialized error (e.g. with the address of the banned entity) as the details
+pub fn error_status(code: Code, error: &SanityCheckError) -> Status {
+    match serde_json::to_vec(error) {
+        Ok(details) => Status::with_details(code, error.message(), details.into()),
+        Err(_) => Status::new(code, error.message()),
+    }
+}
+
+/// Status of the error of the user operation, with the code derived from the JSON-RPC error code
+pub fn user_operation_status(error: &SanityCheckError) -> Status {
+    error_status(status_code(error), error)
+}
+
+/// JSON-RPC error of the user operation in the details of the status, if the status has one
+pub fn user_operation_error(status: &Status) -> Option<SanityCheckError> {
+    serde_json::from_slice::<SanityCheckError>(status.details())
+        .ok()
+        .map(|error| error.into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn user_operation_errors() {
+        let error = SanityCheckError::owned(
+            ENTITY_BANNED_ERROR_CODE,
+            "Paymaster is banned",
+            Some(serde_json::json!({ "paymaster": "0x0000000000000000000000000000000000000001" })),
+        );
+        let status = user_operation_status(&error);
+        assert_eq!(status.code(), Code::PermissionDenied);
+        assert_eq!(status.message(), "Paymaster is banned");
+        assert_eq!(user_operation_error(&status), Some(error));
+
+        let error = SanityCheckError::owned(SANITY_CHECK_ERROR_CODE, "Invalid", None::<bool>);
+        assert_eq!(user_operation_status(&error).code(), Code::InvalidArgument);
+
+        assert_eq!(user_operation_error(&Status::internal("error")), None);
+    }
+}
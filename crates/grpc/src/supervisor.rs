@@ -0,0 +1,237 @@
+use std::{
+    collections::BTreeMap,
+    future::{pending, Future},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use parking_lot::Mutex;
+use tokio::task::{JoinError, JoinHandle};
+use tracing::{error, info};
+
+use crate::ShutdownSignal;
+
+/// Backoff before the first restart of a failed task, doubled with every consecutive failure up to the maximum
+const INITIAL_RESTART_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_RESTART_BACKOFF: Duration = Duration::from_secs(60);
+/// A task that ran at least this long before failing is restarted after the initial backoff again
+const HEALTHY_RUN_DURATION: Duration = Duration::from_secs(5 * 60);
+
+/// Health of a supervised task
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TaskHealth {
+    /// The task is running (false while it waits for the restart, or if it can't be restarted)
+    pub running: bool,
+    pub restarts: u64,
+    /// Panic message or error of the last failure
+    pub last_error: Option<String>,
+}
+
+/// Supervises the background tasks: a task that panics or fails is restarted with backoff, and the health of the
+/// tasks is reported (through `/ready`). The tasks aren't restarted once the shutdown is triggered, a task that
+/// completes successfully is not supervised anymore.
+#[derive(Clone, Debug, Default)]
+pub struct Supervisor {
+    tasks: Arc<Mutex<BTreeMap<String, TaskHealth>>>,
+    shutdown: Option<ShutdownSignal>,
+}
+
+fn failure(result: Result<anyhow::Result<()>, JoinError>) -> Option<String> {
+    match result {
+        Ok(Ok(())) => None,
+        Ok(Err(error)) => Some(format!("{error:?}")),
+        Err(error) if error.is_panic() => {
+            let panic = error.into_panic();
+            let message = panic
+                .downcast_ref::<&str>()
+                .map(|message| message.to_string())
+                .or_else(|| panic.downcast_ref::<String>().cloned())
+                .unwrap_or_default();
+            Some(format!("panicked: {message}"))
+        }
+        Err(error) => Some(format!("{error:?}")),
+    }
+}
+
+fn restart_backoff(consecutive_failures: u32) -> Duration {
+    INITIAL_RESTART_BACKOFF
+        .saturating_mul(2u32.saturating_pow(consecutive_failures.saturating_sub(1)))
+        .min(MAX_RESTART_BACKOFF)
+}
+
+impl Supervisor {
+    pub fn new(shutdown: ShutdownSignal) -> Self {
+        Self {
+            tasks: Default::default(),
+            shutdown: Some(shutdown),
+        }
+    }
+
+    fn is_shutdown(&self) -> bool {
+        self.shutdown
+            .as_ref()
+            .map_or(false, |shutdown| shutdown.is_triggered())
+    }
+
+    async fn wait_for_shutdown(&self) {
+        match self.shutdown.clone() {
+            Some(shutdown) => shutdown.wait().await,
+            None => pending().await,
+        }
+    }
+
+    fn update<F: FnOnce(&mut TaskHealth)>(&self, name: &str, f: F) {
+        f(self.tasks.lock().entry(name.to_string()).or_default());
+    }
+
+    /// Records the failure of the task, it's restarted unless the shutdown was triggered meanwhile
+    fn fail(&self, name: &str, error: String) -> bool {
+        if self.is_shutdown() {
+            return false;
+        }
+        error!("Task {name} failed: {error}");
+        metrics::counter!("supervisor_task_failures", 1, "task" => name.to_string());
+        self.update(name, |health| {
+            health.running = false;
+            health.last_error = Some(error);
+        });
+        true
+    }
+
+    /// Spawns the task created by `task` and restarts it (with backoff) whenever it panics or fails, until it
+    /// completes or the shutdown is triggered
+    pub fn spawn<F, Fut>(&self, name: &str, task: F) -> JoinHandle<()>
+    where
+        F: Fn() -> Fut + Send + 'static,
+        Fut: Future<Output = anyhow::Result<()>> + Send + 'static,
+    {
+        let supervisor = self.clone();
+        let name = name.to_string();
+        supervisor.update(&name, |health| health.running = true);
+        tokio::spawn(async move {
+            let mut consecutive_failures = 0;
+            loop {
+                let started = Instant::now();
+                let error = match failure(tokio::spawn(task()).await) {
+                    Some(error) => error,
+                    None => break,
+                };
+                if !supervisor.fail(&name, error) {
+                    break;
+                }
+
+                if started.elapsed() >= HEALTHY_RUN_DURATION {
+                    consecutive_failures = 0;
+                }
+                consecutive_failures += 1;
+                let backoff = restart_backoff(consecutive_failures);
+                tokio::select! {
+                    _ = tokio::time::sleep(backoff) => {},
+                    _ = supervisor.wait_for_shutdown() => break,
+                }
+
+                info!("Restarting task {name} after {backoff:?}");
+                supervisor.update(&name, |health| {
+                    health.running = true;
+                    health.restarts += 1;
+                });
+            }
+            supervisor.tasks.lock().remove(&name);
+        })
+    }
+
+    /// Reports the failure of a task that can't be restarted (e.g. it owns a receiver), an exit before the shutdown
+    /// is a failure
+    pub fn watch<Fut>(&self, name: &str, task: Fut) -> JoinHandle<()>
+    where
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let supervisor = self.clone();
+        let name = name.to_string();
+        supervisor.update(&name, |health| health.running = true);
+        tokio::spawn(async move {
+            let error = failure(tokio::spawn(task).await.map(Ok))
+                .unwrap_or_else(|| "exited unexpectedly".to_string());
+            if !supervisor.fail(&name, error) {
+                supervisor.tasks.lock().remove(&name);
+            }
+        })
+    }
+
+    pub fn health(&self) -> BTreeMap<String, TaskHealth> {
+        self.tasks.lock().clone()
+    }
+
+    /// Fails if any of the supervised tasks isn't running
+    pub fn check(&self) -> anyhow::Result<()> {
+        let failed: Vec<String> = self
+            .tasks
+            .lock()
+            .iter()
+            .filter(|(_, health)| !health.running)
+            .map(|(name, health)| {
+                format!(
+                    "{name} ({} restarts): {}",
+                    health.restarts,
+                    health.last_error.as_deref().unwrap_or_default()
+                )
+            })
+            .collect();
+        if failed.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow::anyhow!("tasks not running: {}", failed.join(", ")))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    #[test]
+    fn restart_backoffs() {
+        assert_eq!(restart_backoff(1), Duration::from_secs(1));
+        assert_eq!(restart_backoff(3), Duration::from_secs(4));
+        assert_eq!(restart_backoff(30), MAX_RESTART_BACKOFF);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn supervisor_restarts_failed_task() {
+        let (sender, shutdown) = ShutdownSignal::new();
+        let supervisor = Supervisor::new(shutdown.clone());
+        let runs = Arc::new(AtomicU32::new(0));
+
+        let handle = supervisor.spawn("task", {
+            let runs = runs.clone();
+            move || {
+                let runs = runs.clone();
+                let shutdown = shutdown.clone();
+                async move {
+                    if runs.fetch_add(1, Ordering::SeqCst) == 0 {
+                        panic!("first run");
+                    }
+                    shutdown.wait().await;
+                    Ok(())
+                }
+            }
+        });
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(supervisor.check().is_err());
+        assert_eq!(
+            supervisor.health()["task"].last_error.as_deref(),
+            Some("panicked: first run")
+        );
+
+        tokio::time::sleep(INITIAL_RESTART_BACKOFF).await;
+        assert!(supervisor.check().is_ok());
+        assert_eq!(supervisor.health()["task"].restarts, 1);
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+
+        sender.send(true).unwrap();
+        handle.await.unwrap();
+        assert!(supervisor.health().is_empty());
+    }
+}
@@ -0,0 +1,231 @@
+use std::task::{Context, Poll};
+
+use clap::{Parser, ValueEnum};
+use ethers::core::rand;
+use opentelemetry::{
+    global,
+    propagation::{Extractor, Injector},
+    sdk::{propagation::TraceContextPropagator, trace, Resource},
+    KeyValue,
+};
+use opentelemetry_otlp::WithExportConfig;
+use tonic::{
+    codegen::http::{HeaderMap, Request},
+    metadata::{MetadataKey, MetadataMap, MetadataValue},
+    server::NamedService,
+};
+use tower::Service;
+use tracing::{info_span, instrument::Instrumented, level_filters::LevelFilter, Instrument, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
+
+/// Metadata of the gRPC requests with the id of the user operation submission, set by the JSON-RPC server
+const SUBMISSION_ID_METADATA: &str = "x-submission-id";
+
+/// Format of the logs
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    /// Human-readable lines
+    #[default]
+    Text,
+    /// One JSON object per line with the fields of the event and its spans (e.g. `user_operation_hash`, `sender`,
+    /// `entry_point`, `error_code`), for the ingestion into Loki or ELK
+    Json,
+}
+
+/// Logging and tracing options
+#[derive(Clone, Debug, Parser, PartialEq, Eq)]
+pub struct TelemetryOpts {
+    #[clap(long = "log.format", value_enum, default_value = "text")]
+    pub log_format: LogFormat,
+
+    /// Log levels in the `RUST_LOG` syntax, per module (e.g. `info,aa_bundler_uopool=debug,aa_bundler_p2p=warn`),
+    /// overrides `RUST_LOG`
+    #[clap(long = "log.level")]
+    pub log_level: Option<String>,
+
+    /// OTLP (gRPC) endpoint of the OpenTelemetry collector to which the spans are exported (e.g.
+    /// `http://127.0.0.1:4317`), the spans are not exported if not set
+    #[clap(long)]
+    pub otlp_endpoint: Option<String>,
+}
+
+/// Installs the subscriber of the process: the logs (filtered by `--log.level` or `RUST_LOG`, `info` by default) and
+/// the export of the spans to the OpenTelemetry collector, whose trace context is propagated over the gRPC requests.
+/// Has to be called within the tokio runtime.
+pub fn init_tracing(opts: &TelemetryOpts, service_name: &'static str) -> anyhow::Result<()> {
+    let otel_layer = match &opts.otlp_endpoint {
+        Some(endpoint) => {
+            global::set_text_map_propagator(TraceContextPropagator::new());
+            let tracer =
+                opentelemetry_otlp::new_pipeline()
+                    .tracing()
+                    .with_exporter(
+                        opentelemetry_otlp::new_exporter()
+                            .tonic()
+                            .with_endpoint(endpoint),
+                    )
+                    .with_trace_config(trace::config().with_resource(Resource::new([
+                        KeyValue::new("service.name", service_name),
+                    ])))
+                    .install_batch(opentelemetry::runtime::Tokio)?;
+            Some(tracing_opentelemetry::layer().with_tracer(tracer))
+        }
+        None => None,
+    };
+
+    let filter = EnvFilter::builder().with_default_directive(LevelFilter::INFO.into());
+    let filter = match &opts.log_level {
+        Some(log_level) => filter.parse(log_level)?,
+        None => filter.from_env_lossy(),
+    };
+    let json = opts.log_format == LogFormat::Json;
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with((!json).then(fmt::layer))
+        .with(json.then(|| {
+            fmt::layer()
+                .json()
+                .flatten_event(true)
+                .with_current_span(false)
+                .with_span_list(true)
+        }))
+        .with(otel_layer)
+        .try_init()?;
+    Ok(())
+}
+
+/// Exports the spans that haven't been exported yet
+pub fn shutdown_tracing() {
+    global::shutdown_tracer_provider();
+}
+
+struct MetadataInjector<'a>(&'a mut MetadataMap);
+
+impl Injector for MetadataInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(key), Ok(value)) = (
+            MetadataKey::from_bytes(key.as_bytes()),
+            MetadataValue::try_from(value),
+        ) {
+            self.0.insert(key, value);
+        }
+    }
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|key| key.as_str()).collect()
+    }
+}
+
+/// Adds the trace context of the current span to the metadata of the gRPC request (nothing if the spans aren't
+/// exported)
+pub fn inject_trace_context(metadata: &mut MetadataMap) {
+    let context = Span::current().context();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&context, &mut MetadataInjector(metadata))
+    });
+}
+
+/// Random id of a user operation submission, logged by the JSON-RPC server and the uopool (next to the user operation
+/// hash) so that a submission can be followed across the processes
+pub fn new_submission_id() -> String {
+    format!("{:016x}", rand::random::<u64>())
+}
+
+pub fn set_submission_id(metadata: &mut MetadataMap, submission_id: &str) {
+    if let Ok(value) = MetadataValue::try_from(submission_id) {
+        metadata.insert(SUBMISSION_ID_METADATA, value);
+    }
+}
+
+pub fn submission_id(metadata: &MetadataMap) -> Option<&str> {
+    metadata
+        .get(SUBMISSION_ID_METADATA)
+        .and_then(|value| value.to_str().ok())
+}
+
+/// gRPC service whose requests are handled in a span that continues the trace of the client
+#[derive(Clone, Debug)]
+pub struct TraceContext<S> {
+    inner: S,
+}
+
+impl<S> TraceContext<S> {
+    pub fn new(inner: S) -> Self {
+        Self { inner }
+    }
+}
+
+impl<S: NamedService> NamedService for TraceContext<S> {
+    const NAME: &'static str = S::NAME;
+}
+
+impl<S, B> Service<Request<B>> for TraceContext<S>
+where
+    S: Service<Request<B>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Instrumented<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        let span = info_span!("grpc", path = req.uri().path());
+        span.set_parent(global::get_text_map_propagator(|propagator| {
+            propagator.extract(&HeaderExtractor(req.headers()))
+        }));
+        self.inner.call(req).instrument(span)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::{
+        propagation::TextMapPropagator,
+        trace::{SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState},
+    };
+
+    #[test]
+    fn trace_context_propagation() {
+        let propagator = TraceContextPropagator::new();
+        let span_context = SpanContext::new(
+            TraceId::from_bytes(0x4bf92f3577b34da6a3ce929d0e0e4736u128.to_be_bytes()),
+            SpanId::from_bytes(0x00f067aa0ba902b7u64.to_be_bytes()),
+            TraceFlags::SAMPLED,
+            true,
+            TraceState::default(),
+        );
+        let context = opentelemetry::Context::new().with_remote_span_context(span_context.clone());
+
+        let mut request = tonic::Request::new(());
+        propagator.inject_context(&context, &mut MetadataInjector(request.metadata_mut()));
+        assert_eq!(
+            request.metadata().get("traceparent").unwrap(),
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+        );
+
+        let headers = request.metadata().clone().into_headers();
+        let extracted = propagator.extract(&HeaderExtractor(&headers));
+        assert_eq!(
+            extracted.span().span_context().trace_id(),
+            span_context.trace_id()
+        );
+        assert_eq!(
+            extracted.span().span_context().span_id(),
+            span_context.span_id()
+        );
+    }
+}
@@ -0,0 +1,242 @@
+use std::{fs, fs::File, io::BufReader, path::Path, path::PathBuf, sync::Arc};
+
+use clap::Parser;
+use tokio::net::TcpStream;
+use tokio_rustls::{
+    rustls::{
+        server::AllowAnyAuthenticatedClient, Certificate, ClientConfig, PrivateKey, RootCertStore,
+        ServerConfig, ServerName,
+    },
+    TlsAcceptor, TlsConnector,
+};
+use tonic::transport::{self, Channel, Endpoint, Identity, ServerTlsConfig, Uri};
+use tower::service_fn;
+
+/// ALPN protocol of the gRPC (HTTP/2) listeners
+pub const ALPN_H2: &[u8] = b"h2";
+/// ALPN protocol of the JSON-RPC (HTTP/1.1) listeners
+pub const ALPN_HTTP1: &[u8] = b"http/1.1";
+
+/// PEM encoded certificate chain and private key of a TLS listener (the gRPC servers and the JSON-RPC server)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TlsConfig {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    /// PEM encoded CA certificate of the clients, if set the clients have to authenticate with a certificate signed
+    /// by it (mutual TLS)
+    pub client_ca: Option<PathBuf>,
+}
+
+impl TlsConfig {
+    /// Config of the TLS listener if both the certificate and the key are set
+    pub fn from_paths(cert: Option<PathBuf>, key: Option<PathBuf>) -> Option<Self> {
+        Some(Self {
+            cert: cert?,
+            key: key?,
+            client_ca: None,
+        })
+    }
+
+    pub fn with_client_ca(mut self, client_ca: Option<PathBuf>) -> Self {
+        self.client_ca = client_ca;
+        self
+    }
+
+    /// TLS config of the gRPC server, which then accepts only TLS connections (and only the clients with a
+    /// certificate signed by the client CA, if set)
+    pub fn server_tls_config(&self) -> anyhow::Result<ServerTlsConfig> {
+        let config = ServerTlsConfig::new().identity(Identity::from_pem(
+            fs::read(&self.cert)?,
+            fs::read(&self.key)?,
+        ));
+        Ok(match &self.client_ca {
+            Some(client_ca) => {
+                config.client_ca_root(transport::Certificate::from_pem(fs::read(client_ca)?))
+            }
+            None => config,
+        })
+    }
+
+    /// Acceptor of the TLS connections with the certificate (and the client CA) of the config
+    pub fn acceptor(&self, alpn_protocols: &[&[u8]]) -> anyhow::Result<TlsAcceptor> {
+        Ok(TlsAcceptor::from(Arc::new(
+            self.server_config(alpn_protocols)?,
+        )))
+    }
+
+    fn server_config(&self, alpn_protocols: &[&[u8]]) -> anyhow::Result<ServerConfig> {
+        let certs = read_certs(&self.cert)?;
+        let key = read_private_key(&self.key)?;
+
+        let builder = ServerConfig::builder().with_safe_defaults();
+        let builder = match &self.client_ca {
+            Some(client_ca) => builder.with_client_cert_verifier(
+                AllowAnyAuthenticatedClient::new(read_root_store(client_ca)?).boxed(),
+            ),
+            None => builder.with_no_client_auth(),
+        };
+        let mut config = builder.with_single_cert(certs, key)?;
+        config.alpn_protocols = alpn_protocols.iter().map(|p| p.to_vec()).collect();
+        Ok(config)
+    }
+}
+
+/// TLS options of the gRPC clients (the bundler and the JSON-RPC server connecting to the uopool and the bundler)
+#[derive(Clone, Debug, Default, Parser, PartialEq, Eq)]
+pub struct GrpcClientTlsOpts {
+    /// PEM encoded CA certificate that the certificates of the gRPC servers are verified with, the gRPC clients
+    /// connect over TLS if set
+    #[clap(long)]
+    pub grpc_tls_ca: Option<PathBuf>,
+
+    /// PEM encoded certificate chain that the gRPC clients authenticate with (mutual TLS)
+    #[clap(long, requires_all = ["grpc_tls_ca", "grpc_tls_client_key"])]
+    pub grpc_tls_client_cert: Option<PathBuf>,
+
+    /// PEM encoded private key of the gRPC client certificate
+    #[clap(long, requires = "grpc_tls_client_cert")]
+    pub grpc_tls_client_key: Option<PathBuf>,
+}
+
+impl GrpcClientTlsOpts {
+    pub fn tls_config(&self) -> Option<ClientTlsConfig> {
+        Some(ClientTlsConfig {
+            ca: self.grpc_tls_ca.clone()?,
+            identity: self
+                .grpc_tls_client_cert
+                .clone()
+                .zip(self.grpc_tls_client_key.clone()),
+        })
+    }
+}
+
+/// TLS config of a gRPC client
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClientTlsConfig {
+    /// PEM encoded CA certificate that the certificate of the server is verified with
+    pub ca: PathBuf,
+    /// PEM encoded certificate chain and private key of the client (mutual TLS)
+    pub identity: Option<(PathBuf, PathBuf)>,
+}
+
+impl ClientTlsConfig {
+    fn client_config(&self) -> anyhow::Result<ClientConfig> {
+        let builder = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(read_root_store(&self.ca)?);
+        let mut config = match &self.identity {
+            Some((cert, key)) => {
+                builder.with_client_auth_cert(read_certs(cert)?, read_private_key(key)?)?
+            }
+            None => builder.with_no_client_auth(),
+        };
+        config.alpn_protocols = vec![ALPN_H2.to_vec()];
+        Ok(config)
+    }
+}
+
+/// Connects to the gRPC server at the address (`host:port`), over TLS if the config is set
+pub async fn grpc_channel(
+    address: &str,
+    tls_config: Option<&ClientTlsConfig>,
+) -> anyhow::Result<Channel> {
+    connect_endpoint(grpc_endpoint(address)?, address, tls_config).await
+}
+
+pub(crate) fn grpc_endpoint(address: &str) -> anyhow::Result<Endpoint> {
+    Ok(Endpoint::try_from(format!("http://{address}"))?)
+}
+
+/// Connects the endpoint (with its settings, e.g. the timeout) to the address, over TLS if the config is set
+pub(crate) async fn connect_endpoint(
+    endpoint: Endpoint,
+    address: &str,
+    tls_config: Option<&ClientTlsConfig>,
+) -> anyhow::Result<Channel> {
+    let tls_config = match tls_config {
+        Some(tls_config) => tls_config,
+        None => return Ok(endpoint.connect().await?),
+    };
+
+    let connector = TlsConnector::from(Arc::new(tls_config.client_config()?));
+    let host = address
+        .rsplit_once(':')
+        .map_or(address, |(host, _)| host)
+        .trim_start_matches('[')
+        .trim_end_matches(']');
+    let server_name = ServerName::try_from(host)?;
+    let address = address.to_string();
+    // the TLS is handled by the connector, so the endpoint URI keeps the plain scheme
+    let channel = endpoint
+        .connect_with_connector(service_fn(move |_: Uri| {
+            let connector = connector.clone();
+            let server_name = server_name.clone();
+            let address = address.clone();
+            async move {
+                let stream = TcpStream::connect(address).await?;
+                stream.set_nodelay(true)?;
+                connector.connect(server_name, stream).await
+            }
+        }))
+        .await?;
+    Ok(channel)
+}
+
+fn read_certs(path: &Path) -> anyhow::Result<Vec<Certificate>> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(path)?))?
+        .into_iter()
+        .map(Certificate)
+        .collect::<Vec<_>>();
+    if certs.is_empty() {
+        return Err(anyhow::anyhow!(
+            "No certificates found in {}",
+            path.display()
+        ));
+    }
+    Ok(certs)
+}
+
+fn read_root_store(path: &Path) -> anyhow::Result<RootCertStore> {
+    let mut root_store = RootCertStore::empty();
+    for cert in read_certs(path)? {
+        root_store.add(&cert)?;
+    }
+    Ok(root_store)
+}
+
+fn read_private_key(path: &Path) -> anyhow::Result<PrivateKey> {
+    rustls_pemfile::read_all(&mut BufReader::new(File::open(path)?))?
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::ECKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or_else(|| anyhow::anyhow!("No private key found in {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tonic::transport::Server;
+
+    #[test]
+    fn broken_server_tls_config() {
+        let dir = std::env::temp_dir().join(format!("grpc-tls-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let tls_config = TlsConfig {
+            cert: dir.join("cert.pem"),
+            key: dir.join("key.pem"),
+            client_ca: None,
+        };
+        assert!(tls_config.server_tls_config().is_err());
+
+        fs::write(&tls_config.cert, "not a certificate").unwrap();
+        fs::write(&tls_config.key, "not a key").unwrap();
+        assert!(Server::builder()
+            .tls_config(tls_config.server_tls_config().unwrap())
+            .is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
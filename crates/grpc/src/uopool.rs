@@ -1,45 +1,146 @@
 use std::{
+    cmp::Ordering,
     collections::{HashMap, HashSet},
+    future::Future,
     net::SocketAddr,
+    path::PathBuf,
     sync::Arc,
-    time::Duration,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use aa_bundler_contracts::{
-    parse_from_input_data, EntryPoint, EntryPointAPIEvents, EntryPointErr,
-    SimulateValidationResult, UserOperationEventFilter,
+    deployed_canonical_entry_points, parse_from_input_data, EntryPoint, EntryPointAPIEvents,
+    EntryPointErr, EntryPointVersion, SimulateValidationResult, UserOperationEventFilter,
 };
+use aa_bundler_p2p::{NetworkHandle, P2POpts};
 use aa_bundler_primitives::{
-    get_addr, parse_u256, ReputationStatus, SimulationError, UserOperation,
-    UserOperationGasEstimation, BAN_SLACK, MIN_INCLUSION_RATE_DENOMINATOR, THROTTLED_MAX_INCLUDE,
+    get_addr, parse_address, parse_checksummed_address, parse_u256, rejection_reason,
+    with_request_budget, Authorization, EthProvider, ProviderCapabilities, RejectedUserOperation,
+    ReputationStatus, SanityCheckError, Secret, SimulationError, UserOperation,
+    UserOperationGasEstimation, UserOperationHash, BAN_SLACK, MIN_INCLUSION_RATE_DENOMINATOR,
+    RESOURCE_UNAVAILABLE_ERROR_CODE, SANITY_CHECK_ERROR_CODE, THROTTLED_MAX_INCLUDE,
     THROTTLING_SLACK,
 };
 use aa_bundler_uopool::{
-    canonical::simulation::SimulateValidationError, mempool_id, MemoryMempool, MemoryReputation,
-    MempoolId, Overhead, Reputation, UoPool as UserOperationPool,
+    canonical::simulation::SimulateValidationError, mempool_id, required_prefund, MemoryMempool,
+    MemoryReputation, MempoolId, Overhead, Readiness, RejectionLog, Reputation,
+    UoPool as UserOperationPool, UoPoolEvent,
 };
-use anyhow::Result;
+use anyhow::{format_err, Result};
 use async_trait::async_trait;
 use clap::Parser;
 use dashmap::DashMap;
 use ethers::{
+    abi::AbiDecode,
+    contract::EthLogDecode,
     prelude::LogMeta,
-    providers::{Http, Middleware, Provider},
-    types::{Address, H256, U256, U64},
+    providers::Middleware,
+    types::{spoof, Address, BlockNumber, Bytes, Log, H256, U256, U64},
 };
-use tonic::Response;
-use tracing::{debug, info, trace, warn};
+use parking_lot::Mutex;
+use tokio::{
+    net::UnixListener,
+    sync::{
+        broadcast::{self, error::RecvError},
+        mpsc, OwnedSemaphorePermit, Semaphore,
+    },
+    task::JoinHandle,
+};
+use tokio_stream::wrappers::{ReceiverStream, UnixListenerStream};
+use tonic::{service::interceptor::InterceptedService, transport::Server, Response};
+use tonic_health::{
+    server::{health_reporter, HealthReporter},
+    ServingStatus,
+};
+use tracing::{debug, error, field, info, info_span, instrument, trace, warn, Instrument, Span};
 
 const LATEST_SCAN_DEPTH: u64 = 1000;
+/// Selector of the `Error(string)` revert
+const REVERT_ERROR_SELECTOR: [u8; 4] = [0x08, 0xc3, 0x79, 0xa0];
+const EVENTS_POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// Interval of the checks of the execution client reported by the gRPC health checking service
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+/// Service names reported by the gRPC health checking service
+const UOPOOL_SERVICE_NAME: &str = "uopool.UoPool";
+const PROVIDER_SERVICE_NAME: &str = "provider";
+/// Number of mempool events buffered for each subscriber of the stream
+const EVENTS_STREAM_CAPACITY: usize = 1000;
+/// Maximum number of user operations of the batch add request
+const MAX_BATCH_SIZE: usize = 100;
+/// Maximum number of user operations of the batch add request that are verified concurrently
+const MAX_CONCURRENT_VERIFICATIONS: usize = 8;
+/// Upper bound of the call gas limit search if the block gas limit is not available
+const MAX_CALL_GAS: u64 = 30_000_000;
 
 use crate::proto::types::{GetChainIdResponse, GetSupportedEntryPointsResponse};
 use crate::proto::uopool::*;
+use crate::proto::FILE_DESCRIPTOR_SET;
+use crate::{
+    auth_token,
+    blocks::watch_new_blocks,
+    error_status,
+    metrics::{record_user_operation_result, rejection_code, start_uopool_metrics},
+    p2p::{gossip_user_operation, start_p2p},
+    snapshot::UoPoolSnapshot,
+    telemetry::{submission_id, TraceContext},
+    user_operation_error, user_operation_status, AuthValidator, ShutdownSignal, Supervisor,
+    TlsConfig,
+};
 
-#[derive(Clone, Copy, Debug, Parser, PartialEq)]
+#[derive(Clone, Debug, Parser, PartialEq)]
 pub struct UoPoolServiceOpts {
     #[clap(long, default_value = "127.0.0.1:3001")]
     pub uopool_grpc_listen_address: SocketAddr,
 
+    /// PEM encoded certificate chain of the uopool gRPC server, which then accepts only TLS connections (requires
+    /// the key)
+    #[clap(long, requires = "uopool_grpc_tls_key")]
+    pub uopool_grpc_tls_cert: Option<PathBuf>,
+
+    /// PEM encoded private key of the uopool gRPC server certificate
+    #[clap(long, requires = "uopool_grpc_tls_cert")]
+    pub uopool_grpc_tls_key: Option<PathBuf>,
+
+    /// PEM encoded CA certificate of the clients of the uopool gRPC server, which then have to authenticate with a
+    /// certificate signed by it (mutual TLS)
+    #[clap(long, requires = "uopool_grpc_tls_cert")]
+    pub uopool_grpc_tls_client_ca: Option<PathBuf>,
+
+    /// Unix domain socket on which the uopool gRPC server is served to the co-located clients (IPC)
+    #[clap(long)]
+    pub uopool_grpc_ipc_path: Option<PathBuf>,
+
+    /// File with the shared secret that the clients of the uopool gRPC server have to send in the `authorization`
+    /// metadata (`Bearer <token>`), so that the server can be bound on a non-loopback address. The IPC socket is
+    /// protected by its file permissions instead.
+    #[clap(long)]
+    pub uopool_grpc_auth_token_file: Option<PathBuf>,
+
+    /// Shared secret of the uopool gRPC server given directly, only with `AA_BUNDLER_UOPOOL_GRPC_AUTH_TOKEN` or the
+    /// config file
+    #[clap(long, conflicts_with = "uopool_grpc_auth_token_file")]
+    pub uopool_grpc_auth_token: Option<Secret>,
+
+    /// Maximum number of concurrent HTTP/2 streams (requests and subscriptions) of a connection to the uopool gRPC
+    /// server (unlimited if not set)
+    #[clap(long)]
+    pub uopool_grpc_max_concurrent_streams: Option<u32>,
+
+    /// Maximum number of requests of a connection to the uopool gRPC server that are handled concurrently, the
+    /// others wait (unlimited if not set)
+    #[clap(long)]
+    pub uopool_grpc_concurrency_limit: Option<usize>,
+
+    /// Interval in seconds of the HTTP/2 pings of the uopool gRPC server, which keep the idle connections (e.g. of
+    /// the JSON-RPC server) from being dropped by the network. 0 disables the pings.
+    #[clap(long, default_value = "60")]
+    pub uopool_grpc_keepalive_interval: u64,
+
+    /// Time in seconds that the uopool gRPC server waits for the acknowledgement of the ping before it closes the
+    /// connection
+    #[clap(long, default_value = "20")]
+    pub uopool_grpc_keepalive_timeout: u64,
+
     #[clap(long, value_parser=parse_u256, default_value = "1")]
     pub min_stake: U256,
 
@@ -48,142 +149,806 @@ pub struct UoPoolServiceOpts {
 
     #[clap(long, value_parser=parse_u256, default_value = "0")]
     pub min_priority_fee_per_gas: U256,
+
+    /// Maximum difference between the user operation nonce and the sender's entry point nonce,
+    /// user operations with future nonces are queued until the preceding nonces are included
+    #[clap(long, value_parser=parse_u256, default_value = "10")]
+    pub max_queued_nonce_gap: U256,
+
+    /// Signature aggregators whose user operations are accepted by the uopool, the user operations of other
+    /// aggregators are rejected (unsupported aggregator). Any aggregator is accepted if not set, unless the uopool runs
+    /// together with the bundler, which then defaults it to its `--aggregators`
+    #[clap(long, value_delimiter=',', value_parser=parse_address)]
+    pub uopool_aggregators: Vec<Address>,
+
+    /// Expected chain id of the execution client, the uopool doesn't start on another chain
+    #[clap(long)]
+    pub chain_id: Option<u64>,
+
+    /// Interval in seconds of the chain id re-checks, the new user operations are rejected while the execution client
+    /// is on another chain (e.g. the endpoint was switched to another network). 0 disables the re-checks.
+    #[clap(long, default_value = "60")]
+    pub chain_id_check_interval: u64,
+
+    /// Block on which the validation is simulated and the gas is estimated, `pending` for the chains (sequencers)
+    /// whose pending state is ahead of the latest block
+    #[clap(long, default_value = "latest", value_parser = ["latest", "pending"])]
+    pub simulation_block: String,
+
+    /// Maximum number of the execution client requests made to verify (or estimate the gas of) a single user
+    /// operation, the requests beyond it fail the user operation. 0 disables the limit.
+    #[clap(long, default_value = "100")]
+    pub max_eth_client_requests_per_user_operation: u32,
+
+    /// Maximum number of the user operations that are simulated (verified or estimated) at once, across the entry
+    /// points and the sources (JSON-RPC, P2P), the others wait for their turn. 0 disables the limit.
+    #[clap(long, default_value = "64")]
+    pub max_concurrent_simulations: usize,
+
+    /// Number of the most recent rejected user operations kept for the `admin_getRejectedUserOperations` method
+    #[clap(long, default_value = "1000")]
+    pub rejection_log_size: usize,
+
+    /// Latency (in milliseconds) above which the simulation of a user operation is reported as slow (0 disables it)
+    #[clap(long, default_value = "2000")]
+    pub simulation_latency_slo: u64,
+
+    /// Entities (factories, paymasters, aggregators) that are never throttled or banned
+    #[clap(long, value_delimiter=',', value_parser=parse_address)]
+    pub whitelist: Vec<Address>,
+
+    /// Entities whose user operations are always rejected
+    #[clap(long, value_delimiter=',', value_parser=parse_address)]
+    pub blacklist: Vec<Address>,
+
+    /// An entity is expected to have at least one of this many seen user operations included
+    #[clap(long, default_value_t = MIN_INCLUSION_RATE_DENOMINATOR)]
+    pub min_inclusion_denominator: u64,
+
+    /// Number of the expected inclusions an entity can miss before it's throttled
+    #[clap(long, default_value_t = THROTTLING_SLACK)]
+    pub throttling_slack: u64,
+
+    /// Number of the expected inclusions an entity can miss before it's banned
+    #[clap(long, default_value_t = BAN_SLACK)]
+    pub ban_slack: u64,
+
+    /// Maximum number of the user operations in the mempool of each entry point (0 for no limit)
+    #[clap(long, default_value = "0")]
+    pub max_mempool_size: usize,
+
+    /// Maximum number of the queued user operations (with future nonces) of each entry point, the oldest ones are
+    /// evicted to make room for the new ones (0 for no limit)
+    #[clap(long, default_value = "1024")]
+    pub max_queued_user_operations: usize,
+
+    /// Maximum number of the queued user operations of a sender, its further user operations with future nonces are
+    /// rejected (0 for no limit)
+    #[clap(long, default_value = "16")]
+    pub max_queued_user_operations_per_sender: usize,
+
+    /// Options of an entry point that override the global ones, as `<entry point>:<option>=<value>,...` (e.g.
+    /// `0x5FF1...:max-verification-gas=3000000,simulation-block=pending`), the options are
+    /// `max-verification-gas`, `min-priority-fee-per-gas`, `max-mempool-size`, `simulation-block` and
+    /// `trace-validation`
+    #[clap(long = "entry-point-override", value_parser = parse_entry_point_overrides)]
+    pub entry_point_overrides: Vec<EntryPointOverrides>,
+
+    /// File to which the mempool and the reputation are saved on shutdown, they're restored from it at the start
+    /// (the user operations are verified again)
+    #[clap(long)]
+    pub uopool_snapshot_path: Option<PathBuf>,
+}
+
+/// Options of an entry point that override the global ones, so that the entry points can be tuned differently
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct EntryPointOverrides {
+    pub entry_point: Address,
+    pub max_verification_gas: Option<U256>,
+    pub min_priority_fee_per_gas: Option<U256>,
+    pub max_mempool_size: Option<usize>,
+    pub simulation_block: Option<BlockNumber>,
+    /// Validation on the trace of the simulation, it can only be disabled (the execution client has to support it)
+    pub trace_validation: Option<bool>,
+}
+
+/// Parses the options of the entry point from `<entry point>:<option>=<value>,...`
+pub fn parse_entry_point_overrides(value: &str) -> Result<EntryPointOverrides, String> {
+    let (entry_point, options) = value
+        .split_once(':')
+        .ok_or_else(|| format!("{value} is not <entry point>:<option>=<value>,..."))?;
+    let mut overrides = EntryPointOverrides {
+        entry_point: parse_checksummed_address(entry_point)?,
+        ..Default::default()
+    };
+    for option in options.split(',').filter(|option| !option.is_empty()) {
+        let (name, value) = option
+            .split_once('=')
+            .ok_or_else(|| format!("{option} is not <option>=<value>"))?;
+        match name.replace('_', "-").as_str() {
+            "max-verification-gas" => overrides.max_verification_gas = Some(parse_u256(value)?),
+            "min-priority-fee-per-gas" => {
+                overrides.min_priority_fee_per_gas = Some(parse_u256(value)?)
+            }
+            "max-mempool-size" => {
+                overrides.max_mempool_size = Some(
+                    value
+                        .parse()
+                        .map_err(|_| format!("{value} is not a valid mempool size"))?,
+                )
+            }
+            "simulation-block" => {
+                overrides.simulation_block = match value {
+                    "latest" => Some(BlockNumber::Latest),
+                    "pending" => Some(BlockNumber::Pending),
+                    _ => return Err(format!("Simulation block {value} is not latest or pending")),
+                }
+            }
+            "trace-validation" => {
+                overrides.trace_validation = Some(
+                    value
+                        .parse()
+                        .map_err(|_| format!("{value} is not true or false"))?,
+                )
+            }
+            name => return Err(format!("Unknown entry point option {name}")),
+        }
+    }
+    Ok(overrides)
 }
 
 pub struct UoPoolService<M: Middleware> {
     pub mempools: Arc<DashMap<MempoolId, UserOperationPool<M>>>,
+    /// Entry points of the mempools in the configured order (the first one is the preferred)
+    pub entry_points: Arc<Vec<Address>>,
     pub eth_provider: Arc<M>,
     pub chain_id: U256,
+    pub max_queued_nonce_gap: U256,
+    /// Whether the debug methods (used by the `debug_bundler` JSON-RPC namespace) are enabled
+    pub debug: bool,
+    /// New user operations are rejected while not accepting (set by the operator with the admin methods)
+    pub accepting: Arc<Mutex<bool>>,
+    /// New user operations are rejected while the execution client is on another chain than at the start
+    pub chain_id_mismatch: Arc<Mutex<bool>>,
+    /// Handle of the P2P network if the uopool joined the shared mempool
+    pub network: Option<NetworkHandle>,
+    /// Whether the user operations received over gRPC are gossiped to the shared mempool
+    pub gossip_user_operations: bool,
+    /// Maximum number of the execution client requests of the verification (or the estimation) of a user operation
+    pub eth_client_request_budget: Option<u32>,
+    /// Permits of the concurrent simulations, unlimited if not set
+    pub simulation_permits: Option<Arc<Semaphore>>,
+    /// Most recent rejected user operations
+    pub rejections: Arc<Mutex<RejectionLog>>,
+    /// Entry points whose minimum priority fee is overridden, it's not changed by the admin methods (or the reload)
+    pub fixed_min_priority_fees: Arc<HashSet<Address>>,
+}
+
+// the derive would require the middleware to be cloneable
+impl<M: Middleware> Clone for UoPoolService<M> {
+    fn clone(&self) -> Self {
+        Self {
+            mempools: self.mempools.clone(),
+            entry_points: self.entry_points.clone(),
+            eth_provider: self.eth_provider.clone(),
+            chain_id: self.chain_id,
+            max_queued_nonce_gap: self.max_queued_nonce_gap,
+            debug: self.debug,
+            accepting: self.accepting.clone(),
+            chain_id_mismatch: self.chain_id_mismatch.clone(),
+            network: self.network.clone(),
+            gossip_user_operations: self.gossip_user_operations,
+            eth_client_request_budget: self.eth_client_request_budget,
+            simulation_permits: self.simulation_permits.clone(),
+            rejections: self.rejections.clone(),
+            fixed_min_priority_fees: self.fixed_min_priority_fees.clone(),
+        }
+    }
 }
 
 impl<M: Middleware + 'static> UoPoolService<M> {
     pub fn new(
         mempools: Arc<DashMap<MempoolId, UserOperationPool<M>>>,
+        entry_points: Vec<Address>,
         eth_provider: Arc<M>,
         chain_id: U256,
+        max_queued_nonce_gap: U256,
+        debug: bool,
     ) -> Self {
         Self {
             mempools,
+            entry_points: Arc::new(entry_points),
             eth_provider,
             chain_id,
+            max_queued_nonce_gap,
+            debug,
+            accepting: Arc::new(Mutex::new(true)),
+            chain_id_mismatch: Arc::new(Mutex::new(false)),
+            network: None,
+            gossip_user_operations: false,
+            eth_client_request_budget: None,
+            simulation_permits: None,
+            rejections: Arc::new(Mutex::new(RejectionLog::new(0))),
+            fixed_min_priority_fees: Arc::new(HashSet::new()),
         }
     }
 
-    pub async fn find_user_operation_event(
-        &self,
-        user_operation_hash: H256,
-    ) -> anyhow::Result<Option<(UserOperationEventFilter, LogMeta)>> {
-        let mut event: Option<(UserOperationEventFilter, LogMeta)> = None;
-        for pool in self.mempools.iter() {
-            if let Some(res) = pool
-                .get_user_operation_event_meta(user_operation_hash)
-                .await?
-            {
-                event = Some(res);
-                break;
-            }
+    /// Waits for a simulation permit (if the concurrent simulations are bounded). It has to be acquired before the
+    /// mempool is borrowed, so that the waiting requests don't hold the read guards of the mempools.
+    async fn simulation_permit(&self) -> Option<OwnedSemaphorePermit> {
+        match &self.simulation_permits {
+            Some(permits) => permits.clone().acquire_owned().await.ok(),
+            None => None,
         }
-        Ok(event)
     }
-}
 
-#[async_trait]
-impl<M: Middleware + 'static> uo_pool_server::UoPool for UoPoolService<M>
-where
-    EntryPointErr: From<<M as Middleware>::Error>,
-{
-    async fn add(
+    /// Runs the verification (or the estimation) of a user operation within its execution client request budget
+    async fn within_budget<F: Future>(&self, future: F) -> F::Output {
+        match self.eth_client_request_budget {
+            Some(budget) => with_request_budget(budget, future).await,
+            None => future.await,
+        }
+    }
+
+    /// Runs the estimation of a user operation within its execution client request budget, once a simulation permit
+    /// is available
+    async fn within_limits<F: Future>(&self, future: F) -> F::Output {
+        let _permit = self.simulation_permit().await;
+        self.within_budget(future).await
+    }
+
+    /// Simulates the user operation and searches its call gas limit, the errors are returned in the response
+    #[instrument(skip_all)]
+    async fn estimate_user_operation(
         &self,
-        request: tonic::Request<AddRequest>,
-    ) -> Result<Response<AddResponse>, tonic::Status> {
-        let req = request.into_inner();
-        let mut res = AddResponse::default();
+        req: EstimateUserOperationGasRequest,
+    ) -> Result<EstimateUserOperationGasResponse, tonic::Status>
+    where
+        EntryPointErr: From<<M as Middleware>::Error>,
+    {
+        let mut res = EstimateUserOperationGasResponse::default();
 
-        if let AddRequest {
+        if let EstimateUserOperationGasRequest {
             uo: Some(user_operation),
             ep: Some(entry_point),
+            state_override,
         } = req
         {
-            trace!("Receive grpc request to add user operation: {user_operation:?} on entry point: {entry_point:?}");
             let user_operation: UserOperation = user_operation
                 .try_into()
                 .map_err(|_| tonic::Status::invalid_argument("invalid user operation"))?;
             let entry_point: Address = entry_point
                 .try_into()
                 .map_err(|_| tonic::Status::invalid_argument("invalid entry point"))?;
+            let state_override = if state_override.is_empty() {
+                None
+            } else {
+                Some(
+                    serde_json::from_str::<spoof::State>(&state_override).map_err(|_| {
+                        tonic::Status::invalid_argument("invalid state override set")
+                    })?,
+                )
+            };
 
             let mempool_id = mempool_id(&entry_point, &self.chain_id);
 
-            let verification_result = {
-                let uopool = self
-                    .mempools
-                    .get(&mempool_id)
-                    .ok_or_else(|| tonic::Status::invalid_argument("entry point not supported"))?;
-                uopool.verify_user_operation(&user_operation).await
-            };
+            let uopool = self
+                .mempools
+                .get(&mempool_id)
+                .ok_or_else(|| tonic::Status::invalid_argument("entry point not supported"))?;
 
-            match verification_result {
-                Ok(verification_result) => {
-                    let mut uopool = self.mempools.get_mut(&mempool_id).ok_or_else(|| {
-                        tonic::Status::invalid_argument("entry point not supported")
-                    })?;
+            match uopool
+                .simulate_user_operation(&user_operation, state_override.as_ref())
+                .await
+            {
+                Ok(simulation_result) => {
+                    let pre_verification_gas =
+                        Overhead::default().calculate_pre_verification_gas(&user_operation);
 
-                    if let Some(user_operation_hash) =
-                        verification_result.sanity_check_result.user_operation_hash
+                    // pre operation gas includes the pre verification gas of the user operation
+                    let verification_gas_limit = match simulation_result.simulate_validation_result
                     {
-                        uopool
-                            .remove_user_operation(&user_operation_hash)
-                            .unwrap_or_else(|| {
-                                trace!(
-                                    "Unable to remove user operation {:?} from mempool {:?}",
-                                    user_operation_hash,
-                                    mempool_id
-                                )
-                            });
+                        SimulateValidationResult::ValidationResult(validation_result) => {
+                            validation_result.return_info.0
+                        }
+                        SimulateValidationResult::ValidationResultWithAggregation(
+                            validation_result_with_aggregation,
+                        ) => validation_result_with_aggregation.return_info.0,
                     }
+                    .saturating_sub(user_operation.pre_verification_gas);
+
+                    let max_call_gas = uopool
+                        .eth_provider
+                        .get_block(BlockNumber::Latest)
+                        .await
+                        .map_err(|e| {
+                            tonic::Status::internal(format!(
+                                "Getting the latest block error: {e:?}"
+                            ))
+                        })?
+                        .map(|block| block.gas_limit)
+                        .unwrap_or(U256::from(MAX_CALL_GAS));
 
                     match uopool
-                        .mempool
-                        .add(user_operation.clone(), &entry_point, &self.chain_id)
+                        .entry_point
+                        .search_call_gas(
+                            user_operation.clone(),
+                            max_call_gas,
+                            state_override.as_ref(),
+                        )
+                        .await
                     {
-                        Ok(_) => {
-                            // TODO: find better way to atomically store user operation and code hashes
-                            match uopool.mempool.set_code_hashes(
-                                &user_operation.hash(&entry_point, &self.chain_id),
-                                &verification_result.simulation_result.code_hashes,
-                            ) {
-                                Ok(()) | Err(_) => {}
-                            }
-
-                            // TODO: update reputation
-
-                            res.set_result(AddResult::Added);
-                            res.data = serde_json::to_string(
-                                &user_operation.hash(&entry_point, &self.chain_id),
-                            )
-                            .map_err(|_| tonic::Status::internal("error adding user operation"))?;
+                        Ok(call_gas_limit) => {
+                            let estimation = UserOperationGasEstimation {
+                                pre_verification_gas,
+                                verification_gas_limit,
+                                call_gas_limit,
+                            };
+                            res.set_result(EstimateUserOperationGasResult::Estimated);
+                            res.data = serde_json::to_string(&estimation).map_err(|_| {
+                                tonic::Status::internal("error estimating user operation gas")
+                            })?;
+                            res.estimation = Some(estimation.into());
                         }
                         Err(error) => {
-                            res.set_result(AddResult::NotAdded);
-                            res.data = serde_json::to_string(&error.to_string()).map_err(|_| {
-                                tonic::Status::internal("error adding user operation")
-                            })?;
+                            return Err(user_operation_status(&SimulationError::from(
+                                match error {
+                                    EntryPointErr::ExecutionReverted(data) => {
+                                        SimulateValidationError::UserOperationExecution {
+                                            message: format!("execution reverted: {data}"),
+                                        }
+                                    }
+                                    _ => SimulateValidationError::UnknownError {
+                                        error: format!("{error:?}"),
+                                    },
+                                },
+                            )));
                         }
                     }
                 }
-                Err(error) => {
-                    res.set_result(AddResult::NotAdded);
-                    res.data = serde_json::to_string(&error)
-                        .map_err(|_| tonic::Status::internal("error adding user operation"))?;
-                }
+                Err(error) => return Err(user_operation_status(&SimulationError::from(error))),
+            }
+
+            return Ok(res);
+        }
+
+        Err(tonic::Status::invalid_argument("missing user operation"))
+    }
+
+    /// Verifies the user operation and adds it to the mempool (or queues it if its nonce is ahead of the entry point
+    /// nonce), the verification errors are returned in the response
+    async fn add_user_operation(&self, req: AddRequest) -> Result<AddResponse, tonic::Status>
+    where
+        EntryPointErr: From<<M as Middleware>::Error>,
+    {
+        if let AddRequest {
+            uo: Some(user_operation),
+            ep: Some(entry_point),
+            authorization,
+            private,
+        } = req
+        {
+            trace!("Receive grpc request to add user operation: {user_operation:?} on entry point: {entry_point:?}");
+            let user_operation: UserOperation = user_operation
+                .try_into()
+                .map_err(|_| tonic::Status::invalid_argument("invalid user operation"))?;
+            let entry_point: Address = entry_point
+                .try_into()
+                .map_err(|_| tonic::Status::invalid_argument("invalid entry point"))?;
+
+            let user_operation_hash = self
+                .insert_user_operation(
+                    user_operation,
+                    entry_point,
+                    authorization.map(Into::into),
+                    private,
+                )
+                .await?;
+            if let (Some(network), true, false) =
+                (&self.network, self.gossip_user_operations, private)
+            {
+                let uopool_service = self.clone();
+                let network = network.clone();
+                tokio::spawn(async move {
+                    gossip_user_operation(
+                        &uopool_service,
+                        &network,
+                        entry_point,
+                        &user_operation_hash,
+                    )
+                    .await
+                });
             }
 
-            return Ok(Response::new(res));
+            let mut res = AddResponse::default();
+            res.set_result(AddResult::Added);
+            res.data = serde_json::to_string(&user_operation_hash)
+                .map_err(|_| tonic::Status::internal("error adding user operation"))?;
+            return Ok(res);
         }
 
         Err(tonic::Status::invalid_argument("missing user operation"))
     }
 
+    /// Verifies the user operation (received over gRPC or gossiped by a peer) and adds it to the mempool of the entry
+    /// point, or queues it if its nonce is ahead of the entry point nonce
+    #[instrument(skip_all, fields(
+        user_operation_hash = field::Empty,
+        sender = ?user_operation.sender,
+        nonce = %user_operation.nonce,
+        entry_point = ?entry_point,
+    ))]
+    pub async fn insert_user_operation(
+        &self,
+        user_operation: UserOperation,
+        entry_point: Address,
+        authorization: Option<Authorization>,
+        private: bool,
+    ) -> Result<UserOperationHash, tonic::Status>
+    where
+        EntryPointErr: From<<M as Middleware>::Error>,
+    {
+        let user_operation_hash = user_operation.hash(&entry_point, &self.chain_id);
+        Span::current().record("user_operation_hash", field::debug(&user_operation_hash));
+        let (sender, factory, paymaster) = (
+            user_operation.sender,
+            get_addr(&user_operation.init_code),
+            get_addr(&user_operation.paymaster_and_data),
+        );
+
+        let result = self
+            .try_insert_user_operation(user_operation, entry_point, authorization, private)
+            .await;
+        match &result {
+            Ok(_) => debug!("User operation accepted"),
+            Err(status) => {
+                debug!(
+                    error_code = rejection_code(status),
+                    "User operation rejected: {}",
+                    status.message()
+                );
+                let code = user_operation_error(status).map_or(0, |error| error.code());
+                self.rejections.lock().add(RejectedUserOperation {
+                    user_operation_hash,
+                    entry_point,
+                    sender,
+                    factory,
+                    paymaster,
+                    reason: rejection_reason(code).to_string(),
+                    code,
+                    message: status.message().to_string(),
+                    rejected_at: SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .map(|d| d.as_secs())
+                        .unwrap_or_default(),
+                });
+            }
+        }
+        record_user_operation_result(&entry_point, &result);
+        result
+    }
+
+    async fn try_insert_user_operation(
+        &self,
+        user_operation: UserOperation,
+        entry_point: Address,
+        authorization: Option<Authorization>,
+        private: bool,
+    ) -> Result<UserOperationHash, tonic::Status>
+    where
+        EntryPointErr: From<<M as Middleware>::Error>,
+    {
+        if !*self.accepting.lock() {
+            return Err(user_operation_status(&SanityCheckError::owned(
+                RESOURCE_UNAVAILABLE_ERROR_CODE,
+                "User operations are not accepted at the moment",
+                None::<bool>,
+            )));
+        }
+        if *self.chain_id_mismatch.lock() {
+            return Err(user_operation_status(&SanityCheckError::owned(
+                RESOURCE_UNAVAILABLE_ERROR_CODE,
+                "Execution client is on another chain",
+                None::<bool>,
+            )));
+        }
+
+        let mempool_id = mempool_id(&entry_point, &self.chain_id);
+
+        {
+            let uopool = self
+                .mempools
+                .get(&mempool_id)
+                .ok_or_else(|| tonic::Status::invalid_argument("entry point not supported"))?;
+            let user_operation_hash = user_operation.hash(&entry_point, &self.chain_id);
+            if matches!(uopool.mempool.get(&user_operation_hash), Ok(Some(_))) {
+                return Err(error_status(
+                    tonic::Code::AlreadyExists,
+                    &SanityCheckError::owned(
+                        SANITY_CHECK_ERROR_CODE,
+                        format!("User operation {user_operation_hash:?} is already in the mempool"),
+                        None::<bool>,
+                    ),
+                ));
+            }
+            if let Some(ref authorization) = authorization {
+                uopool
+                    .verify_authorization(&user_operation, authorization)
+                    .await
+                    .map_err(|error| user_operation_status(&error))?;
+            }
+        }
+
+        // user operations with nonces ahead of the entry point nonce are queued until the preceding nonces are included
+        let nonce_gap = {
+            let uopool = self
+                .mempools
+                .get(&mempool_id)
+                .ok_or_else(|| tonic::Status::invalid_argument("entry point not supported"))?;
+            let nonce = uopool
+                .entry_point
+                .get_nonce(&user_operation.sender, user_operation.nonce >> 64)
+                .await
+                .map_err(|e| {
+                    tonic::Status::internal(format!("Getting the entry point nonce error: {e:?}"))
+                })?;
+            user_operation.nonce.saturating_sub(nonce)
+        };
+        if nonce_gap > self.max_queued_nonce_gap {
+            return Err(user_operation_status(&SanityCheckError::owned(
+                SANITY_CHECK_ERROR_CODE,
+                format!("Nonce is too far ahead of the sender's nonce (by {nonce_gap})"),
+                None::<bool>,
+            )));
+        }
+        if !nonce_gap.is_zero() {
+            let verification_result = {
+                let _permit = self.simulation_permit().await;
+                let uopool = self
+                    .mempools
+                    .get(&mempool_id)
+                    .ok_or_else(|| tonic::Status::invalid_argument("entry point not supported"))?;
+                self.within_budget(uopool.verify_queued_user_operation(&user_operation))
+                    .await
+            };
+            verification_result.map_err(|error| user_operation_status(&error))?;
+
+            let mut uopool = self
+                .mempools
+                .get_mut(&mempool_id)
+                .ok_or_else(|| tonic::Status::invalid_argument("entry point not supported"))?;
+            let sender = user_operation.sender;
+            let user_operation_hash =
+                uopool
+                    .queue_user_operation(user_operation)
+                    .map_err(|error| {
+                        error_status(
+                            tonic::Code::ResourceExhausted,
+                            &SanityCheckError::owned(
+                                SANITY_CHECK_ERROR_CODE,
+                                format!("Queueing the user operation failed: {error}"),
+                                None::<bool>,
+                            ),
+                        )
+                    })?;
+            if let Some(authorization) = authorization {
+                uopool.set_authorization(sender, authorization);
+            }
+            if private {
+                uopool.set_private(user_operation_hash);
+            }
+            trace!("User operation {user_operation_hash:?} with future nonce is queued");
+            return Ok(user_operation_hash);
+        }
+
+        let verification_result = {
+            let _permit = self.simulation_permit().await;
+            let uopool = self
+                .mempools
+                .get(&mempool_id)
+                .ok_or_else(|| tonic::Status::invalid_argument("entry point not supported"))?;
+            self.within_budget(
+                uopool.verify_user_operation(&user_operation, authorization.as_ref()),
+            )
+            .await
+        };
+
+        let verification_result =
+            verification_result.map_err(|error| user_operation_status(&error))?;
+
+        let mut uopool = self
+            .mempools
+            .get_mut(&mempool_id)
+            .ok_or_else(|| tonic::Status::invalid_argument("entry point not supported"))?;
+
+        let sender = user_operation.sender;
+        let user_operation_hash = user_operation.hash(&entry_point, &self.chain_id);
+        // marked before it's added, so that it's never visible as public
+        if private {
+            uopool.set_private(user_operation_hash);
+        }
+        // the mempool (its storage) can't take more user operations
+        let user_operation_hash = uopool
+            .add_verified_user_operation(user_operation, &verification_result)
+            .map_err(|error| {
+                uopool.unset_private(&user_operation_hash);
+                error_status(
+                    tonic::Code::ResourceExhausted,
+                    &SanityCheckError::owned(
+                        SANITY_CHECK_ERROR_CODE,
+                        format!("Adding the user operation to the mempool failed: {error}"),
+                        None::<bool>,
+                    ),
+                )
+            })?;
+        match authorization {
+            Some(authorization) => uopool.set_authorization(sender, authorization),
+            // the replacement without the authorization was verified without the delegation, so it doesn't need it
+            None if verification_result
+                .sanity_check_result
+                .user_operation_hash
+                .is_some() =>
+            {
+                uopool.unset_authorization(&sender)
+            }
+            None => {}
+        }
+
+        Ok(user_operation_hash)
+    }
+
+    fn debug_disabled() -> tonic::Status {
+        tonic::Status::permission_denied("Debug methods are disabled (enable with --debug-rpc)")
+    }
+
+    pub async fn find_user_operation_event(
+        &self,
+        user_operation_hash: H256,
+    ) -> anyhow::Result<Option<(UserOperationEventFilter, LogMeta)>> {
+        let mut event: Option<(UserOperationEventFilter, LogMeta)> = None;
+        for pool in self.mempools.iter() {
+            if let Some(res) = pool
+                .get_user_operation_event_meta(user_operation_hash)
+                .await?
+            {
+                event = Some(res);
+                break;
+            }
+        }
+        Ok(event)
+    }
+}
+
+/// Response of the user operation that wasn't added to the mempool, with the error of the status
+fn not_added(status: &tonic::Status) -> serde_json::Result<AddResponse> {
+    let error = user_operation_error(status).unwrap_or_else(|| {
+        SanityCheckError::owned(SANITY_CHECK_ERROR_CODE, status.message(), None::<bool>)
+    });
+    let mut res = AddResponse::default();
+    res.set_result(AddResult::NotAdded);
+    res.data = serde_json::to_string(&error)?;
+    Ok(res)
+}
+
+/// Items of the page of the debug dumps (all the remaining items if the limit is 0)
+fn page<T>(items: Vec<T>, offset: u64, limit: u64) -> impl Iterator<Item = T> {
+    let limit = if limit == 0 {
+        usize::MAX
+    } else {
+        limit as usize
+    };
+    items.into_iter().skip(offset as usize).take(limit)
+}
+
+/// Logs emitted during the execution of the user operation (between the previous user operation's event and its own
+/// `UserOperationEvent`) and the revert reason of the execution, if it reverted
+fn user_operation_logs(
+    logs: &[Log],
+    entry_point: &Address,
+    user_operation_hash: &H256,
+) -> (Vec<Log>, String) {
+    let mut start = 0;
+    let mut reason = String::new();
+    for (index, log) in logs.iter().enumerate() {
+        if log.address != *entry_point {
+            continue;
+        }
+        match EntryPointAPIEvents::decode_log(&log.clone().into()) {
+            Ok(EntryPointAPIEvents::BeforeExecutionFilter(_)) => start = index + 1,
+            Ok(EntryPointAPIEvents::UserOperationRevertReasonFilter(event))
+                if H256::from(event.user_op_hash) == *user_operation_hash =>
+            {
+                reason = decode_revert_reason(&event.revert_reason);
+            }
+            Ok(EntryPointAPIEvents::UserOperationEventFilter(event)) => {
+                if H256::from(event.user_op_hash) == *user_operation_hash {
+                    return (logs[start..index].to_vec(), reason);
+                }
+                start = index + 1;
+            }
+            _ => (),
+        }
+    }
+    (vec![], reason)
+}
+
+/// Decodes `Error(string)` revert data, other revert data is returned hex encoded
+fn decode_revert_reason(revert_reason: &Bytes) -> String {
+    if revert_reason.len() >= 4 && revert_reason[..4] == REVERT_ERROR_SELECTOR {
+        if let Ok(message) = String::decode(&revert_reason[4..]) {
+            return message;
+        }
+    }
+    format!("{revert_reason}")
+}
+
+#[async_trait]
+impl<M: Middleware + 'static> uo_pool_server::UoPool for UoPoolService<M>
+where
+    EntryPointErr: From<<M as Middleware>::Error>,
+{
+    async fn add(
+        &self,
+        request: tonic::Request<AddRequest>,
+    ) -> Result<Response<AddResponse>, tonic::Status> {
+        let span = info_span!(
+            "add",
+            submission_id = submission_id(request.metadata()).unwrap_or_default()
+        );
+        Ok(Response::new(
+            self.add_user_operation(request.into_inner())
+                .instrument(span)
+                .await?,
+        ))
+    }
+
+    async fn add_user_operations(
+        &self,
+        request: tonic::Request<AddUserOperationsRequest>,
+    ) -> Result<Response<AddUserOperationsResponse>, tonic::Status> {
+        let requests = request.into_inner().requests;
+        if requests.len() > MAX_BATCH_SIZE {
+            return Err(tonic::Status::invalid_argument(format!(
+                "batch exceeds the limit of {MAX_BATCH_SIZE} user operations"
+            )));
+        }
+        trace!(
+            "Receive grpc request to add a batch of {} user operations",
+            requests.len()
+        );
+
+        // each user operation is verified in its own task (the verification holds the mempool while waiting for the
+        // execution client), at most MAX_CONCURRENT_VERIFICATIONS at once
+        let permits = Arc::new(Semaphore::new(MAX_CONCURRENT_VERIFICATIONS));
+        let tasks: Vec<_> = requests
+            .into_iter()
+            .map(|request| {
+                let service = self.clone();
+                let permits = permits.clone();
+                tokio::spawn(async move {
+                    let _permit = permits.acquire_owned().await;
+                    service.add_user_operation(request).await
+                })
+            })
+            .collect();
+
+        let mut responses = Vec::with_capacity(tasks.len());
+        for task in tasks {
+            let response = match task.await {
+                Ok(Ok(response)) => Ok(response),
+                // the errors fail only their own user operations
+                Ok(Err(status)) => not_added(&status),
+                Err(error) => not_added(&tonic::Status::internal(format!(
+                    "Verification of the user operation failed: {error}"
+                ))),
+            }
+            .map_err(|_| tonic::Status::internal("error adding user operations"))?;
+            responses.push(response);
+        }
+
+        Ok(Response::new(AddUserOperationsResponse { responses }))
+    }
+
     async fn remove(
         &self,
         request: tonic::Request<RemoveRequest>,
@@ -245,9 +1010,9 @@ where
     ) -> Result<Response<GetSupportedEntryPointsResponse>, tonic::Status> {
         Ok(tonic::Response::new(GetSupportedEntryPointsResponse {
             eps: self
-                .mempools
+                .entry_points
                 .iter()
-                .map(|mempool| mempool.value().entry_point.address().into())
+                .map(|entry_point| (*entry_point).into())
                 .collect(),
         }))
     }
@@ -256,91 +1021,11 @@ where
         &self,
         request: tonic::Request<EstimateUserOperationGasRequest>,
     ) -> Result<Response<EstimateUserOperationGasResponse>, tonic::Status> {
-        let req = request.into_inner();
-        let mut res = EstimateUserOperationGasResponse::default();
-
-        if let EstimateUserOperationGasRequest {
-            uo: Some(user_operation),
-            ep: Some(entry_point),
-        } = req
-        {
-            let user_operation: UserOperation = user_operation
-                .try_into()
-                .map_err(|_| tonic::Status::invalid_argument("invalid user operation"))?;
-            let entry_point: Address = entry_point
-                .try_into()
-                .map_err(|_| tonic::Status::invalid_argument("invalid entry point"))?;
-
-            let mempool_id = mempool_id(&entry_point, &self.chain_id);
-
-            let uopool = self
-                .mempools
-                .get(&mempool_id)
-                .ok_or_else(|| tonic::Status::invalid_argument("entry point not supported"))?;
-
-            match uopool.simulate_user_operation(&user_operation).await {
-                Ok(simulation_result) => {
-                    let pre_verification_gas =
-                        Overhead::default().calculate_pre_verification_gas(&user_operation);
-
-                    let verification_gas_limit = match simulation_result.simulate_validation_result
-                    {
-                        SimulateValidationResult::ValidationResult(validation_result) => {
-                            validation_result.return_info.0
-                        }
-                        SimulateValidationResult::ValidationResultWithAggregation(
-                            validation_result_with_aggregation,
-                        ) => validation_result_with_aggregation.return_info.0,
-                    };
-
-                    match uopool
-                        .entry_point
-                        .estimate_call_gas(user_operation.clone())
-                        .await
-                    {
-                        Ok(call_gas_limit) => {
-                            res.set_result(EstimateUserOperationGasResult::Estimated);
-                            res.data = serde_json::to_string(&UserOperationGasEstimation {
-                                pre_verification_gas,
-                                verification_gas_limit,
-                                call_gas_limit,
-                            })
-                            .map_err(|_| {
-                                tonic::Status::internal("error estimating user operation gas")
-                            })?;
-                        }
-                        Err(error) => {
-                            res.set_result(EstimateUserOperationGasResult::NotEstimated);
-                            res.data = serde_json::to_string(&SimulationError::from(match error {
-                                EntryPointErr::JsonRpcError(err) => {
-                                    SimulateValidationError::UserOperationExecution {
-                                        message: err.message,
-                                    }
-                                }
-                                _ => SimulateValidationError::UnknownError {
-                                    error: format!("{error:?}"),
-                                },
-                            }))
-                            .map_err(|_| {
-                                tonic::Status::internal("error estimating user operation gas")
-                            })?;
-                        }
-                    }
-                }
-                Err(error) => {
-                    res.set_result(EstimateUserOperationGasResult::NotEstimated);
-                    res.data =
-                        serde_json::to_string(&SimulationError::from(error)).map_err(|_| {
-                            tonic::Status::internal("error estimating user operation gas")
-                        })?;
-                }
-            }
-
-            return Ok(tonic::Response::new(res));
-        }
-
-        Err(tonic::Status::invalid_argument("missing user operation"))
-    }
+        Ok(Response::new(
+            self.within_limits(self.estimate_user_operation(request.into_inner()))
+                .await?,
+        ))
+    }
 
     async fn get_sorted_user_operations(
         &self,
@@ -349,6 +1034,8 @@ where
         let req = request.into_inner();
         if let GetSortedRequest {
             entry_point: Some(entry_point),
+            max_gas,
+            max_user_operations,
         } = req
         {
             let entry_point: Address = entry_point
@@ -357,14 +1044,15 @@ where
 
             let mempool_id = mempool_id(&entry_point, &self.chain_id);
 
-            let uos = {
+            let (uos, max_gas) = {
                 let uopool = self
                     .mempools
                     .get(&mempool_id)
                     .ok_or_else(|| tonic::Status::invalid_argument("entry point not supported"))?;
-                uopool.mempool.get_sorted().map_err(|e| {
+                let uos = uopool.mempool.get_sorted().map_err(|e| {
                     tonic::Status::internal(format!("Get sorted uos internal error: {e:?}"))
-                })?
+                })?;
+                (uos, max_gas.map_or(uopool.max_verification_gas, Into::into))
             };
 
             let remove_user_op = |uo: &UserOperation| -> Result<(), tonic::Status> {
@@ -382,11 +1070,17 @@ where
             };
 
             let mut valid_user_operations = vec![];
+            let mut aggregated_user_operations: HashMap<Address, Vec<UserOperation>> =
+                HashMap::new();
             let mut senders: HashSet<Address> = HashSet::new();
             let mut total_gas = U256::zero();
             let mut paymaster_deposit: HashMap<Address, U256> = HashMap::new();
             let mut staked_entity_count: HashMap<Address, u64> = HashMap::new();
             for uo in uos.iter() {
+                // a single user operation of each sender is included
+                if max_user_operations > 0 && senders.len() as u64 >= max_user_operations {
+                    break;
+                }
                 if senders.contains(&uo.sender) {
                     continue;
                 }
@@ -427,93 +1121,117 @@ where
                     _ => (),
                 };
 
-                let (simulation_result, max_verification_gas) = {
+                let simulation_result = {
                     let uopool = self.mempools.get(&mempool_id).ok_or_else(|| {
                         tonic::Status::invalid_argument("entry point not supported")
                     })?;
-                    (
-                        uopool.simulate_user_operation(uo).await,
-                        uopool.max_verification_gas,
-                    )
+                    uopool.simulate_user_operation(uo, None).await
                 };
 
-                match simulation_result {
-                    Ok(simulation_result) => {
-                        match simulation_result.simulate_validation_result {
-                            SimulateValidationResult::ValidationResult(res) => {
-                                // TODO
-                                // it would be better to use estimate_gas instead of call_gas_limit
-                                // The result of call_gas_limit is usesally higher and less user op would be included
-                                let user_op_gas_cost =
-                                    res.return_info.0.saturating_add(uo.call_gas_limit);
-                                let new_total_gas = total_gas.saturating_add(user_op_gas_cost);
-                                if new_total_gas.gt(&max_verification_gas) {
-                                    break;
-                                }
-                                if let Some(paymaster) = paymaster_opt {
-                                    let balance = match paymaster_deposit.get(&paymaster) {
-                                        Some(n) => Ok(n.to_owned()),
-                                        None => {
-                                            let uopool = self
-                                                .mempools
-                                                .get(&mempool_id)
-                                                .ok_or_else(|| {
-                                                    tonic::Status::invalid_argument(
-                                                        "entry point not supported",
-                                                    )
-                                                })?;
-                                            uopool
-                                            .eth_provider
-                                            .get_balance(paymaster, None)
-                                            .await
-                                            .map_err(|e| {
-                                                tonic::Status::internal(
-                                                    format!("Could not get paymaster {paymaster:?} balance because of {e:?}")
-                                                )
-                                            })
-                                        }
-                                    }?;
-
-                                    if balance.lt(&res.return_info.1) {
-                                        continue;
-                                    }
-
-                                    let update_balance = balance.saturating_sub(res.return_info.1);
-                                    staked_entity_count
-                                        .entry(paymaster)
-                                        .and_modify(|c| *c += 1)
-                                        .or_insert(1);
-                                    paymaster_deposit.insert(paymaster, update_balance);
-                                };
-                                if let Some(factory) = factory_opt {
-                                    staked_entity_count
-                                        .entry(factory)
-                                        .and_modify(|c| *c += 1)
-                                        .or_insert(1);
-                                };
-                                total_gas = new_total_gas
-                            }
-                            SimulateValidationResult::ValidationResultWithAggregation(_) => {
-                                todo!("Aggregation is not supported now.")
-                            }
+                let (return_info, aggregator) = match simulation_result {
+                    Ok(simulation_result) => match simulation_result.simulate_validation_result {
+                        SimulateValidationResult::ValidationResult(res) => (res.return_info, None),
+                        SimulateValidationResult::ValidationResultWithAggregation(res) => {
+                            (res.return_info, Some(res.aggregator_info.0))
                         }
-                    }
+                    },
                     Err(e) => {
+                        if let Some(uopool) = self.mempools.get(&mempool_id) {
+                            uopool.record_simulation_failure(&e);
+                        }
                         debug!("Failed in 2nd simulation: {e:?} ");
                         remove_user_op(uo)?;
                         continue;
                     }
+                };
+
+                // TODO
+                // it would be better to use estimate_gas instead of call_gas_limit
+                // The result of call_gas_limit is usesally higher and less user op would be included
+                let user_op_gas_cost = return_info.0.saturating_add(uo.call_gas_limit);
+                let new_total_gas = total_gas.saturating_add(user_op_gas_cost);
+                if new_total_gas.gt(&max_gas) {
+                    break;
                 }
+                if let Some(paymaster) = paymaster_opt {
+                    let balance = match paymaster_deposit.get(&paymaster) {
+                        Some(n) => Ok(n.to_owned()),
+                        None => {
+                            let uopool = self.mempools.get(&mempool_id).ok_or_else(|| {
+                                tonic::Status::invalid_argument("entry point not supported")
+                            })?;
+                            uopool
+                                .eth_provider
+                                .get_balance(paymaster, None)
+                                .await
+                                .map_err(|e| {
+                                    tonic::Status::internal(format!(
+                                        "Could not get paymaster {paymaster:?} balance because of {e:?}"
+                                    ))
+                                })
+                        }
+                    }?;
 
-                valid_user_operations.push(uo.to_owned());
+                    if balance.lt(&return_info.1) {
+                        continue;
+                    }
+
+                    let update_balance = balance.saturating_sub(return_info.1);
+                    staked_entity_count
+                        .entry(paymaster)
+                        .and_modify(|c| *c += 1)
+                        .or_insert(1);
+                    paymaster_deposit.insert(paymaster, update_balance);
+                };
+                if let Some(factory) = factory_opt {
+                    staked_entity_count
+                        .entry(factory)
+                        .and_modify(|c| *c += 1)
+                        .or_insert(1);
+                };
+                total_gas = new_total_gas;
+
+                match aggregator {
+                    Some(aggregator) => aggregated_user_operations
+                        .entry(aggregator)
+                        .or_default()
+                        .push(uo.to_owned()),
+                    None => valid_user_operations.push(uo.to_owned()),
+                }
                 senders.insert(uo.sender);
             }
 
+            let authorizations = {
+                let uopool = self
+                    .mempools
+                    .get(&mempool_id)
+                    .ok_or_else(|| tonic::Status::invalid_argument("entry point not supported"))?;
+                senders
+                    .iter()
+                    .filter_map(|sender| {
+                        uopool
+                            .get_authorization(sender)
+                            .map(|authorization| SenderAuthorization {
+                                sender: Some((*sender).into()),
+                                authorization: Some(authorization.into()),
+                            })
+                    })
+                    .collect()
+            };
+
             let response = GetSortedResponse {
                 user_operations: valid_user_operations
                     .into_iter()
                     .map(|u| u.into())
                     .collect(),
+                user_operations_per_aggregator: aggregated_user_operations
+                    .into_iter()
+                    .map(|(aggregator, uos)| UserOperationsPerAggregator {
+                        aggregator: Some(aggregator.into()),
+                        user_operations: uos.into_iter().map(|u| u.into()).collect(),
+                    })
+                    .collect(),
+                authorizations,
             };
             return Ok(tonic::Response::new(response));
         } else {
@@ -556,39 +1274,59 @@ where
             .ok_or_else(|| tonic::Status::invalid_argument("entry point not supported"))?;
 
         let events_filter = uopool.entry_point.events().from_block(last_block);
-        let events = events_filter.query().await.map_err(|e| {
+        let events = events_filter.query_with_meta().await.map_err(|e| {
             tonic::Status::internal(format!("Getting event logs with error: {e:?}"))
         })?;
-        for event in events {
-            match event {
-                EntryPointAPIEvents::UserOperationEventFilter(user_operation_event) => {
-                    uopool
-                        .remove_user_operation(&user_operation_event.user_op_hash.into())
-                        .unwrap_or_else(|| {
-                            // This could be possible when other bundler submit the user operations
-                            trace!(
-                                "Unable to remove user operation {:?} from mempool {:?}",
-                                user_operation_event.user_op_hash,
-                                mempool_id
-                            )
-                        });
-                    uopool.include_address(user_operation_event.sender);
-                    uopool.include_address(user_operation_event.paymaster);
-                    // TODO: include event aggregator
-                }
-                EntryPointAPIEvents::AccountDeployedFilter(account_deploy_event) => {
-                    uopool.include_address(account_deploy_event.factory);
-                }
-                EntryPointAPIEvents::SignatureAggregatorChangedFilter(_) => {
-                    warn!("Aggregate signature is not supported currently");
-                }
-                _ => (),
-            }
+
+        for (transaction_hash, events) in events_per_transaction(events) {
+            uopool.handle_events(events, transaction_hash);
         }
 
         Ok(Response::new(()))
     }
 
+    async fn handle_bundle_transaction(
+        &self,
+        request: tonic::Request<HandleBundleTransactionRequest>,
+    ) -> Result<Response<()>, tonic::Status> {
+        let req = request.into_inner();
+        let entry_point: Address = req
+            .ep
+            .ok_or(tonic::Status::invalid_argument("entry point is missing"))?
+            .into();
+        let transaction_hash: H256 = req
+            .tx_hash
+            .ok_or(tonic::Status::invalid_argument(
+                "transaction hash is missing",
+            ))?
+            .into();
+
+        let transaction_receipt = self
+            .eth_provider
+            .get_transaction_receipt(transaction_hash)
+            .await
+            .map_err(|e| {
+                tonic::Status::internal(format!("Getting transaction receipt with error: {e:?}"))
+            })?
+            .ok_or_else(|| tonic::Status::not_found("Transaction receipt not found"))?;
+
+        let events: Vec<EntryPointAPIEvents> = transaction_receipt
+            .logs
+            .into_iter()
+            .filter(|log| log.address == entry_point)
+            .filter_map(|log| EntryPointAPIEvents::decode_log(&log.into()).ok())
+            .collect();
+
+        let mempool_id = mempool_id(&entry_point, &self.chain_id);
+        let mut uopool = self
+            .mempools
+            .get_mut(&mempool_id)
+            .ok_or_else(|| tonic::Status::invalid_argument("entry point not supported"))?;
+        uopool.handle_events(events, transaction_hash);
+
+        Ok(Response::new(()))
+    }
+
     async fn get_user_operation_by_hash(
         &self,
         request: tonic::Request<UserOperationHashRequest>,
@@ -641,7 +1379,21 @@ where
                     Err(tonic::Status::not_found("User operation not found"))
                 }
             }
-            None => Err(tonic::Status::not_found("User operation not found")),
+            // user operations that are not included yet are returned without the block and transaction
+            None => self
+                .mempools
+                .iter()
+                .find_map(|uopool| {
+                    uopool
+                        .get_pending_user_operation(&user_operation_hash.into())
+                        .map(|user_operation| GetUserOperationByHashResponse {
+                            user_operation: Some(user_operation.into()),
+                            entry_point: Some(uopool.entry_point.address().into()),
+                            ..Default::default()
+                        })
+                })
+                .map(Response::new)
+                .ok_or_else(|| tonic::Status::not_found("User operation not found")),
         }
     }
 
@@ -651,7 +1403,6 @@ where
     ) -> Result<Response<GetUserOperationReceiptResponse>, tonic::Status> {
         let req = request.into_inner();
         let user_operation_hash: H256 = req
-            .clone()
             .hash
             .ok_or(tonic::Status::invalid_argument(
                 "User operation hash is missing",
@@ -679,28 +1430,26 @@ where
                         ))
                     })?
                 {
-                    let user_operation = self
-                        .get_user_operation_by_hash(tonic::Request::new(req))
-                        .await?;
+                    let (logs, reason) = user_operation_logs(
+                        &transaction_receipt.logs,
+                        &log_meta.address,
+                        &user_operation_hash,
+                    );
 
                     let response = Response::new(GetUserOperationReceiptResponse {
                         user_operation_hash: Some(user_operation_hash.into()),
+                        entry_point: Some(log_meta.address.into()),
                         sender: Some(event.sender.into()),
                         nonce: Some(event.nonce.into()),
                         actual_gas_cost: Some(event.actual_gas_cost.into()),
                         actual_gas_used: Some(event.actual_gas_used.into()),
                         success: event.success,
-                        transaction_receipt: Some(transaction_receipt.clone().into()),
-                        logs: transaction_receipt
-                            .logs
-                            .into_iter()
-                            .map(|l| l.into())
-                            .collect(),
-                        paymaster: user_operation
-                            .into_inner()
-                            .user_operation
-                            .and_then(|u| get_addr(u.paymaster_and_data.as_ref()))
+                        transaction_receipt: Some(transaction_receipt.into()),
+                        logs: logs.into_iter().map(|l| l.into()).collect(),
+                        paymaster: Some(event.paymaster)
+                            .filter(|paymaster| !paymaster.is_zero())
                             .map(|p| p.into()),
+                        reason,
                     });
                     Ok(response)
                 } else {
@@ -711,10 +1460,39 @@ where
         }
     }
 
+    async fn handle_failed_op(
+        &self,
+        request: tonic::Request<HandleFailedOpRequest>,
+    ) -> Result<Response<()>, tonic::Status> {
+        let req = request.into_inner();
+        let user_operation: UserOperation = req
+            .uo
+            .ok_or(tonic::Status::invalid_argument("user operation is missing"))?
+            .into();
+        let entry_point: Address = req
+            .ep
+            .ok_or(tonic::Status::invalid_argument("entry point is missing"))?
+            .try_into()
+            .map_err(|_| tonic::Status::invalid_argument("invalid entry point"))?;
+        let mempool_id = mempool_id(&entry_point, &self.chain_id);
+
+        let mut uopool = self
+            .mempools
+            .get_mut(&mempool_id)
+            .ok_or_else(|| tonic::Status::invalid_argument("entry point not supported"))?;
+        uopool.handle_failed_user_operation(&user_operation, &req.reason);
+
+        Ok(Response::new(()))
+    }
+
     async fn get_all(
         &self,
         request: tonic::Request<GetAllRequest>,
     ) -> Result<Response<GetAllResponse>, tonic::Status> {
+        if !self.debug {
+            return Err(Self::debug_disabled());
+        }
+
         let req = request.into_inner();
         let mut res = GetAllResponse::default();
 
@@ -730,12 +1508,18 @@ where
                 .get(&mempool_id)
                 .ok_or_else(|| tonic::Status::invalid_argument("entry point not supported"))?;
 
-            res.result = GetAllResult::GotAll as i32;
-            res.uos = uopool
+            let mut uos: Vec<UserOperation> = uopool
                 .mempool
                 .get_all()
-                .iter()
-                .map(|uo| uo.clone().into())
+                .into_iter()
+                .filter(|uo| !uopool.is_private(&uo.hash(&entry_point, &self.chain_id)))
+                .collect();
+            uos.sort_by_key(|uo| (uo.sender, uo.nonce));
+
+            res.result = GetAllResult::GotAll as i32;
+            res.total = uos.len() as u64;
+            res.uos = page(uos, req.offset, req.limit)
+                .map(|uo| uo.into())
                 .collect();
             trace!("Get all user operations in the mempool: {:?}", res.uos);
 
@@ -749,9 +1533,16 @@ where
         &self,
         _request: tonic::Request<()>,
     ) -> Result<Response<ClearResponse>, tonic::Status> {
+        if !self.debug {
+            return Err(Self::debug_disabled());
+        }
+
         self.mempools.iter_mut().for_each(|mut mempool| {
             let mempool = mempool.value_mut();
             mempool.mempool.clear();
+            mempool.clear_queued_user_operations();
+            mempool.clear_authorizations();
+            mempool.clear_private();
             mempool.reputation.clear()
         });
 
@@ -764,6 +1555,10 @@ where
         &self,
         request: tonic::Request<GetAllReputationRequest>,
     ) -> Result<Response<GetAllReputationResponse>, tonic::Status> {
+        if !self.debug {
+            return Err(Self::debug_disabled());
+        }
+
         let req = request.into_inner();
         let mut res = GetAllReputationResponse::default();
 
@@ -779,12 +1574,13 @@ where
                 .get(&mempool_id)
                 .ok_or_else(|| tonic::Status::invalid_argument("entry point not supported"))?;
 
+            let mut entries = uopool.reputation.get_all();
+            entries.sort_by_key(|entry| entry.address);
+
             res.result = GetAllReputationResult::GotAllReputation as i32;
-            res.res = uopool
-                .reputation
-                .get_all()
-                .iter()
-                .map(|re| (*re).into())
+            res.total = entries.len() as u64;
+            res.res = page(entries, req.offset, req.limit)
+                .map(|entry| entry.into())
                 .collect();
 
             return Ok(tonic::Response::new(res));
@@ -797,6 +1593,10 @@ where
         &self,
         request: tonic::Request<SetReputationRequest>,
     ) -> Result<Response<SetReputationResponse>, tonic::Status> {
+        if !self.debug {
+            return Err(Self::debug_disabled());
+        }
+
         let req = request.into_inner();
         let mut res = SetReputationResponse::default();
 
@@ -822,74 +1622,1290 @@ where
 
         Err(tonic::Status::invalid_argument("missing entry point"))
     }
+
+    async fn set_min_priority_fee(
+        &self,
+        request: tonic::Request<SetMinPriorityFeeRequest>,
+    ) -> Result<Response<()>, tonic::Status> {
+        let min_priority_fee_per_gas: U256 = request
+            .into_inner()
+            .min_priority_fee_per_gas
+            .ok_or_else(|| tonic::Status::invalid_argument("missing min priority fee per gas"))?
+            .into();
+
+        info!("Setting the min priority fee per gas to {min_priority_fee_per_gas}");
+        for mut uopool in self.mempools.iter_mut() {
+            if !self
+                .fixed_min_priority_fees
+                .contains(&uopool.entry_point.address())
+            {
+                uopool.min_priority_fee_per_gas = min_priority_fee_per_gas;
+            }
+        }
+
+        Ok(Response::new(()))
+    }
+
+    async fn set_accepting(
+        &self,
+        request: tonic::Request<SetAcceptingRequest>,
+    ) -> Result<Response<()>, tonic::Status> {
+        let accepting = request.into_inner().accepting;
+
+        info!(
+            "{} accepting user operations",
+            if accepting { "Resuming" } else { "Pausing" }
+        );
+        *self.accepting.lock() = accepting;
+
+        Ok(Response::new(()))
+    }
+
+    async fn set_throttling(
+        &self,
+        request: tonic::Request<SetThrottlingRequest>,
+    ) -> Result<Response<()>, tonic::Status> {
+        let req = request.into_inner();
+        if req.min_inclusion_denominator == 0 {
+            return Err(tonic::Status::invalid_argument(
+                "min inclusion denominator must be positive",
+            ));
+        }
+
+        info!(
+            "Setting the throttling parameters: min inclusion denominator {}, throttling slack {}, ban slack {}",
+            req.min_inclusion_denominator, req.throttling_slack, req.ban_slack
+        );
+        for mut uopool in self.mempools.iter_mut() {
+            uopool.reputation.set_throttling(
+                req.min_inclusion_denominator,
+                req.throttling_slack,
+                req.ban_slack,
+            );
+        }
+
+        Ok(Response::new(()))
+    }
+
+    async fn set_reputation_lists(
+        &self,
+        request: tonic::Request<SetReputationListsRequest>,
+    ) -> Result<Response<()>, tonic::Status> {
+        let req = request.into_inner();
+        let whitelist: Vec<Address> = req.whitelist.into_iter().map(Into::into).collect();
+        let blacklist: Vec<Address> = req.blacklist.into_iter().map(Into::into).collect();
+
+        info!("Setting the whitelisted entities {whitelist:?} and the blacklisted entities {blacklist:?}");
+        for mut uopool in self.mempools.iter_mut() {
+            uopool.reputation.set_lists(&whitelist, &blacklist);
+        }
+
+        Ok(Response::new(()))
+    }
+
+    async fn get_peers(
+        &self,
+        _request: tonic::Request<()>,
+    ) -> Result<Response<GetPeersResponse>, tonic::Status> {
+        let network = self
+            .network
+            .as_ref()
+            .ok_or_else(|| tonic::Status::failed_precondition("P2P network is disabled"))?;
+
+        Ok(Response::new(GetPeersResponse {
+            peers: network.peers().await.into_iter().map(Into::into).collect(),
+        }))
+    }
+
+    async fn get_rejected_user_operations(
+        &self,
+        request: tonic::Request<GetRejectedUserOperationsRequest>,
+    ) -> Result<Response<GetRejectedUserOperationsResponse>, tonic::Status> {
+        let req = request.into_inner();
+        let user_operation_hash = req.uo_hash.map(Into::into);
+        let sender = req.sender.map(Into::into);
+
+        Ok(Response::new(GetRejectedUserOperationsResponse {
+            user_operations: self
+                .rejections
+                .lock()
+                .get(user_operation_hash, sender)
+                .into_iter()
+                .map(Into::into)
+                .collect(),
+        }))
+    }
+
+    async fn get_entry_point_health(
+        &self,
+        _request: tonic::Request<()>,
+    ) -> Result<Response<GetEntryPointHealthResponse>, tonic::Status> {
+        let rejections = self.rejections.lock().get(None, None);
+        let mut entry_points = vec![];
+        for entry_point in self.entry_points.iter() {
+            let deployed = !self
+                .eth_provider
+                .get_code(*entry_point, None)
+                .await
+                .map_err(|error| tonic::Status::unavailable(error.to_string()))?
+                .is_empty();
+
+            let mempool_id = mempool_id(entry_point, &self.chain_id);
+            let uopool = self
+                .mempools
+                .get(&mempool_id)
+                .ok_or_else(|| tonic::Status::internal("missing mempool of the entry point"))?;
+            let reputation = uopool.reputation.get_all();
+            let entities_with_status = |status: ReputationStatus| {
+                reputation
+                    .iter()
+                    .filter(|entry| entry.status == status)
+                    .count() as u64
+            };
+
+            entry_points.push(
+                aa_bundler_primitives::EntryPointHealth {
+                    entry_point: *entry_point,
+                    deployed,
+                    mempool_size: uopool.mempool.get_all().len() as u64,
+                    throttled_entities: entities_with_status(ReputationStatus::THROTTLED),
+                    banned_entities: entities_with_status(ReputationStatus::BANNED),
+                    rejected_user_operations: rejections
+                        .iter()
+                        .filter(|rejected| rejected.entry_point == *entry_point)
+                        .count() as u64,
+                    last_bundle_status: None,
+                    last_bundle_at: None,
+                }
+                .into(),
+            );
+        }
+
+        Ok(Response::new(GetEntryPointHealthResponse { entry_points }))
+    }
+
+    async fn clear_user_operations(
+        &self,
+        request: tonic::Request<ClearUserOperationsRequest>,
+    ) -> Result<Response<ClearUserOperationsResponse>, tonic::Status> {
+        let req = request.into_inner();
+        let entry_point: Option<Address> = req
+            .ep
+            .map(|entry_point| {
+                entry_point
+                    .try_into()
+                    .map_err(|_| tonic::Status::invalid_argument("invalid entry point"))
+            })
+            .transpose()?;
+        let mempool_id = entry_point.map(|entry_point| mempool_id(&entry_point, &self.chain_id));
+        if matches!(mempool_id, Some(mempool_id) if !self.mempools.contains_key(&mempool_id)) {
+            return Err(tonic::Status::invalid_argument("entry point not supported"));
+        }
+        let entity: Option<Address> = req
+            .entity
+            .map(|entity| {
+                entity
+                    .try_into()
+                    .map_err(|_| tonic::Status::invalid_argument("invalid entity"))
+            })
+            .transpose()?;
+
+        let mut removed = 0;
+        for mut uopool in self.mempools.iter_mut() {
+            if mempool_id.map_or(true, |mempool_id| *uopool.key() == mempool_id) {
+                removed += uopool.remove_user_operations_by_entity(entity.as_ref());
+            }
+        }
+        info!(
+            "Removed {removed} user operations (entry point: {entry_point:?}, entity: {entity:?})"
+        );
+
+        Ok(Response::new(ClearUserOperationsResponse {
+            removed: removed as u64,
+        }))
+    }
+
+    type SubscribeEventsStream = ReceiverStream<Result<MempoolEvent, tonic::Status>>;
+
+    async fn subscribe_events(
+        &self,
+        request: tonic::Request<SubscribeEventsRequest>,
+    ) -> Result<Response<Self::SubscribeEventsStream>, tonic::Status> {
+        let entry_points = request
+            .into_inner()
+            .eps
+            .into_iter()
+            .map(|entry_point| {
+                entry_point
+                    .try_into()
+                    .map_err(|_| tonic::Status::invalid_argument("invalid entry point"))
+            })
+            .collect::<Result<HashSet<Address>, _>>()?;
+        if let Some(entry_point) = entry_points
+            .iter()
+            .find(|entry_point| !self.entry_points.contains(entry_point))
+        {
+            return Err(tonic::Status::invalid_argument(format!(
+                "entry point {entry_point:?} not supported"
+            )));
+        }
+        let (tx, rx) = mpsc::channel(EVENTS_STREAM_CAPACITY);
+
+        for uopool in self.mempools.iter() {
+            let entry_point = uopool.entry_point.address();
+            if entry_points.is_empty() || entry_points.contains(&entry_point) {
+                forward_events(entry_point, uopool.subscribe(), tx.clone());
+            }
+        }
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
 }
 
-pub async fn uopool_service_run(
-    opts: UoPoolServiceOpts,
+/// Forwards the events of the mempool to the stream of the subscriber until it disconnects
+fn forward_events(
+    entry_point: Address,
+    mut events: broadcast::Receiver<UoPoolEvent>,
+    tx: mpsc::Sender<Result<MempoolEvent, tonic::Status>>,
+) {
+    tokio::spawn(async move {
+        loop {
+            let event = match events.recv().await {
+                Ok(event) => event,
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("Mempool events subscriber lagged behind, skipped {skipped} events");
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            };
+            if tx
+                .send(Ok(mempool_event(entry_point, event)))
+                .await
+                .is_err()
+            {
+                // subscriber disconnected
+                break;
+            }
+        }
+    });
+}
+
+fn mempool_event(entry_point: Address, event: UoPoolEvent) -> MempoolEvent {
+    match event {
+        UoPoolEvent::NewUserOperation {
+            user_operation_hash,
+            user_operation,
+        } => MempoolEvent {
+            kind: MempoolEventKind::NewUserOperation as i32,
+            ep: Some(entry_point.into()),
+            uo_hash: Some(user_operation_hash.0.into()),
+            sender: Some(user_operation.sender.into()),
+            uo: Some((*user_operation).into()),
+            ..Default::default()
+        },
+        UoPoolEvent::UserOperationIncluded {
+            user_operation_hash,
+            sender,
+            transaction_hash,
+            success,
+        } => MempoolEvent {
+            kind: MempoolEventKind::UserOperationIncluded as i32,
+            ep: Some(entry_point.into()),
+            uo_hash: Some(user_operation_hash.0.into()),
+            sender: Some(sender.into()),
+            tx_hash: Some(transaction_hash.into()),
+            success,
+            ..Default::default()
+        },
+    }
+}
+
+/// Deposits of the paymasters of the user operations in the mempool and the queue, read in a single multicall (none
+/// if they can't be fetched)
+async fn get_paymaster_deposits<M: Middleware + 'static>(
+    mempools: &Arc<DashMap<MempoolId, UserOperationPool<M>>>,
+    mempool_id: &MempoolId,
+) -> HashMap<Address, U256> {
+    let (entry_point, paymasters) = match mempools.get(mempool_id) {
+        Some(uopool) => (
+            EntryPoint::new(uopool.eth_provider.clone(), uopool.entry_point.address()),
+            uopool.get_paymasters().into_iter().collect::<Vec<_>>(),
+        ),
+        None => return HashMap::new(),
+    };
+
+    match entry_point.get_deposit_infos(&paymasters).await {
+        Ok(deposit_infos) => paymasters
+            .into_iter()
+            .zip(deposit_infos)
+            .map(|(paymaster, deposit_info)| (paymaster, U256::from(deposit_info.deposit)))
+            .collect(),
+        Err(e) => {
+            warn!("Failed to get the deposits of the paymasters: {e:?}");
+            HashMap::new()
+        }
+    }
+}
+
+/// Re-checks the user operations of the mempool at the new block (the base fee, the validity and the deposits of
+/// the paymasters), so that the bundles are built only from the user operations that are currently valid
+fn revalidate_user_operations<M: Middleware + 'static>(
+    mempools: &Arc<DashMap<MempoolId, UserOperationPool<M>>>,
+    mempool_id: &MempoolId,
+    base_fee_per_gas: U256,
+    paymaster_deposits: &mut HashMap<Address, U256>,
+) {
+    if let Some(mut uopool) = mempools.get_mut(mempool_id) {
+        let (dropped, queued) =
+            uopool.revalidate_user_operations(base_fee_per_gas, paymaster_deposits);
+        if dropped > 0 || queued > 0 {
+            debug!("Revalidated the mempool: {dropped} user operations expired, {queued} moved to the queue");
+        }
+    }
+}
+
+/// Moves queued user operations whose nonce became the sender's entry point nonce and that can be bundled at the
+/// current block to the mempool (user operations with already used nonces, expired or failing verification are
+/// dropped)
+async fn promote_queued_user_operations<M: Middleware + 'static>(
+    mempools: &Arc<DashMap<MempoolId, UserOperationPool<M>>>,
+    mempool_id: &MempoolId,
+    base_fee_per_gas: U256,
+    paymaster_deposits: &mut HashMap<Address, U256>,
+) {
+    let (queued_user_operations, nonces) = match mempools.get(mempool_id) {
+        Some(uopool) => {
+            let queued_user_operations = uopool.get_queued_user_operations();
+            let senders = queued_user_operations
+                .iter()
+                .map(|user_operation| (user_operation.sender, user_operation.nonce >> 64))
+                .collect::<Vec<_>>();
+            match uopool.entry_point.get_nonces(&senders).await {
+                Ok(nonces) => (queued_user_operations, nonces),
+                Err(e) => {
+                    warn!("Failed to get the entry point nonces: {e:?}");
+                    return;
+                }
+            }
+        }
+        None => return,
+    };
+
+    for (user_operation, nonce) in queued_user_operations.into_iter().zip(nonces) {
+        let verification_result = {
+            let uopool = match mempools.get(mempool_id) {
+                Some(uopool) => uopool,
+                None => return,
+            };
+            match uopool.readiness(&user_operation, base_fee_per_gas, paymaster_deposits) {
+                Readiness::Ready => {}
+                Readiness::Expired => {
+                    drop(uopool);
+                    if let Some(mut uopool) = mempools.get_mut(mempool_id) {
+                        uopool.drop_queued_user_operation(
+                            &user_operation.sender,
+                            &user_operation.nonce,
+                        );
+                    }
+                    trace!("Dropping queued user operation {user_operation:?}, it expired");
+                    continue;
+                }
+                _ => continue,
+            }
+            match user_operation.nonce.cmp(&nonce) {
+                Ordering::Greater => continue,
+                Ordering::Less => None,
+                Ordering::Equal => {
+                    let authorization = uopool.get_authorization(&user_operation.sender);
+                    Some(
+                        uopool
+                            .verify_user_operation(&user_operation, authorization.as_ref())
+                            .await,
+                    )
+                }
+            }
+        };
+
+        let mut uopool = match mempools.get_mut(mempool_id) {
+            Some(uopool) => uopool,
+            None => return,
+        };
+        let (sender, nonce) = (user_operation.sender, user_operation.nonce);
+        match verification_result {
+            Some(Ok(verification_result)) => {
+                uopool.remove_queued_user_operation(&sender, &nonce);
+                let paymaster = get_addr(&user_operation.paymaster_and_data);
+                let prefund = required_prefund(&user_operation);
+                let user_operation_hash =
+                    user_operation.hash(&uopool.entry_point.address(), &uopool.chain_id);
+                match uopool.add_verified_user_operation(user_operation, &verification_result) {
+                    Ok(user_operation_hash) => {
+                        if let Some(deposit) =
+                            paymaster.and_then(|paymaster| paymaster_deposits.get_mut(&paymaster))
+                        {
+                            *deposit = deposit.saturating_sub(prefund);
+                        }
+                        info!("Queued user operation {user_operation_hash:?} moved to the mempool")
+                    }
+                    Err(e) => {
+                        uopool.unset_private(&user_operation_hash);
+                        uopool.release_authorization(&sender);
+                        warn!("Failed to add queued user operation to the mempool: {e:?}")
+                    }
+                }
+            }
+            Some(Err(error)) => {
+                uopool.drop_queued_user_operation(&sender, &nonce);
+                warn!("Queued user operation {user_operation:?} failed verification: {error:?}")
+            }
+            None => {
+                uopool.drop_queued_user_operation(&sender, &nonce);
+                trace!(
+                    "Dropping queued user operation {user_operation:?}, its nonce was already used"
+                )
+            }
+        }
+    }
+}
+
+/// Handles entry point events of every new block, so that user operations included by other bundlers
+/// (from the shared mempool) are removed from the mempools right away, then revalidates the mempools at the block
+fn start_events_watching(
+    supervisor: &Supervisor,
+    mempools: Arc<DashMap<MempoolId, UserOperationPool<EthProvider>>>,
+    eth_provider: Arc<EthProvider>,
     entry_points: Vec<Address>,
-    eth_provider: Arc<Provider<Http>>,
-    max_verification_gas: U256,
+    chain_id: U256,
+) {
+    supervisor.spawn("uopool_events", move || {
+        handle_new_blocks(
+            mempools.clone(),
+            eth_provider.clone(),
+            entry_points.clone(),
+            chain_id,
+        )
+    });
+}
+
+/// Groups the entry point events by the transaction, since the aggregator event applies to the following user
+/// operations of the same transaction
+fn events_per_transaction(
+    events: Vec<(EntryPointAPIEvents, LogMeta)>,
+) -> Vec<(H256, Vec<EntryPointAPIEvents>)> {
+    let mut events_per_transaction: Vec<(H256, Vec<EntryPointAPIEvents>)> = vec![];
+    for (event, log_meta) in events {
+        match events_per_transaction.last_mut() {
+            Some((transaction_hash, events)) if *transaction_hash == log_meta.transaction_hash => {
+                events.push(event)
+            }
+            _ => events_per_transaction.push((log_meta.transaction_hash, vec![event])),
+        }
+    }
+    events_per_transaction
+}
+
+async fn handle_new_blocks(
+    mempools: Arc<DashMap<MempoolId, UserOperationPool<EthProvider>>>,
+    eth_provider: Arc<EthProvider>,
+    entry_points: Vec<Address>,
+    chain_id: U256,
 ) -> Result<()> {
-    let chain_id = eth_provider.get_chainid().await?;
+    let mut blocks = watch_new_blocks(eth_provider.clone(), EVENTS_POLL_INTERVAL);
+    let mut last_block: Option<U64> = None;
+    // last block whose events were handled, per entry point (the range of a failed query is queried again with the
+    // next block)
+    let mut last_scanned_blocks: HashMap<Address, U64> = HashMap::new();
+    while let Some(block_number) = blocks.recv().await {
+        if matches!(last_block, Some(last_block) if block_number <= last_block) {
+            continue;
+        }
+        let base_fee_per_gas = match eth_provider.get_block(block_number).await {
+            Ok(Some(block)) => block.base_fee_per_gas.unwrap_or_default(),
+            Ok(None) => U256::zero(),
+            Err(e) => {
+                warn!("Failed to get the block {block_number}: {e:?}");
+                U256::zero()
+            }
+        };
 
-    tokio::spawn(async move {
-        let mut builder = tonic::transport::Server::builder();
+        for entry_point in entry_points.iter() {
+            let from_block = last_scanned_blocks
+                .get(entry_point)
+                .map_or(block_number, |last_scanned_block| last_scanned_block + 1);
+            let events = match EntryPoint::<EthProvider>::new(eth_provider.clone(), *entry_point)
+                .events()
+                .from_block(from_block)
+                .to_block(block_number)
+                .query_with_meta()
+                .await
+            {
+                Ok(events) => events,
+                Err(e) => {
+                    warn!("Failed to get entry point {entry_point:?} events: {e:?}");
+                    continue;
+                }
+            };
+            last_scanned_blocks.insert(*entry_point, block_number);
+
+            let mempool_id = mempool_id(entry_point, &chain_id);
+            if let Some(mut uopool) = mempools.get_mut(&mempool_id) {
+                for (transaction_hash, events) in events_per_transaction(events) {
+                    uopool.handle_events(events, transaction_hash);
+                }
+            }
+
+            let mut paymaster_deposits = get_paymaster_deposits(&mempools, &mempool_id).await;
+            revalidate_user_operations(
+                &mempools,
+                &mempool_id,
+                base_fee_per_gas,
+                &mut paymaster_deposits,
+            );
+            promote_queued_user_operations(
+                &mempools,
+                &mempool_id,
+                base_fee_per_gas,
+                &mut paymaster_deposits,
+            )
+            .await;
+        }
+
+        last_block = Some(block_number);
+    }
+    Err(format_err!("Watching of the new blocks stopped"))
+}
+
+impl UoPoolServiceOpts {
+    /// TLS config of the uopool gRPC server, if configured
+    pub fn grpc_tls(&self) -> Option<TlsConfig> {
+        Some(
+            TlsConfig::from_paths(
+                self.uopool_grpc_tls_cert.clone(),
+                self.uopool_grpc_tls_key.clone(),
+            )?
+            .with_client_ca(self.uopool_grpc_tls_client_ca.clone()),
+        )
+    }
 
-        let mempools_map = Arc::new(DashMap::<MempoolId, UserOperationPool<Provider<Http>>>::new());
+    /// Builder of the uopool gRPC servers with the configured limits and keepalive. The size of the messages is not
+    /// limited (e.g. of the mempool dumps), since tonic doesn't cap the encoded and decoded messages.
+    fn grpc_server(&self) -> Server {
+        let keepalive_interval = (self.uopool_grpc_keepalive_interval > 0)
+            .then(|| Duration::from_secs(self.uopool_grpc_keepalive_interval));
+        let server = Server::builder()
+            .max_concurrent_streams(self.uopool_grpc_max_concurrent_streams)
+            .http2_keepalive_interval(keepalive_interval)
+            .http2_keepalive_timeout(Some(Duration::from_secs(
+                self.uopool_grpc_keepalive_timeout,
+            )))
+            .tcp_keepalive(keepalive_interval);
+        match self.uopool_grpc_concurrency_limit {
+            Some(limit) => server.concurrency_limit_per_connection(limit),
+            None => server,
+        }
+    }
+}
+
+/// Periodically checks that the execution client is reachable, which the uopool service depends on
+fn start_health_checking(mut health_reporter: HealthReporter, eth_provider: Arc<EthProvider>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(HEALTH_CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            let reachable =
+                tokio::time::timeout(HEALTH_CHECK_INTERVAL, eth_provider.get_block_number())
+                    .await
+                    .map_or(false, |block_number| block_number.is_ok());
+            if !reachable {
+                warn!("Execution client is not reachable");
+            }
+            let status = if reachable {
+                ServingStatus::Serving
+            } else {
+                ServingStatus::NotServing
+            };
+            health_reporter
+                .set_service_status(PROVIDER_SERVICE_NAME, status)
+                .await;
+            // the uopool service is always serving, so the whole server is serving if the execution client is
+            // reachable
+            health_reporter.set_service_status("", status).await;
+        }
+    });
+}
 
-        for entry_point in entry_points {
-            let id = mempool_id(&entry_point, &chain_id);
+/// Re-checks the chain id of the execution client, so that the user operations aren't accepted if the endpoint
+/// silently switches to another network (the acceptance resumes once it's back on the chain)
+fn start_chain_id_watching(
+    supervisor: &Supervisor,
+    eth_provider: Arc<EthProvider>,
+    chain_id: U256,
+    chain_id_mismatch: Arc<Mutex<bool>>,
+    check_interval: Duration,
+) {
+    supervisor.spawn("uopool_chain_id", move || {
+        watch_chain_id(
+            eth_provider.clone(),
+            chain_id,
+            chain_id_mismatch.clone(),
+            check_interval,
+        )
+    });
+}
 
-            let mut reputation = Box::<MemoryReputation>::default();
-            reputation.init(
-                MIN_INCLUSION_RATE_DENOMINATOR,
-                THROTTLING_SLACK,
-                BAN_SLACK,
-                opts.min_stake,
-                opts.min_unstake_delay,
+async fn watch_chain_id(
+    eth_provider: Arc<EthProvider>,
+    chain_id: U256,
+    chain_id_mismatch: Arc<Mutex<bool>>,
+    check_interval: Duration,
+) -> Result<()> {
+    let mut interval = tokio::time::interval(check_interval);
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        let current_chain_id = match eth_provider.get_chainid().await {
+            Ok(current_chain_id) => current_chain_id,
+            Err(e) => {
+                warn!("Failed to get the chain id: {e:?}");
+                continue;
+            }
+        };
+        let mismatch = current_chain_id != chain_id;
+        let mut chain_id_mismatch = chain_id_mismatch.lock();
+        if mismatch && !*chain_id_mismatch {
+            error!("Execution client switched from chain {chain_id} to {current_chain_id}, pausing accepting user operations");
+        } else if !mismatch && *chain_id_mismatch {
+            info!(
+                "Execution client is back on chain {chain_id}, resuming accepting user operations"
             );
+        }
+        *chain_id_mismatch = mismatch;
+    }
+}
 
-            mempools_map.insert(
-                id,
-                UserOperationPool::<Provider<Http>>::new(
-                    EntryPoint::<Provider<Http>>::new(eth_provider.clone(), entry_point),
-                    Box::<MemoryMempool>::default(),
-                    reputation,
-                    eth_provider.clone(),
-                    max_verification_gas,
-                    opts.min_priority_fee_per_gas,
-                    chain_id,
-                ),
+/// Handles of the tasks of the uopool service, which finish after the shutdown signal
+pub struct UoPoolServiceHandle {
+    pub server: JoinHandle<Result<(), tonic::transport::Error>>,
+    pub ipc_server: Option<JoinHandle<()>>,
+    pub reputation: JoinHandle<()>,
+    pub p2p: Option<JoinHandle<()>>,
+    /// Mempools that are saved to the snapshot file once the service stopped
+    pub snapshot: Option<(
+        PathBuf,
+        Arc<DashMap<MempoolId, UserOperationPool<EthProvider>>>,
+    )>,
+}
+
+impl UoPoolServiceHandle {
+    /// Waits for the servers to complete the in-flight requests (at most for the timeout, since the streams of the
+    /// subscriptions are open until the clients disconnect) and for the background tasks to stop, then saves the
+    /// snapshot of the mempools
+    pub async fn join(self, timeout: Duration) -> Result<()> {
+        let snapshot = self.snapshot;
+        let tasks = async move {
+            if let Err(err) = self.server.await? {
+                error!("UoPool gRPC server failed: {err:?}");
+            }
+            if let Some(ipc_server) = self.ipc_server {
+                ipc_server.await?;
+            }
+            self.reputation.await?;
+            if let Some(p2p) = self.p2p {
+                p2p.await?;
+            }
+            Ok::<(), anyhow::Error>(())
+        };
+        let result = match tokio::time::timeout(timeout, tasks).await {
+            Ok(result) => result,
+            Err(_) => {
+                warn!("UoPool service didn't stop in {timeout:?}, dropping the pending requests");
+                Ok(())
+            }
+        };
+
+        if let Some((path, mempools)) = snapshot {
+            let snapshot = UoPoolSnapshot::take(&mempools);
+            snapshot.save(&path).map_err(|e| {
+                format_err!(
+                    "Saving the uopool snapshot to {} failed: {e:?}",
+                    path.display()
+                )
+            })?;
+            info!(
+                "Saved {} user operations to the uopool snapshot {}",
+                snapshot
+                    .mempools
+                    .iter()
+                    .map(|mempool| mempool.user_operations.len())
+                    .sum::<usize>(),
+                path.display()
             );
         }
+        result
+    }
+}
+
+/// Entry points to support: the configured ones, or the canonical entry points deployed on the chain if none are
+/// configured (the pool would be useless without entry points)
+pub async fn resolve_entry_points(
+    eth_provider: &EthProvider,
+    entry_points: Vec<Address>,
+) -> Result<Vec<Address>> {
+    let mut unique = HashSet::new();
+    if let Some(duplicate) = entry_points
+        .iter()
+        .find(|entry_point| !unique.insert(**entry_point))
+    {
+        return Err(format_err!("Entry point {duplicate:?} is configured twice"));
+    }
+    if !entry_points.is_empty() {
+        return Ok(entry_points);
+    }
 
-        let svc = uo_pool_server::UoPoolServer::new(UoPoolService::new(
-            mempools_map.clone(),
+    let chain_id = eth_provider.get_chainid().await?;
+    let entry_points = deployed_canonical_entry_points(eth_provider).await?;
+    if entry_points.is_empty() {
+        return Err(format_err!(
+            "No entry points configured and none of the canonical entry points is deployed on chain {chain_id}"
+        ));
+    }
+    info!("No entry points configured, using the canonical entry points {entry_points:?} deployed on chain {chain_id}");
+    Ok(entry_points)
+}
+
+/// Mempool of the entry point with the options of the uopool (and the overrides of the entry point)
+pub(crate) fn new_user_operation_pool(
+    opts: &UoPoolServiceOpts,
+    eth_provider: Arc<EthProvider>,
+    entry_point: Address,
+    chain_id: U256,
+    capabilities: &ProviderCapabilities,
+    max_verification_gas: U256,
+    simulation_block: BlockNumber,
+) -> UserOperationPool<EthProvider> {
+    let overrides = opts
+        .entry_point_overrides
+        .iter()
+        .find(|overrides| overrides.entry_point == entry_point)
+        .cloned()
+        .unwrap_or_default();
+    if overrides != EntryPointOverrides::default() {
+        info!("Options of the entry point {entry_point:?} are overridden: {overrides:?}");
+    }
+
+    let mut reputation = Box::<MemoryReputation>::default();
+    reputation.init(
+        opts.min_inclusion_denominator,
+        opts.throttling_slack,
+        opts.ban_slack,
+        opts.min_stake,
+        opts.min_unstake_delay,
+    );
+    reputation.set_lists(&opts.whitelist, &opts.blacklist);
+
+    let mut uopool = UserOperationPool::<EthProvider>::new(
+        EntryPoint::<EthProvider>::new(eth_provider.clone(), entry_point)
+            .with_simulation_block(overrides.simulation_block.unwrap_or(simulation_block)),
+        Box::<MemoryMempool>::default(),
+        reputation,
+        eth_provider,
+        overrides
+            .max_verification_gas
+            .unwrap_or(max_verification_gas),
+        overrides
+            .min_priority_fee_per_gas
+            .unwrap_or(opts.min_priority_fee_per_gas),
+        chain_id,
+        opts.uopool_aggregators.iter().copied().collect(),
+    );
+    uopool.chain_spec = uopool.chain_spec.clone().with_capabilities(capabilities);
+    uopool.trace_validation = capabilities.js_tracer && overrides.trace_validation.unwrap_or(true);
+    uopool.max_mempool_size = Some(overrides.max_mempool_size.unwrap_or(opts.max_mempool_size))
+        .filter(|max_mempool_size| *max_mempool_size > 0);
+    uopool.max_queued_user_operations =
+        Some(opts.max_queued_user_operations).filter(|max_queued| *max_queued > 0);
+    uopool.max_queued_user_operations_per_sender =
+        Some(opts.max_queued_user_operations_per_sender).filter(|max_queued| *max_queued > 0);
+    uopool.simulation_latency_slo =
+        Some(Duration::from_millis(opts.simulation_latency_slo)).filter(|slo| !slo.is_zero());
+    uopool
+}
+
+pub async fn uopool_service_run(
+    opts: UoPoolServiceOpts,
+    p2p_opts: P2POpts,
+    entry_points: Vec<Address>,
+    eth_provider: Arc<EthProvider>,
+    max_verification_gas: U256,
+    debug: bool,
+    shutdown: ShutdownSignal,
+    supervisor: Supervisor,
+) -> Result<UoPoolServiceHandle> {
+    let chain_id = eth_provider.get_chainid().await?;
+    if let Some(expected_chain_id) = opts.chain_id {
+        if chain_id != expected_chain_id.into() {
+            return Err(format_err!(
+                "Execution client is on chain {chain_id}, expected chain {expected_chain_id}"
+            ));
+        }
+    }
+    // the broken TLS config fails the startup, the server isn't served without TLS
+    let mut grpc_server = opts.grpc_server();
+    if let Some(tls_config) = opts.grpc_tls() {
+        grpc_server = grpc_server.tls_config(tls_config.server_tls_config()?)?;
+    }
+    let auth_token = auth_token(
+        opts.uopool_grpc_auth_token.as_ref(),
+        opts.uopool_grpc_auth_token_file.as_deref(),
+    )?;
+
+    let simulation_block = opts
+        .simulation_block
+        .parse::<BlockNumber>()
+        .map_err(|e| format_err!("Invalid simulation block: {e}"))?;
+
+    let capabilities = ProviderCapabilities::probe(eth_provider.as_ref()).await;
+    info!("Execution client capabilities: {capabilities:?}");
+    if capabilities.js_tracer {
+        info!("User operations are validated on the trace of the simulation (debug_traceCall)");
+    } else {
+        warn!("Execution client doesn't support debug_traceCall with JS tracers, the validation rules (opcodes, storage access, code hashes) are not checked");
+    }
+
+    for overrides in opts.entry_point_overrides.iter() {
+        if !entry_points.contains(&overrides.entry_point) {
+            return Err(format_err!(
+                "Options are overridden for the entry point {:?} which is not supported",
+                overrides.entry_point
+            ));
+        }
+    }
+
+    let mempools_map = Arc::new(DashMap::<MempoolId, UserOperationPool<EthProvider>>::new());
+
+    for entry_point in entry_points.iter().copied() {
+        let id = mempool_id(&entry_point, &chain_id);
+        if eth_provider.get_code(entry_point, None).await?.is_empty() {
+            return Err(format_err!(
+                "No entry point deployed at {entry_point:?} on chain {chain_id}"
+            ));
+        }
+        let version = EntryPointVersion::detect(eth_provider.as_ref(), entry_point)
+            .await
+            .map_err(|e| {
+                format_err!(
+                    "Failed to detect the version of the entry point {entry_point:?}: {e:?}"
+                )
+            })?;
+        info!("Entry point {entry_point:?} is {version}");
+
+        let uopool = new_user_operation_pool(
+            &opts,
             eth_provider.clone(),
+            entry_point,
             chain_id,
-        ));
+            &capabilities,
+            max_verification_gas,
+            simulation_block,
+        );
+        mempools_map.insert(id, uopool);
+    }
 
+    let mut uopool_service = UoPoolService::new(
+        mempools_map.clone(),
+        entry_points.clone(),
+        eth_provider.clone(),
+        chain_id,
+        opts.max_queued_nonce_gap,
+        debug,
+    );
+    uopool_service.eth_client_request_budget =
+        Some(opts.max_eth_client_requests_per_user_operation).filter(|budget| *budget > 0);
+    uopool_service.simulation_permits = Some(opts.max_concurrent_simulations)
+        .filter(|permits| *permits > 0)
+        .map(|permits| Arc::new(Semaphore::new(permits)));
+    uopool_service.rejections = Arc::new(Mutex::new(RejectionLog::new(opts.rejection_log_size)));
+    uopool_service.fixed_min_priority_fees = Arc::new(
+        opts.entry_point_overrides
+            .iter()
+            .filter(|overrides| overrides.min_priority_fee_per_gas.is_some())
+            .map(|overrides| overrides.entry_point)
+            .collect(),
+    );
+    if let Some(snapshot_path) = &opts.uopool_snapshot_path {
+        match UoPoolSnapshot::load(snapshot_path) {
+            Ok(Some(snapshot)) => {
+                info!("Restoring the uopool snapshot {}", snapshot_path.display());
+                snapshot.restore(&uopool_service);
+            }
+            Ok(None) => {}
+            Err(e) => warn!(
+                "Failed to load the uopool snapshot {}: {e:?}",
+                snapshot_path.display()
+            ),
+        }
+    }
+    let accepting = uopool_service.accepting.clone();
+    if opts.chain_id_check_interval > 0 {
+        start_chain_id_watching(
+            &supervisor,
+            eth_provider.clone(),
+            chain_id,
+            uopool_service.chain_id_mismatch.clone(),
+            Duration::from_secs(opts.chain_id_check_interval),
+        );
+    }
+    let p2p = start_p2p(
+        &p2p_opts,
+        uopool_service.clone(),
+        shutdown.clone(),
+        &supervisor,
+    )?
+    .map(|(network, p2p)| {
+        uopool_service.network = Some(network);
+        uopool_service.gossip_user_operations = p2p_opts.p2p_gossip_user_operations;
+        p2p
+    });
+    start_uopool_metrics(mempools_map.clone());
+    let svc = TraceContext::new(uo_pool_server::UoPoolServer::new(uopool_service));
+
+    let (mut health_reporter, health_svc) = health_reporter();
+    health_reporter
+        .set_service_status(UOPOOL_SERVICE_NAME, ServingStatus::Serving)
+        .await;
+    // the whole server isn't serving until the execution client is checked
+    for service in [PROVIDER_SERVICE_NAME, ""] {
+        health_reporter
+            .set_service_status(service, ServingStatus::NotServing)
+            .await;
+    }
+    start_health_checking(health_reporter, eth_provider.clone());
+    let reflection_svc = tonic_reflection::server::Builder::configure()
+        .register_encoded_file_descriptor_set(FILE_DESCRIPTOR_SET)
+        .build()?;
+
+    let ipc_server = opts.uopool_grpc_ipc_path.clone().map(|ipc_path| {
+        let mut grpc_server = opts.grpc_server();
+        let svc = svc.clone();
+        let health_svc = health_svc.clone();
+        let reflection_svc = reflection_svc.clone();
+        let shutdown = shutdown.clone();
         tokio::spawn(async move {
-            loop {
-                mempools_map
-                    .iter_mut()
-                    .for_each(|mut mempool| mempool.value_mut().reputation.update_hourly());
-                tokio::time::sleep(Duration::from_secs(60 * 60)).await;
+            // the socket of the previous run is left behind if the process didn't exit cleanly
+            let _ = std::fs::remove_file(&ipc_path);
+            let listener = match UnixListener::bind(&ipc_path) {
+                Ok(listener) => listener,
+                Err(err) => {
+                    error!("Binding the uopool gRPC IPC socket failed: {err:?}");
+                    return;
+                }
+            };
+            info!("UoPool gRPC IPC server starting on {}", ipc_path.display());
+            if let Err(err) = grpc_server
+                .add_service(svc)
+                .add_service(health_svc)
+                .add_service(reflection_svc)
+                .serve_with_incoming_shutdown(UnixListenerStream::new(listener), shutdown.wait())
+                .await
+            {
+                error!("UoPool gRPC IPC server failed: {err:?}");
             }
-        });
+            let _ = std::fs::remove_file(&ipc_path);
+        })
+    });
 
-        info!(
-            "UoPool gRPC server starting on {}",
-            opts.uopool_grpc_listen_address
+    let snapshot_mempools = mempools_map.clone();
+    start_events_watching(
+        &supervisor,
+        mempools_map.clone(),
+        eth_provider.clone(),
+        entry_points,
+        chain_id,
+    );
+
+    let reputation = {
+        let shutdown = shutdown.clone();
+        supervisor.spawn("uopool_reputation", move || {
+            let mempools_map = mempools_map.clone();
+            let shutdown = shutdown.clone();
+            async move {
+                loop {
+                    mempools_map
+                        .iter_mut()
+                        .for_each(|mut mempool| mempool.value_mut().reputation.update_hourly());
+                    tokio::select! {
+                        _ = tokio::time::sleep(Duration::from_secs(60 * 60)) => {},
+                        _ = shutdown.clone().wait() => break,
+                    }
+                }
+                Ok(())
+            }
+        })
+    };
+
+    info!(
+        "UoPool gRPC server starting on {}",
+        opts.uopool_grpc_listen_address
+    );
+
+    let server = tokio::spawn(
+        grpc_server
+            .add_service(InterceptedService::new(svc, AuthValidator::new(auth_token)))
+            .add_service(health_svc)
+            .add_service(reflection_svc)
+            .serve_with_shutdown(opts.uopool_grpc_listen_address, async move {
+                shutdown.wait().await;
+                // the new user operations are rejected while the in-flight verifications are completed
+                *accepting.lock() = false;
+                info!("UoPool gRPC server shutting down");
+            }),
+    );
+
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    Ok(UoPoolServiceHandle {
+        server,
+        ipc_server,
+        reputation,
+        p2p,
+        snapshot: opts
+            .uopool_snapshot_path
+            .map(|snapshot_path| (snapshot_path, snapshot_mempools)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::{
+        abi::{encode, AbiEncode, Token},
+        utils::keccak256,
+    };
+
+    fn log(address: Address, signature: &str, topics: Vec<H256>, data: Vec<Token>) -> Log {
+        Log {
+            address,
+            topics: [vec![H256::from(keccak256(signature))], topics].concat(),
+            data: encode(&data).into(),
+            ..Default::default()
+        }
+    }
+
+    fn user_operation_event(entry_point: Address, user_operation_hash: H256) -> Log {
+        log(
+            entry_point,
+            "UserOperationEvent(bytes32,address,address,uint256,bool,uint256,uint256)",
+            vec![user_operation_hash, H256::random(), H256::zero()],
+            vec![
+                Token::Uint(U256::zero()),
+                Token::Bool(false),
+                Token::Uint(U256::zero()),
+                Token::Uint(U256::zero()),
+            ],
+        )
+    }
+
+    #[test]
+    fn user_operation_logs_slice() {
+        let entry_point = Address::random();
+        let user_operation_hash = H256::random();
+        let account_log = |topic: H256| Log {
+            address: Address::random(),
+            topics: vec![topic],
+            ..Default::default()
+        };
+        let mut revert_reason = REVERT_ERROR_SELECTOR.to_vec();
+        revert_reason.extend(encode(&[Token::String("failed".to_string())]));
+
+        let logs = vec![
+            account_log(H256::random()),
+            log(entry_point, "BeforeExecution()", vec![], vec![]),
+            account_log(H256::random()),
+            user_operation_event(entry_point, H256::random()),
+            account_log(H256::repeat_byte(1)),
+            log(
+                entry_point,
+                "UserOperationRevertReason(bytes32,address,uint256,bytes)",
+                vec![user_operation_hash, H256::random()],
+                vec![Token::Uint(U256::zero()), Token::Bytes(revert_reason)],
+            ),
+            user_operation_event(entry_point, user_operation_hash),
+        ];
+
+        let (sliced, reason) = user_operation_logs(&logs, &entry_point, &user_operation_hash);
+        assert_eq!(sliced, logs[4..6].to_vec());
+        assert_eq!(reason, "failed");
+
+        let (sliced, reason) = user_operation_logs(&logs, &entry_point, &H256::random());
+        assert!(sliced.is_empty());
+        assert!(reason.is_empty());
+    }
+    #[test]
+    fn entry_point_overrides() {
+        let entry_point: Address = "0x5FF137D4b0FDCD49DcA30c7CF57E578a026d2789"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            parse_entry_point_overrides(
+                "0x5FF137D4b0FDCD49DcA30c7CF57E578a026d2789:max-verification-gas=3000000,max_mempool_size=100,simulation-block=pending,trace-validation=false"
+            ),
+            Ok(EntryPointOverrides {
+                entry_point,
+                max_verification_gas: Some(U256::from(3000000)),
+                min_priority_fee_per_gas: None,
+                max_mempool_size: Some(100),
+                simulation_block: Some(BlockNumber::Pending),
+                trace_validation: Some(false),
+            })
         );
+        assert!(parse_entry_point_overrides("0x5FF137D4b0FDCD49DcA30c7CF57E578a026d2789:").is_ok());
+        assert!(parse_entry_point_overrides("0x5FF137D4b0FDCD49DcA30c7CF57E578a026d2789").is_err());
+        assert!(parse_entry_point_overrides(
+            "0x5FF137D4b0FDCD49DcA30c7CF57E578a026d2789:min-stake=1"
+        )
+        .is_err());
+    }
 
-        builder
-            .add_service(svc)
-            .serve(opts.uopool_grpc_listen_address)
-            .await
-    });
+    #[tokio::test]
+    async fn promote_queued_user_operation() {
+        let (eth_provider, mock) = ethers::providers::Provider::mocked();
+        let eth_provider = Arc::new(eth_provider);
+        let entry_point = Address::random();
+        let chain_id = U256::from(1337);
+        let mut uopool = UserOperationPool::new(
+            EntryPoint::new(eth_provider.clone(), entry_point),
+            Box::<MemoryMempool>::default(),
+            Box::<MemoryReputation>::default(),
+            eth_provider,
+            U256::from(1500000),
+            U256::zero(),
+            chain_id,
+            HashSet::new(),
+        );
+        let user_operation = UserOperation {
+            nonce: U256::from(9),
+            max_fee_per_gas: U256::from(10),
+            max_priority_fee_per_gas: U256::from(1),
+            paymaster_and_data: Bytes::default(),
+            ..UserOperation::random()
+        };
+        uopool.queue_user_operation(user_operation.clone()).unwrap();
+        let id = mempool_id(&entry_point, &chain_id);
+        let mempools = Arc::new(DashMap::new());
+        mempools.insert(id, uopool);
+
+        // the preceding nonces aren't included yet (the sender's nonce is 7)
+        mock.push(Bytes::from(U256::from(7).encode())).unwrap();
+        promote_queued_user_operations(&mempools, &id, U256::one(), &mut HashMap::new()).await;
+        assert_eq!(
+            mempools.get(&id).unwrap().get_queued_user_operations(),
+            vec![user_operation]
+        );
 
-    tokio::time::sleep(Duration::from_secs(1)).await;
+        // the nonce was used by another user operation
+        mock.push(Bytes::from(U256::from(10).encode())).unwrap();
+        promote_queued_user_operations(&mempools, &id, U256::one(), &mut HashMap::new()).await;
+        assert!(mempools
+            .get(&id)
+            .unwrap()
+            .get_queued_user_operations()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn simulation_permit_before_mempool() {
+        let (eth_provider, mock) = ethers::providers::Provider::mocked();
+        let eth_provider = Arc::new(eth_provider);
+        let entry_point = Address::random();
+        let chain_id = U256::from(1337);
+        let uopool = UserOperationPool::new(
+            EntryPoint::new(eth_provider.clone(), entry_point),
+            Box::<MemoryMempool>::default(),
+            Box::<MemoryReputation>::default(),
+            eth_provider.clone(),
+            U256::from(1500000),
+            U256::zero(),
+            chain_id,
+            HashSet::new(),
+        );
+        let id = mempool_id(&entry_point, &chain_id);
+        let mempools = Arc::new(DashMap::new());
+        mempools.insert(id, uopool);
+        let mut service = UoPoolService::new(
+            mempools.clone(),
+            vec![entry_point],
+            eth_provider,
+            chain_id,
+            U256::from(10),
+            false,
+        );
+        // all the permits are taken
+        service.simulation_permits = Some(Arc::new(Semaphore::new(0)));
+
+        let user_operation = UserOperation {
+            nonce: U256::zero(),
+            ..UserOperation::random()
+        };
+        mock.push(Bytes::from(U256::zero().encode())).unwrap();
+        let insert = tokio::spawn(async move {
+            service
+                .try_insert_user_operation(user_operation, entry_point, None, false)
+                .await
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        // the request waiting for the permit doesn't hold the read guard of the mempool
+        assert!(!insert.is_finished());
+        assert!(mempools.try_get_mut(&id).is_present());
+        insert.abort();
+    }
 
-    Ok(())
+    #[tokio::test]
+    async fn forward_mempool_events() {
+        let entry_point = Address::random();
+        let (events, receiver) = broadcast::channel(16);
+        let (tx, mut rx) = mpsc::channel(EVENTS_STREAM_CAPACITY);
+        forward_events(entry_point, receiver, tx);
+
+        let user_operation = UserOperation::random();
+        let user_operation_hash = UserOperationHash(H256::random());
+        events
+            .send(UoPoolEvent::NewUserOperation {
+                user_operation_hash,
+                user_operation: Box::new(user_operation.clone()),
+            })
+            .unwrap();
+        let event = rx.recv().await.unwrap().unwrap();
+        assert_eq!(event.kind, MempoolEventKind::NewUserOperation as i32);
+        assert_eq!(event.ep, Some(entry_point.into()));
+        assert_eq!(event.uo_hash, Some(user_operation_hash.0.into()));
+        assert_eq!(event.uo, Some(user_operation.into()));
+
+        // the forwarding stops (and unsubscribes) on the next event once the subscriber disconnects
+        drop(rx);
+        events
+            .send(UoPoolEvent::UserOperationIncluded {
+                user_operation_hash,
+                sender: Address::random(),
+                transaction_hash: H256::random(),
+                success: true,
+            })
+            .unwrap();
+        tokio::time::timeout(Duration::from_secs(1), async {
+            while events.receiver_count() > 0 {
+                tokio::task::yield_now().await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+
+    #[test]
+    fn reflection_descriptors() {
+        // the descriptors of the compiled proto files are served by the reflection service
+        assert!(tonic_reflection::server::Builder::configure()
+            .register_encoded_file_descriptor_set(FILE_DESCRIPTOR_SET)
+            .build()
+            .is_ok());
+    }
 }
@@ -0,0 +1,26 @@
+use libp2p::{
+    allow_block_list::{self, BlockedPeers},
+    connection_limits, identify,
+    swarm::{behaviour::toggle::Toggle, NetworkBehaviour},
+    upnp,
+};
+
+use crate::{
+    gossipsub::Gossipsub,
+    request_response::{PooledUserOpHashesBehaviour, PooledUserOpsByHashBehaviour},
+};
+
+/// Protocols of the P2P network of the shared mempool
+#[derive(NetworkBehaviour)]
+pub struct Behaviour {
+    /// Connections of the banned peers are denied
+    pub blocked_peers: allow_block_list::Behaviour<BlockedPeers>,
+    pub connection_limits: connection_limits::Behaviour,
+    /// Exchange of the listen and the observed addresses (and the agent versions) with the peers
+    pub identify: identify::Behaviour,
+    /// Port mapping on the gateway (if enabled)
+    pub upnp: Toggle<upnp::tokio::Behaviour>,
+    pub gossipsub: Gossipsub,
+    pub pooled_user_op_hashes: PooledUserOpHashesBehaviour,
+    pub pooled_user_ops_by_hash: PooledUserOpsByHashBehaviour,
+}
@@ -0,0 +1,62 @@
+use std::net::SocketAddr;
+
+use clap::Parser;
+use discv5::Enr;
+use libp2p::{multiaddr::Protocol, Multiaddr};
+
+#[derive(Clone, Debug, Parser, PartialEq)]
+pub struct P2POpts {
+    /// Address of the P2P listener of the shared mempool (TCP, and UDP for the discovery), the uopool joins the shared
+    /// mempool only if set
+    #[clap(long)]
+    pub p2p_listen_address: Option<SocketAddr>,
+
+    /// Address advertised to the peers if it differs from the listen address (e.g. the public address of the host
+    /// behind NAT with the port forwarded)
+    #[clap(long)]
+    pub p2p_external_address: Option<SocketAddr>,
+
+    /// Maximum number of the connected peers, the discovered nodes are dialed only while there are fewer peers
+    #[clap(long, default_value_t = 50)]
+    pub p2p_max_peers: u32,
+
+    /// ENRs of the nodes that the discovery starts from (base64 `enr:-...` records)
+    #[clap(long, value_delimiter = ',')]
+    pub p2p_bootnodes: Vec<Enr>,
+
+    /// Multiaddresses of the static peers that are dialed on startup (e.g. `/ip4/10.0.0.1/tcp/4337`), the peers with
+    /// the peer id in the address (`/p2p/<peer id>`) are redialed when disconnected
+    #[clap(long, value_delimiter = ',')]
+    pub p2p_peers: Vec<Multiaddr>,
+
+    /// Maps the listen port on the gateway with UPnP (for the nodes behind NAT)
+    #[clap(long)]
+    pub p2p_upnp: bool,
+
+    /// Disables the P2P network even if the listen address is set
+    #[clap(long)]
+    pub p2p_disable: bool,
+
+    /// Gossips the user operations received over RPC to the shared mempool (by default they are only added to the
+    /// local mempool)
+    #[clap(long)]
+    pub p2p_gossip_user_operations: bool,
+}
+
+impl P2POpts {
+    pub fn enabled(&self) -> bool {
+        self.p2p_listen_address.is_some() && !self.p2p_disable
+    }
+
+    pub fn listen_multiaddr(&self) -> Option<Multiaddr> {
+        self.p2p_listen_address.map(tcp_multiaddr)
+    }
+
+    pub fn external_multiaddr(&self) -> Option<Multiaddr> {
+        self.p2p_external_address.map(tcp_multiaddr)
+    }
+}
+
+fn tcp_multiaddr(address: SocketAddr) -> Multiaddr {
+    Multiaddr::from(address.ip()).with(Protocol::Tcp(address.port()))
+}
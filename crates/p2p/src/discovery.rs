@@ -0,0 +1,124 @@
+use std::{future::Future, net::SocketAddr, pin::Pin, time::Duration};
+
+use discv5::{
+    enr::{CombinedKey, NodeId},
+    ConfigBuilder, Discv5, Enr, ListenConfig, QueryError,
+};
+use ethers::types::H256;
+use tokio::time::{interval, Interval, MissedTickBehavior};
+use tracing::{debug, info, warn};
+
+use libp2p::PeerId;
+
+use crate::enr::{build_enr, node_id, supports_mempools};
+
+/// Interval of the searches for new peers
+const DISCOVERY_INTERVAL: Duration = Duration::from_secs(30);
+/// Number of the nodes supporting the mempools that a search looks for
+const TARGET_PEERS_PER_QUERY: usize = 16;
+
+type Query = Pin<Box<dyn Future<Output = Result<Vec<Enr>, QueryError>> + Send>>;
+
+/// Discovery (discv5) of the bundlers of the shared mempools, the nodes advertise the chain id and the mempool ids in
+/// their ENR
+pub struct Discovery {
+    discv5: Discv5,
+    chain_id: u64,
+    mempool_ids: Vec<H256>,
+    /// Search for new peers in progress
+    query: Option<Query>,
+    interval: Interval,
+}
+
+impl Discovery {
+    /// Creates the discovery listening on the address, the ENR advertises the external address (if it differs)
+    pub fn new(
+        enr_key: CombinedKey,
+        listen_address: SocketAddr,
+        external_address: Option<SocketAddr>,
+        chain_id: u64,
+        mempool_ids: Vec<H256>,
+        bootnodes: &[Enr],
+    ) -> anyhow::Result<Self> {
+        let enr = build_enr(
+            &enr_key,
+            external_address.unwrap_or(listen_address),
+            chain_id,
+            &mempool_ids,
+        )?;
+        let config = ConfigBuilder::new(ListenConfig::from_ip(
+            listen_address.ip(),
+            listen_address.port(),
+        ))
+        .build();
+        let discv5 = Discv5::new(enr, enr_key, config)
+            .map_err(|err| anyhow::anyhow!("Creating the discovery failed: {err}"))?;
+        for bootnode in bootnodes {
+            if let Err(err) = discv5.add_enr(bootnode.clone()) {
+                warn!("Adding the bootnode {bootnode} failed: {err}");
+            }
+        }
+
+        let mut interval = interval(DISCOVERY_INTERVAL);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        Ok(Self {
+            discv5,
+            chain_id,
+            mempool_ids,
+            query: None,
+            interval,
+        })
+    }
+
+    pub fn local_enr(&self) -> Enr {
+        self.discv5.local_enr()
+    }
+
+    /// ENR of the peer if it is known to the discovery
+    pub fn find_enr(&self, peer_id: &PeerId) -> Option<Enr> {
+        self.discv5.find_enr(&node_id(peer_id)?)
+    }
+
+    /// Binds the UDP socket of the discovery
+    pub async fn start(&mut self) -> anyhow::Result<()> {
+        self.discv5
+            .start()
+            .await
+            .map_err(|err| anyhow::anyhow!("Starting the discovery failed: {err:?}"))?;
+        info!(
+            "P2P discovery started, ENR: {}",
+            self.local_enr().to_base64()
+        );
+        Ok(())
+    }
+
+    /// Periodically searches for the nodes that support the mempools, resolves with the ENRs found by the search
+    /// (cancel safe, the search in progress is kept)
+    pub async fn next_peers(&mut self) -> Vec<Enr> {
+        loop {
+            match self.query.as_mut() {
+                Some(query) => {
+                    let result = query.await;
+                    self.query = None;
+                    match result {
+                        Ok(enrs) => {
+                            debug!("P2P discovery found {} nodes", enrs.len());
+                            return enrs;
+                        }
+                        Err(err) => warn!("P2P discovery search failed: {err:?}"),
+                    }
+                }
+                None => {
+                    self.interval.tick().await;
+                    let chain_id = self.chain_id;
+                    let mempool_ids = self.mempool_ids.clone();
+                    self.query = Some(Box::pin(self.discv5.find_node_predicate(
+                        NodeId::random(),
+                        Box::new(move |enr: &Enr| supports_mempools(enr, chain_id, &mempool_ids)),
+                        TARGET_PEERS_PER_QUERY,
+                    )));
+                }
+            }
+        }
+    }
+}
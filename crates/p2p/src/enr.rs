@@ -0,0 +1,151 @@
+use std::net::{IpAddr, SocketAddr};
+
+use discv5::{
+    enr::{CombinedKey, CombinedPublicKey, EnrPublicKey, NodeId},
+    Enr,
+};
+use ethers::{types::H256, utils::keccak256};
+use libp2p::{
+    identity::{secp256k1, Keypair, PublicKey},
+    multiaddr::Protocol,
+    Multiaddr, PeerId,
+};
+
+/// ENR key of the chain id of the mempools
+const CHAIN_ID_ENR_KEY: &str = "chain_id";
+/// ENR key of the supported mempool ids (concatenated)
+const MEMPOOLS_ENR_KEY: &str = "mempools";
+
+/// Key of the ENR, the same secp256k1 key as of the libp2p identity
+pub fn enr_key(keypair: &Keypair) -> anyhow::Result<CombinedKey> {
+    let mut secret = keypair
+        .clone()
+        .try_into_secp256k1()
+        .map_err(|_| anyhow::anyhow!("P2P identity must be a secp256k1 key"))?
+        .secret()
+        .to_bytes();
+    CombinedKey::secp256k1_from_bytes(&mut secret)
+        .map_err(|err| anyhow::anyhow!("Invalid P2P identity: {err:?}"))
+}
+
+/// ENR of the node advertising the chain id and the supported mempools, with the same port for the discovery (UDP)
+/// and the libp2p (TCP) listener. The IP address is left out if the listener is bound on all interfaces, then it is
+/// set by the discovery once the peers report it.
+pub fn build_enr(
+    enr_key: &CombinedKey,
+    address: SocketAddr,
+    chain_id: u64,
+    mempool_ids: &[H256],
+) -> anyhow::Result<Enr> {
+    let mut builder = Enr::builder();
+    match address.ip() {
+        IpAddr::V4(ip) if !ip.is_unspecified() => {
+            builder.ip4(ip);
+        }
+        IpAddr::V6(ip) if !ip.is_unspecified() => {
+            builder.ip6(ip);
+        }
+        _ => {}
+    };
+    if address.is_ipv4() {
+        builder.udp4(address.port()).tcp4(address.port());
+    } else {
+        builder.udp6(address.port()).tcp6(address.port());
+    }
+
+    let mempools: Vec<u8> = mempool_ids
+        .iter()
+        .flat_map(|mempool_id| mempool_id.0)
+        .collect();
+    builder
+        .add_value(CHAIN_ID_ENR_KEY, &chain_id)
+        .add_value(MEMPOOLS_ENR_KEY, &mempools)
+        .build(enr_key)
+        .map_err(|err| anyhow::anyhow!("Building the ENR failed: {err:?}"))
+}
+
+pub fn chain_id(enr: &Enr) -> Option<u64> {
+    enr.get_decodable::<u64>(CHAIN_ID_ENR_KEY)?.ok()
+}
+
+pub fn mempool_ids(enr: &Enr) -> Vec<H256> {
+    match enr.get_decodable::<Vec<u8>>(MEMPOOLS_ENR_KEY) {
+        Some(Ok(mempools)) => mempools.chunks_exact(32).map(H256::from_slice).collect(),
+        _ => vec![],
+    }
+}
+
+/// Whether the node of the ENR is on the chain and shares at least one of the mempools
+pub fn supports_mempools(enr: &Enr, chain_id: u64, mempool_ids: &[H256]) -> bool {
+    self::chain_id(enr) == Some(chain_id)
+        && self::mempool_ids(enr)
+            .iter()
+            .any(|mempool_id| mempool_ids.contains(mempool_id))
+}
+
+pub fn peer_id(enr: &Enr) -> Option<PeerId> {
+    match enr.public_key() {
+        CombinedPublicKey::Secp256k1(public_key) => {
+            let public_key = secp256k1::PublicKey::try_from_bytes(&public_key.encode()).ok()?;
+            Some(PeerId::from_public_key(&PublicKey::from(public_key)))
+        }
+        // the libp2p identities are secp256k1 keys
+        CombinedPublicKey::Ed25519(_) => None,
+    }
+}
+
+/// Node id (of the discovery) of the peer, the keccak256 hash of its uncompressed secp256k1 public key (that is
+/// inlined in the peer id)
+pub fn node_id(peer_id: &PeerId) -> Option<NodeId> {
+    // public keys of up to 42 bytes are inlined with the identity multihash
+    if peer_id.as_ref().code() != 0 {
+        return None;
+    }
+    let public_key = PublicKey::try_decode_protobuf(peer_id.as_ref().digest())
+        .ok()?
+        .try_into_secp256k1()
+        .ok()?;
+    Some(NodeId::new(&keccak256(
+        &public_key.to_bytes_uncompressed()[1..],
+    )))
+}
+
+/// Address of the libp2p (TCP) listener of the node
+pub fn multiaddr(enr: &Enr) -> Option<Multiaddr> {
+    if let Some(socket) = enr.tcp4_socket() {
+        return Some(Multiaddr::from(*socket.ip()).with(Protocol::Tcp(socket.port())));
+    }
+    enr.tcp6_socket()
+        .map(|socket| Multiaddr::from(*socket.ip()).with(Protocol::Tcp(socket.port())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mempools_enr() {
+        let keypair = Keypair::generate_secp256k1();
+        let enr_key = enr_key(&keypair).unwrap();
+        let mempool_ids = vec![H256::random(), H256::random()];
+        let enr = build_enr(&enr_key, "10.0.0.1:4337".parse().unwrap(), 5, &mempool_ids).unwrap();
+
+        assert_eq!(chain_id(&enr), Some(5));
+        assert_eq!(self::mempool_ids(&enr), mempool_ids);
+        assert!(supports_mempools(&enr, 5, &mempool_ids[1..]));
+        assert!(!supports_mempools(&enr, 1, &mempool_ids));
+        assert!(!supports_mempools(&enr, 5, &[H256::random()]));
+
+        assert_eq!(peer_id(&enr), Some(keypair.public().to_peer_id()));
+        assert_eq!(node_id(&keypair.public().to_peer_id()), Some(enr.node_id()));
+        assert_eq!(
+            multiaddr(&enr),
+            Some("/ip4/10.0.0.1/tcp/4337".parse().unwrap())
+        );
+
+        // the address is discovered if listening on all interfaces
+        let enr = build_enr(&enr_key, "0.0.0.0:4337".parse().unwrap(), 5, &mempool_ids).unwrap();
+        assert_eq!(enr.ip4(), None);
+        assert_eq!(enr.udp4(), Some(4337));
+    }
+}
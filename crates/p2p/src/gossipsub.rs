@@ -0,0 +1,153 @@
+use std::time::Duration;
+
+use ethers::types::H256;
+use libp2p::gossipsub::{
+    self, ConfigBuilder, DataTransform, IdentTopic, Message, MessageAuthenticity, MessageId,
+    RawMessage, TopicHash, ValidationMode,
+};
+use sha2::{Digest, Sha256};
+use snap::raw::{decompress_len, Decoder, Encoder};
+
+pub type Gossipsub = gossipsub::Behaviour<SnappyTransform>;
+
+const TOPIC_PREFIX: &str = "account_abstraction";
+const USER_OPERATIONS_TOPIC: &str = "user_ops_with_entry_point";
+const SSZ_SNAPPY_ENCODING: &str = "ssz_snappy";
+
+/// Maximum size of the (uncompressed) gossip message
+pub const MAX_GOSSIP_SIZE: usize = 1024 * 1024;
+/// Domain of the ids of the messages with valid compression
+const MESSAGE_DOMAIN_VALID_SNAPPY: [u8; 4] = [1, 0, 0, 0];
+const MESSAGE_ID_LENGTH: usize = 20;
+
+/// Topic of the user operations of the mempool:
+/// `/account_abstraction/<mempool id>/user_ops_with_entry_point/ssz_snappy`
+pub fn topic(mempool_id: &H256) -> IdentTopic {
+    IdentTopic::new(format!(
+        "/{TOPIC_PREFIX}/{mempool_id:x}/{USER_OPERATIONS_TOPIC}/{SSZ_SNAPPY_ENCODING}"
+    ))
+}
+
+/// Message id is the SHA256 of the domain and the uncompressed message (truncated to 20 bytes), so that the same
+/// user operations published by different peers are deduplicated. The messages that can't be decompressed are
+/// rejected before their id is computed.
+fn message_id(message: &Message) -> MessageId {
+    let mut hasher = Sha256::new();
+    hasher.update(MESSAGE_DOMAIN_VALID_SNAPPY);
+    hasher.update(&message.data);
+    MessageId::from(&hasher.finalize()[..MESSAGE_ID_LENGTH])
+}
+
+fn decompress(data: &[u8]) -> Result<Vec<u8>, std::io::Error> {
+    let len = decompress_len(data)?;
+    if len > MAX_GOSSIP_SIZE {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("Gossip message is larger than {MAX_GOSSIP_SIZE} bytes"),
+        ));
+    }
+    Decoder::new().decompress_vec(data).map_err(Into::into)
+}
+
+/// Messages are snappy compressed on the wire (the application sees the SSZ encoded messages)
+#[derive(Clone, Debug, Default)]
+pub struct SnappyTransform;
+
+impl DataTransform for SnappyTransform {
+    fn inbound_transform(&self, raw_message: RawMessage) -> Result<Message, std::io::Error> {
+        metrics::counter!("p2p_bytes_received", raw_message.data.len() as u64, "protocol" => "gossipsub");
+        Ok(Message {
+            source: raw_message.source,
+            data: decompress(&raw_message.data)?,
+            sequence_number: raw_message.sequence_number,
+            topic: raw_message.topic,
+        })
+    }
+
+    fn outbound_transform(
+        &self,
+        _topic: &TopicHash,
+        data: Vec<u8>,
+    ) -> Result<Vec<u8>, std::io::Error> {
+        if data.len() > MAX_GOSSIP_SIZE {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Gossip message is larger than {MAX_GOSSIP_SIZE} bytes"),
+            ));
+        }
+        let data = Encoder::new().compress_vec(&data)?;
+        metrics::counter!("p2p_bytes_sent", data.len() as u64, "protocol" => "gossipsub");
+        Ok(data)
+    }
+}
+
+/// Gossipsub with the parameters of the spec, the messages are forwarded only after the application validated them
+pub fn gossipsub() -> anyhow::Result<Gossipsub> {
+    let config = ConfigBuilder::default()
+        .mesh_n(8)
+        .mesh_n_low(6)
+        .mesh_n_high(12)
+        .gossip_lazy(6)
+        .heartbeat_interval(Duration::from_millis(700))
+        .fanout_ttl(Duration::from_secs(60))
+        .history_length(6)
+        .history_gossip(3)
+        .duplicate_cache_time(Duration::from_millis(550 * 700))
+        .max_transmit_size(MAX_GOSSIP_SIZE)
+        .validation_mode(ValidationMode::Anonymous)
+        .validate_messages()
+        .message_id_fn(message_id)
+        .build()?;
+
+    Gossipsub::new_with_transform(
+        MessageAuthenticity::Anonymous,
+        config,
+        None,
+        SnappyTransform,
+    )
+    .map_err(|err| anyhow::anyhow!("Creating gossipsub failed: {err}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn user_operations_topic() {
+        assert_eq!(
+            topic(&H256::repeat_byte(0xab)).to_string(),
+            format!(
+                "/account_abstraction/{}/user_ops_with_entry_point/ssz_snappy",
+                "ab".repeat(32)
+            )
+        );
+    }
+
+    #[test]
+    fn snappy_transform() {
+        let topic = topic(&H256::zero()).hash();
+        let data = vec![1u8; 1000];
+        let compressed = SnappyTransform
+            .outbound_transform(&topic, data.clone())
+            .unwrap();
+        assert!(compressed.len() < data.len());
+
+        let message = SnappyTransform
+            .inbound_transform(RawMessage {
+                source: None,
+                data: compressed,
+                sequence_number: None,
+                topic: topic.clone(),
+                signature: None,
+                key: None,
+                validated: false,
+            })
+            .unwrap();
+        assert_eq!(message.data, data);
+        assert_eq!(message_id(&message).0.len(), MESSAGE_ID_LENGTH);
+
+        assert!(SnappyTransform
+            .outbound_transform(&topic, vec![0; MAX_GOSSIP_SIZE + 1])
+            .is_err());
+    }
+}
@@ -0,0 +1,29 @@
+#![allow(dead_code)]
+
+mod behaviour;
+mod config;
+mod discovery;
+mod enr;
+mod gossipsub;
+mod network;
+mod peers;
+mod request_response;
+mod types;
+
+pub use config::P2POpts;
+pub use gossipsub::{topic, MAX_GOSSIP_SIZE};
+pub use network::{Network, NetworkEvent, NetworkHandle};
+pub use peers::PeerAction;
+pub use request_response::{POOLED_USER_OPS_BY_HASH_PROTOCOL, POOLED_USER_OP_HASHES_PROTOCOL};
+pub use types::{
+    user_operation_from_ssz_bytes, user_operation_ssz_bytes, Metadata, PooledUserOpHashes,
+    PooledUserOpHashesRequest, PooledUserOpsByHash, PooledUserOpsByHashRequest, Status,
+    UserOperationsWithEntryPoint, MAX_OPS_PER_REQUEST,
+};
+
+pub use discv5::Enr;
+pub use libp2p::{
+    gossipsub::{MessageAcceptance, MessageId},
+    request_response::InboundRequestId,
+    Multiaddr, PeerId,
+};
@@ -0,0 +1,952 @@
+use std::{
+    collections::HashMap,
+    future::{pending, Future},
+    time::Duration,
+};
+
+use aa_bundler_primitives::{PeerInfo, UserOperation};
+use discv5::Enr;
+use ethers::types::{Address, H256, U256};
+use futures::StreamExt;
+use libp2p::{
+    connection_limits::{self, ConnectionLimits},
+    gossipsub::{self, MessageAcceptance, MessageId, TopicHash},
+    identify,
+    identity::Keypair,
+    multiaddr::Protocol,
+    noise,
+    request_response::{self, InboundRequestId, OutboundRequestId, ResponseChannel},
+    swarm::{dial_opts::DialOpts, SwarmEvent},
+    tcp, upnp, yamux, Multiaddr, PeerId, Swarm, SwarmBuilder,
+};
+use tokio::{
+    sync::{mpsc, oneshot},
+    time::{interval, MissedTickBehavior},
+};
+use tracing::{debug, info, trace, warn};
+
+use crate::{
+    behaviour::{Behaviour, BehaviourEvent},
+    discovery::Discovery,
+    enr::{enr_key, mempool_ids, multiaddr, peer_id},
+    gossipsub::{gossipsub, topic, MAX_GOSSIP_SIZE},
+    peers::{PeerAction, PeerScores},
+    request_response::{
+        request_response, POOLED_USER_OPS_BY_HASH_PROTOCOL, POOLED_USER_OP_HASHES_PROTOCOL,
+    },
+    types::{
+        PooledUserOpHashes, PooledUserOpHashesRequest, PooledUserOpsByHash,
+        PooledUserOpsByHashRequest, UserOperationsWithEntryPoint, MAX_OPS_PER_REQUEST,
+    },
+    P2POpts,
+};
+
+/// Number of the gossiped messages waiting for the validation, further messages are ignored
+const EVENTS_CHANNEL_CAPACITY: usize = 1000;
+/// Connections without the open streams are closed after the timeout
+const IDLE_CONNECTION_TIMEOUT: Duration = Duration::from_secs(60);
+/// Interval of lifting the expired bans of the peers and of redialing the disconnected static peers
+const PEER_MAINTENANCE_INTERVAL: Duration = Duration::from_secs(60);
+/// Protocol version exchanged with the identify protocol
+const IDENTIFY_PROTOCOL_VERSION: &str = "/account_abstraction/1.0.0";
+/// Pages of the hashes of the mempool of the peer are requested only up to the offset
+const MAX_SYNCED_USER_OPS_PER_MEMPOOL: u64 = 16 * MAX_OPS_PER_REQUEST as u64;
+
+/// Event of the network that is handled by the uopool
+#[derive(Debug)]
+pub enum NetworkEvent {
+    /// User operations gossiped by the peer, the message is forwarded to the other peers only after its validation
+    /// is reported with [`NetworkHandle::report_validation`]
+    UserOperations {
+        message_id: MessageId,
+        peer: PeerId,
+        mempool_id: H256,
+        message: UserOperationsWithEntryPoint,
+    },
+    /// Page of the hashes of the user operations in the mempool of the peer, the pages are requested when connected
+    /// to the peer (the missing user operations are requested with [`NetworkHandle::request_pooled_user_ops`])
+    PooledUserOpHashes {
+        peer: PeerId,
+        mempool_id: H256,
+        hashes: Vec<H256>,
+    },
+    /// User operations of the mempool that were requested from the peer
+    PooledUserOps {
+        peer: PeerId,
+        mempool_id: H256,
+        entry_point: Address,
+        user_operations: Vec<UserOperation>,
+    },
+    /// Request of the page of the hashes of the user operations in the mempool, answered with
+    /// [`NetworkHandle::respond_pooled_user_op_hashes`]
+    PooledUserOpHashesRequest {
+        peer: PeerId,
+        request_id: InboundRequestId,
+        request: PooledUserOpHashesRequest,
+    },
+    /// Request of the user operations by their hashes, answered with
+    /// [`NetworkHandle::respond_pooled_user_ops_by_hash`]
+    PooledUserOpsByHashRequest {
+        peer: PeerId,
+        request_id: InboundRequestId,
+        request: PooledUserOpsByHashRequest,
+    },
+}
+
+#[derive(Debug)]
+enum NetworkCommand {
+    ReportValidation {
+        message_id: MessageId,
+        peer: PeerId,
+        acceptance: MessageAcceptance,
+    },
+    Publish {
+        mempool_id: H256,
+        message: UserOperationsWithEntryPoint,
+    },
+    ReportPeer {
+        peer: PeerId,
+        action: PeerAction,
+    },
+    Peers {
+        sender: oneshot::Sender<Vec<PeerInfo>>,
+    },
+    RequestPooledUserOps {
+        peer: PeerId,
+        mempool_id: H256,
+        hashes: Vec<H256>,
+    },
+    RespondPooledUserOpHashes {
+        request_id: InboundRequestId,
+        response: PooledUserOpHashes,
+    },
+    RespondPooledUserOpsByHash {
+        request_id: InboundRequestId,
+        response: PooledUserOpsByHash,
+    },
+}
+
+/// Handle to the running network
+#[derive(Clone, Debug)]
+pub struct NetworkHandle {
+    commands: mpsc::UnboundedSender<NetworkCommand>,
+}
+
+impl NetworkHandle {
+    /// Reports the result of the validation of the gossiped message: accepted messages are forwarded, the peers of
+    /// the rejected ones are penalized
+    pub fn report_validation(
+        &self,
+        message_id: MessageId,
+        peer: PeerId,
+        acceptance: MessageAcceptance,
+    ) {
+        // sending fails only if the network stopped
+        let _ = self.commands.send(NetworkCommand::ReportValidation {
+            message_id,
+            peer,
+            acceptance,
+        });
+    }
+
+    /// Publishes the user operations (verified locally) to the gossip topic of the mempool
+    pub fn publish_user_operations(&self, mempool_id: H256, message: UserOperationsWithEntryPoint) {
+        let _ = self.commands.send(NetworkCommand::Publish {
+            mempool_id,
+            message,
+        });
+    }
+
+    /// Penalizes the peer for the misbehavior (e.g. invalid user operations received from the peer outside of the
+    /// gossip), the peer is throttled and eventually banned if its score gets too low
+    pub fn report_peer(&self, peer: PeerId, action: PeerAction) {
+        let _ = self
+            .commands
+            .send(NetworkCommand::ReportPeer { peer, action });
+    }
+
+    /// Connected (and penalized) peers with their scores, empty if the network stopped
+    pub async fn peers(&self) -> Vec<PeerInfo> {
+        let (sender, receiver) = oneshot::channel();
+        if self
+            .commands
+            .send(NetworkCommand::Peers { sender })
+            .is_err()
+        {
+            return vec![];
+        }
+        receiver.await.unwrap_or_default()
+    }
+
+    /// Requests the user operations of the mempool (that are not in the local mempool) from the peer
+    pub fn request_pooled_user_ops(&self, peer: PeerId, mempool_id: H256, hashes: Vec<H256>) {
+        let _ = self.commands.send(NetworkCommand::RequestPooledUserOps {
+            peer,
+            mempool_id,
+            hashes,
+        });
+    }
+
+    pub fn respond_pooled_user_op_hashes(
+        &self,
+        request_id: InboundRequestId,
+        response: PooledUserOpHashes,
+    ) {
+        let _ = self
+            .commands
+            .send(NetworkCommand::RespondPooledUserOpHashes {
+                request_id,
+                response,
+            });
+    }
+
+    pub fn respond_pooled_user_ops_by_hash(
+        &self,
+        request_id: InboundRequestId,
+        response: PooledUserOpsByHash,
+    ) {
+        let _ = self
+            .commands
+            .send(NetworkCommand::RespondPooledUserOpsByHash {
+                request_id,
+                response,
+            });
+    }
+}
+
+/// P2P network of the shared mempool (one gossip topic per mempool)
+pub struct Network {
+    swarm: Swarm<Behaviour>,
+    /// Mempool ids and entry points of the subscribed topics
+    mempools: HashMap<TopicHash, (H256, Address)>,
+    chain_id: U256,
+    discovery: Option<Discovery>,
+    max_peers: usize,
+    /// Peers that are kept connected (with the peer id in the address)
+    static_peers: Vec<(PeerId, Multiaddr)>,
+    peers: PeerScores,
+    /// Outbound requests of the pages of the hashes and of the user operations (of the mempool)
+    hashes_requests: HashMap<OutboundRequestId, PooledUserOpHashesRequest>,
+    user_ops_requests: HashMap<OutboundRequestId, H256>,
+    /// Inbound requests waiting for the uopool to answer them
+    hashes_responses: HashMap<InboundRequestId, ResponseChannel<PooledUserOpHashes>>,
+    user_ops_responses: HashMap<InboundRequestId, ResponseChannel<PooledUserOpsByHash>>,
+    commands: mpsc::UnboundedReceiver<NetworkCommand>,
+    events: mpsc::Sender<NetworkEvent>,
+}
+
+impl Network {
+    /// Creates the network of the mempools (mempool ids and their entry points), starts listening and dials the
+    /// configured peers
+    pub fn new(
+        opts: &P2POpts,
+        chain_id: U256,
+        mempools: Vec<(H256, Address)>,
+    ) -> anyhow::Result<(Self, NetworkHandle, mpsc::Receiver<NetworkEvent>)> {
+        let keypair = Keypair::generate_secp256k1();
+        let discovery = match opts.p2p_listen_address {
+            Some(listen_address) => Some(Discovery::new(
+                enr_key(&keypair)?,
+                listen_address,
+                opts.p2p_external_address,
+                chain_id.low_u64(),
+                mempools.iter().map(|(mempool_id, _)| *mempool_id).collect(),
+                &opts.p2p_bootnodes,
+            )?),
+            None => None,
+        };
+
+        let mut swarm = SwarmBuilder::with_existing_identity(keypair)
+            .with_tokio()
+            .with_tcp(
+                tcp::Config::default().nodelay(true),
+                noise::Config::new,
+                yamux::Config::default,
+            )?
+            .with_behaviour(|keypair| {
+                Ok(Behaviour {
+                    blocked_peers: Default::default(),
+                    connection_limits: connection_limits::Behaviour::new(
+                        ConnectionLimits::default().with_max_established(Some(opts.p2p_max_peers)),
+                    ),
+                    identify: identify::Behaviour::new(
+                        identify::Config::new(IDENTIFY_PROTOCOL_VERSION.into(), keypair.public())
+                            .with_agent_version(format!(
+                                "aa-bundler/{}",
+                                env!("CARGO_PKG_VERSION")
+                            )),
+                    ),
+                    upnp: opts.p2p_upnp.then(upnp::tokio::Behaviour::default).into(),
+                    gossipsub: gossipsub()?,
+                    pooled_user_op_hashes: request_response(POOLED_USER_OP_HASHES_PROTOCOL),
+                    pooled_user_ops_by_hash: request_response(POOLED_USER_OPS_BY_HASH_PROTOCOL),
+                })
+            })
+            .map_err(|err| anyhow::anyhow!("Creating the P2P network failed: {err:?}"))?
+            .with_swarm_config(|config| {
+                config.with_idle_connection_timeout(IDLE_CONNECTION_TIMEOUT)
+            })
+            .build();
+        info!("P2P node id: {}", swarm.local_peer_id());
+
+        let mut topics = HashMap::new();
+        for (mempool_id, entry_point) in mempools {
+            let topic = topic(&mempool_id);
+            swarm
+                .behaviour_mut()
+                .gossipsub
+                .subscribe(&topic)
+                .map_err(|err| anyhow::anyhow!("Subscribing to {topic} failed: {err:?}"))?;
+            topics.insert(topic.hash(), (mempool_id, entry_point));
+        }
+
+        if let Some(listen_address) = opts.listen_multiaddr() {
+            swarm.listen_on(listen_address)?;
+        }
+        if let Some(external_address) = opts.external_multiaddr() {
+            swarm.add_external_address(external_address);
+        }
+        for peer in opts.p2p_peers.iter() {
+            if let Err(err) = swarm.dial(peer.clone()) {
+                warn!("Dialing the peer {peer} failed: {err:?}");
+            }
+        }
+        let static_peers = opts
+            .p2p_peers
+            .iter()
+            .filter_map(|address| match address.iter().last() {
+                Some(Protocol::P2p(peer_id)) => Some((peer_id, address.clone())),
+                _ => None,
+            })
+            .collect();
+
+        let (commands_sender, commands) = mpsc::unbounded_channel();
+        let (events, events_receiver) = mpsc::channel(EVENTS_CHANNEL_CAPACITY);
+        Ok((
+            Self {
+                swarm,
+                mempools: topics,
+                chain_id,
+                discovery,
+                max_peers: opts.p2p_max_peers as usize,
+                static_peers,
+                peers: PeerScores::default(),
+                hashes_requests: HashMap::new(),
+                user_ops_requests: HashMap::new(),
+                hashes_responses: HashMap::new(),
+                user_ops_responses: HashMap::new(),
+                commands,
+                events,
+            },
+            NetworkHandle {
+                commands: commands_sender,
+            },
+            events_receiver,
+        ))
+    }
+
+    pub fn local_peer_id(&self) -> &PeerId {
+        self.swarm.local_peer_id()
+    }
+
+    /// Runs the network until the shutdown future resolves
+    pub async fn run(mut self, shutdown: impl Future<Output = ()>) {
+        if let Some(discovery) = self.discovery.as_mut() {
+            if let Err(err) = discovery.start().await {
+                warn!("{err:?}, only the configured peers are connected");
+                self.discovery = None;
+            }
+        }
+
+        let mut maintenance_interval = interval(PEER_MAINTENANCE_INTERVAL);
+        maintenance_interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        tokio::pin!(shutdown);
+        loop {
+            tokio::select! {
+                _ = maintenance_interval.tick() => {
+                    self.unban_expired();
+                    self.redial_static_peers();
+                }
+                event = self.swarm.select_next_some() => self.handle_swarm_event(event),
+                Some(command) = self.commands.recv() => self.handle_command(command),
+                enrs = next_peers(&mut self.discovery) => self.dial_discovered(enrs),
+                _ = &mut shutdown => break,
+            }
+        }
+        info!("P2P network stopped");
+    }
+
+    /// Dials the discovered nodes that are not connected yet (while there are fewer peers than the target)
+    fn dial_discovered(&mut self, enrs: Vec<Enr>) {
+        for enr in enrs {
+            if self.swarm.connected_peers().count() >= self.max_peers {
+                break;
+            }
+            let (peer_id, address) = match (peer_id(&enr), multiaddr(&enr)) {
+                (Some(peer_id), Some(address)) => (peer_id, address),
+                _ => continue,
+            };
+            if peer_id == *self.swarm.local_peer_id() || self.swarm.is_connected(&peer_id) {
+                continue;
+            }
+            debug!("Dialing the discovered peer {peer_id} at {address}");
+            if let Err(err) = self
+                .swarm
+                .dial(DialOpts::peer_id(peer_id).addresses(vec![address]).build())
+            {
+                debug!("Dialing the discovered peer {peer_id} failed: {err:?}");
+            }
+        }
+    }
+
+    /// Penalizes the peer, the peer is disconnected (and its connections are denied) once it is banned
+    fn report_peer(&mut self, peer: PeerId, action: PeerAction) {
+        metrics::counter!("p2p_peer_penalties", 1, "action" => action.as_str());
+        if self.peers.report(peer, action) {
+            warn!("Banning the peer {peer} for its misbehavior");
+            self.swarm.behaviour_mut().blocked_peers.block_peer(peer);
+            let _ = self.swarm.disconnect_peer_id(peer);
+        }
+        self.record_peer_metrics();
+    }
+
+    fn unban_expired(&mut self) {
+        let unbanned = self.peers.unban_expired();
+        for peer in unbanned.iter() {
+            debug!("Ban of the peer {peer} expired");
+            self.swarm.behaviour_mut().blocked_peers.unblock_peer(*peer);
+        }
+        if !unbanned.is_empty() {
+            self.record_peer_metrics();
+        }
+    }
+
+    fn record_peer_metrics(&self) {
+        metrics::gauge!("p2p_peers_throttled", self.peers.throttled_count() as f64);
+        metrics::gauge!("p2p_peers_banned", self.peers.banned_count() as f64);
+    }
+
+    fn report_validation(
+        &mut self,
+        message_id: MessageId,
+        peer: PeerId,
+        acceptance: MessageAcceptance,
+    ) {
+        let label = match acceptance {
+            MessageAcceptance::Accept => "accept",
+            MessageAcceptance::Reject => "reject",
+            MessageAcceptance::Ignore => "ignore",
+        };
+        metrics::counter!("p2p_gossip_messages_validated", 1, "acceptance" => label);
+        // the message is not in the cache anymore if the validation took too long
+        let _ = self
+            .swarm
+            .behaviour_mut()
+            .gossipsub
+            .report_message_validation_result(&message_id, &peer, acceptance);
+    }
+
+    /// Connected peers and the penalized ones (that may be disconnected), the mempools of the peer are taken from its
+    /// ENR or else from its gossip subscriptions
+    fn peer_infos(&self) -> Vec<PeerInfo> {
+        let mut peers: Vec<PeerId> = self.swarm.connected_peers().copied().collect();
+        for peer in self.peers.penalized() {
+            if !peers.contains(peer) {
+                peers.push(*peer);
+            }
+        }
+
+        let subscriptions: HashMap<&PeerId, Vec<&TopicHash>> =
+            self.swarm.behaviour().gossipsub.all_peers().collect();
+        peers
+            .into_iter()
+            .map(|peer| {
+                let enr = self
+                    .discovery
+                    .as_ref()
+                    .and_then(|discovery| discovery.find_enr(&peer));
+                let mempools = match enr.as_ref() {
+                    Some(enr) => mempool_ids(enr),
+                    None => subscriptions
+                        .get(&peer)
+                        .map(|topics| {
+                            topics
+                                .iter()
+                                .filter_map(|topic| self.mempools.get(*topic))
+                                .map(|(mempool_id, _)| *mempool_id)
+                                .collect()
+                        })
+                        .unwrap_or_default(),
+                };
+                PeerInfo {
+                    peer_id: peer.to_string(),
+                    connected: self.swarm.is_connected(&peer),
+                    enr: enr.map(|enr| enr.to_base64()),
+                    mempools,
+                    score: self.peers.score(&peer),
+                    throttled: self.peers.is_throttled(&peer),
+                    banned: self.peers.is_banned(&peer),
+                }
+            })
+            .collect()
+    }
+
+    /// Dials the static peers that got disconnected (unless banned)
+    fn redial_static_peers(&mut self) {
+        for (peer_id, address) in self.static_peers.iter() {
+            if self.swarm.is_connected(peer_id) || self.peers.is_banned(peer_id) {
+                continue;
+            }
+            debug!("Redialing the static peer {peer_id}");
+            if let Err(err) = self.swarm.dial(
+                DialOpts::peer_id(*peer_id)
+                    .addresses(vec![address.clone()])
+                    .build(),
+            ) {
+                debug!("Redialing the static peer {peer_id} failed: {err:?}");
+            }
+        }
+    }
+
+    fn handle_command(&mut self, command: NetworkCommand) {
+        match command {
+            NetworkCommand::ReportValidation {
+                message_id,
+                peer,
+                acceptance,
+            } => {
+                // the messages are also ignored for the local reasons, the duplicates are reported by the uopool
+                if let MessageAcceptance::Reject = acceptance {
+                    self.report_peer(peer, PeerAction::InvalidUserOperation);
+                }
+                self.report_validation(message_id, peer, acceptance);
+            }
+            NetworkCommand::Publish {
+                mempool_id,
+                message,
+            } => self.publish(mempool_id, message),
+            NetworkCommand::ReportPeer { peer, action } => self.report_peer(peer, action),
+            NetworkCommand::Peers { sender } => {
+                let _ = sender.send(self.peer_infos());
+            }
+            NetworkCommand::RequestPooledUserOps {
+                peer,
+                mempool_id,
+                hashes,
+            } => {
+                for hashes in hashes.chunks(MAX_OPS_PER_REQUEST) {
+                    let request_id = self
+                        .swarm
+                        .behaviour_mut()
+                        .pooled_user_ops_by_hash
+                        .send_request(
+                            &peer,
+                            PooledUserOpsByHashRequest {
+                                hashes: hashes.to_vec(),
+                            },
+                        );
+                    self.user_ops_requests.insert(request_id, mempool_id);
+                }
+            }
+            NetworkCommand::RespondPooledUserOpHashes {
+                request_id,
+                response,
+            } => {
+                if let Some(channel) = self.hashes_responses.remove(&request_id) {
+                    // fails if the request timed out or the peer disconnected
+                    let _ = self
+                        .swarm
+                        .behaviour_mut()
+                        .pooled_user_op_hashes
+                        .send_response(channel, response);
+                }
+            }
+            NetworkCommand::RespondPooledUserOpsByHash {
+                request_id,
+                mut response,
+            } => {
+                if let Some(channel) = self.user_ops_responses.remove(&request_id) {
+                    // the user operations that don't fit into the message are left out
+                    while response.as_ssz_bytes().len() > MAX_GOSSIP_SIZE {
+                        response.user_operations.pop();
+                    }
+                    let _ = self
+                        .swarm
+                        .behaviour_mut()
+                        .pooled_user_ops_by_hash
+                        .send_response(channel, response);
+                }
+            }
+        }
+    }
+
+    fn publish(&mut self, mempool_id: H256, message: UserOperationsWithEntryPoint) {
+        let topic = match self.mempools.iter().find(|(_, (id, _))| *id == mempool_id) {
+            Some((topic, _)) => topic.clone(),
+            None => return,
+        };
+        match self
+            .swarm
+            .behaviour_mut()
+            .gossipsub
+            .publish(topic, message.as_ssz_bytes())
+        {
+            Ok(_) => {
+                metrics::counter!(
+                    "p2p_user_operations_published",
+                    message.user_operations.len() as u64
+                );
+            }
+            // the message was already gossiped by a peer
+            Err(gossipsub::PublishError::Duplicate) => {}
+            Err(err) => debug!("Publishing the user operations failed: {err:?}"),
+        }
+    }
+
+    /// Requests the first pages of the hashes of all the mempools from the newly connected peer
+    fn sync_pooled_user_ops(&mut self, peer: PeerId) {
+        let mempool_ids: Vec<H256> = self
+            .mempools
+            .values()
+            .map(|(mempool_id, _)| *mempool_id)
+            .collect();
+        for mempool_id in mempool_ids {
+            self.request_pooled_user_op_hashes(
+                peer,
+                PooledUserOpHashesRequest {
+                    mempool: mempool_id,
+                    offset: 0,
+                },
+            );
+        }
+    }
+
+    fn request_pooled_user_op_hashes(&mut self, peer: PeerId, request: PooledUserOpHashesRequest) {
+        let request_id = self
+            .swarm
+            .behaviour_mut()
+            .pooled_user_op_hashes
+            .send_request(&peer, request.clone());
+        self.hashes_requests.insert(request_id, request);
+    }
+
+    /// Passes the event to the uopool, the event is dropped if the uopool lags behind
+    fn emit(&self, event: NetworkEvent) -> bool {
+        match self.events.try_send(event) {
+            Ok(()) => true,
+            Err(_) => {
+                warn!("Too many P2P network events waiting for the uopool, dropping the event");
+                false
+            }
+        }
+    }
+
+    fn handle_pooled_user_op_hashes_event(
+        &mut self,
+        event: request_response::Event<PooledUserOpHashesRequest, PooledUserOpHashes>,
+    ) {
+        match event {
+            request_response::Event::Message {
+                peer,
+                message:
+                    request_response::Message::Request {
+                        request_id,
+                        request,
+                        channel,
+                    },
+            } => {
+                // the request fails if the channel is dropped
+                if !self.peers.is_throttled(&peer)
+                    && self.emit(NetworkEvent::PooledUserOpHashesRequest {
+                        peer,
+                        request_id,
+                        request,
+                    })
+                {
+                    self.hashes_responses.insert(request_id, channel);
+                }
+            }
+            request_response::Event::Message {
+                peer,
+                message:
+                    request_response::Message::Response {
+                        request_id,
+                        response,
+                    },
+            } => {
+                let request = match self.hashes_requests.remove(&request_id) {
+                    Some(request) => request,
+                    None => return,
+                };
+                if response.hashes.len() > MAX_OPS_PER_REQUEST {
+                    self.report_peer(peer, PeerAction::ProtocolViolation);
+                    return;
+                }
+                if response.hashes.is_empty() {
+                    return;
+                }
+
+                let offset = request.offset + response.hashes.len() as u64;
+                if response.more_flag != 0 && offset < MAX_SYNCED_USER_OPS_PER_MEMPOOL {
+                    self.request_pooled_user_op_hashes(
+                        peer,
+                        PooledUserOpHashesRequest {
+                            mempool: request.mempool,
+                            offset,
+                        },
+                    );
+                }
+                self.emit(NetworkEvent::PooledUserOpHashes {
+                    peer,
+                    mempool_id: request.mempool,
+                    hashes: response.hashes,
+                });
+            }
+            request_response::Event::OutboundFailure {
+                peer,
+                request_id,
+                error,
+            } => {
+                self.hashes_requests.remove(&request_id);
+                if let request_response::OutboundFailure::Io(_) = error {
+                    self.report_peer(peer, PeerAction::ProtocolViolation);
+                }
+                debug!("Requesting the pooled user operation hashes from the peer {peer} failed: {error}");
+            }
+            request_response::Event::InboundFailure {
+                peer,
+                request_id,
+                error,
+            } => {
+                self.hashes_responses.remove(&request_id);
+                if let request_response::InboundFailure::Io(_) = error {
+                    self.report_peer(peer, PeerAction::ProtocolViolation);
+                }
+                debug!("Responding with the pooled user operation hashes to the peer {peer} failed: {error}");
+            }
+            request_response::Event::ResponseSent { .. } => {}
+        }
+    }
+
+    fn handle_pooled_user_ops_by_hash_event(
+        &mut self,
+        event: request_response::Event<PooledUserOpsByHashRequest, PooledUserOpsByHash>,
+    ) {
+        match event {
+            request_response::Event::Message {
+                peer,
+                message:
+                    request_response::Message::Request {
+                        request_id,
+                        request,
+                        channel,
+                    },
+            } => {
+                if !self.peers.is_throttled(&peer)
+                    && self.emit(NetworkEvent::PooledUserOpsByHashRequest {
+                        peer,
+                        request_id,
+                        request,
+                    })
+                {
+                    self.user_ops_responses.insert(request_id, channel);
+                }
+            }
+            request_response::Event::Message {
+                peer,
+                message:
+                    request_response::Message::Response {
+                        request_id,
+                        response,
+                    },
+            } => {
+                let mempool_id = match self.user_ops_requests.remove(&request_id) {
+                    Some(mempool_id) => mempool_id,
+                    None => return,
+                };
+                let entry_point = match self.mempools.values().find(|(id, _)| *id == mempool_id) {
+                    Some((_, entry_point)) => *entry_point,
+                    None => return,
+                };
+                if !response.user_operations.is_empty() {
+                    self.emit(NetworkEvent::PooledUserOps {
+                        peer,
+                        mempool_id,
+                        entry_point,
+                        user_operations: response.user_operations,
+                    });
+                }
+            }
+            request_response::Event::OutboundFailure {
+                peer,
+                request_id,
+                error,
+            } => {
+                self.user_ops_requests.remove(&request_id);
+                if let request_response::OutboundFailure::Io(_) = error {
+                    self.report_peer(peer, PeerAction::ProtocolViolation);
+                }
+                debug!(
+                    "Requesting the pooled user operations from the peer {peer} failed: {error}"
+                );
+            }
+            request_response::Event::InboundFailure {
+                peer,
+                request_id,
+                error,
+            } => {
+                self.user_ops_responses.remove(&request_id);
+                if let request_response::InboundFailure::Io(_) = error {
+                    self.report_peer(peer, PeerAction::ProtocolViolation);
+                }
+                debug!(
+                    "Responding with the pooled user operations to the peer {peer} failed: {error}"
+                );
+            }
+            request_response::Event::ResponseSent { .. } => {}
+        }
+    }
+
+    fn handle_swarm_event(&mut self, event: SwarmEvent<BehaviourEvent>) {
+        match event {
+            SwarmEvent::Behaviour(BehaviourEvent::Gossipsub(gossipsub::Event::Message {
+                propagation_source,
+                message_id,
+                message,
+            })) => {
+                let acceptance = self.handle_message(
+                    propagation_source,
+                    message_id.clone(),
+                    &message.topic,
+                    &message.data,
+                );
+                if let Some(acceptance) = acceptance {
+                    self.report_validation(message_id, propagation_source, acceptance);
+                }
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::PooledUserOpHashes(event)) => {
+                self.handle_pooled_user_op_hashes_event(event)
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::PooledUserOpsByHash(event)) => {
+                self.handle_pooled_user_ops_by_hash_event(event)
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Identify(identify::Event::Received {
+                peer_id,
+                info,
+                ..
+            })) => debug!(
+                "Peer {peer_id} ({}) observed the address {}",
+                info.agent_version, info.observed_addr
+            ),
+            SwarmEvent::Behaviour(BehaviourEvent::Upnp(event)) => match event {
+                upnp::Event::NewExternalAddr(address) => {
+                    info!("UPnP mapped the external address {address}")
+                }
+                upnp::Event::ExpiredExternalAddr(address) => {
+                    debug!("UPnP mapping of the external address {address} expired")
+                }
+                upnp::Event::GatewayNotFound => warn!("UPnP gateway not found"),
+                upnp::Event::NonRoutableGateway => {
+                    warn!("UPnP gateway is not exposed to the public network")
+                }
+            },
+            SwarmEvent::ExternalAddrConfirmed { address } => {
+                info!("P2P network reachable at {address}")
+            }
+            SwarmEvent::NewListenAddr { address, .. } => {
+                info!("P2P network listening on {address}")
+            }
+            SwarmEvent::ConnectionEstablished {
+                peer_id,
+                num_established,
+                ..
+            } => {
+                debug!("Connected to the peer {peer_id}");
+                if num_established.get() == 1 {
+                    metrics::gauge!(
+                        "p2p_peers_connected",
+                        self.swarm.connected_peers().count() as f64
+                    );
+                    self.sync_pooled_user_ops(peer_id);
+                }
+            }
+            SwarmEvent::ConnectionClosed {
+                peer_id,
+                cause,
+                num_established,
+                ..
+            } => {
+                debug!("Disconnected from the peer {peer_id}: {cause:?}");
+                if num_established == 0 {
+                    metrics::gauge!(
+                        "p2p_peers_connected",
+                        self.swarm.connected_peers().count() as f64
+                    );
+                    self.peers.remove_forgiven(&peer_id);
+                }
+            }
+            SwarmEvent::OutgoingConnectionError { peer_id, error, .. } => {
+                warn!("Connecting to the peer {peer_id:?} failed: {error:?}")
+            }
+            event => trace!("P2P network event: {event:?}"),
+        }
+    }
+
+    /// Passes the gossiped user operations to the uopool for the validation, the message is rejected (or ignored)
+    /// right away if it is not valid for the topic
+    fn handle_message(
+        &mut self,
+        peer: PeerId,
+        message_id: MessageId,
+        topic: &TopicHash,
+        data: &[u8],
+    ) -> Option<MessageAcceptance> {
+        let (mempool_id, entry_point) = match self.mempools.get(topic) {
+            Some(mempool) => *mempool,
+            None => return Some(MessageAcceptance::Ignore),
+        };
+        metrics::counter!("p2p_gossip_messages_received", 1, "mempool" => format!("{mempool_id:x}"));
+        if self.peers.is_throttled(&peer) {
+            trace!("Ignoring the gossip message of the throttled peer {peer}");
+            return Some(MessageAcceptance::Ignore);
+        }
+
+        let message = match UserOperationsWithEntryPoint::from_ssz_bytes(data) {
+            Ok(message) => message,
+            Err(err) => {
+                debug!("Invalid gossip message from the peer {peer}: {err:?}");
+                self.report_peer(peer, PeerAction::ProtocolViolation);
+                return Some(MessageAcceptance::Reject);
+            }
+        };
+        if message.entry_point != entry_point || message.chain_id != self.chain_id {
+            debug!(
+                "Gossip message from the peer {peer} doesn't belong to the mempool of the topic"
+            );
+            self.report_peer(peer, PeerAction::ProtocolViolation);
+            return Some(MessageAcceptance::Reject);
+        }
+
+        match self.events.try_send(NetworkEvent::UserOperations {
+            message_id,
+            peer,
+            mempool_id,
+            message,
+        }) {
+            Ok(()) => None,
+            Err(_) => {
+                warn!("Too many gossiped user operations waiting for the validation, ignoring the message");
+                Some(MessageAcceptance::Ignore)
+            }
+        }
+    }
+}
+
+/// Next peers found by the discovery (never resolves if the discovery is disabled)
+async fn next_peers(discovery: &mut Option<Discovery>) -> Vec<Enr> {
+    match discovery {
+        Some(discovery) => discovery.next_peers().await,
+        None => pending().await,
+    }
+}
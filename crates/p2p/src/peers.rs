@@ -0,0 +1,189 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use libp2p::PeerId;
+
+/// Messages of the peers with a lower score are ignored
+const THROTTLE_THRESHOLD: f64 = -50.0;
+/// Peers with a lower score are disconnected and banned
+const BAN_THRESHOLD: f64 = -100.0;
+/// Banned peers can connect again after the duration
+const BAN_DURATION: Duration = Duration::from_secs(60 * 60);
+/// Penalties are forgiven over time, the score halves in the period
+const SCORE_HALF_LIFE: Duration = Duration::from_secs(10 * 60);
+
+/// Misbehavior of the peer
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PeerAction {
+    /// User operation that failed the verification
+    InvalidUserOperation,
+    /// User operation that is already in the mempool
+    DuplicateUserOperation,
+    /// Message that is not valid according to the protocol (decoding, wrong mempool, too large)
+    ProtocolViolation,
+}
+
+impl PeerAction {
+    fn penalty(&self) -> f64 {
+        match self {
+            PeerAction::InvalidUserOperation => 10.0,
+            PeerAction::DuplicateUserOperation => 1.0,
+            PeerAction::ProtocolViolation => 20.0,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PeerAction::InvalidUserOperation => "invalid_user_operation",
+            PeerAction::DuplicateUserOperation => "duplicate_user_operation",
+            PeerAction::ProtocolViolation => "protocol_violation",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Score {
+    value: f64,
+    updated_at: Instant,
+}
+
+impl Score {
+    fn decayed(&self, now: Instant) -> f64 {
+        let half_lives = now.saturating_duration_since(self.updated_at).as_secs_f64()
+            / SCORE_HALF_LIFE.as_secs_f64();
+        self.value * 0.5f64.powf(half_lives)
+    }
+}
+
+/// Scores of the peers, lowered by their misbehavior
+#[derive(Debug, Default)]
+pub struct PeerScores {
+    scores: HashMap<PeerId, Score>,
+    /// Banned peers and the time of their ban
+    banned: HashMap<PeerId, Instant>,
+}
+
+impl PeerScores {
+    pub fn score(&self, peer: &PeerId) -> f64 {
+        self.scores
+            .get(peer)
+            .map(|score| score.decayed(Instant::now()))
+            .unwrap_or_default()
+    }
+
+    pub fn is_throttled(&self, peer: &PeerId) -> bool {
+        self.score(peer) < THROTTLE_THRESHOLD
+    }
+
+    pub fn is_banned(&self, peer: &PeerId) -> bool {
+        self.banned.contains_key(peer)
+    }
+
+    /// Penalizes the peer for the misbehavior, returns true if the peer is banned now
+    pub fn report(&mut self, peer: PeerId, action: PeerAction) -> bool {
+        let now = Instant::now();
+        let score = self.scores.entry(peer).or_insert(Score {
+            value: 0.0,
+            updated_at: now,
+        });
+        *score = Score {
+            value: score.decayed(now) - action.penalty(),
+            updated_at: now,
+        };
+
+        if score.value < BAN_THRESHOLD && !self.banned.contains_key(&peer) {
+            self.banned.insert(peer, now);
+            return true;
+        }
+        false
+    }
+
+    pub fn throttled_count(&self) -> usize {
+        self.scores
+            .keys()
+            .filter(|peer| self.is_throttled(peer))
+            .count()
+    }
+
+    pub fn banned_count(&self) -> usize {
+        self.banned.len()
+    }
+
+    /// Lifts the bans that expired (the peers start with a clean score), returns the unbanned peers
+    pub fn unban_expired(&mut self) -> Vec<PeerId> {
+        let now = Instant::now();
+        let expired: Vec<PeerId> = self
+            .banned
+            .iter()
+            .filter(|(_, banned_at)| now.saturating_duration_since(**banned_at) >= BAN_DURATION)
+            .map(|(peer, _)| *peer)
+            .collect();
+        for peer in expired.iter() {
+            self.banned.remove(peer);
+            self.scores.remove(peer);
+        }
+        expired
+    }
+
+    /// Forgets the score of the disconnected peer once its penalties are forgiven
+    pub fn remove_forgiven(&mut self, peer: &PeerId) {
+        if !self.is_banned(peer) && self.score(peer) > -1.0 {
+            self.scores.remove(peer);
+        }
+    }
+
+    /// Peers that were penalized (or banned) and are not forgiven yet
+    pub fn penalized(&self) -> impl Iterator<Item = &PeerId> {
+        self.scores.keys().chain(
+            self.banned
+                .keys()
+                .filter(|peer| !self.scores.contains_key(*peer)),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn peer_banning() {
+        let mut scores = PeerScores::default();
+        let peer = PeerId::random();
+        let other_peer = PeerId::random();
+
+        assert!(!scores.report(peer, PeerAction::DuplicateUserOperation));
+        assert!(scores.score(&peer) < 0.0);
+        assert!(!scores.is_throttled(&peer));
+
+        for _ in 0..3 {
+            assert!(!scores.report(peer, PeerAction::ProtocolViolation));
+        }
+        assert!(scores.is_throttled(&peer));
+        assert!(!scores.is_banned(&peer));
+
+        let banned: Vec<bool> = (0..5)
+            .map(|_| scores.report(peer, PeerAction::InvalidUserOperation))
+            .collect();
+        assert_eq!(banned, vec![false, false, false, true, false]);
+        assert!(scores.is_banned(&peer));
+        assert!(scores.unban_expired().is_empty());
+
+        assert_eq!(scores.penalized().collect::<Vec<_>>(), vec![&peer]);
+        assert_eq!(scores.score(&other_peer), 0.0);
+        assert!(!scores.is_banned(&other_peer));
+    }
+
+    #[test]
+    fn score_decay() {
+        let now = Instant::now();
+        let score = Score {
+            value: -80.0,
+            updated_at: now,
+        };
+        assert!((score.decayed(now + SCORE_HALF_LIFE) + 40.0).abs() < 1e-9);
+        assert!((score.decayed(now + 2 * SCORE_HALF_LIFE) + 20.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,278 @@
+use std::{
+    io::{self, Read},
+    marker::PhantomData,
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use libp2p::{
+    request_response::{self, Codec, ProtocolSupport},
+    StreamProtocol,
+};
+use snap::{read::FrameDecoder, write::FrameEncoder};
+use ssz::{Decode, DecodeError, Encode};
+
+use crate::{
+    gossipsub::MAX_GOSSIP_SIZE,
+    types::{
+        Metadata, PooledUserOpHashes, PooledUserOpHashesRequest, PooledUserOpsByHash,
+        PooledUserOpsByHashRequest, Status,
+    },
+};
+
+/// Protocol of the pages of the hashes of the user operations in the mempool
+pub const POOLED_USER_OP_HASHES_PROTOCOL: &str =
+    "/account_abstraction/req/pooled_user_op_hashes/1/ssz_snappy";
+/// Protocol of the user operations by their hashes
+pub const POOLED_USER_OPS_BY_HASH_PROTOCOL: &str =
+    "/account_abstraction/req/pooled_user_ops_by_hash/1/ssz_snappy";
+
+/// Requests without the response are failed after the timeout
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// Result code of the successful response
+const SUCCESS: u8 = 0;
+/// Maximum length of the unsigned LEB128 varint of the message length
+const MAX_VARINT_LENGTH: usize = 10;
+
+pub type PooledUserOpHashesBehaviour =
+    request_response::Behaviour<SszSnappyCodec<PooledUserOpHashesRequest, PooledUserOpHashes>>;
+pub type PooledUserOpsByHashBehaviour =
+    request_response::Behaviour<SszSnappyCodec<PooledUserOpsByHashRequest, PooledUserOpsByHash>>;
+
+/// Message of the request-response protocols (SSZ encoded)
+pub trait SszMessage: Sized {
+    fn to_ssz_bytes(&self) -> Vec<u8>;
+    fn from_ssz(bytes: &[u8]) -> Result<Self, DecodeError>;
+}
+
+macro_rules! ssz_message {
+    ($($message:ty),*) => {
+        $(
+            impl SszMessage for $message {
+                fn to_ssz_bytes(&self) -> Vec<u8> {
+                    self.as_ssz_bytes()
+                }
+
+                fn from_ssz(bytes: &[u8]) -> Result<Self, DecodeError> {
+                    Self::from_ssz_bytes(bytes)
+                }
+            }
+        )*
+    };
+}
+
+ssz_message!(
+    PooledUserOpHashesRequest,
+    PooledUserOpHashes,
+    PooledUserOpsByHashRequest,
+    PooledUserOpsByHash,
+    Status,
+    Metadata
+);
+
+/// Codec of the spec: the request is the varint length of the SSZ encoded message followed by the snappy frames
+/// of the message, the response is prefixed with the result code
+pub struct SszSnappyCodec<Req, Resp>(PhantomData<(Req, Resp)>);
+
+// the derives would require the messages to be cloneable
+impl<Req, Resp> Clone for SszSnappyCodec<Req, Resp> {
+    fn clone(&self) -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl<Req, Resp> Default for SszSnappyCodec<Req, Resp> {
+    fn default() -> Self {
+        Self(PhantomData)
+    }
+}
+
+#[async_trait]
+impl<Req, Resp> Codec for SszSnappyCodec<Req, Resp>
+where
+    Req: SszMessage + Send,
+    Resp: SszMessage + Send,
+{
+    type Protocol = StreamProtocol;
+    type Request = Req;
+    type Response = Resp;
+
+    async fn read_request<T>(&mut self, protocol: &StreamProtocol, io: &mut T) -> io::Result<Req>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        let mut data = vec![];
+        read_limited(io, &mut data).await?;
+        record_received(protocol, &data);
+        decode(&data)
+    }
+
+    async fn read_response<T>(&mut self, protocol: &StreamProtocol, io: &mut T) -> io::Result<Resp>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        let mut data = vec![];
+        read_limited(io, &mut data).await?;
+        record_received(protocol, &data);
+        match data.split_first() {
+            Some((&SUCCESS, data)) => decode(data),
+            Some((code, _)) => Err(invalid_data(format!(
+                "Request failed with the result code {code}"
+            ))),
+            None => Err(io::ErrorKind::UnexpectedEof.into()),
+        }
+    }
+
+    async fn write_request<T>(
+        &mut self,
+        protocol: &StreamProtocol,
+        io: &mut T,
+        req: Req,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        let data = encode(&req)?;
+        record_sent(protocol, &data);
+        io.write_all(&data).await?;
+        io.close().await
+    }
+
+    async fn write_response<T>(
+        &mut self,
+        protocol: &StreamProtocol,
+        io: &mut T,
+        res: Resp,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        let mut data = vec![SUCCESS];
+        data.extend(encode(&res)?);
+        record_sent(protocol, &data);
+        io.write_all(&data).await?;
+        io.close().await
+    }
+}
+
+/// Request-response behaviour of the protocol (both inbound and outbound)
+pub fn request_response<Req, Resp>(
+    protocol: &'static str,
+) -> request_response::Behaviour<SszSnappyCodec<Req, Resp>>
+where
+    Req: SszMessage + Send + 'static,
+    Resp: SszMessage + Send + 'static,
+{
+    request_response::Behaviour::new(
+        [(StreamProtocol::new(protocol), ProtocolSupport::Full)],
+        request_response::Config::default().with_request_timeout(REQUEST_TIMEOUT),
+    )
+}
+
+fn record_received(protocol: &StreamProtocol, data: &[u8]) {
+    metrics::counter!("p2p_bytes_received", data.len() as u64, "protocol" => protocol.to_string());
+}
+
+fn record_sent(protocol: &StreamProtocol, data: &[u8]) {
+    metrics::counter!("p2p_bytes_sent", data.len() as u64, "protocol" => protocol.to_string());
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Reads the whole stream (closed by the sender), at most as much as the compressed message of the maximum size
+async fn read_limited<T>(io: &mut T, data: &mut Vec<u8>) -> io::Result<()>
+where
+    T: AsyncRead + Unpin + Send,
+{
+    // snappy frames of the incompressible data are slightly larger than the data
+    let limit = 1 + MAX_VARINT_LENGTH + snap::raw::max_compress_len(MAX_GOSSIP_SIZE) + 1024;
+    io.take(limit as u64).read_to_end(data).await?;
+    Ok(())
+}
+
+fn encode<M: SszMessage>(message: &M) -> io::Result<Vec<u8>> {
+    let ssz = message.to_ssz_bytes();
+    if ssz.len() > MAX_GOSSIP_SIZE {
+        return Err(invalid_data(format!(
+            "Message is larger than {MAX_GOSSIP_SIZE} bytes"
+        )));
+    }
+
+    let mut data = vec![];
+    let mut len = ssz.len();
+    loop {
+        let byte = (len & 0x7f) as u8;
+        len >>= 7;
+        if len == 0 {
+            data.push(byte);
+            break;
+        }
+        data.push(byte | 0x80);
+    }
+
+    let mut encoder = FrameEncoder::new(data);
+    io::Write::write_all(&mut encoder, &ssz)?;
+    encoder.into_inner().map_err(|err| err.into_error())
+}
+
+fn decode<M: SszMessage>(data: &[u8]) -> io::Result<M> {
+    let mut len = 0usize;
+    let mut prefix = 0;
+    loop {
+        let byte = match data.get(prefix) {
+            Some(byte) if prefix < MAX_VARINT_LENGTH => *byte,
+            _ => return Err(invalid_data("Invalid length prefix".into())),
+        };
+        len |= ((byte & 0x7f) as usize) << (7 * prefix);
+        prefix += 1;
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+    if len > MAX_GOSSIP_SIZE {
+        return Err(invalid_data(format!(
+            "Message is larger than {MAX_GOSSIP_SIZE} bytes"
+        )));
+    }
+
+    let mut ssz = vec![0; len];
+    FrameDecoder::new(&data[prefix..]).read_exact(&mut ssz)?;
+    M::from_ssz(&ssz).map_err(|err| invalid_data(format!("Invalid SSZ message: {err:?}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aa_bundler_primitives::UserOperation;
+    use ethers::types::H256;
+
+    #[test]
+    fn ssz_snappy_encoding() {
+        let request = PooledUserOpHashesRequest {
+            mempool: H256::random(),
+            offset: 1024,
+        };
+        let data = encode(&request).unwrap();
+        assert_eq!(data[0] as usize, request.as_ssz_bytes().len());
+        assert_eq!(decode::<PooledUserOpHashesRequest>(&data).unwrap(), request);
+
+        let response = PooledUserOpsByHash {
+            user_operations: vec![
+                UserOperation::random(),
+                UserOperation {
+                    call_data: vec![1; 300].into(),
+                    ..UserOperation::random()
+                },
+            ],
+        };
+        let data = encode(&response).unwrap();
+        assert_eq!(decode::<PooledUserOpsByHash>(&data).unwrap(), response);
+
+        // the length prefix must match the message
+        assert!(decode::<PooledUserOpsByHash>(&data[..data.len() - 1]).is_err());
+        assert!(decode::<PooledUserOpHashes>(&[0x80; 11]).is_err());
+    }
+}
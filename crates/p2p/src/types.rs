@@ -0,0 +1,278 @@
+use aa_bundler_primitives::UserOperation;
+use ethers::types::{Address, H256, U256};
+use ssz::{Decode, DecodeError, Encode};
+use ssz_derive::{Decode, Encode};
+
+/// Maximum number of the user operations (or their hashes) of the gossip and the request-response messages
+pub const MAX_OPS_PER_REQUEST: usize = 4096;
+
+/// SSZ container of the user operation (the dynamic fields are byte lists)
+#[derive(Encode, Decode)]
+struct UserOperationSsz {
+    sender: Address,
+    nonce: U256,
+    init_code: Vec<u8>,
+    call_data: Vec<u8>,
+    call_gas_limit: U256,
+    verification_gas_limit: U256,
+    pre_verification_gas: U256,
+    max_fee_per_gas: U256,
+    max_priority_fee_per_gas: U256,
+    paymaster_and_data: Vec<u8>,
+    signature: Vec<u8>,
+}
+
+impl From<&UserOperation> for UserOperationSsz {
+    fn from(user_operation: &UserOperation) -> Self {
+        Self {
+            sender: user_operation.sender,
+            nonce: user_operation.nonce,
+            init_code: user_operation.init_code.to_vec(),
+            call_data: user_operation.call_data.to_vec(),
+            call_gas_limit: user_operation.call_gas_limit,
+            verification_gas_limit: user_operation.verification_gas_limit,
+            pre_verification_gas: user_operation.pre_verification_gas,
+            max_fee_per_gas: user_operation.max_fee_per_gas,
+            max_priority_fee_per_gas: user_operation.max_priority_fee_per_gas,
+            paymaster_and_data: user_operation.paymaster_and_data.to_vec(),
+            signature: user_operation.signature.to_vec(),
+        }
+    }
+}
+
+impl From<UserOperationSsz> for UserOperation {
+    fn from(user_operation: UserOperationSsz) -> Self {
+        Self {
+            sender: user_operation.sender,
+            nonce: user_operation.nonce,
+            init_code: user_operation.init_code.into(),
+            call_data: user_operation.call_data.into(),
+            call_gas_limit: user_operation.call_gas_limit,
+            verification_gas_limit: user_operation.verification_gas_limit,
+            pre_verification_gas: user_operation.pre_verification_gas,
+            max_fee_per_gas: user_operation.max_fee_per_gas,
+            max_priority_fee_per_gas: user_operation.max_priority_fee_per_gas,
+            paymaster_and_data: user_operation.paymaster_and_data.into(),
+            signature: user_operation.signature.into(),
+        }
+    }
+}
+
+/// SSZ encoding of the user operation (the container of the spec)
+pub fn user_operation_ssz_bytes(user_operation: &UserOperation) -> Vec<u8> {
+    UserOperationSsz::from(user_operation).as_ssz_bytes()
+}
+
+pub fn user_operation_from_ssz_bytes(bytes: &[u8]) -> Result<UserOperation, DecodeError> {
+    UserOperationSsz::from_ssz_bytes(bytes).map(Into::into)
+}
+
+/// SSZ container of the gossip message, the block hash is a `uint256` in the spec
+#[derive(Encode, Decode)]
+struct UserOperationsWithEntryPointSsz {
+    entry_point_contract: Address,
+    verified_at_block_hash: U256,
+    chain_id: U256,
+    user_operations: Vec<UserOperationSsz>,
+}
+
+/// Gossip message of the shared mempool: user operations of the entry point, verified at the block
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UserOperationsWithEntryPoint {
+    pub entry_point: Address,
+    pub verified_at_block_hash: H256,
+    pub chain_id: U256,
+    pub user_operations: Vec<UserOperation>,
+}
+
+impl UserOperationsWithEntryPoint {
+    pub fn as_ssz_bytes(&self) -> Vec<u8> {
+        UserOperationsWithEntryPointSsz {
+            entry_point_contract: self.entry_point,
+            verified_at_block_hash: U256::from_big_endian(self.verified_at_block_hash.as_bytes()),
+            chain_id: self.chain_id,
+            user_operations: self.user_operations.iter().map(Into::into).collect(),
+        }
+        .as_ssz_bytes()
+    }
+
+    pub fn from_ssz_bytes(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let message = UserOperationsWithEntryPointSsz::from_ssz_bytes(bytes)?;
+        if message.user_operations.len() > MAX_OPS_PER_REQUEST {
+            return Err(DecodeError::BytesInvalid(format!(
+                "More than {MAX_OPS_PER_REQUEST} user operations"
+            )));
+        }
+
+        let mut verified_at_block_hash = H256::zero();
+        message
+            .verified_at_block_hash
+            .to_big_endian(verified_at_block_hash.as_bytes_mut());
+        Ok(Self {
+            entry_point: message.entry_point_contract,
+            verified_at_block_hash,
+            chain_id: message.chain_id,
+            user_operations: message
+                .user_operations
+                .into_iter()
+                .map(Into::into)
+                .collect(),
+        })
+    }
+}
+
+/// Request of the page (starting at the offset) of the hashes of the user operations in the peer's mempool
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+pub struct PooledUserOpHashesRequest {
+    pub mempool: H256,
+    pub offset: u64,
+}
+
+/// Page of the hashes of the user operations in the mempool, `more_flag` is non-zero if there are more pages
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+pub struct PooledUserOpHashes {
+    pub more_flag: u64,
+    pub hashes: Vec<H256>,
+}
+
+/// Request of the user operations (of any of the mempools) by their hashes
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+pub struct PooledUserOpsByHashRequest {
+    pub hashes: Vec<H256>,
+}
+
+/// Status of the node exchanged with the peer on the connection (the peers of other chains are disconnected)
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+pub struct Status {
+    pub chain_id: u64,
+    pub block_hash: H256,
+    pub block_number: u64,
+}
+
+/// Metadata of the node, the sequence number is increased whenever the supported mempools change
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+pub struct Metadata {
+    pub seq_number: u64,
+    pub supported_mempools: Vec<H256>,
+}
+
+/// User operations that the peer has of the requested ones
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PooledUserOpsByHash {
+    pub user_operations: Vec<UserOperation>,
+}
+
+impl PooledUserOpsByHash {
+    pub fn as_ssz_bytes(&self) -> Vec<u8> {
+        self.user_operations
+            .iter()
+            .map(UserOperationSsz::from)
+            .collect::<Vec<_>>()
+            .as_ssz_bytes()
+    }
+
+    pub fn from_ssz_bytes(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let user_operations = Vec::<UserOperationSsz>::from_ssz_bytes(bytes)?;
+        if user_operations.len() > MAX_OPS_PER_REQUEST {
+            return Err(DecodeError::BytesInvalid(format!(
+                "More than {MAX_OPS_PER_REQUEST} user operations"
+            )));
+        }
+        Ok(Self {
+            user_operations: user_operations.into_iter().map(Into::into).collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user_operation() -> UserOperation {
+        UserOperation {
+            sender: Address::repeat_byte(0x11),
+            nonce: 1.into(),
+            init_code: vec![].into(),
+            call_data: vec![0xaa, 0xbb].into(),
+            call_gas_limit: 2.into(),
+            verification_gas_limit: 3.into(),
+            pre_verification_gas: 4.into(),
+            max_fee_per_gas: 5.into(),
+            max_priority_fee_per_gas: 6.into(),
+            paymaster_and_data: vec![].into(),
+            signature: vec![0xcc].into(),
+        }
+    }
+
+    fn uint256(value: u8) -> Vec<u8> {
+        let mut bytes = vec![0; 32];
+        bytes[0] = value;
+        bytes
+    }
+
+    #[test]
+    fn user_operation_ssz() {
+        // fixed part: sender, nonce, the offsets of the byte lists and the gas fields (little-endian uint256)
+        let mut expected = vec![0x11; 20];
+        expected.extend(uint256(1));
+        expected.extend(228u32.to_le_bytes());
+        expected.extend(228u32.to_le_bytes());
+        for value in 2..=6 {
+            expected.extend(uint256(value));
+        }
+        expected.extend(230u32.to_le_bytes());
+        expected.extend(230u32.to_le_bytes());
+        expected.extend([0xaa, 0xbb, 0xcc]);
+
+        let user_operation = user_operation();
+        let bytes = user_operation_ssz_bytes(&user_operation);
+        assert_eq!(bytes, expected);
+        assert_eq!(
+            user_operation_from_ssz_bytes(&bytes).unwrap(),
+            user_operation
+        );
+        assert!(user_operation_from_ssz_bytes(&bytes[..200]).is_err());
+    }
+
+    #[test]
+    fn messages_ssz() {
+        let message = UserOperationsWithEntryPoint {
+            entry_point: Address::random(),
+            verified_at_block_hash: H256::random(),
+            chain_id: 1337.into(),
+            user_operations: vec![user_operation(), UserOperation::random()],
+        };
+        assert_eq!(
+            UserOperationsWithEntryPoint::from_ssz_bytes(&message.as_ssz_bytes()).unwrap(),
+            message
+        );
+
+        let hashes = PooledUserOpHashes {
+            more_flag: 1,
+            hashes: vec![H256::repeat_byte(1), H256::repeat_byte(2)],
+        };
+        let bytes = hashes.as_ssz_bytes();
+        // more_flag, the offset of the list and the hashes
+        assert_eq!(bytes.len(), 8 + 4 + 2 * 32);
+        assert_eq!(&bytes[..12], &[1, 0, 0, 0, 0, 0, 0, 0, 12, 0, 0, 0]);
+        assert_eq!(PooledUserOpHashes::from_ssz_bytes(&bytes).unwrap(), hashes);
+
+        let status = Status {
+            chain_id: 1,
+            block_hash: H256::random(),
+            block_number: 17_000_000,
+        };
+        let bytes = status.as_ssz_bytes();
+        assert_eq!(bytes.len(), 8 + 32 + 8);
+        assert_eq!(Status::from_ssz_bytes(&bytes).unwrap(), status);
+
+        let metadata = Metadata {
+            seq_number: 2,
+            supported_mempools: vec![H256::random()],
+        };
+        assert_eq!(
+            Metadata::from_ssz_bytes(&metadata.as_ssz_bytes()).unwrap(),
+            metadata
+        );
+    }
+}
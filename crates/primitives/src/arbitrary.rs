@@ -0,0 +1,142 @@
+//! `Arbitrary` of the core types, for the property tests and the fuzz targets (feature `arbitrary`)
+
+use arbitrary::{Arbitrary, Result, Unstructured};
+use ethers::types::{Address, Bytes, H256, U256};
+
+use crate::{CodeHash, ReputationEntry, ReputationStatus, UserOperation};
+
+fn address(u: &mut Unstructured<'_>) -> Result<Address> {
+    Ok(Address::from(<[u8; 20]>::arbitrary(u)?))
+}
+
+fn h256(u: &mut Unstructured<'_>) -> Result<H256> {
+    Ok(H256::from(<[u8; 32]>::arbitrary(u)?))
+}
+
+fn u256(u: &mut Unstructured<'_>) -> Result<U256> {
+    Ok(U256::from_big_endian(&<[u8; 32]>::arbitrary(u)?))
+}
+
+fn bytes(u: &mut Unstructured<'_>) -> Result<Bytes> {
+    Ok(Vec::<u8>::arbitrary(u)?.into())
+}
+
+impl<'a> Arbitrary<'a> for UserOperation {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self {
+            sender: address(u)?,
+            nonce: u256(u)?,
+            init_code: bytes(u)?,
+            call_data: bytes(u)?,
+            call_gas_limit: u256(u)?,
+            verification_gas_limit: u256(u)?,
+            pre_verification_gas: u256(u)?,
+            max_fee_per_gas: u256(u)?,
+            max_priority_fee_per_gas: u256(u)?,
+            paymaster_and_data: bytes(u)?,
+            signature: bytes(u)?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for CodeHash {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self {
+            address: address(u)?,
+            hash: h256(u)?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for ReputationStatus {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(*u.choose(&[
+            ReputationStatus::OK,
+            ReputationStatus::THROTTLED,
+            ReputationStatus::BANNED,
+        ])?)
+    }
+}
+
+impl<'a> Arbitrary<'a> for ReputationEntry {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Self {
+            address: address(u)?,
+            uo_seen: u.arbitrary()?,
+            uo_included: u.arbitrary()?,
+            status: u.arbitrary()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::{
+        abi::{AbiDecode, AbiEncode},
+        utils::rlp,
+    };
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::PackedUserOperation;
+
+    fn from_bytes<T: for<'a> Arbitrary<'a>>(data: &[u8]) -> Option<T> {
+        T::arbitrary(&mut Unstructured::new(data)).ok()
+    }
+
+    proptest! {
+        #[test]
+        fn user_operation_round_trip(data in any::<Vec<u8>>()) {
+            let Some(user_operation) = from_bytes::<UserOperation>(&data) else {
+                return Ok(());
+            };
+
+            let encoded = user_operation.clone().encode();
+            prop_assert_eq!(&UserOperation::decode(encoded).unwrap(), &user_operation);
+
+            let json = serde_json::to_string(&user_operation).unwrap();
+            prop_assert_eq!(&serde_json::from_str::<UserOperation>(&json).unwrap(), &user_operation);
+
+            if let Ok(packed) = PackedUserOperation::try_from(user_operation.clone()) {
+                let rlp_encoded = rlp::encode(&packed);
+                prop_assert_eq!(&rlp::decode::<PackedUserOperation>(&rlp_encoded).unwrap(), &packed);
+                prop_assert_eq!(&UserOperation::try_from(packed).unwrap(), &user_operation);
+            }
+        }
+
+        #[test]
+        fn user_operation_hash(data in any::<Vec<u8>>(), signature in any::<Vec<u8>>()) {
+            let Some(user_operation) = from_bytes::<UserOperation>(&data) else {
+                return Ok(());
+            };
+            let entry_point = Address::repeat_byte(1);
+            let chain_id = U256::from(1);
+
+            // the signature isn't part of the hash
+            let resigned = UserOperation {
+                signature: signature.into(),
+                ..user_operation.clone()
+            };
+            prop_assert_eq!(
+                user_operation.hash(&entry_point, &chain_id),
+                resigned.hash(&entry_point, &chain_id)
+            );
+            prop_assert_ne!(
+                user_operation.hash(&entry_point, &chain_id),
+                user_operation.hash(&entry_point, &(chain_id + 1))
+            );
+        }
+
+        #[test]
+        fn code_hash_and_reputation_entry_round_trip(data in any::<Vec<u8>>()) {
+            let mut u = Unstructured::new(&data);
+            let (Ok(code_hash), Ok(entry)) = (CodeHash::arbitrary(&mut u), ReputationEntry::arbitrary(&mut u)) else {
+                return Ok(());
+            };
+
+            prop_assert_eq!(&CodeHash::decode(code_hash.clone().encode()).unwrap(), &code_hash);
+            let json = serde_json::to_string(&entry).unwrap();
+            prop_assert_eq!(serde_json::from_str::<ReputationEntry>(&json).unwrap(), entry);
+        }
+    }
+}
@@ -0,0 +1,177 @@
+use std::collections::BTreeMap;
+
+use ethers::{
+    types::{spoof, Address, Bytes, RecoveryMessage, Signature, SignatureError, H256, U256, U64},
+    utils::{keccak256, rlp::RlpStream},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::UserOperation;
+
+/// Prefix of the message signed by the authority (EIP-7702)
+const AUTHORIZATION_MAGIC: u8 = 0x05;
+/// Prefix of the delegation designator that becomes the code of the authority (EIP-7702)
+const DELEGATION_PREFIX: [u8; 3] = [0xef, 0x01, 0x00];
+
+/// Signed EIP-7702 authorization, which delegates the code of the authority (EOA) to the address
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Authorization {
+    pub chain_id: U256,
+    pub address: Address,
+    pub nonce: U256,
+    pub y_parity: U64,
+    pub r: U256,
+    pub s: U256,
+}
+
+impl Authorization {
+    /// Hash that is signed by the authority: `keccak256(0x05 || rlp([chain_id, address, nonce]))`
+    pub fn signature_hash(&self) -> H256 {
+        let mut stream = RlpStream::new_list(3);
+        stream.append(&self.chain_id);
+        stream.append(&self.address);
+        stream.append(&self.nonce);
+        let mut message = vec![AUTHORIZATION_MAGIC];
+        message.extend_from_slice(&stream.out());
+        keccak256(message).into()
+    }
+
+    /// Address of the account that signed the authorization
+    pub fn authority(&self) -> Result<Address, SignatureError> {
+        Signature {
+            r: self.r,
+            s: self.s,
+            v: self.y_parity.as_u64() + 27,
+        }
+        .recover(RecoveryMessage::Hash(self.signature_hash()))
+    }
+
+    /// Code of the authority once the authorization is applied: `0xef0100 || address`
+    pub fn delegation_code(&self) -> Bytes {
+        [&DELEGATION_PREFIX[..], self.address.as_bytes()]
+            .concat()
+            .into()
+    }
+
+    /// Sets the code of the authority to the delegation in the state override, so that the user operation can be
+    /// simulated before the authorization is applied on chain
+    pub fn apply(&self, authority: Address, state_override: &mut spoof::State) {
+        state_override
+            .account(authority)
+            .code(self.delegation_code());
+    }
+
+    pub fn rlp_append(&self, stream: &mut RlpStream) {
+        stream.begin_list(6);
+        stream.append(&self.chain_id);
+        stream.append(&self.address);
+        stream.append(&self.nonce);
+        stream.append(&self.y_parity);
+        stream.append(&self.r);
+        stream.append(&self.s);
+    }
+}
+
+/// User operation as received over JSON-RPC, with the optional EIP-7702 authorization of the sender
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserOperationWithAuthorization {
+    #[serde(flatten)]
+    pub user_operation: UserOperation,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub eip7702_auth: Option<Authorization>,
+}
+
+/// Authorizations of user operations (by sender) that are sent in the same transaction. Each sender can
+/// have only one authorization, since a later one would override the delegation of the earlier one.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AuthorizationList(BTreeMap<Address, Authorization>);
+
+impl AuthorizationList {
+    /// Adds the authorization of the sender, returns false if it conflicts with the sender's existing authorization
+    pub fn add(&mut self, sender: Address, authorization: Authorization) -> bool {
+        match self.0.get(&sender) {
+            Some(existing) => *existing == authorization,
+            None => {
+                self.0.insert(sender, authorization);
+                true
+            }
+        }
+    }
+
+    pub fn get(&self, sender: &Address) -> Option<&Authorization> {
+        self.0.get(sender)
+    }
+
+    /// Authorizations of the given senders (in order, without duplicates)
+    pub fn for_senders<'a, I: IntoIterator<Item = &'a Address>>(
+        &self,
+        senders: I,
+    ) -> Vec<Authorization> {
+        let mut authorizations: BTreeMap<Address, Authorization> = BTreeMap::new();
+        for sender in senders {
+            if let Some(authorization) = self.0.get(sender) {
+                authorizations.insert(*sender, authorization.clone());
+            }
+        }
+        authorizations.into_values().collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::signers::{LocalWallet, Signer};
+
+    fn authorization(wallet: &LocalWallet, address: Address, nonce: u64) -> Authorization {
+        let mut authorization = Authorization {
+            chain_id: U256::from(1),
+            address,
+            nonce: nonce.into(),
+            y_parity: U64::zero(),
+            r: U256::zero(),
+            s: U256::zero(),
+        };
+        let signature = wallet
+            .sign_hash(authorization.signature_hash())
+            .expect("Signing must succeed");
+        authorization.y_parity = (signature.v - 27).into();
+        authorization.r = signature.r;
+        authorization.s = signature.s;
+        authorization
+    }
+
+    #[test]
+    fn authorization_list() {
+        let wallet = LocalWallet::new(&mut ethers::core::rand::thread_rng());
+        let delegate = Address::random();
+        let auth = authorization(&wallet, delegate, 0);
+        assert_eq!(auth.authority().unwrap(), wallet.address());
+
+        let mut authorizations = AuthorizationList::default();
+        assert!(authorizations.add(wallet.address(), auth.clone()));
+        assert!(authorizations.add(wallet.address(), auth.clone()));
+        assert!(!authorizations.add(wallet.address(), authorization(&wallet, delegate, 1)));
+        assert_eq!(
+            authorizations.for_senders(&[wallet.address(), wallet.address(), Address::random()]),
+            vec![auth.clone()]
+        );
+
+        let delegation_code = auth.delegation_code();
+        assert_eq!(delegation_code.len(), 23);
+        assert_eq!(&delegation_code[..3], &[0xef, 0x01, 0x00]);
+        assert_eq!(&delegation_code[3..], delegate.as_bytes());
+        let mut state_override = spoof::State::default();
+        auth.apply(wallet.address(), &mut state_override);
+        assert_eq!(
+            serde_json::to_value(&state_override).unwrap()[format!("{:?}", wallet.address())]
+                ["code"],
+            serde_json::to_value(&delegation_code).unwrap()
+        );
+    }
+}
@@ -1,4 +1,7 @@
-use serde::Deserialize;
+use ethers::types::{Address, H256, U256, U64};
+use serde::{Deserialize, Serialize};
+
+use crate::UserOperationHash;
 
 #[derive(Debug, Deserialize)]
 pub enum Mode {
@@ -9,3 +12,51 @@ pub enum Mode {
 }
 
 pub const DEFAULT_INTERVAL: u64 = 10;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum BundleStatus {
+    /// Bundle was assembled and is being sent (or waits for inclusion)
+    Pending,
+    /// Bundle transaction was included and confirmed
+    Included,
+    /// Bundle couldn't be sent, was cancelled or reverted on chain
+    Failed,
+}
+
+/// User operation that was removed from the bundle before it was sent
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DroppedUserOperation {
+    pub user_operation_hash: UserOperationHash,
+    pub reason: String,
+}
+
+/// Single submission of the bundle transaction (the original one or a replacement)
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BundleAttempt {
+    pub tx_hash: H256,
+    pub max_fee_per_gas: U256,
+    pub max_priority_fee_per_gas: U256,
+    pub block_number: U64,
+}
+
+/// Record of an assembled bundle, kept for auditing which user operations were (or weren't) included
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BundleRecord {
+    pub id: u64,
+    pub entry_point: Address,
+    pub user_operations: Vec<UserOperationHash>,
+    pub dropped_user_operations: Vec<DroppedUserOperation>,
+    pub transaction_hash: Option<H256>,
+    pub gas_used: Option<U256>,
+    pub status: BundleStatus,
+    pub revert_reason: Option<String>,
+    /// All transactions sent for the bundle (original, replacements and cancellation)
+    #[serde(default)]
+    pub attempts: Vec<BundleAttempt>,
+    /// Unix timestamp (in seconds) at which the bundle was assembled
+    pub created_at: u64,
+}
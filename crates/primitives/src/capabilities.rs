@@ -0,0 +1,122 @@
+use ethers::{
+    providers::{JsonRpcError, Middleware, RpcError},
+    types::{
+        BlockNumber, Bytes, GethDebugTracerType, GethDebugTracingCallOptions,
+        GethDebugTracingOptions, TransactionRequest,
+    },
+};
+use serde_json::json;
+use tracing::trace;
+
+/// JSON-RPC error code of the unknown methods
+const METHOD_NOT_FOUND_CODE: i64 = -32601;
+/// Error messages of the clients that don't use the standard code for the unknown (or disabled) methods
+const METHOD_NOT_FOUND_MESSAGES: [&str; 4] =
+    ["not exist", "not available", "not supported", "unsupported"];
+/// Tracer with the functions required by Geth, it only checks that the JS tracers can be run
+const PROBE_JS_TRACER: &str = "{result: function() { return true; }, fault: function() {}}";
+
+/// Features of the execution client that the simulation and the submission backends depend on, probed at startup so
+/// that the compatible backends are selected upfront (instead of failing at the first user operation)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ProviderCapabilities {
+    /// `debug_traceCall` with the custom JS tracers (the validation rules are checked on the trace)
+    pub js_tracer: bool,
+    /// `eth_sendRawTransactionConditional`
+    pub conditional_transactions: bool,
+    /// `eth_feeHistory` (EIP-1559 fee estimation)
+    pub fee_history: bool,
+}
+
+impl ProviderCapabilities {
+    pub async fn probe<M: Middleware>(provider: &M) -> Self {
+        let js_tracer = match provider
+            .debug_trace_call(
+                TransactionRequest::new(),
+                Some(BlockNumber::Latest.into()),
+                GethDebugTracingCallOptions {
+                    tracing_options: GethDebugTracingOptions {
+                        tracer: Some(GethDebugTracerType::JsTracer(PROBE_JS_TRACER.to_string())),
+                        ..Default::default()
+                    },
+                },
+            )
+            .await
+        {
+            Ok(_) => true,
+            Err(e) => {
+                trace!("debug_traceCall with JS tracer failed: {e:?}");
+                false
+            }
+        };
+
+        // the empty transaction is rejected, unless the method itself isn't known
+        let conditional_transactions = match provider
+            .provider()
+            .request::<_, serde_json::Value>(
+                "eth_sendRawTransactionConditional",
+                (Bytes::from(vec![0xc0]), json!({})),
+            )
+            .await
+        {
+            Ok(_) => true,
+            Err(e) => {
+                trace!("eth_sendRawTransactionConditional failed: {e:?}");
+                e.as_error_response().map_or(false, is_supported)
+            }
+        };
+
+        let fee_history = match provider
+            .fee_history(1u64, BlockNumber::Latest, &[50.0])
+            .await
+        {
+            Ok(_) => true,
+            Err(e) => {
+                trace!("eth_feeHistory failed: {e:?}");
+                false
+            }
+        };
+
+        Self {
+            js_tracer,
+            conditional_transactions,
+            fee_history,
+        }
+    }
+}
+
+/// Whether the error of the request comes from the method (e.g. invalid parameters), not from the unknown method
+fn is_supported(error: &JsonRpcError) -> bool {
+    let message = error.message.to_lowercase();
+    error.code != METHOD_NOT_FOUND_CODE
+        && !METHOD_NOT_FOUND_MESSAGES
+            .iter()
+            .any(|not_found| message.contains(not_found))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(code: i64, message: &str) -> JsonRpcError {
+        JsonRpcError {
+            code,
+            message: message.to_string(),
+            data: None,
+        }
+    }
+
+    #[test]
+    fn method_support() {
+        assert!(is_supported(&error(
+            -32000,
+            "rlp: value size exceeds available input length"
+        )));
+        assert!(is_supported(&error(-32602, "invalid argument 0")));
+        assert!(!is_supported(&error(
+            METHOD_NOT_FOUND_CODE,
+            "the method eth_sendRawTransactionConditional does not exist/is not available"
+        )));
+        assert!(!is_supported(&error(-32000, "Method not supported")));
+    }
+}
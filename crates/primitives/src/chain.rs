@@ -0,0 +1,133 @@
+use std::time::Duration;
+
+use crate::ProviderCapabilities;
+
+/// Default maximum size of the transaction data accepted by the execution clients (128 KB)
+const DEFAULT_MAX_CALLDATA_SIZE: usize = 128 * 1024;
+
+/// Chain specific behavior that the bundler has to take into account when submitting bundles
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChainSpec {
+    pub chain_id: u64,
+    pub name: &'static str,
+    /// Whether the chain supports `eth_sendRawTransactionConditional`
+    pub conditional_transactions: bool,
+    /// Whether the bundle transactions have to use legacy (pre EIP-1559) gas pricing
+    pub legacy_gas: bool,
+    /// Whether the execution client serves `eth_feeHistory`, the EIP-1559 fees are set to the gas price without it
+    pub fee_history: bool,
+    /// Private relay to which the bundles can be submitted (instead of the public mempool)
+    pub private_relay: Option<&'static str>,
+    /// Maximum size of the bundle transaction data
+    pub max_calldata_size: usize,
+    /// Expected time until the transaction is included (block time)
+    pub inclusion_latency: Duration,
+}
+
+impl ChainSpec {
+    pub fn mainnet() -> Self {
+        Self {
+            chain_id: 1,
+            name: "mainnet",
+            conditional_transactions: false,
+            legacy_gas: false,
+            fee_history: true,
+            private_relay: Some("https://relay.flashbots.net"),
+            max_calldata_size: DEFAULT_MAX_CALLDATA_SIZE,
+            inclusion_latency: Duration::from_secs(12),
+        }
+    }
+
+    pub fn polygon() -> Self {
+        Self {
+            chain_id: 137,
+            name: "polygon",
+            conditional_transactions: false,
+            legacy_gas: false,
+            fee_history: true,
+            private_relay: None,
+            max_calldata_size: DEFAULT_MAX_CALLDATA_SIZE,
+            inclusion_latency: Duration::from_secs(2),
+        }
+    }
+
+    pub fn arbitrum() -> Self {
+        Self {
+            chain_id: 42161,
+            name: "arbitrum",
+            conditional_transactions: true,
+            legacy_gas: false,
+            fee_history: true,
+            private_relay: None,
+            max_calldata_size: 117964,
+            inclusion_latency: Duration::from_millis(250),
+        }
+    }
+
+    pub fn optimism() -> Self {
+        Self {
+            chain_id: 10,
+            name: "optimism",
+            conditional_transactions: true,
+            legacy_gas: false,
+            fee_history: true,
+            private_relay: None,
+            max_calldata_size: DEFAULT_MAX_CALLDATA_SIZE,
+            inclusion_latency: Duration::from_secs(2),
+        }
+    }
+
+    pub fn base() -> Self {
+        Self {
+            chain_id: 8453,
+            name: "base",
+            conditional_transactions: true,
+            legacy_gas: false,
+            fee_history: true,
+            private_relay: None,
+            max_calldata_size: DEFAULT_MAX_CALLDATA_SIZE,
+            inclusion_latency: Duration::from_secs(2),
+        }
+    }
+
+    pub fn bsc() -> Self {
+        Self {
+            chain_id: 56,
+            name: "bsc",
+            conditional_transactions: false,
+            legacy_gas: true,
+            fee_history: true,
+            private_relay: None,
+            max_calldata_size: DEFAULT_MAX_CALLDATA_SIZE,
+            inclusion_latency: Duration::from_secs(3),
+        }
+    }
+
+    /// Built-in profile of the chain, or the default (mainnet-like, without private relay) profile for unknown chains
+    pub fn from_chain_id(chain_id: u64) -> Self {
+        match chain_id {
+            1 => Self::mainnet(),
+            137 => Self::polygon(),
+            42161 => Self::arbitrum(),
+            10 => Self::optimism(),
+            8453 => Self::base(),
+            56 => Self::bsc(),
+            _ => Self {
+                chain_id,
+                name: "unknown",
+                private_relay: None,
+                ..Self::mainnet()
+            },
+        }
+    }
+
+    /// Disables the features the execution client doesn't support
+    pub fn with_capabilities(self, capabilities: &ProviderCapabilities) -> Self {
+        Self {
+            conditional_transactions: self.conditional_transactions
+                && capabilities.conditional_transactions,
+            fee_history: capabilities.fee_history,
+            ..self
+        }
+    }
+}
@@ -1,10 +1,13 @@
 // simulation
 pub const SIMULATE_VALIDATION_ERROR_CODE: i32 = -32500;
+pub const PAYMASTER_VALIDATION_ERROR_CODE: i32 = -32501;
 pub const OPCODE_VALIDATION_ERROR_CODE: i32 = -32502;
+pub const EXPIRATION_ERROR_CODE: i32 = -32503;
 
 // reputation
 pub const ENTITY_BANNED_ERROR_CODE: i32 = -32504;
 pub const STAKE_TOO_LOW_ERROR_CODE: i32 = -32505;
+pub const UNSUPPORTED_AGGREGATOR_ERROR_CODE: i32 = -32506;
 
 // sanity check
 pub const USER_OPERATION_HASH_ERROR_CODE: i32 = -32601;
@@ -12,3 +15,8 @@ pub const SANITY_CHECK_ERROR_CODE: i32 = -32602;
 
 pub const SIGNATURE_FAILED_ERROR_CODE: i32 = -32507;
 pub const EXECUTION_ERROR_CODE: i32 = -32521;
+
+// rpc server
+pub const LIMIT_EXCEEDED_ERROR_CODE: i32 = -32005;
+pub const UNAUTHORIZED_ERROR_CODE: i32 = -32001;
+pub const RESOURCE_UNAVAILABLE_ERROR_CODE: i32 = -32002;
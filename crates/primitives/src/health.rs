@@ -0,0 +1,23 @@
+use ethers::types::Address;
+use serde::{Deserialize, Serialize};
+
+use crate::BundleStatus;
+
+/// Health of a supported entry point, reported separately for each entry point so that a misconfigured one doesn't
+/// mask the problems of the others
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EntryPointHealth {
+    pub entry_point: Address,
+    /// The entry point contract is deployed on the chain
+    pub deployed: bool,
+    pub mempool_size: u64,
+    pub throttled_entities: u64,
+    pub banned_entities: u64,
+    /// User operations to the entry point in the rejection log
+    pub rejected_user_operations: u64,
+    /// Status of the last bundle sent to the entry point (none if no bundle was sent since the start)
+    pub last_bundle_status: Option<BundleStatus>,
+    /// Unix timestamp (in seconds) at which the last bundle was assembled
+    pub last_bundle_at: Option<u64>,
+}
@@ -1,25 +1,54 @@
 #![allow(dead_code)]
 
+#[cfg(any(test, feature = "arbitrary"))]
+mod arbitrary;
+mod authorization;
 mod bundler;
+mod capabilities;
+mod chain;
 mod error_codes;
+mod health;
+mod p2p;
+mod packed_user_operation;
+mod provider;
+mod rejection;
 mod reputation;
 mod sanity_check;
+mod secret;
 mod simulation;
 mod user_operation;
+mod user_operation_builder;
 mod utils;
 mod wallet;
 
-pub use bundler::{Mode, DEFAULT_INTERVAL};
+pub use authorization::{Authorization, AuthorizationList, UserOperationWithAuthorization};
+pub use bundler::{
+    BundleAttempt, BundleRecord, BundleStatus, DroppedUserOperation, Mode, DEFAULT_INTERVAL,
+};
+pub use capabilities::ProviderCapabilities;
+pub use chain::ChainSpec;
 pub use error_codes::*;
+pub use health::EntryPointHealth;
+pub use p2p::PeerInfo;
+pub use packed_user_operation::{PackedUserOperation, PackedUserOperationError};
+pub use provider::{
+    connect_provider, with_request_budget, EthClient, EthClientRetryOpts, EthProvider, RetryPolicy,
+};
+pub use rejection::{rejection_reason, RejectedUserOperation};
 pub use reputation::{
-    BadReputationError, ReputationEntry, ReputationStatus, StakeInfo, BAN_SLACK,
-    MIN_INCLUSION_RATE_DENOMINATOR, THROTTLED_MAX_INCLUDE, THROTTLING_SLACK,
+    BadReputationError, ReputationEntry, ReputationError, ReputationStatus, StakeInfo,
+    ThrottlingParams, BAN_SLACK, MIN_INCLUSION_RATE_DENOMINATOR, THROTTLED_MAX_INCLUDE,
+    THROTTLING_SLACK,
 };
 pub use sanity_check::SanityCheckError;
+pub use secret::{Secret, REDACTED};
 pub use simulation::{CodeHash, SimulationError};
 pub use user_operation::{
-    UserOperation, UserOperationByHash, UserOperationGasEstimation, UserOperationHash,
-    UserOperationPartial, UserOperationReceipt,
+    IncludedUserOperation, PendingUserOperation, SendUserOperationOptions,
+    UnestimatedUserOperation, UserOperation, UserOperationByHash, UserOperationGasEstimation,
+    UserOperationHash, UserOperationReceipt, UserOperationSubscriptionFilter, DUMMY_SIGNATURE,
+    ESTIMATION_VERIFICATION_GAS_LIMIT,
 };
-pub use utils::{get_addr, parse_address, parse_u256};
+pub use user_operation_builder::UserOperationBuilder;
+pub use utils::{get_addr, parse_address, parse_checksummed_address, parse_u256};
 pub use wallet::Wallet;
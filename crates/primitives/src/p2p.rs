@@ -0,0 +1,18 @@
+use ethers::types::H256;
+use serde::{Deserialize, Serialize};
+
+/// Peer of the shared mempool with its score (negative for the misbehaving peers), the peers with a low score are
+/// throttled and eventually banned
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PeerInfo {
+    pub peer_id: String,
+    pub connected: bool,
+    /// ENR of the peer if it is known to the discovery
+    pub enr: Option<String>,
+    /// Mempools that the peer supports (advertised in its ENR, or subscribed to)
+    pub mempools: Vec<H256>,
+    pub score: f64,
+    pub throttled: bool,
+    pub banned: bool,
+}
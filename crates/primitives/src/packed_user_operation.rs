@@ -0,0 +1,461 @@
+use ethers::{
+    abi::AbiEncode,
+    prelude::{EthAbiCodec, EthAbiType},
+    types::{Address, Bytes, H256, U256},
+    utils::{
+        keccak256,
+        rlp::{Decodable, DecoderError, Encodable, Rlp, RlpStream},
+    },
+};
+use serde::{Deserialize, Serialize};
+use std::{fmt, ops::Deref};
+
+use super::utils::as_checksum;
+use crate::{UserOperation, UserOperationHash};
+
+/// Length of the paymaster address and its verification and post-op gas limits at the start of `paymasterAndData`
+const PAYMASTER_FIELDS_LENGTH: usize = 20 + 16 + 16;
+
+/// Error of the conversion between the v0.6 and the v0.7 (packed) user operations, when a field can't be represented
+/// in the other version
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PackedUserOperationError {
+    /// Gas limit or fee that doesn't fit into the 128 bits of its packed field
+    Overflow { field: &'static str },
+    /// `paymasterAndData` that is neither empty nor starts with the paymaster address (and, in v0.7, its gas limits)
+    InvalidPaymasterAndData { paymaster_and_data: Bytes },
+    /// Paymaster gas limits that differ from the verification gas limit, which bounds the paymaster in v0.6
+    PaymasterGasLimits {
+        verification_gas_limit: U256,
+        paymaster_verification_gas_limit: U256,
+        paymaster_post_op_gas_limit: U256,
+    },
+}
+
+impl fmt::Display for PackedUserOperationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PackedUserOperationError::Overflow { field } => {
+                write!(f, "{field} doesn't fit into 128 bits")
+            }
+            PackedUserOperationError::InvalidPaymasterAndData { paymaster_and_data } => {
+                write!(f, "Paymaster and data {paymaster_and_data} is invalid")
+            }
+            PackedUserOperationError::PaymasterGasLimits {
+                verification_gas_limit,
+                paymaster_verification_gas_limit,
+                paymaster_post_op_gas_limit,
+            } => write!(
+                f,
+                "Paymaster gas limits {paymaster_verification_gas_limit} and {paymaster_post_op_gas_limit} differ from the verification gas limit {verification_gas_limit}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PackedUserOperationError {}
+
+/// User operation of the v0.7 entry point, with the gas limits and the fees packed into words and the paymaster's gas
+/// limits in `paymasterAndData`
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, EthAbiCodec, EthAbiType)]
+#[serde(rename_all = "camelCase")]
+pub struct PackedUserOperation {
+    #[serde(serialize_with = "as_checksum")]
+    pub sender: Address,
+    pub nonce: U256,
+    /// Factory address and factory data
+    pub init_code: Bytes,
+    pub call_data: Bytes,
+    /// Verification gas limit (high 128 bits) and call gas limit (low 128 bits)
+    pub account_gas_limits: H256,
+    pub pre_verification_gas: U256,
+    /// Max priority fee per gas (high 128 bits) and max fee per gas (low 128 bits)
+    pub gas_fees: H256,
+    /// Paymaster address, its verification and post-op gas limits (128 bits each) and paymaster data
+    pub paymaster_and_data: Bytes,
+    pub signature: Bytes,
+}
+
+#[derive(EthAbiCodec, EthAbiType)]
+struct PackedUserOperationForSignature {
+    sender: Address,
+    nonce: U256,
+    init_code: H256,
+    call_data: H256,
+    account_gas_limits: H256,
+    pre_verification_gas: U256,
+    gas_fees: H256,
+    paymaster_and_data: H256,
+}
+
+/// Packs the values into a word (`high << 128 | low`)
+fn pack_u128(
+    high: U256,
+    high_field: &'static str,
+    low: U256,
+    low_field: &'static str,
+) -> Result<H256, PackedUserOperationError> {
+    for (value, field) in [(high, high_field), (low, low_field)] {
+        if value > U256::from(u128::MAX) {
+            return Err(PackedUserOperationError::Overflow { field });
+        }
+    }
+    let mut word = [0u8; 32];
+    ((high << 128) | low).to_big_endian(&mut word);
+    Ok(H256(word))
+}
+
+/// High and low 128 bits of the word
+fn unpack_u128(word: &H256) -> (U256, U256) {
+    (
+        U256::from_big_endian(&word[..16]),
+        U256::from_big_endian(&word[16..]),
+    )
+}
+
+impl PackedUserOperation {
+    pub fn verification_gas_limit(&self) -> U256 {
+        unpack_u128(&self.account_gas_limits).0
+    }
+
+    pub fn call_gas_limit(&self) -> U256 {
+        unpack_u128(&self.account_gas_limits).1
+    }
+
+    pub fn max_priority_fee_per_gas(&self) -> U256 {
+        unpack_u128(&self.gas_fees).0
+    }
+
+    pub fn max_fee_per_gas(&self) -> U256 {
+        unpack_u128(&self.gas_fees).1
+    }
+
+    /// Factory of the account, if the user operation deploys it
+    pub fn factory(&self) -> Option<Address> {
+        (self.init_code.len() >= 20).then(|| Address::from_slice(&self.init_code[..20]))
+    }
+
+    pub fn factory_data(&self) -> Bytes {
+        self.init_code.get(20..).unwrap_or_default().to_vec().into()
+    }
+
+    pub fn paymaster(&self) -> Option<Address> {
+        (self.paymaster_and_data.len() >= 20)
+            .then(|| Address::from_slice(&self.paymaster_and_data[..20]))
+    }
+
+    pub fn paymaster_verification_gas_limit(&self) -> U256 {
+        self.paymaster_and_data
+            .get(20..36)
+            .map(U256::from_big_endian)
+            .unwrap_or_default()
+    }
+
+    pub fn paymaster_post_op_gas_limit(&self) -> U256 {
+        self.paymaster_and_data
+            .get(36..PAYMASTER_FIELDS_LENGTH)
+            .map(U256::from_big_endian)
+            .unwrap_or_default()
+    }
+
+    pub fn paymaster_data(&self) -> Bytes {
+        self.paymaster_and_data
+            .get(PAYMASTER_FIELDS_LENGTH..)
+            .unwrap_or_default()
+            .to_vec()
+            .into()
+    }
+
+    pub fn pack(&self) -> Bytes {
+        Bytes::from(self.clone().encode())
+    }
+
+    pub fn pack_for_signature(&self) -> Bytes {
+        PackedUserOperationForSignature {
+            sender: self.sender,
+            nonce: self.nonce,
+            init_code: H256::from(&keccak256(self.init_code.deref())),
+            call_data: H256::from(&keccak256(self.call_data.deref())),
+            account_gas_limits: self.account_gas_limits,
+            pre_verification_gas: self.pre_verification_gas,
+            gas_fees: self.gas_fees,
+            paymaster_and_data: H256::from(&keccak256(self.paymaster_and_data.deref())),
+        }
+        .encode()
+        .into()
+    }
+
+    /// Hash of the user operation as `getUserOpHash` of the v0.7 entry point
+    pub fn hash(&self, entry_point: &Address, chain_id: &U256) -> UserOperationHash {
+        H256::from_slice(
+            keccak256(
+                [
+                    keccak256(self.pack_for_signature().deref()).to_vec(),
+                    entry_point.encode(),
+                    chain_id.encode(),
+                ]
+                .concat(),
+            )
+            .as_slice(),
+        )
+        .into()
+    }
+}
+
+impl Encodable for PackedUserOperation {
+    fn rlp_append(&self, stream: &mut RlpStream) {
+        stream.begin_list(9);
+        stream.append(&self.sender);
+        stream.append(&self.nonce);
+        stream.append(&self.init_code);
+        stream.append(&self.call_data);
+        stream.append(&self.account_gas_limits);
+        stream.append(&self.pre_verification_gas);
+        stream.append(&self.gas_fees);
+        stream.append(&self.paymaster_and_data);
+        stream.append(&self.signature);
+    }
+}
+
+impl Decodable for PackedUserOperation {
+    fn decode(rlp: &Rlp) -> Result<Self, DecoderError> {
+        if rlp.item_count()? != 9 {
+            return Err(DecoderError::RlpIncorrectListLen);
+        }
+        Ok(Self {
+            sender: rlp.val_at(0)?,
+            nonce: rlp.val_at(1)?,
+            init_code: rlp.val_at(2)?,
+            call_data: rlp.val_at(3)?,
+            account_gas_limits: rlp.val_at(4)?,
+            pre_verification_gas: rlp.val_at(5)?,
+            gas_fees: rlp.val_at(6)?,
+            paymaster_and_data: rlp.val_at(7)?,
+            signature: rlp.val_at(8)?,
+        })
+    }
+}
+
+/// The paymaster of the v0.6 user operation is bounded by the verification gas limit (in the validation and in the
+/// post-op), which become its gas limits in v0.7
+impl TryFrom<UserOperation> for PackedUserOperation {
+    type Error = PackedUserOperationError;
+
+    fn try_from(user_operation: UserOperation) -> Result<Self, Self::Error> {
+        let paymaster_and_data = match user_operation.paymaster_and_data.len() {
+            0 => Bytes::default(),
+            length if length < 20 => {
+                return Err(PackedUserOperationError::InvalidPaymasterAndData {
+                    paymaster_and_data: user_operation.paymaster_and_data,
+                })
+            }
+            _ => {
+                let gas_limits = pack_u128(
+                    user_operation.verification_gas_limit,
+                    "verificationGasLimit",
+                    user_operation.verification_gas_limit,
+                    "verificationGasLimit",
+                )?;
+                [
+                    &user_operation.paymaster_and_data[..20],
+                    &gas_limits[..],
+                    &user_operation.paymaster_and_data[20..],
+                ]
+                .concat()
+                .into()
+            }
+        };
+        Ok(Self {
+            sender: user_operation.sender,
+            nonce: user_operation.nonce,
+            init_code: user_operation.init_code,
+            call_data: user_operation.call_data,
+            account_gas_limits: pack_u128(
+                user_operation.verification_gas_limit,
+                "verificationGasLimit",
+                user_operation.call_gas_limit,
+                "callGasLimit",
+            )?,
+            pre_verification_gas: user_operation.pre_verification_gas,
+            gas_fees: pack_u128(
+                user_operation.max_priority_fee_per_gas,
+                "maxPriorityFeePerGas",
+                user_operation.max_fee_per_gas,
+                "maxFeePerGas",
+            )?,
+            paymaster_and_data,
+            signature: user_operation.signature,
+        })
+    }
+}
+
+/// Inverse of the conversion of the v0.6 user operation, fails if the paymaster's gas limits aren't the verification
+/// gas limit
+impl TryFrom<PackedUserOperation> for UserOperation {
+    type Error = PackedUserOperationError;
+
+    fn try_from(user_operation: PackedUserOperation) -> Result<Self, Self::Error> {
+        let verification_gas_limit = user_operation.verification_gas_limit();
+        let paymaster_and_data = match user_operation.paymaster_and_data.len() {
+            0 => Bytes::default(),
+            length if length < PAYMASTER_FIELDS_LENGTH => {
+                return Err(PackedUserOperationError::InvalidPaymasterAndData {
+                    paymaster_and_data: user_operation.paymaster_and_data,
+                })
+            }
+            _ => {
+                let paymaster_verification_gas_limit =
+                    user_operation.paymaster_verification_gas_limit();
+                let paymaster_post_op_gas_limit = user_operation.paymaster_post_op_gas_limit();
+                if paymaster_verification_gas_limit != verification_gas_limit
+                    || paymaster_post_op_gas_limit != verification_gas_limit
+                {
+                    return Err(PackedUserOperationError::PaymasterGasLimits {
+                        verification_gas_limit,
+                        paymaster_verification_gas_limit,
+                        paymaster_post_op_gas_limit,
+                    });
+                }
+                [
+                    &user_operation.paymaster_and_data[..20],
+                    &user_operation.paymaster_and_data[PAYMASTER_FIELDS_LENGTH..],
+                ]
+                .concat()
+                .into()
+            }
+        };
+        Ok(Self {
+            sender: user_operation.sender,
+            nonce: user_operation.nonce,
+            call_gas_limit: user_operation.call_gas_limit(),
+            verification_gas_limit,
+            pre_verification_gas: user_operation.pre_verification_gas,
+            max_fee_per_gas: user_operation.max_fee_per_gas(),
+            max_priority_fee_per_gas: user_operation.max_priority_fee_per_gas(),
+            init_code: user_operation.init_code,
+            call_data: user_operation.call_data,
+            paymaster_and_data,
+            signature: user_operation.signature,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use ethers::{abi::AbiDecode, utils::rlp};
+
+    use super::*;
+
+    fn user_operation() -> UserOperation {
+        UserOperation {
+            sender: "0x9c5754De1443984659E1b3a8d1931D83475ba29C".parse().unwrap(),
+            nonce: U256::from(1),
+            init_code: Bytes::from_str("0x9406cc6185a346906296840746125a0e449764545fbfb9cf000000000000000000000000ce0fefa6f7979c4c9b5373e0f5105b7259092c6d0000000000000000000000000000000000000000000000000000000000000000").unwrap(),
+            call_data: Bytes::from_str("0xb61d27f6").unwrap(),
+            call_gas_limit: U256::from(33100),
+            verification_gas_limit: U256::from(361460),
+            pre_verification_gas: U256::from(44980),
+            max_fee_per_gas: U256::from(1695000030_u64),
+            max_priority_fee_per_gas: U256::from(1695000000),
+            paymaster_and_data: Bytes::from_str("0x00000000000000000000000000000000000000aa1234").unwrap(),
+            signature: Bytes::from(vec![1; 65]),
+        }
+    }
+
+    #[test]
+    fn packed_user_operation_fields() {
+        let packed = PackedUserOperation::try_from(user_operation()).unwrap();
+        assert_eq!(
+            packed.account_gas_limits,
+            H256::from_str("0x000000000000000000000000000583f40000000000000000000000000000814c")
+                .unwrap()
+        );
+        assert_eq!(packed.verification_gas_limit(), U256::from(361460));
+        assert_eq!(packed.call_gas_limit(), U256::from(33100));
+        assert_eq!(packed.max_priority_fee_per_gas(), U256::from(1695000000));
+        assert_eq!(packed.max_fee_per_gas(), U256::from(1695000030_u64));
+        assert_eq!(
+            packed.factory(),
+            Some(
+                "0x9406Cc6185a346906296840746125a0E44976454"
+                    .parse()
+                    .unwrap()
+            )
+        );
+        assert_eq!(packed.factory_data().len(), 68);
+        assert_eq!(
+            packed.paymaster(),
+            Some(
+                "0x00000000000000000000000000000000000000aa"
+                    .parse()
+                    .unwrap()
+            )
+        );
+        assert_eq!(
+            packed.paymaster_verification_gas_limit(),
+            U256::from(361460)
+        );
+        assert_eq!(packed.paymaster_post_op_gas_limit(), U256::from(361460));
+        assert_eq!(packed.paymaster_data(), Bytes::from_str("0x1234").unwrap());
+    }
+
+    #[test]
+    fn packed_user_operation_conversion() {
+        let packed = PackedUserOperation::try_from(user_operation()).unwrap();
+        assert_eq!(
+            UserOperation::try_from(packed.clone()),
+            Ok(user_operation())
+        );
+
+        let mut other_paymaster_gas = packed;
+        other_paymaster_gas.paymaster_and_data = [
+            &other_paymaster_gas.paymaster_and_data[..20],
+            &[0u8; 32][..],
+            &other_paymaster_gas.paymaster_and_data[PAYMASTER_FIELDS_LENGTH..],
+        ]
+        .concat()
+        .into();
+        assert!(matches!(
+            UserOperation::try_from(other_paymaster_gas),
+            Err(PackedUserOperationError::PaymasterGasLimits { .. })
+        ));
+
+        let mut overflow = user_operation();
+        overflow.max_fee_per_gas = U256::from(u128::MAX) + 1;
+        assert_eq!(
+            PackedUserOperation::try_from(overflow),
+            Err(PackedUserOperationError::Overflow {
+                field: "maxFeePerGas"
+            })
+        );
+    }
+
+    #[test]
+    fn packed_user_operation_encoding() {
+        let packed = PackedUserOperation::try_from(user_operation()).unwrap();
+        assert_eq!(
+            <PackedUserOperation as AbiDecode>::decode(packed.pack()).unwrap(),
+            packed.clone()
+        );
+        assert_eq!(
+            rlp::decode::<PackedUserOperation>(&rlp::encode(&packed)).unwrap(),
+            packed.clone()
+        );
+
+        let entry_point = "0x0000000071727De22E5E9d8BAf0edAc6f37da032"
+            .parse()
+            .unwrap();
+        let mut signed = packed.clone();
+        signed.signature = Bytes::default();
+        assert_eq!(
+            signed.hash(&entry_point, &U256::from(1)),
+            packed.hash(&entry_point, &U256::from(1))
+        );
+        assert_ne!(
+            packed.hash(&entry_point, &U256::from(1)),
+            packed.hash(&entry_point, &U256::from(10))
+        );
+    }
+}
@@ -0,0 +1,42 @@
+use ethers::types::Address;
+use serde::{Deserialize, Serialize};
+
+use crate::{error_codes::*, UserOperationHash};
+
+/// User operation rejected by the uopool, kept so that the rejection can be looked up afterwards
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RejectedUserOperation {
+    pub user_operation_hash: UserOperationHash,
+    pub entry_point: Address,
+    pub sender: Address,
+    pub factory: Option<Address>,
+    pub paymaster: Option<Address>,
+    /// Class of the error (e.g. `opcode_validation`, `entity_banned`), see [rejection_reason]
+    pub reason: String,
+    /// JSON-RPC error code, 0 for the errors that aren't specific to the user operation
+    pub code: i32,
+    pub message: String,
+    /// Unix timestamp (in seconds) of the rejection
+    pub rejected_at: u64,
+}
+
+/// Machine-readable class of the user operation errors with the JSON-RPC error code
+pub fn rejection_reason(code: i32) -> &'static str {
+    match code {
+        SIMULATE_VALIDATION_ERROR_CODE => "simulate_validation",
+        PAYMASTER_VALIDATION_ERROR_CODE => "paymaster_validation",
+        OPCODE_VALIDATION_ERROR_CODE => "opcode_validation",
+        EXPIRATION_ERROR_CODE => "expiration",
+        ENTITY_BANNED_ERROR_CODE => "entity_banned",
+        STAKE_TOO_LOW_ERROR_CODE => "stake_too_low",
+        UNSUPPORTED_AGGREGATOR_ERROR_CODE => "unsupported_aggregator",
+        SANITY_CHECK_ERROR_CODE => "sanity_check",
+        SIGNATURE_FAILED_ERROR_CODE => "signature_failed",
+        EXECUTION_ERROR_CODE => "execution",
+        LIMIT_EXCEEDED_ERROR_CODE => "limit_exceeded",
+        UNAUTHORIZED_ERROR_CODE => "unauthorized",
+        RESOURCE_UNAVAILABLE_ERROR_CODE => "resource_unavailable",
+        _ => "other",
+    }
+}
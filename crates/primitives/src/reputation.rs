@@ -43,6 +43,16 @@ pub struct StakeInfo {
     pub unstake_delay: U256, // seconds
 }
 
+/// Parameters of the entity throttling and banning, adjustable at runtime
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ThrottlingParams {
+    pub min_inclusion_denominator: u64,
+    pub throttling_slack: u64,
+    pub ban_slack: u64,
+}
+
+#[derive(Debug)]
 pub enum BadReputationError {
     EntityBanned {
         address: Address,
@@ -0,0 +1,47 @@
+use std::{convert::Infallible, fmt, str::FromStr, sync::Arc};
+
+/// Placeholder of the secret values in the logs and in the config dump
+pub const REDACTED: &str = "<redacted>";
+
+/// Sensitive option value (e.g. the signer's mnemonic or an auth token), which is never printed in the logs
+#[derive(Clone, PartialEq, Eq)]
+pub struct Secret(Arc<str>);
+
+impl Secret {
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromStr for Secret {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self(s.into()))
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Secret({REDACTED})")
+    }
+}
+
+impl fmt::Display for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secret_is_redacted() {
+        let secret: Secret = "test test test junk".parse().unwrap();
+        assert_eq!(secret.expose(), "test test test junk");
+        assert_eq!(format!("{secret:?}"), "Secret(<redacted>)");
+        assert_eq!(secret.to_string(), REDACTED);
+    }
+}
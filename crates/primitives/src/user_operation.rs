@@ -1,7 +1,8 @@
 use ethers::{
     abi::AbiEncode,
     prelude::{EthAbiCodec, EthAbiType},
-    types::{Address, BlockNumber, Bytes, Log, TransactionReceipt, H256, U256},
+    signers::Signer,
+    types::{Address, Bytes, Log, TransactionReceipt, H256, U256, U64},
     utils::keccak256,
 };
 use rustc_hex::FromHexError;
@@ -148,29 +149,61 @@ impl UserOperation {
         .into()
     }
 
+    /// Gas that the user operation can use at most, the verification gas is spent up to three times with the
+    /// paymaster (validation and the two postOp calls)
+    pub fn total_gas(&self) -> U256 {
+        let verification_gas_multiplier = if self.paymaster_and_data.is_empty() {
+            1
+        } else {
+            3
+        };
+        self.call_gas_limit
+            .saturating_add(
+                self.verification_gas_limit
+                    .saturating_mul(verification_gas_multiplier.into()),
+            )
+            .saturating_add(self.pre_verification_gas)
+    }
+
+    /// Maximum cost of the user operation, which the paymaster (or the sender) has to deposit
+    pub fn max_cost(&self) -> U256 {
+        self.total_gas().saturating_mul(self.max_fee_per_gas)
+    }
+
+    /// Signature of the user operation hash with EIP-191 (`personal_sign`), as the sample accounts verify it
+    pub async fn sign<S: Signer>(
+        &self,
+        signer: &S,
+        entry_point: &Address,
+        chain_id: &U256,
+    ) -> Result<Bytes, S::Error> {
+        let user_operation_hash = self.hash(entry_point, chain_id);
+        Ok(signer
+            .sign_message(user_operation_hash.0.as_bytes())
+            .await?
+            .to_vec()
+            .into())
+    }
+
     #[cfg(feature = "test-utils")]
     pub fn random() -> Self {
-        Self {
-            sender: Address::random(),
-            nonce: U256::zero(),
-            init_code: Bytes::default(),
-            call_data: Bytes::default(),
-            call_gas_limit: U256::zero(),
-            verification_gas_limit: U256::from(100000),
-            pre_verification_gas: U256::from(21000),
-            max_fee_per_gas: U256::from(0),
-            max_priority_fee_per_gas: U256::from(1e9 as u64),
-            paymaster_and_data: Bytes::default(),
-            signature: Bytes::default(),
-        }
+        crate::UserOperationBuilder::new(Address::random(), U256::zero())
+            .verification_gas_limit(U256::from(100000))
+            .pre_verification_gas(U256::from(21000))
+            .fees(U256::zero(), U256::from(1e9 as u64))
+            .build()
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// Result of `eth_getUserOperationReceipt`: the `UserOperationEvent` of the user operation, the logs emitted during its
+/// execution and the receipt of the bundle transaction
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct UserOperationReceipt {
     pub user_op_hash: UserOperationHash,
     #[serde(serialize_with = "as_checksum")]
+    pub entry_point: Address,
+    #[serde(serialize_with = "as_checksum")]
     pub sender: Address,
     pub nonce: U256,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -183,20 +216,76 @@ pub struct UserOperationReceipt {
     pub receipt: TransactionReceipt,
 }
 
-#[derive(Serialize, Deserialize)]
+/// Result of `eth_getUserOperationByHash`
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct UserOperationByHash {
     pub user_operation: UserOperation,
     #[serde(serialize_with = "as_checksum")]
     pub entry_point: Address,
-    pub block_number: BlockNumber,
-    pub block_hash: H256,
-    pub transaction_hash: H256,
+    /// Block and transaction are `null` while the user operation is pending in the mempool
+    pub block_number: Option<U64>,
+    pub block_hash: Option<H256>,
+    pub transaction_hash: Option<H256>,
+}
+
+/// Filter of the user operation subscriptions (`eth_subscribe`), empty fields match all user operations
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserOperationSubscriptionFilter {
+    pub entry_point: Option<Address>,
+    pub sender: Option<Address>,
 }
 
+impl UserOperationSubscriptionFilter {
+    pub fn matches(&self, entry_point: &Address, sender: &Address) -> bool {
+        self.entry_point.map_or(true, |ep| ep == *entry_point)
+            && self.sender.map_or(true, |s| s == *sender)
+    }
+}
+
+/// Options of `eth_sendUserOperation`: private user operations are not gossiped to the shared mempool, dumped or
+/// notified to the subscribers until they are included (frontrunning protection)
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SendUserOperationOptions {
+    #[serde(default)]
+    pub private: bool,
+}
+
+/// Notification of the `newPendingUserOperations` subscription
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PendingUserOperation {
+    pub user_op_hash: UserOperationHash,
+    #[serde(serialize_with = "as_checksum")]
+    pub entry_point: Address,
+    pub user_operation: UserOperation,
+}
+
+/// Notification of the `userOperationIncluded` subscription
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
-pub struct UserOperationPartial {
+pub struct IncludedUserOperation {
+    pub user_op_hash: UserOperationHash,
+    #[serde(serialize_with = "as_checksum")]
+    pub entry_point: Address,
+    #[serde(serialize_with = "as_checksum")]
+    pub sender: Address,
+    pub transaction_hash: H256,
+    pub success: bool,
+}
+
+/// Verification gas limit of the estimated user operations that don't set one, the bound of the simulation
+pub const ESTIMATION_VERIFICATION_GAS_LIMIT: u64 = 10_000_000;
+/// Signature of the estimated user operations that don't set one, of the length of an ECDSA signature so that the
+/// pre-verification gas covers the signed user operation
+pub const DUMMY_SIGNATURE: [u8; 65] = [1; 65];
+
+/// User operation of `eth_estimateUserOperationGas`, whose gas limits, fees and signature are not known yet
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UnestimatedUserOperation {
     pub sender: Address,
     pub nonce: U256,
     pub init_code: Option<Bytes>,
@@ -210,75 +299,60 @@ pub struct UserOperationPartial {
     pub signature: Option<Bytes>,
 }
 
-impl From<UserOperationPartial> for UserOperation {
-    fn from(user_operation: UserOperationPartial) -> Self {
-        Self {
+impl UnestimatedUserOperation {
+    /// Checks the fields that the estimation can't fill in: the factory and the paymaster addresses and the order
+    /// of the fees
+    pub fn validate(&self) -> Result<(), String> {
+        for (field, value) in [
+            ("initCode", &self.init_code),
+            ("paymasterAndData", &self.paymaster_and_data),
+        ] {
+            if value
+                .as_ref()
+                .map_or(false, |value| !value.is_empty() && value.len() < 20)
+            {
+                return Err(format!("{field} has to be empty or start with an address"));
+            }
+        }
+        if let (Some(max_fee_per_gas), Some(max_priority_fee_per_gas)) =
+            (self.max_fee_per_gas, self.max_priority_fee_per_gas)
+        {
+            if max_priority_fee_per_gas > max_fee_per_gas {
+                return Err(format!(
+                    "Max priority fee per gas {max_priority_fee_per_gas} is higher than max fee per gas {max_fee_per_gas}"
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The missing fields are empty or zero, except the verification gas limit (zero is also replaced) and the signature
+/// (an empty one is also replaced), which the simulation needs
+impl TryFrom<UnestimatedUserOperation> for UserOperation {
+    type Error = String;
+
+    fn try_from(user_operation: UnestimatedUserOperation) -> Result<Self, Self::Error> {
+        user_operation.validate()?;
+        Ok(Self {
             sender: user_operation.sender,
             nonce: user_operation.nonce,
-            init_code: {
-                if let Some(init_code) = user_operation.init_code {
-                    init_code
-                } else {
-                    Bytes::default()
-                }
-            },
-            call_data: {
-                if let Some(call_data) = user_operation.call_data {
-                    call_data
-                } else {
-                    Bytes::default()
-                }
-            },
-            call_gas_limit: {
-                if let Some(call_gas_limit) = user_operation.call_gas_limit {
-                    call_gas_limit
-                } else {
-                    U256::zero()
-                }
-            },
-            verification_gas_limit: {
-                if let Some(verification_gas_limit) = user_operation.verification_gas_limit {
-                    verification_gas_limit
-                } else {
-                    U256::from(10000000)
-                }
-            },
-            pre_verification_gas: {
-                if let Some(pre_verification_gas) = user_operation.pre_verification_gas {
-                    pre_verification_gas
-                } else {
-                    U256::zero()
-                }
-            },
-            max_fee_per_gas: {
-                if let Some(max_fee_per_gas) = user_operation.max_fee_per_gas {
-                    max_fee_per_gas
-                } else {
-                    U256::zero()
-                }
-            },
-            max_priority_fee_per_gas: {
-                if let Some(max_priority_fee_per_gas) = user_operation.max_priority_fee_per_gas {
-                    max_priority_fee_per_gas
-                } else {
-                    U256::zero()
-                }
-            },
-            paymaster_and_data: {
-                if let Some(paymaster_and_data) = user_operation.paymaster_and_data {
-                    paymaster_and_data
-                } else {
-                    Bytes::default()
-                }
-            },
-            signature: {
-                if let Some(signature) = user_operation.signature {
-                    signature
-                } else {
-                    Bytes::from(vec![1; 65])
-                }
-            },
-        }
+            init_code: user_operation.init_code.unwrap_or_default(),
+            call_data: user_operation.call_data.unwrap_or_default(),
+            call_gas_limit: user_operation.call_gas_limit.unwrap_or_default(),
+            verification_gas_limit: user_operation
+                .verification_gas_limit
+                .filter(|verification_gas_limit| !verification_gas_limit.is_zero())
+                .unwrap_or_else(|| ESTIMATION_VERIFICATION_GAS_LIMIT.into()),
+            pre_verification_gas: user_operation.pre_verification_gas.unwrap_or_default(),
+            max_fee_per_gas: user_operation.max_fee_per_gas.unwrap_or_default(),
+            max_priority_fee_per_gas: user_operation.max_priority_fee_per_gas.unwrap_or_default(),
+            paymaster_and_data: user_operation.paymaster_and_data.unwrap_or_default(),
+            signature: user_operation
+                .signature
+                .filter(|signature| !signature.is_empty())
+                .unwrap_or_else(|| DUMMY_SIGNATURE.to_vec().into()),
+        })
     }
 }
 
@@ -418,4 +492,111 @@ mod tests {
                 .into()
         );
     }
+
+    #[test]
+    fn unestimated_user_operation() {
+        let user_operation: UnestimatedUserOperation = serde_json::from_str(
+            r#"{"sender":"0x9c5754De1443984659E1b3a8d1931D83475ba29C","nonce":"0x1","verificationGasLimit":"0x0","signature":"0x"}"#,
+        )
+        .unwrap();
+        let user_operation = UserOperation::try_from(user_operation).unwrap();
+        assert_eq!(user_operation.nonce, U256::from(1));
+        assert_eq!(
+            user_operation.verification_gas_limit,
+            U256::from(ESTIMATION_VERIFICATION_GAS_LIMIT)
+        );
+        assert_eq!(user_operation.call_gas_limit, U256::zero());
+        assert_eq!(
+            user_operation.signature,
+            Bytes::from(DUMMY_SIGNATURE.to_vec())
+        );
+
+        let invalid = UnestimatedUserOperation {
+            paymaster_and_data: Some(Bytes::from(vec![1; 19])),
+            ..Default::default()
+        };
+        assert!(UserOperation::try_from(invalid).is_err());
+        let invalid = UnestimatedUserOperation {
+            max_fee_per_gas: Some(U256::from(1)),
+            max_priority_fee_per_gas: Some(U256::from(2)),
+            ..Default::default()
+        };
+        assert!(invalid.validate().is_err());
+    }
+
+    #[test]
+    fn user_operation_receipt_json() {
+        let receipt = UserOperationReceipt {
+            user_op_hash: UserOperationHash::repeat_byte(1),
+            entry_point: "0x5FF137D4b0FDCD49DcA30c7CF57E578a026d2789"
+                .parse()
+                .unwrap(),
+            sender: "0x9c5754De1443984659E1b3a8d1931D83475ba29C"
+                .parse()
+                .unwrap(),
+            nonce: U256::from(1),
+            paymaster: None,
+            actual_gas_cost: U256::from(2),
+            actual_gas_used: U256::from(3),
+            success: true,
+            reason: String::new(),
+            logs: vec![],
+            receipt: TransactionReceipt::default(),
+        };
+        let json = serde_json::to_value(&receipt).unwrap();
+        assert_eq!(
+            json["entryPoint"],
+            "0x5FF137D4b0FDCD49DcA30c7CF57E578a026d2789"
+        );
+        assert_eq!(json["sender"], "0x9c5754De1443984659E1b3a8d1931D83475ba29C");
+        assert_eq!(json["nonce"], "0x1");
+        assert_eq!(json["actualGasCost"], "0x2");
+        assert!(json.get("paymaster").is_none());
+        assert!(json["receipt"].is_object());
+        assert_eq!(
+            serde_json::from_value::<UserOperationReceipt>(json).unwrap(),
+            receipt
+        );
+
+        let by_hash = UserOperationByHash {
+            user_operation: user_operation_by_hash(),
+            entry_point: receipt.entry_point,
+            block_number: Some(U64::from(16)),
+            block_hash: Some(H256::repeat_byte(2)),
+            transaction_hash: None,
+        };
+        let json = serde_json::to_value(&by_hash).unwrap();
+        assert_eq!(json["blockNumber"], "0x10");
+        assert!(json["transactionHash"].is_null());
+        assert_eq!(json["userOperation"]["callGasLimit"], "0x0");
+        assert_eq!(
+            serde_json::from_value::<UserOperationByHash>(json).unwrap(),
+            by_hash
+        );
+    }
+
+    fn user_operation_by_hash() -> UserOperation {
+        UserOperation {
+            sender: Address::zero(),
+            nonce: U256::zero(),
+            init_code: Bytes::default(),
+            call_data: Bytes::default(),
+            call_gas_limit: U256::zero(),
+            verification_gas_limit: U256::from(100000),
+            pre_verification_gas: U256::from(21000),
+            max_fee_per_gas: U256::zero(),
+            max_priority_fee_per_gas: U256::from(1e9 as u64),
+            paymaster_and_data: Bytes::default(),
+            signature: Bytes::default(),
+        }
+    }
+
+    #[test]
+    fn send_user_operation_options() {
+        let options: SendUserOperationOptions = serde_json::from_str("{}").unwrap();
+        assert!(!options.private);
+        let options: SendUserOperationOptions =
+            serde_json::from_str(r#"{"private":true}"#).unwrap();
+        assert!(options.private);
+    }
 }
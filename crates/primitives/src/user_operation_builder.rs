@@ -0,0 +1,164 @@
+use ethers::{
+    signers::Signer,
+    types::{Address, Bytes, U256},
+};
+
+use crate::{UserOperation, DUMMY_SIGNATURE, ESTIMATION_VERIFICATION_GAS_LIMIT};
+
+/// Builder of the user operation, the fields that are not set are empty or zero
+#[derive(Clone, Debug)]
+pub struct UserOperationBuilder {
+    user_operation: UserOperation,
+}
+
+impl UserOperationBuilder {
+    pub fn new(sender: Address, nonce: U256) -> Self {
+        Self {
+            user_operation: UserOperation {
+                sender,
+                nonce,
+                init_code: Bytes::default(),
+                call_data: Bytes::default(),
+                call_gas_limit: U256::zero(),
+                verification_gas_limit: U256::zero(),
+                pre_verification_gas: U256::zero(),
+                max_fee_per_gas: U256::zero(),
+                max_priority_fee_per_gas: U256::zero(),
+                paymaster_and_data: Bytes::default(),
+                signature: Bytes::default(),
+            },
+        }
+    }
+
+    /// Init code of the counterfactual account: the factory address and the factory call data
+    pub fn factory(mut self, factory: Address, factory_data: impl AsRef<[u8]>) -> Self {
+        self.user_operation.init_code = [factory.as_bytes(), factory_data.as_ref()].concat().into();
+        self
+    }
+
+    pub fn init_code(mut self, init_code: Bytes) -> Self {
+        self.user_operation.init_code = init_code;
+        self
+    }
+
+    pub fn call_data(mut self, call_data: Bytes) -> Self {
+        self.user_operation.call_data = call_data;
+        self
+    }
+
+    pub fn call_gas_limit(mut self, call_gas_limit: U256) -> Self {
+        self.user_operation.call_gas_limit = call_gas_limit;
+        self
+    }
+
+    pub fn verification_gas_limit(mut self, verification_gas_limit: U256) -> Self {
+        self.user_operation.verification_gas_limit = verification_gas_limit;
+        self
+    }
+
+    pub fn pre_verification_gas(mut self, pre_verification_gas: U256) -> Self {
+        self.user_operation.pre_verification_gas = pre_verification_gas;
+        self
+    }
+
+    pub fn fees(mut self, max_fee_per_gas: U256, max_priority_fee_per_gas: U256) -> Self {
+        self.user_operation.max_fee_per_gas = max_fee_per_gas;
+        self.user_operation.max_priority_fee_per_gas = max_priority_fee_per_gas;
+        self
+    }
+
+    /// Paymaster and the paymaster data
+    pub fn paymaster(mut self, paymaster: Address, paymaster_data: impl AsRef<[u8]>) -> Self {
+        self.user_operation.paymaster_and_data = [paymaster.as_bytes(), paymaster_data.as_ref()]
+            .concat()
+            .into();
+        self
+    }
+
+    pub fn signature(mut self, signature: Bytes) -> Self {
+        self.user_operation.signature = signature;
+        self
+    }
+
+    /// Sets the verification gas limit and the signature that the gas estimation needs (if they are not set): the
+    /// bound of the simulation and a dummy signature of the length of an ECDSA signature
+    pub fn with_defaults_for_estimation(mut self) -> Self {
+        if self.user_operation.verification_gas_limit.is_zero() {
+            self.user_operation.verification_gas_limit = ESTIMATION_VERIFICATION_GAS_LIMIT.into();
+        }
+        if self.user_operation.signature.is_empty() {
+            self.user_operation.signature = DUMMY_SIGNATURE.to_vec().into();
+        }
+        self
+    }
+
+    pub fn build(self) -> UserOperation {
+        self.user_operation
+    }
+
+    /// Builds the user operation signed by the signer (the signature of the builder is replaced)
+    pub async fn build_signed<S: Signer>(
+        self,
+        signer: &S,
+        entry_point: &Address,
+        chain_id: &U256,
+    ) -> Result<UserOperation, S::Error> {
+        let mut user_operation = self.user_operation;
+        user_operation.signature = user_operation.sign(signer, entry_point, chain_id).await?;
+        Ok(user_operation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::signers::LocalWallet;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn user_operation_builder() {
+        let wallet = LocalWallet::new(&mut ethers::core::rand::thread_rng());
+        let entry_point = "0x5FF137D4b0FDCD49DcA30c7CF57E578a026d2789"
+            .parse()
+            .unwrap();
+        let user_operation = UserOperationBuilder::new(Address::random(), U256::from(1))
+            .factory(Address::repeat_byte(1), [2u8, 3])
+            .call_gas_limit(U256::from(10))
+            .pre_verification_gas(U256::from(5))
+            .fees(U256::from(2), U256::from(1))
+            .with_defaults_for_estimation()
+            .build_signed(&wallet, &entry_point, &U256::from(1))
+            .await
+            .unwrap();
+        assert_eq!(user_operation.init_code.len(), 22);
+        assert_eq!(
+            user_operation.verification_gas_limit,
+            U256::from(ESTIMATION_VERIFICATION_GAS_LIMIT)
+        );
+
+        let signature =
+            ethers::types::Signature::try_from(user_operation.signature.as_ref()).unwrap();
+        assert_eq!(
+            signature
+                .recover(
+                    user_operation
+                        .hash(&entry_point, &U256::from(1))
+                        .0
+                        .as_bytes()
+                )
+                .unwrap(),
+            wallet.address()
+        );
+
+        assert_eq!(
+            user_operation.total_gas(),
+            U256::from(10 + ESTIMATION_VERIFICATION_GAS_LIMIT + 5)
+        );
+        assert_eq!(user_operation.max_cost(), user_operation.total_gas() * 2);
+        let with_paymaster = UserOperationBuilder::new(Address::random(), U256::zero())
+            .verification_gas_limit(U256::from(100))
+            .paymaster(Address::repeat_byte(4), Bytes::default())
+            .build();
+        assert_eq!(with_paymaster.total_gas(), U256::from(300));
+    }
+}
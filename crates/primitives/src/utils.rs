@@ -23,6 +23,48 @@ pub fn get_addr(bytes: &[u8]) -> Option<Address> {
 pub fn parse_address(s: &str) -> Result<Address, String> {
     Address::from_str(s).map_err(|_| format!("Adress {s} is not a valid address"))
 }
+/// Parses the address and, if it's mixed-case, checks the EIP-55 checksum (to catch the typos in the addresses that
+/// can't be recovered, e.g. of the entry points)
+pub fn parse_checksummed_address(s: &str) -> Result<Address, String> {
+    let address = parse_address(s)?;
+    let hex = s.trim_start_matches("0x");
+    let mixed_case =
+        hex.chars().any(|c| c.is_ascii_lowercase()) && hex.chars().any(|c| c.is_ascii_uppercase());
+    if mixed_case && to_checksum(&address, None).trim_start_matches("0x") != hex {
+        return Err(format!(
+            "Address {s} has an invalid checksum, expected {}",
+            to_checksum(&address, None)
+        ));
+    }
+    Ok(address)
+}
+
 pub fn parse_u256(s: &str) -> Result<U256, String> {
     U256::from_str_radix(s, 10).map_err(|_| format!("{s} is not a valid U256"))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksummed_address() {
+        let address: Address = "0x5ff137d4b0fdcd49dca30c7cf57e578a026d2789"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            parse_checksummed_address("0x5FF137D4b0FDCD49DcA30c7CF57E578a026d2789"),
+            Ok(address)
+        );
+        assert_eq!(
+            parse_checksummed_address("0x5ff137d4b0fdcd49dca30c7cf57e578a026d2789"),
+            Ok(address)
+        );
+        assert_eq!(
+            parse_checksummed_address("0x5FF137D4B0FDCD49DCA30C7CF57E578A026D2789"),
+            Ok(address)
+        );
+        assert!(parse_checksummed_address("0x5FF137D4b0FDCD49DcA30c7CF57E578a026d2788").is_err());
+        assert!(parse_checksummed_address("0x5Ff137D4b0FDCD49DcA30c7CF57E578a026d2789").is_err());
+    }
+}
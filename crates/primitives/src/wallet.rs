@@ -1,6 +1,6 @@
 use ethers::{
     prelude::{k256::ecdsa::SigningKey, rand},
-    signers::{coins_bip39::English, MnemonicBuilder, Signer},
+    signers::{coins_bip39::English, LocalWallet, MnemonicBuilder, Signer},
     types::U256,
 };
 use expanded_pathbuf::ExpandedPathBuf;
@@ -35,4 +35,27 @@ impl Wallet {
             signer: wallet.with_chain_id(chain_id.as_u64()),
         })
     }
+
+    pub fn from_phrase(phrase: &str, chain_id: U256) -> anyhow::Result<Self> {
+        let wallet = MnemonicBuilder::<English>::default()
+            .phrase(phrase)
+            .build()?;
+
+        Ok(Self {
+            signer: wallet.with_chain_id(chain_id.as_u64()),
+        })
+    }
+
+    /// Wallet of the hex encoded private key
+    pub fn from_private_key(private_key: &str, chain_id: U256) -> anyhow::Result<Self> {
+        let wallet: LocalWallet = private_key
+            .trim()
+            .trim_start_matches("0x")
+            .parse()
+            .map_err(|_| anyhow::anyhow!("Invalid private key"))?;
+
+        Ok(Self {
+            signer: wallet.with_chain_id(chain_id.as_u64()),
+        })
+    }
 }
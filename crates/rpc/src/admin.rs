@@ -0,0 +1,218 @@
+use std::collections::BTreeMap;
+
+use aa_bundler_grpc::{
+    bundler_client::BundlerClient, ConfigReloader, GetBundlesRequest,
+    GetRejectedUserOperationsRequest, SetAcceptingRequest, SetBundlingPausedRequest,
+    SetMinPriorityFeeRequest, SetThrottlingRequest, UoPoolGrpcClient,
+};
+use aa_bundler_primitives::{
+    BundleRecord, EntryPointHealth, PeerInfo, RejectedUserOperation, ThrottlingParams,
+    UserOperationHash,
+};
+use async_trait::async_trait;
+use ethers::types::{Address, U256};
+use jsonrpsee::core::RpcResult;
+use serde_json::Value;
+use tracing::info;
+
+use crate::admin_api::AdminApiServer;
+
+/// Runtime configuration of the uopool and the bundler, changed without restarting (and dropping the mempool)
+pub struct AdminApiServerImpl {
+    pub uopool_grpc_client: UoPoolGrpcClient,
+    pub bundler_grpc_client: BundlerClient<tonic::transport::Channel>,
+    /// Reloads the configuration of the uopool and the bundler running in the same process
+    pub config_reloader: Option<ConfigReloader>,
+    /// Options the process was started with, the secrets redacted
+    pub config: BTreeMap<String, Value>,
+}
+
+impl AdminApiServerImpl {
+    async fn set_accepting(&self, accepting: bool) -> RpcResult<()> {
+        let mut uopool_grpc_client = self.uopool_grpc_client.clone();
+        let request = tonic::Request::new(SetAcceptingRequest { accepting });
+        match uopool_grpc_client.set_accepting(request).await {
+            Ok(_) => Ok(()),
+            Err(status) => Err(jsonrpsee::core::Error::Custom(format!(
+                "GRPC error (uopool): {}",
+                status.message()
+            ))),
+        }
+    }
+
+    async fn set_bundling_paused(&self, paused: bool) -> RpcResult<()> {
+        let mut bundler_grpc_client = self.bundler_grpc_client.clone();
+        let request = tonic::Request::new(SetBundlingPausedRequest { paused });
+        match bundler_grpc_client.set_bundling_paused(request).await {
+            Ok(_) => Ok(()),
+            Err(status) => Err(jsonrpsee::core::Error::Custom(format!(
+                "GRPC error (bundler): {}",
+                status.message()
+            ))),
+        }
+    }
+
+    async fn last_bundle(&self, entry_point: &Address) -> RpcResult<Option<BundleRecord>> {
+        let mut bundler_grpc_client = self.bundler_grpc_client.clone();
+        let request = tonic::Request::new(GetBundlesRequest {
+            ep: Some((*entry_point).into()),
+        });
+        match bundler_grpc_client.get_bundles(request).await {
+            Ok(response) => Ok(response
+                .into_inner()
+                .bundles
+                .into_iter()
+                .next()
+                .map(Into::into)),
+            Err(status) => Err(jsonrpsee::core::Error::Custom(format!(
+                "GRPC error (bundler): {}",
+                status.message()
+            ))),
+        }
+    }
+}
+
+#[async_trait]
+impl AdminApiServer for AdminApiServerImpl {
+    async fn set_min_priority_fee_per_gas(&self, min_priority_fee_per_gas: U256) -> RpcResult<()> {
+        info!("Admin request to set the min priority fee per gas to {min_priority_fee_per_gas}");
+        let mut uopool_grpc_client = self.uopool_grpc_client.clone();
+        let request = tonic::Request::new(SetMinPriorityFeeRequest {
+            min_priority_fee_per_gas: Some(min_priority_fee_per_gas.into()),
+        });
+        match uopool_grpc_client.set_min_priority_fee(request).await {
+            Ok(_) => Ok(()),
+            Err(status) => Err(jsonrpsee::core::Error::Custom(format!(
+                "GRPC error (uopool): {}",
+                status.message()
+            ))),
+        }
+    }
+
+    async fn pause_user_operations(&self) -> RpcResult<()> {
+        info!("Admin request to pause accepting user operations");
+        self.set_accepting(false).await
+    }
+
+    async fn resume_user_operations(&self) -> RpcResult<()> {
+        info!("Admin request to resume accepting user operations");
+        self.set_accepting(true).await
+    }
+
+    async fn pause_bundling(&self) -> RpcResult<()> {
+        info!("Admin request to pause bundling");
+        self.set_bundling_paused(true).await
+    }
+
+    async fn resume_bundling(&self) -> RpcResult<()> {
+        info!("Admin request to resume bundling");
+        self.set_bundling_paused(false).await
+    }
+
+    async fn set_throttling(&self, throttling_params: ThrottlingParams) -> RpcResult<()> {
+        info!("Admin request to set the throttling parameters: {throttling_params:?}");
+        let mut uopool_grpc_client = self.uopool_grpc_client.clone();
+        let request = tonic::Request::new(SetThrottlingRequest {
+            min_inclusion_denominator: throttling_params.min_inclusion_denominator,
+            throttling_slack: throttling_params.throttling_slack,
+            ban_slack: throttling_params.ban_slack,
+        });
+        match uopool_grpc_client.set_throttling(request).await {
+            Ok(_) => Ok(()),
+            Err(status) => Err(jsonrpsee::core::Error::Custom(format!(
+                "GRPC error (uopool): {}",
+                status.message()
+            ))),
+        }
+    }
+
+    async fn get_peers(&self) -> RpcResult<Vec<PeerInfo>> {
+        let mut uopool_grpc_client = self.uopool_grpc_client.clone();
+        match uopool_grpc_client.get_peers(tonic::Request::new(())).await {
+            Ok(response) => Ok(response
+                .into_inner()
+                .peers
+                .into_iter()
+                .map(Into::into)
+                .collect()),
+            Err(status) => Err(jsonrpsee::core::Error::Custom(format!(
+                "GRPC error (uopool): {}",
+                status.message()
+            ))),
+        }
+    }
+
+    async fn get_rejected_user_operations(
+        &self,
+        user_operation_hash: Option<UserOperationHash>,
+        sender: Option<Address>,
+    ) -> RpcResult<Vec<RejectedUserOperation>> {
+        let mut uopool_grpc_client = self.uopool_grpc_client.clone();
+        let request = tonic::Request::new(GetRejectedUserOperationsRequest {
+            uo_hash: user_operation_hash.map(Into::into),
+            sender: sender.map(Into::into),
+        });
+        match uopool_grpc_client
+            .get_rejected_user_operations(request)
+            .await
+        {
+            Ok(response) => Ok(response
+                .into_inner()
+                .user_operations
+                .into_iter()
+                .map(Into::into)
+                .collect()),
+            Err(status) => Err(jsonrpsee::core::Error::Custom(format!(
+                "GRPC error (uopool): {}",
+                status.message()
+            ))),
+        }
+    }
+
+    async fn reload_config(&self) -> RpcResult<()> {
+        info!("Admin request to reload the configuration");
+        let config_reloader = self.config_reloader.as_ref().ok_or_else(|| {
+            jsonrpsee::core::Error::Custom(
+                "Configuration can't be reloaded by the JSON-RPC server running in a separate process, send SIGHUP to the uopool instead".to_string(),
+            )
+        })?;
+        config_reloader.reload().await.map(|_| ()).map_err(|err| {
+            jsonrpsee::core::Error::Custom(format!("Reloading the configuration failed: {err}"))
+        })
+    }
+
+    async fn get_entry_point_health(&self) -> RpcResult<Vec<EntryPointHealth>> {
+        let mut uopool_grpc_client = self.uopool_grpc_client.clone();
+        let entry_points: Vec<EntryPointHealth> = match uopool_grpc_client
+            .get_entry_point_health(tonic::Request::new(()))
+            .await
+        {
+            Ok(response) => response
+                .into_inner()
+                .entry_points
+                .into_iter()
+                .map(Into::into)
+                .collect(),
+            Err(status) => {
+                return Err(jsonrpsee::core::Error::Custom(format!(
+                    "GRPC error (uopool): {}",
+                    status.message()
+                )))
+            }
+        };
+
+        let mut health = Vec::with_capacity(entry_points.len());
+        for mut entry_point in entry_points {
+            if let Some(bundle) = self.last_bundle(&entry_point.entry_point).await? {
+                entry_point.last_bundle_status = Some(bundle.status);
+                entry_point.last_bundle_at = Some(bundle.created_at);
+            }
+            health.push(entry_point);
+        }
+        Ok(health)
+    }
+
+    async fn get_config(&self) -> RpcResult<BTreeMap<String, Value>> {
+        Ok(self.config.clone())
+    }
+}
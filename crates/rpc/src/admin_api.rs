@@ -0,0 +1,48 @@
+use std::collections::BTreeMap;
+
+use aa_bundler_primitives::{
+    EntryPointHealth, PeerInfo, RejectedUserOperation, ThrottlingParams, UserOperationHash,
+};
+use ethers::types::{Address, U256};
+use jsonrpsee::{core::RpcResult, proc_macros::rpc};
+use serde_json::Value;
+
+#[rpc(server, namespace = "admin")]
+pub trait AdminApi {
+    #[method(name = "setMinPriorityFeePerGas")]
+    async fn set_min_priority_fee_per_gas(&self, min_priority_fee_per_gas: U256) -> RpcResult<()>;
+
+    #[method(name = "pauseUserOperations")]
+    async fn pause_user_operations(&self) -> RpcResult<()>;
+
+    #[method(name = "resumeUserOperations")]
+    async fn resume_user_operations(&self) -> RpcResult<()>;
+
+    #[method(name = "pauseBundling")]
+    async fn pause_bundling(&self) -> RpcResult<()>;
+
+    #[method(name = "resumeBundling")]
+    async fn resume_bundling(&self) -> RpcResult<()>;
+
+    #[method(name = "setThrottling")]
+    async fn set_throttling(&self, throttling_params: ThrottlingParams) -> RpcResult<()>;
+
+    #[method(name = "getPeers")]
+    async fn get_peers(&self) -> RpcResult<Vec<PeerInfo>>;
+
+    #[method(name = "getRejectedUserOperations")]
+    async fn get_rejected_user_operations(
+        &self,
+        user_operation_hash: Option<UserOperationHash>,
+        sender: Option<Address>,
+    ) -> RpcResult<Vec<RejectedUserOperation>>;
+
+    #[method(name = "reloadConfig")]
+    async fn reload_config(&self) -> RpcResult<()>;
+
+    #[method(name = "getEntryPointHealth")]
+    async fn get_entry_point_health(&self) -> RpcResult<Vec<EntryPointHealth>>;
+
+    #[method(name = "getConfig")]
+    async fn get_config(&self) -> RpcResult<BTreeMap<String, Value>>;
+}
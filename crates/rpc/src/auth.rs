@@ -0,0 +1,465 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt,
+    future::Future,
+    path::Path,
+    pin::Pin,
+    str::FromStr,
+    sync::Arc,
+    task::{Context, Poll},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use aa_bundler_primitives::{REDACTED, UNAUTHORIZED_ERROR_CODE};
+use hyper::{header, header::HeaderMap, Body, Method, Request, Response, StatusCode};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use serde::Deserialize;
+use serde_json::Value;
+use tower::{Layer, Service};
+
+use crate::{
+    batch::call_filtered,
+    rate_limit::RateLimits,
+    response::{json_response, jsonrpc_error, jsonrpc_error_body},
+};
+
+/// Maximum difference between the `iat` claim of the JWT and the current time (same as the Engine API)
+const JWT_IAT_TOLERANCE_SECONDS: u64 = 60;
+
+/// Name under which the clients authenticated with the JWT are tracked
+const JWT_CLIENT_NAME: &str = "jwt";
+
+/// Header with the API key (alternative to the `Authorization: Bearer <key>` header)
+const API_KEY_HEADER: &str = "x-api-key";
+
+/// Prefix of the methods that require the admin scope
+pub(crate) const ADMIN_METHOD_PREFIX: &str = "admin_";
+
+/// Scope of the API keys that can call the `admin_` methods
+const ADMIN_SCOPE: &str = "admin";
+
+/// Static API key in the format `name:key[:requests_per_second[:user_operations_per_minute[:admin]]]`, the limits
+/// override the server rate limits for the clients using the key, and only the keys with the `admin` scope can
+/// call the `admin_` methods
+#[derive(Clone, PartialEq, Eq)]
+pub struct ApiKey {
+    pub name: String,
+    pub key: String,
+    pub limits: RateLimits,
+    pub admin: bool,
+}
+
+impl fmt::Debug for ApiKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ApiKey")
+            .field("name", &self.name)
+            .field("key", &REDACTED)
+            .field("limits", &self.limits)
+            .field("admin", &self.admin)
+            .finish()
+    }
+}
+
+impl FromStr for ApiKey {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse_limit = |limit: Option<&str>| -> anyhow::Result<Option<u32>> {
+            limit
+                .filter(|limit| !limit.is_empty())
+                .map(|limit| limit.parse::<u32>())
+                .transpose()
+                .map_err(|err| anyhow::anyhow!("Invalid API key limit: {err}"))
+        };
+
+        let mut parts = s.split(':');
+        let name = parts.next().unwrap_or_default();
+        let key = parts.next().unwrap_or_default();
+        if name.is_empty() || key.is_empty() {
+            return Err(anyhow::anyhow!(
+                "API key has to be in the format name:key[:requests_per_second[:user_operations_per_minute[:admin]]]"
+            ));
+        }
+        let limits = RateLimits {
+            requests_per_second: parse_limit(parts.next())?,
+            user_operations_per_minute: parse_limit(parts.next())?,
+        };
+        let admin = match parts.next().unwrap_or_default() {
+            "" => false,
+            ADMIN_SCOPE => true,
+            scope => return Err(anyhow::anyhow!("Invalid API key scope: {scope}")),
+        };
+
+        Ok(Self {
+            name: name.to_string(),
+            key: key.to_string(),
+            limits,
+            admin,
+        })
+    }
+}
+
+/// Client that passed the authentication, added to the extensions of the request
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AuthenticatedClient {
+    pub name: String,
+    pub limits: RateLimits,
+    /// Whether the client can call the `admin_` methods (API key with the admin scope or the JWT)
+    pub admin: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct Claims {
+    iat: u64,
+}
+
+/// Authenticates the clients with the static API keys or with the JWT signed by the shared secret (HS256)
+#[derive(Debug, Default)]
+pub struct Authenticator {
+    api_keys: HashMap<String, ApiKey>,
+    jwt_secret: Option<Vec<u8>>,
+}
+
+impl Authenticator {
+    pub fn new(api_keys: Vec<ApiKey>, jwt_secret: Option<Vec<u8>>) -> Self {
+        Self {
+            api_keys: api_keys
+                .into_iter()
+                .map(|api_key| (api_key.key.clone(), api_key))
+                .collect(),
+            jwt_secret,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.api_keys.is_empty() || self.jwt_secret.is_some()
+    }
+
+    fn verify_jwt(&self, token: &str) -> bool {
+        let secret = match &self.jwt_secret {
+            Some(secret) => secret,
+            None => return false,
+        };
+
+        let mut validation = Validation::new(Algorithm::HS256);
+        validation.validate_exp = false;
+        validation.required_spec_claims.clear();
+        let claims = match jsonwebtoken::decode::<Claims>(
+            token,
+            &DecodingKey::from_secret(secret),
+            &validation,
+        ) {
+            Ok(token) => token.claims,
+            Err(_) => return false,
+        };
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or_default();
+        now.abs_diff(claims.iat) <= JWT_IAT_TOLERANCE_SECONDS
+    }
+
+    /// Returns the client if the request carries a valid API key or JWT
+    pub fn authenticate(&self, headers: &HeaderMap) -> Option<AuthenticatedClient> {
+        let token = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .or_else(|| {
+                headers
+                    .get(API_KEY_HEADER)
+                    .and_then(|value| value.to_str().ok())
+            })?
+            .trim();
+
+        if let Some(api_key) = self.api_keys.get(token) {
+            return Some(AuthenticatedClient {
+                name: api_key.name.clone(),
+                limits: api_key.limits,
+                admin: api_key.admin,
+            });
+        }
+
+        self.verify_jwt(token).then(|| AuthenticatedClient {
+            name: JWT_CLIENT_NAME.to_string(),
+            limits: RateLimits::default(),
+            admin: true,
+        })
+    }
+}
+
+/// Reads the hex encoded JWT secret (as used by the Engine API) from the file
+pub fn read_jwt_secret(path: &Path) -> anyhow::Result<Vec<u8>> {
+    let secret = std::fs::read_to_string(path)?;
+    let secret = secret.trim();
+    Ok(hex::decode(secret.strip_prefix("0x").unwrap_or(secret))?)
+}
+
+fn unauthorized() -> Response<Body> {
+    let mut response = jsonrpc_error(Value::Null, UNAUTHORIZED_ERROR_CODE, "Unauthorized");
+    *response.status_mut() = StatusCode::UNAUTHORIZED;
+    response
+}
+
+/// Middleware that rejects the requests (and WebSocket handshakes) of the unauthenticated clients
+#[derive(Clone, Debug)]
+pub struct AuthLayer {
+    authenticator: Arc<Authenticator>,
+}
+
+impl AuthLayer {
+    pub fn new(authenticator: Arc<Authenticator>) -> Self {
+        Self { authenticator }
+    }
+}
+
+impl<S> Layer<S> for AuthLayer {
+    type Service = Auth<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Auth {
+            inner,
+            authenticator: self.authenticator.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Auth<S> {
+    inner: S,
+    authenticator: Arc<Authenticator>,
+}
+
+impl<S> Service<Request<Body>> for Auth<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>, Error = Box<dyn Error + Send + Sync>>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send,
+{
+    type Response = Response<Body>;
+    type Error = Box<dyn Error + Send + Sync>;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request<Body>) -> Self::Future {
+        if !self.authenticator.is_enabled() {
+            return Box::pin(self.inner.call(request));
+        }
+
+        match self.authenticator.authenticate(request.headers()) {
+            Some(client) => {
+                metrics::counter!("rpc_requests", 1, "api_key" => client.name.clone());
+                request.extensions_mut().insert(client);
+                Box::pin(self.inner.call(request))
+            }
+            None => {
+                metrics::counter!("rpc_requests_unauthorized", 1);
+                Box::pin(async { Ok(unauthorized()) })
+            }
+        }
+    }
+}
+
+fn is_admin_call(call: &Value) -> bool {
+    call.get("method")
+        .and_then(|method| method.as_str())
+        .map_or(false, |method| method.starts_with(ADMIN_METHOD_PREFIX))
+}
+
+/// Error response of the `admin_` call of a client without the admin scope
+fn admin_scope_required(call: &Value) -> Value {
+    jsonrpc_error_body(
+        call.get("id").cloned().unwrap_or_default(),
+        UNAUTHORIZED_ERROR_CODE,
+        "The admin scope is required",
+    )
+}
+
+pub(crate) fn is_websocket_upgrade(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::UPGRADE)
+        .and_then(|value| value.to_str().ok())
+        .map_or(false, |value| value.eq_ignore_ascii_case("websocket"))
+}
+
+/// Middleware that rejects the `admin_` calls of the authenticated clients without the admin scope, enabled on the
+/// listeners that serve the admin namespace with the authentication. The calls over WebSocket can't be checked, so
+/// the WebSocket connections of these clients are rejected on such listeners.
+#[derive(Clone, Debug)]
+pub struct AdminScopeLayer {
+    enabled: bool,
+}
+
+impl AdminScopeLayer {
+    pub fn new(enabled: bool) -> Self {
+        Self { enabled }
+    }
+}
+
+impl<S> Layer<S> for AdminScopeLayer {
+    type Service = AdminScope<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        AdminScope {
+            inner,
+            enabled: self.enabled,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct AdminScope<S> {
+    inner: S,
+    enabled: bool,
+}
+
+impl<S> Service<Request<Body>> for AdminScope<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>, Error = Box<dyn Error + Send + Sync>>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send,
+{
+    type Response = Response<Body>;
+    type Error = Box<dyn Error + Send + Sync>;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        let admin = request
+            .extensions()
+            .get::<AuthenticatedClient>()
+            .map_or(false, |client| client.admin);
+        if !self.enabled || admin {
+            return Box::pin(self.inner.call(request));
+        }
+        if is_websocket_upgrade(request.headers()) {
+            return Box::pin(async { Ok(unauthorized()) });
+        }
+        if request.method() != Method::POST {
+            return Box::pin(self.inner.call(request));
+        }
+
+        // the inner service was polled to be ready, so it's the one that has to be called
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        Box::pin(async move {
+            let (parts, body) = request.into_parts();
+            let body = hyper::body::to_bytes(body).await?;
+            match serde_json::from_slice::<Value>(&body) {
+                Ok(Value::Array(calls)) if calls.iter().any(is_admin_call) => {
+                    let (rejected, accepted): (Vec<Value>, Vec<Value>) =
+                        calls.into_iter().partition(is_admin_call);
+                    let rejected = rejected.iter().map(admin_scope_required).collect();
+                    call_filtered(&mut inner, parts, accepted, rejected).await
+                }
+                Ok(call) if is_admin_call(&call) => Ok(json_response(&admin_scope_required(&call))),
+                // the other requests (and the invalid ones) are handled by the server
+                _ => {
+                    inner
+                        .call(Request::from_parts(parts, Body::from(body)))
+                        .await
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::{EncodingKey, Header};
+
+    #[test]
+    fn authenticate() {
+        let api_key: ApiKey = "wallet:secret-key:10".parse().unwrap();
+        assert_eq!(api_key.limits.requests_per_second, Some(10));
+        assert_eq!(api_key.limits.user_operations_per_minute, None);
+        assert!(!api_key.admin);
+        assert!(!format!("{api_key:?}").contains("secret-key"));
+        assert!("wallet".parse::<ApiKey>().is_err());
+        assert!("wallet:key:fast".parse::<ApiKey>().is_err());
+        assert!("ops:key:::root".parse::<ApiKey>().is_err());
+        let admin_key: ApiKey = "ops:admin-key:::admin".parse().unwrap();
+        assert!(admin_key.admin);
+        assert_eq!(admin_key.limits, RateLimits::default());
+
+        let secret = vec![1u8; 32];
+        let authenticator = Authenticator::new(vec![api_key, admin_key], Some(secret.clone()));
+
+        let mut headers = HeaderMap::new();
+        assert_eq!(authenticator.authenticate(&headers), None);
+        headers.insert(API_KEY_HEADER, "secret-key".parse().unwrap());
+        assert_eq!(
+            authenticator
+                .authenticate(&headers)
+                .map(|client| client.name),
+            Some("wallet".to_string())
+        );
+        assert!(!authenticator.authenticate(&headers).unwrap().admin);
+        headers.insert(API_KEY_HEADER, "admin-key".parse().unwrap());
+        assert!(authenticator.authenticate(&headers).unwrap().admin);
+
+        let iat = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let jwt = |iat: u64| {
+            jsonwebtoken::encode(
+                &Header::new(Algorithm::HS256),
+                &serde_json::json!({ "iat": iat }),
+                &EncodingKey::from_secret(&secret),
+            )
+            .unwrap()
+        };
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::AUTHORIZATION,
+            format!("Bearer {}", jwt(iat)).parse().unwrap(),
+        );
+        assert_eq!(
+            authenticator
+                .authenticate(&headers)
+                .map(|client| client.name),
+            Some(JWT_CLIENT_NAME.to_string())
+        );
+        headers.insert(
+            header::AUTHORIZATION,
+            format!("Bearer {}", jwt(iat - 2 * JWT_IAT_TOLERANCE_SECONDS))
+                .parse()
+                .unwrap(),
+        );
+        assert_eq!(authenticator.authenticate(&headers), None);
+    }
+
+    #[test]
+    fn admin_calls() {
+        let call =
+            serde_json::json!({"jsonrpc": "2.0", "id": 7, "method": "admin_setMinPriorityFee"});
+        assert!(is_admin_call(&call));
+        assert!(!is_admin_call(
+            &serde_json::json!({"jsonrpc": "2.0", "id": 1, "method": "eth_chainId"})
+        ));
+
+        let error = admin_scope_required(&call);
+        assert_eq!(error["error"]["code"], UNAUTHORIZED_ERROR_CODE);
+        assert_eq!(error["id"], 7);
+
+        let mut headers = HeaderMap::new();
+        assert!(!is_websocket_upgrade(&headers));
+        headers.insert(header::UPGRADE, "WebSocket".parse().unwrap());
+        assert!(is_websocket_upgrade(&headers));
+    }
+}
@@ -0,0 +1,223 @@
+use std::{
+    error::Error,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use aa_bundler_primitives::LIMIT_EXCEEDED_ERROR_CODE;
+use hyper::{header, Body, Method, Request, Response};
+use serde_json::Value;
+use tower::{Layer, Service};
+
+use crate::response::{json_response, jsonrpc_error_body};
+
+/// Method that submits the user operations
+pub const SEND_USER_OPERATION_METHOD: &str = "eth_sendUserOperation";
+
+pub fn is_send_user_operation(call: &Value) -> bool {
+    call.get("method").and_then(|method| method.as_str()) == Some(SEND_USER_OPERATION_METHOD)
+}
+
+/// Limits of the batch requests (0 means no limit)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BatchLimits {
+    /// Maximum number of calls in the batch
+    pub max_batch_size: usize,
+    /// Maximum number of `eth_sendUserOperation` calls in the batch
+    pub max_user_operations: usize,
+}
+
+impl BatchLimits {
+    pub fn is_unlimited(&self) -> bool {
+        self.max_batch_size == 0 && self.max_user_operations == 0
+    }
+
+    /// Splits the batch into the calls that are executed and the error responses of the calls that are
+    /// rejected (user operations above the limit). Returns the error response if the whole batch is rejected.
+    pub fn apply(&self, calls: Vec<Value>) -> Result<(Vec<Value>, Vec<Value>), Value> {
+        if self.max_batch_size > 0 && calls.len() > self.max_batch_size {
+            return Err(jsonrpc_error_body(
+                Value::Null,
+                LIMIT_EXCEEDED_ERROR_CODE,
+                format!(
+                    "Batch of {} calls exceeds the limit of {}",
+                    calls.len(),
+                    self.max_batch_size
+                ),
+            ));
+        }
+
+        let mut accepted = Vec::with_capacity(calls.len());
+        let mut rejected = Vec::new();
+        let mut user_operations = 0;
+        for call in calls {
+            if is_send_user_operation(&call) {
+                user_operations += 1;
+                if self.max_user_operations > 0 && user_operations > self.max_user_operations {
+                    rejected.push(jsonrpc_error_body(
+                        call.get("id").cloned().unwrap_or_default(),
+                        LIMIT_EXCEEDED_ERROR_CODE,
+                        format!(
+                            "Batch exceeds the limit of {} user operations",
+                            self.max_user_operations
+                        ),
+                    ));
+                    continue;
+                }
+            }
+            accepted.push(call);
+        }
+        Ok((accepted, rejected))
+    }
+}
+
+/// Middleware that applies the [BatchLimits](BatchLimits) to the HTTP batch requests
+#[derive(Clone, Debug)]
+pub struct BatchLimitLayer {
+    limits: BatchLimits,
+}
+
+impl BatchLimitLayer {
+    pub fn new(limits: BatchLimits) -> Self {
+        Self { limits }
+    }
+}
+
+impl<S> Layer<S> for BatchLimitLayer {
+    type Service = BatchLimit<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        BatchLimit {
+            inner,
+            limits: self.limits,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct BatchLimit<S> {
+    inner: S,
+    limits: BatchLimits,
+}
+
+impl<S> Service<Request<Body>> for BatchLimit<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>, Error = Box<dyn Error + Send + Sync>>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send,
+{
+    type Response = Response<Body>;
+    type Error = Box<dyn Error + Send + Sync>;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        if self.limits.is_unlimited() || request.method() != Method::POST {
+            return Box::pin(self.inner.call(request));
+        }
+
+        let limits = self.limits;
+        // the inner service was polled to be ready, so it's the one that has to be called
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        Box::pin(async move {
+            let (parts, body) = request.into_parts();
+            let body = hyper::body::to_bytes(body).await?;
+            let calls = match serde_json::from_slice::<Value>(&body) {
+                Ok(Value::Array(calls)) => calls,
+                // single calls and invalid requests are handled by the server
+                _ => {
+                    return inner
+                        .call(Request::from_parts(parts, Body::from(body)))
+                        .await
+                }
+            };
+
+            let (accepted, rejected) = match limits.apply(calls) {
+                Ok(batch) => batch,
+                Err(error) => return Ok(json_response(&error)),
+            };
+            if rejected.is_empty() {
+                return inner
+                    .call(Request::from_parts(parts, Body::from(body)))
+                    .await;
+            }
+            call_filtered(&mut inner, parts, accepted, rejected).await
+        })
+    }
+}
+
+/// Calls the inner service with the accepted calls of the batch and appends the error responses of the
+/// rejected calls to its response
+pub(crate) async fn call_filtered<S>(
+    inner: &mut S,
+    parts: hyper::http::request::Parts,
+    accepted: Vec<Value>,
+    rejected: Vec<Value>,
+) -> Result<Response<Body>, Box<dyn Error + Send + Sync>>
+where
+    S: Service<Request<Body>, Response = Response<Body>, Error = Box<dyn Error + Send + Sync>>,
+{
+    if accepted.is_empty() {
+        return Ok(json_response(&Value::Array(rejected)));
+    }
+
+    let body = Value::Array(accepted).to_string();
+    let mut request = Request::from_parts(parts, Body::from(body));
+    request.headers_mut().remove(header::CONTENT_LENGTH);
+    let response = inner.call(request).await?;
+
+    // responses of the batch are matched by the id, so the errors are appended to the executed calls
+    let (mut parts, body) = response.into_parts();
+    let body = hyper::body::to_bytes(body).await?;
+    let body = match serde_json::from_slice::<Value>(&body) {
+        Ok(Value::Array(mut responses)) => {
+            responses.extend(rejected);
+            Body::from(Value::Array(responses).to_string())
+        }
+        _ => Body::from(body),
+    };
+    parts.headers.remove(header::CONTENT_LENGTH);
+    Ok(Response::from_parts(parts, body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn batch_limits() {
+        let call = |id: u64, method: &str| serde_json::json!({"jsonrpc": "2.0", "id": id, "method": method});
+        let calls = vec![
+            call(1, "eth_estimateUserOperationGas"),
+            call(2, SEND_USER_OPERATION_METHOD),
+            call(3, SEND_USER_OPERATION_METHOD),
+        ];
+
+        let limits = BatchLimits {
+            max_batch_size: 2,
+            max_user_operations: 1,
+        };
+        assert!(limits.apply(calls.clone()).is_err());
+
+        let limits = BatchLimits {
+            max_batch_size: 3,
+            max_user_operations: 1,
+        };
+        let (accepted, rejected) = limits.apply(calls.clone()).unwrap();
+        assert_eq!(accepted, calls[..2].to_vec());
+        assert_eq!(rejected.len(), 1);
+        assert_eq!(rejected[0]["id"], 3);
+
+        let (accepted, rejected) = BatchLimits::default().apply(calls.clone()).unwrap();
+        assert_eq!(accepted, calls);
+        assert!(rejected.is_empty());
+    }
+}
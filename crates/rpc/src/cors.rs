@@ -0,0 +1,181 @@
+use std::{
+    error::Error,
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use hyper::{
+    header::{self, HeaderValue},
+    Body, Method, Request, Response, StatusCode,
+};
+use tower::{Layer, Service};
+
+/// Seconds for which the browsers can cache the preflight response
+const PREFLIGHT_MAX_AGE: &str = "86400";
+
+/// Origins that are allowed to call the server from the browser. Each domain is either `*` (any origin),
+/// an exact origin (`https://wallet.example`) or contains a wildcard (`https://*.example`).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AllowedOrigins(Vec<String>);
+
+impl AllowedOrigins {
+    pub fn new(domains: Vec<String>) -> Self {
+        Self(domains)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn is_any(&self) -> bool {
+        self.0.iter().any(|domain| domain == "*")
+    }
+
+    pub fn is_allowed(&self, origin: &str) -> bool {
+        self.0.iter().any(|domain| match domain.split_once('*') {
+            Some((prefix, suffix)) => {
+                origin.len() >= prefix.len() + suffix.len()
+                    && origin[..prefix.len()].eq_ignore_ascii_case(prefix)
+                    && origin[origin.len() - suffix.len()..].eq_ignore_ascii_case(suffix)
+            }
+            None => domain.eq_ignore_ascii_case(origin),
+        })
+    }
+}
+
+/// Middleware that answers the CORS preflight requests and adds the CORS headers to the responses
+#[derive(Clone, Debug)]
+pub struct CorsLayer {
+    origins: Arc<AllowedOrigins>,
+}
+
+impl CorsLayer {
+    pub fn new(origins: AllowedOrigins) -> Self {
+        Self {
+            origins: Arc::new(origins),
+        }
+    }
+}
+
+impl<S> Layer<S> for CorsLayer {
+    type Service = Cors<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Cors {
+            inner,
+            origins: self.origins.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Cors<S> {
+    inner: S,
+    origins: Arc<AllowedOrigins>,
+}
+
+impl<S> Cors<S> {
+    /// Value of the `Access-Control-Allow-Origin` header, `None` if the origin isn't allowed
+    fn allow_origin(&self, origin: &HeaderValue) -> Option<HeaderValue> {
+        if self.origins.is_any() {
+            Some(HeaderValue::from_static("*"))
+        } else {
+            origin
+                .to_str()
+                .ok()
+                .filter(|origin| self.origins.is_allowed(origin))
+                .map(|_| origin.clone())
+        }
+    }
+}
+
+impl<S> Service<Request<Body>> for Cors<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>, Error = Box<dyn Error + Send + Sync>>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send,
+{
+    type Response = Response<Body>;
+    type Error = Box<dyn Error + Send + Sync>;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        let origin = match request.headers().get(header::ORIGIN) {
+            Some(origin) if !self.origins.is_empty() => origin.clone(),
+            _ => return Box::pin(self.inner.call(request)),
+        };
+        let allow_origin = self.allow_origin(&origin);
+        let is_preflight = request.method() == Method::OPTIONS
+            && request
+                .headers()
+                .contains_key(header::ACCESS_CONTROL_REQUEST_METHOD);
+
+        if is_preflight {
+            let response = match allow_origin {
+                Some(allow_origin) => {
+                    let allow_headers = request
+                        .headers()
+                        .get(header::ACCESS_CONTROL_REQUEST_HEADERS)
+                        .cloned()
+                        .unwrap_or_else(|| HeaderValue::from_static("content-type"));
+                    Response::builder()
+                        .status(StatusCode::OK)
+                        .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin)
+                        .header(header::ACCESS_CONTROL_ALLOW_METHODS, "POST, OPTIONS")
+                        .header(header::ACCESS_CONTROL_ALLOW_HEADERS, allow_headers)
+                        .header(header::ACCESS_CONTROL_MAX_AGE, PREFLIGHT_MAX_AGE)
+                        .header(header::VARY, "origin")
+                        .body(Body::empty())
+                }
+                None => Response::builder()
+                    .status(StatusCode::FORBIDDEN)
+                    .body(Body::empty()),
+            }
+            .map_err(Into::into);
+            return Box::pin(async move { response });
+        }
+
+        let response = self.inner.call(request);
+        Box::pin(async move {
+            let mut response = response.await?;
+            if let Some(allow_origin) = allow_origin {
+                let headers = response.headers_mut();
+                headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin);
+                headers.insert(header::VARY, HeaderValue::from_static("origin"));
+            }
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allowed_origins() {
+        let origins = AllowedOrigins::new(vec![
+            "https://wallet.example".into(),
+            "https://*.bundler.example".into(),
+        ]);
+        assert!(!origins.is_any());
+        assert!(origins.is_allowed("https://wallet.example"));
+        assert!(origins.is_allowed("https://WALLET.example"));
+        assert!(origins.is_allowed("https://app.bundler.example"));
+        assert!(!origins.is_allowed("https://bundler.example"));
+        assert!(!origins.is_allowed("http://app.bundler.example"));
+        assert!(!origins.is_allowed("https://evil.example"));
+
+        let origins = AllowedOrigins::new(vec!["*".into()]);
+        assert!(origins.is_any());
+        assert!(origins.is_allowed("https://evil.example"));
+    }
+}
@@ -1,9 +1,10 @@
 use aa_bundler_grpc::{
-    bundler_client::BundlerClient, uo_pool_client::UoPoolClient, ClearResult,
-    GetAllReputationRequest, GetAllReputationResult, GetAllRequest, GetAllResult, Mode as GrpcMode,
-    SetModeRequest, SetReputationRequest, SetReputationResult,
+    bundler_client::BundlerClient, ClearResult, ClearUserOperationsRequest,
+    GetAllReputationRequest, GetAllReputationResult, GetAllRequest, GetAllResult,
+    GetBundlesRequest, Mode as GrpcMode, SetModeRequest, SetReputationRequest, SetReputationResult,
+    UoPoolGrpcClient,
 };
-use aa_bundler_primitives::{Mode, ReputationEntry, UserOperation, DEFAULT_INTERVAL};
+use aa_bundler_primitives::{BundleRecord, Mode, ReputationEntry, UserOperation, DEFAULT_INTERVAL};
 use anyhow::format_err;
 use async_trait::async_trait;
 use ethers::types::{Address, H256};
@@ -12,8 +13,11 @@ use tracing::{debug, trace};
 
 use crate::debug_api::DebugApiServer;
 
+/// Number of the user operations (or reputation entries) fetched from the uopool per request of the dumps
+const DUMP_PAGE_SIZE: u64 = 500;
+
 pub struct DebugApiServerImpl {
-    pub uopool_grpc_client: UoPoolClient<tonic::transport::Channel>,
+    pub uopool_grpc_client: UoPoolGrpcClient,
     pub bundler_grpc_client: BundlerClient<tonic::transport::Channel>,
 }
 
@@ -37,29 +41,51 @@ impl DebugApiServer for DebugApiServerImpl {
         ))
     }
 
-    async fn dump_mempool(&self, entry_point: Address) -> RpcResult<Vec<UserOperation>> {
+    async fn clear_mempool(&self) -> RpcResult<()> {
         let mut uopool_grpc_client = self.uopool_grpc_client.clone();
 
-        let request = tonic::Request::new(GetAllRequest {
-            ep: Some(entry_point.into()),
-        });
-        debug!("Sending getAll request to mempool");
         let response = uopool_grpc_client
-            .get_all(request)
+            .clear_user_operations(tonic::Request::new(ClearUserOperationsRequest::default()))
             .await
             .map_err(|status| format_err!("GRPC error (uopool): {}", status.message()))?
             .into_inner();
-        trace!("Getall from mempool: {response:?}");
-        if response.result == GetAllResult::GotAll as i32 {
-            let mut uos: Vec<UserOperation> =
-                response.uos.iter().map(|uo| uo.clone().into()).collect();
-            uos.sort_by(|a, b| a.nonce.cmp(&b.nonce));
-            return Ok(uos);
+        debug!("Cleared {} user operations", response.removed);
+
+        Ok(())
+    }
+
+    async fn dump_mempool(&self, entry_point: Address) -> RpcResult<Vec<UserOperation>> {
+        let mut uopool_grpc_client = self.uopool_grpc_client.clone();
+
+        let mut uos: Vec<UserOperation> = vec![];
+        loop {
+            let request = tonic::Request::new(GetAllRequest {
+                ep: Some(entry_point.into()),
+                offset: uos.len() as u64,
+                limit: DUMP_PAGE_SIZE,
+            });
+            debug!("Sending getAll request to mempool");
+            let response = uopool_grpc_client
+                .get_all(request)
+                .await
+                .map_err(|status| format_err!("GRPC error (uopool): {}", status.message()))?
+                .into_inner();
+            trace!("Getall from mempool: {response:?}");
+            if response.result != GetAllResult::GotAll as i32 {
+                return Err(jsonrpsee::core::Error::Custom(
+                    "error getting mempool".to_string(),
+                ));
+            }
+
+            let last_page = response.uos.len() < DUMP_PAGE_SIZE as usize;
+            uos.extend(response.uos.into_iter().map(|uo| uo.into()));
+            if last_page || uos.len() as u64 >= response.total {
+                break;
+            }
         }
 
-        Err(jsonrpsee::core::Error::Custom(
-            "error getting mempool".to_string(),
-        ))
+        uos.sort_by(|a, b| a.nonce.cmp(&b.nonce));
+        Ok(uos)
     }
 
     async fn set_reputation(
@@ -92,23 +118,33 @@ impl DebugApiServer for DebugApiServerImpl {
     async fn dump_reputation(&self, entry_point: Address) -> RpcResult<Vec<ReputationEntry>> {
         let mut uopool_grpc_client = self.uopool_grpc_client.clone();
 
-        let request = tonic::Request::new(GetAllReputationRequest {
-            ep: Some(entry_point.into()),
-        });
-
-        let response = uopool_grpc_client
-            .get_all_reputation(request)
-            .await
-            .map_err(|status| format_err!("GRPC error (uopool): {}", status.message()))?
-            .into_inner();
-
-        if response.result == GetAllReputationResult::GotAllReputation as i32 {
-            return Ok(response.res.iter().map(|re| re.clone().into()).collect());
+        let mut entries: Vec<ReputationEntry> = vec![];
+        loop {
+            let request = tonic::Request::new(GetAllReputationRequest {
+                ep: Some(entry_point.into()),
+                offset: entries.len() as u64,
+                limit: DUMP_PAGE_SIZE,
+            });
+
+            let response = uopool_grpc_client
+                .get_all_reputation(request)
+                .await
+                .map_err(|status| format_err!("GRPC error (uopool): {}", status.message()))?
+                .into_inner();
+            if response.result != GetAllReputationResult::GotAllReputation as i32 {
+                return Err(jsonrpsee::core::Error::Custom(
+                    "error getting reputation".to_string(),
+                ));
+            }
+
+            let last_page = response.res.len() < DUMP_PAGE_SIZE as usize;
+            entries.extend(response.res.into_iter().map(ReputationEntry::from));
+            if last_page || entries.len() as u64 >= response.total {
+                break;
+            }
         }
 
-        Err(jsonrpsee::core::Error::Custom(
-            "error getting reputation".to_string(),
-        ))
+        Ok(entries)
     }
 
     async fn set_bundling_mode(&self, mode: Mode) -> RpcResult<()> {
@@ -143,4 +179,23 @@ impl DebugApiServer for DebugApiServerImpl {
             ))),
         }
     }
+
+    async fn get_bundles(&self, entry_point: Address) -> RpcResult<Vec<BundleRecord>> {
+        let mut bundler_grpc_client = self.bundler_grpc_client.clone();
+        let request = tonic::Request::new(GetBundlesRequest {
+            ep: Some(entry_point.into()),
+        });
+        match bundler_grpc_client.get_bundles(request).await {
+            Ok(response) => Ok(response
+                .into_inner()
+                .bundles
+                .into_iter()
+                .map(|record| record.into())
+                .collect()),
+            Err(status) => Err(jsonrpsee::core::Error::Custom(format!(
+                "GRPC error (bundler): {}",
+                status.message()
+            ))),
+        }
+    }
 }
@@ -1,4 +1,4 @@
-use aa_bundler_primitives::{Mode, ReputationEntry, UserOperation};
+use aa_bundler_primitives::{BundleRecord, Mode, ReputationEntry, UserOperation};
 use ethers::types::{Address, H256};
 use jsonrpsee::{core::RpcResult, proc_macros::rpc};
 
@@ -7,6 +7,10 @@ pub trait DebugApi {
     #[method(name = "clearState")]
     async fn clear_state(&self) -> RpcResult<()>;
 
+    /// Removes the user operations of all entry points (unlike `clearState`, the reputation is kept)
+    #[method(name = "clearMempool")]
+    async fn clear_mempool(&self) -> RpcResult<()>;
+
     #[method(name = "dumpMempool")]
     async fn dump_mempool(&self, entry_point: Address) -> RpcResult<Vec<UserOperation>>;
 
@@ -25,4 +29,7 @@ pub trait DebugApi {
 
     #[method(name = "sendBundleNow")]
     async fn send_bundle_now(&self) -> RpcResult<H256>;
+
+    #[method(name = "getBundles")]
+    async fn get_bundles(&self, entry_point: Address) -> RpcResult<Vec<BundleRecord>>;
 }
@@ -1,30 +1,51 @@
 use std::str::FromStr;
 
 use aa_bundler_grpc::{
-    uo_pool_client::UoPoolClient, AddRequest, AddResult, EstimateUserOperationGasRequest,
-    EstimateUserOperationGasResult, UserOperationHashRequest,
+    new_submission_id, set_submission_id, user_operation_error, AddRequest, AddResult,
+    EstimateUserOperationGasRequest, EstimateUserOperationGasResult, MempoolEvent,
+    MempoolEventKind, SubscribeEventsRequest, UoPoolGrpcClient, UserOperationHashRequest,
 };
 use aa_bundler_primitives::{
-    UserOperation, UserOperationByHash, UserOperationGasEstimation, UserOperationHash,
-    UserOperationPartial, UserOperationReceipt, USER_OPERATION_HASH_ERROR_CODE,
+    IncludedUserOperation, PendingUserOperation, SendUserOperationOptions,
+    UnestimatedUserOperation, UserOperation, UserOperationByHash, UserOperationGasEstimation,
+    UserOperationHash, UserOperationReceipt, UserOperationSubscriptionFilter,
+    UserOperationWithAuthorization, USER_OPERATION_HASH_ERROR_CODE,
 };
 use anyhow::format_err;
 use async_trait::async_trait;
 use ethers::{
-    types::{Address, U64},
+    types::{spoof, Address, U64},
     utils::to_checksum,
 };
 use jsonrpsee::{
     core::RpcResult,
-    types::{error::CallError, ErrorObject},
+    types::{
+        error::{CallError, INTERNAL_ERROR_CODE, INVALID_PARAMS_CODE},
+        ErrorObject, SubscriptionResult,
+    },
+    SubscriptionSink,
 };
-use tracing::{debug, trace};
+use tokio_stream::StreamExt;
+use tracing::{debug, field, instrument, trace, Span};
 
 use crate::eth_api::EthApiServer;
 
+/// Subscription to the user operations that entered the mempool
+const NEW_PENDING_USER_OPERATIONS: &str = "newPendingUserOperations";
+/// Subscription to the user operations that were included on chain
+const USER_OPERATION_INCLUDED: &str = "userOperationIncluded";
+
+/// Error of the user operation (with its JSON-RPC error code) if the status carries one
+fn uopool_error(status: tonic::Status) -> jsonrpsee::core::Error {
+    match user_operation_error(&status) {
+        Some(error) => jsonrpsee::core::Error::Call(CallError::Custom(error)),
+        None => format_err!("GRPC error (uopool): {}", status.message()).into(),
+    }
+}
+
 pub struct EthApiServerImpl {
     pub call_gas_limit: u64,
-    pub uopool_grpc_client: UoPoolClient<tonic::transport::Channel>,
+    pub uopool_grpc_client: UoPoolGrpcClient,
 }
 
 #[async_trait]
@@ -57,28 +78,41 @@ impl EthApiServer for EthApiServerImpl {
             .collect());
     }
 
+    #[instrument(skip_all, fields(
+        submission_id = field::Empty,
+        user_operation_hash = field::Empty,
+        sender = ?user_operation.user_operation.sender,
+        entry_point = ?entry_point,
+    ))]
     async fn send_user_operation(
         &self,
-        user_operation: UserOperation,
+        user_operation: UserOperationWithAuthorization,
         entry_point: Address,
+        options: Option<SendUserOperationOptions>,
     ) -> RpcResult<UserOperationHash> {
         let mut uopool_grpc_client = self.uopool_grpc_client.clone();
         trace!("Receive user operation {user_operation:?} from {entry_point:x?}");
 
-        let request = tonic::Request::new(AddRequest {
-            uo: Some(user_operation.into()),
+        let submission_id = new_submission_id();
+        Span::current().record("submission_id", submission_id.as_str());
+        let mut request = tonic::Request::new(AddRequest {
+            uo: Some(user_operation.user_operation.into()),
             ep: Some(entry_point.into()),
+            authorization: user_operation.eip7702_auth.map(Into::into),
+            private: options.unwrap_or_default().private,
         });
+        set_submission_id(request.metadata_mut(), &submission_id);
 
         let response = uopool_grpc_client
             .add(request)
             .await
-            .map_err(|status| format_err!("GRPC error (uopool): {}", status.message()))?
+            .map_err(uopool_error)?
             .into_inner();
         trace!("Send user operation response: {response:?}");
         if response.result == AddResult::Added as i32 {
             let user_operation_hash = serde_json::from_str::<UserOperationHash>(&response.data)
                 .map_err(|err| format_err!("error parsing user operation hash: {}", err))?;
+            Span::current().record("user_operation_hash", field::debug(&user_operation_hash));
             return Ok(user_operation_hash);
         }
 
@@ -88,24 +122,42 @@ impl EthApiServer for EthApiServerImpl {
         )))
     }
 
+    #[instrument(skip_all, fields(entry_point = ?entry_point))]
     async fn estimate_user_operation_gas(
         &self,
-        user_operation: UserOperationPartial,
+        user_operation: UnestimatedUserOperation,
         entry_point: Address,
+        state_override: Option<spoof::State>,
     ) -> RpcResult<UserOperationGasEstimation> {
         let mut uopool_grpc_client = self.uopool_grpc_client.clone();
 
+        let state_override = match state_override {
+            Some(state_override) => serde_json::to_string(&state_override)
+                .map_err(|err| format_err!("error serializing state override set: {}", err))?,
+            None => String::new(),
+        };
+        let user_operation = UserOperation::try_from(user_operation).map_err(|message| {
+            jsonrpsee::core::Error::Call(CallError::Custom(ErrorObject::owned(
+                INVALID_PARAMS_CODE,
+                message,
+                None::<bool>,
+            )))
+        })?;
         let request = tonic::Request::new(EstimateUserOperationGasRequest {
-            uo: Some(UserOperation::from(user_operation).into()),
+            uo: Some(user_operation.into()),
             ep: Some(entry_point.into()),
+            state_override,
         });
 
         let response = uopool_grpc_client
             .estimate_user_operation_gas(request)
             .await
-            .map_err(|status| format_err!("GRPC error (uopool): {}", status.message()))?
+            .map_err(uopool_error)?
             .into_inner();
 
+        if let Some(estimation) = response.estimation {
+            return Ok(estimation.into());
+        }
         if response.result == EstimateUserOperationGasResult::Estimated as i32 {
             let user_operation_gas_estimation = serde_json::from_str::<UserOperationGasEstimation>(
                 &response.data,
@@ -142,13 +194,14 @@ impl EthApiServer for EthApiServerImpl {
                         let receipt = result.user_operation_hash.and_then(|user_op_hash| {
                             Some(UserOperationReceipt {
                                 user_op_hash: user_op_hash.into(),
+                                entry_point: result.entry_point?.into(),
                                 sender: result.sender?.into(),
                                 nonce: result.nonce?.into(),
                                 paymaster: result.paymaster.map(|p| p.into()),
                                 actual_gas_cost: result.actual_gas_cost?.into(),
                                 actual_gas_used: result.actual_gas_used?.into(),
                                 success: result.success,
-                                reason: String::new(),
+                                reason: result.reason,
                                 logs: result.logs.into_iter().map(|l| l.into()).collect(),
                                 receipt: result.transaction_receipt?.into(),
                             })
@@ -202,14 +255,14 @@ impl EthApiServer for EthApiServerImpl {
                         let uo: Option<UserOperationByHash> =
                             result.user_operation.and_then(|user_operation| {
                                 let entry_point = result.entry_point?.into();
-                                let block_hash = result.block_hash?.into();
-                                let transaction_hash = result.transaction_hash?.into();
+                                // pending user operation (in the mempool) has no block and transaction
+                                let block_hash = result.block_hash.map(Into::into);
                                 Some(UserOperationByHash {
                                     user_operation: user_operation.into(),
                                     entry_point,
-                                    block_number: result.block_number.into(),
+                                    block_number: block_hash.map(|_| result.block_number.into()),
                                     block_hash,
-                                    transaction_hash,
+                                    transaction_hash: result.transaction_hash.map(Into::into),
                                 })
                             });
                         Ok(uo)
@@ -238,4 +291,87 @@ impl EthApiServer for EthApiServerImpl {
             ))),
         }
     }
+
+    fn subscribe(
+        &self,
+        mut sink: SubscriptionSink,
+        kind: String,
+        filter: Option<UserOperationSubscriptionFilter>,
+    ) -> SubscriptionResult {
+        let event_kind = match kind.as_str() {
+            NEW_PENDING_USER_OPERATIONS => MempoolEventKind::NewUserOperation,
+            USER_OPERATION_INCLUDED => MempoolEventKind::UserOperationIncluded,
+            _ => {
+                sink.reject(ErrorObject::owned(
+                    INVALID_PARAMS_CODE,
+                    format!("Unsupported subscription: {kind}"),
+                    None::<bool>,
+                ))?;
+                return Ok(());
+            }
+        };
+        let filter = filter.unwrap_or_default();
+        let mut uopool_grpc_client = self.uopool_grpc_client.clone();
+
+        tokio::spawn(async move {
+            let events = match uopool_grpc_client
+                .subscribe_events(tonic::Request::new(SubscribeEventsRequest {
+                    eps: filter.entry_point.map(Into::into).into_iter().collect(),
+                }))
+                .await
+            {
+                Ok(response) => response.into_inner(),
+                Err(status) => {
+                    let _ = sink.reject(ErrorObject::owned(
+                        INTERNAL_ERROR_CODE,
+                        format!("GRPC error (uopool): {}", status.message()),
+                        None::<bool>,
+                    ));
+                    return;
+                }
+            };
+
+            let notifications = events.filter_map(move |event| match event {
+                Ok(event) => subscription_notification(event_kind, &filter, event).map(Ok),
+                Err(status) => Some(Err(status)),
+            });
+            let result = sink.pipe_from_try_stream(notifications).await;
+            debug!("Subscription {kind} closed: {result:?}");
+        });
+
+        Ok(())
+    }
+}
+
+/// Notification of the subscription for the mempool event, `None` if the event doesn't match the subscription
+fn subscription_notification(
+    kind: MempoolEventKind,
+    filter: &UserOperationSubscriptionFilter,
+    event: MempoolEvent,
+) -> Option<serde_json::Value> {
+    if event.kind != kind as i32 {
+        return None;
+    }
+    let entry_point: Address = event.ep?.into();
+    let sender: Address = event.sender?.into();
+    if !filter.matches(&entry_point, &sender) {
+        return None;
+    }
+    let user_op_hash: UserOperationHash = event.uo_hash?.into();
+
+    match kind {
+        MempoolEventKind::NewUserOperation => serde_json::to_value(PendingUserOperation {
+            user_op_hash,
+            entry_point,
+            user_operation: event.uo?.into(),
+        }),
+        MempoolEventKind::UserOperationIncluded => serde_json::to_value(IncludedUserOperation {
+            user_op_hash,
+            entry_point,
+            sender,
+            transaction_hash: event.tx_hash?.into(),
+            success: event.success,
+        }),
+    }
+    .ok()
 }
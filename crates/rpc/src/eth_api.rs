@@ -1,8 +1,9 @@
 use aa_bundler_primitives::{
-    UserOperation, UserOperationByHash, UserOperationGasEstimation, UserOperationHash,
-    UserOperationPartial, UserOperationReceipt,
+    SendUserOperationOptions, UnestimatedUserOperation, UserOperationByHash,
+    UserOperationGasEstimation, UserOperationHash, UserOperationReceipt,
+    UserOperationSubscriptionFilter, UserOperationWithAuthorization,
 };
-use ethers::types::{Address, U64};
+use ethers::types::{spoof, Address, U64};
 use jsonrpsee::{core::RpcResult, proc_macros::rpc};
 
 #[rpc(server, namespace = "eth")]
@@ -13,18 +14,23 @@ pub trait EthApi {
     #[method(name = "supportedEntryPoints")]
     async fn supported_entry_points(&self) -> RpcResult<Vec<String>>;
 
+    /// The user operation is kept out of the shared mempool (and the dumps) until included if sent as private
     #[method(name = "sendUserOperation")]
     async fn send_user_operation(
         &self,
-        user_operation: UserOperation,
+        user_operation: UserOperationWithAuthorization,
         entry_point: Address,
+        options: Option<SendUserOperationOptions>,
     ) -> RpcResult<UserOperationHash>;
 
+    /// The optional state override set (as of `eth_call`) is applied to the simulation calls, e.g. to estimate the
+    /// gas of a counterfactual account or with a simulated paymaster deposit
     #[method(name = "estimateUserOperationGas")]
     async fn estimate_user_operation_gas(
         &self,
-        user_operation: UserOperationPartial,
+        user_operation: UnestimatedUserOperation,
         entry_point: Address,
+        state_override: Option<spoof::State>,
     ) -> RpcResult<UserOperationGasEstimation>;
 
     #[method(name = "getUserOperationReceipt")]
@@ -38,4 +44,8 @@ pub trait EthApi {
         &self,
         user_operation_hash: String,
     ) -> RpcResult<Option<UserOperationByHash>>;
+
+    /// Subscribes to `newPendingUserOperations` or `userOperationIncluded` (WebSocket only)
+    #[subscription(name = "subscribe" => "subscription", unsubscribe = "unsubscribe", item = serde_json::Value)]
+    fn subscribe(&self, kind: String, filter: Option<UserOperationSubscriptionFilter>);
 }
@@ -0,0 +1,266 @@
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use aa_bundler_grpc::{Supervisor, UoPoolGrpcClient};
+use aa_bundler_primitives::EthProvider;
+use ethers::{providers::Middleware, types::Address};
+use hyper::{header, Body, Method, Request, Response, StatusCode};
+use serde::Serialize;
+use tower::{Layer, Service};
+
+/// Liveness probe: the execution client and the uopool gRPC service are reachable
+pub const HEALTH_PATH: &str = "/health";
+/// Readiness probe: additionally the chain ids match, the supported entry points are deployed and the background
+/// tasks (of the same process) are running
+pub const READY_PATH: &str = "/ready";
+
+/// Maximum duration of a single check, so that the probes don't hang on an unresponsive backend
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthStatus {
+    Ok,
+    Unavailable,
+}
+
+/// Result of the health or the readiness checks, returned as the body of the probe response
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct HealthReport {
+    pub status: HealthStatus,
+    /// Error of each failed check (`ok` if the check passed)
+    pub checks: BTreeMap<&'static str, String>,
+}
+
+impl HealthReport {
+    fn new(checks: Vec<(&'static str, anyhow::Result<()>)>) -> Self {
+        let status = if checks.iter().all(|(_, result)| result.is_ok()) {
+            HealthStatus::Ok
+        } else {
+            HealthStatus::Unavailable
+        };
+        Self {
+            status,
+            checks: checks
+                .into_iter()
+                .map(|(name, result)| {
+                    (
+                        name,
+                        result.map_or_else(|error| error.to_string(), |_| "ok".to_string()),
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    fn into_response(self) -> Result<Response<Body>, Box<dyn Error + Send + Sync>> {
+        let status = match self.status {
+            HealthStatus::Ok => StatusCode::OK,
+            HealthStatus::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+        };
+        Ok(Response::builder()
+            .status(status)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(serde_json::to_string(&self)?))?)
+    }
+}
+
+async fn with_timeout<T, F: Future<Output = anyhow::Result<T>>>(check: F) -> anyhow::Result<T> {
+    tokio::time::timeout(CHECK_TIMEOUT, check)
+        .await
+        .map_err(|_| anyhow::anyhow!("timed out after {}s", CHECK_TIMEOUT.as_secs()))?
+}
+
+/// Checks of the backends the JSON-RPC server depends on (execution client and uopool gRPC service)
+#[derive(Clone)]
+pub struct HealthChecker {
+    eth_provider: Arc<EthProvider>,
+    uopool_grpc_client: UoPoolGrpcClient,
+    supervisor: Option<Supervisor>,
+}
+
+impl HealthChecker {
+    pub fn new(eth_provider: Arc<EthProvider>, uopool_grpc_client: UoPoolGrpcClient) -> Self {
+        Self {
+            eth_provider,
+            uopool_grpc_client,
+            supervisor: None,
+        }
+    }
+
+    /// Reports the health of the tasks of the supervisor, if the uopool or the bundler run in the same process
+    pub fn with_supervisor(mut self, supervisor: Supervisor) -> Self {
+        self.supervisor = Some(supervisor);
+        self
+    }
+
+    async fn provider_chain_id(&self) -> anyhow::Result<u64> {
+        with_timeout(async { Ok(self.eth_provider.get_chainid().await?.as_u64()) }).await
+    }
+
+    async fn uopool_chain_id(&self) -> anyhow::Result<u64> {
+        let mut uopool_grpc_client = self.uopool_grpc_client.clone();
+        with_timeout(async {
+            Ok(uopool_grpc_client
+                .get_chain_id(tonic::Request::new(()))
+                .await?
+                .into_inner()
+                .chain_id)
+        })
+        .await
+    }
+
+    async fn entry_points(&self) -> anyhow::Result<()> {
+        let mut uopool_grpc_client = self.uopool_grpc_client.clone();
+        with_timeout(async {
+            let entry_points = uopool_grpc_client
+                .get_supported_entry_points(tonic::Request::new(()))
+                .await?
+                .into_inner()
+                .eps;
+            for entry_point in entry_points {
+                let entry_point: Address = entry_point.into();
+                if self
+                    .eth_provider
+                    .get_code(entry_point, None)
+                    .await?
+                    .is_empty()
+                {
+                    anyhow::bail!("entry point {entry_point:?} has no code");
+                }
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    pub async fn health(&self) -> HealthReport {
+        let (provider, uopool) = tokio::join!(self.provider_chain_id(), self.uopool_chain_id());
+        HealthReport::new(vec![
+            ("provider", provider.map(|_| ())),
+            ("uopool", uopool.map(|_| ())),
+        ])
+    }
+
+    pub async fn ready(&self) -> HealthReport {
+        let (provider, uopool, entry_points) = tokio::join!(
+            self.provider_chain_id(),
+            self.uopool_chain_id(),
+            self.entry_points()
+        );
+        let chain_id = match (&provider, &uopool) {
+            (Ok(provider), Ok(uopool)) if provider != uopool => Err(anyhow::anyhow!(
+                "execution client chain id {provider} doesn't match the uopool chain id {uopool}"
+            )),
+            (Ok(_), Ok(_)) => Ok(()),
+            _ => Err(anyhow::anyhow!("chain ids are not available")),
+        };
+        let mut checks = vec![
+            ("provider", provider.map(|_| ())),
+            ("uopool", uopool.map(|_| ())),
+            ("chain_id", chain_id),
+            ("entry_points", entry_points),
+        ];
+        if let Some(supervisor) = &self.supervisor {
+            checks.push(("tasks", supervisor.check()));
+        }
+        HealthReport::new(checks)
+    }
+}
+
+/// Middleware that answers the `GET /health` and `GET /ready` probes, the other requests are passed through
+#[derive(Clone)]
+pub struct HealthLayer {
+    checker: Option<Arc<HealthChecker>>,
+}
+
+impl HealthLayer {
+    pub fn new(checker: Option<Arc<HealthChecker>>) -> Self {
+        Self { checker }
+    }
+}
+
+impl<S> Layer<S> for HealthLayer {
+    type Service = Health<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Health {
+            inner,
+            checker: self.checker.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Health<S> {
+    inner: S,
+    checker: Option<Arc<HealthChecker>>,
+}
+
+impl<S> Service<Request<Body>> for Health<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>, Error = Box<dyn Error + Send + Sync>>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send,
+{
+    type Response = Response<Body>;
+    type Error = Box<dyn Error + Send + Sync>;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        let checker = match &self.checker {
+            Some(checker) if request.method() == Method::GET => checker.clone(),
+            _ => return Box::pin(self.inner.call(request)),
+        };
+
+        match request.uri().path() {
+            HEALTH_PATH => Box::pin(async move { checker.health().await.into_response() }),
+            READY_PATH => Box::pin(async move { checker.ready().await.into_response() }),
+            _ => Box::pin(self.inner.call(request)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn health_report() {
+        let report = HealthReport::new(vec![("provider", Ok(())), ("uopool", Ok(()))]);
+        assert_eq!(report.status, HealthStatus::Ok);
+
+        let report = HealthReport::new(vec![
+            ("provider", Ok(())),
+            ("uopool", Err(anyhow::anyhow!("connection refused"))),
+        ]);
+        assert_eq!(report.status, HealthStatus::Unavailable);
+        assert_eq!(
+            serde_json::to_value(&report).unwrap(),
+            serde_json::json!({
+                "status": "unavailable",
+                "checks": {
+                    "provider": "ok",
+                    "uopool": "connection refused",
+                },
+            })
+        );
+        assert_eq!(
+            report.into_response().unwrap().status(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+    }
+}
@@ -0,0 +1,232 @@
+use std::{
+    collections::HashSet,
+    error::Error,
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Instant,
+};
+
+use hyper::{Body, Method, Request, Response};
+use serde_json::Value;
+use tower::{Layer, Service};
+
+use crate::AuthenticatedClient;
+
+/// Label of the calls of the methods that are not served, so that the clients can't inflate the number of series
+const UNKNOWN_METHOD: &str = "unknown";
+/// Label of the requests of the clients that didn't authenticate (or if the authentication is disabled)
+const ANONYMOUS_CLIENT: &str = "anonymous";
+
+/// Records the per-method metrics of the JSON-RPC calls:
+/// - `rpc_method_requests` (`method`, `client`): number of calls
+/// - `rpc_method_errors` (`method`, `code`): number of error responses by error code
+/// - `rpc_method_latency_seconds` (`method`): latency of the calls (of the whole request for the batches)
+#[derive(Clone, Debug, Default)]
+pub struct MethodMetrics {
+    methods: Arc<HashSet<&'static str>>,
+}
+
+impl MethodMetrics {
+    pub fn new(methods: impl IntoIterator<Item = &'static str>) -> Self {
+        Self {
+            methods: Arc::new(methods.into_iter().collect()),
+        }
+    }
+
+    fn method(&self, call: &Value) -> &'static str {
+        call.get("method")
+            .and_then(|method| method.as_str())
+            .and_then(|method| self.methods.get(method).copied())
+            .unwrap_or(UNKNOWN_METHOD)
+    }
+
+    /// Ids and methods of the calls of the request (single call or batch)
+    fn calls(&self, request: &Value) -> Vec<(Value, &'static str)> {
+        let call = |call: &Value| {
+            (
+                call.get("id").cloned().unwrap_or_default(),
+                self.method(call),
+            )
+        };
+        match request {
+            Value::Array(calls) => calls.iter().map(call).collect(),
+            _ => vec![call(request)],
+        }
+    }
+
+    /// Methods and codes of the error responses, matched with the calls by the id
+    fn errors(calls: &[(Value, &'static str)], response: &Value) -> Vec<(&'static str, i64)> {
+        let responses = match response {
+            Value::Array(responses) => responses.iter().collect(),
+            _ => vec![response],
+        };
+        responses
+            .into_iter()
+            .filter_map(|response| {
+                let code = response.get("error")?.get("code")?.as_i64()?;
+                let id = response.get("id").cloned().unwrap_or_default();
+                let method = calls
+                    .iter()
+                    .find(|(call_id, _)| *call_id == id)
+                    .map_or(UNKNOWN_METHOD, |(_, method)| method);
+                Some((method, code))
+            })
+            .collect()
+    }
+
+    /// Records the calls of the request (single call or batch) of the client, returns the ids and the methods of the
+    /// calls to match the response with
+    pub(crate) fn record_calls(&self, request: &Value, client: &str) -> Vec<(Value, &'static str)> {
+        let calls = self.calls(request);
+        for (_, method) in calls.iter() {
+            metrics::counter!("rpc_method_requests", 1, "method" => *method, "client" => client.to_string());
+        }
+        calls
+    }
+
+    /// Records the latency of the calls and the errors of the response
+    pub(crate) fn record_response(calls: &[(Value, &'static str)], elapsed: f64, response: &[u8]) {
+        for (_, method) in calls.iter() {
+            metrics::histogram!("rpc_method_latency_seconds", elapsed, "method" => *method);
+        }
+        if let Ok(response) = serde_json::from_slice::<Value>(response) {
+            for (method, code) in Self::errors(calls, &response) {
+                metrics::counter!("rpc_method_errors", 1, "method" => method, "code" => code.to_string());
+            }
+        }
+    }
+}
+
+/// Label of the client of the HTTP request (or of the WebSocket handshake)
+pub(crate) fn client_label<B>(request: &Request<B>) -> String {
+    request
+        .extensions()
+        .get::<AuthenticatedClient>()
+        .map_or_else(
+            || ANONYMOUS_CLIENT.to_string(),
+            |client| client.name.clone(),
+        )
+}
+
+/// Middleware that records the [MethodMetrics](MethodMetrics) of the HTTP requests (the messages of the WebSocket
+/// connections are recorded by the [WebSocketLayer](crate::WebSocketLayer))
+#[derive(Clone, Debug)]
+pub struct MetricsLayer {
+    metrics: MethodMetrics,
+}
+
+impl MetricsLayer {
+    pub fn new(metrics: MethodMetrics) -> Self {
+        Self { metrics }
+    }
+}
+
+impl<S> Layer<S> for MetricsLayer {
+    type Service = Metrics<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Metrics {
+            inner,
+            metrics: self.metrics.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Metrics<S> {
+    inner: S,
+    metrics: MethodMetrics,
+}
+
+impl<S> Service<Request<Body>> for Metrics<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>, Error = Box<dyn Error + Send + Sync>>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send,
+{
+    type Response = Response<Body>;
+    type Error = Box<dyn Error + Send + Sync>;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        if request.method() != Method::POST {
+            return Box::pin(self.inner.call(request));
+        }
+
+        let metrics = self.metrics.clone();
+        let client = client_label(&request);
+        // the inner service was polled to be ready, so it's the one that has to be called
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        Box::pin(async move {
+            let (parts, body) = request.into_parts();
+            let body = hyper::body::to_bytes(body).await?;
+            let calls = match serde_json::from_slice::<Value>(&body) {
+                Ok(request) => metrics.record_calls(&request, &client),
+                // invalid requests are handled by the server
+                Err(_) => {
+                    return inner
+                        .call(Request::from_parts(parts, Body::from(body)))
+                        .await
+                }
+            };
+            let started_at = Instant::now();
+            let response = inner
+                .call(Request::from_parts(parts, Body::from(body)))
+                .await?;
+            let elapsed = started_at.elapsed().as_secs_f64();
+
+            let (parts, body) = response.into_parts();
+            let body = hyper::body::to_bytes(body).await?;
+            MethodMetrics::record_response(&calls, elapsed, &body);
+            Ok(Response::from_parts(parts, Body::from(body)))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn method_errors() {
+        let metrics = MethodMetrics::new(["eth_chainId", "eth_sendUserOperation"]);
+        let request = serde_json::json!([
+            {"jsonrpc": "2.0", "id": 1, "method": "eth_chainId"},
+            {"jsonrpc": "2.0", "id": 2, "method": "eth_sendUserOperation", "params": []},
+            {"jsonrpc": "2.0", "id": 3, "method": "eth_accounts"},
+        ]);
+        let calls = metrics.calls(&request);
+        assert_eq!(
+            calls.iter().map(|(_, method)| *method).collect::<Vec<_>>(),
+            vec!["eth_chainId", "eth_sendUserOperation", UNKNOWN_METHOD]
+        );
+
+        let response = serde_json::json!([
+            {"jsonrpc": "2.0", "id": 1, "result": "0x1"},
+            {"jsonrpc": "2.0", "id": 2, "error": {"code": -32602, "message": "invalid params"}},
+            {"jsonrpc": "2.0", "id": 3, "error": {"code": -32601, "message": "method not found"}},
+        ]);
+        assert_eq!(
+            MethodMetrics::errors(&calls, &response),
+            vec![("eth_sendUserOperation", -32602), (UNKNOWN_METHOD, -32601)]
+        );
+
+        let calls = metrics
+            .calls(&serde_json::json!({"jsonrpc": "2.0", "id": "a", "method": "eth_chainId"}));
+        let response = serde_json::json!({"jsonrpc": "2.0", "id": "a", "error": {"code": -32000, "message": "error"}});
+        assert_eq!(
+            MethodMetrics::errors(&calls, &response),
+            vec![("eth_chainId", -32000)]
+        );
+    }
+}
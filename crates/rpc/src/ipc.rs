@@ -0,0 +1,187 @@
+use std::path::Path;
+
+use jsonrpsee::{
+    core::server::rpc_module::Methods,
+    types::error::{
+        INVALID_REQUEST_CODE, OVERSIZED_REQUEST_CODE, OVERSIZED_REQUEST_MSG, PARSE_ERROR_CODE,
+    },
+};
+use serde_json::Value;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{UnixListener, UnixStream},
+    sync::mpsc,
+};
+use tokio_stream::StreamExt;
+use tracing::{debug, info};
+
+use crate::{response::jsonrpc_error_body, BatchLimits, PayloadLimits};
+
+/// Size of the chunks in which the requests are read from the socket
+const READ_BUFFER_SIZE: usize = 8192;
+
+/// Calls the method and forwards the subscription notifications (if any) to the connection
+async fn call(
+    methods: &Methods,
+    call: serde_json::Value,
+    tx: &mpsc::UnboundedSender<String>,
+) -> String {
+    match methods.raw_json_request(&call.to_string()).await {
+        Ok((response, mut notifications)) => {
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                while let Some(notification) = notifications.next().await {
+                    if tx.send(notification).is_err() {
+                        break;
+                    }
+                }
+            });
+            response.result
+        }
+        Err(_) => {
+            jsonrpc_error_body(Value::Null, INVALID_REQUEST_CODE, "Invalid request").to_string()
+        }
+    }
+}
+
+/// Applies the batch limits and validates the payloads of the request (single call or batch) of a connection (IPC or
+/// WebSocket) before calling the methods, returns the response
+pub(crate) async fn process_request(
+    methods: &Methods,
+    batch_limits: BatchLimits,
+    payload_limits: PayloadLimits,
+    request: serde_json::Value,
+    tx: &mpsc::UnboundedSender<String>,
+) -> String {
+    match request {
+        serde_json::Value::Array(calls) => match batch_limits.apply(calls) {
+            Ok((accepted, rejected)) => {
+                let mut responses = Vec::with_capacity(accepted.len() + rejected.len());
+                for c in accepted {
+                    match payload_limits.validate_call(&c) {
+                        Some(error) => responses.push(error.to_string()),
+                        None => responses.push(call(methods, c, tx).await),
+                    }
+                }
+                responses.extend(rejected.iter().map(|response| response.to_string()));
+                format!("[{}]", responses.join(","))
+            }
+            Err(error) => error.to_string(),
+        },
+        request => match payload_limits.validate_call(&request) {
+            Some(error) => error.to_string(),
+            None => call(methods, request, tx).await,
+        },
+    }
+}
+
+async fn handle_request(
+    methods: Methods,
+    batch_limits: BatchLimits,
+    payload_limits: PayloadLimits,
+    request: serde_json::Value,
+    tx: mpsc::UnboundedSender<String>,
+) {
+    let response = process_request(&methods, batch_limits, payload_limits, request, &tx).await;
+    let _ = tx.send(response);
+}
+
+async fn handle_connection(
+    stream: UnixStream,
+    methods: Methods,
+    batch_limits: BatchLimits,
+    payload_limits: PayloadLimits,
+) {
+    let (mut reader, mut writer) = stream.into_split();
+    let (tx, mut rx) = mpsc::unbounded_channel::<String>();
+
+    tokio::spawn(async move {
+        while let Some(response) = rx.recv().await {
+            if writer.write_all(response.as_bytes()).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut buffer = Vec::new();
+    let mut chunk = vec![0u8; READ_BUFFER_SIZE];
+    loop {
+        let read = match reader.read(&mut chunk).await {
+            Ok(0) | Err(_) => break,
+            Ok(read) => read,
+        };
+        buffer.extend_from_slice(&chunk[..read]);
+
+        // requests are JSON values written back to back, the last one can be incomplete
+        let mut requests = serde_json::Deserializer::from_slice(&buffer).into_iter();
+        let mut consumed = 0;
+        loop {
+            match requests.next() {
+                Some(Ok(request)) => {
+                    consumed = requests.byte_offset();
+                    tokio::spawn(handle_request(
+                        methods.clone(),
+                        batch_limits,
+                        payload_limits,
+                        request,
+                        tx.clone(),
+                    ));
+                }
+                Some(Err(err)) if !err.is_eof() => {
+                    debug!("Invalid IPC request: {err:?}");
+                    let _ = tx.send(
+                        jsonrpc_error_body(Value::Null, PARSE_ERROR_CODE, "Parse error")
+                            .to_string(),
+                    );
+                    consumed = buffer.len();
+                    break;
+                }
+                _ => break,
+            }
+        }
+        buffer.drain(..consumed);
+
+        // the incomplete request can't grow without bound
+        if buffer.len() > payload_limits.max_request_body_size {
+            let _ = tx.send(
+                jsonrpc_error_body(Value::Null, OVERSIZED_REQUEST_CODE, OVERSIZED_REQUEST_MSG)
+                    .to_string(),
+            );
+            break;
+        }
+    }
+}
+
+/// Serves the methods over the Unix domain socket (IPC). As with the execution clients, the requests and the
+/// responses are JSON values written to the socket back to back.
+pub async fn ipc_serve(
+    path: &Path,
+    methods: Methods,
+    batch_limits: BatchLimits,
+    payload_limits: PayloadLimits,
+) -> anyhow::Result<()> {
+    // the socket of the previous run is left behind if the process didn't exit cleanly
+    if path.exists() {
+        std::fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    info!("JSON-RPC IPC server listening on {}", path.display());
+
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    tokio::spawn(handle_connection(
+                        stream,
+                        methods.clone(),
+                        batch_limits,
+                        payload_limits,
+                    ));
+                }
+                Err(err) => debug!("Accepting IPC connection failed: {err:?}"),
+            }
+        }
+    });
+
+    Ok(())
+}
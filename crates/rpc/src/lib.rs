@@ -1,11 +1,40 @@
 #![allow(dead_code)]
 
+mod admin;
+mod admin_api;
+mod auth;
+mod batch;
+mod cors;
 mod debug;
 mod debug_api;
 mod eth;
 mod eth_api;
+mod health;
+mod instrumentation;
+mod ipc;
+mod proxy;
+mod rate_limit;
+mod response;
+mod rpc;
+mod validation;
+mod ws;
 
+pub use admin::AdminApiServerImpl;
+pub use admin_api::AdminApiServer;
+pub use auth::{
+    read_jwt_secret, AdminScopeLayer, ApiKey, AuthLayer, AuthenticatedClient, Authenticator,
+};
+pub use batch::{BatchLimitLayer, BatchLimits};
+pub use cors::{AllowedOrigins, CorsLayer};
 pub use debug::DebugApiServerImpl;
 pub use debug_api::DebugApiServer;
 pub use eth::EthApiServerImpl;
 pub use eth_api::EthApiServer;
+pub use health::{HealthChecker, HealthLayer, HealthReport, HealthStatus, HEALTH_PATH, READY_PATH};
+pub use instrumentation::{MethodMetrics, MetricsLayer};
+pub use ipc::ipc_serve;
+pub use proxy::proxy_serve;
+pub use rate_limit::{RateLimitLayer, RateLimitedClient, RateLimiter, RateLimits};
+pub use rpc::{JsonRpcServer, JsonRpcServerHandle, JsonRpcServerOpts};
+pub use validation::{PayloadLimits, PayloadValidationLayer};
+pub use ws::WebSocketLayer;
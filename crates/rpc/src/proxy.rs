@@ -0,0 +1,232 @@
+use std::{
+    collections::HashSet,
+    convert::Infallible,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+
+use hyper::{
+    client::HttpConnector,
+    header::{HeaderMap, HeaderValue},
+    server::conn::Http,
+    service::service_fn,
+    Body, Client, Request, Response, StatusCode, Uri,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpListener, TcpStream},
+};
+use tokio_rustls::TlsAcceptor;
+use tracing::{debug, info};
+
+use crate::auth::is_websocket_upgrade;
+
+/// Header with the address of the client, set by the listener in front of the server (the value sent by the client
+/// is always replaced)
+pub(crate) const CLIENT_IP_HEADER: &str = "x-aa-bundler-client-ip";
+
+/// Address of the client: the address of the connection, or the address reported by the trusted reverse proxy the
+/// connection comes from (the last address of the `X-Forwarded-For` header that isn't one of the proxies, or the
+/// `X-Real-IP` header)
+fn client_ip(peer: IpAddr, headers: &HeaderMap, trusted_proxies: &HashSet<IpAddr>) -> IpAddr {
+    if !trusted_proxies.contains(&peer) {
+        return peer;
+    }
+
+    let forwarded = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|ip| ip.trim().parse::<IpAddr>().ok())
+        .collect::<Vec<_>>();
+    forwarded
+        .into_iter()
+        .rev()
+        .find(|ip| !trusted_proxies.contains(ip))
+        .or_else(|| {
+            headers
+                .get("x-real-ip")
+                .and_then(|value| value.to_str().ok())
+                .and_then(|ip| ip.trim().parse().ok())
+        })
+        .unwrap_or(peer)
+}
+
+fn bad_gateway() -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::BAD_GATEWAY)
+        .body(Body::empty())
+        .expect("Bad gateway response is valid")
+}
+
+/// Forwards the request (or the WebSocket connection) to the server with the address of the client
+async fn forward(
+    mut request: Request<Body>,
+    peer: IpAddr,
+    upstream: SocketAddr,
+    client: Client<HttpConnector>,
+    trusted_proxies: Arc<HashSet<IpAddr>>,
+) -> Result<Response<Body>, Infallible> {
+    let ip = client_ip(peer, request.headers(), &trusted_proxies);
+    request.headers_mut().insert(
+        CLIENT_IP_HEADER,
+        HeaderValue::from_str(&ip.to_string()).expect("IP address is a valid header value"),
+    );
+
+    let path = request
+        .uri()
+        .path_and_query()
+        .map_or("/", |path| path.as_str());
+    *request.uri_mut() = match format!("http://{upstream}{path}").parse::<Uri>() {
+        Ok(uri) => uri,
+        Err(_) => return Ok(bad_gateway()),
+    };
+
+    let upgrade = is_websocket_upgrade(request.headers()).then(|| hyper::upgrade::on(&mut request));
+    let mut response = match client.request(request).await {
+        Ok(response) => response,
+        Err(err) => {
+            debug!("Forwarding the request to {upstream} failed: {err:?}");
+            return Ok(bad_gateway());
+        }
+    };
+
+    if let Some(upgrade) = upgrade {
+        if response.status() == StatusCode::SWITCHING_PROTOCOLS {
+            let upstream_upgrade = hyper::upgrade::on(&mut response);
+            tokio::spawn(async move {
+                match tokio::try_join!(upgrade, upstream_upgrade) {
+                    Ok((mut downstream, mut upstream)) => {
+                        let _ = tokio::io::copy_bidirectional(&mut downstream, &mut upstream).await;
+                    }
+                    Err(err) => debug!("WebSocket upgrade failed: {err:?}"),
+                }
+            });
+        }
+    }
+
+    Ok(response)
+}
+
+async fn serve_connection<S>(
+    stream: S,
+    peer: SocketAddr,
+    upstream: SocketAddr,
+    client: Client<HttpConnector>,
+    trusted_proxies: Arc<HashSet<IpAddr>>,
+) where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let service = service_fn(move |request| {
+        forward(
+            request,
+            peer.ip(),
+            upstream,
+            client.clone(),
+            trusted_proxies.clone(),
+        )
+    });
+    if let Err(err) = Http::new()
+        .http1_only(true)
+        .serve_connection(stream, service)
+        .with_upgrades()
+        .await
+    {
+        debug!("Connection of {peer} closed: {err:?}");
+    }
+}
+
+async fn accept(
+    stream: TcpStream,
+    peer: SocketAddr,
+    upstream: SocketAddr,
+    tls_acceptor: Option<TlsAcceptor>,
+    client: Client<HttpConnector>,
+    trusted_proxies: Arc<HashSet<IpAddr>>,
+) {
+    let _ = stream.set_nodelay(true);
+    match tls_acceptor {
+        Some(acceptor) => match acceptor.accept(stream).await {
+            Ok(stream) => serve_connection(stream, peer, upstream, client, trusted_proxies).await,
+            Err(err) => debug!("TLS handshake of {peer} failed: {err:?}"),
+        },
+        None => serve_connection(stream, peer, upstream, client, trusted_proxies).await,
+    }
+}
+
+/// Listens on the address (over TLS if the acceptor is set) in front of the server on the loopback upstream address,
+/// so that the middlewares (the rate limiting) see the address of the client in the `CLIENT_IP_HEADER` header. The
+/// server doesn't expose the address of the connection to the middlewares.
+pub async fn proxy_serve(
+    listen_address: &str,
+    upstream: SocketAddr,
+    tls_acceptor: Option<TlsAcceptor>,
+    trusted_proxies: HashSet<IpAddr>,
+) -> anyhow::Result<SocketAddr> {
+    let listener = TcpListener::bind(listen_address).await?;
+    let local_address = listener.local_addr()?;
+    debug!("JSON-RPC listener on {local_address} forwarding to {upstream}");
+    if !trusted_proxies.is_empty() {
+        info!("Trusting the client addresses reported by the proxies {trusted_proxies:?}");
+    }
+
+    let client = Client::new();
+    let trusted_proxies = Arc::new(trusted_proxies);
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, peer)) => {
+                    tokio::spawn(accept(
+                        stream,
+                        peer,
+                        upstream,
+                        tls_acceptor.clone(),
+                        client.clone(),
+                        trusted_proxies.clone(),
+                    ));
+                }
+                Err(err) => debug!("Accepting JSON-RPC connection failed: {err:?}"),
+            }
+        }
+    });
+
+    Ok(local_address)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn client_address() {
+        let peer: IpAddr = "10.0.0.9".parse().unwrap();
+        let proxy: IpAddr = "10.0.0.1".parse().unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", "1.1.1.1, 2.2.2.2".parse().unwrap());
+        headers.insert("x-real-ip", "3.3.3.3".parse().unwrap());
+
+        // the headers of the clients that connect directly are ignored
+        assert_eq!(client_ip(peer, &headers, &HashSet::new()), peer);
+        assert_eq!(client_ip(peer, &headers, &HashSet::from([proxy])), peer);
+
+        // the address appended by the proxy, the ones before it can be spoofed by the client
+        assert_eq!(
+            client_ip(proxy, &headers, &HashSet::from([proxy])),
+            "2.2.2.2".parse::<IpAddr>().unwrap()
+        );
+        let chained: IpAddr = "2.2.2.2".parse().unwrap();
+        assert_eq!(
+            client_ip(proxy, &headers, &HashSet::from([proxy, chained])),
+            "1.1.1.1".parse::<IpAddr>().unwrap()
+        );
+
+        headers.remove("x-forwarded-for");
+        assert_eq!(
+            client_ip(proxy, &headers, &HashSet::from([proxy])),
+            "3.3.3.3".parse::<IpAddr>().unwrap()
+        );
+        headers.remove("x-real-ip");
+        assert_eq!(client_ip(proxy, &headers, &HashSet::from([proxy])), proxy);
+    }
+}
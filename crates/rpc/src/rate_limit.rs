@@ -0,0 +1,397 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    future::Future,
+    net::IpAddr,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Instant,
+};
+
+use aa_bundler_primitives::LIMIT_EXCEEDED_ERROR_CODE;
+use hyper::{header::HeaderMap, Body, Method, Request, Response, StatusCode};
+use parking_lot::Mutex;
+use tower::{Layer, Service};
+
+use crate::{
+    auth::AuthenticatedClient, batch::is_send_user_operation, proxy::CLIENT_IP_HEADER,
+    response::jsonrpc_error,
+};
+
+/// Number of tracked clients above which the clients with full buckets are dropped
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+pub(crate) const LIMIT_EXCEEDED_MSG: &str = "Rate limit exceeded";
+
+#[derive(Clone, Debug)]
+struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    refill_per_second: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(capacity: u32, refill_per_second: f64) -> Self {
+        Self {
+            capacity: capacity as f64,
+            tokens: capacity as f64,
+            refill_per_second,
+            last_refill: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.refill_per_second).min(self.capacity);
+        self.last_refill = now;
+    }
+
+    /// Takes the tokens if there are enough of them
+    fn try_take(&mut self, tokens: u32) -> bool {
+        self.refill();
+        if self.tokens >= tokens as f64 {
+            self.tokens -= tokens as f64;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn is_full(&mut self) -> bool {
+        self.refill();
+        self.tokens >= self.capacity
+    }
+}
+
+/// Token bucket rate limits of a single client
+#[derive(Clone, Debug, Default)]
+struct ClientBuckets {
+    requests: Option<TokenBucket>,
+    user_operations: Option<TokenBucket>,
+}
+
+/// Limits of the requests and of the submitted user operations (unlimited if not set)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RateLimits {
+    pub requests_per_second: Option<u32>,
+    pub user_operations_per_minute: Option<u32>,
+}
+
+impl RateLimits {
+    pub fn is_unlimited(&self) -> bool {
+        self.requests_per_second.is_none() && self.user_operations_per_minute.is_none()
+    }
+
+    /// Limits that are set, falling back to the default limits
+    pub fn or(self, default: RateLimits) -> RateLimits {
+        RateLimits {
+            requests_per_second: self.requests_per_second.or(default.requests_per_second),
+            user_operations_per_minute: self
+                .user_operations_per_minute
+                .or(default.user_operations_per_minute),
+        }
+    }
+}
+
+/// Client whose requests are limited, either by the API key it authenticated with or by its address
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum RateLimitedClient {
+    ApiKey(String),
+    Ip(Option<IpAddr>),
+}
+
+/// Per-client rate limiter of the JSON-RPC requests and of the submitted user operations.
+///
+/// Unauthenticated clients are identified by their address, as set by the listener in front of the server (the
+/// address of the connection, or the one reported by a trusted reverse proxy).
+#[derive(Debug)]
+pub struct RateLimiter {
+    limits: RateLimits,
+    clients: Mutex<HashMap<RateLimitedClient, ClientBuckets>>,
+}
+
+impl RateLimiter {
+    pub fn new(limits: RateLimits) -> Self {
+        Self {
+            limits,
+            clients: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn client_buckets<'a>(
+        clients: &'a mut HashMap<RateLimitedClient, ClientBuckets>,
+        client: &RateLimitedClient,
+        limits: &RateLimits,
+    ) -> &'a mut ClientBuckets {
+        if clients.len() >= MAX_TRACKED_CLIENTS && !clients.contains_key(client) {
+            clients.retain(|_, buckets| {
+                !buckets.requests.as_mut().map_or(true, |b| b.is_full())
+                    || !buckets
+                        .user_operations
+                        .as_mut()
+                        .map_or(true, |b| b.is_full())
+            });
+        }
+        clients
+            .entry(client.clone())
+            .or_insert_with(|| ClientBuckets {
+                requests: limits
+                    .requests_per_second
+                    .map(|limit| TokenBucket::new(limit, limit as f64)),
+                user_operations: limits
+                    .user_operations_per_minute
+                    .map(|limit| TokenBucket::new(limit, limit as f64 / 60.0)),
+            })
+    }
+
+    /// Returns false if the client exceeded the requests limit
+    pub fn check_request(&self, client: &RateLimitedClient, limits: &RateLimits) -> bool {
+        let mut clients = self.clients.lock();
+        Self::client_buckets(&mut clients, client, limits)
+            .requests
+            .as_mut()
+            .map_or(true, |bucket| bucket.try_take(1))
+    }
+
+    /// Returns false if the client exceeded the user operations limit
+    pub fn check_user_operations(
+        &self,
+        client: &RateLimitedClient,
+        limits: &RateLimits,
+        user_operations: u32,
+    ) -> bool {
+        let mut clients = self.clients.lock();
+        Self::client_buckets(&mut clients, client, limits)
+            .user_operations
+            .as_mut()
+            .map_or(true, |bucket| bucket.try_take(user_operations))
+    }
+
+    /// Returns false if the client exceeded the requests limit, or the user operations limit with the calls of the
+    /// (single or batch) JSON-RPC request
+    pub fn check_call(
+        &self,
+        client: &RateLimitedClient,
+        limits: &RateLimits,
+        request: &serde_json::Value,
+    ) -> bool {
+        if !self.check_request(client, limits) {
+            return false;
+        }
+        let user_operations = user_operations(request);
+        user_operations == 0 || self.check_user_operations(client, limits, user_operations)
+    }
+
+    /// Client of the HTTP request (or of the WebSocket handshake) and its limits: the ones of the API key it
+    /// authenticated with (falling back to the default ones), or the default ones for its address
+    pub(crate) fn client<B>(&self, request: &Request<B>) -> (RateLimitedClient, RateLimits) {
+        match request.extensions().get::<AuthenticatedClient>() {
+            Some(authenticated) => (
+                RateLimitedClient::ApiKey(authenticated.name.clone()),
+                authenticated.limits.or(self.limits),
+            ),
+            None => (
+                RateLimitedClient::Ip(client_ip(request.headers())),
+                self.limits,
+            ),
+        }
+    }
+}
+
+/// Address of the client as set by the listener in front of the server
+fn client_ip(headers: &HeaderMap) -> Option<IpAddr> {
+    headers
+        .get(CLIENT_IP_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|ip| ip.trim().parse().ok())
+}
+
+/// Number of `eth_sendUserOperation` calls in the (single or batch) JSON-RPC request
+fn user_operations(request: &serde_json::Value) -> u32 {
+    match request {
+        serde_json::Value::Array(calls) => calls
+            .iter()
+            .filter(|call| is_send_user_operation(call))
+            .count() as u32,
+        call => is_send_user_operation(call) as u32,
+    }
+}
+
+fn user_operations_count(body: &[u8]) -> u32 {
+    serde_json::from_slice::<serde_json::Value>(body).map_or(0, |request| user_operations(&request))
+}
+
+fn limit_exceeded() -> Response<Body> {
+    let mut response = jsonrpc_error(
+        serde_json::Value::Null,
+        LIMIT_EXCEEDED_ERROR_CODE,
+        LIMIT_EXCEEDED_MSG,
+    );
+    *response.status_mut() = StatusCode::TOO_MANY_REQUESTS;
+    response
+}
+
+/// Middleware that applies the [RateLimiter](RateLimiter) to the HTTP requests and to the WebSocket handshakes (the
+/// messages of the WebSocket connections are limited by the [WebSocketLayer](crate::WebSocketLayer))
+#[derive(Clone, Debug)]
+pub struct RateLimitLayer {
+    limiter: Arc<RateLimiter>,
+}
+
+impl RateLimitLayer {
+    pub fn new(limiter: Arc<RateLimiter>) -> Self {
+        Self { limiter }
+    }
+}
+
+impl<S> Layer<S> for RateLimitLayer {
+    type Service = RateLimit<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimit {
+            inner,
+            limiter: self.limiter.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RateLimit<S> {
+    inner: S,
+    limiter: Arc<RateLimiter>,
+}
+
+impl<S> Service<Request<Body>> for RateLimit<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>, Error = Box<dyn Error + Send + Sync>>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send,
+{
+    type Response = Response<Body>;
+    type Error = Box<dyn Error + Send + Sync>;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        let limiter = self.limiter.clone();
+        // the inner service was polled to be ready, so it's the one that has to be called
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        Box::pin(async move {
+            let (client, limits) = limiter.client(&request);
+            if limits.is_unlimited() {
+                return inner.call(request).await;
+            }
+
+            if !limiter.check_request(&client, &limits) {
+                return Ok(limit_exceeded());
+            }
+
+            if limits.user_operations_per_minute.is_some() && request.method() == Method::POST {
+                let (parts, body) = request.into_parts();
+                let body = hyper::body::to_bytes(body).await?;
+                let user_operations = user_operations_count(&body);
+                if user_operations > 0
+                    && !limiter.check_user_operations(&client, &limits, user_operations)
+                {
+                    return Ok(limit_exceeded());
+                }
+                return inner
+                    .call(Request::from_parts(parts, Body::from(body)))
+                    .await;
+            }
+
+            inner.call(request).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rate_limiter() {
+        let limits = RateLimits {
+            requests_per_second: Some(2),
+            user_operations_per_minute: Some(3),
+        };
+        let limiter = RateLimiter::new(limits);
+        let client = RateLimitedClient::Ip(Some("10.0.0.1".parse().unwrap()));
+        let other_client = RateLimitedClient::Ip(Some("10.0.0.2".parse().unwrap()));
+
+        assert!(limiter.check_request(&client, &limits));
+        assert!(limiter.check_request(&client, &limits));
+        assert!(!limiter.check_request(&client, &limits));
+        assert!(limiter.check_request(&other_client, &limits));
+
+        assert!(limiter.check_user_operations(&client, &limits, 2));
+        assert!(!limiter.check_user_operations(&client, &limits, 2));
+        assert!(limiter.check_user_operations(&client, &limits, 1));
+
+        let api_key = RateLimitedClient::ApiKey("wallet".into());
+        let api_key_limits = RateLimits {
+            requests_per_second: Some(3),
+            user_operations_per_minute: None,
+        }
+        .or(limits);
+        assert_eq!(api_key_limits.user_operations_per_minute, Some(3));
+        for _ in 0..3 {
+            assert!(limiter.check_request(&api_key, &api_key_limits));
+        }
+        assert!(!limiter.check_request(&api_key, &api_key_limits));
+    }
+
+    #[test]
+    fn check_call() {
+        let limits = RateLimits {
+            requests_per_second: Some(3),
+            user_operations_per_minute: Some(2),
+        };
+        let limiter = RateLimiter::new(limits);
+        let client = RateLimitedClient::Ip(Some("10.0.0.1".parse().unwrap()));
+
+        let batch = serde_json::json!([
+            {"jsonrpc": "2.0", "id": 1, "method": "eth_sendUserOperation"},
+            {"jsonrpc": "2.0", "id": 2, "method": "eth_sendUserOperation"},
+        ]);
+        assert!(limiter.check_call(&client, &limits, &batch));
+        // the user operations bucket is empty, the other calls are only limited by the requests
+        assert!(!limiter.check_call(&client, &limits, &batch));
+        let call = serde_json::json!({"jsonrpc": "2.0", "id": 3, "method": "eth_chainId"});
+        assert!(limiter.check_call(&client, &limits, &call));
+        assert!(!limiter.check_call(&client, &limits, &call));
+    }
+
+    #[test]
+    fn client_ip_and_user_operations() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(client_ip(&headers), None);
+        // the headers of the proxies are resolved by the listener in front of the server
+        headers.insert("x-forwarded-for", "10.0.0.1".parse().unwrap());
+        assert_eq!(client_ip(&headers), None);
+        headers.insert(CLIENT_IP_HEADER, "10.0.0.2".parse().unwrap());
+        assert_eq!(client_ip(&headers), Some("10.0.0.2".parse().unwrap()));
+
+        assert_eq!(
+            user_operations_count(br#"{"jsonrpc":"2.0","method":"eth_sendUserOperation","id":1}"#),
+            1
+        );
+        assert_eq!(
+            user_operations_count(
+                br#"[{"method":"eth_sendUserOperation"},{"method":"eth_chainId"},{"method":"eth_sendUserOperation"}]"#
+            ),
+            2
+        );
+        assert_eq!(user_operations_count(b"invalid"), 0);
+    }
+}
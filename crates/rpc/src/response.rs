@@ -0,0 +1,45 @@
+use hyper::{header, Body, Response};
+use serde_json::Value;
+
+/// Error response of the JSON-RPC call with the id (`null` if the request couldn't be read)
+pub(crate) fn jsonrpc_error_body(id: Value, code: i32, message: impl Into<String>) -> Value {
+    serde_json::json!({
+        "jsonrpc": "2.0",
+        "error": {
+            "code": code,
+            "message": message.into(),
+        },
+        "id": id,
+    })
+}
+
+/// HTTP response with the error of the JSON-RPC call, the middlewares that reject the whole request set its status
+pub(crate) fn jsonrpc_error(id: Value, code: i32, message: impl Into<String>) -> Response<Body> {
+    json_response(&jsonrpc_error_body(id, code, message))
+}
+
+pub(crate) fn json_response(value: &Value) -> Response<Body> {
+    Response::builder()
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(value.to_string()))
+        .expect("JSON response is valid")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn error_response() {
+        assert_eq!(
+            jsonrpc_error_body(Value::from(7), -32005, "Rate limit exceeded"),
+            serde_json::json!({
+                "jsonrpc": "2.0",
+                "error": {"code": -32005, "message": "Rate limit exceeded"},
+                "id": 7,
+            })
+        );
+        let response = jsonrpc_error(Value::Null, -32005, "Rate limit exceeded");
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+    }
+}
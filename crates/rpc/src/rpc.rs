@@ -0,0 +1,399 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    net::{IpAddr, SocketAddr, ToSocketAddrs},
+    path::PathBuf,
+    sync::Arc,
+};
+
+use aa_bundler_grpc::{
+    bundler_client::BundlerClient, grpc_channel, ClientTlsConfig, ConfigReloader, TlsConfig,
+    UoPoolGrpcClient, ALPN_HTTP1,
+};
+use clap::Parser;
+use jsonrpsee::{
+    core::server::rpc_module::Methods,
+    server::{ServerBuilder, ServerHandle},
+};
+use serde_json::Value;
+use tracing::info;
+
+use crate::{
+    auth::ADMIN_METHOD_PREFIX, ipc_serve, proxy_serve, read_jwt_secret, AdminApiServer,
+    AdminApiServerImpl, AdminScopeLayer, AllowedOrigins, ApiKey, AuthLayer, Authenticator,
+    BatchLimitLayer, BatchLimits, CorsLayer, DebugApiServer, DebugApiServerImpl, EthApiServer,
+    EthApiServerImpl, HealthChecker, HealthLayer, MethodMetrics, MetricsLayer, PayloadLimits,
+    PayloadValidationLayer, RateLimitLayer, RateLimiter, RateLimits, WebSocketLayer,
+};
+
+/// Gas limit of the calls used by the `eth_` namespace (e.g. gas estimation)
+const CALL_GAS_LIMIT: u64 = 100_000_000;
+
+/// Address of the server behind the listener that passes the addresses of the clients (see `proxy_serve`)
+const UPSTREAM_ADDRESS: &str = "127.0.0.1:0";
+
+#[derive(Clone, Debug, Default, Parser, PartialEq)]
+pub struct JsonRpcServerOpts {
+    /// Serve WebSocket (`eth_subscribe`) alongside HTTP
+    #[clap(long)]
+    pub rpc_ws: bool,
+
+    /// Origins allowed to call the server from the browser (`*` for any, wildcards like `https://*.example`,
+    /// an empty value disables CORS)
+    #[clap(long, value_delimiter = ',', default_value = "*")]
+    pub rpc_cors_domains: Vec<String>,
+
+    /// Maximum number of JSON-RPC requests per second of a client (unlimited if not set)
+    #[clap(long)]
+    pub rpc_requests_per_second: Option<u32>,
+
+    /// Maximum number of user operations per minute that a client can send (unlimited if not set)
+    #[clap(long)]
+    pub rpc_user_operations_per_minute: Option<u32>,
+
+    /// Addresses of the reverse proxies in front of the server, the clients behind them are identified by the
+    /// `X-Forwarded-For` (or `X-Real-IP`) header for the rate limiting, the other clients by their address
+    #[clap(long, value_delimiter = ',')]
+    pub rpc_trusted_proxies: Vec<IpAddr>,
+
+    /// API keys that the clients have to authenticate with (`Authorization: Bearer <key>` or `X-Api-Key`),
+    /// in the format `name:key[:requests_per_second[:user_operations_per_minute[:admin]]]`, only the keys with the
+    /// `admin` scope (and the JWT) can call the `admin_` methods
+    #[clap(long, value_delimiter = ',')]
+    pub rpc_api_keys: Vec<ApiKey>,
+
+    /// File with the hex encoded secret of the JWTs (HS256) that the clients can authenticate with
+    #[clap(long)]
+    pub rpc_jwt_secret: Option<PathBuf>,
+
+    /// PEM encoded certificate chain, the server is served over TLS (HTTPS/WSS) if set with the key
+    #[clap(long, requires = "rpc_tls_key")]
+    pub rpc_tls_cert: Option<PathBuf>,
+
+    /// PEM encoded private key of the TLS certificate
+    #[clap(long, requires = "rpc_tls_cert")]
+    pub rpc_tls_key: Option<PathBuf>,
+
+    /// Unix domain socket on which the server is served to the co-located clients (IPC)
+    #[clap(long)]
+    pub rpc_ipc_path: Option<PathBuf>,
+
+    /// Maximum number of calls in a batch request (0 for no limit)
+    #[clap(long, default_value = "100")]
+    pub rpc_max_batch_size: usize,
+
+    /// Maximum number of user operations submitted in a batch request, the calls above the limit fail
+    /// individually (0 for no limit)
+    #[clap(long, default_value = "10")]
+    pub rpc_max_user_operations_per_batch: usize,
+
+    /// Maximum size of the request body in bytes
+    #[clap(long, default_value = "1048576")]
+    pub rpc_max_request_body_size: u32,
+
+    /// Maximum size of the response body in bytes
+    #[clap(long, default_value = "10485760")]
+    pub rpc_max_response_body_size: u32,
+
+    /// Maximum size of the bytes fields of the user operation (`initCode`, `callData`, `paymasterAndData`,
+    /// `signature`) in bytes
+    #[clap(long, default_value = "32768")]
+    pub rpc_max_user_operation_field_size: usize,
+
+    /// Loopback address of a separate listener of the `admin` and `debug_bundler` namespaces, which are then not
+    /// served on the public address (IPC serves all the namespaces)
+    #[clap(long)]
+    pub rpc_private_listen_address: Option<String>,
+}
+
+/// Whether all the addresses the listen address resolves to are loopback addresses
+fn is_loopback(listen_address: &str) -> anyhow::Result<bool> {
+    let addresses = listen_address
+        .to_socket_addrs()?
+        .collect::<Vec<SocketAddr>>();
+    Ok(!addresses.is_empty() && addresses.iter().all(|address| address.ip().is_loopback()))
+}
+
+/// Handles of the listeners of the server, which are stopped when the handles are dropped
+pub struct JsonRpcServerHandle {
+    pub public: ServerHandle,
+    pub private: Option<ServerHandle>,
+}
+
+impl JsonRpcServerHandle {
+    /// Stops accepting new connections and waits for the listeners to complete the in-flight requests
+    pub async fn stop(self) {
+        for handle in std::iter::once(self.public).chain(self.private) {
+            // it's already stopped if the listener failed
+            let _ = handle.stop();
+            handle.stopped().await;
+        }
+    }
+}
+
+/// JSON-RPC server that exposes the bundler namespaces backed by the uopool and bundler gRPC services.
+/// It serves HTTP and, if enabled, WebSocket (required for subscriptions) on the same address.
+pub struct JsonRpcServer {
+    listen_address: String,
+    opts: JsonRpcServerOpts,
+    methods: Methods,
+    /// Methods of the private listener (if set)
+    private_methods: Methods,
+    health_checker: Option<Arc<HealthChecker>>,
+    config_reloader: Option<ConfigReloader>,
+    config: BTreeMap<String, Value>,
+}
+
+impl JsonRpcServer {
+    pub fn new(listen_address: String, opts: JsonRpcServerOpts) -> Self {
+        Self {
+            listen_address,
+            opts,
+            methods: Methods::new(),
+            private_methods: Methods::new(),
+            health_checker: None,
+            config_reloader: None,
+            config: BTreeMap::new(),
+        }
+    }
+
+    /// Serves the `/health` and `/ready` probes (e.g. for Kubernetes) with the checks of the backends
+    pub fn set_health_checker(&mut self, health_checker: HealthChecker) {
+        self.health_checker = Some(Arc::new(health_checker));
+    }
+
+    /// Lets the `admin_reloadConfig` method reload the configuration, has to be set before the namespaces are added
+    pub fn set_config_reloader(&mut self, config_reloader: ConfigReloader) {
+        self.config_reloader = Some(config_reloader);
+    }
+
+    /// Options reported by the `admin_getConfig` method (see `dump_config`), has to be set before the namespaces are
+    /// added
+    pub fn set_config(&mut self, config: BTreeMap<String, Value>) {
+        self.config = config;
+    }
+
+    pub fn add_methods(&mut self, methods: impl Into<Methods>) -> anyhow::Result<()> {
+        self.methods.merge(methods)?;
+        Ok(())
+    }
+
+    /// Adds the methods to the private listener, or to the public one if there is no private listener
+    pub fn add_private_methods(&mut self, methods: impl Into<Methods>) -> anyhow::Result<()> {
+        if self.opts.rpc_private_listen_address.is_some() {
+            self.private_methods.merge(methods)?;
+        } else {
+            self.methods.merge(methods)?;
+        }
+        Ok(())
+    }
+
+    /// Adds the enabled namespaces (`eth`, `admin`) and, if `debug` is set, the `debug_bundler` namespace
+    /// (the bundler gRPC client is only needed for `admin` and `debug_bundler`). The `admin` and `debug_bundler`
+    /// namespaces are served on the private listener if it's set, otherwise the `admin` namespace requires the
+    /// clients to authenticate with an API key of the admin scope or the JWT. The bundler gRPC client connects over TLS if the config is set.
+    pub async fn add_namespaces(
+        &mut self,
+        rpc_api: &[String],
+        debug: bool,
+        uopool_grpc_client: UoPoolGrpcClient,
+        bundler_grpc_listen_address: &str,
+        grpc_tls_config: Option<&ClientTlsConfig>,
+    ) -> anyhow::Result<()> {
+        let rpc_api: HashSet<&str> = rpc_api.iter().map(|api| api.as_str()).collect();
+
+        if rpc_api.contains("eth") {
+            self.add_methods(
+                EthApiServerImpl {
+                    call_gas_limit: CALL_GAS_LIMIT,
+                    uopool_grpc_client: uopool_grpc_client.clone(),
+                }
+                .into_rpc(),
+            )?;
+        }
+
+        let admin = rpc_api.contains("admin");
+        if admin
+            && self.opts.rpc_private_listen_address.is_none()
+            && self.opts.rpc_api_keys.is_empty()
+            && self.opts.rpc_jwt_secret.is_none()
+        {
+            anyhow::bail!(
+                "The admin namespace requires authentication (--rpc-api-keys or --rpc-jwt-secret) or the private listener (--rpc-private-listen-address)"
+            );
+        }
+
+        if admin || debug {
+            let bundler_grpc_client = BundlerClient::new(
+                grpc_channel(bundler_grpc_listen_address, grpc_tls_config).await?,
+            );
+
+            if admin {
+                self.add_private_methods(
+                    AdminApiServerImpl {
+                        uopool_grpc_client: uopool_grpc_client.clone(),
+                        bundler_grpc_client: bundler_grpc_client.clone(),
+                        config_reloader: self.config_reloader.clone(),
+                        config: self.config.clone(),
+                    }
+                    .into_rpc(),
+                )?;
+            }
+
+            if debug {
+                self.add_private_methods(
+                    DebugApiServerImpl {
+                        uopool_grpc_client,
+                        bundler_grpc_client,
+                    }
+                    .into_rpc(),
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Starts the listener of the methods on the address, served over TLS if it's configured
+    async fn serve(
+        &self,
+        listen_address: &str,
+        methods: Methods,
+        tls_config: Option<TlsConfig>,
+    ) -> anyhow::Result<ServerHandle> {
+        let jwt_secret = self
+            .opts
+            .rpc_jwt_secret
+            .as_deref()
+            .map(read_jwt_secret)
+            .transpose()?;
+        let authenticator = Arc::new(Authenticator::new(
+            self.opts.rpc_api_keys.clone(),
+            jwt_secret,
+        ));
+        let admin_scope = authenticator.is_enabled()
+            && methods
+                .method_names()
+                .any(|method| method.starts_with(ADMIN_METHOD_PREFIX));
+        let rate_limiter = Arc::new(RateLimiter::new(RateLimits {
+            requests_per_second: self.opts.rpc_requests_per_second,
+            user_operations_per_minute: self.opts.rpc_user_operations_per_minute,
+        }));
+        let allowed_origins = AllowedOrigins::new(
+            self.opts
+                .rpc_cors_domains
+                .iter()
+                .filter(|domain| !domain.is_empty())
+                .cloned()
+                .collect(),
+        );
+        let metrics = MethodMetrics::new(methods.method_names());
+        // the WebSocket connections are served by the middleware, so that each message goes through the limits
+        let builder = ServerBuilder::default()
+            .max_request_body_size(self.opts.rpc_max_request_body_size)
+            .max_response_body_size(self.opts.rpc_max_response_body_size)
+            .set_middleware(
+                tower::ServiceBuilder::new()
+                    .layer(HealthLayer::new(self.health_checker.clone()))
+                    .layer(CorsLayer::new(allowed_origins))
+                    .layer(AuthLayer::new(authenticator))
+                    .layer(PayloadValidationLayer::new(self.payload_limits()))
+                    .layer(AdminScopeLayer::new(admin_scope))
+                    .layer(MetricsLayer::new(metrics.clone()))
+                    .layer(RateLimitLayer::new(rate_limiter.clone()))
+                    .layer(BatchLimitLayer::new(self.batch_limits()))
+                    .layer(WebSocketLayer::new(
+                        self.opts.rpc_ws,
+                        methods.clone(),
+                        rate_limiter,
+                        metrics,
+                        self.batch_limits(),
+                        self.payload_limits(),
+                    )),
+            )
+            .http_only();
+
+        // the server listens on the loopback interface and the listener in front of it (which terminates TLS)
+        // forwards the requests with the addresses of the clients
+        let tls_acceptor = tls_config
+            .map(|tls_config| tls_config.acceptor(&[ALPN_HTTP1]))
+            .transpose()?;
+        let server = builder.build(UPSTREAM_ADDRESS).await?;
+        let upstream = server.local_addr()?;
+        proxy_serve(
+            listen_address,
+            upstream,
+            tls_acceptor,
+            self.opts.rpc_trusted_proxies.iter().copied().collect(),
+        )
+        .await?;
+        Ok(server.start(methods)?)
+    }
+
+    fn batch_limits(&self) -> BatchLimits {
+        BatchLimits {
+            max_batch_size: self.opts.rpc_max_batch_size,
+            max_user_operations: self.opts.rpc_max_user_operations_per_batch,
+        }
+    }
+
+    fn payload_limits(&self) -> PayloadLimits {
+        PayloadLimits {
+            max_request_body_size: self.opts.rpc_max_request_body_size as usize,
+            max_user_operation_field_size: self.opts.rpc_max_user_operation_field_size,
+        }
+    }
+
+    pub async fn start(&self) -> anyhow::Result<JsonRpcServerHandle> {
+        if let Some(ipc_path) = &self.opts.rpc_ipc_path {
+            let mut methods = self.methods.clone();
+            methods.merge(self.private_methods.clone())?;
+            ipc_serve(
+                ipc_path,
+                methods,
+                self.batch_limits(),
+                self.payload_limits(),
+            )
+            .await?;
+        }
+
+        let private = match &self.opts.rpc_private_listen_address {
+            Some(private_listen_address) => {
+                if !is_loopback(private_listen_address)? {
+                    anyhow::bail!(
+                        "The private listen address {private_listen_address} is not a loopback address"
+                    );
+                }
+                let handle = self
+                    .serve(private_listen_address, self.private_methods.clone(), None)
+                    .await?;
+                info!("JSON-RPC private listener on {private_listen_address}");
+                Some(handle)
+            }
+            None => None,
+        };
+
+        let tls_config = TlsConfig::from_paths(
+            self.opts.rpc_tls_cert.clone(),
+            self.opts.rpc_tls_key.clone(),
+        );
+        let public = self
+            .serve(&self.listen_address, self.methods.clone(), tls_config)
+            .await?;
+
+        Ok(JsonRpcServerHandle { public, private })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loopback_listen_address() {
+        assert!(is_loopback("127.0.0.1:3001").unwrap());
+        assert!(is_loopback("[::1]:3001").unwrap());
+        assert!(!is_loopback("0.0.0.0:3001").unwrap());
+        assert!(!is_loopback("10.0.0.1:3001").unwrap());
+        assert!(is_loopback("127.0.0.1").is_err());
+    }
+}
@@ -0,0 +1,298 @@
+use std::{
+    error::Error,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use hyper::{body::HttpBody, header, Body, Method, Request, Response, StatusCode};
+use jsonrpsee::types::error::{INVALID_PARAMS_CODE, OVERSIZED_REQUEST_CODE, OVERSIZED_REQUEST_MSG};
+use serde_json::Value;
+use tower::{Layer, Service};
+
+use crate::{
+    batch::{call_filtered, SEND_USER_OPERATION_METHOD},
+    response::{json_response, jsonrpc_error, jsonrpc_error_body},
+};
+
+/// Methods whose parameters are the user operation and the entry point
+const USER_OPERATION_METHODS: [&str; 2] =
+    [SEND_USER_OPERATION_METHOD, "eth_estimateUserOperationGas"];
+const ADDRESS_FIELDS: [&str; 1] = ["sender"];
+const QUANTITY_FIELDS: [&str; 6] = [
+    "nonce",
+    "callGasLimit",
+    "verificationGasLimit",
+    "preVerificationGas",
+    "maxFeePerGas",
+    "maxPriorityFeePerGas",
+];
+const BYTES_FIELDS: [&str; 4] = ["initCode", "callData", "paymasterAndData", "signature"];
+/// Number of hex digits of an address
+const ADDRESS_DIGITS: usize = 40;
+/// Number of hex digits of the largest quantity (256 bits)
+const MAX_QUANTITY_DIGITS: usize = 64;
+
+/// Hex digits of the field, which has to be a `0x` prefixed string
+fn hex_digits<'a>(field: &str, value: &'a Value) -> Result<&'a str, String> {
+    let digits = value
+        .as_str()
+        .and_then(|value| value.strip_prefix("0x"))
+        .ok_or_else(|| format!("{field} must be a 0x prefixed hex string"))?;
+    if !digits.bytes().all(|digit| digit.is_ascii_hexdigit()) {
+        return Err(format!("{field} contains invalid hex characters"));
+    }
+    Ok(digits)
+}
+
+fn validate_address(field: &str, value: &Value) -> Result<(), String> {
+    if hex_digits(field, value)?.len() != ADDRESS_DIGITS {
+        return Err(format!("{field} must be a 20 bytes address"));
+    }
+    Ok(())
+}
+
+/// Limits of the payloads of the JSON-RPC requests
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PayloadLimits {
+    /// Maximum size of the request body in bytes
+    pub max_request_body_size: usize,
+    /// Maximum size of the bytes fields of the user operation (e.g. `callData`) in bytes
+    pub max_user_operation_field_size: usize,
+}
+
+impl PayloadLimits {
+    /// Strict validation of the user operation fields (sizes and hex encoding), the fields that are not
+    /// present are left to the deserialization of the method parameters
+    pub fn validate_user_operation(&self, user_operation: &Value) -> Result<(), String> {
+        let user_operation = user_operation
+            .as_object()
+            .ok_or_else(|| "User operation must be an object".to_string())?;
+
+        for (field, value) in user_operation.iter().filter(|(_, value)| !value.is_null()) {
+            if ADDRESS_FIELDS.contains(&field.as_str()) {
+                validate_address(field, value)?;
+            } else if QUANTITY_FIELDS.contains(&field.as_str()) {
+                let digits = hex_digits(field, value)?;
+                if digits.is_empty() || digits.len() > MAX_QUANTITY_DIGITS {
+                    return Err(format!("{field} must be a quantity of at most 256 bits"));
+                }
+            } else if BYTES_FIELDS.contains(&field.as_str()) {
+                let digits = hex_digits(field, value)?;
+                if digits.len() % 2 != 0 {
+                    return Err(format!("{field} must have an even number of hex digits"));
+                }
+                if digits.len() / 2 > self.max_user_operation_field_size {
+                    return Err(format!(
+                        "{field} exceeds the limit of {} bytes",
+                        self.max_user_operation_field_size
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Validates the parameters of the call, returns the error response if they are invalid
+    pub fn validate_call(&self, call: &Value) -> Option<Value> {
+        let method = call.get("method").and_then(|method| method.as_str())?;
+        if !USER_OPERATION_METHODS.contains(&method) {
+            return None;
+        }
+
+        let (user_operation, entry_point) = match call.get("params") {
+            Some(Value::Array(params)) => (params.first(), params.get(1)),
+            Some(Value::Object(params)) => {
+                (params.get("user_operation"), params.get("entry_point"))
+            }
+            _ => (None, None),
+        };
+        let result = user_operation
+            .map(|user_operation| self.validate_user_operation(user_operation))
+            .unwrap_or(Ok(()))
+            .and_then(|_| {
+                entry_point
+                    .map(|entry_point| validate_address("Entry point", entry_point))
+                    .unwrap_or(Ok(()))
+            });
+
+        result.err().map(|message| {
+            jsonrpc_error_body(
+                call.get("id").cloned().unwrap_or_default(),
+                INVALID_PARAMS_CODE,
+                message,
+            )
+        })
+    }
+}
+
+/// Reads the body up to the limit, returns `None` if the body is larger
+async fn read_body(mut body: Body, limit: usize) -> Result<Option<Vec<u8>>, hyper::Error> {
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+        if bytes.len() + chunk.len() > limit {
+            return Ok(None);
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(Some(bytes))
+}
+
+fn oversized_request() -> Response<Body> {
+    let mut response = jsonrpc_error(Value::Null, OVERSIZED_REQUEST_CODE, OVERSIZED_REQUEST_MSG);
+    *response.status_mut() = StatusCode::PAYLOAD_TOO_LARGE;
+    response
+}
+
+/// Middleware that rejects the oversized request bodies (before they are buffered by the other middlewares) and
+/// the calls with invalid user operations
+#[derive(Clone, Debug)]
+pub struct PayloadValidationLayer {
+    limits: PayloadLimits,
+}
+
+impl PayloadValidationLayer {
+    pub fn new(limits: PayloadLimits) -> Self {
+        Self { limits }
+    }
+}
+
+impl<S> Layer<S> for PayloadValidationLayer {
+    type Service = PayloadValidation<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        PayloadValidation {
+            inner,
+            limits: self.limits,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PayloadValidation<S> {
+    inner: S,
+    limits: PayloadLimits,
+}
+
+impl<S> Service<Request<Body>> for PayloadValidation<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>, Error = Box<dyn Error + Send + Sync>>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send,
+{
+    type Response = Response<Body>;
+    type Error = Box<dyn Error + Send + Sync>;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        if request.method() != Method::POST {
+            return Box::pin(self.inner.call(request));
+        }
+
+        let limits = self.limits;
+        let content_length = request
+            .headers()
+            .get(header::CONTENT_LENGTH)
+            .and_then(|length| length.to_str().ok())
+            .and_then(|length| length.parse::<usize>().ok());
+        if matches!(content_length, Some(length) if length > limits.max_request_body_size) {
+            return Box::pin(async { Ok(oversized_request()) });
+        }
+
+        // the inner service was polled to be ready, so it's the one that has to be called
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        Box::pin(async move {
+            let (parts, body) = request.into_parts();
+            let body = match read_body(body, limits.max_request_body_size).await? {
+                Some(body) => body,
+                None => return Ok(oversized_request()),
+            };
+
+            match serde_json::from_slice::<Value>(&body) {
+                Ok(Value::Array(calls)) => {
+                    let (accepted, rejected): (Vec<Value>, Vec<Value>) = calls.into_iter().fold(
+                        (vec![], vec![]),
+                        |(mut accepted, mut rejected), call| {
+                            match limits.validate_call(&call) {
+                                Some(error) => rejected.push(error),
+                                None => accepted.push(call),
+                            }
+                            (accepted, rejected)
+                        },
+                    );
+                    if !rejected.is_empty() {
+                        return call_filtered(&mut inner, parts, accepted, rejected).await;
+                    }
+                }
+                Ok(call) => {
+                    if let Some(error) = limits.validate_call(&call) {
+                        return Ok(json_response(&error));
+                    }
+                }
+                // invalid requests are handled by the server
+                Err(_) => {}
+            }
+
+            inner
+                .call(Request::from_parts(parts, Body::from(body)))
+                .await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn payload_validation() {
+        let limits = PayloadLimits {
+            max_request_body_size: 1024,
+            max_user_operation_field_size: 4,
+        };
+        let call = |user_operation: Value| {
+            serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": SEND_USER_OPERATION_METHOD,
+                "params": [user_operation, "0x5ff137d4b0fdcd49dca30c7cf57e578a026d2789"],
+            })
+        };
+
+        let valid = serde_json::json!({
+            "sender": "0xef5b78898d61b7020a6db5a39608c4b02f95b50f",
+            "nonce": "0x0",
+            "callData": "0x01020304",
+            "paymasterAndData": "0x",
+        });
+        assert_eq!(limits.validate_call(&call(valid.clone())), None);
+
+        for (field, value) in [
+            ("sender", "0xef5b78898d61b7020a6db5a39608c4b02f95b5"),
+            ("nonce", "0"),
+            ("nonce", "0x"),
+            ("callData", "0x0102030405"),
+            ("callData", "0x010"),
+            ("signature", "0xzz"),
+        ] {
+            let mut invalid = valid.clone();
+            invalid[field] = Value::String(value.to_string());
+            let error = limits.validate_call(&call(invalid)).unwrap();
+            assert_eq!(error["error"]["code"], INVALID_PARAMS_CODE);
+            assert_eq!(error["id"], 1);
+        }
+
+        let other = serde_json::json!({"jsonrpc": "2.0", "id": 2, "method": "eth_chainId"});
+        assert_eq!(limits.validate_call(&other), None);
+    }
+}
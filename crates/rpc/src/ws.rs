@@ -0,0 +1,291 @@
+use std::{
+    error::Error,
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Instant,
+};
+
+use aa_bundler_primitives::LIMIT_EXCEEDED_ERROR_CODE;
+use futures_util::io::{BufReader, BufWriter};
+use hyper::{upgrade::Upgraded, Body, Request, Response, StatusCode};
+use jsonrpsee::{
+    core::server::rpc_module::Methods,
+    types::error::{OVERSIZED_REQUEST_CODE, OVERSIZED_REQUEST_MSG, PARSE_ERROR_CODE},
+};
+use serde_json::Value;
+use soketto::{
+    connection,
+    handshake::http::{is_upgrade_request, Server},
+};
+use tokio::sync::mpsc;
+use tokio_util::compat::TokioAsyncReadCompatExt;
+use tower::{Layer, Service};
+use tracing::debug;
+
+use crate::{
+    instrumentation::client_label,
+    ipc::process_request,
+    rate_limit::{RateLimitedClient, LIMIT_EXCEEDED_MSG},
+    response::jsonrpc_error_body,
+    BatchLimits, MethodMetrics, PayloadLimits, RateLimiter, RateLimits,
+};
+
+/// Serves the WebSocket connections, each message goes through the rate limiter, the metrics, the batch limits and
+/// the payload validation the same way as the HTTP requests do through the middlewares
+#[derive(Clone, Debug)]
+struct WebSocketServer {
+    methods: Methods,
+    rate_limiter: Arc<RateLimiter>,
+    metrics: MethodMetrics,
+    batch_limits: BatchLimits,
+    payload_limits: PayloadLimits,
+}
+
+/// Client of the WebSocket connection, as authenticated (or identified by its address) on the handshake
+#[derive(Clone, Debug)]
+struct WebSocketClient {
+    client: RateLimitedClient,
+    limits: RateLimits,
+    label: String,
+}
+
+impl WebSocketServer {
+    async fn handle_message(
+        self,
+        client: WebSocketClient,
+        request: Value,
+        tx: mpsc::UnboundedSender<String>,
+    ) {
+        if !client.limits.is_unlimited()
+            && !self
+                .rate_limiter
+                .check_call(&client.client, &client.limits, &request)
+        {
+            let _ = tx.send(
+                jsonrpc_error_body(Value::Null, LIMIT_EXCEEDED_ERROR_CODE, LIMIT_EXCEEDED_MSG)
+                    .to_string(),
+            );
+            return;
+        }
+
+        let calls = self.metrics.record_calls(&request, &client.label);
+        let started_at = Instant::now();
+        let response = process_request(
+            &self.methods,
+            self.batch_limits,
+            self.payload_limits,
+            request,
+            &tx,
+        )
+        .await;
+        MethodMetrics::record_response(
+            &calls,
+            started_at.elapsed().as_secs_f64(),
+            response.as_bytes(),
+        );
+        let _ = tx.send(response);
+    }
+
+    async fn serve(self, server: Server<'static>, upgraded: Upgraded, client: WebSocketClient) {
+        let mut builder = server.into_builder(BufReader::new(BufWriter::new(upgraded.compat())));
+        builder.set_max_message_size(self.payload_limits.max_request_body_size);
+        let (mut sender, mut receiver) = builder.finish();
+        let (tx, mut rx) = mpsc::unbounded_channel::<String>();
+
+        tokio::spawn(async move {
+            while let Some(message) = rx.recv().await {
+                if sender.send_text(&message).await.is_err() || sender.flush().await.is_err() {
+                    break;
+                }
+            }
+            let _ = sender.close().await;
+        });
+
+        let mut message = Vec::new();
+        loop {
+            message.clear();
+            match receiver.receive_data(&mut message).await {
+                Ok(_) => {}
+                Err(connection::Error::MessageTooLarge { .. }) => {
+                    let _ = tx.send(
+                        jsonrpc_error_body(
+                            Value::Null,
+                            OVERSIZED_REQUEST_CODE,
+                            OVERSIZED_REQUEST_MSG,
+                        )
+                        .to_string(),
+                    );
+                    break;
+                }
+                Err(err) => {
+                    debug!("WebSocket connection closed: {err:?}");
+                    break;
+                }
+            }
+
+            match serde_json::from_slice::<Value>(&message) {
+                Ok(request) => {
+                    tokio::spawn(
+                        self.clone()
+                            .handle_message(client.clone(), request, tx.clone()),
+                    );
+                }
+                Err(err) => {
+                    debug!("Invalid WebSocket request: {err:?}");
+                    let _ = tx.send(
+                        jsonrpc_error_body(Value::Null, PARSE_ERROR_CODE, "Parse error")
+                            .to_string(),
+                    );
+                }
+            }
+        }
+    }
+}
+
+fn bad_request() -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::BAD_REQUEST)
+        .body(Body::empty())
+        .expect("Bad request response is valid")
+}
+
+/// Middleware that serves the WebSocket connections (if enabled) instead of the server, so that the rate limits, the
+/// batch limits, the payload validation and the metrics apply to each message of the connection and not only to the
+/// handshake. It has to be the innermost middleware, the handshake still goes through the others.
+#[derive(Clone, Debug)]
+pub struct WebSocketLayer {
+    server: Option<WebSocketServer>,
+}
+
+impl WebSocketLayer {
+    pub fn new(
+        enabled: bool,
+        methods: Methods,
+        rate_limiter: Arc<RateLimiter>,
+        metrics: MethodMetrics,
+        batch_limits: BatchLimits,
+        payload_limits: PayloadLimits,
+    ) -> Self {
+        Self {
+            server: enabled.then_some(WebSocketServer {
+                methods,
+                rate_limiter,
+                metrics,
+                batch_limits,
+                payload_limits,
+            }),
+        }
+    }
+}
+
+impl<S> Layer<S> for WebSocketLayer {
+    type Service = WebSocket<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        WebSocket {
+            inner,
+            server: self.server.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct WebSocket<S> {
+    inner: S,
+    server: Option<WebSocketServer>,
+}
+
+impl<S> Service<Request<Body>> for WebSocket<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>, Error = Box<dyn Error + Send + Sync>>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send,
+{
+    type Response = Response<Body>;
+    type Error = Box<dyn Error + Send + Sync>;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        // the server only serves HTTP, so the handshakes are rejected if the WebSocket is disabled
+        let server = match &self.server {
+            Some(server) if is_upgrade_request(&request) => server.clone(),
+            _ => return Box::pin(self.inner.call(request)),
+        };
+
+        let (client, limits) = server.rate_limiter.client(&request);
+        let client = WebSocketClient {
+            client,
+            limits,
+            label: client_label(&request),
+        };
+        let mut handshake = Server::new();
+        let response = match handshake.receive_request(&request) {
+            Ok(response) => response.map(|()| Body::empty()),
+            Err(err) => {
+                debug!("Invalid WebSocket handshake: {err:?}");
+                return Box::pin(async { Ok(bad_request()) });
+            }
+        };
+
+        tokio::spawn(async move {
+            match hyper::upgrade::on(request).await {
+                Ok(upgraded) => server.serve(handshake, upgraded, client).await,
+                Err(err) => debug!("WebSocket upgrade failed: {err:?}"),
+            }
+        });
+        Box::pin(async { Ok(response) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use jsonrpsee::RpcModule;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn rate_limited_messages() {
+        let mut module = RpcModule::new(());
+        module
+            .register_method("eth_chainId", |_, _| Ok("0x1"))
+            .unwrap();
+        let limits = RateLimits {
+            requests_per_second: Some(1),
+            user_operations_per_minute: None,
+        };
+        let server = WebSocketServer {
+            methods: module.into(),
+            rate_limiter: Arc::new(RateLimiter::new(limits)),
+            metrics: MethodMetrics::new(["eth_chainId"]),
+            batch_limits: BatchLimits::default(),
+            payload_limits: PayloadLimits::default(),
+        };
+        let client = WebSocketClient {
+            client: RateLimitedClient::Ip(Some("10.0.0.1".parse().unwrap())),
+            limits,
+            label: "anonymous".into(),
+        };
+        let request = serde_json::json!({"jsonrpc": "2.0", "id": 1, "method": "eth_chainId"});
+        let (tx, mut rx) = mpsc::unbounded_channel();
+
+        // the requests limit applies per message of the connection, not only to the handshake
+        for _ in 0..2 {
+            server
+                .clone()
+                .handle_message(client.clone(), request.clone(), tx.clone())
+                .await;
+        }
+        let response = serde_json::from_str::<Value>(&rx.recv().await.unwrap()).unwrap();
+        assert_eq!(response["result"], "0x1");
+        let response = serde_json::from_str::<Value>(&rx.recv().await.unwrap()).unwrap();
+        assert_eq!(response["error"]["code"], LIMIT_EXCEEDED_ERROR_CODE);
+    }
+}
@@ -1,12 +1,14 @@
 use aa_bundler_primitives::{
-    ReputationStatus, SanityCheckError, StakeInfo, UserOperation, UserOperationHash,
-    EXECUTION_ERROR_CODE, SANITY_CHECK_ERROR_CODE,
+    get_addr, Authorization, BadReputationError, ReputationError, ReputationStatus,
+    SanityCheckError, StakeInfo, UserOperation, UserOperationHash, ENTITY_BANNED_ERROR_CODE,
+    EXECUTION_ERROR_CODE, SANITY_CHECK_ERROR_CODE, THROTTLED_MAX_INCLUDE,
 };
 use ethers::{
     providers::Middleware,
     types::{Address, BlockNumber, Bytes, U256},
 };
 use jsonrpsee::types::error::ErrorCode;
+use serde_json::json;
 
 use crate::{
     utils::{calculate_valid_gas, Overhead},
@@ -36,6 +38,12 @@ pub enum BadUserOperationError<M: Middleware> {
     PaymasterVerification {
         paymaster_and_data: Bytes,
     },
+    PaymasterBanned {
+        paymaster: Address,
+    },
+    PaymasterThrottled {
+        paymaster: Address,
+    },
     LowCallGasLimit {
         call_gas_limit: U256,
         call_gas_estimation: U256,
@@ -52,9 +60,14 @@ pub enum BadUserOperationError<M: Middleware> {
         max_priority_fee_per_gas: U256,
         min_priority_fee_per_gas: U256,
     },
+    LegacyGasPrice {
+        max_fee_per_gas: U256,
+        max_priority_fee_per_gas: U256,
+    },
     SenderVerification {
         sender: Address,
     },
+    Reputation(BadReputationError),
     UserOperationExecution {
         message: String,
     },
@@ -64,6 +77,31 @@ pub enum BadUserOperationError<M: Middleware> {
     },
 }
 
+impl<M: Middleware> BadUserOperationError<M> {
+    /// Sanity check that the user operation failed
+    pub fn rule(&self) -> &'static str {
+        match self {
+            BadUserOperationError::SenderOrInitCode { .. } => "sender_or_init_code",
+            BadUserOperationError::FactoryVerification { .. } => "factory",
+            BadUserOperationError::HighVerificationGasLimit { .. } => "verification_gas_limit",
+            BadUserOperationError::LowPreVerificationGas { .. } => "pre_verification_gas",
+            BadUserOperationError::PaymasterVerification { .. } => "paymaster",
+            BadUserOperationError::PaymasterBanned { .. } => "paymaster_banned",
+            BadUserOperationError::PaymasterThrottled { .. } => "paymaster_throttled",
+            BadUserOperationError::LowCallGasLimit { .. } => "call_gas_limit",
+            BadUserOperationError::LowMaxFeePerGas { .. } => "max_fee_per_gas",
+            BadUserOperationError::HighMaxPriorityFeePerGas { .. }
+            | BadUserOperationError::LowMaxPriorityFeePerGas { .. } => "max_priority_fee_per_gas",
+            BadUserOperationError::LegacyGasPrice { .. } => "legacy_gas_price",
+            BadUserOperationError::SenderVerification { .. } => "sender",
+            BadUserOperationError::Reputation(_) => "reputation",
+            BadUserOperationError::UserOperationExecution { .. } => "execution",
+            BadUserOperationError::Middleware(_) => "execution_client",
+            BadUserOperationError::UnknownError { .. } => "unknown",
+        }
+    }
+}
+
 impl<M: Middleware> From<BadUserOperationError<M>> for SanityCheckError {
     fn from(error: BadUserOperationError<M>) -> Self {
         match error {
@@ -110,6 +148,20 @@ impl<M: Middleware> From<BadUserOperationError<M>> for SanityCheckError {
                     None::<bool>,
                 )
             },
+            BadUserOperationError::PaymasterBanned { paymaster } => SanityCheckError::owned(
+                ENTITY_BANNED_ERROR_CODE,
+                format!("Paymaster with address {paymaster} is banned"),
+                Some(json!({
+                    "paymaster": paymaster,
+                })),
+            ),
+            BadUserOperationError::PaymasterThrottled { paymaster } => SanityCheckError::owned(
+                ENTITY_BANNED_ERROR_CODE,
+                format!("Paymaster with address {paymaster} is throttled"),
+                Some(json!({
+                    "paymaster": paymaster,
+                })),
+            ),
             BadUserOperationError::LowCallGasLimit {
                 call_gas_limit,
                 call_gas_estimation,
@@ -150,11 +202,22 @@ impl<M: Middleware> From<BadUserOperationError<M>> for SanityCheckError {
                 ),
                 None::<bool>,
             ),
+            BadUserOperationError::LegacyGasPrice {
+                max_fee_per_gas,
+                max_priority_fee_per_gas,
+            } => SanityCheckError::owned(
+                SANITY_CHECK_ERROR_CODE,
+                format!(
+                    "Max fee per gas {max_fee_per_gas} and max priority fee per gas {max_priority_fee_per_gas} have to be equal on chains without EIP-1559",
+                ),
+                None::<bool>,
+            ),
             BadUserOperationError::SenderVerification { sender } => SanityCheckError::owned(
                 SANITY_CHECK_ERROR_CODE,
                 format!("Sender {sender} is invalid (sender check)",),
                 None::<bool>,
             ),
+            BadUserOperationError::Reputation(error) => ReputationError::from(error),
             BadUserOperationError::UserOperationExecution { message } => {
                 SanityCheckError::owned(
                     EXECUTION_ERROR_CODE,
@@ -185,15 +248,16 @@ impl<M: Middleware + 'static> UoPool<M> {
     async fn sender_or_init_code(
         &self,
         user_operation: &UserOperation,
+        authorization: Option<&Authorization>,
     ) -> Result<(), BadUserOperationError<M>> {
         let code = self
             .eth_provider
             .get_code(user_operation.sender, None)
             .await
             .map_err(|error| BadUserOperationError::Middleware(error))?;
-        if (code.is_empty() && user_operation.init_code.is_empty())
-            || (!code.is_empty() && !user_operation.init_code.is_empty())
-        {
+        // the sender that is delegated by the authorization gets its code with the bundle transaction
+        let has_code = !code.is_empty() || authorization.is_some();
+        if has_code == !user_operation.init_code.is_empty() {
             return Err(BadUserOperationError::SenderOrInitCode {
                 sender: user_operation.sender,
                 init_code: user_operation.init_code.clone(),
@@ -258,13 +322,33 @@ impl<M: Middleware + 'static> UoPool<M> {
                     paymaster_and_data: user_operation.paymaster_and_data.clone(),
                 })?;
 
-            if U256::from(deposit_info.deposit) < user_operation.max_fee_per_gas
-                || self.reputation.get_status(&paymaster_address) == ReputationStatus::BANNED
-            {
+            if U256::from(deposit_info.deposit) < user_operation.max_fee_per_gas {
                 return Err(BadUserOperationError::PaymasterVerification {
                     paymaster_and_data: user_operation.paymaster_and_data.clone(),
                 });
             }
+
+            match self.reputation.get_status(&paymaster_address) {
+                ReputationStatus::BANNED => {
+                    return Err(BadUserOperationError::PaymasterBanned {
+                        paymaster: paymaster_address,
+                    });
+                }
+                ReputationStatus::THROTTLED => {
+                    let paymaster_count = self
+                        .mempool
+                        .get_all()
+                        .iter()
+                        .filter(|uo| get_addr(&uo.paymaster_and_data) == Some(paymaster_address))
+                        .count() as u64;
+                    if paymaster_count >= THROTTLED_MAX_INCLUDE {
+                        return Err(BadUserOperationError::PaymasterThrottled {
+                            paymaster: paymaster_address,
+                        });
+                    }
+                }
+                _ => {}
+            }
         }
 
         Ok(())
@@ -309,28 +393,39 @@ impl<M: Middleware + 'static> UoPool<M> {
             .await
             .map_err(|error| BadUserOperationError::Middleware(error))?;
 
-        let base_fee_per_gas = if let Some(block) = block {
-            if let Some(base_fee_per_gas) = block.base_fee_per_gas {
-                base_fee_per_gas
-            } else {
-                return Err(BadUserOperationError::UnknownError {
-                    error: "Can't get base fee per gas".to_string(),
-                });
+        match block.and_then(|block| block.base_fee_per_gas) {
+            Some(base_fee_per_gas) if !self.chain_spec.legacy_gas => {
+                if base_fee_per_gas + user_operation.max_priority_fee_per_gas
+                    > user_operation.max_fee_per_gas
+                {
+                    return Err(BadUserOperationError::LowMaxFeePerGas {
+                        max_fee_per_gas: user_operation.max_fee_per_gas,
+                        max_fee_per_gas_estimated: base_fee_per_gas
+                            + user_operation.max_priority_fee_per_gas,
+                    });
+                }
+            }
+            _ => {
+                // the entry point doesn't read the base fee (which the chain may not have) if the fees are equal,
+                // the bundle transaction then pays the gas price
+                if user_operation.max_fee_per_gas != user_operation.max_priority_fee_per_gas {
+                    return Err(BadUserOperationError::LegacyGasPrice {
+                        max_fee_per_gas: user_operation.max_fee_per_gas,
+                        max_priority_fee_per_gas: user_operation.max_priority_fee_per_gas,
+                    });
+                }
+                let gas_price = self
+                    .eth_provider
+                    .get_gas_price()
+                    .await
+                    .map_err(|error| BadUserOperationError::Middleware(error))?;
+                if gas_price > user_operation.max_fee_per_gas {
+                    return Err(BadUserOperationError::LowMaxFeePerGas {
+                        max_fee_per_gas: user_operation.max_fee_per_gas,
+                        max_fee_per_gas_estimated: gas_price,
+                    });
+                }
             }
-        } else {
-            return Err(BadUserOperationError::UnknownError {
-                error: "Can't get base fee per gas".to_string(),
-            });
-        };
-
-        if base_fee_per_gas + user_operation.max_priority_fee_per_gas
-            > user_operation.max_fee_per_gas
-        {
-            return Err(BadUserOperationError::LowMaxFeePerGas {
-                max_fee_per_gas: user_operation.max_fee_per_gas,
-                max_fee_per_gas_estimated: base_fee_per_gas
-                    + user_operation.max_priority_fee_per_gas,
-            });
         }
 
         if user_operation.max_priority_fee_per_gas < self.min_priority_fee_per_gas {
@@ -403,9 +498,7 @@ impl<M: Middleware + 'static> UoPool<M> {
                     }),
                 ) {
                     Ok(_) => Ok(None),
-                    Err(_) => Err(BadUserOperationError::SenderVerification {
-                        sender: user_operation.sender,
-                    }),
+                    Err(error) => Err(BadUserOperationError::Reputation(error)),
                 }
             }
         }
@@ -414,9 +507,20 @@ impl<M: Middleware + 'static> UoPool<M> {
     pub async fn validate_user_operation(
         &self,
         user_operation: &UserOperation,
+    ) -> Result<SanityCheckResult, BadUserOperationError<M>> {
+        self.validate_authorized_user_operation(user_operation, None)
+            .await
+    }
+
+    /// Sanity checks of the user operation whose sender is delegated by the EIP-7702 authorization (if any)
+    pub async fn validate_authorized_user_operation(
+        &self,
+        user_operation: &UserOperation,
+        authorization: Option<&Authorization>,
     ) -> Result<SanityCheckResult, BadUserOperationError<M>> {
         // Either the sender is an existing contract, or the initCode is not empty (but not both)
-        self.sender_or_init_code(user_operation).await?;
+        self.sender_or_init_code(user_operation, authorization)
+            .await?;
 
         // The verificationGasLimit is sufficiently low (<= MAX_VERIFICATION_GAS) and the preVerificationGas is sufficiently high (enough to pay for the calldata gas cost of serializing the UserOperation plus PRE_VERIFICATION_OVERHEAD_GAS)
         self.verification_gas(user_operation)?;
@@ -437,6 +541,19 @@ impl<M: Middleware + 'static> UoPool<M> {
             user_operation_hash: user_operation_prev_hash,
         })
     }
+
+    /// Sanity checks of the user operation with a future nonce. Checks of the sender (deployment and other user
+    /// operations in the pool) are done once the user operation is promoted to the mempool.
+    pub async fn validate_queued_user_operation(
+        &self,
+        user_operation: &UserOperation,
+    ) -> Result<(), BadUserOperationError<M>> {
+        self.verification_gas(user_operation)?;
+        self.verify_paymaster(user_operation).await?;
+        self.call_gas_limit(user_operation).await?;
+        self.max_fee_per_gas(user_operation).await?;
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -447,7 +564,7 @@ mod tests {
         providers::{Http, Provider},
         types::{Address, Bytes, U256},
     };
-    use std::{str::FromStr, sync::Arc};
+    use std::{collections::HashSet, str::FromStr, sync::Arc};
 
     use crate::{
         memory::{mempool::MemoryMempool, reputation::MemoryReputation},
@@ -482,6 +599,7 @@ mod tests {
             U256::from(1500000),
             U256::from(2),
             chain_id,
+            HashSet::new(),
         );
 
         let max_priority_fee_per_gas = U256::from(1500000000_u64);
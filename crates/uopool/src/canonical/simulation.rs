@@ -1,21 +1,25 @@
 use aa_bundler_contracts::{
-    Call, CallEntry, EntryPointErr, JsTracerFrame, SimulateValidationResult,
+    get_code_hashes, Call, CallEntry, EntryPointErr, JsTracerFrame, SimulateValidationResult,
     ValidatePaymasterUserOpReturn, CONTRACTS_FUNCTIONS,
 };
 use aa_bundler_primitives::{
-    CodeHash, SimulationError, StakeInfo, UserOperation, EXECUTION_ERROR_CODE,
-    OPCODE_VALIDATION_ERROR_CODE, SIGNATURE_FAILED_ERROR_CODE, SIMULATE_VALIDATION_ERROR_CODE,
+    get_addr, CodeHash, SimulationError, StakeInfo, UserOperation, EXECUTION_ERROR_CODE,
+    EXPIRATION_ERROR_CODE, OPCODE_VALIDATION_ERROR_CODE, PAYMASTER_VALIDATION_ERROR_CODE,
+    SIGNATURE_FAILED_ERROR_CODE, SIMULATE_VALIDATION_ERROR_CODE, UNSUPPORTED_AGGREGATOR_ERROR_CODE,
 };
 use ethers::{
     abi::AbiDecode,
     providers::Middleware,
-    types::{Address, Bytes, GethTrace, H256, U256},
+    types::{spoof, Address, Bytes, GethTrace, U256},
     utils::keccak256,
 };
 use jsonrpsee::types::error::ErrorCode;
 use lazy_static::lazy_static;
-use std::collections::{HashMap, HashSet};
-use tokio::task::JoinSet;
+use serde_json::json;
+use std::{
+    collections::{HashMap, HashSet},
+    time::{SystemTime, UNIX_EPOCH},
+};
 use tracing::trace;
 
 use crate::{utils::equal_code_hashes, UoPool};
@@ -25,6 +29,16 @@ use crate::{utils::equal_code_hashes, UoPool};
 // opcode NUMBER is marker between levels
 const NUMBER_LEVELS: usize = 3;
 const LEVEL_TO_ENTITY: [&str; NUMBER_LEVELS] = ["factory", "account", "paymaster"];
+/// User operations that expire sooner than this (in seconds) are rejected, since they would likely expire before inclusion
+const EXPIRATION_MARGIN: u64 = 30;
+/// Prefix of the `FailedOp` reasons of the paymaster validation (e.g. `AA33 reverted`)
+const PAYMASTER_FAILED_OP_PREFIX: &str = "AA3";
+
+/// Whether the time range (`validAfter`, `validUntil`) of the user operation has started and doesn't end within the
+/// next `EXPIRATION_MARGIN` seconds (a zero `validUntil` never expires)
+fn is_within_time_range(valid_after: u64, valid_until: u64, now: u64) -> bool {
+    valid_after <= now && (valid_until == 0 || valid_until >= now + EXPIRATION_MARGIN)
+}
 
 lazy_static! {
     static ref FORBIDDEN_OPCODES: HashSet<String> = {
@@ -57,13 +71,71 @@ lazy_static! {
 #[derive(Debug)]
 pub enum SimulateValidationError {
     SignatureValidation {},
-    UserOperationRejected { message: String },
-    OpcodeValidation { entity: String, opcode: String },
-    UserOperationExecution { message: String },
-    StorageAccessValidation { slot: String },
-    CallStackValidation { message: String },
-    CodeHashesValidation { message: String },
-    UnknownError { error: String },
+    UserOperationRejected {
+        message: String,
+    },
+    PaymasterValidation {
+        paymaster: Address,
+        message: String,
+    },
+    Expiration {
+        valid_after: u64,
+        valid_until: u64,
+        paymaster: Option<Address>,
+    },
+    UnsupportedAggregator {
+        aggregator: Address,
+    },
+    OpcodeValidation {
+        entity: String,
+        opcode: String,
+    },
+    UserOperationExecution {
+        message: String,
+    },
+    StorageAccessValidation {
+        slot: String,
+    },
+    CallStackValidation {
+        message: String,
+    },
+    CodeHashesValidation {
+        message: String,
+    },
+    UnknownError {
+        error: String,
+    },
+}
+
+impl SimulateValidationError {
+    /// Class of the error, the label of the simulation failure metrics
+    pub fn kind(&self) -> &'static str {
+        match self {
+            SimulateValidationError::SignatureValidation {} => "signature",
+            SimulateValidationError::UserOperationRejected { .. } => "rejected",
+            SimulateValidationError::PaymasterValidation { .. } => "paymaster",
+            SimulateValidationError::Expiration { .. } => "expiration",
+            SimulateValidationError::UnsupportedAggregator { .. } => "unsupported_aggregator",
+            SimulateValidationError::OpcodeValidation { .. } => "opcode",
+            SimulateValidationError::UserOperationExecution { .. } => "execution",
+            SimulateValidationError::StorageAccessValidation { .. } => "storage_access",
+            SimulateValidationError::CallStackValidation { .. } => "call_stack",
+            SimulateValidationError::CodeHashesValidation { .. } => "code_hashes",
+            SimulateValidationError::UnknownError { error } if error.contains("timeout") => {
+                "timeout"
+            }
+            SimulateValidationError::UnknownError { .. } => "unknown",
+        }
+    }
+
+    /// Entity whose validation failed, if the error is specific to one
+    pub fn entity(&self) -> Option<&str> {
+        match self {
+            SimulateValidationError::PaymasterValidation { .. } => Some("paymaster"),
+            SimulateValidationError::OpcodeValidation { entity, .. } => Some(entity),
+            _ => None,
+        }
+    }
 }
 
 impl From<SimulateValidationError> for SimulationError {
@@ -77,6 +149,44 @@ impl From<SimulateValidationError> for SimulationError {
             SimulateValidationError::UserOperationRejected { message } => {
                 SimulationError::owned(SIMULATE_VALIDATION_ERROR_CODE, message, None::<bool>)
             }
+            SimulateValidationError::PaymasterValidation { paymaster, message } => {
+                SimulationError::owned(
+                    PAYMASTER_VALIDATION_ERROR_CODE,
+                    message,
+                    Some(json!({
+                        "paymaster": paymaster,
+                    })),
+                )
+            }
+            SimulateValidationError::Expiration {
+                valid_after,
+                valid_until,
+                paymaster,
+            } => {
+                let mut data = json!({
+                    "validAfter": valid_after,
+                    "validUntil": valid_until,
+                });
+                if let Some(paymaster) = paymaster {
+                    data["paymaster"] = json!(paymaster);
+                }
+                SimulationError::owned(
+                    EXPIRATION_ERROR_CODE,
+                    format!(
+                        "User operation is outside of its time range (valid after {valid_after}, valid until {valid_until})"
+                    ),
+                    Some(data),
+                )
+            }
+            SimulateValidationError::UnsupportedAggregator { aggregator } => {
+                SimulationError::owned(
+                    UNSUPPORTED_AGGREGATOR_ERROR_CODE,
+                    format!("Signature aggregator {aggregator} is not supported"),
+                    Some(json!({
+                        "aggregator": aggregator,
+                    })),
+                )
+            }
             SimulateValidationError::OpcodeValidation { entity, opcode } => SimulationError::owned(
                 OPCODE_VALIDATION_ERROR_CODE,
                 format!("{entity} uses banned opcode: {opcode}"),
@@ -109,22 +219,35 @@ pub struct SimulationResult {
     pub code_hashes: Vec<CodeHash>,
 }
 
+impl SimulationResult {
+    /// `validUntil` of the validation (0 if the user operation never expires)
+    pub fn valid_until(&self) -> u64 {
+        match &self.simulate_validation_result {
+            SimulateValidationResult::ValidationResult(validation_result) => {
+                validation_result.return_info.4
+            }
+            SimulateValidationResult::ValidationResultWithAggregation(
+                validation_result_with_aggregation,
+            ) => validation_result_with_aggregation.return_info.4,
+        }
+    }
+}
+
 impl<M: Middleware + 'static> UoPool<M> {
     async fn simulate_validation(
         &self,
         user_operation: &UserOperation,
+        state_override: Option<&spoof::State>,
     ) -> Result<SimulateValidationResult, SimulateValidationError> {
         match self
             .entry_point
-            .simulate_validation(user_operation.clone())
+            .simulate_validation(user_operation.clone(), state_override)
             .await
         {
             Ok(simulate_validation_result) => Ok(simulate_validation_result),
             Err(entry_point_error) => match entry_point_error {
                 EntryPointErr::FailedOp(failed_op) => {
-                    Err(SimulateValidationError::UserOperationRejected {
-                        message: format!("{failed_op}"),
-                    })
+                    Err(Self::failed_op_error(user_operation, failed_op.reason))
                 }
                 _ => Err(SimulateValidationError::UserOperationRejected {
                     message: "unknown error".to_string(),
@@ -136,18 +259,21 @@ impl<M: Middleware + 'static> UoPool<M> {
     async fn simulate_validation_trace(
         &self,
         user_operation: &UserOperation,
+        state_override: Option<&spoof::State>,
     ) -> Result<GethTrace, SimulateValidationError> {
         match self
             .entry_point
-            .simulate_validation_trace(user_operation.clone())
+            .simulate_validation_trace(user_operation.clone(), state_override)
             .await
         {
             Ok(geth_trace) => Ok(geth_trace),
             Err(entry_point_error) => match entry_point_error {
                 EntryPointErr::FailedOp(failed_op) => {
-                    Err(SimulateValidationError::UserOperationRejected {
-                        message: format!("{failed_op}"),
-                    })
+                    Err(Self::failed_op_error(user_operation, failed_op.reason))
+                }
+                // the tracer was interrupted by the node (e.g. `execution timeout`)
+                EntryPointErr::JsonRpcError(err) if err.message.contains("timeout") => {
+                    Err(SimulateValidationError::UnknownError { error: err.message })
                 }
                 _ => Err(SimulateValidationError::UserOperationRejected {
                     message: "unknown error".to_string(),
@@ -156,6 +282,20 @@ impl<M: Middleware + 'static> UoPool<M> {
         }
     }
 
+    /// Rejection of the user operation by the entry point, the failures of the paymaster validation (`AA3x`)
+    /// carry the paymaster address
+    fn failed_op_error(user_operation: &UserOperation, reason: String) -> SimulateValidationError {
+        match get_addr(&user_operation.paymaster_and_data) {
+            Some(paymaster) if reason.starts_with(PAYMASTER_FAILED_OP_PREFIX) => {
+                SimulateValidationError::PaymasterValidation {
+                    paymaster,
+                    message: reason,
+                }
+            }
+            _ => SimulateValidationError::UserOperationRejected { message: reason },
+        }
+    }
+
     fn signature(
         &self,
         simulate_validation_result: &SimulateValidationResult,
@@ -176,6 +316,59 @@ impl<M: Middleware + 'static> UoPool<M> {
         Ok(())
     }
 
+    /// The user operation has to be valid already and must not expire within the next `EXPIRATION_MARGIN` seconds
+    fn expiration(
+        &self,
+        user_operation: &UserOperation,
+        simulate_validation_result: &SimulateValidationResult,
+    ) -> Result<(), SimulateValidationError> {
+        let (valid_after, valid_until) = match simulate_validation_result {
+            SimulateValidationResult::ValidationResult(validation_result) => (
+                validation_result.return_info.3,
+                validation_result.return_info.4,
+            ),
+            SimulateValidationResult::ValidationResultWithAggregation(
+                validation_result_with_aggregation,
+            ) => (
+                validation_result_with_aggregation.return_info.3,
+                validation_result_with_aggregation.return_info.4,
+            ),
+        };
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or_default();
+        if !is_within_time_range(valid_after, valid_until, now) {
+            return Err(SimulateValidationError::Expiration {
+                valid_after,
+                valid_until,
+                paymaster: get_addr(&user_operation.paymaster_and_data),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// User operations with a signature aggregator are only accepted if the aggregator is supported (any aggregator if
+    /// the supported aggregators aren't set)
+    fn aggregator(
+        &self,
+        simulate_validation_result: &SimulateValidationResult,
+    ) -> Result<(), SimulateValidationError> {
+        if let SimulateValidationResult::ValidationResultWithAggregation(
+            validation_result_with_aggregation,
+        ) = simulate_validation_result
+        {
+            let aggregator = validation_result_with_aggregation.aggregator_info.0;
+            if !self.aggregators.is_empty() && !self.aggregators.contains(&aggregator) {
+                return Err(SimulateValidationError::UnsupportedAggregator { aggregator });
+            }
+        }
+
+        Ok(())
+    }
+
     fn extract_stake_info(
         &self,
         user_operation: &UserOperation,
@@ -490,32 +683,17 @@ impl<M: Middleware + 'static> UoPool<M> {
         contract_addresses: Vec<Address>,
         code_hashes: &mut Vec<CodeHash>,
     ) -> Result<(), SimulateValidationError> {
-        let mut tasks: JoinSet<Option<(Address, H256)>> = JoinSet::new();
-
-        for contract_address in contract_addresses {
-            let eth_provider = self.eth_provider.clone();
-
-            tasks.spawn(async move {
-                match eth_provider.get_code(contract_address, None).await {
-                    Ok(code) => Some((contract_address, keccak256(&code).into())),
-                    Err(_) => None,
-                }
-            });
-        }
-
-        while let Some(result) = tasks.join_next().await {
-            match result {
-                Ok(Some(code_hash)) => code_hashes.push(CodeHash {
-                    address: code_hash.0,
-                    hash: code_hash.1,
-                }),
-                Ok(None) | Err(_) => {
-                    return Err(SimulateValidationError::UnknownError {
-                        error: "failed to get code hashes".to_string(),
-                    });
-                }
-            }
-        }
+        let hashes = get_code_hashes(self.eth_provider.as_ref(), &contract_addresses)
+            .await
+            .map_err(|e| SimulateValidationError::UnknownError {
+                error: format!("failed to get code hashes: {e:?}"),
+            })?;
+        code_hashes.extend(
+            contract_addresses
+                .into_iter()
+                .zip(hashes)
+                .map(|(address, hash)| CodeHash { address, hash }),
+        );
 
         Ok(())
     }
@@ -565,16 +743,47 @@ impl<M: Middleware + 'static> UoPool<M> {
         }
     }
 
+    /// Counts the failed simulation in `uopool_simulation_failures` (`entry_point`, `kind`, `entity`)
+    pub fn record_simulation_failure(&self, error: &SimulateValidationError) {
+        metrics::counter!(
+            "uopool_simulation_failures",
+            1,
+            "entry_point" => format!("{:?}", self.entry_point.address()),
+            "kind" => error.kind(),
+            "entity" => error.entity().unwrap_or_default().to_string()
+        );
+    }
+
+    /// Simulates the validation of the user operation, the state override is used for the gas estimation and for the
+    /// EIP-7702 delegation of the sender (otherwise the user operations are simulated against the actual state)
     pub async fn simulate_user_operation(
         &self,
         user_operation: &UserOperation,
+        state_override: Option<&spoof::State>,
     ) -> Result<SimulationResult, SimulateValidationError> {
-        let simulate_validation_result = self.simulate_validation(user_operation).await?;
+        let simulate_validation_result = self
+            .simulate_validation(user_operation, state_override)
+            .await?;
 
         // check signature
         self.signature(&simulate_validation_result)?;
 
-        let geth_trace = self.simulate_validation_trace(user_operation).await?;
+        // check expiration
+        self.expiration(user_operation, &simulate_validation_result)?;
+
+        // check signature aggregator
+        self.aggregator(&simulate_validation_result)?;
+
+        if !self.trace_validation {
+            return Ok(SimulationResult {
+                simulate_validation_result,
+                code_hashes: vec![],
+            });
+        }
+
+        let geth_trace = self
+            .simulate_validation_trace(user_operation, state_override)
+            .await?;
 
         trace!("Simulate user operation {user_operation:?} with trace {geth_trace:?}");
 
@@ -609,3 +818,86 @@ impl<M: Middleware + 'static> UoPool<M> {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn simulate_validation_error_codes() {
+        let paymaster = Address::random();
+
+        let error = SimulationError::from(SimulateValidationError::PaymasterValidation {
+            paymaster,
+            message: "AA33 reverted".to_string(),
+        });
+        assert_eq!(error.code(), PAYMASTER_VALIDATION_ERROR_CODE);
+        assert_eq!(error.message(), "AA33 reverted");
+        assert_eq!(
+            error.data().map(|data| data.get()),
+            Some(json!({ "paymaster": paymaster }).to_string().as_str())
+        );
+
+        let error = SimulationError::from(SimulateValidationError::Expiration {
+            valid_after: 1,
+            valid_until: 2,
+            paymaster: None,
+        });
+        assert_eq!(error.code(), EXPIRATION_ERROR_CODE);
+        assert_eq!(
+            error.data().map(|data| data.get()),
+            Some(
+                json!({ "validAfter": 1, "validUntil": 2 })
+                    .to_string()
+                    .as_str()
+            )
+        );
+
+        let aggregator = Address::random();
+        let error =
+            SimulationError::from(SimulateValidationError::UnsupportedAggregator { aggregator });
+        assert_eq!(error.code(), UNSUPPORTED_AGGREGATOR_ERROR_CODE);
+        assert_eq!(
+            error.data().map(|data| data.get()),
+            Some(json!({ "aggregator": aggregator }).to_string().as_str())
+        );
+    }
+
+    #[test]
+    fn time_range() {
+        let now = 1_700_000_000;
+        assert!(is_within_time_range(0, 0, now));
+        assert!(is_within_time_range(now, now + EXPIRATION_MARGIN, now));
+        assert!(!is_within_time_range(0, now + EXPIRATION_MARGIN - 1, now));
+        // not valid yet
+        assert!(!is_within_time_range(now + 1, 0, now));
+    }
+
+    #[test]
+    fn simulate_validation_error_kinds() {
+        let error = SimulateValidationError::OpcodeValidation {
+            entity: "factory".to_string(),
+            opcode: "GASPRICE".to_string(),
+        };
+        assert_eq!((error.kind(), error.entity()), ("opcode", Some("factory")));
+
+        let error = SimulateValidationError::PaymasterValidation {
+            paymaster: Address::random(),
+            message: "AA33 reverted".to_string(),
+        };
+        assert_eq!(
+            (error.kind(), error.entity()),
+            ("paymaster", Some("paymaster"))
+        );
+
+        let error = SimulateValidationError::StorageAccessValidation {
+            slot: "0x00".to_string(),
+        };
+        assert_eq!((error.kind(), error.entity()), ("storage_access", None));
+
+        let error = SimulateValidationError::UnknownError {
+            error: "execution timeout".to_string(),
+        };
+        assert_eq!((error.kind(), error.entity()), ("timeout", None));
+    }
+}
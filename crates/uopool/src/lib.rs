@@ -1,8 +1,10 @@
 #![allow(dead_code)]
 
 mod database;
+mod logs;
 mod memory;
 mod mempool;
+mod rejections;
 mod reputation;
 mod uopool;
 mod utils;
@@ -10,9 +12,10 @@ mod utils;
 pub use database::mempool::DatabaseMempool;
 pub use memory::{mempool::MemoryMempool, reputation::MemoryReputation};
 pub use mempool::{mempool_id, MempoolId};
+pub use rejections::RejectionLog;
 pub use reputation::Reputation;
-pub use uopool::UoPool;
-pub use utils::Overhead;
+pub use uopool::{Readiness, UoPool, UoPoolEvent, UserOperationStatus};
+pub use utils::{required_prefund, Overhead};
 
 // canonical mempool
 pub mod canonical;
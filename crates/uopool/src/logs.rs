@@ -0,0 +1,192 @@
+use std::{future::Future, sync::Mutex};
+
+use ethers::{
+    providers::Middleware,
+    types::{Address, U64},
+};
+use tracing::trace;
+
+/// Block range of the first `eth_getLogs` request
+const INITIAL_CHUNK_SIZE: u64 = 1000;
+/// Limits of the block range of the `eth_getLogs` requests
+const MIN_CHUNK_SIZE: u64 = 8;
+const MAX_CHUNK_SIZE: u64 = 100_000;
+
+/// Block range of the `eth_getLogs` requests and its limit learned from the failed requests
+#[derive(Clone, Copy)]
+struct ChunkSize {
+    size: u64,
+    limit: u64,
+}
+
+/// Scans the logs of a contract backwards from the latest block in chunks, so that the providers with strict
+/// `eth_getLogs` range limits (e.g. the public RPCs) can be used. The chunk size adapts to the provider: it's halved
+/// when the request fails (range too large, too many results), which also limits it from then on, and doubled after
+/// a successful one.
+pub struct LogScanner {
+    chunk_size: Mutex<ChunkSize>,
+    /// Block in which the contract was deployed, the scans stop there (`None` until searched, `Some(None)` if the
+    /// provider has no historical state)
+    deployment_block: Mutex<Option<Option<U64>>>,
+}
+
+impl Default for LogScanner {
+    fn default() -> Self {
+        Self {
+            chunk_size: Mutex::new(ChunkSize {
+                size: INITIAL_CHUNK_SIZE,
+                limit: MAX_CHUNK_SIZE,
+            }),
+            deployment_block: Mutex::new(None),
+        }
+    }
+}
+
+impl LogScanner {
+    fn chunk_size(&self) -> u64 {
+        self.chunk_size
+            .lock()
+            .expect("chunk size lock poisoned")
+            .size
+    }
+
+    fn grow_chunk_size(&self) {
+        let mut chunk_size = self.chunk_size.lock().expect("chunk size lock poisoned");
+        chunk_size.size = (chunk_size.size * 2).min(chunk_size.limit);
+    }
+
+    fn shrink_chunk_size(&self, failed_size: u64) {
+        let mut chunk_size = self.chunk_size.lock().expect("chunk size lock poisoned");
+        chunk_size.limit = (failed_size / 2).max(MIN_CHUNK_SIZE);
+        chunk_size.size = chunk_size.size.min(chunk_size.limit);
+    }
+
+    /// Scans from the latest block down to the first block, returns the logs of the most recent chunk that has any
+    pub async fn scan<T, E, F, Fut>(
+        &self,
+        first_block: U64,
+        latest_block: U64,
+        mut get_logs: F,
+    ) -> Result<Vec<T>, E>
+    where
+        E: std::fmt::Debug,
+        F: FnMut(U64, U64) -> Fut,
+        Fut: Future<Output = Result<Vec<T>, E>>,
+    {
+        let mut to_block = latest_block;
+        loop {
+            let chunk_size = self.chunk_size();
+            let from_block = to_block
+                .saturating_sub(U64::from(chunk_size - 1))
+                .max(first_block);
+            match get_logs(from_block, to_block).await {
+                Ok(logs) => {
+                    self.grow_chunk_size();
+                    if !logs.is_empty() || from_block <= first_block {
+                        return Ok(logs);
+                    }
+                    to_block = from_block - 1;
+                }
+                Err(e) if chunk_size > MIN_CHUNK_SIZE => {
+                    trace!("Getting logs of blocks {from_block}-{to_block} failed, shrinking the range: {e:?}");
+                    self.shrink_chunk_size(chunk_size);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Block in which the contract was deployed, found by the binary search of its code at the historical blocks
+    /// (once). `None` if the provider doesn't serve the historical state.
+    pub async fn deployment_block<M: Middleware>(
+        &self,
+        provider: &M,
+        address: Address,
+        latest_block: U64,
+    ) -> Option<U64> {
+        if let Some(deployment_block) = *self
+            .deployment_block
+            .lock()
+            .expect("deployment block lock poisoned")
+        {
+            return deployment_block;
+        }
+
+        let mut low = U64::zero();
+        let mut high = latest_block;
+        let mut deployment_block = None;
+        while low < high {
+            let mid = (low + high) / 2;
+            match provider.get_code(address, Some(mid.into())).await {
+                Ok(code) if code.is_empty() => low = mid + 1,
+                Ok(_) => high = mid,
+                Err(e) => {
+                    trace!("Getting the code at block {mid} failed, the historical state is not available: {e:?}");
+                    break;
+                }
+            }
+        }
+        if low == high {
+            deployment_block = Some(low);
+        }
+        *self
+            .deployment_block
+            .lock()
+            .expect("deployment block lock poisoned") = Some(deployment_block);
+        deployment_block
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn adaptive_chunking() {
+        // provider that limits the range to 100 blocks, with the log at block 5000
+        let requests = Mutex::new(vec![]);
+        let get_logs = |from_block: U64, to_block: U64| {
+            requests.lock().unwrap().push((from_block, to_block));
+            async move {
+                if to_block - from_block >= U64::from(100) {
+                    return Err("block range is too large");
+                }
+                Ok(
+                    if from_block <= U64::from(5000) && U64::from(5000) <= to_block {
+                        vec![5000]
+                    } else {
+                        vec![]
+                    },
+                )
+            }
+        };
+
+        let scanner = LogScanner::default();
+        assert_eq!(
+            scanner
+                .scan(U64::from(1000), U64::from(10_000), get_logs)
+                .await,
+            Ok(vec![5000])
+        );
+        let requests = requests.into_inner().unwrap();
+        // the ranges are contiguous and never above the limit after it's found
+        assert!(requests
+            .windows(2)
+            .all(|pair| pair[1].1 == pair[0].1 || pair[1].1 + 1 == pair[0].0));
+        assert!(requests
+            .iter()
+            .skip_while(|(from, to)| to - from >= U64::from(100))
+            .all(|(from, to)| to - from < U64::from(100)));
+        assert_eq!(scanner.chunk_size(), 62);
+
+        // nothing found down to the first block
+        assert_eq!(
+            scanner
+                .scan(U64::from(9000), U64::from(10_000), |_, _| async {
+                    Ok::<Vec<u64>, ()>(vec![])
+                })
+                .await,
+            Ok(vec![])
+        );
+    }
+}
@@ -131,6 +131,10 @@ impl Mempool for MemoryMempool {
         self.user_operations.values().cloned().collect()
     }
 
+    fn size(&self) -> usize {
+        self.user_operations.len()
+    }
+
     fn clear(&mut self) {
         self.user_operations.clear();
         self.user_operations_by_sender.clear();
@@ -142,6 +146,8 @@ impl Mempool for MemoryMempool {
 mod tests {
     use super::*;
     use crate::utils::tests::mempool_test_case;
+    use arbitrary::{Arbitrary, Unstructured};
+    use proptest::prelude::*;
 
     #[allow(clippy::unit_cmp)]
     #[tokio::test]
@@ -149,4 +155,44 @@ mod tests {
         let mempool = MemoryMempool::default();
         mempool_test_case(mempool, "User operation not found");
     }
+
+    proptest! {
+        #[test]
+        fn memory_mempool_add_remove(data in any::<Vec<u8>>()) {
+            let mut u = Unstructured::new(&data);
+            let (Ok(user_operations), Ok(code_hashes)) = (
+                Vec::<UserOperation>::arbitrary(&mut u),
+                Vec::<CodeHash>::arbitrary(&mut u),
+            ) else {
+                return Ok(());
+            };
+            let entry_point = Address::repeat_byte(1);
+            let chain_id = U256::from(1);
+
+            let mut mempool = MemoryMempool::default();
+            let mut hashes = HashSet::new();
+            for user_operation in user_operations.iter() {
+                let hash = mempool
+                    .add(user_operation.clone(), &entry_point, &chain_id)
+                    .unwrap();
+                mempool.set_code_hashes(&hash, &code_hashes).unwrap();
+                prop_assert_eq!(hash, user_operation.hash(&entry_point, &chain_id));
+                hashes.insert(hash);
+            }
+            prop_assert_eq!(mempool.size(), hashes.len());
+
+            for hash in hashes.iter() {
+                let user_operation = mempool.get(hash).unwrap().unwrap();
+                prop_assert_eq!(user_operation.hash(&entry_point, &chain_id), *hash);
+                mempool.remove(hash).unwrap();
+                prop_assert!(mempool.get(hash).unwrap().is_none());
+                prop_assert!(mempool.get_code_hashes(hash).is_empty());
+                prop_assert!(mempool.remove(hash).is_err());
+            }
+            prop_assert_eq!(mempool.size(), 0);
+            for user_operation in user_operations.iter() {
+                prop_assert_eq!(mempool.get_number_by_sender(&user_operation.sender), 0);
+            }
+        }
+    }
 }
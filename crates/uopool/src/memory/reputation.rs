@@ -52,6 +52,17 @@ impl Reputation for MemoryReputation {
         self.min_unstake_delay = min_unstake_delay;
     }
 
+    fn set_throttling(
+        &mut self,
+        min_inclusion_denominator: u64,
+        throttling_slack: u64,
+        ban_slack: u64,
+    ) {
+        self.min_inclusion_denominator = min_inclusion_denominator;
+        self.throttling_slack = throttling_slack;
+        self.ban_slack = ban_slack;
+    }
+
     fn get(&mut self, address: &Address) -> ReputationEntry {
         if let Some(entity) = self.entities.get(address) {
             return *entity;
@@ -116,6 +127,11 @@ impl Reputation for MemoryReputation {
         self.blacklist.contains(address)
     }
 
+    fn set_lists(&mut self, whitelist: &[Address], blacklist: &[Address]) {
+        self.whitelist = whitelist.iter().copied().collect();
+        self.blacklist = blacklist.iter().copied().collect();
+    }
+
     fn get_status(&self, address: &Address) -> ReputationStatus {
         if self.is_whitelist(address) {
             return ReputationStatus::OK;
@@ -288,5 +304,14 @@ mod tests {
             reputation.get_status(&addresses[3]),
             ReputationStatus::BANNED
         );
+
+        reputation.set_lists(&[addresses[3]], &[addresses[4]]);
+        assert_eq!(reputation.is_whitelist(&addresses[2]), false);
+        assert_eq!(reputation.is_blacklist(&addresses[1]), false);
+        assert_eq!(reputation.get_status(&addresses[3]), ReputationStatus::OK);
+        assert_eq!(
+            reputation.get_status(&addresses[4]),
+            ReputationStatus::BANNED
+        );
     }
 }
@@ -48,5 +48,9 @@ pub trait Mempool: Debug {
     // Get UserOperations sorted by max_priority_fee_per_gas without dup sender
     fn get_sorted(&self) -> Result<Self::UserOperations, Self::Error>;
     fn get_all(&self) -> Self::UserOperations;
+    /// Number of the user operations in the mempool
+    fn size(&self) -> usize {
+        self.get_all().into_iter().count()
+    }
     fn clear(&mut self);
 }
@@ -0,0 +1,95 @@
+use std::collections::VecDeque;
+
+use aa_bundler_primitives::{RejectedUserOperation, UserOperationHash};
+use ethers::types::Address;
+
+/// Most recent user operations rejected by the uopool (the oldest records are removed first)
+#[derive(Debug)]
+pub struct RejectionLog {
+    records: VecDeque<RejectedUserOperation>,
+    max_size: usize,
+}
+
+impl RejectionLog {
+    pub fn new(max_size: usize) -> Self {
+        Self {
+            records: VecDeque::new(),
+            max_size,
+        }
+    }
+
+    pub fn add(&mut self, record: RejectedUserOperation) {
+        if self.max_size == 0 {
+            return;
+        }
+        while self.records.len() >= self.max_size {
+            self.records.pop_front();
+        }
+        self.records.push_back(record);
+    }
+
+    /// Rejections of the user operation and of the sender (all of them if not set), the newest first
+    pub fn get(
+        &self,
+        user_operation_hash: Option<UserOperationHash>,
+        sender: Option<Address>,
+    ) -> Vec<RejectedUserOperation> {
+        self.records
+            .iter()
+            .rev()
+            .filter(|record| {
+                user_operation_hash.map_or(true, |hash| record.user_operation_hash == hash)
+                    && sender.map_or(true, |sender| record.sender == sender)
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::types::H256;
+
+    fn record(hash: u64, sender: u64) -> RejectedUserOperation {
+        RejectedUserOperation {
+            user_operation_hash: H256::from_low_u64_be(hash).into(),
+            entry_point: Address::zero(),
+            sender: Address::from_low_u64_be(sender),
+            factory: None,
+            paymaster: None,
+            reason: "sanity_check".to_string(),
+            code: -32602,
+            message: "Invalid".to_string(),
+            rejected_at: hash,
+        }
+    }
+
+    #[test]
+    fn rejection_log() {
+        let mut log = RejectionLog::new(3);
+        for (hash, sender) in [(1, 1), (2, 2), (3, 1), (4, 1)] {
+            log.add(record(hash, sender));
+        }
+
+        assert_eq!(
+            log.get(None, None),
+            vec![record(4, 1), record(3, 1), record(2, 2)]
+        );
+        assert_eq!(
+            log.get(None, Some(Address::from_low_u64_be(1))),
+            vec![record(4, 1), record(3, 1)]
+        );
+        assert_eq!(
+            log.get(Some(H256::from_low_u64_be(2).into()), None),
+            vec![record(2, 2)]
+        );
+        assert!(log
+            .get(Some(H256::from_low_u64_be(1).into()), None)
+            .is_empty());
+
+        let mut log = RejectionLog::new(0);
+        log.add(record(1, 1));
+        assert!(log.get(None, None).is_empty());
+    }
+}
@@ -19,6 +19,13 @@ pub trait Reputation: Debug {
         min_stake: U256,
         min_unstake_delay: U256,
     );
+    /// Changes the throttling and banning parameters, keeping the reputation entries
+    fn set_throttling(
+        &mut self,
+        min_inclusion_denominator: u64,
+        throttling_slack: u64,
+        ban_slack: u64,
+    );
     fn get(&mut self, address: &Address) -> ReputationEntry;
     fn increment_seen(&mut self, address: &Address);
     fn increment_included(&mut self, address: &Address);
@@ -29,6 +36,8 @@ pub trait Reputation: Debug {
     fn add_blacklist(&mut self, address: &Address) -> bool;
     fn remove_blacklist(&mut self, address: &Address) -> bool;
     fn is_blacklist(&self, address: &Address) -> bool;
+    /// Replaces the whitelisted and the blacklisted entities
+    fn set_lists(&mut self, whitelist: &[Address], blacklist: &[Address]);
     fn get_status(&self, address: &Address) -> ReputationStatus;
     fn update_handle_ops_reverted(&mut self, address: &Address);
     fn verify_stake(
@@ -1,24 +1,91 @@
-use std::sync::Arc;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
-use aa_bundler_contracts::{EntryPoint, UserOperationEventFilter};
-use aa_bundler_primitives::{CodeHash, ReputationEntry, UserOperation, UserOperationHash};
+use aa_bundler_contracts::{EntryPoint, EntryPointAPIEvents, UserOperationEventFilter};
+use aa_bundler_primitives::{
+    get_addr, Authorization, ChainSpec, CodeHash, ReputationEntry, SanityCheckError, UserOperation,
+    UserOperationHash, SANITY_CHECK_ERROR_CODE,
+};
 use ethers::{
+    contract::EthLogDecode,
     prelude::LogMeta,
     providers::Middleware,
-    types::{Address, H256, U256},
+    types::{spoof, Address, H256, U256},
 };
-use jsonrpsee::types::ErrorObject;
-use tracing::warn;
+use jsonrpsee::types::{error::ErrorCode, ErrorObject};
+use tokio::sync::broadcast;
+use tracing::{info_span, trace, warn, Instrument};
 
 use crate::{
     canonical::{sanity_check::SanityCheckResult, simulation::SimulationResult},
+    logs::LogScanner,
     mempool::MempoolBox,
     reputation::ReputationBox,
+    utils::required_prefund,
 };
 
 type VecUo = Vec<UserOperation>;
 type VecCh = Vec<CodeHash>;
 
+/// Maximum number of user operations whose status is kept after they leave the mempool
+const MAX_USER_OPERATION_STATUSES: usize = 10000;
+/// Number of events buffered for the subscribers (slow subscribers miss the oldest events)
+const EVENTS_CHANNEL_CAPACITY: usize = 1000;
+/// Number of past blocks that are searched for the event of the user operation that was not included through this uopool,
+/// if the entry point deployment block is unknown (the provider has no historical state)
+const USER_OPERATION_EVENT_SCAN_DEPTH: u64 = 1000;
+
+/// Status of the user operation after it left the mempool
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum UserOperationStatus {
+    Included {
+        transaction_hash: H256,
+        success: bool,
+    },
+    Failed {
+        reason: String,
+    },
+}
+
+/// Change of the mempool that is published to the subscribers
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum UoPoolEvent {
+    /// User operation was verified and added to the mempool
+    NewUserOperation {
+        user_operation_hash: UserOperationHash,
+        user_operation: Box<UserOperation>,
+    },
+    /// User operation was included on chain (by any bundler)
+    UserOperationIncluded {
+        user_operation_hash: UserOperationHash,
+        sender: Address,
+        transaction_hash: H256,
+        success: bool,
+    },
+}
+
+/// Whether the user operation can be included in a bundle at the current block
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Readiness {
+    Ready,
+    /// Max fee per gas doesn't cover the base fee and the minimum priority fee of the uopool
+    LowMaxFeePerGas,
+    /// Deposit of the paymaster doesn't cover the prefund (left after the other user operations of the paymaster)
+    LowPaymasterDeposit,
+    /// `validUntil` of the user operation has passed
+    Expired,
+}
+
+/// User operation waiting in the queue, the oldest ones are evicted first once the queue is full
+#[derive(Clone, Debug)]
+struct QueuedUserOperation {
+    user_operation: UserOperation,
+    queued_at: Instant,
+}
+
 #[derive(Debug)]
 pub struct VerificationResult {
     pub sanity_check_result: SanityCheckResult,
@@ -33,9 +100,40 @@ pub struct UoPool<M: Middleware> {
     pub max_verification_gas: U256,
     pub min_priority_fee_per_gas: U256,
     pub chain_id: U256,
+    /// Chain specific behavior (e.g. the legacy gas pricing)
+    pub chain_spec: ChainSpec,
+    /// Signature aggregators whose user operations are accepted (any aggregator if empty)
+    pub aggregators: HashSet<Address>,
+    /// Whether the validation rules (opcodes, storage access, code hashes) are checked on the trace of the simulation,
+    /// which requires `debug_traceCall` with the JS tracers
+    pub trace_validation: bool,
+    /// Simulations that take longer are reported as slow
+    pub simulation_latency_slo: Option<Duration>,
+    /// Maximum number of the user operations in the mempool, the new user operations (but not the replacements) are
+    /// rejected once it's full
+    pub max_mempool_size: Option<usize>,
+    /// Maximum number of the queued user operations, the oldest ones are evicted to make room for the new ones
+    pub max_queued_user_operations: Option<usize>,
+    /// Maximum number of the queued user operations of a sender, its new user operations (but not the replacements)
+    /// are rejected once it's reached
+    pub max_queued_user_operations_per_sender: Option<usize>,
+    user_operation_statuses: HashMap<UserOperationHash, UserOperationStatus>,
+    user_operation_statuses_order: VecDeque<UserOperationHash>,
+    /// User operations with nonces ahead of the sender's entry point nonce, waiting for the preceding nonces
+    queued_user_operations: BTreeMap<(Address, U256), QueuedUserOperation>,
+    /// EIP-7702 authorizations of the senders, included in the bundle transaction together with their user operations
+    authorizations: HashMap<Address, Authorization>,
+    /// User operations that are kept out of the shared mempool, the dumps and the notifications until included
+    private_user_operations: HashSet<UserOperationHash>,
+    /// `validUntil` of the verified user operations (also kept while they are moved to the queue)
+    valid_until: HashMap<UserOperationHash, u64>,
+    /// Scanner of the entry point logs, for the user operations whose inclusion wasn't tracked
+    log_scanner: LogScanner,
+    events: broadcast::Sender<UoPoolEvent>,
 }
 
 impl<M: Middleware + 'static> UoPool<M> {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         entry_point: EntryPoint<M>,
         mempool: MempoolBox<VecUo, VecCh>,
@@ -44,6 +142,7 @@ impl<M: Middleware + 'static> UoPool<M> {
         max_verification_gas: U256,
         min_priority_fee_per_gas: U256,
         chain_id: U256,
+        aggregators: HashSet<Address>,
     ) -> Self {
         Self {
             entry_point,
@@ -53,18 +152,514 @@ impl<M: Middleware + 'static> UoPool<M> {
             max_verification_gas,
             min_priority_fee_per_gas,
             chain_id,
+            chain_spec: ChainSpec::from_chain_id(chain_id.as_u64()),
+            aggregators,
+            trace_validation: true,
+            simulation_latency_slo: None,
+            max_mempool_size: None,
+            max_queued_user_operations: None,
+            max_queued_user_operations_per_sender: None,
+            user_operation_statuses: HashMap::new(),
+            user_operation_statuses_order: VecDeque::new(),
+            queued_user_operations: BTreeMap::new(),
+            authorizations: HashMap::new(),
+            private_user_operations: HashSet::new(),
+            valid_until: HashMap::new(),
+            log_scanner: LogScanner::default(),
+            events: broadcast::channel(EVENTS_CHANNEL_CAPACITY).0,
+        }
+    }
+
+    /// Subscribes to the events of the mempool (new and included user operations)
+    pub fn subscribe(&self) -> broadcast::Receiver<UoPoolEvent> {
+        self.events.subscribe()
+    }
+
+    /// Verifies the user operation with a future nonce (only sanity checks, since it can't be simulated yet)
+    pub async fn verify_queued_user_operation(
+        &self,
+        user_operation: &UserOperation,
+    ) -> Result<(), ErrorObject<'static>> {
+        Ok(self.validate_queued_user_operation(user_operation).await?)
+    }
+
+    /// Holds the user operation (excluded from bundles) until its preceding nonces are included. A new user operation
+    /// (not a replacement) is rejected if its sender has the maximum number of queued user operations, and evicts the
+    /// oldest queued user operations if the queue is full.
+    pub fn queue_user_operation(
+        &mut self,
+        user_operation: UserOperation,
+    ) -> anyhow::Result<UserOperationHash> {
+        let sender = user_operation.sender;
+        if !self
+            .queued_user_operations
+            .contains_key(&(sender, user_operation.nonce))
+        {
+            if let Some(max_per_sender) = self.max_queued_user_operations_per_sender {
+                if self.queued_number_by_sender(&sender) >= max_per_sender {
+                    return Err(anyhow::anyhow!(
+                        "sender has the maximum number of queued user operations ({max_per_sender})"
+                    ));
+                }
+            }
+            if let Some(max_queued) = self.max_queued_user_operations {
+                while self.queued_user_operations.len() >= max_queued {
+                    if !self.evict_oldest_queued_user_operation() {
+                        break;
+                    }
+                }
+            }
+        }
+
+        let user_operation_hash = user_operation.hash(&self.entry_point.address(), &self.chain_id);
+        self.insert_queued_user_operation(user_operation);
+        Ok(user_operation_hash)
+    }
+
+    fn insert_queued_user_operation(&mut self, user_operation: UserOperation) {
+        self.queued_user_operations.insert(
+            (user_operation.sender, user_operation.nonce),
+            QueuedUserOperation {
+                user_operation,
+                queued_at: Instant::now(),
+            },
+        );
+    }
+
+    /// Number of the queued user operations of the sender
+    fn queued_number_by_sender(&self, sender: &Address) -> usize {
+        self.queued_user_operations
+            .range((*sender, U256::zero())..=(*sender, U256::MAX))
+            .count()
+    }
+
+    /// Drops the user operation that has been queued for the longest time, returns false if the queue is empty
+    fn evict_oldest_queued_user_operation(&mut self) -> bool {
+        let oldest = self
+            .queued_user_operations
+            .iter()
+            .min_by_key(|(_, queued)| queued.queued_at)
+            .map(|(key, _)| *key);
+        let Some((sender, nonce)) = oldest else {
+            return false;
+        };
+
+        if let Some(user_operation) = self.drop_queued_user_operation(&sender, &nonce) {
+            let user_operation_hash =
+                user_operation.hash(&self.entry_point.address(), &self.chain_id);
+            trace!("Queued user operation {user_operation_hash:?} is evicted, the queue is full");
+            self.set_user_operation_status(
+                user_operation_hash,
+                UserOperationStatus::Failed {
+                    reason: "evicted from the full queue".to_string(),
+                },
+            );
+        }
+        true
+    }
+
+    pub fn get_queued_user_operations(&self) -> Vec<UserOperation> {
+        self.queued_user_operations
+            .values()
+            .map(|queued| queued.user_operation.clone())
+            .collect()
+    }
+
+    pub fn remove_queued_user_operation(
+        &mut self,
+        sender: &Address,
+        nonce: &U256,
+    ) -> Option<UserOperation> {
+        self.queued_user_operations
+            .remove(&(*sender, *nonce))
+            .map(|queued| queued.user_operation)
+    }
+
+    /// Drops the queued user operation for good (expired, nonce used up or failed the verification), together with
+    /// its private flag and the sender's authorization if it was the sender's last user operation
+    pub fn drop_queued_user_operation(
+        &mut self,
+        sender: &Address,
+        nonce: &U256,
+    ) -> Option<UserOperation> {
+        let user_operation = self.remove_queued_user_operation(sender, nonce)?;
+        self.unset_private(&user_operation.hash(&self.entry_point.address(), &self.chain_id));
+        self.release_authorization(sender);
+        Some(user_operation)
+    }
+
+    /// User operation that is waiting in the mempool (or the queue) to be included
+    pub fn get_pending_user_operation(
+        &self,
+        user_operation_hash: &UserOperationHash,
+    ) -> Option<UserOperation> {
+        if let Ok(Some(user_operation)) = self.mempool.get(user_operation_hash) {
+            return Some(user_operation);
+        }
+        let entry_point = self.entry_point.address();
+        self.queued_user_operations
+            .values()
+            .map(|queued| &queued.user_operation)
+            .find(|uo| uo.hash(&entry_point, &self.chain_id) == *user_operation_hash)
+            .cloned()
+    }
+
+    pub fn clear_queued_user_operations(&mut self) {
+        self.queued_user_operations.clear();
+    }
+
+    /// Paymasters of the user operations in the mempool and the queue
+    pub fn get_paymasters(&self) -> HashSet<Address> {
+        self.mempool
+            .get_all()
+            .iter()
+            .chain(
+                self.queued_user_operations
+                    .values()
+                    .map(|queued| &queued.user_operation),
+            )
+            .filter_map(|user_operation| get_addr(&user_operation.paymaster_and_data))
+            .collect()
+    }
+
+    /// Whether the user operation can be bundled with the base fee of the current block and the deposits of the
+    /// paymasters (the paymasters without the known deposit are not checked)
+    pub fn readiness(
+        &self,
+        user_operation: &UserOperation,
+        base_fee_per_gas: U256,
+        paymaster_deposits: &HashMap<Address, U256>,
+    ) -> Readiness {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or_default();
+        let user_operation_hash = user_operation.hash(&self.entry_point.address(), &self.chain_id);
+        let valid_until = self
+            .valid_until
+            .get(&user_operation_hash)
+            .copied()
+            .unwrap_or_default();
+        if valid_until != 0 && valid_until <= now {
+            return Readiness::Expired;
+        }
+
+        // the priority fee is capped by the max fee, so it only has to leave the minimum priority fee over the base fee
+        if base_fee_per_gas.saturating_add(self.min_priority_fee_per_gas)
+            > user_operation.max_fee_per_gas
+        {
+            return Readiness::LowMaxFeePerGas;
+        }
+
+        match get_addr(&user_operation.paymaster_and_data)
+            .and_then(|paymaster| paymaster_deposits.get(&paymaster))
+        {
+            Some(deposit) if required_prefund(user_operation) > *deposit => {
+                Readiness::LowPaymasterDeposit
+            }
+            _ => Readiness::Ready,
+        }
+    }
+
+    /// Re-checks the user operations in the mempool at the new block: the expired ones are dropped, the ones that
+    /// can't be bundled at the moment (fee below the base fee, paymaster deposit used up) are moved to the queue
+    /// until they are ready again. The prefunds of the ready user operations (the higher priority fees first) are
+    /// subtracted from the paymaster deposits. Returns the number of the dropped and the queued user operations.
+    pub fn revalidate_user_operations(
+        &mut self,
+        base_fee_per_gas: U256,
+        paymaster_deposits: &mut HashMap<Address, U256>,
+    ) -> (usize, usize) {
+        let user_operations = self
+            .mempool
+            .get_sorted()
+            .unwrap_or_else(|_| self.mempool.get_all());
+        let entry_point = self.entry_point.address();
+        let (mut dropped, mut queued) = (0, 0);
+        for user_operation in user_operations {
+            let user_operation_hash = user_operation.hash(&entry_point, &self.chain_id);
+            match self.readiness(&user_operation, base_fee_per_gas, paymaster_deposits) {
+                Readiness::Ready => {
+                    if let Some(deposit) = get_addr(&user_operation.paymaster_and_data)
+                        .and_then(|paymaster| paymaster_deposits.get_mut(&paymaster))
+                    {
+                        *deposit = deposit.saturating_sub(required_prefund(&user_operation));
+                    }
+                }
+                Readiness::Expired => {
+                    self.remove_user_operation(&user_operation_hash);
+                    self.set_user_operation_status(
+                        user_operation_hash,
+                        UserOperationStatus::Failed {
+                            reason: "expired".to_string(),
+                        },
+                    );
+                    dropped += 1;
+                }
+                readiness => {
+                    trace!(
+                        "User operation {user_operation_hash:?} is moved to the queue: {readiness:?}"
+                    );
+                    // the private flag and the validity are kept for the promotion
+                    self.mempool.remove(&user_operation_hash).ok();
+                    self.insert_queued_user_operation(user_operation);
+                    queued += 1;
+                }
+            }
+        }
+
+        // forget the validity of the user operations that left both the mempool and the queue
+        let mut pending: HashSet<UserOperationHash> = self
+            .queued_user_operations
+            .values()
+            .map(|queued| queued.user_operation.hash(&entry_point, &self.chain_id))
+            .collect();
+        pending.extend(
+            self.mempool
+                .get_all()
+                .iter()
+                .map(|user_operation| user_operation.hash(&entry_point, &self.chain_id)),
+        );
+        self.valid_until
+            .retain(|user_operation_hash, _| pending.contains(user_operation_hash));
+
+        (dropped, queued)
+    }
+
+    /// Removes the user operations (the queued ones as well) that involve the entity as the sender, the paymaster or
+    /// the factory, or all of them if the entity is not set. Returns the number of removed user operations.
+    pub fn remove_user_operations_by_entity(&mut self, entity: Option<&Address>) -> usize {
+        let involves = |user_operation: &UserOperation| match entity {
+            Some(entity) => {
+                user_operation.sender == *entity
+                    || get_addr(user_operation.paymaster_and_data.0.as_ref()) == Some(*entity)
+                    || get_addr(user_operation.init_code.0.as_ref()) == Some(*entity)
+            }
+            None => true,
+        };
+
+        let entry_point = self.entry_point.address();
+        let user_operation_hashes: Vec<UserOperationHash> = self
+            .mempool
+            .get_all()
+            .into_iter()
+            .filter(|user_operation| involves(user_operation))
+            .map(|user_operation| user_operation.hash(&entry_point, &self.chain_id))
+            .collect();
+        for user_operation_hash in user_operation_hashes.iter() {
+            self.remove_user_operation(user_operation_hash);
+        }
+
+        let queued: Vec<(Address, U256)> = self
+            .queued_user_operations
+            .iter()
+            .filter(|(_, queued)| involves(&queued.user_operation))
+            .map(|(key, _)| *key)
+            .collect();
+        for (sender, nonce) in queued.iter() {
+            self.drop_queued_user_operation(sender, nonce);
+        }
+        user_operation_hashes.len() + queued.len()
+    }
+
+    /// Verifies that the EIP-7702 authorization is signed by the sender of the user operation for this chain, with
+    /// the sender's current account nonce (otherwise it would be skipped by the bundle transaction)
+    pub async fn verify_authorization(
+        &self,
+        user_operation: &UserOperation,
+        authorization: &Authorization,
+    ) -> Result<(), ErrorObject<'static>> {
+        if !authorization.chain_id.is_zero() && authorization.chain_id != self.chain_id {
+            return Err(SanityCheckError::owned(
+                SANITY_CHECK_ERROR_CODE,
+                format!(
+                    "Authorization is for chain {} instead of {}",
+                    authorization.chain_id, self.chain_id
+                ),
+                None::<bool>,
+            ));
+        }
+        match authorization.authority() {
+            Ok(authority) if authority == user_operation.sender => {}
+            _ => {
+                return Err(SanityCheckError::owned(
+                    SANITY_CHECK_ERROR_CODE,
+                    "Authorization is not signed by the sender",
+                    None::<bool>,
+                ))
+            }
+        }
+
+        let nonce = self
+            .eth_provider
+            .get_transaction_count(user_operation.sender, None)
+            .await
+            .map_err(|_| SanityCheckError::from(ErrorCode::InternalError))?;
+        if authorization.nonce != nonce {
+            return Err(SanityCheckError::owned(
+                SANITY_CHECK_ERROR_CODE,
+                format!(
+                    "Authorization nonce {} doesn't match the sender's nonce {nonce}",
+                    authorization.nonce
+                ),
+                None::<bool>,
+            ));
+        }
+        Ok(())
+    }
+
+    /// Stores the (verified) EIP-7702 authorization of the sender, replacing the previous one
+    pub fn set_authorization(&mut self, sender: Address, authorization: Authorization) {
+        self.authorizations.insert(sender, authorization);
+    }
+
+    pub fn unset_authorization(&mut self, sender: &Address) {
+        self.authorizations.remove(sender);
+    }
+
+    pub fn get_authorization(&self, sender: &Address) -> Option<Authorization> {
+        self.authorizations.get(sender).cloned()
+    }
+
+    /// Forgets the authorization of the sender once none of its user operations are left in the mempool or the queue,
+    /// so that it isn't attached to the bundles anymore
+    pub fn release_authorization(&mut self, sender: &Address) {
+        if !self.authorizations.contains_key(sender) {
+            return;
+        }
+        let queued = self
+            .queued_user_operations
+            .range((*sender, U256::zero())..=(*sender, U256::MAX))
+            .next()
+            .is_some();
+        if !queued && self.mempool.get_number_by_sender(sender) == 0 {
+            self.authorizations.remove(sender);
         }
     }
 
+    pub fn clear_authorizations(&mut self) {
+        self.authorizations.clear();
+    }
+
+    /// Marks the user operation as private (before it's added to the mempool or queued)
+    pub fn set_private(&mut self, user_operation_hash: UserOperationHash) {
+        self.private_user_operations.insert(user_operation_hash);
+    }
+
+    pub fn unset_private(&mut self, user_operation_hash: &UserOperationHash) {
+        self.private_user_operations.remove(user_operation_hash);
+    }
+
+    pub fn is_private(&self, user_operation_hash: &UserOperationHash) -> bool {
+        self.private_user_operations.contains(user_operation_hash)
+    }
+
+    pub fn clear_private(&mut self) {
+        self.private_user_operations.clear();
+    }
+
+    /// Adds the verified user operation to the mempool (replacing the previous user operation of the sender), the
+    /// user operation is counted as seen for its entities only once it is added
+    pub fn add_verified_user_operation(
+        &mut self,
+        user_operation: UserOperation,
+        verification_result: &VerificationResult,
+    ) -> anyhow::Result<UserOperationHash> {
+        if let Some(user_operation_hash) =
+            verification_result.sanity_check_result.user_operation_hash
+        {
+            // the replacement was verified with the sender's authorization, which is kept for it
+            let authorization = self.get_authorization(&user_operation.sender);
+            self.remove_user_operation(&user_operation_hash);
+            if let Some(authorization) = authorization {
+                self.set_authorization(user_operation.sender, authorization);
+            }
+        } else if let Some(max_mempool_size) = self.max_mempool_size {
+            if self.mempool.size() >= max_mempool_size {
+                return Err(anyhow::anyhow!(
+                    "mempool is full ({max_mempool_size} user operations)"
+                ));
+            }
+        }
+
+        let entry_point = self.entry_point.address();
+        let user_operation_hash =
+            self.mempool
+                .add(user_operation.clone(), &entry_point, &self.chain_id)?;
+        self.valid_until.insert(
+            user_operation_hash,
+            verification_result.simulation_result.valid_until(),
+        );
+        // TODO: find better way to atomically store user operation and code hashes
+        self.mempool
+            .set_code_hashes(
+                &user_operation_hash,
+                &verification_result.simulation_result.code_hashes,
+            )
+            .ok();
+
+        for entity in [
+            Some(user_operation.sender),
+            get_addr(&user_operation.init_code),
+            get_addr(&user_operation.paymaster_and_data),
+        ]
+        .iter()
+        .flatten()
+        {
+            self.reputation.increment_seen(entity);
+        }
+
+        // sending fails only if there are no subscribers
+        if !self.is_private(&user_operation_hash) {
+            self.events
+                .send(UoPoolEvent::NewUserOperation {
+                    user_operation_hash,
+                    user_operation: Box::new(user_operation),
+                })
+                .ok();
+        }
+
+        Ok(user_operation_hash)
+    }
+
+    /// Verifies the user operation with the sanity checks and the simulation. The EIP-7702 authorization of the
+    /// sender (if any) is applied to the simulation, since the sender gets its code only with the bundle transaction.
     pub async fn verify_user_operation(
         &self,
         user_operation: &UserOperation,
+        authorization: Option<&Authorization>,
     ) -> Result<VerificationResult, ErrorObject<'static>> {
         // sanity check
-        let sanity_check_result = self.validate_user_operation(user_operation).await?;
+        let sanity_check_result = self
+            .validate_authorized_user_operation(user_operation, authorization)
+            .instrument(info_span!("sanity_check"))
+            .await?;
 
         // simulation
-        let simulation_result = self.simulate_user_operation(user_operation).await?;
+        let state_override = authorization.map(|authorization| {
+            let mut state_override = spoof::State::default();
+            authorization.apply(user_operation.sender, &mut state_override);
+            state_override
+        });
+        let start = Instant::now();
+        let simulation_result = self
+            .simulate_user_operation(user_operation, state_override.as_ref())
+            .instrument(info_span!("simulation"))
+            .await;
+        let latency = start.elapsed();
+        let entry_point = format!("{:?}", self.entry_point.address());
+        metrics::histogram!("uopool_simulation_latency_seconds", latency.as_secs_f64(), "entry_point" => entry_point.clone());
+        if let Some(slo) = self.simulation_latency_slo.filter(|slo| latency > *slo) {
+            warn!(
+                sender = ?user_operation.sender,
+                "Simulation took {latency:?}, longer than the latency SLO {slo:?}"
+            );
+            metrics::counter!("uopool_slow_simulations", 1, "entry_point" => entry_point);
+        }
+        if let Err(error) = &simulation_result {
+            self.record_simulation_failure(error);
+        }
+        let simulation_result = simulation_result?;
 
         Ok(VerificationResult {
             sanity_check_result,
@@ -72,17 +667,75 @@ impl<M: Middleware + 'static> UoPool<M> {
         })
     }
 
+    /// Finds the `UserOperationEvent` of the user operation, from the receipt of the bundle transaction if the
+    /// inclusion was tracked by the uopool, otherwise by scanning the entry point logs back to its deployment (or the
+    /// recent blocks if the provider has no historical state)
     pub async fn get_user_operation_event_meta(
         &self,
         user_operation_hash: H256,
     ) -> anyhow::Result<Option<(UserOperationEventFilter, LogMeta)>> {
+        if let Some(UserOperationStatus::Included {
+            transaction_hash, ..
+        }) = self.get_user_operation_status(&user_operation_hash.into())
+        {
+            if let Some(tx_receipt) = self
+                .eth_provider
+                .get_transaction_receipt(transaction_hash)
+                .await?
+            {
+                let event = tx_receipt
+                    .logs
+                    .iter()
+                    .filter(|log| {
+                        log.address == self.entry_point.address()
+                            && log.topics.get(1) == Some(&user_operation_hash)
+                    })
+                    .find_map(
+                        |log| match EntryPointAPIEvents::decode_log(&log.clone().into()) {
+                            Ok(EntryPointAPIEvents::UserOperationEventFilter(event)) => {
+                                Some((event, LogMeta::from(log)))
+                            }
+                            _ => None,
+                        },
+                    );
+                if event.is_some() {
+                    return Ok(event);
+                }
+            }
+        }
+
+        // the user operations in the mempool aren't included yet
+        if self
+            .get_pending_user_operation(&user_operation_hash.into())
+            .is_some()
+        {
+            return Ok(None);
+        }
+
         let mut event: Option<(UserOperationEventFilter, LogMeta)> = None;
-        let filter = self
-            .entry_point
-            .entry_point_api()
-            .event::<UserOperationEventFilter>()
-            .topic1(user_operation_hash);
-        let res: Vec<(UserOperationEventFilter, LogMeta)> = filter.query_with_meta().await?;
+        let block_number = self.eth_provider.get_block_number().await?;
+        let first_block = self
+            .log_scanner
+            .deployment_block(
+                self.eth_provider.as_ref(),
+                self.entry_point.address(),
+                block_number,
+            )
+            .await
+            .unwrap_or_else(|| block_number.saturating_sub(USER_OPERATION_EVENT_SCAN_DEPTH.into()));
+        let res: Vec<(UserOperationEventFilter, LogMeta)> = self
+            .log_scanner
+            .scan(first_block, block_number, |from_block, to_block| {
+                let filter = self
+                    .entry_point
+                    .entry_point_api()
+                    .event::<UserOperationEventFilter>()
+                    .topic1(user_operation_hash)
+                    .from_block(from_block)
+                    .to_block(to_block);
+                async move { filter.query_with_meta().await }
+            })
+            .await?;
         if res.len() >= 2 {
             warn!(
                 "There are duplicate user operations with the same hash: {user_operation_hash:x?}"
@@ -102,7 +755,324 @@ impl<M: Middleware + 'static> UoPool<M> {
     }
 
     pub fn remove_user_operation(&mut self, user_operation_hash: &UserOperationHash) -> Option<()> {
+        let sender = match self.mempool.get(user_operation_hash) {
+            Ok(Some(user_operation)) => Some(user_operation.sender),
+            _ => None,
+        };
         self.mempool.remove(user_operation_hash).ok();
+        self.private_user_operations.remove(user_operation_hash);
+        if let Some(sender) = sender {
+            self.release_authorization(&sender);
+        }
         None
     }
+
+    /// Removes the user operation that failed in the bundle (FailedOp) and penalizes the entity
+    /// (factory or paymaster) that is responsible for the failure, based on the reason code
+    pub fn handle_failed_user_operation(&mut self, user_operation: &UserOperation, reason: &str) {
+        let user_operation_hash = user_operation.hash(&self.entry_point.address(), &self.chain_id);
+        self.remove_user_operation(&user_operation_hash);
+        if self.is_included(&user_operation_hash) {
+            // included by another bundler (frontrun), which is not the fault of any entity
+            return;
+        }
+        self.set_user_operation_status(
+            user_operation_hash,
+            UserOperationStatus::Failed {
+                reason: reason.to_string(),
+            },
+        );
+
+        let entity = if reason.starts_with("AA1") {
+            get_addr(&user_operation.init_code)
+        } else if reason.starts_with("AA3") {
+            get_addr(&user_operation.paymaster_and_data)
+        } else {
+            None
+        };
+
+        if let Some(address) = entity {
+            warn!("Entity {address:?} caused user operation {user_operation_hash:?} to fail in bundle: {reason}");
+            self.reputation.update_handle_ops_reverted(&address);
+        }
+    }
+
+    pub fn get_user_operation_status(
+        &self,
+        user_operation_hash: &UserOperationHash,
+    ) -> Option<UserOperationStatus> {
+        self.user_operation_statuses
+            .get(user_operation_hash)
+            .cloned()
+    }
+
+    fn set_user_operation_status(
+        &mut self,
+        user_operation_hash: UserOperationHash,
+        status: UserOperationStatus,
+    ) {
+        if self
+            .user_operation_statuses
+            .insert(user_operation_hash, status)
+            .is_none()
+        {
+            self.user_operation_statuses_order
+                .push_back(user_operation_hash);
+        }
+        while self.user_operation_statuses_order.len() > MAX_USER_OPERATION_STATUSES {
+            if let Some(hash) = self.user_operation_statuses_order.pop_front() {
+                self.user_operation_statuses.remove(&hash);
+            }
+        }
+    }
+
+    fn is_included(&self, user_operation_hash: &UserOperationHash) -> bool {
+        matches!(
+            self.user_operation_statuses.get(user_operation_hash),
+            Some(UserOperationStatus::Included { .. })
+        )
+    }
+
+    /// Handles entry point events emitted in the single (bundle) transaction: removes included user operations
+    /// from the mempool and updates reputation of the included entities. Events of user operations that were
+    /// already handled are skipped, so the same transaction can be handled multiple times.
+    pub fn handle_events(&mut self, events: Vec<EntryPointAPIEvents>, transaction_hash: H256) {
+        let mut aggregator: Option<Address> = None;
+        for event in events {
+            match event {
+                EntryPointAPIEvents::UserOperationEventFilter(event) => {
+                    let user_operation_hash: UserOperationHash = event.user_op_hash.into();
+                    if self.is_included(&user_operation_hash) {
+                        continue;
+                    }
+                    self.remove_user_operation(&user_operation_hash);
+                    // authorization is used up (or not needed anymore) once the sender's user operation is included
+                    self.authorizations.remove(&event.sender);
+                    self.include_address(event.sender);
+                    if !event.paymaster.is_zero() {
+                        self.include_address(event.paymaster);
+                    }
+                    if let Some(aggregator) = aggregator {
+                        self.include_address(aggregator);
+                    }
+                    self.set_user_operation_status(
+                        user_operation_hash,
+                        UserOperationStatus::Included {
+                            transaction_hash,
+                            success: event.success,
+                        },
+                    );
+                    self.events
+                        .send(UoPoolEvent::UserOperationIncluded {
+                            user_operation_hash,
+                            sender: event.sender,
+                            transaction_hash,
+                            success: event.success,
+                        })
+                        .ok();
+                }
+                EntryPointAPIEvents::AccountDeployedFilter(event)
+                    if !self.is_included(&event.user_op_hash.into()) =>
+                {
+                    self.include_address(event.factory);
+                }
+                EntryPointAPIEvents::SignatureAggregatorChangedFilter(event) => {
+                    aggregator = Some(event.aggregator).filter(|a| !a.is_zero());
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::providers::{MockProvider, Provider};
+
+    use super::*;
+    use crate::memory::{mempool::MemoryMempool, reputation::MemoryReputation};
+
+    fn uopool() -> UoPool<Provider<MockProvider>> {
+        let eth_provider = Arc::new(Provider::mocked().0);
+        UoPool::new(
+            EntryPoint::new(eth_provider.clone(), Address::random()),
+            Box::<MemoryMempool>::default(),
+            Box::<MemoryReputation>::default(),
+            eth_provider,
+            U256::from(1500000),
+            U256::from(2),
+            U256::from(1337),
+            HashSet::new(),
+        )
+    }
+
+    fn authorization() -> Authorization {
+        Authorization {
+            chain_id: U256::from(1337),
+            address: Address::random(),
+            nonce: U256::zero(),
+            y_parity: Default::default(),
+            r: U256::one(),
+            s: U256::one(),
+        }
+    }
+
+    #[test]
+    fn authorization_released_with_last_user_operation() {
+        let mut uopool = uopool();
+        let entry_point = uopool.entry_point.address();
+        let user_operation = UserOperation::random();
+        let sender = user_operation.sender;
+        let queued = UserOperation {
+            nonce: user_operation.nonce + 1,
+            ..user_operation.clone()
+        };
+
+        uopool.set_authorization(sender, authorization());
+        let user_operation_hash = uopool
+            .mempool
+            .add(user_operation, &entry_point, &uopool.chain_id)
+            .unwrap();
+        uopool.queue_user_operation(queued.clone()).unwrap();
+
+        uopool.remove_user_operation(&user_operation_hash);
+        assert!(uopool.get_authorization(&sender).is_some());
+        assert_eq!(
+            uopool.drop_queued_user_operation(&sender, &queued.nonce),
+            Some(queued)
+        );
+        assert!(uopool.get_authorization(&sender).is_none());
+
+        // the user operations of other senders don't keep the authorization
+        uopool.set_authorization(sender, authorization());
+        uopool
+            .queue_user_operation(UserOperation::random())
+            .unwrap();
+        uopool.release_authorization(&sender);
+        assert!(uopool.get_authorization(&sender).is_none());
+    }
+
+    #[test]
+    fn readiness() {
+        let mut uopool = uopool();
+        let paymaster = Address::random();
+        // max cost of 262 wei (see `required_prefund_calculation`)
+        let user_operation = UserOperation {
+            call_gas_limit: U256::from(100),
+            verification_gas_limit: U256::from(10),
+            pre_verification_gas: U256::from(1),
+            max_fee_per_gas: U256::from(2),
+            max_priority_fee_per_gas: U256::from(2),
+            paymaster_and_data: paymaster.as_bytes().to_vec().into(),
+            ..UserOperation::random()
+        };
+        let deposits = HashMap::from([(paymaster, U256::from(262))]);
+
+        // the max fee covers the base fee with the minimum priority fee of the uopool (2), though not with the max
+        // priority fee of the user operation
+        uopool.min_priority_fee_per_gas = U256::zero();
+        assert_eq!(
+            uopool.readiness(&user_operation, U256::from(1), &deposits),
+            Readiness::Ready
+        );
+        assert_eq!(
+            uopool.readiness(&user_operation, U256::from(2), &deposits),
+            Readiness::Ready
+        );
+        uopool.min_priority_fee_per_gas = U256::one();
+        assert_eq!(
+            uopool.readiness(&user_operation, U256::from(2), &deposits),
+            Readiness::LowMaxFeePerGas
+        );
+        assert_eq!(
+            uopool.readiness(&user_operation, U256::from(1), &deposits),
+            Readiness::Ready
+        );
+
+        let low_deposits = HashMap::from([(paymaster, U256::from(261))]);
+        assert_eq!(
+            uopool.readiness(&user_operation, U256::from(1), &low_deposits),
+            Readiness::LowPaymasterDeposit
+        );
+        // the deposits of unknown paymasters aren't checked
+        assert_eq!(
+            uopool.readiness(&user_operation, U256::from(1), &HashMap::new()),
+            Readiness::Ready
+        );
+
+        let user_operation_hash =
+            user_operation.hash(&uopool.entry_point.address(), &uopool.chain_id);
+        uopool.valid_until.insert(user_operation_hash, 1);
+        assert_eq!(
+            uopool.readiness(&user_operation, U256::from(1), &deposits),
+            Readiness::Expired
+        );
+    }
+
+    #[test]
+    fn queue_limits() {
+        let mut uopool = uopool();
+        uopool.max_queued_user_operations = Some(2);
+        uopool.max_queued_user_operations_per_sender = Some(1);
+        let entry_point = uopool.entry_point.address();
+
+        // the sender with the maximum number of queued user operations can only replace them
+        let first = UserOperation::random();
+        uopool.queue_user_operation(first.clone()).unwrap();
+        let next = UserOperation {
+            nonce: first.nonce + 1,
+            ..first.clone()
+        };
+        assert!(uopool.queue_user_operation(next).is_err());
+        let replacement = UserOperation {
+            max_fee_per_gas: first.max_fee_per_gas + 1,
+            ..first.clone()
+        };
+        uopool.queue_user_operation(replacement.clone()).unwrap();
+        assert_eq!(
+            uopool.get_queued_user_operations(),
+            vec![replacement.clone()]
+        );
+
+        // the oldest user operation is evicted to make room in the full queue
+        std::thread::sleep(Duration::from_millis(1));
+        let second = UserOperation::random();
+        uopool.queue_user_operation(second.clone()).unwrap();
+        std::thread::sleep(Duration::from_millis(1));
+        let third = UserOperation::random();
+        let third_hash = uopool.queue_user_operation(third.clone()).unwrap();
+        let queued = uopool.get_queued_user_operations();
+        assert_eq!(queued.len(), 2);
+        assert!(queued.contains(&second) && queued.contains(&third));
+        assert_eq!(
+            uopool.get_user_operation_status(&replacement.hash(&entry_point, &uopool.chain_id)),
+            Some(UserOperationStatus::Failed {
+                reason: "evicted from the full queue".to_string()
+            })
+        );
+        assert_eq!(uopool.get_pending_user_operation(&third_hash), Some(third));
+    }
+
+    #[test]
+    fn drop_queued_user_operation() {
+        let mut uopool = uopool();
+        let user_operation = UserOperation::random();
+        let sender = user_operation.sender;
+        let user_operation_hash = uopool.queue_user_operation(user_operation.clone()).unwrap();
+        uopool.set_private(user_operation_hash);
+        uopool.set_authorization(sender, authorization());
+
+        assert_eq!(
+            uopool.drop_queued_user_operation(&sender, &(user_operation.nonce + 1)),
+            None
+        );
+        assert!(uopool.get_authorization(&sender).is_some());
+        assert_eq!(
+            uopool.drop_queued_user_operation(&sender, &user_operation.nonce),
+            Some(user_operation)
+        );
+        assert!(uopool.get_queued_user_operations().is_empty());
+        assert!(!uopool.is_private(&user_operation_hash));
+        assert!(uopool.get_authorization(&sender).is_none());
+    }
 }
@@ -83,6 +83,11 @@ pub fn calculate_valid_gas(gas_price: U256, gas_increase_perc: U256) -> U256 {
     U256::from((gas_price * (1.0 + gas_increase_perc / 100.0)).ceil() as u64)
 }
 
+/// Maximum cost of the user operation that the paymaster (or the sender) has to deposit
+pub fn required_prefund(user_operation: &UserOperation) -> U256 {
+    user_operation.max_cost()
+}
+
 #[cfg(test)]
 pub mod tests {
     use std::{fmt::Debug, str::FromStr};
@@ -116,6 +121,25 @@ pub mod tests {
         );
     }
 
+    #[test]
+    fn required_prefund_calculation() {
+        let user_operation = UserOperation {
+            call_gas_limit: U256::from(100),
+            verification_gas_limit: U256::from(10),
+            pre_verification_gas: U256::from(1),
+            max_fee_per_gas: U256::from(2),
+            paymaster_and_data: Bytes::default(),
+            ..UserOperation::random()
+        };
+        assert_eq!(required_prefund(&user_operation), U256::from(222));
+
+        let user_operation = UserOperation {
+            paymaster_and_data: Address::random().as_bytes().to_vec().into(),
+            ..user_operation
+        };
+        assert_eq!(required_prefund(&user_operation), U256::from(262));
+    }
+
     pub fn mempool_test_case<T>(mut mempool: T, not_found_error_message: &str)
     where
         T: Mempool<UserOperations = Vec<UserOperation>> + Debug,
@@ -176,6 +200,7 @@ pub mod tests {
         }
 
         assert_eq!(mempool.get_all().len(), 7);
+        assert_eq!(mempool.size(), 7);
         assert_eq!(mempool.get_all_by_sender(&senders[0]).len(), 2);
         assert_eq!(mempool.get_all_by_sender(&senders[1]).len(), 2);
         assert_eq!(mempool.get_all_by_sender(&senders[2]).len(), 3);
@@ -196,6 +221,7 @@ pub mod tests {
         assert_eq!(mempool.clear(), ());
 
         assert_eq!(mempool.get_all().len(), 0);
+        assert_eq!(mempool.size(), 0);
         assert_eq!(mempool.get_all_by_sender(&senders[0]).len(), 0);
 
         for i in 0..3 {